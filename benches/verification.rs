@@ -0,0 +1,156 @@
+// Baseline for the offline part of the verification: normalization, hashing and the root hash.
+// All inputs are generated from fixed seeds so the numbers are comparable between runs.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{rngs::StdRng, SeedableRng};
+use serde_json::json;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use subxt::sp_core::hashing::blake2_256;
+
+use kilt_verify::{
+    credential::{Claim, Credential},
+    utils::{hex_encode, DidKeyPair, KeyType},
+};
+
+// counts the live heap to report the peak memory of hashing a large property
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const CTYPE_HASH: &str = "0x3291bb126e33b4862d421bfaa1d2f272e6cdfc4f96658988fbcffea8914bd9ac";
+
+// ed25519 signatures are deterministic, unlike sr25519 ones
+fn owner_key() -> DidKeyPair {
+    DidKeyPair::from_seed("//Alice", KeyType::Ed25519).unwrap()
+}
+
+fn claim(contents: serde_json::Value) -> Claim {
+    Claim {
+        ctype_hash: CTYPE_HASH.to_string(),
+        contents,
+        owner: owner_key().did(),
+        ..Default::default()
+    }
+}
+
+fn small_claim() -> Claim {
+    claim(json!({"Email": "alice@example.com", "Name": "Alice"}))
+}
+
+// 50 properties, every fifth one is a nested object with an array
+fn large_claim() -> Claim {
+    let contents = (0..50)
+        .map(|i| {
+            let value = match i % 5 {
+                0 => json!({"street": format!("Street {}", i), "numbers": [i, i * 2, i * 3], "verified": true}),
+                1 => json!(i * 1000),
+                2 => json!(i % 2 == 0),
+                3 => json!(null),
+                _ => json!(format!("value number {} with ümlauts", i)),
+            };
+            (format!("Property{}", i), value)
+        })
+        .collect();
+    claim(serde_json::Value::Object(contents))
+}
+
+fn credential(claim: Claim) -> Credential {
+    let credential =
+        Credential::create(claim, &owner_key(), &mut StdRng::seed_from_u64(0)).unwrap();
+    // parse it like a credential file
+    serde_json::from_str(&serde_json::to_string(&credential).unwrap()).unwrap()
+}
+
+fn bench_normalize(c: &mut Criterion) {
+    for (name, claim) in [("small", small_claim()), ("large", large_claim())] {
+        c.bench_function(&format!("normalize_{}", name), |b| {
+            b.iter(|| black_box(&claim).normalize().unwrap())
+        });
+        c.bench_function(&format!("hash_statements_{}", name), |b| {
+            b.iter(|| black_box(&claim).hash_statements().unwrap())
+        });
+    }
+}
+
+// a base64 document scan of 10 MB as a property; hashing it should not copy it
+fn bench_large_property(c: &mut Criterion) {
+    let scan: String = (0..10 * 1024 * 1024)
+        .map(|i| {
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"[i % 64] as char
+        })
+        .collect();
+    let claim = claim(json!({"Name": "Alice", "Scan": scan}));
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    black_box(claim.hash_statements().unwrap());
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    println!("hash_statements_10mb: peak memory {} bytes", peak);
+    assert!(
+        peak < 64 * 1024,
+        "hashing copied the property: {} bytes",
+        peak
+    );
+
+    c.bench_function("hash_statements_10mb", |b| {
+        b.iter(|| black_box(&claim).hash_statements().unwrap())
+    });
+}
+
+fn bench_root_hash(c: &mut Criterion) {
+    // a credential with 1000 claim hashes, the claim itself doesn't matter for the root hash
+    let mut credential = credential(small_claim());
+    credential.claim_hashes = (0..1000u32)
+        .map(|i| hex_encode(blake2_256(&i.to_le_bytes())))
+        .collect();
+    c.bench_function("root_hash_1000", |b| {
+        b.iter(|| black_box(&credential).compute_root_hash().unwrap())
+    });
+}
+
+fn bench_offline_verification(c: &mut Criterion) {
+    for (name, claim) in [("small", small_claim()), ("large", large_claim())] {
+        let json = serde_json::to_string(&credential(claim)).unwrap();
+        c.bench_function(&format!("offline_verification_{}", name), |b| {
+            b.iter_batched(
+                || json.clone(),
+                |json| {
+                    let credential: Credential = serde_json::from_str(&json).unwrap();
+                    credential.check_claim_contents().unwrap();
+                    credential.check_root_hash().unwrap();
+                    credential.check_challenge(None).unwrap();
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_normalize,
+    bench_large_property,
+    bench_root_hash,
+    bench_offline_verification
+);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+// Feed structurally valid but arbitrary claim contents into normalization and hashing.
+// `cargo +nightly fuzz run claim_contents -- -rss_limit_mb=256`
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use serde_json::{Map, Value};
+
+use kilt_verify::{
+    credential::{Claim, Credential},
+    limits::Limits,
+};
+
+// serde_json refuses to parse deeper documents, so deeper contents can't come from a credential file
+const MAX_DEPTH: usize = 128;
+
+#[derive(Arbitrary, Debug)]
+enum JsonValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    // a huge string without paying for it in fuzzer input
+    Repeated(char, u16),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    fn to_value(&self, depth: usize) -> Value {
+        match self {
+            JsonValue::Null => Value::Null,
+            JsonValue::Bool(b) => Value::from(*b),
+            JsonValue::Int(i) => Value::from(*i),
+            JsonValue::Float(f) => Value::from(*f),
+            JsonValue::String(s) => Value::from(s.as_str()),
+            JsonValue::Repeated(c, n) => Value::from(c.to_string().repeat(*n as usize * 16)),
+            _ if depth >= MAX_DEPTH => Value::Null,
+            JsonValue::Array(values) => {
+                Value::Array(values.iter().map(|v| v.to_value(depth + 1)).collect())
+            }
+            JsonValue::Object(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_value(depth + 1)))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    ctype_hash: String,
+    owner: String,
+    contents: Vec<(String, JsonValue)>,
+}
+
+fuzz_target!(|input: Input| {
+    let contents = input
+        .contents
+        .iter()
+        .map(|(k, v)| (k.clone(), v.to_value(1)))
+        .collect::<Map<_, _>>();
+    let claim = Claim {
+        ctype_hash: input.ctype_hash,
+        contents: Value::Object(contents),
+        owner: input.owner,
+        ..Default::default()
+    };
+
+    // contents fit the limits or are refused, both without panicking
+    let _ = Limits::default().check_claim(&claim);
+
+    let normalized = claim.normalize().expect("objects always normalize");
+    let hashes = claim.hash_statements().expect("objects always hash");
+    assert_eq!(normalized.len(), hashes.len());
+    // normalization is deterministic
+    assert_eq!(claim.normalize().unwrap(), normalized);
+
+    // without nonces the contents can never be valid
+    let credential = Credential {
+        claim,
+        ..Default::default()
+    };
+    assert!(credential.check_claim_contents().is_err());
+});
@@ -0,0 +1,19 @@
+// Feed arbitrary bytes into the credential parser and run the offline checks on everything it accepts.
+// Run with a memory limit to catch unbounded allocations:
+// `cargo +nightly fuzz run credential -- -rss_limit_mb=256 -max_len=65536`
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use kilt_verify::utils::{get_did_account_id, get_did_key_uri, ParseOptions};
+
+fuzz_target!(|data: &[u8]| {
+    // the limited path, like every credential the verifier reads
+    if let Ok(credential) = ParseOptions::default().parse("fuzz.json", data) {
+        let _ = credential.check_claim_contents();
+        let _ = credential.check_root_hash();
+        let _ = credential.check_challenge(Some(""));
+        let _ = get_did_account_id(&credential.claim.owner);
+        let _ = get_did_key_uri(&credential.claimer_signature.key_uri);
+    }
+});
@@ -0,0 +1,391 @@
+use flate2::read::GzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Component, Path},
+};
+
+use crate::{batch::BatchInput, errors::Error};
+
+/// Which entries of an archive are verified and how much of it is decompressed at most.
+/// Archives come from untrusted holders, so every entry is read into memory with a bounded size.
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    /// Globs of the entry paths to verify, all `.json` files if empty
+    pub include: Vec<String>,
+    /// Globs of the entry paths to skip, even if they are included
+    pub exclude: Vec<String>,
+    /// Maximum decompressed size of a single entry in bytes
+    pub max_entry_size: u64,
+    /// Maximum decompressed size of all verified entries in bytes
+    pub max_total_size: u64,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions {
+            include: vec![],
+            exclude: vec![],
+            max_entry_size: 1024 * 1024,
+            max_total_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn format(file: &str) -> Option<Format> {
+    if file.ends_with(".zip") {
+        Some(Format::Zip)
+    } else if file.ends_with(".tar") {
+        Some(Format::Tar)
+    } else if file.ends_with(".tar.gz") || file.ends_with(".tgz") {
+        Some(Format::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Zip files and (gzipped) tar files are verified as a batch of their entries
+pub fn is_archive(file: &str) -> bool {
+    format(file).is_some()
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    Ok(builder.build()?)
+}
+
+// the include and exclude globs of the options
+struct Filter {
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    fn new(options: &ArchiveOptions) -> Result<Self, Error> {
+        let include = if options.include.is_empty() {
+            vec!["*.json".to_string()]
+        } else {
+            options.include.clone()
+        };
+        Ok(Filter {
+            include: glob_set(&include)?,
+            exclude: glob_set(&options.exclude)?,
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.include.is_match(path) && !self.exclude.is_match(path)
+    }
+}
+
+// Entries with absolute paths or `..` are rejected instead of being skipped,
+// an archive containing them was crafted and none of its entries should be trusted.
+fn check_path(path: &str) -> Result<(), Error> {
+    let safe = !path.is_empty()
+        && !path.contains('\0')
+        && !path.starts_with('\\')
+        // windows drive letters like `c:`
+        && !path
+            .split(['/', '\\'])
+            .next()
+            .is_some_and(|first| first.ends_with(':'))
+        && Path::new(&path.replace('\\', "/"))
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if safe {
+        Ok(())
+    } else {
+        Err(Error::InvalidArchive(format!(
+            "unsafe entry path {:?}",
+            path
+        )))
+    }
+}
+
+// Keeps track of the decompressed bytes of the whole archive
+struct Budget {
+    options: ArchiveOptions,
+    used: u64,
+}
+
+impl Budget {
+    // read an entry into memory, failing as soon as it exceeds a limit
+    fn read(&mut self, path: &str, entry: impl Read) -> Result<Vec<u8>, Error> {
+        let limit = self
+            .options
+            .max_entry_size
+            .min(self.options.max_total_size - self.used);
+        let mut data = Vec::new();
+        entry.take(limit + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > limit {
+            return Err(Error::InvalidArchive(
+                if limit == self.options.max_entry_size {
+                    format!(
+                        "entry {} is larger than {} bytes",
+                        path, self.options.max_entry_size
+                    )
+                } else {
+                    format!(
+                        "archive is larger than {} bytes",
+                        self.options.max_total_size
+                    )
+                },
+            ));
+        }
+        self.used += data.len() as u64;
+        Ok(data)
+    }
+}
+
+fn read_zip(file: File, filter: &Filter, budget: &mut Budget) -> Result<Vec<BatchInput>, Error> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut inputs = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        let path = entry.name()?.into_owned();
+        check_path(&path)?;
+        if entry.is_dir() || !filter.matches(&path) {
+            continue;
+        }
+        inputs.push(BatchInput {
+            data: budget.read(&path, entry)?,
+            source: path,
+        });
+    }
+    Ok(inputs)
+}
+
+fn read_tar(
+    reader: impl Read,
+    filter: &Filter,
+    budget: &mut Budget,
+) -> Result<Vec<BatchInput>, Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut inputs = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        check_path(&path)?;
+        // links and special files are never verified
+        if !entry.header().entry_type().is_file() || !filter.matches(&path) {
+            continue;
+        }
+        inputs.push(BatchInput {
+            data: budget.read(&path, entry)?,
+            source: path,
+        });
+    }
+    Ok(inputs)
+}
+
+// Limits the decompressed stream of a tar.gz, skipped entries are decompressed as well
+struct Bounded<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for Bounded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(io::Error::other(
+                "decompressed archive exceeds the size limit",
+            ));
+        }
+        let max = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Read the entries of an archive that match the globs of the options, in archive order.
+/// The source of each input is its path inside the archive.
+pub fn read_archive(file: &str, options: &ArchiveOptions) -> Result<Vec<BatchInput>, Error> {
+    let filter = Filter::new(options)?;
+    let mut budget = Budget {
+        options: options.clone(),
+        used: 0,
+    };
+    let reader = File::open(file)?;
+    match format(file) {
+        Some(Format::Zip) => read_zip(reader, &filter, &mut budget),
+        Some(Format::Tar) => read_tar(reader, &filter, &mut budget),
+        Some(Format::TarGz) => {
+            // tar headers and padding need some room on top of the entries
+            let stream = Bounded {
+                inner: GzDecoder::new(reader),
+                remaining: options.max_total_size.saturating_mul(2).max(1024 * 1024),
+            };
+            read_tar(stream, &filter, &mut budget)
+        }
+        None => Err(Error::InvalidArchive(format!("{} is not an archive", file))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("kilt-verify-{}-{}", std::process::id(), name))
+            .display()
+            .to_string()
+    }
+
+    fn write_zip(file: &str, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(file).unwrap());
+        for (path, data) in entries {
+            zip.start_file(*path, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar_gz(file: &str, entries: &[(&str, &[u8])]) {
+        let gz = GzEncoder::new(File::create(file).unwrap(), Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            // set the name directly, `set_path` refuses unsafe paths
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, *data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn sources(inputs: Vec<BatchInput>) -> Vec<String> {
+        inputs.into_iter().map(|input| input.source).collect()
+    }
+
+    #[test]
+    fn test_read_archive() {
+        let entries: &[(&str, &[u8])] = &[
+            ("creds/a.json", b"{}"),
+            ("creds/notes.txt", b"ignored"),
+            ("creds/old/b.json", b"{}"),
+            ("c.json", b"{\"c\":1}"),
+        ];
+        let zip_file = temp_file("backup.zip");
+        let tar_file = temp_file("backup.tar.gz");
+        write_zip(&zip_file, entries);
+        write_tar_gz(&tar_file, entries);
+
+        for file in [&zip_file, &tar_file] {
+            assert!(is_archive(file));
+            let inputs = read_archive(file, &ArchiveOptions::default()).unwrap();
+            assert_eq!(inputs[2].data, b"{\"c\":1}");
+            assert_eq!(
+                sources(inputs),
+                vec!["creds/a.json", "creds/old/b.json", "c.json"]
+            );
+
+            let options = ArchiveOptions {
+                include: vec!["creds/**".to_string()],
+                exclude: vec!["**/old/**".to_string()],
+                ..Default::default()
+            };
+            let inputs = read_archive(file, &options).unwrap();
+            assert_eq!(sources(inputs), vec!["creds/a.json", "creds/notes.txt"]);
+        }
+        assert!(!is_archive("backup.json"));
+
+        std::fs::remove_file(zip_file).unwrap();
+        std::fs::remove_file(tar_file).unwrap();
+    }
+
+    #[test]
+    fn test_unsafe_paths() {
+        assert!(check_path("creds/a.json").is_ok());
+        assert!(check_path("./a.json").is_ok());
+        for path in [
+            "/etc/a.json",
+            "../a.json",
+            "creds/../../a.json",
+            "\\a.json",
+            "c:\\a.json",
+            "",
+        ] {
+            assert!(
+                matches!(check_path(path), Err(Error::InvalidArchive(_))),
+                "{}",
+                path
+            );
+        }
+
+        let zip_file = temp_file("unsafe.zip");
+        let tar_file = temp_file("unsafe.tar.gz");
+        let entries: &[(&str, &[u8])] = &[("a.json", b"{}"), ("../evil.json", b"{}")];
+        write_zip(&zip_file, entries);
+        write_tar_gz(&tar_file, entries);
+        for file in [&zip_file, &tar_file] {
+            let res = read_archive(file, &ArchiveOptions::default());
+            assert!(matches!(res, Err(Error::InvalidArchive(_))), "{:?}", res);
+        }
+        std::fs::remove_file(zip_file).unwrap();
+        std::fs::remove_file(tar_file).unwrap();
+    }
+
+    #[test]
+    fn test_size_limits() {
+        // zeros compress very well, like the entries of a zip bomb
+        let zeros = vec![0u8; 4096];
+        let entries: &[(&str, &[u8])] = &[("a.json", &zeros), ("b.json", &zeros)];
+        let zip_file = temp_file("bomb.zip");
+        let tar_file = temp_file("bomb.tar.gz");
+        write_zip(&zip_file, entries);
+        write_tar_gz(&tar_file, entries);
+
+        for file in [&zip_file, &tar_file] {
+            let entry_limit = ArchiveOptions {
+                max_entry_size: 4095,
+                ..Default::default()
+            };
+            let res = read_archive(file, &entry_limit);
+            assert!(
+                matches!(&res, Err(Error::InvalidArchive(msg)) if msg.contains("entry a.json")),
+                "{:?}",
+                res
+            );
+
+            let total_limit = ArchiveOptions {
+                max_total_size: 6000,
+                ..Default::default()
+            };
+            let res = read_archive(file, &total_limit);
+            assert!(
+                matches!(&res, Err(Error::InvalidArchive(msg)) if msg.contains("archive is larger")),
+                "{:?}",
+                res
+            );
+
+            assert_eq!(
+                read_archive(file, &ArchiveOptions::default())
+                    .unwrap()
+                    .len(),
+                2
+            );
+        }
+        std::fs::remove_file(zip_file).unwrap();
+        std::fs::remove_file(tar_file).unwrap();
+    }
+}
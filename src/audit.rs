@@ -0,0 +1,418 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    time::SystemTime,
+};
+
+use crate::{credential::ClaimRequirements, errors::Error};
+
+/// The hash the first entry of a log chains to
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// When the entries of the audit log are synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FlushPolicy {
+    /// Sync every entry before the next credential is reported
+    Entry,
+    /// Sync once at the end of the run, a crash can lose the entries of the run
+    Run,
+}
+
+/// The accept or reject decision of a verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Accept,
+    Reject,
+}
+
+/// What is logged about a verification, claim contents are never part of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Root hash given in the credential, `None` if it could not be parsed
+    pub root_hash: Option<String>,
+    /// Error code of a rejected credential
+    pub error: Option<String>,
+    pub endpoint: String,
+    /// Hash of the block all lookups were pinned to, `None` for the latest block
+    pub block: Option<String>,
+    /// See `policy_hash`
+    pub policy_hash: String,
+}
+
+impl AuditRecord {
+    pub fn new(
+        root_hash: Option<&str>,
+        result: &Result<(), Error>,
+        endpoint: &str,
+        block: Option<String>,
+        policy_hash: &str,
+    ) -> Self {
+        AuditRecord {
+            root_hash: root_hash.map(str::to_string),
+            error: result.as_ref().err().map(|err| err.code().to_string()),
+            endpoint: endpoint.to_string(),
+            block,
+            policy_hash: policy_hash.to_string(),
+        }
+    }
+}
+
+/// A line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Counts from 1 across all runs that append to the log
+    pub seq: u64,
+    /// RFC 3339 time of the decision
+    pub timestamp: String,
+    pub root_hash: Option<String>,
+    pub decision: Decision,
+    pub error: Option<String>,
+    pub endpoint: String,
+    pub block: Option<String>,
+    pub policy_hash: String,
+    /// SHA-256 of the previous line, `GENESIS` for the first one
+    pub prev_hash: String,
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// How the chain state a credential is checked against is read, beyond its claim
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainPolicy {
+    /// Signatures of keys removed within this many blocks are accepted, see --key-grace-blocks
+    pub key_grace_blocks: Option<u32>,
+    /// Blocks the attestation has to be confirmed by, 0 if it does not matter
+    pub min_confirmations: u32,
+}
+
+/// SHA-256 of the options a credential is accepted under: the allowed issuers, the challenge, the
+/// requirements of its claim and the chain policy. The issuers and properties are sorted, so their
+/// order does not matter.
+pub fn policy_hash(
+    allowed_issuers: &[&str],
+    challenge: Option<&str>,
+    requirements: &ClaimRequirements,
+    chain: &ChainPolicy,
+) -> String {
+    let mut issuers = allowed_issuers.to_vec();
+    issuers.sort_unstable();
+    issuers.dedup();
+    let mut properties: Vec<_> = requirements.properties.iter().collect();
+    properties.sort_unstable();
+    properties.dedup();
+    // the keys of json objects are sorted, the serialization is stable
+    let mut policy = serde_json::json!({
+        "allowedIssuers": issuers,
+        "challenge": challenge,
+        "expectedOwner": requirements.owner,
+        "requiredProperties": properties,
+    });
+    // only set, so the hashes of policies from before it stay the same
+    if requirements.full_did {
+        policy["requireFullDid"] = true.into();
+    }
+    if let Some(blocks) = chain.key_grace_blocks {
+        policy["keyGraceBlocks"] = blocks.into();
+    }
+    if chain.min_confirmations > 0 {
+        policy["minConfirmations"] = chain.min_confirmations.into();
+    }
+    sha256(policy.to_string().as_bytes())
+}
+
+/// An audit log opened for appending
+pub struct AuditLog {
+    file: File,
+    flush: FlushPolicy,
+    next_seq: u64,
+    prev_hash: String,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, creating it if it does not exist. The chain continues after the last
+    /// entry, a log whose last line is not a complete entry is not appended to.
+    pub fn open(path: &Path, flush: FlushPolicy) -> Result<Self, Error> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let (next_seq, prev_hash) = match data.strip_suffix(b"\n") {
+            _ if data.is_empty() => (1, GENESIS.to_string()),
+            Some(lines) => {
+                let last = lines.rsplit(|b| *b == b'\n').next().unwrap_or_default();
+                let entry: AuditEntry = serde_json::from_slice(last).map_err(|err| {
+                    Error::AuditLog(format!(
+                        "last entry of {} is invalid: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+                (entry.seq + 1, sha256(last))
+            }
+            None => {
+                return Err(Error::AuditLog(format!(
+                    "{} ends with an incomplete entry",
+                    path.display()
+                )))
+            }
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file,
+            flush,
+            next_seq,
+            prev_hash,
+        })
+    }
+
+    /// Append the decision on a credential at `now`
+    pub fn append(&mut self, record: AuditRecord, now: SystemTime) -> Result<AuditEntry, Error> {
+        let entry = AuditEntry {
+            seq: self.next_seq,
+            timestamp: DateTime::<Utc>::from(now).to_rfc3339_opts(SecondsFormat::Millis, true),
+            decision: if record.error.is_none() {
+                Decision::Accept
+            } else {
+                Decision::Reject
+            },
+            root_hash: record.root_hash,
+            error: record.error,
+            endpoint: record.endpoint,
+            block: record.block,
+            policy_hash: record.policy_hash,
+            prev_hash: self.prev_hash.clone(),
+        };
+        let line = serde_json::to_string(&entry)?;
+        // one write per line, so a crash leaves at most the last line incomplete
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        if self.flush == FlushPolicy::Entry {
+            self.file.sync_data()?;
+        }
+        self.next_seq += 1;
+        self.prev_hash = sha256(line.as_bytes());
+        Ok(entry)
+    }
+
+    /// Sync all appended entries to disk
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.file.sync_data()?)
+    }
+}
+
+/// An intact audit log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSummary {
+    pub entries: u64,
+    /// SHA-256 of the last line, the hash the next entry chains to
+    pub head: String,
+}
+
+/// Check that every line of the log chains to the one before it and the sequence has no gaps.
+/// Entries cut from the end keep the chain intact, they show as a head that differs from one
+/// recorded earlier.
+pub fn verify(path: &Path) -> Result<ChainSummary, Error> {
+    let data = std::fs::read(path)?;
+    let mut summary = ChainSummary {
+        entries: 0,
+        head: GENESIS.to_string(),
+    };
+    if data.is_empty() {
+        return Ok(summary);
+    }
+    let lines = data
+        .strip_suffix(b"\n")
+        .ok_or_else(|| Error::AuditLog("the last entry is incomplete".to_string()))?;
+    for (index, line) in lines.split(|b| *b == b'\n').enumerate() {
+        let line_number = index + 1;
+        let entry: AuditEntry = serde_json::from_slice(line)
+            .map_err(|err| Error::AuditLog(format!("line {} is invalid: {}", line_number, err)))?;
+        if entry.seq != summary.entries + 1 {
+            return Err(Error::AuditLog(format!(
+                "line {} has sequence number {}, expected {}",
+                line_number,
+                entry.seq,
+                summary.entries + 1
+            )));
+        }
+        if entry.prev_hash != summary.head {
+            return Err(Error::AuditLog(format!(
+                "line {} does not chain to the line before it, an entry was changed or removed",
+                line_number
+            )));
+        }
+        summary.entries += 1;
+        summary.head = sha256(line);
+    }
+    Ok(summary)
+}
+
+/// Work with the audit log of `--audit-log`
+#[derive(Args, Debug)]
+pub struct AuditArgs {
+    #[clap(subcommand)]
+    command: AuditCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditCommand {
+    /// Check the hash chain of an audit log
+    Verify(VerifyArgs),
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// The audit log file
+    #[clap(value_parser)]
+    path: String,
+}
+
+pub fn run(args: &AuditArgs) -> Result<(), Error> {
+    match &args.command {
+        AuditCommand::Verify(verify_args) => {
+            let summary = verify(Path::new(&verify_args.path))?;
+            println!(
+                "✅ Audit log is intact: {} entries, head {}",
+                summary.entries, summary.head
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_policy_hash() {
+        let requirements = ClaimRequirements {
+            owner: None,
+            properties: vec!["Email".to_string(), "Name".to_string()],
+            full_did: false,
+        };
+        let reordered = ClaimRequirements {
+            owner: None,
+            properties: vec!["Name".to_string(), "Email".to_string()],
+            full_did: false,
+        };
+        let strict = ChainPolicy::default();
+        let hash = policy_hash(&["did:kilt:a", "did:kilt:b"], None, &requirements, &strict);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            hash,
+            policy_hash(&["did:kilt:b", "did:kilt:a"], None, &reordered, &strict)
+        );
+        assert_ne!(
+            hash,
+            policy_hash(
+                &["did:kilt:a", "did:kilt:b"],
+                Some("0x123"),
+                &requirements,
+                &strict
+            )
+        );
+        assert_ne!(
+            hash,
+            policy_hash(&["did:kilt:a"], None, &requirements, &strict)
+        );
+
+        // a grace window or confirmations change the policy, a window of 0 blocks is set as well
+        for chain in [
+            ChainPolicy {
+                key_grace_blocks: Some(0),
+                ..strict
+            },
+            ChainPolicy {
+                min_confirmations: 6,
+                ..strict
+            },
+        ] {
+            assert_ne!(
+                hash,
+                policy_hash(&["did:kilt:a", "did:kilt:b"], None, &requirements, &chain)
+            );
+        }
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let file =
+            std::env::temp_dir().join(format!("kilt-verify-audit-{}.ndjson", std::process::id()));
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let record = |root_hash: &str, result: Result<(), Error>| {
+            AuditRecord::new(Some(root_hash), &result, "wss://node", None, "policy")
+        };
+
+        let mut log = AuditLog::open(&file, FlushPolicy::Entry).unwrap();
+        let first = log.append(record("0x1", Ok(())), now).unwrap();
+        assert_eq!((first.seq, first.prev_hash.as_str()), (1, GENESIS));
+        assert_eq!(first.decision, Decision::Accept);
+        assert_eq!(first.timestamp, "2023-11-14T22:13:20.000Z");
+        drop(log);
+
+        // a later run continues the chain
+        let mut log = AuditLog::open(&file, FlushPolicy::Run).unwrap();
+        let second = log
+            .append(record("0x2", Err(Error::AttestationRevoked)), now)
+            .unwrap();
+        log.append(record("0x3", Ok(())), now).unwrap();
+        log.flush().unwrap();
+        assert_eq!(second.seq, 2);
+        assert_eq!(second.decision, Decision::Reject);
+        assert_eq!(second.error.as_deref(), Some("attestation_revoked"));
+        let summary = verify(&file).unwrap();
+        assert_eq!(summary.entries, 3);
+
+        let data = std::fs::read_to_string(&file).unwrap();
+        let lines: Vec<_> = data.lines().collect();
+        assert_eq!(second.prev_hash, sha256(lines[0].as_bytes()));
+        assert_eq!(summary.head, sha256(lines[2].as_bytes()));
+        // claim contents and owners are never logged
+        assert!(!data.contains("contents") && !data.contains("owner"));
+
+        let tampered_file = file.with_extension("tampered");
+        for (lines, reason) in [
+            (vec![lines[0], lines[2]], "sequence number 3, expected 2"),
+            (vec![lines[1], lines[2]], "sequence number 2, expected 1"),
+            (
+                vec![lines[0], &lines[1][..20], lines[2]],
+                "line 2 is invalid",
+            ),
+            (
+                vec![lines[0], &lines[1].replace("reject", "accept"), lines[2]],
+                "line 3 does not chain",
+            ),
+        ] {
+            std::fs::write(&tampered_file, format!("{}\n", lines.join("\n"))).unwrap();
+            let res = verify(&tampered_file);
+            assert!(
+                matches!(&res, Err(Error::AuditLog(msg)) if msg.contains(reason)),
+                "{}: {:?}",
+                reason,
+                res
+            );
+        }
+
+        // a partly written entry stops the chain from being continued
+        std::fs::write(&tampered_file, &data[..data.len() - 5]).unwrap();
+        assert!(matches!(verify(&tampered_file), Err(Error::AuditLog(_))));
+        assert!(matches!(
+            AuditLog::open(&tampered_file, FlushPolicy::Entry),
+            Err(Error::AuditLog(_))
+        ));
+
+        std::fs::remove_file(&tampered_file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,978 @@
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use futures::future::BoxFuture;
+use lazy_static::lazy_static;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use subxt::{
+    sp_core::{storage::StorageKey, twox_64, H256},
+    sp_runtime::AccountId32,
+    storage::StorageKeyPrefix,
+};
+use tokio::sync::OnceCell;
+
+use crate::{
+    errors::{Error, QueryAttempt},
+    kilt::{
+        did::storage::ServiceEndpoints,
+        runtime_types::{
+            attestation::attestations::AttestationDetails,
+            did::{
+                did_details::{DidDetails, DidPublicKeyDetails},
+                service_endpoints::DidEndpoint,
+            },
+            frame_support::storage::bounded_vec::BoundedVec,
+            pallet_web3_names::web3_name::AsciiWeb3Name,
+        },
+        KiltRuntimeApi,
+    },
+    metrics,
+    progress::VerificationObserver,
+    throttle::{Priority, RpcLimiter, Throttle, Throttled, THROTTLE_PENALTY},
+    utils::Token,
+};
+
+/// The blocks an attestation was created and revoked in, which the chain state does not keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttestationHistory {
+    pub created: u64,
+    pub revoked: Option<u64>,
+}
+
+/// A key that is not in the DID document anymore, with the block it was removed at
+#[derive(Debug)]
+pub struct RemovedKey {
+    pub details: DidPublicKeyDetails,
+    /// Number of the first block the key is missing at
+    pub removed_at: u32,
+}
+
+/// The chain state a verifier needs to look up.
+/// This is implemented by the runtime api and can be replaced by a mock in tests.
+/// Every lookup takes an optional block hash to query historical state, `None` means the latest block.
+#[async_trait]
+pub trait ChainBackend: Send + Sync {
+    /// Get the DID document of a DID from the `did.did` storage
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error>;
+
+    /// Get the attestation of a root hash from the `attestation.attestations` storage
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error>;
+
+    /// Get the blocks an attestation was created and revoked in, `None` if the backend does not
+    /// know them. Only indexers do, the chain only has the current state
+    async fn attestation_history(
+        &self,
+        _root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        Ok(None)
+    }
+
+    /// Get a key that was removed from the DID document of a DID, `None` if the backend does not
+    /// accept removed keys. Only `grace::KeyGraceBackend` does, for keys removed within its window
+    async fn removed_key(
+        &self,
+        _did: &AccountId32,
+        _key_id: &H256,
+    ) -> Result<Option<RemovedKey>, Error> {
+        Ok(None)
+    }
+
+    /// Get the DID that owns a web3name from the `web3Names.owner` storage
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error>;
+
+    /// Get the web3name of a DID from the `web3Names.names` storage
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error>;
+
+    /// Get the creator of a ctype from the `ctype.ctypes` storage
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error>;
+
+    /// Get all service endpoints of a DID from the `did.serviceEndpoints` storage
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error>;
+
+    /// Get the native token of the chain to format balances, the KILT token by default
+    async fn token(&self) -> Result<Token, Error> {
+        Ok(Token::default())
+    }
+}
+
+lazy_static! {
+    // the token never changes, so a node is only asked once per run
+    static ref TOKEN: OnceCell<Token> = OnceCell::new();
+}
+
+#[async_trait]
+impl ChainBackend for KiltRuntimeApi {
+    #[tracing::instrument(name = "rpc", skip_all, fields(storage = "did.did", at = ?at))]
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        Ok(self.storage().did().did(did, at).await?)
+    }
+
+    #[tracing::instrument(name = "rpc", skip_all, fields(storage = "attestation.attestations", at = ?at))]
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        Ok(self
+            .storage()
+            .attestation()
+            .attestations(root_hash, at)
+            .await?)
+    }
+
+    #[tracing::instrument(name = "rpc", skip_all, fields(storage = "web3Names.owner", at = ?at))]
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        let name = AsciiWeb3Name(BoundedVec(name.as_bytes().to_vec()));
+        let ownership = self.storage().web3_names().owner(&name, at).await?;
+        Ok(ownership.map(|ownership| ownership.owner))
+    }
+
+    #[tracing::instrument(name = "rpc", skip_all, fields(storage = "web3Names.names", at = ?at))]
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        let name = self.storage().web3_names().names(owner, at).await?;
+        // web3names are restricted to ascii on chain
+        Ok(name.map(|AsciiWeb3Name(BoundedVec(name))| String::from_utf8_lossy(&name).into_owned()))
+    }
+
+    #[tracing::instrument(name = "rpc", skip_all, fields(storage = "ctype.ctypes", at = ?at))]
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        Ok(self.storage().ctype().ctypes(ctype_hash, at).await?)
+    }
+
+    #[tracing::instrument(name = "rpc", skip_all, fields(storage = "did.serviceEndpoints", at = ?at))]
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        // the endpoints of a DID share the prefix of the first key of the double map
+        let mut prefix = StorageKeyPrefix::new::<ServiceEndpoints>()
+            .to_storage_key()
+            .0;
+        prefix.extend(twox_64(&did.encode()));
+        prefix.extend(did.encode());
+        let rpc = self.client.rpc();
+
+        let mut endpoints = Vec::new();
+        let mut start = None;
+        loop {
+            let keys = rpc
+                .storage_keys_paged(Some(StorageKey(prefix.clone())), 100, start, at)
+                .await?;
+            for key in keys.iter() {
+                if let Some(data) = rpc.storage(key, at).await? {
+                    let endpoint = DidEndpoint::decode(&mut &data.0[..])
+                        .map_err(|err| Error::ConnectionError(err.into()))?;
+                    endpoints.push(endpoint);
+                }
+            }
+            if keys.len() < 100 {
+                return Ok(endpoints);
+            }
+            start = keys.last().cloned();
+        }
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        let token = TOKEN
+            .get_or_try_init(|| async {
+                let properties = self.client.rpc().system_properties().await?;
+                Ok::<_, Error>(Token::from_properties(&properties))
+            })
+            .await?;
+        Ok(token.clone())
+    }
+}
+
+/// Counts the lookups that go through to the wrapped backend, i.e. the RPC calls to a node
+pub struct CountingBackend<'a> {
+    inner: &'a dyn ChainBackend,
+    calls: AtomicUsize,
+}
+
+impl<'a> CountingBackend<'a> {
+    pub fn new(inner: &'a dyn ChainBackend) -> Self {
+        CountingBackend {
+            inner,
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    fn count(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl ChainBackend for CountingBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.count();
+        self.inner.did(did, at).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        self.count();
+        self.inner.attestation(root_hash, at).await
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.count();
+        self.inner.attestation_history(root_hash).await
+    }
+
+    async fn removed_key(
+        &self,
+        did: &AccountId32,
+        key_id: &H256,
+    ) -> Result<Option<RemovedKey>, Error> {
+        self.count();
+        self.inner.removed_key(did, key_id).await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.count();
+        self.inner.web3_name_owner(name, at).await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.count();
+        self.inner.web3_name(owner, at).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.count();
+        self.inner.ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.count();
+        self.inner.service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.inner.token().await
+    }
+}
+
+/// Reads the state of one block for all lookups that do not ask for a block themselves,
+/// i.e. to verify a credential as it was at a point in time
+pub struct PinnedBackend<'a> {
+    inner: &'a dyn ChainBackend,
+    block: H256,
+}
+
+impl<'a> PinnedBackend<'a> {
+    pub fn new(inner: &'a dyn ChainBackend, block: H256) -> Self {
+        PinnedBackend { inner, block }
+    }
+}
+
+#[async_trait]
+impl ChainBackend for PinnedBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.inner.did(did, at.or(Some(self.block))).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        self.inner
+            .attestation(root_hash, at.or(Some(self.block)))
+            .await
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.inner.attestation_history(root_hash).await
+    }
+
+    async fn removed_key(
+        &self,
+        did: &AccountId32,
+        key_id: &H256,
+    ) -> Result<Option<RemovedKey>, Error> {
+        self.inner.removed_key(did, key_id).await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner
+            .web3_name_owner(name, at.or(Some(self.block)))
+            .await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.inner.web3_name(owner, at.or(Some(self.block))).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner
+            .ctype_creator(ctype_hash, at.or(Some(self.block)))
+            .await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.inner
+            .service_endpoints(did, at.or(Some(self.block)))
+            .await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.inner.token().await
+    }
+}
+
+/// Runs every storage query of the wrapped backends with its own timeout, so one query that hangs
+/// does not stall a verification on an otherwise healthy connection. A query that times out or
+/// fails because of the node is tried again up to `retries` times, each attempt against the next
+/// backend in turn, i.e. the same endpoint again or a fallback. Once all attempts failed the error
+/// lists every attempt with its endpoint and duration. With a limiter each attempt waits for a
+/// permit first, the wait does not count against the timeout. A query the RPC provider throttled
+/// pauses the endpoint for the delay the provider asked for, or for a penalty, before it is tried
+/// again, with a shared throttle every query to the endpoint pauses.
+pub struct QueryBackend<'a> {
+    /// The endpoint names and their backends, the first one is tried first
+    backends: Vec<(String, &'a dyn ChainBackend)>,
+    query_timeout: Option<Duration>,
+    retries: usize,
+    observer: Option<&'a dyn VerificationObserver>,
+    limiter: Option<(&'a RpcLimiter, Priority)>,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a> QueryBackend<'a> {
+    pub fn new(endpoint: &str, backend: &'a dyn ChainBackend) -> Self {
+        QueryBackend {
+            backends: vec![(endpoint.to_string(), backend)],
+            query_timeout: None,
+            retries: 0,
+            observer: None,
+            limiter: None,
+            throttle: None,
+        }
+    }
+
+    /// Another endpoint that retries are sent to, in the order the fallbacks are added
+    pub fn fallback(mut self, endpoint: &str, backend: &'a dyn ChainBackend) -> Self {
+        self.backends.push((endpoint.to_string(), backend));
+        self
+    }
+
+    /// Give up on a single query after this long, `None` waits as long as the node takes
+    pub fn query_timeout(self, query_timeout: Option<Duration>) -> Self {
+        QueryBackend {
+            query_timeout,
+            ..self
+        }
+    }
+
+    /// How many times a failed query is tried again
+    pub fn retries(self, retries: usize) -> Self {
+        QueryBackend { retries, ..self }
+    }
+
+    /// Tell the observer whenever a query is tried again
+    pub fn observed(self, observer: &'a dyn VerificationObserver) -> Self {
+        QueryBackend {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// Take a permit of the limiter for every query
+    pub fn limited(self, limiter: Option<&'a RpcLimiter>, priority: Priority) -> Self {
+        QueryBackend {
+            limiter: limiter.map(|limiter| (limiter, priority)),
+            ..self
+        }
+    }
+
+    /// Share the pauses of throttled endpoints with every other query of the throttle
+    pub fn throttled(self, throttle: Option<&'a Throttle>) -> Self {
+        QueryBackend { throttle, ..self }
+    }
+
+    async fn query<'f, T>(
+        &'f self,
+        storage: &'static str,
+        lookup: impl Fn(&'f dyn ChainBackend) -> BoxFuture<'f, Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let mut attempts = Vec::new();
+        for attempt in 0..=self.retries {
+            let (endpoint, backend) = &self.backends[attempt % self.backends.len()];
+            if let (Some(observer), true) = (self.observer, attempt > 0) {
+                observer.on_retry(storage, attempt + 1);
+            }
+            if let Some(throttle) = self.throttle {
+                throttle.wait(endpoint).await;
+            }
+            let _permit = match self.limiter {
+                Some((limiter, priority)) => Some(limiter.acquire(priority).await),
+                None => None,
+            };
+            let start = Instant::now();
+            let res = match self.query_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, lookup(*backend)).await.ok(),
+                None => Some(lookup(*backend).await),
+            };
+            // only a failing node is worth another try, a missing DID stays missing
+            let mut pause = None;
+            let error = match res {
+                None => "timed out".to_string(),
+                Some(Err(err)) if err.is_infrastructure() => {
+                    let error = err.to_string();
+                    match Throttled::detect(&error) {
+                        Some(throttled) => {
+                            metrics::record_rpc_throttled(endpoint);
+                            let delay = match self.throttle {
+                                Some(throttle) => throttle.pause(endpoint, throttled),
+                                None => *pause.insert(throttled.delay(THROTTLE_PENALTY)),
+                            };
+                            format!(
+                                "throttled, paused for {}: {}",
+                                humantime::format_duration(delay),
+                                error
+                            )
+                        }
+                        None => error,
+                    }
+                }
+                Some(res) => return res,
+            };
+            attempts.push(QueryAttempt {
+                endpoint: endpoint.clone(),
+                duration: start.elapsed(),
+                error,
+            });
+            // without a shared throttle only this query pauses
+            if let (Some(pause), true) = (pause, attempt < self.retries) {
+                tokio::time::sleep(pause).await;
+            }
+        }
+        Err(Error::QueryFailed { storage, attempts })
+    }
+}
+
+#[async_trait]
+impl ChainBackend for QueryBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.query("did.did", |backend| backend.did(did, at)).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        self.query("attestation.attestations", |backend| {
+            backend.attestation(root_hash, at)
+        })
+        .await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.query("web3Names.owner", |backend| {
+            backend.web3_name_owner(name, at)
+        })
+        .await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.query("web3Names.names", |backend| backend.web3_name(owner, at))
+            .await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.query("ctype.ctypes", |backend| {
+            backend.ctype_creator(ctype_hash, at)
+        })
+        .await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.query("did.serviceEndpoints", |backend| {
+            backend.service_endpoints(did, at)
+        })
+        .await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.query("system.properties", |backend| backend.token())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures,
+        mock::MockBackend,
+        progress::{CheckStep, StepStatus},
+        utils::get_did_account_id,
+    };
+    use std::sync::Mutex;
+
+    // a node whose first DID lookups never answer
+    struct Hanging {
+        inner: MockBackend,
+        hangs: AtomicUsize,
+    }
+
+    impl Hanging {
+        fn new(hangs: usize) -> Self {
+            Hanging {
+                inner: fixtures::backend(),
+                hangs: AtomicUsize::new(hangs),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChainBackend for Hanging {
+        async fn did(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<DidDetails>, Error> {
+            let hangs = self.hangs.load(Ordering::Relaxed);
+            if hangs > 0 {
+                self.hangs.store(hangs - 1, Ordering::Relaxed);
+                std::future::pending::<()>().await;
+            }
+            self.inner.did(did, at).await
+        }
+
+        async fn attestation(
+            &self,
+            root_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            self.inner.attestation(root_hash, at).await
+        }
+
+        async fn web3_name_owner(
+            &self,
+            name: &str,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.inner.web3_name_owner(name, at).await
+        }
+
+        async fn web3_name(
+            &self,
+            owner: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            self.inner.web3_name(owner, at).await
+        }
+
+        async fn ctype_creator(
+            &self,
+            ctype_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.inner.ctype_creator(ctype_hash, at).await
+        }
+
+        async fn service_endpoints(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            self.inner.service_endpoints(did, at).await
+        }
+    }
+
+    // a node that takes a while for every DID lookup and counts the lookups in flight
+    struct Slow {
+        inner: MockBackend,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ChainBackend for Slow {
+        async fn did(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<DidDetails>, Error> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.inner.did(did, at).await
+        }
+
+        async fn attestation(
+            &self,
+            root_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            self.inner.attestation(root_hash, at).await
+        }
+
+        async fn web3_name_owner(
+            &self,
+            name: &str,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.inner.web3_name_owner(name, at).await
+        }
+
+        async fn web3_name(
+            &self,
+            owner: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            self.inner.web3_name(owner, at).await
+        }
+
+        async fn ctype_creator(
+            &self,
+            ctype_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.inner.ctype_creator(ctype_hash, at).await
+        }
+
+        async fn service_endpoints(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            self.inner.service_endpoints(did, at).await
+        }
+    }
+
+    // a node whose provider rejects the first DID lookups with a rate limit error
+    struct Throttling {
+        inner: MockBackend,
+        message: &'static str,
+        throttles: AtomicUsize,
+        calls: AtomicUsize,
+    }
+
+    impl Throttling {
+        fn new(throttles: usize, message: &'static str) -> Self {
+            Throttling {
+                inner: fixtures::backend(),
+                message,
+                throttles: AtomicUsize::new(throttles),
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChainBackend for Throttling {
+        async fn did(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<DidDetails>, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let throttles = self.throttles.load(Ordering::SeqCst);
+            if throttles > 0 {
+                self.throttles.store(throttles - 1, Ordering::SeqCst);
+                return Err(Error::ConnectionError(subxt::BasicError::Other(
+                    self.message.to_string(),
+                )));
+            }
+            self.inner.did(did, at).await
+        }
+
+        async fn attestation(
+            &self,
+            root_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            self.inner.attestation(root_hash, at).await
+        }
+
+        async fn web3_name_owner(
+            &self,
+            name: &str,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.inner.web3_name_owner(name, at).await
+        }
+
+        async fn web3_name(
+            &self,
+            owner: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            self.inner.web3_name(owner, at).await
+        }
+
+        async fn ctype_creator(
+            &self,
+            ctype_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.inner.ctype_creator(ctype_hash, at).await
+        }
+
+        async fn service_endpoints(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            self.inner.service_endpoints(did, at).await
+        }
+    }
+
+    #[derive(Default)]
+    struct Retries(Mutex<Vec<(String, usize)>>);
+
+    impl VerificationObserver for Retries {
+        fn on_step(&self, _step: CheckStep, _status: StepStatus<'_>) {}
+
+        fn on_retry(&self, what: &str, attempt: usize) {
+            self.0.lock().unwrap().push((what.to_string(), attempt));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_backend() {
+        let did = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let timeout = Some(Duration::from_millis(20));
+
+        // a query that hangs once is answered by the next attempt
+        let node = Hanging::new(1);
+        let retries = Retries::default();
+        let queries = QueryBackend::new("ws://node", &node)
+            .query_timeout(timeout)
+            .retries(2)
+            .observed(&retries);
+        assert!(queries.did(&did, None).await.unwrap().is_some());
+        assert_eq!(*retries.0.lock().unwrap(), vec![("did.did".to_string(), 2)]);
+
+        // retries go to the fallback
+        let node = Hanging::new(usize::MAX);
+        let fallback = Hanging::new(0);
+        let queries = QueryBackend::new("ws://node", &node)
+            .fallback("ws://fallback", &fallback)
+            .query_timeout(timeout)
+            .retries(1);
+        assert!(queries.did(&did, None).await.unwrap().is_some());
+
+        // every attempt is recorded once the retries are used up
+        let queries = QueryBackend::new("ws://node", &node)
+            .query_timeout(timeout)
+            .retries(1);
+        let err = queries.did(&did, None).await.unwrap_err();
+        match &err {
+            Error::QueryFailed { storage, attempts } => {
+                assert_eq!(*storage, "did.did");
+                assert_eq!(attempts.len(), 2);
+                for attempt in attempts {
+                    assert_eq!(attempt.endpoint, "ws://node");
+                    assert_eq!(attempt.error, "timed out");
+                    assert!(attempt.duration >= Duration::from_millis(20));
+                }
+            }
+            err => panic!("{:?}", err),
+        }
+        assert!(err.is_infrastructure());
+        assert!(err
+            .to_string()
+            .starts_with("Query of did.did failed after 2 attempts: ws://node: timed out after"));
+
+        // the other lookups are not bounded by a hanging one
+        assert!(queries
+            .attestation(&H256([0; 32]), None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_limited_queries() {
+        let did = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let node = Slow {
+            inner: fixtures::backend(),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        };
+        let limiter = RpcLimiter::new(2, Some(1));
+        // the wait for a permit does not time out the query
+        let background = QueryBackend::new("ws://node", &node)
+            .query_timeout(Some(Duration::from_millis(100)))
+            .limited(Some(&limiter), Priority::Background);
+        let interactive = QueryBackend::new("ws://node", &node)
+            .query_timeout(Some(Duration::from_millis(100)))
+            .limited(Some(&limiter), Priority::Interactive);
+
+        let start = Instant::now();
+        let refreshes = futures::future::join_all((0..6).map(|_| background.did(&did, None)));
+        let request = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let start = Instant::now();
+            interactive.did(&did, None).await.unwrap();
+            start.elapsed()
+        };
+        let (refreshes, latency) = futures::join!(refreshes, request);
+        assert!(refreshes.iter().all(|res| matches!(res, Ok(Some(_)))));
+
+        // the background lookups run one after another, the request does not wait for them
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(latency < Duration::from_millis(100), "{:?}", latency);
+        assert_eq!(node.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_throttled_queries() {
+        let did = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+
+        // the pause the provider asked for holds back every query to the endpoint
+        let node = Throttling::new(1, "HTTP error: 429 Too Many Requests, retry after 100ms");
+        let throttle = Throttle::default();
+        let queries = QueryBackend::new("ws://node", &node)
+            .retries(1)
+            .throttled(Some(&throttle));
+        let start = Instant::now();
+        let throttled = queries.did(&did, None);
+        let others = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            futures::future::join_all((0..3).map(|_| queries.did(&did, None))).await
+        };
+        let (throttled, others) = futures::join!(throttled, others);
+        assert!(throttled.unwrap().is_some());
+        assert!(others.iter().all(|res| matches!(res, Ok(Some(_)))));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(throttle.throttled(), 1);
+        assert_eq!(node.calls.load(Ordering::SeqCst), 5);
+
+        // without a delay from the provider the penalty is used, every attempt is recorded
+        let node = Throttling::new(usize::MAX, "rate limit exceeded");
+        let throttle = Throttle::new(Duration::from_millis(20));
+        let queries = QueryBackend::new("ws://node", &node)
+            .retries(1)
+            .throttled(Some(&throttle));
+        let start = Instant::now();
+        match queries.did(&did, None).await.unwrap_err() {
+            Error::QueryFailed { attempts, .. } => {
+                assert_eq!(attempts.len(), 2);
+                for attempt in attempts {
+                    assert_eq!(
+                        attempt.error,
+                        "throttled, paused for 20ms: Connection error: Other error: rate limit exceeded"
+                    );
+                }
+            }
+            err => panic!("{:?}", err),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(throttle.throttled(), 2);
+
+        // a query without a shared throttle only pauses itself
+        let queries = QueryBackend::new("ws://node", &node);
+        match queries.did(&did, None).await.unwrap_err() {
+            Error::QueryFailed { attempts, .. } => assert!(
+                attempts[0].error.starts_with("throttled, paused for 5s: "),
+                "{}",
+                attempts[0].error
+            ),
+            err => panic!("{:?}", err),
+        }
+    }
+}
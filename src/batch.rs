@@ -0,0 +1,1787 @@
+use futures::{
+    stream::{self, StreamExt},
+    Future,
+};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    archive::{self, ArchiveOptions},
+    backend::{ChainBackend, CountingBackend},
+    credential::{ClaimRequirements, Credential},
+    ctypes::CtypeNames,
+    decompress,
+    did::{self, ServiceEndpoint},
+    errors::Error,
+    metrics::{self, Check, CheckTimings},
+    porcelain,
+    progress::{self, CheckStep},
+    registry::IssuerRegistry,
+    resolver,
+    stats::{BatchStats, Latency},
+    status::{CheckRecorder, CheckReport, CheckWarning, Verdict},
+    utils::{get_did_account_id, to_sorted_json, DidUrl, ParseOptions},
+};
+
+/// One credential of a batch, read into memory before the verification starts
+#[derive(Debug, Clone)]
+pub struct BatchInput {
+    /// Where the credential comes from, i.e. "creds/a.json" or "creds.ndjson:3"
+    pub source: String,
+    pub data: Vec<u8>,
+}
+
+impl AsRef<BatchInput> for BatchInput {
+    fn as_ref(&self) -> &BatchInput {
+        self
+    }
+}
+
+/// How the results of a batch are reported
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    /// One line of tab separated fields per credential, see `porcelain`
+    Porcelain,
+    /// One row per credential with a header row
+    Csv,
+    /// JUnit XML with one testcase per credential, i.e. for CI pipelines
+    Junit,
+}
+
+/// Order of the results in human and csv output, ties are broken by the source
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    /// Failures first, grouped by their error code
+    Status,
+    File,
+    Owner,
+    Attester,
+}
+
+/// How many credentials of a batch may be invalid before the batch fails,
+/// a count like `10` or a percentage of the batch like `0.5%`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaxFailures {
+    Count(usize),
+    Percent(f64),
+}
+
+impl Default for MaxFailures {
+    fn default() -> Self {
+        MaxFailures::Count(0)
+    }
+}
+
+impl std::str::FromStr for MaxFailures {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => {
+                    Ok(MaxFailures::Percent(percent))
+                }
+                _ => Err(format!("{} is not a percentage between 0% and 100%", s)),
+            },
+            None => s
+                .parse()
+                .map(MaxFailures::Count)
+                .map_err(|_| format!("{} is neither a count nor a percentage", s)),
+        }
+    }
+}
+
+impl MaxFailures {
+    /// More of the batch failed than allowed
+    pub fn exceeded(&self, failed: usize, total: usize) -> bool {
+        match *self {
+            MaxFailures::Count(max) => failed > max,
+            MaxFailures::Percent(percent) => failed as f64 * 100.0 > percent * total as f64,
+        }
+    }
+}
+
+/// Version of the json report. It is bumped whenever a field is added, removed or changes its
+/// meaning, and `--report-version` writes the shape of the versions since `MIN_REPORT_VERSION`:
+/// - 1: the results and the summary
+/// - 2: `report_version`, the `status` and the `checks` of each result
+/// - 3: `key_removed_at_block` of results whose key was accepted within --key-grace-blocks
+/// - 4: `throttled` of the summary
+pub const REPORT_VERSION: u32 = 4;
+
+/// Oldest version of the json report that can still be written
+pub const MIN_REPORT_VERSION: u32 = 1;
+
+/// Options of a batch run
+#[derive(Debug)]
+pub struct BatchOptions<'a> {
+    pub allowed_issuers: &'a [&'a str],
+    pub challenge: Option<&'a str>,
+    pub requirements: &'a ClaimRequirements,
+    /// Number of credentials verified at the same time
+    pub concurrency: usize,
+    pub output: OutputFormat,
+    pub quiet: bool,
+    /// Only report the failures and the summary, the progress bar is still drawn
+    pub summary_only: bool,
+    /// Results are reported sorted at the end instead of as they come in
+    pub sort_by: Option<SortBy>,
+    /// Look up the service endpoints of the owners of valid credentials for the json report
+    pub include_services: bool,
+    /// Leave the flattened claims of valid credentials out of the json report
+    pub redact_claims: bool,
+    /// Leave out the parts of errors that can quote claim values, see `Error::without_pii`
+    pub redact_pii: bool,
+    /// How the credentials are parsed, by default json or YAML by the extension of their source
+    pub parse: ParseOptions,
+    /// All credentials must have the same owner
+    pub require_same_owner: bool,
+    /// All credentials must be signed with the same key, and so have the same owner
+    pub require_same_key: bool,
+    /// Stops the batch, the credentials that are not verified yet fail with a timeout
+    pub cancel: CancellationToken,
+    /// Names the ctypes of the report
+    pub ctype_names: &'a CtypeNames,
+    /// Shape of the json report, see `REPORT_VERSION`
+    pub report_version: u32,
+}
+
+/// The verification result of one credential
+#[derive(Debug)]
+pub struct BatchResult {
+    pub source: String,
+    /// Endpoint that served the lookups if it is not the same for the whole batch, i.e. of a manifest
+    pub endpoint: Option<String>,
+    /// Root hash given in the credential, `None` if it could not be parsed
+    pub root_hash: Option<String>,
+    pub owner: Option<String>,
+    /// Hash of the ctype of the claim, `None` if the credential could not be parsed
+    pub ctype_hash: Option<String>,
+    /// Key the owner signed the credential with, empty if it was inferred, see `Credential::key_inferred`
+    pub key_uri: Option<String>,
+    /// DID of the attester of a valid credential
+    pub attester: Option<String>,
+    /// Service endpoints of the owner of a valid credential if they were asked for
+    pub services: Option<Vec<ServiceEndpoint>>,
+    /// Issuers the credential was checked against if they are not the same for the whole batch
+    pub allowlist: Option<Allowlist>,
+    /// Flattened claim of a valid credential, see `Claim::flatten`, `None` if claims are redacted
+    pub claims: Option<Vec<(String, serde_json::Value)>>,
+    /// The DID document of the owner was resolved via the HTTP resolver, not read from the chain
+    pub resolved_via_http: bool,
+    /// The first block the signing key was missing from the DID document of the owner at, if it
+    /// was accepted within the grace window of --key-grace-blocks
+    pub key_removed_at: Option<u32>,
+    /// How each check ended, `None` if the credential could not be parsed, see `checks`
+    pub checks: Option<CheckReport>,
+    pub result: Result<(), Error>,
+    /// End-to-end duration including parsing
+    pub duration: Duration,
+    pub timings: CheckTimings,
+}
+
+/// The issuers a credential of a manifest was checked against and where they come from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Allowlist {
+    /// The entry has its own allowed issuers or trust policy instead of the issuers of the run
+    pub overridden: bool,
+    /// Registry of the trust policy of the entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_policy: Option<String>,
+    /// DIDs of the trusted issuers, sorted
+    pub issuers: Vec<String>,
+}
+
+/// The results of a batch in input order
+#[derive(Debug)]
+pub struct BatchReport {
+    pub results: Vec<BatchResult>,
+    /// Number of storage lookups sent to the backend
+    pub rpc_calls: usize,
+    /// Number of storage lookups the RPC provider throttled, see `throttle::Throttle`
+    pub throttled: usize,
+    /// The owner all credentials share if the batch requires one, see `common_owner`
+    pub owner: Option<Result<String, Error>>,
+    /// Registry the allowed issuers were read from, if they were
+    pub registry: Option<IssuerRegistry>,
+}
+
+/// Directories, newline delimited json files, also compressed, and archives are verified as a
+/// batch
+pub fn is_batch(file: &str) -> bool {
+    let path = Path::new(file);
+    path.is_dir()
+        || archive::is_archive(file)
+        || matches!(
+            Path::new(decompress::strip_extension(file))
+                .extension()
+                .and_then(|ext| ext.to_str()),
+            Some("ndjson") | Some("jsonl")
+        )
+}
+
+/// Read all `.json`, YAML and `.cbor` files of a directory in name order, all lines of a newline delimited json file,
+/// or the entries of an archive selected by the archive options. A compressed newline delimited
+/// json file is decompressed as a whole, files of a directory when they are parsed.
+pub fn read_inputs(
+    file: &str,
+    archive: &ArchiveOptions,
+    parse: &ParseOptions,
+) -> Result<Vec<BatchInput>, Error> {
+    let path = Path::new(file);
+    if archive::is_archive(file) {
+        archive::read_archive(file, archive)
+    } else if path.is_dir() {
+        let mut paths = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| {
+            let name = path.to_string_lossy();
+            path.is_file()
+                && Path::new(decompress::strip_extension(&name))
+                    .extension()
+                    .is_some_and(|ext| ["json", "yaml", "yml", "cbor"].iter().any(|e| ext == *e))
+        });
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                Ok(BatchInput {
+                    data: std::fs::read(&path)?,
+                    source: path.display().to_string(),
+                })
+            })
+            .collect()
+    } else {
+        let data = std::fs::read(path)?;
+        let text = String::from_utf8(parse.decompress(&data)?.into_owned())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        Ok(text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| BatchInput {
+                source: format!("{}:{}", file, i + 1),
+                data: line.as_bytes().to_vec(),
+            })
+            .collect())
+    }
+}
+
+async fn owner_services(
+    backend: &dyn ChainBackend,
+    owner: &str,
+) -> Result<Vec<ServiceEndpoint>, Error> {
+    did::service_endpoints(backend, &get_did_account_id(owner)?, None).await
+}
+
+// parse and verify a single credential of the batch
+#[tracing::instrument(skip_all, fields(source = %input.source))]
+pub(crate) async fn verify_input(
+    backend: &dyn ChainBackend,
+    input: &BatchInput,
+    options: &BatchOptions<'_>,
+) -> BatchResult {
+    let start = Instant::now();
+    let mut timings = CheckTimings::default();
+    let recorder = CheckRecorder::default();
+    let (root_hash, owner, ctype_hash, key_uri, claims, checks, result) =
+        match options.parse.parse(&input.source, &input.data) {
+            Ok(cred) => {
+                let result = cred
+                    .verify_timed(
+                        backend,
+                        options.allowed_issuers,
+                        options.challenge,
+                        &mut timings,
+                        &recorder,
+                    )
+                    .await
+                    .and_then(|attester| {
+                        progress::observe(&recorder, CheckStep::Requirements, || {
+                            cred.check_requirements(options.requirements)
+                        })?;
+                        Ok(attester)
+                    });
+                let checks = recorder.report(&cred, options.challenge, options.requirements);
+                let claims = match (&result, options.redact_claims) {
+                    (Ok(_), false) => Some(cred.claim.flatten()),
+                    _ => None,
+                };
+                (
+                    Some(cred.root_hash),
+                    Some(cred.claim.owner),
+                    Some(cred.claim.ctype_hash),
+                    Some(cred.claimer_signature.key_uri),
+                    claims,
+                    Some(checks),
+                    result,
+                )
+            }
+            Err(err) => (None, None, None, None, None, None, Err(err)),
+        };
+    let (attester, result) = match result {
+        Ok(attester) => (Some(attester), Ok(())),
+        Err(err) => (None, Err(err)),
+    };
+    let (services, result) = match (&owner, result) {
+        (Some(owner), Ok(())) if options.include_services => {
+            match owner_services(backend, owner).await {
+                Ok(services) => (Some(services), Ok(())),
+                Err(err) => (None, Err(err)),
+            }
+        }
+        (_, result) => (None, result),
+    };
+    let result = match result {
+        Err(err) if options.redact_pii => Err(err.without_pii()),
+        result => result,
+    };
+    BatchResult {
+        source: input.source.clone(),
+        endpoint: None,
+        root_hash,
+        owner,
+        ctype_hash,
+        key_uri,
+        attester,
+        services,
+        allowlist: None,
+        claims,
+        resolved_via_http: false,
+        key_removed_at: None,
+        checks,
+        result,
+        duration: start.elapsed(),
+        timings,
+    }
+}
+
+// the progress bar is only drawn for humans watching a terminal
+fn progress_bar(total: usize, options: &BatchOptions) -> ProgressBar {
+    let machine_readable = options.output != OutputFormat::Human && !options.summary_only;
+    if options.quiet || machine_readable || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stderr());
+    bar.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} {per_sec} {msg} ETA {eta}")
+            .expect("valid progress template"),
+    );
+    bar.set_message("0 failed");
+    bar
+}
+
+/// Verify all credentials of the batch, `concurrency` of them at the same time.
+/// Results are printed as they come in, the returned report keeps the input order.
+pub async fn verify_batch(
+    backend: &dyn ChainBackend,
+    inputs: &[BatchInput],
+    options: &BatchOptions<'_>,
+) -> BatchReport {
+    let backend = CountingBackend::new(backend);
+    let results = run_batch(inputs, options, |input| {
+        verify_input(&backend, input, options)
+    })
+    .await;
+    BatchReport::new(results, backend.calls(), options)
+}
+
+/// Run `verify` on all inputs like `verify_batch` does, with the progress bar, live output and
+/// cancellation, for batches whose credentials are not all verified the same way
+pub async fn run_batch<'i, T, F, Fut>(
+    inputs: &'i [T],
+    options: &BatchOptions<'_>,
+    verify: F,
+) -> Vec<BatchResult>
+where
+    T: AsRef<BatchInput>,
+    F: Fn(&'i T) -> Fut,
+    Fut: Future<Output = BatchResult>,
+{
+    let bar = progress_bar(inputs.len(), options);
+    let failed = AtomicUsize::new(0);
+
+    let live = !options.quiet && !options.summary_only && options.sort_by.is_none();
+    let results = stream::iter(inputs)
+        .map(|input| {
+            let verification = verify(input);
+            async move {
+                let timer = metrics::time_verification();
+                let result = verification.await;
+                timer.observe_duration();
+                metrics::record_outcome(&result.result);
+                result
+            }
+        })
+        // results come out in input order however fast each verification is,
+        // so the live lines are the same from run to run
+        .buffered(options.concurrency.max(1))
+        .inspect(|result| {
+            if result.result.is_err() {
+                let failed = failed.fetch_add(1, Ordering::Relaxed) + 1;
+                bar.set_message(format!("{} failed", failed));
+            }
+            if options.output == OutputFormat::Human && live {
+                // printing through the bar keeps the lines above it
+                bar.suspend(|| match &result.result {
+                    Ok(()) => println!("✅ {}{}", result.label(), result.notes()),
+                    Err(err) => println!("❌ {}: {}", result.label(), err),
+                });
+            }
+            bar.inc(1);
+        })
+        .take_until(options.cancel.cancelled())
+        .collect::<Vec<_>>()
+        .await;
+    bar.finish_and_clear();
+
+    // the credentials that were in flight or not started when the batch was cancelled
+    let mut results = results;
+    results.extend(
+        inputs[results.len()..]
+            .iter()
+            .map(|input| BatchResult::failed(&input.as_ref().source, Error::Timeout)),
+    );
+    results
+}
+
+#[derive(Serialize)]
+struct JsonResult<'a> {
+    source: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<&'a str>,
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ctype_hash: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ctype_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    key_inferred: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    light_did_owner: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    resolved_via_http_resolver: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_removed_at_block: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Verdict>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<CheckReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    services: Option<&'a [ServiceEndpoint]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowlist: Option<&'a Allowlist>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claims: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl<'a> JsonResult<'a> {
+    fn new(r: &'a BatchResult, ctype_names: &'a CtypeNames, version: u32) -> Self {
+        let checks = r.checks().filter(|_| version >= 2);
+        JsonResult {
+            source: &r.source,
+            endpoint: r.endpoint.as_deref(),
+            valid: r.result.is_ok(),
+            ctype_hash: r.ctype_hash.as_deref(),
+            ctype_name: r
+                .ctype_hash
+                .as_deref()
+                .and_then(|hash| ctype_names.name(hash)),
+            key_inferred: r.key_inferred(),
+            light_did_owner: r.light_owner(),
+            resolved_via_http_resolver: r.resolved_via_http,
+            key_removed_at_block: r.key_removed_at.filter(|_| version >= 3),
+            error: r.result.as_ref().err().map(|err| err.to_string()),
+            code: r.result.as_ref().err().map(Error::code),
+            status: checks.as_ref().map(CheckReport::verdict),
+            checks,
+            services: r.services.as_deref(),
+            allowlist: r.allowlist.as_ref(),
+            claims: r
+                .claims
+                .as_ref()
+                .map(|claims| claims.iter().cloned().collect()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonSummary<'a> {
+    total: usize,
+    valid: usize,
+    invalid: usize,
+    /// Included in `invalid`
+    timed_out: usize,
+    /// Not verified because the node failed, included in `invalid`
+    incomplete: usize,
+    /// Invalid credentials by error code
+    failures: BTreeMap<&'static str, usize>,
+    rpc_calls: usize,
+    /// Storage lookups the RPC provider throttled, since version 4
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throttled: Option<usize>,
+    /// The credentials were parsed as JSON5
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    lenient_json: bool,
+    /// Owner of all credentials if the batch requires them to have the same owner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<&'a str>,
+    /// Why they don't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inconsistency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issuer_registry: Option<JsonRegistry<'a>>,
+    /// End-to-end latency as "total" and the latency of each check by its label
+    latency_ms: BTreeMap<&'static str, Latency>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonRegistry<'a> {
+    did: &'a str,
+    fetched_at: String,
+    cached: bool,
+    members: usize,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report_version: Option<u32>,
+    results: Vec<JsonResult<'a>>,
+    summary: JsonSummary<'a>,
+}
+
+/// Quote a csv field if it contains a separator, a quote or a line break
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The classname and name of the testcase of a credential: the directory and the file, or the
+/// file and the line of a newline delimited json file
+pub fn junit_case(source: &str) -> (&str, &str) {
+    if let Some((file, line)) = source.rsplit_once(':') {
+        if !line.is_empty() && line.chars().all(|c| c.is_ascii_digit()) {
+            return (file, line);
+        }
+    }
+    match source.rsplit_once('/') {
+        Some((dir, file)) => (dir, file),
+        None => (".", source),
+    }
+}
+
+/// Escape text for XML attributes and elements. Characters XML 1.0 does not allow, like most
+/// control characters, are replaced
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' => escaped.push_str("&#9;"),
+            c if c < ' ' || c == '\u{FFFE}' || c == '\u{FFFF}' => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl BatchResult {
+    /// The result of a credential that failed before it was parsed
+    pub fn failed(source: &str, err: Error) -> Self {
+        BatchResult {
+            source: source.to_string(),
+            endpoint: None,
+            root_hash: None,
+            owner: None,
+            ctype_hash: None,
+            key_uri: None,
+            attester: None,
+            services: None,
+            allowlist: None,
+            claims: None,
+            resolved_via_http: false,
+            key_removed_at: None,
+            checks: None,
+            result: Err(err),
+            duration: Duration::ZERO,
+            timings: CheckTimings::default(),
+        }
+    }
+
+    /// The result of a single credential that was verified outside of a batch
+    pub fn verified(
+        source: &str,
+        cred: &Credential,
+        checks: CheckReport,
+        result: Result<(), Error>,
+    ) -> Self {
+        BatchResult {
+            source: source.to_string(),
+            endpoint: None,
+            root_hash: Some(cred.root_hash.clone()),
+            owner: Some(cred.claim.owner.clone()),
+            ctype_hash: Some(cred.claim.ctype_hash.clone()),
+            key_uri: Some(cred.claimer_signature.key_uri.clone()),
+            attester: None,
+            services: None,
+            allowlist: None,
+            claims: None,
+            resolved_via_http: false,
+            key_removed_at: None,
+            checks: Some(checks),
+            result,
+            duration: Duration::ZERO,
+            timings: CheckTimings::default(),
+        }
+    }
+
+    /// How each check ended, an owner that was resolved via HTTP or a signing key that was removed
+    /// weakens the signature check
+    pub fn checks(&self) -> Option<CheckReport> {
+        let mut checks = self.checks.clone()?;
+        if self.resolved_via_http {
+            checks.warn(CheckStep::Signature, CheckWarning::ResolvedViaHttp);
+        }
+        if self.key_removed_at.is_some() {
+            checks.warn(CheckStep::Signature, CheckWarning::KeyRemoved);
+        }
+        Some(checks)
+    }
+
+    /// The result failed after its checks, i.e. for too few confirmations of the attestation, or
+    /// its error was explained, see `CheckReport::fail`
+    pub fn fail_check(&mut self, check: CheckStep) {
+        if let (Err(err), Some(checks)) = (&self.result, &mut self.checks) {
+            checks.fail(check, err);
+        }
+    }
+
+    /// The entry of the result in the json report
+    pub fn to_json(&self, ctype_names: &CtypeNames) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(&JsonResult::new(
+            self,
+            ctype_names,
+            REPORT_VERSION,
+        ))?)
+    }
+
+    // the source, with the endpoint if it is not the default one
+    fn label(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) => format!("{} ({})", self.source, endpoint),
+            None => self.source.clone(),
+        }
+    }
+
+    /// The owner signed a credential that names no key, any of the owner's keys may have signed it
+    pub fn key_inferred(&self) -> bool {
+        self.key_uri.as_deref() == Some("")
+    }
+
+    /// The owner is a light DID, see `Credential::light_owner`
+    pub fn light_owner(&self) -> bool {
+        self.owner
+            .as_deref()
+            .is_some_and(|owner| DidUrl::parse(owner).is_ok_and(|did| did.is_light()))
+    }
+
+    // marks the weaker binding of credentials whose key was inferred, whose owner is a light DID
+    // or whose key was removed and the weaker trust in owners that were resolved via HTTP
+    fn notes(&self) -> String {
+        let mut notes = String::new();
+        if self.key_inferred() {
+            notes.push_str(" (key inferred)");
+        }
+        if self.light_owner() {
+            notes.push_str(" (light DID owner)");
+        }
+        if self.resolved_via_http {
+            notes.push_str(&format!(" ({})", resolver::RESOLVED_VIA));
+        }
+        if let Some(block) = self.key_removed_at {
+            notes.push_str(&format!(" (key removed at block {})", block));
+        }
+        notes
+    }
+
+    /// The `--porcelain` line of the result
+    pub fn porcelain(&self) -> String {
+        match (&self.result, &self.attester) {
+            (Ok(()), Some(attester)) => porcelain::valid(
+                self.root_hash.as_deref().unwrap_or(porcelain::MISSING),
+                self.owner.as_deref().unwrap_or(porcelain::MISSING),
+                attester,
+            ),
+            (Ok(()), None) => unreachable!("valid credentials have an attester"),
+            (Err(err), _) => porcelain::invalid(self.root_hash.as_deref(), err),
+        }
+    }
+}
+
+// the sources of the credentials grouped by a value, in the order the values first appear
+fn group_sources<'r>(
+    results: &'r [BatchResult],
+    value: impl Fn(&'r BatchResult) -> Option<&'r str>,
+) -> Vec<(&'r str, Vec<&'r str>)> {
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    for result in results {
+        let value = match value(result) {
+            Some(value) => value,
+            None => continue,
+        };
+        match groups.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, sources)) => sources.push(&result.source),
+            None => groups.push((value, vec![&result.source])),
+        }
+    }
+    groups
+}
+
+// the owners or keys with their credentials, i.e. "did:kilt:4a (a.json, b.json), did:kilt:4b (c.json)"
+fn format_groups(groups: &[(&str, Vec<&str>)]) -> String {
+    let groups: Vec<_> = groups
+        .iter()
+        .map(|(value, sources)| format!("{} ({})", value, sources.join(", ")))
+        .collect();
+    groups.join(", ")
+}
+
+/// The owner all credentials of a batch share, `None` if no credential could be parsed.
+/// With `same_key` they must all be signed with the same key as well. Credentials that could
+/// not be parsed have no owner and are left out, they fail the batch anyway.
+pub fn common_owner(results: &[BatchResult], same_key: bool) -> Result<Option<String>, Error> {
+    let owners = group_sources(results, |r| r.owner.as_deref());
+    if owners.len() > 1 {
+        return Err(Error::OwnerInconsistency(format!(
+            "{} different owners: {}",
+            owners.len(),
+            format_groups(&owners)
+        )));
+    }
+    let keys = group_sources(results, |r| r.key_uri.as_deref());
+    if same_key && keys.len() > 1 {
+        return Err(Error::OwnerInconsistency(format!(
+            "signed with {} different keys: {}",
+            keys.len(),
+            format_groups(&keys)
+        )));
+    }
+    Ok(owners.first().map(|(owner, _)| owner.to_string()))
+}
+
+impl BatchReport {
+    /// The report of the results, with their common owner if the options require one
+    pub fn new(results: Vec<BatchResult>, rpc_calls: usize, options: &BatchOptions) -> Self {
+        let owner = if options.require_same_owner || options.require_same_key {
+            common_owner(&results, options.require_same_key).transpose()
+        } else {
+            None
+        };
+        BatchReport {
+            results,
+            rpc_calls,
+            throttled: 0,
+            owner,
+            registry: None,
+        }
+    }
+
+    pub fn invalid(&self) -> usize {
+        self.results.iter().filter(|r| r.result.is_err()).count()
+    }
+
+    /// Number of credentials that could not be verified because the node failed
+    pub fn incomplete(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| matches!(&r.result, Err(err) if err.is_infrastructure()))
+            .count()
+    }
+
+    /// Number of credentials whose owner was resolved via the HTTP resolver
+    pub fn resolved_via_http(&self) -> usize {
+        self.results.iter().filter(|r| r.resolved_via_http).count()
+    }
+
+    /// Number of credentials by the hash of their ctype, the most frequent first
+    pub fn ctypes(&self) -> Vec<(&str, usize)> {
+        let mut ctypes = BTreeMap::new();
+        for hash in self.results.iter().filter_map(|r| r.ctype_hash.as_deref()) {
+            *ctypes.entry(hash).or_insert(0) += 1;
+        }
+        let mut ctypes: Vec<_> = ctypes.into_iter().collect();
+        ctypes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        ctypes
+    }
+
+    /// Number of invalid credentials by error code, the most frequent first
+    pub fn failures(&self) -> Vec<(&'static str, usize)> {
+        let mut failures = BTreeMap::new();
+        for r in self.results.iter() {
+            if let Err(err) = &r.result {
+                *failures.entry(err.code()).or_insert(0) += 1;
+            }
+        }
+        let mut failures: Vec<_> = failures.into_iter().collect();
+        failures.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        failures
+    }
+
+    /// Number of credentials that were not verified before the batch was cancelled
+    pub fn timed_out(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| matches!(r.result, Err(Error::Timeout)))
+            .count()
+    }
+
+    /// Latency histograms of all verifications of the batch that completed
+    pub fn stats(&self) -> BatchStats {
+        let mut stats = BatchStats::default();
+        for result in self
+            .results
+            .iter()
+            .filter(|r| !matches!(r.result, Err(Error::Timeout)))
+        {
+            stats.record(result.duration, &result.timings);
+        }
+        stats
+    }
+
+    // the end-to-end latency followed by the latency of every check that ran
+    fn latencies(stats: &BatchStats) -> Vec<(&'static str, Latency)> {
+        let total = stats.total().map(|latency| ("total", latency));
+        total
+            .into_iter()
+            .chain(
+                Check::ALL.iter().filter_map(|check| {
+                    stats.check(*check).map(|latency| (check.label(), latency))
+                }),
+            )
+            .collect()
+    }
+
+    /// The results in the given order, stable for equal keys in input order
+    pub fn sorted(&self, by: SortBy) -> Vec<&BatchResult> {
+        let mut results: Vec<&BatchResult> = self.results.iter().collect();
+        // missing owners and attesters go last
+        let key = |r: &BatchResult| match by {
+            SortBy::Status => (
+                r.result.is_ok(),
+                r.result
+                    .as_ref()
+                    .err()
+                    .map(Error::code)
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            SortBy::File => (false, String::new()),
+            SortBy::Owner => (r.owner.is_none(), r.owner.clone().unwrap_or_default()),
+            SortBy::Attester => (r.attester.is_none(), r.attester.clone().unwrap_or_default()),
+        };
+        results.sort_by(|a, b| key(a).cmp(&key(b)).then_with(|| a.source.cmp(&b.source)));
+        results
+    }
+
+    /// Write the summary, for json output together with all results in input order.
+    /// Porcelain output only has the line of each result, csv a row for each result.
+    /// With `summary_only` the results are limited to the failures, human output lists them
+    /// before the summary, as it does with all results if they are sorted.
+    pub fn write(&self, out: &mut dyn Write, options: &BatchOptions) -> Result<(), Error> {
+        let (output, summary_only) = (options.output, options.summary_only);
+        let total = self.results.len();
+        let invalid = self.invalid();
+        let latencies = Self::latencies(&self.stats());
+        let sorted = match options.sort_by {
+            Some(by)
+                if !matches!(
+                    output,
+                    OutputFormat::Json | OutputFormat::Porcelain | OutputFormat::Junit
+                ) =>
+            {
+                self.sorted(by)
+            }
+            _ => self.results.iter().collect(),
+        };
+        let results = sorted
+            .into_iter()
+            .filter(|r| !summary_only || r.result.is_err());
+        match output {
+            OutputFormat::Human => {
+                if summary_only && invalid > 0 {
+                    writeln!(out, "Failures:")?;
+                }
+                if summary_only || options.sort_by.is_some() {
+                    for r in results {
+                        match &r.result {
+                            Ok(()) => writeln!(out, "✅ {}{}", r.label(), r.notes())?,
+                            Err(err) => {
+                                writeln!(out, "❌ {} [{}]: {}", r.label(), err.code(), err)?
+                            }
+                        }
+                    }
+                }
+                writeln!(
+                    out,
+                    "{} credentials: {} valid, {} invalid",
+                    total,
+                    total - invalid,
+                    invalid
+                )?;
+                for (code, count) in self.failures() {
+                    writeln!(out, "{:>8} {}", count, code)?;
+                }
+                let ctypes = self.ctypes();
+                if !ctypes.is_empty() {
+                    writeln!(out, "By ctype:")?;
+                }
+                for (hash, count) in ctypes {
+                    writeln!(out, "{:>8} {}", count, options.ctype_names.describe(hash))?;
+                }
+                if self.timed_out() > 0 {
+                    writeln!(out, "{} not verified before the timeout", self.timed_out())?;
+                }
+                if self.incomplete() > 0 {
+                    writeln!(
+                        out,
+                        "{} not verified because of infrastructure errors, the run is incomplete",
+                        self.incomplete()
+                    )?;
+                }
+                if self.resolved_via_http() > 0 {
+                    writeln!(
+                        out,
+                        "{} credentials have an owner {}, not read from the chain",
+                        self.resolved_via_http(),
+                        resolver::RESOLVED_VIA
+                    )?;
+                }
+                match &self.owner {
+                    Some(Ok(owner)) => writeln!(out, "All credentials are owned by {}", owner)?,
+                    Some(Err(err)) => writeln!(out, "❌ {}", err)?,
+                    None => {}
+                }
+                if let Some(registry) = &self.registry {
+                    writeln!(out, "{}", registry.describe())?;
+                }
+                if options.parse.lenient_json {
+                    writeln!(out, "Credentials were parsed leniently as JSON5")?;
+                }
+                if !latencies.is_empty() {
+                    writeln!(
+                        out,
+                        "{:<16}{:>10}{:>10}{:>10}{:>10}{:>10}",
+                        "latency (ms)", "min", "p50", "p90", "p99", "max"
+                    )?;
+                    for (name, l) in latencies.iter() {
+                        writeln!(
+                            out,
+                            "{:<16}{:>10.3}{:>10.3}{:>10.3}{:>10.3}{:>10.3}",
+                            name, l.min, l.p50, l.p90, l.p99, l.max
+                        )?;
+                    }
+                }
+                writeln!(out, "{} RPC calls", self.rpc_calls)?;
+                if self.throttled > 0 {
+                    writeln!(out, "{} throttled by the RPC provider", self.throttled)?;
+                }
+            }
+            OutputFormat::Json => {
+                let version = options.report_version;
+                let report = JsonReport {
+                    report_version: Some(version).filter(|&version| version >= 2),
+                    results: results
+                        .map(|r| JsonResult::new(r, options.ctype_names, version))
+                        .collect(),
+                    summary: JsonSummary {
+                        total,
+                        valid: total - invalid,
+                        invalid,
+                        timed_out: self.timed_out(),
+                        incomplete: self.incomplete(),
+                        failures: self.failures().into_iter().collect(),
+                        rpc_calls: self.rpc_calls,
+                        throttled: Some(self.throttled).filter(|_| version >= 4),
+                        lenient_json: options.parse.lenient_json,
+                        owner: self.owner.as_ref().and_then(|owner| owner.as_deref().ok()),
+                        inconsistency: self
+                            .owner
+                            .as_ref()
+                            .and_then(|owner| owner.as_ref().err())
+                            .map(|err| err.to_string()),
+                        issuer_registry: self.registry.as_ref().map(|registry| JsonRegistry {
+                            did: &registry.did,
+                            fetched_at: registry.fetched_at(),
+                            cached: registry.cached,
+                            members: registry.members.len(),
+                        }),
+                        latency_ms: latencies.into_iter().collect(),
+                    },
+                };
+                writeln!(out, "{}", to_sorted_json(&report)?)?;
+            }
+            OutputFormat::Porcelain => {
+                for r in results {
+                    writeln!(out, "{}", r.porcelain())?;
+                }
+            }
+            OutputFormat::Csv => {
+                writeln!(out, "source,status,code,root_hash,owner,attester,endpoint")?;
+                for r in results {
+                    let fields = [
+                        r.source.as_str(),
+                        if r.result.is_ok() { "valid" } else { "invalid" },
+                        r.result.as_ref().err().map(Error::code).unwrap_or_default(),
+                        r.root_hash.as_deref().unwrap_or_default(),
+                        r.owner.as_deref().unwrap_or_default(),
+                        r.attester.as_deref().unwrap_or_default(),
+                        r.endpoint.as_deref().unwrap_or_default(),
+                    ];
+                    let row: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                    writeln!(out, "{}", row.join(","))?;
+                }
+            }
+            OutputFormat::Junit => {
+                let results: Vec<_> = results.collect();
+                let failures = results.iter().filter(|r| r.result.is_err()).count();
+                let time: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+                writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+                writeln!(
+                    out,
+                    r#"<testsuites tests="{}" failures="{}" time="{:.3}">"#,
+                    results.len(),
+                    failures,
+                    time
+                )?;
+                writeln!(
+                    out,
+                    r#"  <testsuite name="kilt-verify" tests="{}" failures="{}" errors="0" skipped="0" time="{:.3}">"#,
+                    results.len(),
+                    failures,
+                    time
+                )?;
+                for r in results {
+                    let (classname, name) = junit_case(&r.source);
+                    write!(
+                        out,
+                        r#"    <testcase classname="{}" name="{}" time="{:.3}""#,
+                        xml_escape(classname),
+                        xml_escape(name),
+                        r.duration.as_secs_f64()
+                    )?;
+                    match &r.result {
+                        Ok(()) => writeln!(out, "/>")?,
+                        Err(err) => {
+                            let message = xml_escape(&err.to_string());
+                            writeln!(out, ">")?;
+                            writeln!(
+                                out,
+                                r#"      <failure type="{}" message="{}">{}: {}</failure>"#,
+                                err.code(),
+                                message,
+                                err.code(),
+                                message
+                            )?;
+                            writeln!(out, "    </testcase>")?;
+                        }
+                    }
+                }
+                writeln!(out, "  </testsuite>")?;
+                writeln!(out, "</testsuites>")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The batch fails with a timeout if it was cancelled, as incomplete if the node failed for any
+    /// credential, if more credentials are invalid than `max_failures` allows,
+    /// or if the credentials do not have the owner in common they are required to have
+    pub fn into_result(self, max_failures: MaxFailures) -> Result<(), Error> {
+        if self.timed_out() > 0 {
+            return Err(Error::Timeout);
+        }
+        if self.incomplete() > 0 {
+            return Err(Error::Incomplete(self.incomplete()));
+        }
+        let invalid = self.invalid();
+        match (
+            self.owner,
+            max_failures.exceeded(invalid, self.results.len()),
+        ) {
+            (_, true) => Err(Error::InvalidCredentials(invalid)),
+            (Some(Err(err)), false) => Err(err),
+            (_, false) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures,
+        kilt::runtime_types::{
+            did::service_endpoints::DidEndpoint, frame_support::storage::bounded_vec::BoundedVec,
+        },
+    };
+    use std::collections::BTreeSet;
+
+    static NO_REQUIREMENTS: ClaimRequirements = ClaimRequirements {
+        owner: None,
+        properties: Vec::new(),
+        full_did: false,
+    };
+
+    static BUILTIN_NAMES: CtypeNames = CtypeNames::builtin();
+
+    fn options<'a>(allowed_issuers: &'a [&'a str]) -> BatchOptions<'a> {
+        BatchOptions {
+            allowed_issuers,
+            challenge: None,
+            requirements: &NO_REQUIREMENTS,
+            concurrency: 3,
+            output: OutputFormat::Json,
+            quiet: true,
+            summary_only: false,
+            sort_by: None,
+            include_services: false,
+            redact_claims: false,
+            redact_pii: false,
+            parse: Default::default(),
+            require_same_owner: false,
+            require_same_key: false,
+            cancel: CancellationToken::new(),
+            ctype_names: &BUILTIN_NAMES,
+            report_version: REPORT_VERSION,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_batch() {
+        let valid = serde_json::to_vec(&fixtures::credential()).unwrap();
+        let mut tampered = fixtures::credential();
+        tampered.claim.contents = serde_json::json!({"Email": "mallory@example.com"});
+        let inputs: Vec<BatchInput> = [
+            ("a", valid.clone()),
+            ("b", b"not json".to_vec()),
+            ("c", serde_json::to_vec(&tampered).unwrap()),
+            ("d", valid),
+        ]
+        .into_iter()
+        .map(|(source, data)| BatchInput {
+            source: source.to_string(),
+            data,
+        })
+        .collect();
+
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let mut report =
+            verify_batch(&fixtures::backend(), &inputs, &options(&allowed_issuers)).await;
+
+        let summary: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| (r.source.as_str(), format!("{:?}", r.result)))
+            .collect();
+        assert_eq!(summary[0], ("a", "Ok(())".to_string()));
+        assert!(summary[1].1.starts_with("Err(Serde("), "{:?}", summary[1]);
+        assert_eq!(summary[2], ("c", "Err(InvalidClaimContents)".to_string()));
+        assert_eq!(summary[3], ("d", "Ok(())".to_string()));
+
+        let credential = fixtures::credential();
+        assert_eq!(
+            report.results[0].porcelain(),
+            format!(
+                "VALID\t{}\t{}\t{}",
+                credential.root_hash, credential.claim.owner, attester
+            )
+        );
+        assert_eq!(report.results[1].porcelain(), "INVALID\t-\tinvalid_json");
+        assert_eq!(
+            report.results[2].porcelain(),
+            format!("INVALID\t{}\tinvalid_claim_contents", credential.root_hash)
+        );
+
+        report.registry = Some(IssuerRegistry {
+            did: "did:kilt:4registry".to_string(),
+            members: vec![attester.clone()],
+            fetched_at: 1_700_000_000,
+            cached: true,
+        });
+        let mut summary_options = options(&allowed_issuers);
+        summary_options.summary_only = true;
+        let mut summary = Vec::new();
+        report.write(&mut summary, &summary_options).unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&summary).unwrap();
+        assert_eq!(
+            summary["summary"]["issuerRegistry"],
+            serde_json::json!({
+                "did": "did:kilt:4registry",
+                "fetchedAt": "2023-11-14T22:13:20Z",
+                "cached": true,
+                "members": 1,
+            })
+        );
+        let failures: Vec<_> = summary["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| (r["source"].as_str().unwrap(), r["code"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            failures,
+            vec![("b", "invalid_json"), ("c", "invalid_claim_contents")]
+        );
+        assert_eq!(summary["summary"]["total"], 4);
+
+        summary_options.output = OutputFormat::Human;
+        let mut summary = Vec::new();
+        report.write(&mut summary, &summary_options).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(
+            summary.starts_with("Failures:\n❌ b [invalid_json]: "),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("\n❌ c [invalid_claim_contents]: Invalid claim contents\n4 credentials: 2 valid, 2 invalid\n"), "{}", summary);
+        assert!(
+            summary.contains("\nBy ctype:\n       3 SocialKYC Email (0x3291bb12)\n"),
+            "{}",
+            summary
+        );
+        assert!(
+            summary.contains("\nTrusted issuers of registry did:kilt:4registry as of 2023-11-14T22:13:20Z (cached, the registry could not be read)\n"),
+            "{}",
+            summary
+        );
+
+        let order = |by| -> Vec<_> {
+            report
+                .sorted(by)
+                .into_iter()
+                .map(|r| r.source.as_str())
+                .collect()
+        };
+        // invalid_claim_contents sorts before invalid_json
+        assert_eq!(order(SortBy::Status), vec!["c", "b", "a", "d"]);
+        assert_eq!(order(SortBy::File), vec!["a", "b", "c", "d"]);
+        assert_eq!(order(SortBy::Owner), vec!["a", "c", "d", "b"]);
+        assert_eq!(order(SortBy::Attester), vec!["a", "d", "b", "c"]);
+
+        let mut csv_options = options(&allowed_issuers);
+        csv_options.output = OutputFormat::Csv;
+        csv_options.sort_by = Some(SortBy::Status);
+        let mut csv = Vec::new();
+        report.write(&mut csv, &csv_options).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            rows[0],
+            "source,status,code,root_hash,owner,attester,endpoint"
+        );
+        assert_eq!(rows[2], "b,invalid,invalid_json,,,,");
+        assert_eq!(
+            rows[3],
+            format!(
+                "a,valid,,{},{},{},",
+                credential.root_hash, credential.claim.owner, attester
+            )
+        );
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(junit_case("creds.ndjson:3"), ("creds.ndjson", "3"));
+        assert_eq!(junit_case("a/b/c.json"), ("a/b", "c.json"));
+        assert_eq!(junit_case("c.json"), (".", "c.json"));
+
+        // the invalid json and the tampered contents never reach the backend
+        assert_eq!(report.rpc_calls, 4);
+        assert!(report.results[0].timings.get(Check::Attestation).is_some());
+        assert!(report.results[2].timings.get(Check::Signature).is_none());
+        let stats = report.stats();
+        assert!(stats.total().is_some());
+        assert!(stats.check(Check::Signature).is_some());
+        assert!(matches!(
+            report.into_result(MaxFailures::default()),
+            Err(Error::InvalidCredentials(2))
+        ));
+    }
+
+    #[test]
+    fn test_max_failures() {
+        assert_eq!("3".parse(), Ok(MaxFailures::Count(3)));
+        assert_eq!("0.5%".parse(), Ok(MaxFailures::Percent(0.5)));
+        assert!("101%".parse::<MaxFailures>().is_err());
+        assert!("-1".parse::<MaxFailures>().is_err());
+        assert!("some".parse::<MaxFailures>().is_err());
+        assert!(!MaxFailures::Count(0).exceeded(0, 10));
+        assert!(MaxFailures::Count(0).exceeded(1, 10));
+        assert!(!MaxFailures::Percent(10.0).exceeded(1, 10));
+        assert!(MaxFailures::Percent(10.0).exceeded(2, 10));
+    }
+
+    #[test]
+    fn test_common_owner() {
+        let result = |source: &str, owner: Option<&str>, key: &str| BatchResult {
+            result: Ok(()),
+            owner: owner.map(str::to_string),
+            key_uri: owner.map(|owner| format!("{}#{}", owner, key)),
+            ..BatchResult::failed(source, Error::Timeout)
+        };
+        let mut options = options(&[]);
+        options.require_same_owner = true;
+
+        let report = BatchReport::new(
+            vec![
+                result("a", Some("did:kilt:4alice"), "0x1"),
+                result("b", None, ""),
+                result("c", Some("did:kilt:4alice"), "0x2"),
+            ],
+            0,
+            &options,
+        );
+        assert!(matches!(&report.owner, Some(Ok(owner)) if owner == "did:kilt:4alice"));
+        let mut out = Vec::new();
+        report.write(&mut out, &options).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["summary"]["owner"], "did:kilt:4alice");
+        assert!(json["summary"].get("inconsistency").is_none());
+        assert!(report.into_result(MaxFailures::default()).is_ok());
+
+        let res = common_owner(
+            &[
+                result("a", Some("did:kilt:4alice"), "0x1"),
+                result("b", Some("did:kilt:4bob"), "0x1"),
+                result("c", Some("did:kilt:4alice"), "0x1"),
+            ],
+            false,
+        );
+        assert!(
+            matches!(&res, Err(Error::OwnerInconsistency(msg))
+                if msg == "2 different owners: did:kilt:4alice (a, c), did:kilt:4bob (b)"),
+            "{:?}",
+            res
+        );
+
+        let keys = [
+            result("a", Some("did:kilt:4alice"), "0x1"),
+            result("c", Some("did:kilt:4alice"), "0x2"),
+        ];
+        assert!(common_owner(&keys, false).is_ok());
+        let res = common_owner(&keys, true);
+        assert!(
+            matches!(&res, Err(Error::OwnerInconsistency(msg)) if msg.starts_with("signed with 2 different keys: did:kilt:4alice#0x1 (a)")),
+            "{:?}",
+            res
+        );
+        options.output = OutputFormat::Human;
+        options.require_same_key = true;
+        let report = BatchReport::new(keys.into(), 0, &options);
+        let mut out = Vec::new();
+        report.write(&mut out, &options).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.contains("❌ Credentials are inconsistent: signed with 2"),
+            "{}",
+            text
+        );
+        assert!(matches!(
+            report.into_result(MaxFailures::default()),
+            Err(Error::OwnerInconsistency(_))
+        ));
+        assert!(common_owner(&[], true).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_batch() {
+        let inputs: Vec<BatchInput> = (0..3)
+            .map(|i| BatchInput {
+                source: i.to_string(),
+                data: serde_json::to_vec(&fixtures::credential()).unwrap(),
+            })
+            .collect();
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let options = options(&allowed_issuers);
+        options.cancel.cancel();
+
+        let report = verify_batch(&fixtures::backend(), &inputs, &options).await;
+        assert_eq!(report.results.len(), 3);
+        assert_eq!(report.timed_out(), 3);
+        assert_eq!(report.results[2].porcelain(), "INVALID\t-\ttimeout");
+        assert!(report.stats().total().is_none());
+        assert!(matches!(
+            report.into_result(MaxFailures::default()),
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_include_services() {
+        let mut backend = fixtures::backend();
+        let owner = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        backend.latest_mut().insert_service_endpoint(
+            owner,
+            &DidEndpoint {
+                id: BoundedVec(b"messaging".to_vec()),
+                service_types: BoundedVec(vec![BoundedVec(b"KiltMessagingService".to_vec())]),
+                urls: BoundedVec(vec![BoundedVec(b"https://example.com".to_vec())]),
+            },
+        );
+        let inputs = vec![
+            BatchInput {
+                source: "a".to_string(),
+                data: serde_json::to_vec(&fixtures::credential()).unwrap(),
+            },
+            BatchInput {
+                source: "b".to_string(),
+                data: b"not json".to_vec(),
+            },
+        ];
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let mut options = options(&allowed_issuers);
+        options.include_services = true;
+
+        let report = verify_batch(&backend, &inputs, &options).await;
+        assert!(report.results[1].services.is_none());
+        let mut out = Vec::new();
+        report.write(&mut out, &options).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            json["results"][0]["services"],
+            serde_json::json!([{
+                "id": "messaging",
+                "types": ["KiltMessagingService"],
+                "urls": ["https://example.com"],
+            }])
+        );
+        assert!(json["results"][1].get("services").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_redact_claims() {
+        let backend = fixtures::backend();
+        let inputs = vec![BatchInput {
+            source: "a".to_string(),
+            data: serde_json::to_vec(&fixtures::credential()).unwrap(),
+        }];
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let mut options = options(&allowed_issuers);
+        options.output = OutputFormat::Json;
+
+        for redact_claims in [false, true] {
+            options.redact_claims = redact_claims;
+            let report = verify_batch(&backend, &inputs, &options).await;
+            let mut out = Vec::new();
+            report.write(&mut out, &options).unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+            let claims = json["results"][0].get("claims");
+            match redact_claims {
+                false => assert_eq!(
+                    claims,
+                    Some(&serde_json::json!({"Email": "alice@example.com", "Name": "Alice"}))
+                ),
+                true => assert!(claims.is_none()),
+            }
+        }
+
+        // serde quotes the value of a field with the wrong type
+        let inputs = vec![BatchInput {
+            source: "a".to_string(),
+            data: br#"{"claim": "alice@example.com"}"#.to_vec(),
+        }];
+        for redact_pii in [false, true] {
+            options.redact_pii = redact_pii;
+            let report = verify_batch(&backend, &inputs, &options).await;
+            let mut out = Vec::new();
+            report.write(&mut out, &options).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(out.contains("alice@example.com"), !redact_pii, "{}", out);
+            assert!(out.contains("invalid_json"), "{}", out);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_to_json() {
+        let cred = fixtures::credential();
+        let inputs = vec![BatchInput {
+            source: "a".to_string(),
+            data: serde_json::to_vec(&cred).unwrap(),
+        }];
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let options = options(&allowed_issuers);
+        let report = verify_batch(&fixtures::backend(), &inputs, &options).await;
+        let mut out = Vec::new();
+        report.write(&mut out, &options).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        // a result is published as it is in the report
+        let published = report.results[0].to_json(&BUILTIN_NAMES).unwrap();
+        let published: serde_json::Value = serde_json::from_slice(&published).unwrap();
+        assert_eq!(published, json["results"][0]);
+        // valid, but without a challenge it could be a replay
+        assert_eq!(published["status"], "partially_verified");
+        assert_eq!(
+            published["checks"][2],
+            serde_json::json!({"check": "challenge", "status": "skipped", "reason": "no_challenge"})
+        );
+
+        let checks = CheckRecorder::default().report(&cred, None, &ClaimRequirements::default());
+        let mut verified =
+            BatchResult::verified("a", &cred, checks, Err(Error::AttestationRevoked));
+        verified.fail_check(CheckStep::Attestation);
+        let verified: serde_json::Value =
+            serde_json::from_slice(&verified.to_json(&BUILTIN_NAMES).unwrap()).unwrap();
+        assert_eq!(verified["valid"], false);
+        assert_eq!(verified["code"], "attestation_revoked");
+        assert_eq!(verified["status"], "invalid");
+        assert_eq!(verified["checks"][4]["code"], "attestation_revoked");
+        assert_eq!(verified["ctype_hash"], published["ctype_hash"]);
+    }
+
+    // the reports of two runs of the same batch are the same and match the golden files, once the
+    // timings are left out. Run with UPDATE_GOLDEN=1 to write the golden files again
+    #[tokio::test]
+    async fn test_golden_reports() {
+        let valid = serde_json::to_vec(&fixtures::credential()).unwrap();
+        let mut tampered = fixtures::credential();
+        tampered.claim.contents = serde_json::json!({"Email": "mallory@example.com"});
+        let mut unattested = fixtures::credential();
+        unattested.root_hash = crate::utils::hex_encode([7u8; 32]);
+        let inputs: Vec<BatchInput> = [
+            ("e.json", valid.clone()),
+            ("a.json", b"not json".to_vec()),
+            ("d.json", serde_json::to_vec(&tampered).unwrap()),
+            ("c.json", serde_json::to_vec(&unattested).unwrap()),
+            ("b.json", valid),
+        ]
+        .into_iter()
+        .map(|(source, data)| BatchInput {
+            source: source.to_string(),
+            data,
+        })
+        .collect();
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let backend = fixtures::backend();
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let mut options = options(&allowed_issuers);
+            let mut report = verify_batch(&backend, &inputs, &options).await;
+            let mut json = Vec::new();
+            report.write(&mut json, &options).unwrap();
+            let mut json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+            json["summary"].as_object_mut().unwrap().remove("latencyMs");
+            let mut csv = Vec::new();
+            options.output = OutputFormat::Csv;
+            options.sort_by = Some(SortBy::Status);
+            report.write(&mut csv, &options).unwrap();
+            // the measured durations differ between runs
+            for r in report.results.iter_mut() {
+                r.duration = Duration::from_millis(12);
+            }
+            report.results[0].source = "creds/e.json".to_string();
+            report.results[1].result = Err(Error::Webhook("<a & 'b'>\n\u{1}\"c\"".to_string()));
+            let mut junit = Vec::new();
+            options.output = OutputFormat::Junit;
+            report.write(&mut junit, &options).unwrap();
+            runs.push((
+                to_sorted_json(&json).unwrap() + "\n",
+                String::from_utf8(csv).unwrap(),
+                String::from_utf8(junit).unwrap(),
+            ));
+        }
+        assert_eq!(runs[0], runs[1]);
+
+        let golden = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/golden");
+        let (json, csv, junit) = &runs[0];
+        for (file, output) in [
+            ("batch.json", json),
+            ("batch.csv", csv),
+            ("batch.junit.xml", junit),
+        ] {
+            let file = format!("{}/{}", golden, file);
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                std::fs::create_dir_all(golden).unwrap();
+                std::fs::write(&file, output).unwrap();
+            }
+            assert_eq!(&std::fs::read_to_string(&file).unwrap(), output, "{}", file);
+        }
+    }
+
+    // the paths of the fields of a json report, maps keyed by data like the failures by code are
+    // a single field
+    fn report_fields(value: &serde_json::Value, path: String, fields: &mut BTreeSet<String>) {
+        const MAPS: [&str; 3] = ["results[].claims", "summary.failures", "summary.latencyMs"];
+        match value {
+            serde_json::Value::Object(_) if MAPS.contains(&path.as_str()) => {
+                fields.insert(format!("{}.*", path));
+            }
+            serde_json::Value::Object(members) => {
+                for (key, value) in members {
+                    let path = match path.as_str() {
+                        "" => key.clone(),
+                        path => format!("{}.{}", path, key),
+                    };
+                    report_fields(value, path, fields);
+                }
+            }
+            serde_json::Value::Array(items) if !items.is_empty() => {
+                for item in items {
+                    report_fields(item, format!("{}[]", path), fields);
+                }
+            }
+            _ => {
+                fields.insert(path);
+            }
+        }
+    }
+
+    // the fields of every version of the json report are kept in the golden files, a report whose
+    // fields change needs a new version. UPDATE_GOLDEN=1 writes the fields of a version that has
+    // none yet, never those of a version consumers already read
+    #[tokio::test]
+    async fn test_report_versions() {
+        let valid = serde_json::to_vec(&fixtures::credential()).unwrap();
+        let mut tampered = fixtures::credential();
+        tampered.claim.contents = serde_json::json!({"Email": "mallory@example.com"});
+        let inputs: Vec<BatchInput> = [
+            ("a.json", valid),
+            ("b.json", b"not json".to_vec()),
+            ("c.json", serde_json::to_vec(&tampered).unwrap()),
+        ]
+        .into_iter()
+        .map(|(source, data)| BatchInput {
+            source: source.to_string(),
+            data,
+        })
+        .collect();
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let mut options = options(&allowed_issuers);
+        options.require_same_owner = true;
+        let mut report = verify_batch(&fixtures::backend(), &inputs, &options).await;
+        for r in report.results.iter_mut() {
+            r.endpoint = Some("ws://127.0.0.1:9944".to_string());
+            r.resolved_via_http = true;
+            r.key_removed_at = Some(7);
+        }
+        report.throttled = 2;
+
+        let golden = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/golden");
+        for version in MIN_REPORT_VERSION..=REPORT_VERSION {
+            options.report_version = version;
+            let mut json = Vec::new();
+            report.write(&mut json, &options).unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+            let expected = match version {
+                1 => serde_json::Value::Null,
+                version => version.into(),
+            };
+            assert_eq!(json["report_version"], expected);
+            let mut fields = BTreeSet::new();
+            report_fields(&json, String::new(), &mut fields);
+            let fields: String = fields.into_iter().map(|field| field + "\n").collect();
+
+            let file = format!("{}/report-v{}.fields", golden, version);
+            if std::env::var_os("UPDATE_GOLDEN").is_some() && !Path::new(&file).exists() {
+                std::fs::write(&file, &fields).unwrap();
+            }
+            let expected = std::fs::read_to_string(&file).unwrap_or_default();
+            assert!(
+                expected == fields,
+                "the fields of version {} of the json report changed, bump REPORT_VERSION:\n{}",
+                version,
+                fields
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_inputs() {
+        let dir = std::env::temp_dir().join(format!("kilt-verify-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.json"), "{}").unwrap();
+        std::fs::write(dir.join("a.json"), "{}").unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        std::fs::write(dir.join("creds.ndjson"), "{\"a\":1}\n\n{\"b\":2}\n").unwrap();
+
+        let dir_name = dir.display().to_string();
+        assert!(is_batch(&dir_name));
+        let sources: Vec<_> =
+            read_inputs(&dir_name, &ArchiveOptions::default(), &Default::default())
+                .unwrap()
+                .into_iter()
+                .map(|input| input.source)
+                .collect();
+        assert_eq!(
+            sources,
+            vec![
+                dir.join("a.json").display().to_string(),
+                dir.join("b.json").display().to_string()
+            ]
+        );
+
+        let ndjson = dir.join("creds.ndjson").display().to_string();
+        assert!(is_batch(&ndjson));
+        let inputs = read_inputs(&ndjson, &ArchiveOptions::default(), &Default::default()).unwrap();
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[1].source, format!("{}:3", ndjson));
+        assert_eq!(inputs[1].data, b"{\"b\":2}");
+
+        // a gzipped file is decompressed as a whole before it is split into lines
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"{\"a\":1}\n{\"b\":2}\n").unwrap();
+        let gzipped = dir.join("creds.ndjson.gz");
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+        let gzipped = gzipped.display().to_string();
+        assert!(is_batch(&gzipped));
+        let inputs =
+            read_inputs(&gzipped, &ArchiveOptions::default(), &Default::default()).unwrap();
+        assert_eq!(inputs[1].data, b"{\"b\":2}");
+        let limited = ParseOptions {
+            max_decompressed_size: Some(8),
+            ..Default::default()
+        };
+        let res = read_inputs(&gzipped, &ArchiveOptions::default(), &limited);
+        assert!(
+            matches!(res, Err(Error::Decompression(_))),
+            "{:?}",
+            res.is_ok()
+        );
+
+        assert!(!is_batch(&dir.join("a.json").display().to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
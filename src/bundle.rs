@@ -0,0 +1,508 @@
+use clap::Args;
+use codec::{Decode, Encode};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sp_trie::{read_trie_value, LayoutV1, StorageProof};
+use subxt::{
+    sp_core::{storage::StorageKey, H256},
+    sp_runtime::{generic::Header, traits::BlakeTwo256},
+    storage::StorageKeyPrefix,
+    StorageEntry,
+};
+
+use crate::{
+    archive::{self, ArchiveOptions},
+    audit::{self, ChainPolicy},
+    credential::{ClaimRequirements, Credential, StatementKeys},
+    errors::Error,
+    kilt::{
+        attestation::storage::Attestations,
+        did::storage::Did,
+        runtime_types::{
+            attestation::attestations::AttestationDetails, did::did_details::DidDetails,
+        },
+        KiltRuntimeApi, NETWORKS,
+    },
+    mock::MockBackend,
+    utils::{get_did_account_id, hex_decode, hex_decode_h256, hex_encode, ParseOptions},
+};
+
+/// Version of the bundle format, bundles of other versions are rejected
+pub const BUNDLE_VERSION: u64 = 1;
+
+/// The credential as it was verified
+const CREDENTIAL_ENTRY: &str = "credential.json";
+/// The `ProofBundle`
+const BUNDLE_ENTRY: &str = "bundle.json";
+
+/// What a credential was accepted or rejected under and why, everything `audit-bundle` needs to
+/// check the result again without a node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofBundle {
+    pub version: u64,
+    pub genesis_hash: String,
+    pub block_number: u32,
+    pub block_hash: String,
+    /// The SCALE encoded header of the block, its state root is what the proof is checked against
+    pub header: String,
+    /// Trie nodes of the read proof of the DID of the owner and the attestation at the block
+    pub proof: Vec<String>,
+    pub allowed_issuers: Vec<String>,
+    pub challenge: Option<String>,
+    pub expected_owner: Option<String>,
+    pub required_properties: Vec<String>,
+    /// Bundles of versions without it did not require one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_full_did: bool,
+    /// Template of the statement keys the claim is normalized with, `None` for the keys of KILT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_keys: Option<String>,
+    pub report: BundleReport,
+}
+
+/// The result of the verification the bundle was exported after
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleReport {
+    pub root_hash: String,
+    pub endpoint: String,
+    /// See `audit::policy_hash`
+    pub policy_hash: String,
+    /// Error code of a rejected credential, `None` if it is valid
+    pub error: Option<String>,
+    pub message: Option<String>,
+}
+
+impl BundleReport {
+    pub fn new(
+        cred: &Credential,
+        endpoint: &str,
+        policy_hash: &str,
+        res: &Result<(), Error>,
+    ) -> Self {
+        BundleReport {
+            root_hash: cred.root_hash.clone(),
+            endpoint: endpoint.to_string(),
+            policy_hash: policy_hash.to_string(),
+            error: res.as_ref().err().map(|err| err.code().to_string()),
+            message: res.as_ref().err().map(ToString::to_string),
+        }
+    }
+}
+
+// the storage keys of the DID of the owner and of the attestation, the only entries a
+// verification reads
+fn storage_keys(cred: &Credential) -> Result<[StorageKey; 2], Error> {
+    let owner = get_did_account_id(&cred.claim.owner)?;
+    let root_hash = hex_decode_h256(&cred.root_hash)?;
+    Ok([
+        Did(&owner).key().final_key(StorageKeyPrefix::new::<Did>()),
+        Attestations(&root_hash)
+            .key()
+            .final_key(StorageKeyPrefix::new::<Attestations>()),
+    ])
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::InvalidBundle(msg.into())
+}
+
+/// Read the header of the block, its genesis and the proofs of the storage a verification of the
+/// credential reads from the node, and write them with the credential, the policy and the result
+/// to a gzipped tar archive at `path`
+#[allow(clippy::too_many_arguments)]
+pub async fn export(
+    cli: &KiltRuntimeApi,
+    cred: &Credential,
+    block: H256,
+    allowed_issuers: &[&str],
+    challenge: Option<&str>,
+    requirements: &ClaimRequirements,
+    report: BundleReport,
+    path: &str,
+) -> Result<(), Error> {
+    let rpc = cli.client.rpc();
+    let header = rpc.header(Some(block)).await?.ok_or(Error::BlockNotFound)?;
+    let genesis_hash = rpc.genesis_hash().await?;
+    let proof = rpc
+        .read_proof(storage_keys(cred)?.to_vec(), Some(block))
+        .await?;
+    let bundle = ProofBundle {
+        version: BUNDLE_VERSION,
+        genesis_hash: hex_encode(genesis_hash),
+        block_number: header.number,
+        block_hash: hex_encode(block),
+        header: hex_encode(header.encode()),
+        proof: proof.proof.iter().map(|node| hex_encode(&node.0)).collect(),
+        allowed_issuers: allowed_issuers.iter().map(|s| s.to_string()).collect(),
+        challenge: challenge.map(str::to_string),
+        expected_owner: requirements.owner.clone(),
+        required_properties: requirements.properties.clone(),
+        require_full_did: requirements.full_did,
+        statement_keys: cred.claim.statement_keys.template().map(str::to_string),
+        report,
+    };
+    write(path, &serde_json::to_vec_pretty(cred)?, &bundle)
+}
+
+fn write(path: &str, credential: &[u8], bundle: &ProofBundle) -> Result<(), Error> {
+    let mut tar = tar::Builder::new(GzEncoder::new(
+        std::fs::File::create(path)?,
+        Compression::default(),
+    ));
+    let bundle = serde_json::to_vec_pretty(bundle)?;
+    for (name, data) in [(CREDENTIAL_ENTRY, credential), (BUNDLE_ENTRY, &bundle)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, data)?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// What `audit` found, the credential verifies at the block like the report says
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleAudit {
+    pub root_hash: String,
+    pub block_number: u32,
+    pub block_hash: String,
+    /// Name of the network of the genesis hash, `None` for other chains
+    pub network: Option<&'static str>,
+    /// Error code of a rejected credential, `None` if it is valid
+    pub error: Option<String>,
+}
+
+/// Check a bundle without a node: the header has the block hash, the proof is complete for the
+/// storage of the credential against the state root of the header, and the credential verifies
+/// against the proven storage with the result of the report. That the block is part of the chain
+/// of the genesis hash cannot be checked offline.
+pub async fn audit(path: &str) -> Result<BundleAudit, Error> {
+    let entries = archive::read_archive(path, &ArchiveOptions::default())
+        .map_err(|err| invalid(format!("cannot read {}: {}", path, err)))?;
+    let entry = |name: &str| {
+        entries
+            .iter()
+            .find(|entry| entry.source == name)
+            .ok_or_else(|| invalid(format!("{} has no {}", path, name)))
+    };
+    let bundle: ProofBundle = serde_json::from_slice(&entry(BUNDLE_ENTRY)?.data)?;
+    if bundle.version != BUNDLE_VERSION {
+        return Err(invalid(format!(
+            "version {} is not supported",
+            bundle.version
+        )));
+    }
+    let statement_keys = match &bundle.statement_keys {
+        Some(template) => template.parse().map_err(invalid)?,
+        None => StatementKeys::default(),
+    };
+    let parse = ParseOptions {
+        statement_keys,
+        ..Default::default()
+    };
+    let cred = parse.parse(CREDENTIAL_ENTRY, &entry(CREDENTIAL_ENTRY)?.data)?;
+    if cred.root_hash != bundle.report.root_hash {
+        return Err(invalid(format!(
+            "the report is about {}, not the credential {}",
+            bundle.report.root_hash, cred.root_hash
+        )));
+    }
+
+    let header = Header::<u32, BlakeTwo256>::decode(&mut &hex_decode(&bundle.header)?[..])
+        .map_err(|err| invalid(format!("cannot decode the header: {}", err)))?;
+    if hex_encode(header.hash()) != bundle.block_hash || header.number != bundle.block_number {
+        return Err(invalid(format!(
+            "the header is not the one of block #{} {}",
+            bundle.block_number, bundle.block_hash
+        )));
+    }
+    let nodes = bundle
+        .proof
+        .iter()
+        .map(hex_decode)
+        .collect::<Result<Vec<_>, _>>()?;
+    let db = StorageProof::new(nodes).into_memory_db::<BlakeTwo256>();
+    let [did_key, attestation_key] = storage_keys(&cred)?;
+    let read = |key: &StorageKey| {
+        read_trie_value::<LayoutV1<BlakeTwo256>, _>(&db, &header.state_root, &key.0)
+            .map_err(|err| invalid(format!("the proof does not show the storage: {}", err)))
+    };
+
+    // the proven storage is the chain state the credential is verified against
+    let mut backend = MockBackend::default();
+    if let Some(data) = read(&did_key)? {
+        let details = DidDetails::decode(&mut &data[..])
+            .map_err(|err| invalid(format!("cannot decode the DID: {}", err)))?;
+        backend.insert_did(get_did_account_id(&cred.claim.owner)?, &details);
+    }
+    if let Some(data) = read(&attestation_key)? {
+        let details = AttestationDetails::decode(&mut &data[..])
+            .map_err(|err| invalid(format!("cannot decode the attestation: {}", err)))?;
+        backend.insert_attestation(hex_decode_h256(&cred.root_hash)?, &details);
+    }
+    let allowed_issuers: Vec<&str> = bundle.allowed_issuers.iter().map(String::as_str).collect();
+    let requirements = ClaimRequirements {
+        owner: bundle.expected_owner.clone(),
+        properties: bundle.required_properties.clone(),
+        full_did: bundle.require_full_did,
+    };
+    // bundles are not exported with a grace window or confirmations, see --export-proof-bundle
+    let policy_hash = audit::policy_hash(
+        &allowed_issuers,
+        bundle.challenge.as_deref(),
+        &requirements,
+        &ChainPolicy::default(),
+    );
+    if policy_hash != bundle.report.policy_hash {
+        return Err(invalid("the policy is not the one of the report"));
+    }
+    let challenge = bundle.challenge.as_deref();
+    let res = match cred.verify(&backend, &allowed_issuers, challenge).await {
+        Ok(()) => cred.check_requirements(&requirements),
+        Err(err) => Err(err),
+    };
+    let error = res.as_ref().err().map(|err| err.code().to_string());
+    if error != bundle.report.error {
+        return Err(invalid(format!(
+            "the report says {}, but the credential verifies as {}",
+            bundle.report.error.as_deref().unwrap_or("valid"),
+            error.as_deref().unwrap_or("valid")
+        )));
+    }
+
+    let genesis_hash = bundle.genesis_hash.to_ascii_lowercase();
+    Ok(BundleAudit {
+        root_hash: cred.root_hash,
+        block_number: bundle.block_number,
+        block_hash: bundle.block_hash,
+        network: NETWORKS
+            .iter()
+            .find(|network| network.genesis_hash == genesis_hash)
+            .map(|network| network.name),
+        error,
+    })
+}
+
+/// Check a proof bundle without a node
+#[derive(Args, Debug)]
+pub struct AuditBundleArgs {
+    /// The bundle written with --export-proof-bundle
+    #[clap(value_parser)]
+    path: String,
+}
+
+pub async fn run(args: &AuditBundleArgs) -> Result<(), Error> {
+    let audit = audit(&args.path).await?;
+    let network = audit
+        .network
+        .map(|name| format!("on {}", name))
+        .unwrap_or_else(|| "on an unknown chain".to_string());
+    match &audit.error {
+        None => println!("✅ Bundle is consistent: the credential is valid, like the report says"),
+        Some(code) => println!(
+            "✅ Bundle is consistent: the credential is rejected with {}, like the report says",
+            code
+        ),
+    }
+    println!("  credential: {}", audit.root_hash);
+    println!(
+        "  block:      #{} {} {}",
+        audit.block_number, audit.block_hash, network
+    );
+    println!("  compare the block hash with a node or explorer you trust, it is not checked to be part of the chain");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use sp_trie::{trie_types::TrieDBMutV1, MemoryDB, TrieMut};
+    use subxt::sp_runtime::{traits::Header as _, Digest};
+
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "kilt-verify-{}-{}.tar.gz",
+                name,
+                std::process::id()
+            ))
+            .display()
+            .to_string()
+    }
+
+    // a bundle of a block whose state is the storage entries, every trie node is in the proof
+    fn bundle(cred: &Credential, entries: &[(&StorageKey, Vec<u8>)]) -> ProofBundle {
+        let mut db = MemoryDB::<BlakeTwo256>::default();
+        let mut state_root = Default::default();
+        {
+            let mut trie = TrieDBMutV1::<BlakeTwo256>::new(&mut db, &mut state_root);
+            for (key, value) in entries {
+                trie.insert(&key.0, value).unwrap();
+            }
+        }
+        let header = Header::<u32, BlakeTwo256>::new(
+            7,
+            Default::default(),
+            state_root,
+            Default::default(),
+            Digest::default(),
+        );
+        let allowed_issuers = [fixtures::attester_did()];
+        let allowed_issuers: Vec<&str> = allowed_issuers.iter().map(String::as_str).collect();
+        let policy_hash = audit::policy_hash(
+            &allowed_issuers,
+            None,
+            &Default::default(),
+            &Default::default(),
+        );
+        ProofBundle {
+            version: BUNDLE_VERSION,
+            genesis_hash: NETWORKS[0].genesis_hash.to_string(),
+            block_number: 7,
+            block_hash: hex_encode(header.hash()),
+            header: hex_encode(header.encode()),
+            proof: db
+                .drain()
+                .into_values()
+                .map(|(node, _)| hex_encode(node))
+                .collect(),
+            allowed_issuers: allowed_issuers.iter().map(|s| s.to_string()).collect(),
+            challenge: None,
+            expected_owner: None,
+            required_properties: vec![],
+            require_full_did: false,
+            statement_keys: None,
+            report: BundleReport::new(cred, "ws://127.0.0.1:9944", &policy_hash, &Ok(())),
+        }
+    }
+
+    fn chain_state(cred: &Credential, revoked: bool) -> ProofBundle {
+        let [did_key, attestation_key] = storage_keys(cred).unwrap();
+        let did = fixtures::did_details(&fixtures::owner_key()).encode();
+        let attestation = fixtures::attestation_details(revoked).encode();
+        bundle(cred, &[(&did_key, did), (&attestation_key, attestation)])
+    }
+
+    #[tokio::test]
+    async fn test_audit_bundle() {
+        let cred = fixtures::credential();
+        let credential = serde_json::to_vec(&cred).unwrap();
+        let file = temp_file("bundle");
+
+        write(&file, &credential, &chain_state(&cred, false)).unwrap();
+        let checked = audit(&file).await.unwrap();
+        assert_eq!(checked.error, None);
+        assert_eq!(checked.block_number, 7);
+        assert_eq!(checked.network, Some(NETWORKS[0].name));
+
+        // a revoked attestation supports a report that rejects the credential
+        let mut revoked = chain_state(&cred, true);
+        let rejected = Err(Error::AttestationRevoked);
+        revoked.report = BundleReport::new(&cred, "", &revoked.report.policy_hash, &rejected);
+        write(&file, &credential, &revoked).unwrap();
+        let checked = audit(&file).await.unwrap();
+        assert_eq!(checked.error.as_deref(), Some("attestation_revoked"));
+
+        // but not one that accepts it
+        revoked.report.error = None;
+        write(&file, &credential, &revoked).unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "the report says valid, but the credential verifies as attestation_revoked"
+            ),
+            "{}",
+            err
+        );
+
+        // the proof has to match the state root of the header
+        let mut forged = chain_state(&cred, false);
+        forged.proof = chain_state(&cred, true).proof;
+        write(&file, &credential, &forged).unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("the proof does not show the storage"),
+            "{}",
+            err
+        );
+
+        // the header has to be the one of the block
+        let mut forged = chain_state(&cred, false);
+        forged.block_number = 8;
+        write(&file, &credential, &forged).unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert_eq!(err.code(), "invalid_bundle");
+
+        // the policy has to be the one of the report
+        let mut forged = chain_state(&cred, false);
+        forged.challenge = Some("0x1234".to_string());
+        write(&file, &credential, &forged).unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert!(err.to_string().contains("policy"), "{}", err);
+
+        let mut future = chain_state(&cred, false);
+        future.version = 2;
+        write(&file, &credential, &future).unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert!(err.to_string().contains("version 2"), "{}", err);
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_audit_statement_keys() {
+        // a credential of a fork is audited with the statement keys of the bundle
+        let data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/vectors/custom-statement-keys.json"
+        ));
+        let template = "fork:ctype:{ctype}#{property}";
+        let parse = ParseOptions {
+            statement_keys: template.parse().unwrap(),
+            ..Default::default()
+        };
+        let cred = parse.parse("fork.json", data).unwrap();
+        let file = temp_file("bundle-fork");
+        let mut bundle = chain_state(&cred, false);
+        bundle.statement_keys = Some(template.to_string());
+        write(&file, data, &bundle).unwrap();
+        let checked = audit(&file).await.unwrap();
+        assert_eq!(checked.error, None);
+
+        // with the keys of KILT the claim does not match the claim hashes
+        bundle.statement_keys = None;
+        write(&file, data, &bundle).unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert!(
+            err.to_string().contains("invalid_claim_contents"),
+            "{}",
+            err
+        );
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_audit_missing_storage() {
+        // the proof shows that there is no attestation at the block
+        let cred = fixtures::credential();
+        let [did_key, _] = storage_keys(&cred).unwrap();
+        let did = fixtures::did_details(&fixtures::owner_key()).encode();
+        let mut bundle = bundle(&cred, &[(&did_key, did)]);
+        let policy_hash = bundle.report.policy_hash.clone();
+        let rejected = Err(Error::AttestationNotFound);
+        bundle.report = BundleReport::new(&cred, "", &policy_hash, &rejected);
+        let file = temp_file("bundle-missing");
+        write(&file, &serde_json::to_vec(&cred).unwrap(), &bundle).unwrap();
+        let checked = audit(&file).await.unwrap();
+        assert_eq!(checked.error.as_deref(), Some("attestation_not_found"));
+
+        std::fs::write(&file, b"not an archive").unwrap();
+        let err = audit(&file).await.unwrap_err();
+        assert_eq!(err.code(), "invalid_bundle");
+        std::fs::remove_file(&file).unwrap();
+    }
+}
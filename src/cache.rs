@@ -0,0 +1,567 @@
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use subxt::{events::Events, sp_core::H256, sp_runtime::AccountId32, BasicError};
+
+use crate::{
+    backend::{AttestationHistory, ChainBackend},
+    errors::Error,
+    kilt::{
+        attestation::Event as AttestationPalletEvent,
+        runtime_types::{
+            attestation::attestations::AttestationDetails,
+            did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+        },
+        Event, KiltConfig,
+    },
+    progress::VerificationObserver,
+    utils::Token,
+};
+
+// entries are only ever encoded attestations, like the storage of a node
+fn decode(data: &[u8]) -> Result<AttestationDetails, Error> {
+    AttestationDetails::decode(&mut &data[..]).map_err(|err| Error::ConnectionError(err.into()))
+}
+
+/// A change of an attestation announced by an event of the attestation pallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttestationEvent {
+    Created(H256),
+    Revoked(H256),
+    Removed(H256),
+}
+
+impl AttestationEvent {
+    pub fn root_hash(&self) -> H256 {
+        match self {
+            AttestationEvent::Created(root_hash)
+            | AttestationEvent::Revoked(root_hash)
+            | AttestationEvent::Removed(root_hash) => *root_hash,
+        }
+    }
+}
+
+/// The attestation events of a block in the order they were emitted. An event that cannot be
+/// decoded fails the whole block, it may have been a revocation the cache would miss
+pub fn attestation_events(
+    events: &Events<KiltConfig, Event>,
+) -> Result<Vec<AttestationEvent>, Error> {
+    decoded_attestation_events(
+        events
+            .iter()
+            .map(|event| event.map(|details| details.event)),
+    )
+}
+
+// the attestation events among the decoded events of a block
+fn decoded_attestation_events(
+    events: impl Iterator<Item = Result<Event, BasicError>>,
+) -> Result<Vec<AttestationEvent>, Error> {
+    let mut attestation_events = Vec::new();
+    for event in events {
+        let event = match event? {
+            Event::Attestation(AttestationPalletEvent::AttestationCreated(_, root_hash, ..)) => {
+                AttestationEvent::Created(root_hash)
+            }
+            Event::Attestation(AttestationPalletEvent::AttestationRevoked(_, root_hash)) => {
+                AttestationEvent::Revoked(root_hash)
+            }
+            Event::Attestation(AttestationPalletEvent::AttestationRemoved(_, root_hash)) => {
+                AttestationEvent::Removed(root_hash)
+            }
+            _ => continue,
+        };
+        attestation_events.push(event);
+    }
+    Ok(attestation_events)
+}
+
+/// The events of a block could not be applied, the cache has to be synced again before it serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    /// Last block whose events were applied, `None` if the cache was never synced
+    pub last_block: Option<u32>,
+    pub block: u32,
+}
+
+/// How current the cache is, for reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStatus {
+    /// Entries are only served while the events of every block since the sync were applied
+    pub synced: bool,
+    /// Last block whose events were applied
+    pub last_block: Option<u32>,
+    /// Time since the events of the last block were applied
+    #[serde(serialize_with = "serialize_secs")]
+    pub age: Option<Duration>,
+    pub entries: usize,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+fn serialize_secs<S: serde::Serializer>(age: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_some(&age.map(|age| age.as_secs()))
+}
+
+impl fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.synced, self.last_block) {
+            (true, Some(block)) => write!(f, "attestation cache synced to block #{}", block)?,
+            (false, Some(block)) => write!(
+                f,
+                "attestation cache stale since block #{}, lookups go to the node",
+                block
+            )?,
+            (_, None) => write!(f, "attestation cache not synced, lookups go to the node")?,
+        }
+        if let Some(age) = self.age {
+            write!(f, " {}s ago", age.as_secs())?;
+        }
+        write!(
+            f,
+            ", {} entries, {} hits, {} misses",
+            self.entries, self.hits, self.misses
+        )
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// SCALE encoded attestations, the generated types cannot be cloned.
+    /// `None` entries are attestations that are known not to exist
+    entries: HashMap<H256, Option<Vec<u8>>>,
+    synced: bool,
+    last_block: Option<u32>,
+    applied_at: Option<Instant>,
+    hits: usize,
+    misses: usize,
+}
+
+/// The attestations of root hashes kept up to date by the attestation events of each block.
+/// The events of every block have to be applied in order, a block that is skipped marks the
+/// cache stale until it is synced again, so a revocation in a missed block is never hidden.
+#[derive(Default)]
+pub struct RevocationCache {
+    state: Mutex<CacheState>,
+}
+
+impl RevocationCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().expect("cache lock is not poisoned")
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        let state = self.lock();
+        CacheStatus {
+            synced: state.synced,
+            last_block: state.last_block,
+            age: state.applied_at.map(|at| at.elapsed()),
+            entries: state.entries.len(),
+            hits: state.hits,
+            misses: state.misses,
+        }
+    }
+
+    /// Stop serving entries until the next sync, i.e. when the event subscription ends
+    pub fn invalidate(&self) {
+        self.lock().synced = false;
+    }
+
+    /// Look up the attestations of the cached and the hot root hashes at a block and serve them
+    /// from the events of the following blocks on. The cache stays stale if a lookup fails.
+    pub async fn sync(
+        &self,
+        backend: &dyn ChainBackend,
+        hot: &[H256],
+        block: u32,
+        block_hash: H256,
+    ) -> Result<(), Error> {
+        let mut root_hashes: HashSet<H256> = hot.iter().copied().collect();
+        {
+            let mut state = self.lock();
+            state.synced = false;
+            root_hashes.extend(state.entries.keys().copied());
+        }
+        let entries = stream::iter(root_hashes)
+            .map(|root_hash| async move {
+                let attestation = backend.attestation(&root_hash, Some(block_hash)).await?;
+                Ok::<_, Error>((root_hash, attestation.map(|a| a.encode())))
+            })
+            .buffer_unordered(8)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        let mut state = self.lock();
+        state.entries = entries;
+        state.synced = true;
+        state.last_block = Some(block);
+        state.applied_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Apply the attestation events of the block after the last applied one and return the root
+    /// hashes that were revoked or removed. Blocks that were applied already are ignored, any
+    /// other block is a gap.
+    pub fn apply(&self, block: u32, events: &[AttestationEvent]) -> Result<HashSet<H256>, Gap> {
+        let mut state = self.lock();
+        let gap = Gap {
+            last_block: state.last_block,
+            block,
+        };
+        match state.last_block {
+            Some(last) if state.synced && block <= last => return Ok(HashSet::new()),
+            Some(last) if state.synced && block == last + 1 => {}
+            _ => {
+                state.synced = false;
+                return Err(gap);
+            }
+        }
+        let mut changed = HashSet::new();
+        for event in events {
+            match event {
+                // the details are not in the event, they are looked up when they are needed
+                AttestationEvent::Created(root_hash) => {
+                    state.entries.remove(root_hash);
+                }
+                AttestationEvent::Revoked(root_hash) => {
+                    match state
+                        .entries
+                        .get(root_hash)
+                        .map(|entry| entry.as_deref().map(decode))
+                    {
+                        Some(Some(Ok(mut attestation))) => {
+                            attestation.revoked = true;
+                            state.entries.insert(*root_hash, Some(attestation.encode()));
+                        }
+                        _ => {
+                            state.entries.remove(root_hash);
+                        }
+                    }
+                    changed.insert(*root_hash);
+                }
+                AttestationEvent::Removed(root_hash) => {
+                    state.entries.insert(*root_hash, None);
+                    changed.insert(*root_hash);
+                }
+            }
+        }
+        state.last_block = Some(block);
+        state.applied_at = Some(Instant::now());
+        Ok(changed)
+    }
+}
+
+/// Serves the latest attestations from a synced cache and everything else from the backend.
+/// Misses are looked up and cached unless events were applied while they were looked up.
+pub struct CachedBackend<'a> {
+    inner: &'a dyn ChainBackend,
+    cache: &'a RevocationCache,
+    observer: Option<&'a dyn VerificationObserver>,
+}
+
+impl<'a> CachedBackend<'a> {
+    pub fn new(inner: &'a dyn ChainBackend, cache: &'a RevocationCache) -> Self {
+        CachedBackend {
+            inner,
+            cache,
+            observer: None,
+        }
+    }
+
+    /// Tell the observer about every attestation served from the cache
+    pub fn observed(self, observer: &'a dyn VerificationObserver) -> Self {
+        CachedBackend {
+            observer: Some(observer),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl ChainBackend for CachedBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.inner.did(did, at).await
+    }
+
+    #[tracing::instrument(name = "cached_attestation", skip_all, fields(hit = false))]
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        if at.is_some() {
+            return self.inner.attestation(root_hash, at).await;
+        }
+        let last_block = {
+            let mut state = self.cache.lock();
+            if let Some(entry) = state.entries.get(root_hash).filter(|_| state.synced) {
+                let attestation = entry.as_deref().map(decode).transpose()?;
+                state.hits += 1;
+                drop(state);
+                tracing::Span::current().record("hit", &true);
+                if let Some(observer) = self.observer {
+                    observer.on_cache_hit(root_hash);
+                }
+                return Ok(attestation);
+            }
+            state.misses += 1;
+            state.synced.then_some(state.last_block).flatten()
+        };
+        let attestation = self.inner.attestation(root_hash, None).await?;
+        let mut state = self.cache.lock();
+        if state.synced && last_block.is_some() && state.last_block == last_block {
+            state
+                .entries
+                .insert(*root_hash, attestation.as_ref().map(|a| a.encode()));
+        }
+        Ok(attestation)
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.inner.attestation_history(root_hash).await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.web3_name_owner(name, at).await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.inner.web3_name(owner, at).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.inner.service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.inner.token().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::CountingBackend,
+        fixtures,
+        mock::MockState,
+        utils::{get_did_account_id, hex_decode_h256},
+    };
+
+    #[tokio::test]
+    async fn test_cache() {
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        let other = H256::repeat_byte(7);
+        let mut chain = fixtures::backend();
+        let block_hash = H256::repeat_byte(1);
+        let mut block = MockState::default();
+        block.insert_attestation(root_hash, &fixtures::attestation_details(false));
+        chain.insert_block(block_hash, block);
+        let counting = CountingBackend::new(&chain);
+        let cache = RevocationCache::new();
+        let backend = CachedBackend::new(&counting, &cache);
+
+        // nothing is served before the first sync
+        assert_eq!(
+            cache.apply(10, &[]),
+            Err(Gap {
+                last_block: None,
+                block: 10
+            })
+        );
+        assert!(backend
+            .attestation(&root_hash, None)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(counting.calls(), 1);
+        assert_eq!(cache.status().entries, 0);
+
+        cache
+            .sync(&counting, &[root_hash], 10, block_hash)
+            .await
+            .unwrap();
+        let calls = counting.calls();
+        assert!(backend
+            .attestation(&root_hash, None)
+            .await
+            .unwrap()
+            .is_some());
+        // a miss is cached, known missing attestations too
+        assert!(backend.attestation(&other, None).await.unwrap().is_none());
+        assert!(backend.attestation(&other, None).await.unwrap().is_none());
+        assert_eq!(counting.calls(), calls + 1);
+        let status = cache.status();
+        assert_eq!((status.hits, status.entries), (2, 2));
+        assert!(
+            status
+                .to_string()
+                .starts_with("attestation cache synced to block #10"),
+            "{}",
+            status
+        );
+
+        // the revocation is applied from the event, the node is not asked again
+        let changed = cache
+            .apply(
+                11,
+                &[
+                    AttestationEvent::Revoked(root_hash),
+                    AttestationEvent::Created(other),
+                ],
+            )
+            .unwrap();
+        assert_eq!(changed, HashSet::from([root_hash]));
+        assert!(
+            backend
+                .attestation(&root_hash, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .revoked
+        );
+        assert_eq!(counting.calls(), calls + 1);
+        // the created attestation is looked up
+        backend.attestation(&other, None).await.unwrap();
+        assert_eq!(counting.calls(), calls + 2);
+        // a block seen twice changes nothing
+        assert_eq!(
+            cache.apply(11, &[AttestationEvent::Removed(root_hash)]),
+            Ok(HashSet::new())
+        );
+
+        // a skipped block makes the cache stale, it is not served until it is synced again
+        assert_eq!(
+            cache.apply(13, &[]),
+            Err(Gap {
+                last_block: Some(11),
+                block: 13
+            })
+        );
+        let status = cache.status();
+        assert!(!status.synced);
+        assert!(
+            status
+                .to_string()
+                .starts_with("attestation cache stale since block #11"),
+            "{}",
+            status
+        );
+        let calls = counting.calls();
+        // the node says the attestation is valid, the missed block did not revoke it after all
+        assert!(
+            !backend
+                .attestation(&root_hash, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .revoked
+        );
+        assert_eq!(counting.calls(), calls + 1);
+        assert_eq!(
+            cache.apply(14, &[]),
+            Err(Gap {
+                last_block: Some(11),
+                block: 14
+            })
+        );
+
+        cache.sync(&counting, &[], 14, block_hash).await.unwrap();
+        // the entries of the cache are synced again
+        assert_eq!(cache.status().entries, 2);
+        assert_eq!(
+            cache.apply(15, &[AttestationEvent::Removed(root_hash)]),
+            Ok(HashSet::from([root_hash]))
+        );
+        assert!(backend
+            .attestation(&root_hash, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        // lookups at a block never use the cache
+        assert!(backend
+            .attestation(&root_hash, Some(block_hash))
+            .await
+            .unwrap()
+            .is_some());
+        cache.invalidate();
+        assert!(!cache.status().synced);
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_event() {
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        let attester = get_did_account_id(&fixtures::attester_did()).unwrap();
+        let revoked = || {
+            Ok(Event::Attestation(
+                AttestationPalletEvent::AttestationRevoked(attester.clone(), root_hash),
+            ))
+        };
+        assert_eq!(
+            decoded_attestation_events([revoked()].into_iter()).unwrap(),
+            vec![AttestationEvent::Revoked(root_hash)]
+        );
+        // the event that cannot be decoded could be a revocation as well
+        let undecodable = Err(BasicError::Other("cannot decode the event".to_string()));
+        let res = decoded_attestation_events([revoked(), undecodable].into_iter());
+        assert!(res.is_err(), "{:?}", res);
+
+        // the block is not applied, the cache is stale until it is synced again
+        let mut chain = fixtures::backend();
+        let block_hash = H256::repeat_byte(1);
+        let mut block = MockState::default();
+        block.insert_attestation(root_hash, &fixtures::attestation_details(false));
+        chain.insert_block(block_hash, block);
+        let cache = RevocationCache::new();
+        cache
+            .sync(&chain, &[root_hash], 10, block_hash)
+            .await
+            .unwrap();
+        cache.invalidate();
+        assert_eq!(
+            cache.apply(11, &[]),
+            Err(Gap {
+                last_block: Some(10),
+                block: 11
+            })
+        );
+        assert!(!cache.status().synced);
+    }
+}
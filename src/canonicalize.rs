@@ -0,0 +1,201 @@
+use clap::Args;
+use serde_json::{Map, Value};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    credential::{Credential, StatementKeys},
+    errors::Error,
+    utils::{parse_credential, read_credential_json},
+};
+
+/// Write a credential in its canonical form, i.e. so archived copies can be deduplicated by the
+/// hash of the file
+#[derive(Args, Debug)]
+pub struct CanonicalizeArgs {
+    /// File containing the credential to canonicalize
+    #[clap(short, long, value_parser, default_value = "stdin")]
+    file: String,
+
+    /// File to write the canonical credential to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+}
+
+// the credential with the checks that need no chain, like minimize does
+fn check_offline(data: &[u8], statement_keys: &StatementKeys) -> Result<Credential, Error> {
+    let mut cred = parse_credential(data)?;
+    cred.claim.statement_keys = statement_keys.clone();
+    cred.check_claim_contents()?;
+    cred.check_root_hash()?;
+    Ok(cred)
+}
+
+/// Write the json of a credential in its canonical form: no whitespace, the members of objects
+/// sorted by name, strings in NFC and `0x` hex in lowercase. What is committed to is kept byte for
+/// byte: the claim and the nonces by the root hash, the challenge by the signature, and the order
+/// of `claimHashes`. The output has to pass the checks that need no chain with the same root hash,
+/// the claim is normalized with the statement keys.
+pub fn canonicalize(data: &[u8], statement_keys: &StatementKeys) -> Result<Vec<u8>, Error> {
+    let cred = check_offline(data, statement_keys)?;
+
+    let mut json: Value = serde_json::from_slice(data)?;
+    canonical_credential(&mut json);
+    let json = serde_json::to_vec(&json)?;
+
+    let canonical = check_offline(&json, statement_keys)?;
+    if canonical.root_hash != cred.root_hash {
+        return Err(Error::InvalidRootHash);
+    }
+    Ok(json)
+}
+
+fn canonical_credential(value: &mut Value) {
+    let fields = match value {
+        Value::Object(fields) => fields,
+        value => return canonical(value),
+    };
+    for (name, field) in fields.iter_mut() {
+        match (name.as_str(), field) {
+            ("claim", _) => {}
+            ("claimNonceMap", Value::Object(nonces)) => {
+                *nonces = std::mem::take(nonces)
+                    .into_iter()
+                    .map(|(hash, nonce)| (canonical_string(&hash), nonce))
+                    .collect();
+            }
+            ("claimerSignature", Value::Object(signature)) => {
+                for (name, field) in signature.iter_mut() {
+                    if name != "challenge" {
+                        canonical(field);
+                    }
+                }
+            }
+            ("legitimations", Value::Array(legitimations)) => {
+                legitimations.iter_mut().for_each(canonical_credential)
+            }
+            (_, field) => canonical(field),
+        }
+    }
+}
+
+fn canonical(value: &mut Value) {
+    match value {
+        Value::String(string) => *string = canonical_string(string),
+        Value::Array(values) => values.iter_mut().for_each(canonical),
+        Value::Object(members) => {
+            *members = std::mem::take(members)
+                .into_iter()
+                .map(|(name, mut value)| {
+                    canonical(&mut value);
+                    (canonical_string(&name), value)
+                })
+                .collect::<Map<_, _>>();
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    s.strip_prefix("0x")
+        .is_some_and(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// a string in NFC, with hex and the hex fragment of a key uri in lowercase
+fn canonical_string(s: &str) -> String {
+    let s: String = s.nfc().collect();
+    if is_hex(&s) {
+        return s.to_ascii_lowercase();
+    }
+    match s.split_once('#') {
+        Some((did, fragment)) if is_hex(fragment) => {
+            format!("{}#{}", did, fragment.to_ascii_lowercase())
+        }
+        _ => s,
+    }
+}
+
+pub fn run(args: &CanonicalizeArgs, statement_keys: &StatementKeys) -> Result<(), Error> {
+    let data = read_credential_json(&args.file)?.into_bytes();
+    let canonical = canonicalize(&data, statement_keys)?;
+    if args.output == "stdout" {
+        println!("{}", String::from_utf8_lossy(&canonical));
+    } else {
+        std::fs::write(&args.output, &canonical)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_canonicalize() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let cred = fixtures::credential();
+        let plain = serde_json::to_vec(&cred).unwrap();
+
+        // the same credential with whitespace, uppercase hex and decomposed strings
+        let mut json = serde_json::to_value(&cred).unwrap();
+        let signature = json["claimerSignature"]["signature"].as_str().unwrap();
+        json["claimerSignature"]["signature"] = json!(format!(
+            "0x{}",
+            signature.trim_start_matches("0x").to_ascii_uppercase()
+        ));
+        let (did, fragment) = cred.claimer_signature.key_uri.split_once('#').unwrap();
+        json["claimerSignature"]["keyUri"] = json!(format!(
+            "{}#0x{}",
+            did,
+            fragment.trim_start_matches("0x").to_ascii_uppercase()
+        ));
+        let mut with_note = json.clone();
+        json["note"] = json!("Cafe\u{301}");
+        with_note["note"] = json!("Caf\u{e9}");
+        let messy = serde_json::to_vec_pretty(&json).unwrap();
+
+        let canonical = canonicalize(&messy, &Default::default()).unwrap();
+        assert_eq!(
+            canonical,
+            canonicalize(
+                &serde_json::to_vec(&with_note).unwrap(),
+                &Default::default()
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            canonicalize(&canonical, &Default::default()).unwrap(),
+            canonical
+        );
+        assert!(!canonical.contains(&b'\n'));
+        let output: Value = serde_json::from_slice(&canonical).unwrap();
+        assert_eq!(output["note"], json!("Caf\u{e9}"));
+        assert_eq!(output["claim"], serde_json::to_value(&cred.claim).unwrap());
+        assert_eq!(output["claimHashes"], json!(cred.claim_hashes));
+        assert_eq!(
+            output["claimerSignature"]["keyUri"],
+            json!(cred.claimer_signature.key_uri)
+        );
+
+        // the canonical form verifies the same as the input
+        let expected = parse_credential(&plain)
+            .unwrap()
+            .verify(&backend, &[&attester], None)
+            .await;
+        assert!(expected.is_ok(), "{:?}", expected);
+        for data in [&messy, &canonical] {
+            let res = parse_credential(data)
+                .unwrap()
+                .verify(&backend, &[&attester], None)
+                .await;
+            assert_eq!(format!("{:?}", res), format!("{:?}", expected));
+        }
+
+        // committed strings are kept, so a tampered claim still fails
+        json["claim"]["contents"]["Email"] = json!("mallory@example.com");
+        let res = canonicalize(&serde_json::to_vec(&json).unwrap(), &Default::default());
+        assert!(matches!(res, Err(Error::InvalidClaimContents)), "{:?}", res);
+    }
+}
@@ -0,0 +1,192 @@
+use ciborium::value::Value as Cbor;
+use serde_json::{Map, Number, Value};
+
+use crate::errors::Error;
+
+/// Encode the json of a credential as deterministic CBOR (RFC 8949 section 4.2): numbers and
+/// lengths in their shortest form and the members of objects ordered by their encoded name, that
+/// is shorter names first. Strings stay text, so `to_json` gives back the same json.
+pub fn to_cbor(value: &Value) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(&cbor(value), &mut data)
+        .map_err(|err| Error::Cbor(err.to_string()))?;
+    Ok(data)
+}
+
+fn cbor(value: &Value) -> Cbor {
+    match value {
+        Value::Null => Cbor::Null,
+        Value::Bool(boolean) => Cbor::Bool(*boolean),
+        Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+            (Some(int), _) => Cbor::Integer(int.into()),
+            (_, Some(int)) => Cbor::Integer(int.into()),
+            // json numbers are always finite
+            _ => Cbor::Float(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(string) => Cbor::Text(string.clone()),
+        Value::Array(values) => Cbor::Array(values.iter().map(cbor).collect()),
+        Value::Object(members) => {
+            let mut members: Vec<_> = members.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+            Cbor::Map(
+                members
+                    .into_iter()
+                    .map(|(name, value)| (Cbor::Text(name.clone()), cbor(value)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Decode a CBOR credential into the json the claim is normalized from. Only what json has is
+/// accepted: maps with unique text keys, integers that fit 64 bits and finite floats. The map
+/// order of the input does not matter.
+pub fn to_json(data: &[u8]) -> Result<Value, Error> {
+    let mut rest = data;
+    let value: Cbor =
+        ciborium::de::from_reader(&mut rest).map_err(|err| Error::Cbor(err.to_string()))?;
+    if !rest.is_empty() {
+        return Err(Error::Cbor(format!(
+            "{} bytes after the credential",
+            rest.len()
+        )));
+    }
+    json(value, "$")
+}
+
+fn json(value: Cbor, path: &str) -> Result<Value, Error> {
+    let invalid = |what: &str| Err(Error::Cbor(format!("{} at {}", what, path)));
+    match value {
+        Cbor::Null => Ok(Value::Null),
+        Cbor::Bool(boolean) => Ok(Value::Bool(boolean)),
+        Cbor::Integer(int) => {
+            let int = i128::from(int);
+            match (u64::try_from(int), i64::try_from(int)) {
+                (Ok(int), _) => Ok(int.into()),
+                (_, Ok(int)) => Ok(int.into()),
+                _ => invalid(&format!("integer {} does not fit 64 bits", int)),
+            }
+        }
+        Cbor::Float(float) => match Number::from_f64(float) {
+            Some(number) => Ok(Value::Number(number)),
+            None => invalid(&format!("{} is not a finite number", float)),
+        },
+        Cbor::Text(string) => Ok(Value::String(string)),
+        Cbor::Array(values) => values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| json(value, &format!("{}[{}]", path, index)))
+            .collect(),
+        Cbor::Map(entries) => {
+            let mut members = Map::new();
+            for (key, value) in entries {
+                let name = match key {
+                    Cbor::Text(name) => name,
+                    _ => return invalid("a key that is not text"),
+                };
+                let value = json(value, &format!("{}.{}", path, name))?;
+                if members.insert(name.clone(), value).is_some() {
+                    return invalid(&format!("duplicate key {}", name));
+                }
+            }
+            Ok(Value::Object(members))
+        }
+        Cbor::Bytes(_) => invalid("a byte string"),
+        Cbor::Tag(tag, _) => invalid(&format!("tag {}", tag)),
+        _ => invalid("a value json does not have"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, utils::parse_credential};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_cbor_credential() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let json = serde_json::to_value(fixtures::credential()).unwrap();
+        let data = serde_json::to_vec(&json).unwrap();
+        let encoded = to_cbor(&json).unwrap();
+        assert!(encoded.len() < data.len());
+        eprintln!(
+            "json {} bytes, cbor {} bytes, {:.1}% smaller",
+            data.len(),
+            encoded.len(),
+            100.0 * (data.len() - encoded.len()) as f64 / data.len() as f64
+        );
+
+        let decoded = to_json(&encoded).unwrap();
+        assert_eq!(decoded, json);
+        let from_json = parse_credential(&data).unwrap();
+        let from_cbor = parse_credential(&serde_json::to_vec(&decoded).unwrap()).unwrap();
+        assert_eq!(
+            from_cbor.claim.hash_statements().unwrap(),
+            from_json.claim.hash_statements().unwrap()
+        );
+        let res = from_json.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(
+            format!("{:?}", from_cbor.verify(&backend, &[&attester], None).await),
+            format!("{:?}", res)
+        );
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let value = json!({"bb": 1, "a": [1.5, -2, 1.0], "c": {"zz": null, "y": true}});
+        let encoded = to_cbor(&value).unwrap();
+        // shorter keys first, small integers and floats in their shortest form
+        assert_eq!(
+            hex::encode(&encoded),
+            "a3616183f93e0021f93c006163a26179f5627a7af662626201"
+        );
+        assert_eq!(to_cbor(&to_json(&encoded).unwrap()).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let encode = |value: &Cbor| {
+            let mut data = Vec::new();
+            ciborium::ser::into_writer(value, &mut data).unwrap();
+            data
+        };
+        let text = |s: &str| Cbor::Text(s.to_string());
+        // maps in any order
+        let map = Cbor::Map(vec![(text("b"), text("x")), (text("a"), 1.into())]);
+        assert_eq!(to_json(&encode(&map)).unwrap(), json!({"a": 1, "b": "x"}));
+
+        for (value, error) in [
+            (Cbor::Bytes(vec![1]), "a byte string at $"),
+            (
+                Cbor::Map(vec![(1.into(), text("x"))]),
+                "a key that is not text at $",
+            ),
+            (
+                Cbor::Map(vec![(text("a"), 1.into()), (text("a"), 2.into())]),
+                "duplicate key a at $",
+            ),
+            (
+                Cbor::Array(vec![Cbor::Float(f64::NAN)]),
+                "NaN is not a finite number at $[0]",
+            ),
+            (
+                Cbor::Map(vec![(text("a"), Cbor::Tag(0, Box::new(text("2024"))))]),
+                "tag 0 at $.a",
+            ),
+        ] {
+            let res = to_json(&encode(&value));
+            assert!(
+                matches!(&res, Err(Error::Cbor(msg)) if msg == error),
+                "{:?}",
+                res
+            );
+        }
+        let mut data = encode(&text("a"));
+        data.push(0);
+        assert!(matches!(to_json(&data), Err(Error::Cbor(_))));
+        assert!(matches!(to_json(&[0xff]), Err(Error::Cbor(_))));
+    }
+}
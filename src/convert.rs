@@ -0,0 +1,114 @@
+use clap::Args;
+use std::io::Write;
+
+use crate::{
+    cbor,
+    errors::Error,
+    utils::{parse_credential, read_credential_data, InputFormat, ParseOptions},
+};
+
+/// Write a credential in another format, i.e. as CBOR for NFC and QR codes
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// File containing the credential, its format is taken from the extension or --input-format
+    #[clap(short, long, value_parser, default_value = "stdin")]
+    file: String,
+
+    /// Format to write the credential in
+    #[clap(long, value_enum)]
+    to: InputFormat,
+
+    /// File to write the converted credential to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+}
+
+// the checks that need no chain, like minimize does
+fn check_offline(json: &serde_json::Value) -> Result<String, Error> {
+    let cred = parse_credential(&serde_json::to_vec(json)?)?;
+    cred.check_claim_contents()?;
+    cred.check_root_hash()?;
+    Ok(cred.root_hash)
+}
+
+/// Write the json a credential stands for in another format. Fields this tool does not know are
+/// kept. The credential has to pass the checks that need no chain, before and after, with the
+/// same root hash, so converting never changes what is verified.
+pub fn convert(
+    source: &str,
+    data: &[u8],
+    parse: &ParseOptions,
+    to: InputFormat,
+) -> Result<Vec<u8>, Error> {
+    let json = parse.to_json(source, data)?;
+    let root_hash = check_offline(&json)?;
+    let converted = match to {
+        InputFormat::Json => serde_json::to_vec(&json)?,
+        InputFormat::Yaml => serde_yaml::to_string(&json)?.into_bytes(),
+        InputFormat::Cbor => cbor::to_cbor(&json)?,
+    };
+    let strict = ParseOptions {
+        format: Some(to),
+        ..Default::default()
+    };
+    if check_offline(&strict.to_json(source, &converted)?)? != root_hash {
+        return Err(Error::InvalidRootHash);
+    }
+    Ok(converted)
+}
+
+pub fn run(args: &ConvertArgs, parse: &ParseOptions) -> Result<(), Error> {
+    let data = read_credential_data(&args.file)?;
+    let converted = convert(&args.file, &data, parse, args.to)?;
+    if args.output == "stdout" {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(&converted)?;
+        if args.to == InputFormat::Json {
+            writeln!(stdout)?;
+        }
+    } else {
+        std::fs::write(&args.output, &converted)?;
+    }
+    eprintln!(
+        "Converted {} bytes of {} to {} bytes of {} ({:+.1}%)",
+        data.len(),
+        parse.format_of(&args.file).name(),
+        converted.len(),
+        args.to.name(),
+        100.0 * (converted.len() as f64 - data.len() as f64) / data.len().max(1) as f64
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_convert() {
+        let data = include_bytes!("../presentation-1.json");
+        let parse = ParseOptions::default();
+        let json: Value = serde_json::from_slice(data).unwrap();
+        for to in [InputFormat::Cbor, InputFormat::Yaml, InputFormat::Json] {
+            let converted = convert("presentation-1.json", data, &parse, to).unwrap();
+            let strict = ParseOptions {
+                format: Some(to),
+                ..Default::default()
+            };
+            assert_eq!(strict.to_json("stdin", &converted).unwrap(), json);
+            let back = convert("stdin", &converted, &strict, InputFormat::Json).unwrap();
+            assert_eq!(serde_json::from_slice::<Value>(&back).unwrap(), json);
+        }
+        let cbor = convert("presentation-1.json", data, &parse, InputFormat::Cbor).unwrap();
+        let compact = serde_json::to_vec(&json).unwrap();
+        assert!(cbor.len() < compact.len());
+
+        // credentials that fail already are not converted
+        let mut tampered = json.clone();
+        tampered["claim"]["contents"]["Email"] = "mallory@example.com".into();
+        let tampered = serde_json::to_vec(&tampered).unwrap();
+        let res = convert("stdin", &tampered, &parse, InputFormat::Cbor);
+        assert!(matches!(res, Err(Error::InvalidClaimContents)), "{:?}", res);
+    }
+}
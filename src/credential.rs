@@ -1,25 +1,31 @@
 use blake2::{digest::consts::U32, Blake2b, Digest};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
-use std::collections::HashMap;
-use subxt::sp_runtime::app_crypto::RuntimePublic;
+use std::{collections::HashMap, io::Write, str::FromStr, sync::Mutex};
+use subxt::{sp_core::H256, sp_runtime::app_crypto::RuntimePublic};
 
 use crate::{
+    backend::ChainBackend,
+    did,
     errors::Error,
-    kilt::{
-        runtime_types::did::did_details::{
-            DidPublicKey::PublicVerificationKey, DidVerificationKey,
-        },
-        KiltRuntimeApi,
+    kilt::runtime_types::did::did_details::{
+        DidPublicKey::{self, PublicVerificationKey},
+        DidVerificationKey,
+    },
+    limits::{self, Limits},
+    metrics::{self, Check, CheckTimings},
+    progress::{observe, observe_async, CheckStep, StepStatus, VerificationObserver},
+    utils::{
+        get_did_account_id, hex_decode, hex_decode_h256, hex_encode, issuer_account, short_hash,
+        DidKeyPair, DidUrl,
     },
-    utils::{get_did_account_id, get_did_key_uri, hex_decode, hex_encode},
 };
 
 type Blake2b256 = Blake2b<U32>;
 
 /// Top-level structure of a credential
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Credential {
     #[serde(rename = "claim")]
     pub claim: Claim,
@@ -27,14 +33,45 @@ pub struct Credential {
     pub claim_hashes: Vec<String>,
     #[serde(rename = "claimNonceMap")]
     pub claim_nonce_map: HashMap<String, String>,
-    #[serde(rename = "claimerSignature")]
+    /// Exports of SDKs before 1.0 have the signature only, see `key_inferred`
+    #[serde(
+        rename = "claimerSignature",
+        deserialize_with = "current_or_legacy",
+        serialize_with = "serialize_current_or_legacy"
+    )]
     pub claimer_signature: ClaimerSignature,
-    #[serde(rename = "rootHash")]
+    /// Minimal presentation formats leave it out, it is derived from the claim hashes then
+    #[serde(rename = "rootHash", default)]
     pub root_hash: String,
+    /// The credential had no root hash, it was computed by `parse_credential`
+    #[serde(skip)]
+    pub root_hash_derived: bool,
+    /// How deep the legitimations nest, they are not checked and not kept
+    #[serde(
+        rename = "legitimations",
+        default,
+        deserialize_with = "limits::legitimation_depth",
+        skip_serializing
+    )]
+    pub legitimation_depth: usize,
+    /// Limits checked by `check_claim_contents`, set by `ParseOptions::parse`
+    #[serde(skip)]
+    pub limits: Limits,
+}
+
+/// What a verifier expects of the claim on top of a valid credential
+#[derive(Debug, Clone, Default)]
+pub struct ClaimRequirements {
+    /// DID the claim must be owned by
+    pub owner: Option<String>,
+    /// Properties that must be disclosed in the claim contents
+    pub properties: Vec<String>,
+    /// The owner must be a full DID, not the light DID of one, see `Credential::light_owner`
+    pub full_did: bool,
 }
 
 /// The claim holds the actual data that is attested
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Claim {
     #[serde(rename = "cTypeHash")]
     pub ctype_hash: String,
@@ -42,69 +79,588 @@ pub struct Claim {
     pub contents: serde_json::Value,
     #[serde(rename = "owner")]
     pub owner: String,
+    /// How the statements of the properties are keyed when the claim is normalized, set by
+    /// `ParseOptions::parse`
+    #[serde(skip)]
+    pub statement_keys: StatementKeys,
+}
+
+/// The key of the normalized statement of a property, the KILT SDK writes
+/// `kilt:ctype:<ctype hash>#<property>`. Forks of the chain and SDK may use another scheme
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StatementKeys {
+    #[default]
+    KiltCtype,
+    /// A template in which `{ctype}` is replaced by the ctype hash and `{property}` by the
+    /// property, i.e. `fork:ctype:{ctype}#{property}`
+    Custom(String),
+}
+
+/// The template of the keys the KILT SDK writes
+pub const KILT_STATEMENT_KEYS: &str = "kilt:ctype:{ctype}#{property}";
+
+impl StatementKeys {
+    /// The key of the statement of the property of a claim of the ctype
+    pub fn key(&self, ctype_hash: &str, property: &str) -> String {
+        let template = match self {
+            StatementKeys::KiltCtype => KILT_STATEMENT_KEYS,
+            StatementKeys::Custom(template) => template,
+        };
+        // the parts are filled in one pass, a ctype hash or property that reads like a
+        // placeholder stays as it is
+        template
+            .split("{property}")
+            .map(|part| part.replace("{ctype}", ctype_hash))
+            .collect::<Vec<_>>()
+            .join(property)
+    }
+
+    /// The template of custom keys, `None` for the keys of KILT
+    pub fn template(&self) -> Option<&str> {
+        match self {
+            StatementKeys::KiltCtype => None,
+            StatementKeys::Custom(template) => Some(template),
+        }
+    }
+}
+
+impl FromStr for StatementKeys {
+    type Err = String;
+
+    /// A template with `{property}`, otherwise all properties would have the same key
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        match template {
+            KILT_STATEMENT_KEYS => Ok(StatementKeys::KiltCtype),
+            _ if !template.contains("{property}") => Err(format!(
+                "{} has no {{property}}, i.e. {}",
+                template, KILT_STATEMENT_KEYS
+            )),
+            _ => Ok(StatementKeys::Custom(template.to_string())),
+        }
+    }
 }
 
 /// The claimer signature proofs that the owner of the claim signed the claim
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ClaimerSignature {
     pub signature: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub challenge: String,
     #[serde(rename = "keyUri")]
     pub key_uri: String,
+    /// A bare signature of a legacy export, it names no key
+    #[serde(skip)]
+    pub legacy: bool,
+}
+
+// a claimer signature with its key uri, or a bare signature of a legacy export
+fn current_or_legacy<'de, D>(deserializer: D) -> Result<ClaimerSignature, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AnySignature {
+        Current(ClaimerSignature),
+        Legacy(String),
+    }
+    Ok(match AnySignature::deserialize(deserializer)? {
+        AnySignature::Current(signature) => signature,
+        AnySignature::Legacy(signature) => ClaimerSignature {
+            signature,
+            challenge: String::new(),
+            key_uri: String::new(),
+            legacy: true,
+        },
+    })
+}
+
+// a legacy signature stays bare, so the credential parses the same way again
+fn serialize_current_or_legacy<S>(
+    signature: &ClaimerSignature,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if signature.legacy {
+        serializer.serialize_str(&signature.signature)
+    } else {
+        signature.serialize(serializer)
+    }
+}
+
+// check a sr25519 or ed25519 signature, other key types are not supported
+pub(crate) fn verifies(
+    key: &DidPublicKey,
+    msg: &[u8],
+    signature: &[u8; 64],
+) -> Result<bool, Error> {
+    match key {
+        PublicVerificationKey(DidVerificationKey::Sr25519(key)) => {
+            let pub_key = subxt::sp_core::sr25519::Public::from_raw(key.0);
+            let sig = subxt::sp_core::sr25519::Signature::from_raw(*signature);
+            Ok(pub_key.verify(&msg, &sig))
+        }
+        PublicVerificationKey(DidVerificationKey::Ed25519(key)) => {
+            let pub_key = subxt::sp_core::ed25519::Public::from_raw(key.0);
+            let sig = subxt::sp_core::ed25519::Signature::from_raw(*signature);
+            Ok(pub_key.verify(&msg, &sig))
+        }
+        _ => Err(Error::InvalidDid),
+    }
+}
+
+fn flatten_into(
+    path: String,
+    value: &serde_json::Value,
+    properties: &mut Vec<(String, serde_json::Value)>,
+) {
+    match value {
+        serde_json::Value::Object(members) if !members.is_empty() => {
+            for (name, value) in members {
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                flatten_into(path, value, properties);
+            }
+        }
+        serde_json::Value::Array(items) if !items.is_empty() => {
+            for (index, item) in items.iter().enumerate() {
+                flatten_into(format!("{}[{}]", path, index), item, properties);
+            }
+        }
+        value => properties.push((path, value.clone())),
+    }
+}
+
+/// What claim values are replaced with when they are kept out of the output
+pub const REDACTED: &str = "[redacted]";
+
+/// Longest value shown in a claim listing, longer ones are cut
+pub const MAX_CLAIM_VALUE_CHARS: usize = 64;
+
+/// Whether a string claim value would read like a value of another type when it is shown without
+/// quotes, i.e. `"null"` next to null, `"true"` next to true, or `""` next to nothing
+pub fn reads_as_other_value(value: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(value)
+        .map_or(value.trim().is_empty(), |value| !value.is_string())
+}
+
+/// List the flattened properties of a claim one per line, i.e. `  Email: tino@kilt.io`.
+/// Strings are shown without quotes, unless they would read like another value, and control
+/// characters are escaped so that a value cannot forge lines of a log. With `redact` only the
+/// property names are shown.
+pub fn describe_claim(claim: &Claim, redact: bool) -> String {
+    let mut text = String::new();
+    for (path, value) in claim.flatten() {
+        let value = match (redact, &value) {
+            (true, _) => REDACTED.to_string(),
+            (false, serde_json::Value::String(text)) if !reads_as_other_value(text) => text
+                .chars()
+                .map(|c| {
+                    if c.is_control() {
+                        c.escape_default().to_string()
+                    } else {
+                        c.to_string()
+                    }
+                })
+                .collect(),
+            (false, value) => value.to_string(),
+        };
+        let value = match value.chars().nth(MAX_CLAIM_VALUE_CHARS) {
+            Some(_) => {
+                let cut: String = value.chars().take(MAX_CLAIM_VALUE_CHARS - 1).collect();
+                format!("{}…", cut)
+            }
+            None => value,
+        };
+        text += &format!("  {}: {}\n", path, value);
+    }
+    text
 }
 
 impl Claim {
+    /// The normalized statements of the claim, the owner like `{"@id":"did:kilt:12345"}` and one
+    /// for every top-level entry of the contents like `{"kilt:ctype:12345#Email":"foo@bar.com"}`,
+    /// keyed as `statement_keys` says
     pub fn normalize(&self) -> Result<Vec<String>, Error> {
         let mut normalized = Vec::new();
+        self.write_statements(|write| {
+            let mut statement = Vec::new();
+            write(&mut statement)?;
+            // serde_json only writes utf-8
+            normalized.push(String::from_utf8_lossy(&statement).into_owned());
+            Ok(())
+        })?;
+        Ok(normalized)
+    }
 
-        // First add the owner field like `{"@id":"did:kilt:12345"}`
-        let owner_map = serde_json::json!({"@id": self.owner.clone()});
-        normalized.push(serde_json::to_string(&owner_map)?);
-
-        // Now add for every toplevel entry in the contents one object like this:
-        // `{"kilt:ctype:12345#Email":"foo@bar.com"}`
-        self.contents
+    // call `each` with a writer of every normalized statement, so a statement can be used
+    // without building it
+    fn write_statements(
+        &self,
+        mut each: impl FnMut(&dyn Fn(&mut dyn Write) -> Result<(), Error>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        each(&|w| write_owner_statement(w, &self.owner))?;
+        for (key, value) in self
+            .contents
             .as_object()
             .ok_or(Error::InvalidClaimContents)?
-            .iter()
-            .try_for_each(|(key, value)| -> Result<(), Error> {
-                let key = format!("kilt:ctype:{}#{}", self.ctype_hash, key);
-                normalized.push(serde_json::to_string(&json!({ key: value }))?);
-                Ok(())
-            })?;
+        {
+            each(&|w| write_statement(w, &self.statement_keys.key(&self.ctype_hash, key), value))?;
+        }
+        Ok(())
+    }
 
-        Ok(normalized)
+    /// The disclosed properties by their path with their json values. Nested objects are joined
+    /// with dots and array items get their index, i.e. `address.city` and `nationalities[0]`.
+    /// Empty objects and arrays are kept as values so that every disclosed property shows up.
+    pub fn flatten(&self) -> Vec<(String, serde_json::Value)> {
+        let mut properties = Vec::new();
+        flatten_into(String::new(), &self.contents, &mut properties);
+        properties
+    }
+
+    /// Hash all normalized statements of the claim. The statements are written into the hasher,
+    /// so a large property is not copied
+    pub fn hash_statements(&self) -> Result<Vec<String>, Error> {
+        let mut hashes = Vec::new();
+        self.write_statements(|write| {
+            let mut hasher = HashWriter(Blake2b256::new());
+            write(&mut hasher)?;
+            hashes.push(hex_encode(hasher.0.finalize()));
+            Ok(())
+        })?;
+        Ok(hashes)
+    }
+}
+
+// feeds what is written into the hasher
+struct HashWriter(Blake2b256);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// write the normalized statement of the owner, i.e. `{"@id":"did:kilt:12345"}`
+fn write_owner_statement(w: &mut dyn Write, owner: &str) -> Result<(), Error> {
+    w.write_all(br#"{"@id":"#)?;
+    serde_json::to_writer(&mut *w, owner)?;
+    Ok(w.write_all(b"}")?)
+}
+
+// write the normalized statement of a property by its key, i.e. `{"kilt:ctype:12345#Email":"foo@bar.com"}`
+fn write_statement(w: &mut dyn Write, key: &str, value: &serde_json::Value) -> Result<(), Error> {
+    w.write_all(b"{")?;
+    serde_json::to_writer(&mut *w, key)?;
+    w.write_all(b":")?;
+    serde_json::to_writer(&mut *w, value)?;
+    Ok(w.write_all(b"}")?)
+}
+
+// hash what is written with blake2b256, a hasher cannot fail to be written to
+fn hash_written(write: impl FnOnce(&mut dyn Write) -> Result<(), Error>) -> String {
+    let mut hasher = HashWriter(Blake2b256::new());
+    write(&mut hasher).expect("json values can be written to a hasher");
+    hex_encode(hasher.0.finalize())
+}
+
+/// The hash of the statement of one property of a claim of the ctype, the key of its nonce in
+/// the nonce map. The same as `Claim::hash_statements` gives for the property, without a claim.
+/// See fixtures/vectors/claim-hashes.json for test vectors
+pub fn hash_statement(ctype_hash: &str, property: &str, value: &serde_json::Value) -> String {
+    let key = StatementKeys::KiltCtype.key(ctype_hash, property);
+    hash_written(|w| write_statement(w, &key, value))
+}
+
+/// The hash of the statement of the owner of a claim, the first of `Claim::hash_statements`
+pub fn hash_owner_statement(owner: &str) -> String {
+    hash_written(|w| write_owner_statement(w, owner))
+}
+
+// hash a normalized statement with blake2b256, i.e. `{"@id":"did:kilt:12345"}`
+pub(crate) fn hash_normalized(statement: &str) -> String {
+    let mut hasher = Blake2b256::new();
+    hasher.update(statement);
+    hex_encode(hasher.finalize())
+}
+
+/// Salt a statement hash with its nonce, the result is listed in the claim hashes
+pub fn salt_hash(nonce: &str, statement_hash: &str) -> String {
+    let mut hasher = Blake2b256::new();
+    hasher.update(nonce);
+    hasher.update(statement_hash);
+    hex_encode(hasher.finalize())
+}
+
+// hash the raw bytes of all claim hashes together in the given order
+pub(crate) fn calculate_root_hash(claim_hashes: &[String]) -> Result<String, Error> {
+    let mut hasher = Blake2b256::new();
+    for hash in claim_hashes.iter() {
+        let data = hex_decode(hash)?;
+        hasher.update(&data);
+    }
+    Ok(hex_encode(hasher.finalize()))
+}
+
+// the claimer signs the raw root hash followed by the challenge of the verifier
+fn signing_data(root_hash: &str, challenge: &str) -> Result<Vec<u8>, Error> {
+    let mut msg = hex_decode(root_hash)?;
+    msg.extend_from_slice(challenge.as_bytes());
+    Ok(msg)
+}
+
+/// Claim hashes, the nonce map from statement hash to nonce and the root hash of a claim
+pub type ClaimHashes = (Vec<String>, HashMap<String, String>, String);
+
+/// Hash the statements of a claim with fresh nonces in exactly the way the KILT SDK does:
+/// a random uuid as nonce for every statement, the salted hashes sorted and the root hash over them.
+/// Claims whose contents were edited need new hashes, the owner has to sign the new root hash.
+pub fn rehash<R: Rng>(claim: &Claim, rng: &mut R) -> Result<ClaimHashes, Error> {
+    let mut claim_hashes = Vec::new();
+    let mut claim_nonce_map = HashMap::new();
+    for hash in claim.hash_statements()? {
+        let nonce = uuid::Builder::from_random_bytes(rng.gen())
+            .into_uuid()
+            .to_string();
+        claim_hashes.push(salt_hash(&nonce, &hash));
+        claim_nonce_map.insert(hash, nonce);
     }
+    // The order of the claim hashes must not reveal which statement they belong to
+    claim_hashes.sort();
+
+    let root_hash = calculate_root_hash(&claim_hashes)?;
+    Ok((claim_hashes, claim_nonce_map, root_hash))
 }
 
 impl Credential {
-    /// This will verify a credential
+    /// Create a credential for the claim and sign it with the key of the owner.
+    /// Like the KILT SDK this uses a random uuid as nonce for every statement.
+    pub fn create<R: Rng>(
+        claim: Claim,
+        owner_key: &DidKeyPair,
+        rng: &mut R,
+    ) -> Result<Self, Error> {
+        let (claim_hashes, claim_nonce_map, root_hash) = rehash(&claim, rng)?;
+        let mut credential = Credential {
+            claim,
+            claim_hashes,
+            claim_nonce_map,
+            claimer_signature: ClaimerSignature::default(),
+            root_hash,
+            ..Default::default()
+        };
+        credential.sign(owner_key)?;
+        Ok(credential)
+    }
+
+    /// Sign the root hash with the key of the owner without a challenge, i.e. after `rehash`
+    pub fn sign(&mut self, owner_key: &DidKeyPair) -> Result<(), Error> {
+        let signature = owner_key.sign(&signing_data(&self.root_hash, "")?);
+        self.claimer_signature = ClaimerSignature {
+            signature: hex_encode(signature),
+            challenge: String::new(),
+            key_uri: owner_key.key_uri(),
+            legacy: false,
+        };
+        Ok(())
+    }
+
+    /// Create a presentation that only discloses the given properties of the claim.
+    /// The owner signs the root hash again together with the challenge of the verifier.
+    pub fn create_presentation(
+        &self,
+        reveal: &[String],
+        challenge: &str,
+        owner_key: &DidKeyPair,
+    ) -> Result<Self, Error> {
+        let contents = self
+            .claim
+            .contents
+            .as_object()
+            .ok_or(Error::InvalidClaimContents)?;
+        let revealed = reveal
+            .iter()
+            .map(|key| {
+                contents
+                    .get(key)
+                    .map(|value| (key.clone(), value.clone()))
+                    .ok_or_else(|| Error::PropertyNotFound(key.clone()))
+            })
+            .collect::<Result<serde_json::Map<_, _>, _>>()?;
+        let claim = Claim {
+            contents: serde_json::Value::Object(revealed),
+            ..self.claim.clone()
+        };
+
+        // Only the nonces of the disclosed statements stay, the claim hashes are unchanged
+        let hashes = claim.hash_statements()?;
+        let claim_nonce_map = self
+            .claim_nonce_map
+            .iter()
+            .filter(|(hash, _)| hashes.contains(hash))
+            .map(|(hash, nonce)| (hash.clone(), nonce.clone()))
+            .collect();
+
+        // The key uri refers to the owner DID, which keeps its identifier after key rotations.
+        // The keys of an upgraded light DID are the ones of its full DID
+        let did = DidUrl::parse(&self.claim.owner)?.full_did();
+        let signature = owner_key.sign(&signing_data(&self.root_hash, challenge)?);
+
+        Ok(Credential {
+            claim,
+            claim_hashes: self.claim_hashes.clone(),
+            claim_nonce_map,
+            claimer_signature: ClaimerSignature {
+                signature: hex_encode(signature),
+                challenge: challenge.to_string(),
+                key_uri: format!("{}#{}", did, hex_encode(owner_key.key_id())),
+                legacy: false,
+            },
+            root_hash: self.root_hash.clone(),
+            root_hash_derived: false,
+            legitimation_depth: 0,
+            limits: self.limits,
+        })
+    }
+
+    /// This will verify a credential, optionally requiring that it was signed for a challenge
     pub async fn verify(
         &self,
-        cli: &KiltRuntimeApi,
+        backend: &dyn ChainBackend,
         allowed_issuers: &[&str],
+        challenge: Option<&str>,
     ) -> Result<(), Error> {
-        self.check_claim_contents()?;
-        self.check_root_hash()?;
-        self.check_signature(cli).await?;
-        self.check_attestation(cli, allowed_issuers).await?;
-        Ok(())
+        self.verify_timed(
+            backend,
+            allowed_issuers,
+            challenge,
+            &mut CheckTimings::default(),
+            &|_: CheckStep, _: StepStatus<'_>| {},
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Like `verify_observed`, but also records how long each of the checks took.
+    /// Returns the DID of the attester of a valid credential.
+    pub async fn verify_timed(
+        &self,
+        backend: &dyn ChainBackend,
+        allowed_issuers: &[&str],
+        challenge: Option<&str>,
+        timings: &mut CheckTimings,
+        observer: &dyn VerificationObserver,
+    ) -> Result<String, Error> {
+        // Failed checks are timed as well, the first failure ends the verification
+        let recorded = Mutex::new(CheckTimings::default());
+        let timer = |step: CheckStep, status: StepStatus<'_>| {
+            if let (Some(check), Some(elapsed)) = (step.check(), status.elapsed()) {
+                recorded.lock().unwrap().record(check, elapsed);
+            }
+            observer.on_step(step, status);
+        };
+        let res = self
+            .verify_observed(backend, allowed_issuers, challenge, &timer)
+            .await;
+        let recorded = recorded.into_inner().unwrap();
+        for check in Check::ALL {
+            if let Some(elapsed) = recorded.get(check) {
+                timings.record(check, elapsed);
+            }
+        }
+        res
+    }
+
+    /// Like `verify`, but tells the observer when each step starts and ends.
+    /// Returns the DID of the attester of a valid credential.
+    #[tracing::instrument(
+        name = "verify",
+        skip_all,
+        fields(
+            root_hash = short_hash(&self.root_hash),
+            owner = %self.claim.owner,
+            attester,
+            outcome,
+            code
+        )
+    )]
+    pub async fn verify_observed(
+        &self,
+        backend: &dyn ChainBackend,
+        allowed_issuers: &[&str],
+        challenge: Option<&str>,
+        observer: &dyn VerificationObserver,
+    ) -> Result<String, Error> {
+        let res = self
+            .verify_steps(backend, allowed_issuers, challenge, observer)
+            .await;
+        let span = tracing::Span::current();
+        match &res {
+            Ok(attester) => {
+                span.record("attester", &attester.as_str());
+                span.record("outcome", &"valid");
+            }
+            Err(err) if err.is_infrastructure() => {
+                span.record("outcome", &"incomplete");
+                span.record("code", &err.code());
+            }
+            Err(err) => {
+                span.record("outcome", &"invalid");
+                span.record("code", &err.code());
+            }
+        }
+        res
+    }
+
+    // run the checks one after another, the first failed one ends the verification
+    async fn verify_steps(
+        &self,
+        backend: &dyn ChainBackend,
+        allowed_issuers: &[&str],
+        challenge: Option<&str>,
+        observer: &dyn VerificationObserver,
+    ) -> Result<String, Error> {
+        observe(observer, CheckStep::ClaimContents, || {
+            self.check_claim_contents()
+        })?;
+        observe(observer, CheckStep::RootHash, || self.check_root_hash())?;
+        observe(observer, CheckStep::Challenge, || {
+            self.check_challenge(challenge)
+        })?;
+        observe_async(
+            observer,
+            CheckStep::Signature,
+            self.check_signature(backend),
+        )
+        .await?;
+        observe_async(
+            observer,
+            CheckStep::Attestation,
+            self.check_attestation(backend, allowed_issuers),
+        )
+        .await
     }
 
     /// This will check all disclosed contents against the hashes given in the credential
+    #[tracing::instrument(skip_all)]
     pub fn check_claim_contents(&self) -> Result<(), Error> {
-        // We need to normalize the owner and the contents
-        let normalized_parts = self.claim.normalize()?;
+        let _timer = metrics::time_check(Check::ClaimContents);
+        self.limits.check(self)?;
 
-        // At this point we can calculate the hashes of the normalized statements using blake2b256
-        let hashes = normalized_parts
-            .iter()
-            .map(|part| -> String {
-                let mut hasher = Blake2b256::new();
-                hasher.update(part.as_str());
-                hex_encode(&hasher.finalize())
-            })
-            .collect::<Vec<String>>();
+        // We need to normalize the owner and the contents and
+        // calculate the hashes of the normalized statements using blake2b256
+        let hashes = self.claim.hash_statements()?;
 
         // Each of these hashes should have a corresponding nonce in the nonce map
         // The nonce hashed together with the hash should be listed in the claim_hashes of the credential
@@ -113,10 +669,7 @@ impl Credential {
                 .claim_nonce_map
                 .get(hash)
                 .ok_or(Error::InvalidClaimContents)?;
-            let mut hasher = Blake2b256::new();
-            hasher.update(nonce);
-            hasher.update(hash);
-            let salted_hash = hex_encode(&hasher.finalize());
+            let salted_hash = salt_hash(nonce, hash);
             if !self.claim_hashes.contains(&salted_hash) {
                 Err(Error::InvalidClaimContents)
             } else {
@@ -128,15 +681,17 @@ impl Credential {
         Ok(())
     }
 
+    /// Hash the claim hashes together in the order they are listed
+    pub fn compute_root_hash(&self) -> Result<String, Error> {
+        calculate_root_hash(&self.claim_hashes)
+    }
+
     /// Hashing the claim-hashes together should result in the root hash of the credential
+    #[tracing::instrument(skip_all)]
     pub fn check_root_hash(&self) -> Result<(), Error> {
-        let mut hasher = Blake2b256::new();
-        for hash in self.claim_hashes.iter() {
-            let data = hex_decode(&hash)?;
-            hasher.update(&data);
-        }
+        let _timer = metrics::time_check(Check::RootHash);
 
-        let root_hash = hex_encode(&hasher.finalize());
+        let root_hash = self.compute_root_hash()?;
         if root_hash != self.root_hash {
             Err(Error::InvalidRootHash)
         } else {
@@ -144,73 +699,152 @@ impl Credential {
         }
     }
 
+    /// If the verifier asked for a challenge, the claimer must have signed exactly that one
+    pub fn check_challenge(&self, challenge: Option<&str>) -> Result<(), Error> {
+        match challenge {
+            Some(challenge) if challenge != self.claimer_signature.challenge => {
+                Err(Error::InvalidChallenge)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The claim must be owned by the expected owner and disclose all required properties. A
+    /// property that is set to null is disclosed, only one left out of the contents is not
+    pub fn check_requirements(&self, requirements: &ClaimRequirements) -> Result<(), Error> {
+        if requirements.full_did && self.light_owner() {
+            return Err(Error::LightDidOwner(self.claim.owner.clone()));
+        }
+        if let Some(owner) = &requirements.owner {
+            if get_did_account_id(owner)? != get_did_account_id(&self.claim.owner)? {
+                return Err(Error::UnexpectedOwner);
+            }
+        }
+        match requirements
+            .properties
+            .iter()
+            .find(|property| self.claim.contents.get(property.as_str()).is_none())
+        {
+            Some(property) => Err(Error::PropertyNotFound(property.clone())),
+            None => Ok(()),
+        }
+    }
+
     /// The signature of the credential is checked against the public key of the owner
-    pub async fn check_signature(&self, cli: &KiltRuntimeApi) -> Result<(), Error> {
-        let owner = get_did_account_id(self.claim.owner.as_str())?;
-
-        // Lookup DID doc on chain
-        let did_doc = cli
-            .storage()
-            .did()
-            .did(&owner, None)
-            .await?
-            .ok_or(Error::DidNotFound)?;
+    #[tracing::instrument(skip_all, fields(key_inferred = self.key_inferred()))]
+    pub async fn check_signature(&self, backend: &dyn ChainBackend) -> Result<(), Error> {
+        let _timer = metrics::time_check(Check::Signature);
+
+        let owner_did = DidUrl::parse(&self.claim.owner)?;
+        let owner = owner_did.account_id()?;
+
+        // Lookup DID doc on chain, a light DID owner has one once it was upgraded
+        let did_doc = match backend.did(&owner, None).await? {
+            Some(did_doc) => did_doc,
+            None if owner_did.is_light() => {
+                return Err(Error::LightDidNotUpgraded(self.claim.owner.clone()))
+            }
+            None => return Err(Error::DidNotFound),
+        };
+
+        let signature: [u8; 64] = hex_decode(&self.claimer_signature.signature)?
+            .try_into()
+            .map_err(|_| Error::InvalidHex(hex::FromHexError::OddLength))?;
+        let msg = signing_data(&self.root_hash, &self.claimer_signature.challenge)?;
+
+        // Without a key uri the authentication key of the owner has to have signed
+        if self.key_inferred() {
+            let verified = did_doc
+                .public_keys
+                .0
+                .iter()
+                .find(|(key, _)| *key == did_doc.authentication_key)
+                .is_some_and(|(_, details)| {
+                    verifies(&details.key, &msg, &signature).unwrap_or(false)
+                });
+            return if verified {
+                Ok(())
+            } else {
+                Err(Error::InvalidSignature)
+            };
+        }
+
+        // The key has to be one of the account of the owner, the full DID of a light DID owner
+        let key_uri = DidUrl::parse(&self.claimer_signature.key_uri)?;
+        if key_uri.account_id()? != owner {
+            return Err(Error::KeyOfOtherDid {
+                key_uri: self.claimer_signature.key_uri.clone(),
+                owner: self.claim.owner.clone(),
+            });
+        }
 
         // Get the public verification key of the owner from the DID doc
-        let did_key_uri = get_did_key_uri(&self.claimer_signature.key_uri)?;
-        let details = &did_doc
+        let did_key_uri = key_uri.key_id()?;
+        let removed;
+        let details = match did_doc
             .public_keys
             .0
             .iter()
             .find(|(key, _)| key.0 == did_key_uri.0)
-            .ok_or(Error::InvalidDid)?
-            .1;
-
-        // Make sure the public key is a sr25519 public verification key and check the signature
-        match &details.key {
-            PublicVerificationKey(DidVerificationKey::Sr25519(key)) => {
-                let pub_key = subxt::sp_core::sr25519::Public::from_raw(key.0);
-                let sig = subxt::sp_core::sr25519::Signature::from_raw(
-                    hex_decode(&self.claimer_signature.signature)?
-                        .try_into()
-                        .map_err(|_| Error::InvalidHex(hex::FromHexError::OddLength))?,
-                );
-
-                let mut msg = hex_decode(&self.root_hash)?;
-                let mut challenge = self.claimer_signature.challenge.as_bytes().to_vec();
-                msg.append(&mut challenge);
-
-                if pub_key.verify(&msg, &sig) {
-                    Ok(())
-                } else {
-                    Err(Error::InvalidSignature)
+        {
+            Some((_, details)) => details,
+            // a key removed within the grace window of the backend still verifies
+            None => match backend.removed_key(&owner, &H256(did_key_uri.0)).await? {
+                Some(key) => {
+                    removed = key;
+                    &removed.details
                 }
-            }
-            _ => Err(Error::InvalidDid),
+                None => {
+                    return Err(Error::KeyNotFound {
+                        key_id: hex_encode(did_key_uri.0),
+                        available: did::describe_keys(&did_doc),
+                        keys_changed_at: did::keys_changed_at(&did_doc),
+                    })
+                }
+            },
+        };
+
+        // Make sure the public key is a verification key and check the signature
+        if verifies(&details.key, &msg, &signature)? {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
         }
     }
 
+    /// The owner is the light DID of an account, its signature is checked against the keys of the
+    /// full DID it was upgraded to
+    pub fn light_owner(&self) -> bool {
+        DidUrl::parse(&self.claim.owner).is_ok_and(|did| did.is_light())
+    }
+
+    /// The credential names no key of the owner, like exports of SDKs before 1.0.
+    /// Its signature is checked against the authentication key of the owner at the time of the
+    /// check, a weaker binding than a key named by the credential.
+    pub fn key_inferred(&self) -> bool {
+        self.claimer_signature.legacy
+    }
+
     /// Finally we need to check if the root hash is ok:
     /// - it's written to chain
     /// - the attestation is not revoked
     /// - we trust the attester
+    ///
+    /// Returns the DID of the attester.
+    #[tracing::instrument(skip_all, fields(attester))]
     pub async fn check_attestation(
         &self,
-        cli: &KiltRuntimeApi,
+        backend: &dyn ChainBackend,
         allowed_issuers: &[&str],
-    ) -> Result<(), Error> {
+    ) -> Result<String, Error> {
+        let _timer = metrics::time_check(Check::Attestation);
+
         // Get the raw root hash
-        let hash = subxt::sp_core::H256(
-            hex_decode(&self.root_hash)?
-                .try_into()
-                .map_err(|_| Error::InvalidHex(hex::FromHexError::OddLength))?,
-        );
+        let hash = hex_decode_h256(&self.root_hash)?;
 
         // Retrieve the attestation from chain
-        let attestation = cli
-            .storage()
-            .attestation()
-            .attestations(&hash, None)
+        let attestation = backend
+            .attestation(&hash, None)
             .await?
             .ok_or(Error::AttestationNotFound)?;
 
@@ -218,15 +852,19 @@ impl Credential {
         if attestation.revoked {
             Err(Error::AttestationRevoked)
         } else {
-            // Build the attester DID string to check against the allowed issuers
+            // Compare accounts, issuers can be DIDs or addresses in any spelling of them
+            let trusted = allowed_issuers.iter().any(|issuer| {
+                issuer_account(issuer).is_ok_and(|account| account == attestation.attester)
+            });
             let attester = format!(
                 "did:kilt:{}",
                 attestation
                     .attester
                     .to_ss58check_with_version(Ss58AddressFormat::custom(38))
             );
-            if allowed_issuers.contains(&attester.as_str()) {
-                Ok(())
+            tracing::Span::current().record("attester", &attester.as_str());
+            if trusted {
+                Ok(attester)
             } else {
                 Err(Error::InvalidIssuer)
             }
@@ -236,7 +874,31 @@ impl Credential {
 
 #[cfg(test)]
 mod test {
-    use crate::kilt::connect;
+    use crate::{
+        cache::{CachedBackend, RevocationCache},
+        fixtures,
+        kilt::connect,
+        mock::{MockBackend, MockState},
+        utils::{KeyType, ParseOptions},
+    };
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+    use subxt::sp_core::Pair;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, Layer, SubscriberExt},
+        registry::LookupSpan,
+        Registry,
+    };
 
     use super::*;
 
@@ -261,7 +923,7 @@ mod test {
         "delegationId": null,
         "rootHash": "0xf69ce26ca50b5d5f38cd32a99d031cd52fff42f17b9afb32895ffba260fb616a",
         "claimerSignature": {
-            "keyId": "did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH#0x78579576fa15684e5d868c9e123d62d471f1a95d8f9fc8032179d3735069784d",
+            "keyUri": "did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH#0x78579576fa15684e5d868c9e123d62d471f1a95d8f9fc8032179d3735069784d",
             "signature": "0x6243baecdfa9c752161f501597bafbb0242db1174bb8362c18d6e51bdbbdf041997fb736a07dcf56cb023687c4cc044ffba39e0dfcf01b7caa00f0f8b4fbbd81"
         }
     }
@@ -280,6 +942,239 @@ mod test {
             .normalize()
             .expect("Failed to normalize claim");
         println!("{}", serde_json::to_string_pretty(&normalized).unwrap());
+
+        // the statements are written like the json objects they stand for
+        let claim = &credential.claim;
+        let mut expected = vec![serde_json::to_string(&json!({"@id": claim.owner})).unwrap()];
+        for (key, value) in claim.contents.as_object().unwrap() {
+            let key = format!("kilt:ctype:{}#{}", claim.ctype_hash, key);
+            expected.push(serde_json::to_string(&json!({ key: value })).unwrap());
+        }
+        assert_eq!(normalized, expected);
+        let hashes: Vec<_> = expected.iter().map(|s| hash_normalized(s)).collect();
+        assert_eq!(claim.hash_statements().unwrap(), hashes);
+    }
+
+    #[test]
+    fn test_claim_hash_vectors() {
+        let vectors: serde_json::Value = serde_json::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/vectors/claim-hashes.json"
+        )))
+        .unwrap();
+        let text = |v: &serde_json::Value| v.as_str().unwrap().to_string();
+        for v in vectors["statements"].as_array().unwrap() {
+            let hash = hash_statement(
+                v["ctypeHash"].as_str().unwrap(),
+                v["property"].as_str().unwrap(),
+                &v["value"],
+            );
+            assert_eq!(hash, text(&v["hash"]), "{}", v["property"]);
+            assert_eq!(hash_normalized(v["statement"].as_str().unwrap()), hash);
+        }
+        for v in vectors["owners"].as_array().unwrap() {
+            let hash = hash_owner_statement(v["owner"].as_str().unwrap());
+            assert_eq!(hash, text(&v["hash"]));
+            assert_eq!(hash_normalized(v["statement"].as_str().unwrap()), hash);
+        }
+        for v in vectors["salted"].as_array().unwrap() {
+            let hash = salt_hash(
+                v["nonce"].as_str().unwrap(),
+                v["statementHash"].as_str().unwrap(),
+            );
+            assert_eq!(hash, text(&v["hash"]));
+        }
+
+        // the functions hash a property like the claim it is part of
+        let credential: Credential = serde_json::from_str(EXAMPLE_CRED).unwrap();
+        let claim = &credential.claim;
+        assert_eq!(
+            claim.hash_statements().unwrap(),
+            vec![
+                hash_owner_statement(&claim.owner),
+                hash_statement(&claim.ctype_hash, "Email", &claim.contents["Email"])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_null_and_boolean_values() {
+        let credential = crate::utils::parse_credential(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/vectors/edge-values.json"
+        )))
+        .unwrap();
+        let backend = fixtures::backend_for(&credential);
+        let attester = fixtures::attester_did();
+        let res = credential.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "{:?}", res);
+        let claim = &credential.claim;
+        for (property, value) in [
+            ("nickname", json!(null)),
+            ("verified", json!(true)),
+            ("subscribed", json!(false)),
+            ("bio", json!("")),
+            ("extra", json!({})),
+        ] {
+            assert_eq!(claim.contents[property], value);
+            let hash = hash_statement(&claim.ctype_hash, property, &value);
+            assert!(
+                claim.hash_statements().unwrap().contains(&hash),
+                "{}",
+                property
+            );
+        }
+
+        // a property set to null is disclosed, one that is left out is not
+        let presentation = credential
+            .create_presentation(
+                &["nickname".to_string(), "subscribed".to_string()],
+                "0x1234",
+                &fixtures::owner_key(),
+            )
+            .unwrap();
+        assert_eq!(
+            presentation.claim.contents,
+            json!({"nickname": null, "subscribed": false})
+        );
+        let res = presentation
+            .verify(&backend, &[&attester], Some("0x1234"))
+            .await;
+        assert!(res.is_ok(), "{:?}", res);
+        let requires = |properties: &[&str]| ClaimRequirements {
+            properties: properties.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(presentation
+            .check_requirements(&requires(&["nickname", "subscribed"]))
+            .is_ok());
+        assert!(matches!(
+            presentation.check_requirements(&requires(&["nickname", "bio"])),
+            Err(Error::PropertyNotFound(property)) if property == "bio"
+        ));
+        assert!(credential
+            .check_requirements(&requires(&["bio", "extra", "verified"]))
+            .is_ok());
+
+        // the string "null" is listed apart from null
+        let listing = describe_claim(claim, false);
+        for line in [
+            "  bio: \"\"\n",
+            "  extra: {}\n",
+            "  nickname: null\n",
+            "  note: \"null\"\n",
+            "  subscribed: false\n",
+            "  verified: true\n",
+        ] {
+            assert!(listing.contains(line), "{}", listing);
+        }
+        for text in ["null", "true", "42", "[]", "", " "] {
+            assert!(reads_as_other_value(text), "{:?}", text);
+        }
+        for text in ["alice@example.com", "null pointer", "\"quoted\""] {
+            assert!(!reads_as_other_value(text), "{:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_statement_keys() {
+        let fork: StatementKeys = "fork:ctype:{ctype}#{property}".parse().unwrap();
+        assert_eq!(fork.key("0x12", "Email"), "fork:ctype:0x12#Email");
+        assert_eq!(
+            StatementKeys::KiltCtype.key("0x12", "Email"),
+            "kilt:ctype:0x12#Email"
+        );
+        // placeholders in the claim are not filled in
+        assert_eq!(
+            fork.key("{property}", "{ctype}"),
+            "fork:ctype:{property}#{ctype}"
+        );
+        assert_eq!(KILT_STATEMENT_KEYS.parse(), Ok(StatementKeys::KiltCtype));
+        assert!("fork:ctype:{ctype}#".parse::<StatementKeys>().is_err());
+
+        // a credential of a fork verifies with its keys, and only with them
+        let data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/vectors/custom-statement-keys.json"
+        ));
+        let parse = ParseOptions {
+            statement_keys: fork.clone(),
+            ..Default::default()
+        };
+        let credential = parse.parse("fork.json", data).unwrap();
+        assert_eq!(credential.claim.statement_keys, fork);
+        let backend = fixtures::backend_for(&credential);
+        let attester = fixtures::attester_did();
+        let res = credential.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "{:?}", res);
+        assert!(credential.claim.normalize().unwrap()[1].starts_with("{\"fork:ctype:0x"));
+        let kilt = ParseOptions::default().parse("fork.json", data).unwrap();
+        let res = kilt.verify(&backend, &[&attester], None).await;
+        assert!(res.is_err());
+
+        // presentations keep the keys of the credential
+        let presentation = credential
+            .create_presentation(&["Email".to_string()], "0x1234", &fixtures::owner_key())
+            .unwrap();
+        let res = presentation
+            .verify(&backend, &[&attester], Some("0x1234"))
+            .await;
+        assert!(res.is_ok(), "{:?}", res);
+        let reparsed = parse
+            .parse(
+                "presentation.json",
+                &serde_json::to_vec(&presentation).unwrap(),
+            )
+            .unwrap();
+        assert!(reparsed.check_claim_contents().is_ok());
+    }
+
+    #[test]
+    fn test_flatten_claim() {
+        let claim = Claim {
+            contents: json!({
+                "Email": "tino@kilt.io",
+                "address": {"city": "Berlin", "geo": {"lat": 52.5, "verified": true}},
+                "nationalities": ["DE", {"code": "FR"}],
+                "age": 42,
+                "nickname": null,
+                "tags": [],
+                "extra": {},
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            claim.flatten(),
+            vec![
+                ("Email".to_string(), json!("tino@kilt.io")),
+                ("address.city".to_string(), json!("Berlin")),
+                ("address.geo.lat".to_string(), json!(52.5)),
+                ("address.geo.verified".to_string(), json!(true)),
+                ("age".to_string(), json!(42)),
+                ("extra".to_string(), json!({})),
+                ("nationalities[0]".to_string(), json!("DE")),
+                ("nationalities[1].code".to_string(), json!("FR")),
+                ("nickname".to_string(), json!(null)),
+                ("tags".to_string(), json!([])),
+            ]
+        );
+
+        let listing = describe_claim(&claim, false);
+        assert!(listing.starts_with("  Email: tino@kilt.io\n  address.city: Berlin\n"));
+        assert!(listing.contains("  age: 42\n  extra: {}\n"));
+        let redacted = describe_claim(&claim, true);
+        assert!(redacted.contains("  Email: [redacted]\n"));
+        assert!(!redacted.contains("tino"));
+
+        let long = Claim {
+            contents: json!({"bio": "x".repeat(100), "name": "a\nforged: line"}),
+            ..Default::default()
+        };
+        let listing = describe_claim(&long, false);
+        let bio = listing.lines().next().unwrap();
+        assert_eq!(bio.chars().count(), "  bio: ".len() + MAX_CLAIM_VALUE_CHARS);
+        assert!(bio.ends_with('…'));
+        assert_eq!(listing.lines().nth(1), Some("  name: a\\nforged: line"));
     }
 
     #[test]
@@ -298,7 +1193,564 @@ mod test {
         assert!(res.is_ok(), "Failed to check root hash: {:?}", res);
     }
 
+    #[test]
+    fn test_create() {
+        let owner_key = DidKeyPair::from_seed("//Alice", KeyType::Sr25519).unwrap();
+        let claim = Claim {
+            ctype_hash: "0x3291bb126e33b4862d421bfaa1d2f272e6cdfc4f96658988fbcffea8914bd9ac"
+                .to_string(),
+            contents: json!({"Email": "a@b.c"}),
+            owner: owner_key.did(),
+            ..Default::default()
+        };
+        let credential = Credential::create(claim, &owner_key, &mut rand::thread_rng())
+            .expect("Failed to create credential");
+
+        assert_eq!(credential.claim_hashes.len(), 2);
+        assert_eq!(credential.claim_nonce_map.len(), 2);
+        assert_eq!(credential.claimer_signature.key_uri, owner_key.key_uri());
+        assert!(credential.check_claim_contents().is_ok());
+        assert!(credential.check_root_hash().is_ok());
+
+        let DidKeyPair::Sr25519(pair) = &owner_key else {
+            panic!("Expected sr25519 key")
+        };
+        let sig = subxt::sp_core::sr25519::Signature::from_raw(
+            hex_decode(&credential.claimer_signature.signature)
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        );
+        assert!(pair
+            .public()
+            .verify(&hex_decode(&credential.root_hash).unwrap(), &sig));
+    }
+
+    #[test]
+    fn test_create_presentation() {
+        let owner_key = DidKeyPair::from_seed("//Alice", KeyType::Sr25519).unwrap();
+        let claim = Claim {
+            ctype_hash: "0x3291bb126e33b4862d421bfaa1d2f272e6cdfc4f96658988fbcffea8914bd9ac"
+                .to_string(),
+            contents: json!({"Email": "a@b.c", "Name": "Alice"}),
+            owner: owner_key.did(),
+            ..Default::default()
+        };
+        let credential = Credential::create(claim, &owner_key, &mut rand::thread_rng()).unwrap();
+
+        let cases: Vec<(&str, Vec<String>, usize)> = vec![
+            ("reveal nothing", vec![], 1),
+            ("reveal one property", vec!["Email".to_string()], 2),
+            (
+                "reveal everything",
+                vec!["Email".to_string(), "Name".to_string()],
+                3,
+            ),
+        ];
+        for (name, reveal, nonces) in cases {
+            let presentation = credential
+                .create_presentation(&reveal, "0x1234", &owner_key)
+                .unwrap();
+            assert_eq!(presentation.claim_nonce_map.len(), nonces, "{}", name);
+            assert_eq!(
+                presentation.claim_hashes, credential.claim_hashes,
+                "{}",
+                name
+            );
+            assert!(presentation.check_claim_contents().is_ok(), "{}", name);
+            assert!(presentation.check_root_hash().is_ok(), "{}", name);
+            assert!(
+                presentation.check_challenge(Some("0x1234")).is_ok(),
+                "{}",
+                name
+            );
+            assert!(
+                presentation.check_challenge(Some("0x5678")).is_err(),
+                "{}",
+                name
+            );
+            assert_eq!(presentation.claimer_signature.key_uri, owner_key.key_uri());
+        }
+
+        let res = credential.create_presentation(&["Age".to_string()], "0x1234", &owner_key);
+        assert!(matches!(res, Err(Error::PropertyNotFound(_))));
+    }
+
+    fn claim_value() -> impl Strategy<Value = serde_json::Value> {
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(serde_json::Value::from),
+            any::<i64>().prop_map(serde_json::Value::from),
+            ".*".prop_map(serde_json::Value::from),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(serde_json::Value::from),
+                prop::collection::btree_map(".*", inner, 0..4)
+                    .prop_map(|map| serde_json::Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    fn claim_contents() -> impl Strategy<Value = serde_json::Map<String, serde_json::Value>> {
+        prop::collection::btree_map(".*", claim_value(), 0..8)
+            .prop_map(|contents| contents.into_iter().collect())
+    }
+
+    fn fixture_claim(contents: serde_json::Map<String, serde_json::Value>) -> Claim {
+        Claim {
+            ctype_hash: fixtures::CTYPE_HASH.to_string(),
+            contents: serde_json::Value::Object(contents),
+            owner: fixtures::owner_key().did(),
+            ..Default::default()
+        }
+    }
+
+    // run a full verification against the fixture backend with the credential attested by Bob
+    fn verify_with_fixtures(
+        credential: &Credential,
+        attested: &Credential,
+        challenge: Option<&str>,
+    ) -> Result<(), Error> {
+        let backend = fixtures::backend_for(attested);
+        let attester = fixtures::attester_did();
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(credential.verify(&backend, &[&attester], challenge))
+    }
+
+    proptest! {
+        // Failing cases are persisted in proptest-regressions/ and are meant to be committed
+
+        #[test]
+        fn test_create_verify_round_trip(contents in claim_contents()) {
+            let credential =
+                Credential::create(fixture_claim(contents), &fixtures::owner_key(), &mut rand::thread_rng())
+                    .unwrap();
+
+            // go through json like a credential file would
+            let json = serde_json::to_string(&credential).unwrap();
+            let parsed: Credential = serde_json::from_str(&json).unwrap();
+            prop_assert!(parsed.check_claim_contents().is_ok());
+            prop_assert!(parsed.check_root_hash().is_ok());
+            let res = verify_with_fixtures(&parsed, &credential, None);
+            prop_assert!(res.is_ok(), "{:?}", res);
+        }
+
+        #[test]
+        fn test_normalize_deterministic(contents in claim_contents()) {
+            let claim = fixture_claim(contents);
+            let normalized = claim.normalize().unwrap();
+            prop_assert_eq!(&claim.normalize().unwrap(), &normalized);
+
+            // the order of the properties in the json doesn't matter
+            let mut entries: Vec<_> = claim.contents.as_object().unwrap().iter().collect();
+            entries.reverse();
+            let reversed = format!(
+                "{{{}}}",
+                entries
+                    .iter()
+                    .map(|(k, v)| format!("{}:{}", serde_json::to_string(k).unwrap(), v))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+            let reordered = Claim {
+                contents: serde_json::from_str(&reversed).unwrap(),
+                ..claim.clone()
+            };
+            prop_assert_eq!(reordered.normalize().unwrap(), normalized.clone());
+            prop_assert_eq!(reordered.hash_statements().unwrap(), claim.hash_statements().unwrap());
+
+            // one statement for the owner and one per property
+            prop_assert_eq!(normalized.len(), claim.contents.as_object().unwrap().len() + 1);
+        }
+
+        #[test]
+        fn test_redacted_presentation_verifies(
+            (contents, reveal) in claim_contents().prop_flat_map(|contents| {
+                let keys: Vec<String> = contents.keys().cloned().collect();
+                let len = keys.len();
+                (Just(contents), prop::sample::subsequence(keys, 0..=len))
+            }),
+            challenge in "[0-9a-f]{0,32}",
+        ) {
+            let owner_key = fixtures::owner_key();
+            let credential =
+                Credential::create(fixture_claim(contents), &owner_key, &mut rand::thread_rng()).unwrap();
+            let presentation = credential.create_presentation(&reveal, &challenge, &owner_key).unwrap();
+
+            prop_assert_eq!(presentation.claim.contents.as_object().unwrap().len(), reveal.len());
+            prop_assert_eq!(presentation.claim_nonce_map.len(), reveal.len() + 1);
+            let res = verify_with_fixtures(&presentation, &credential, Some(&challenge));
+            prop_assert!(res.is_ok(), "{:?}", res);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_signature_fixture() {
+        let backend = fixtures::backend();
+        let credential = fixtures::credential();
+        let res = credential.check_signature(&backend).await;
+        assert!(res.is_ok(), "Failed to check signature: {:?}", res);
+
+        // a signature by someone else
+        let mut forged = credential.clone();
+        forged.claimer_signature.signature =
+            hex_encode(fixtures::attester_key().sign(&hex_decode(&credential.root_hash).unwrap()));
+        let res = forged.check_signature(&backend).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+
+        // a key that is not in the DID document
+        let mut unknown_key = credential.clone();
+        unknown_key.claimer_signature.key_uri = format!(
+            "{}#{}",
+            credential.claim.owner,
+            hex_encode(fixtures::attester_key().key_id())
+        );
+        let res = unknown_key.check_signature(&backend).await;
+        let owner_key = format!(
+            "#{} (sr25519, authentication, attestation)",
+            hex_encode(fixtures::owner_key().key_id())
+        );
+        assert!(
+            matches!(&res, Err(Error::KeyNotFound { key_id, available, keys_changed_at: Some(1) })
+                if *key_id == hex_encode(fixtures::attester_key().key_id()) && available.contains(&owner_key)),
+            "{:?}",
+            res
+        );
+
+        // an owner without DID on chain
+        let res = credential.check_signature(&MockBackend::default()).await;
+        assert!(matches!(res, Err(Error::DidNotFound)), "{:?}", res);
+    }
+
     #[tokio::test]
+    async fn test_light_owner() {
+        // Alice claims as the light DID she upgraded to her full DID and signs with its key
+        let owner_key = fixtures::owner_key();
+        let light = format!("did:kilt:light:00{}", &owner_key.did()["did:kilt:".len()..]);
+        let claim = Claim {
+            ctype_hash: fixtures::CTYPE_HASH.to_string(),
+            contents: json!({"Email": "alice@example.com"}),
+            owner: light.clone(),
+            ..Default::default()
+        };
+        let credential =
+            Credential::create(claim, &owner_key, &mut StdRng::seed_from_u64(0)).unwrap();
+        assert!(credential.light_owner());
+        let backend = fixtures::backend_for(&credential);
+        let res = credential
+            .verify(&backend, &[&fixtures::attester_did()], None)
+            .await;
+        assert!(res.is_ok(), "{:?}", res);
+        let presentation = credential
+            .create_presentation(&[], "0x1234", &owner_key)
+            .unwrap();
+        assert_eq!(presentation.claimer_signature.key_uri, owner_key.key_uri());
+        assert!(presentation.check_signature(&backend).await.is_ok());
+
+        let requirements = ClaimRequirements {
+            owner: Some(owner_key.did()),
+            properties: vec![],
+            full_did: true,
+        };
+        let res = credential.check_requirements(&requirements);
+        assert!(
+            matches!(&res, Err(Error::LightDidOwner(owner)) if *owner == light),
+            "{:?}",
+            res
+        );
+
+        // a key of another DID does not sign for the owner, even if it verifies the signature
+        let bob = fixtures::attester_key();
+        let mut other_key = fixtures::credential();
+        other_key.sign(&bob).unwrap();
+        let res = other_key.check_signature(&backend).await;
+        assert!(
+            matches!(&res, Err(Error::KeyOfOtherDid { key_uri, .. }) if *key_uri == bob.key_uri()),
+            "{:?}",
+            res
+        );
+
+        // a light DID that was never upgraded
+        let res = credential.check_signature(&MockBackend::default()).await;
+        assert!(
+            matches!(&res, Err(Error::LightDidNotUpgraded(owner)) if *owner == light),
+            "{:?}",
+            res
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_attestation_fixture() {
+        let credential = fixtures::credential();
+        let attester = fixtures::attester_did();
+        let root_hash = hex_decode_h256(&credential.root_hash).unwrap();
+
+        let res = credential
+            .check_attestation(&fixtures::backend(), &[&attester])
+            .await;
+        assert_eq!(res.ok(), Some(attester.clone()));
+
+        // the attester as bare KILT address
+        let address = attester.strip_prefix("did:kilt:").unwrap();
+        let res = credential
+            .check_attestation(&fixtures::backend(), &["not an issuer", address])
+            .await;
+        assert_eq!(res.ok(), Some(attester.clone()));
+
+        let res = credential
+            .check_attestation(&fixtures::backend(), &ALLOWED_ISSUERS)
+            .await;
+        assert!(matches!(res, Err(Error::InvalidIssuer)), "{:?}", res);
+
+        let mut revoked = fixtures::backend();
+        revoked.insert_attestation(root_hash, &fixtures::attestation_details(true));
+        let res = credential.check_attestation(&revoked, &[&attester]).await;
+        assert!(matches!(res, Err(Error::AttestationRevoked)), "{:?}", res);
+
+        let res = credential
+            .check_attestation(&MockBackend::default(), &[&attester])
+            .await;
+        assert!(matches!(res, Err(Error::AttestationNotFound)), "{:?}", res);
+    }
+
+    #[test]
+    fn test_check_requirements() {
+        let credential = fixtures::credential();
+        assert!(credential
+            .check_requirements(&ClaimRequirements::default())
+            .is_ok());
+
+        let requirements = ClaimRequirements {
+            owner: Some(fixtures::owner_key().did()),
+            properties: vec!["Email".to_string()],
+            full_did: false,
+        };
+        assert!(credential.check_requirements(&requirements).is_ok());
+
+        let requirements = ClaimRequirements {
+            owner: Some(fixtures::attester_did()),
+            properties: vec![],
+            full_did: false,
+        };
+        let res = credential.check_requirements(&requirements);
+        assert!(matches!(res, Err(Error::UnexpectedOwner)), "{:?}", res);
+
+        // undisclosed properties are missing as well
+        let presentation = credential
+            .create_presentation(&["Name".to_string()], "0x1234", &fixtures::owner_key())
+            .unwrap();
+        let requirements = ClaimRequirements {
+            owner: None,
+            properties: vec!["Name".to_string(), "Email".to_string()],
+            full_did: false,
+        };
+        let res = presentation.check_requirements(&requirements);
+        assert!(
+            matches!(&res, Err(Error::PropertyNotFound(p)) if p == "Email"),
+            "{:?}",
+            res
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_fixture() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let credential = fixtures::credential();
+        let res = credential.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+
+        let presentation = credential
+            .create_presentation(&["Email".to_string()], "0x1234", &fixtures::owner_key())
+            .unwrap();
+        let res = presentation
+            .verify(&backend, &[&attester], Some("0x1234"))
+            .await;
+        assert!(res.is_ok(), "Failed to verify presentation: {:?}", res);
+
+        let res = presentation
+            .verify(&backend, &[&attester], Some("0x5678"))
+            .await;
+        assert!(matches!(res, Err(Error::InvalidChallenge)), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_export() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let credential = fixtures::credential();
+        assert!(!credential.key_inferred());
+        let mut json = serde_json::to_value(&credential).unwrap();
+        json["claimerSignature"] = json!(credential.claimer_signature.signature);
+        let legacy = crate::utils::parse_credential(json.to_string().as_bytes()).unwrap();
+        assert!(legacy.key_inferred());
+        let res = legacy.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+
+        // none of the keys of the owner signed it
+        let mut forged = legacy.clone();
+        forged.claimer_signature.signature =
+            hex_encode(fixtures::attester_key().sign(&hex_decode(&credential.root_hash).unwrap()));
+        let res = forged.check_signature(&backend).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+
+        // a legacy credential is written back with its bare signature
+        let reparsed = serde_json::to_string(&legacy).unwrap();
+        let reparsed = crate::utils::parse_credential(reparsed.as_bytes()).unwrap();
+        assert!(reparsed.key_inferred());
+
+        // an empty key uri is not a legacy export
+        let mut empty = json.clone();
+        empty["claimerSignature"] = json!({
+            "signature": credential.claimer_signature.signature,
+            "keyUri": "",
+        });
+        let empty = crate::utils::parse_credential(empty.to_string().as_bytes()).unwrap();
+        assert!(!empty.key_inferred());
+        assert!(empty.check_signature(&backend).await.is_err());
+
+        json["claimerSignature"] = json!(42);
+        let res = crate::utils::parse_credential(json.to_string().as_bytes());
+        assert!(matches!(res, Err(Error::Serde(_))), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_export_other_keys() {
+        // the owner has an attestation key next to its authentication key
+        let owner = fixtures::owner_key();
+        let attestation_key =
+            DidKeyPair::from_seed("//Alice//attestation", KeyType::Sr25519).unwrap();
+        let mut backend = fixtures::backend();
+        backend.insert_did(
+            get_did_account_id(&owner.did()).unwrap(),
+            &fixtures::did_details_with_attestation_key(&owner, &attestation_key),
+        );
+        let credential = fixtures::credential();
+        let mut json = serde_json::to_value(&credential).unwrap();
+        json["claimerSignature"] = json!(credential.claimer_signature.signature);
+        let legacy = crate::utils::parse_credential(json.to_string().as_bytes()).unwrap();
+        let res = legacy.check_signature(&backend).await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+
+        // only the authentication key signs a claim
+        let mut attested = legacy.clone();
+        attested.claimer_signature.signature =
+            hex_encode(attestation_key.sign(&hex_decode(&credential.root_hash).unwrap()));
+        let res = attested.check_signature(&backend).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_rehash() {
+        let attester = fixtures::attester_did();
+        let mut credential = fixtures::credential();
+        credential.claim.contents = json!({"Email": "alice@example.org", "Name": "Alice"});
+        assert!(credential.check_claim_contents().is_err());
+
+        let (claim_hashes, claim_nonce_map, root_hash) =
+            rehash(&credential.claim, &mut rand::thread_rng()).unwrap();
+        assert_eq!(claim_hashes.len(), 3);
+        assert!(claim_hashes.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(claim_nonce_map.len(), 3);
+        credential.claim_hashes = claim_hashes;
+        credential.claim_nonce_map = claim_nonce_map;
+        credential.root_hash = root_hash;
+        assert!(credential.check_claim_contents().is_ok());
+        assert!(credential.check_root_hash().is_ok());
+
+        // the old signature is of the old root hash
+        let backend = fixtures::backend_for(&credential);
+        let res = credential.check_signature(&backend).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+
+        credential.sign(&fixtures::owner_key()).unwrap();
+        let res = credential.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_derived_root_hash() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let credential = fixtures::credential();
+        let declared =
+            crate::utils::parse_credential(&serde_json::to_vec(&credential).unwrap()).unwrap();
+        assert!(!declared.root_hash_derived);
+
+        let mut json = serde_json::to_value(&credential).unwrap();
+        json.as_object_mut().unwrap().remove("rootHash");
+        let derived = crate::utils::parse_credential(json.to_string().as_bytes()).unwrap();
+        assert!(derived.root_hash_derived);
+        assert_eq!(derived.root_hash, credential.root_hash);
+        let res = derived.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+
+        // a derived root hash still has to be the one the owner signed
+        json["claimHashes"].as_array_mut().unwrap().pop();
+        let derived = crate::utils::parse_credential(json.to_string().as_bytes()).unwrap();
+        let res = derived.verify(&backend, &[&attester], None).await;
+        assert!(matches!(res, Err(Error::InvalidClaimContents)), "{:?}", res);
+        json["claimHashes"] = serde_json::json!(["0x123"]);
+        let res = crate::utils::parse_credential(json.to_string().as_bytes());
+        assert!(matches!(res, Err(Error::InvalidHex(_))), "{:?}", res);
+    }
+
+    // the DID documents and the attestation the example credential is verified against on spiritnet
+    fn example_backend() -> MockBackend {
+        MockBackend::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/scenarios/spiritnet-example.json"
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_signature_example() {
+        let credential: Credential =
+            serde_json::from_str(EXAMPLE_CRED).expect("Failed to parse claims");
+        let res = credential.check_signature(&example_backend()).await;
+        assert!(res.is_ok(), "Failed to check signature: {:?}", res);
+
+        let mut forged = credential.clone();
+        forged.root_hash = hex_encode([0u8; 32]);
+        let res = forged.check_signature(&example_backend()).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_check_attestation_example() {
+        let credential: Credential =
+            serde_json::from_str(EXAMPLE_CRED).expect("Failed to parse claims");
+        let res = credential
+            .check_attestation(&example_backend(), &ALLOWED_ISSUERS)
+            .await;
+        assert!(res.is_ok(), "Failed to check attestation: {:?}", res);
+
+        let attester = fixtures::attester_did();
+        let res = credential
+            .check_attestation(&example_backend(), &[&attester])
+            .await;
+        assert!(matches!(res, Err(Error::InvalidIssuer)), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_verify_example() {
+        let credential: Credential =
+            serde_json::from_str(EXAMPLE_CRED).expect("Failed to parse claims");
+        let res = credential
+            .verify(&example_backend(), &ALLOWED_ISSUERS, None)
+            .await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(
+        not(feature = "live-tests"),
+        ignore = "requires a connection to spiritnet"
+    )]
     async fn test_check_signature() {
         let credential: Credential =
             serde_json::from_str(EXAMPLE_CRED).expect("Failed to parse claims");
@@ -310,6 +1762,10 @@ mod test {
     }
 
     #[tokio::test]
+    #[cfg_attr(
+        not(feature = "live-tests"),
+        ignore = "requires a connection to spiritnet"
+    )]
     async fn test_check_attestation() {
         let credential: Credential =
             serde_json::from_str(EXAMPLE_CRED).expect("Failed to parse claims");
@@ -321,13 +1777,120 @@ mod test {
     }
 
     #[tokio::test]
+    #[cfg_attr(
+        not(feature = "live-tests"),
+        ignore = "requires a connection to spiritnet"
+    )]
     async fn test_verify() {
         let credential: Credential =
             serde_json::from_str(EXAMPLE_CRED).expect("Failed to parse claims");
         let cli = connect("wss://spiritnet.kilt.io:443")
             .await
             .expect("Failed to connect to kilt");
-        let res = credential.verify(&cli, &ALLOWED_ISSUERS).await;
+        let res = credential.verify(&cli, &ALLOWED_ISSUERS, None).await;
         assert!(res.is_ok(), "Failed to verify: {:?}", res);
     }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct RecordedSpan {
+        name: &'static str,
+        parent: Option<&'static str>,
+        fields: BTreeMap<String, String>,
+    }
+
+    // records the spans of this crate and their fields, the index of a span is kept in its extensions
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<RecordedSpan>>>);
+
+    struct Fields<'a>(&'a mut BTreeMap<String, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S> Layer<S> for Recorder
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            if !span.metadata().target().starts_with("kilt_verify") {
+                return;
+            }
+            let mut fields = BTreeMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            span.extensions_mut().insert(spans.len());
+            spans.push(RecordedSpan {
+                name: span.name(),
+                parent: span.parent().map(|parent| parent.name()),
+                fields,
+            });
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let index = span.extensions().get::<usize>().copied();
+            if let Some(index) = index {
+                values.record(&mut Fields(&mut self.0.lock().unwrap()[index].fields));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tracing() {
+        let cred = fixtures::credential();
+        let root_hash = hex_decode_h256(&cred.root_hash).unwrap();
+        let attester = fixtures::attester_did();
+        let block_hash = H256::repeat_byte(1);
+        let mut chain = fixtures::backend();
+        let mut block = MockState::default();
+        block.insert_attestation(root_hash, &fixtures::attestation_details(false));
+        chain.insert_block(block_hash, block);
+        let cache = RevocationCache::new();
+        cache
+            .sync(&chain, &[root_hash], 1, block_hash)
+            .await
+            .unwrap();
+
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(recorder.clone()));
+        cred.verify(&CachedBackend::new(&chain, &cache), &[&attester], None)
+            .await
+            .unwrap();
+
+        let spans = recorder.0.lock().unwrap().clone();
+        let tree: Vec<_> = spans.iter().map(|span| (span.name, span.parent)).collect();
+        assert_eq!(
+            tree,
+            vec![
+                ("verify", None),
+                ("check_claim_contents", Some("verify")),
+                ("check_root_hash", Some("verify")),
+                ("check_signature", Some("verify")),
+                ("check_attestation", Some("verify")),
+                ("cached_attestation", Some("check_attestation")),
+            ]
+        );
+        let verify = &spans[0].fields;
+        assert_eq!(verify["root_hash"], cred.root_hash[..10]);
+        assert_eq!(verify["owner"], cred.claim.owner);
+        assert_eq!(verify["attester"], attester);
+        assert_eq!(verify["outcome"], "valid");
+        assert!(!verify.contains_key("code"));
+        assert_eq!(spans[5].fields["hit"], "true");
+        // the claim contents are never part of a span
+        for span in spans.iter() {
+            for value in span.fields.values() {
+                assert!(!value.contains("@"), "{}: {}", span.name, value);
+            }
+        }
+    }
 }
@@ -0,0 +1,152 @@
+use clap::Args;
+use std::collections::BTreeMap;
+
+use crate::{errors::Error, utils::short_hash};
+
+/// Names of well-known ctypes by their hash
+const BUILTIN: [(&str, &str); 4] = [
+    (
+        "0x3291bb126e33b4862d421bfaa1d2f272e6cdfc4f96658988fbcffea8914bd9ac",
+        "SocialKYC Email",
+    ),
+    (
+        "0x47d04c42bdf7fdd3fc5a194bcaa367b2f4766a6b16ae3df628927656d818f420",
+        "SocialKYC Twitter",
+    ),
+    (
+        "0xd8c61a235204cb9e3c6acb1898d78880488846a7247d325b833243b46d923abe",
+        "SocialKYC Discord",
+    ),
+    (
+        "0xad52bd7a8bd8a52e03181a99d2743e00d0a5e96fdc0182626655fcf0c0a776d0",
+        "SocialKYC GitHub",
+    ),
+];
+
+/// What unknown ctypes are printed with
+pub const NAMES_HINT: &str = "no name, --ctype-names can label it";
+
+/// Human names of ctypes, the built-in ones and those of a `--ctype-names` file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CtypeNames {
+    /// Names of the file by lowercase hash, they replace built-in names of the same hash
+    names: BTreeMap<String, String>,
+}
+
+impl CtypeNames {
+    /// Only the built-in names
+    pub const fn builtin() -> Self {
+        CtypeNames {
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// The built-in names with those of a TOML file of `"<ctype hash>" = "<name>"` lines
+    pub fn load(file: &str) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(file)?;
+        Self::builtin().extended(&text)
+    }
+
+    fn extended(mut self, toml: &str) -> Result<Self, Error> {
+        let names: BTreeMap<String, String> =
+            toml::from_str(toml).map_err(|err| Error::CtypeNames(err.to_string()))?;
+        for (hash, name) in names {
+            let digits = hash.strip_prefix("0x").unwrap_or_default();
+            if digits.len() != 64 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(Error::CtypeNames(format!("{} is not a ctype hash", hash)));
+            }
+            self.names.insert(hash.to_ascii_lowercase(), name);
+        }
+        Ok(self)
+    }
+
+    pub fn name(&self, hash: &str) -> Option<&str> {
+        let hash = hash.to_ascii_lowercase();
+        match self.names.get(&hash) {
+            Some(name) => Some(name),
+            None => BUILTIN
+                .iter()
+                .find(|(builtin, _)| *builtin == hash)
+                .map(|(_, name)| *name),
+        }
+    }
+
+    /// The name with the start of the hash, or the whole hash with a hint if it has no name
+    pub fn describe(&self, hash: &str) -> String {
+        match self.name(hash) {
+            Some(name) => format!("{} ({})", name, short_hash(hash)),
+            None => format!("{} ({})", hash, NAMES_HINT),
+        }
+    }
+
+    /// All names sorted by hash
+    pub fn all(&self) -> BTreeMap<&str, &str> {
+        let builtin = BUILTIN.iter().copied();
+        let names = self
+            .names
+            .iter()
+            .map(|(hash, name)| (hash.as_str(), name.as_str()));
+        builtin.chain(names).collect()
+    }
+}
+
+/// Show the names of ctypes
+#[derive(Args, Debug)]
+pub struct CtypeArgs {
+    /// Hashes of the ctypes, all named ctypes are listed without
+    #[clap(value_parser)]
+    hashes: Vec<String>,
+}
+
+pub fn run(names: &CtypeNames, args: &CtypeArgs) -> Result<(), Error> {
+    if args.hashes.is_empty() {
+        for (hash, name) in names.all() {
+            println!("{} {}", hash, name);
+        }
+    }
+    for hash in args.hashes.iter() {
+        match names.name(hash) {
+            Some(name) => println!("{} {}", hash, name),
+            None => println!("{} ({})", hash, NAMES_HINT),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::CTYPE_HASH;
+
+    #[test]
+    fn test_ctype_names() {
+        let names = CtypeNames::builtin();
+        assert_eq!(names.name(CTYPE_HASH), Some("SocialKYC Email"));
+        assert_eq!(
+            names.name(&CTYPE_HASH.to_uppercase().replace("0X", "0x")),
+            Some("SocialKYC Email")
+        );
+        assert_eq!(names.describe(CTYPE_HASH), "SocialKYC Email (0x3291bb12)");
+        let unknown = format!("0x{}", "ab".repeat(32));
+        assert_eq!(names.name(&unknown), None);
+        assert_eq!(
+            names.describe(&unknown),
+            format!("{} (no name, --ctype-names can label it)", unknown)
+        );
+
+        let toml = format!(
+            "\"{}\" = \"Email\"\n\"0x{}\" = \"Membership\"\n",
+            CTYPE_HASH,
+            "AB".repeat(32)
+        );
+        let names = names.extended(&toml).unwrap();
+        assert_eq!(names.name(CTYPE_HASH), Some("Email"));
+        assert_eq!(names.name(&unknown), Some("Membership"));
+        assert_eq!(names.all().len(), BUILTIN.len() + 1);
+
+        for toml in ["\"0x1234\" = \"Short\"", "not toml", "\"0x12\" = 3"] {
+            let res = CtypeNames::builtin().extended(toml);
+            assert!(matches!(res, Err(Error::CtypeNames(_))), "{:?}", res);
+        }
+    }
+}
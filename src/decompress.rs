@@ -0,0 +1,133 @@
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+use std::{borrow::Cow, io::Read};
+
+use crate::errors::Error;
+
+/// Default of `--max-decompressed-size`, the same as the limit of all entries of an archive
+pub const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Compression of an input, told by its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompress gzip or zstd data, other data is returned as it is. Decompressing stops after
+/// `max_size` bytes, so a small input cannot fill the memory.
+pub fn decompress(data: &[u8], max_size: u64) -> Result<Cow<'_, [u8]>, Error> {
+    let reader: Box<dyn Read + '_> = match Compression::sniff(data) {
+        None => return Ok(Cow::Borrowed(data)),
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(data)),
+        Some(Compression::Zstd) => Box::new(
+            StreamingDecoder::new(data)
+                .map_err(|err| Error::Decompression(format!("invalid zstd frame: {}", err)))?,
+        ),
+    };
+    let mut decompressed = Vec::new();
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut decompressed)
+        .map_err(|err| Error::Decompression(err.to_string()))?;
+    if decompressed.len() as u64 > max_size {
+        return Err(Error::Decompression(format!(
+            "larger than {} bytes once decompressed",
+            max_size
+        )));
+    }
+    Ok(Cow::Owned(decompressed))
+}
+
+/// The name of a file without the extension of its compression, i.e. `creds.ndjson` for
+/// `creds.ndjson.gz`
+pub fn strip_extension(file: &str) -> &str {
+    file.strip_suffix(".gz")
+        .or_else(|| file.strip_suffix(".zst"))
+        .unwrap_or(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, utils::ParseOptions};
+    use flate2::{write::GzEncoder, Compression as Level};
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decompressed_credential() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let data = serde_json::to_vec(&fixtures::credential()).unwrap();
+        let parse = ParseOptions::default();
+        let plain = parse.parse("cred.json", &data).unwrap();
+        let expected = format!("{:?}", plain.verify(&backend, &[&attester], None).await);
+        assert!(expected.starts_with("Ok("), "{}", expected);
+
+        let gzipped = gzip(&data);
+        let zstd = compress_to_vec(&data[..], CompressionLevel::Fastest);
+        for (source, compressed) in [("cred.json.gz", &gzipped), ("stdin", &zstd)] {
+            let cred = parse.parse(source, compressed).unwrap();
+            assert_eq!(cred.root_hash, plain.root_hash);
+            let res = cred.verify(&backend, &[&attester], None).await;
+            assert_eq!(format!("{:?}", res), expected);
+        }
+
+        // without sniffing the compressed data is not json
+        let raw = ParseOptions {
+            max_decompressed_size: None,
+            ..Default::default()
+        };
+        assert!(matches!(
+            raw.parse("cred.json.gz", &gzipped),
+            Err(Error::Serde(_))
+        ));
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        let zeros = vec![0; 4 * 1024 * 1024];
+        let bombs = [
+            gzip(&zeros),
+            compress_to_vec(&zeros[..], CompressionLevel::Fastest),
+        ];
+        for bomb in bombs {
+            assert!(bomb.len() < 64 * 1024, "{}", bomb.len());
+            let res = decompress(&bomb, 1024 * 1024);
+            assert!(
+                matches!(&res, Err(Error::Decompression(msg)) if msg == "larger than 1048576 bytes once decompressed"),
+                "{:?}",
+                res.map(|data| data.len())
+            );
+            assert_eq!(
+                decompress(&bomb, zeros.len() as u64).unwrap().len(),
+                zeros.len()
+            );
+        }
+
+        assert!(matches!(decompress(b"{}", 1), Ok(Cow::Borrowed(b"{}"))));
+        let res = decompress(&[0x1f, 0x8b, 0, 0], 1024);
+        assert!(matches!(res, Err(Error::Decompression(_))));
+        assert_eq!(strip_extension("creds.ndjson.gz"), "creds.ndjson");
+        assert_eq!(strip_extension("cred.json"), "cred.json");
+    }
+}
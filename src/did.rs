@@ -0,0 +1,306 @@
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    kilt::runtime_types::{
+        did::{
+            did_details::{DidDetails, DidEncryptionKey, DidPublicKey, DidVerificationKey},
+            service_endpoints::DidEndpoint,
+        },
+        frame_support::storage::bounded_vec::BoundedVec,
+    },
+    utils::{get_did_account_id, hex_encode},
+};
+
+/// Look up DIDs on chain
+#[derive(Args, Debug)]
+pub struct DidArgs {
+    #[clap(subcommand)]
+    command: DidCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum DidCommand {
+    /// Print the keys, web3name and service endpoints of a DID
+    Resolve(ResolveArgs),
+}
+
+#[derive(Args, Debug)]
+struct ResolveArgs {
+    /// The DID to resolve, i.e. did:kilt:4...
+    #[clap(value_parser)]
+    did: String,
+}
+
+/// A service a DID publishes, i.e. a messaging or credential exchange URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceEndpoint {
+    pub id: String,
+    pub types: Vec<String>,
+    pub urls: Vec<String>,
+}
+
+fn utf8(BoundedVec(bytes): &BoundedVec<u8>) -> Option<String> {
+    String::from_utf8(bytes.clone()).ok()
+}
+
+impl ServiceEndpoint {
+    /// Decode the strings of a stored endpoint, `None` if any of them is not valid UTF-8
+    pub fn decode(endpoint: &DidEndpoint) -> Option<Self> {
+        Some(ServiceEndpoint {
+            id: utf8(&endpoint.id)?,
+            types: endpoint
+                .service_types
+                .0
+                .iter()
+                .map(utf8)
+                .collect::<Option<_>>()?,
+            urls: endpoint.urls.0.iter().map(utf8).collect::<Option<_>>()?,
+        })
+    }
+}
+
+/// The service endpoints of a DID ordered by their id.
+/// Endpoints that are not valid UTF-8 cannot be used by anyone and are skipped with a warning.
+pub async fn service_endpoints(
+    backend: &dyn ChainBackend,
+    did: &AccountId32,
+    at: Option<H256>,
+) -> Result<Vec<ServiceEndpoint>, Error> {
+    let mut endpoints = Vec::new();
+    let mut skipped = Vec::new();
+    for endpoint in backend.service_endpoints(did, at).await? {
+        match ServiceEndpoint::decode(&endpoint) {
+            Some(endpoint) => endpoints.push(endpoint),
+            None => skipped.push(String::from_utf8_lossy(&endpoint.id.0).into_owned()),
+        }
+    }
+    // storage is iterated in hash order, warn in the order of the ids like the endpoints are listed
+    skipped.sort();
+    for id in skipped {
+        eprintln!(
+            "Warning: skipping service endpoint {} of {}, it is not valid UTF-8",
+            id, did
+        );
+    }
+    endpoints.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(endpoints)
+}
+
+/// The service endpoints as an indented list, one line per id, type and URL
+pub fn format_services(services: &[ServiceEndpoint]) -> String {
+    let mut text = String::new();
+    for service in services {
+        text += &format!("    #{}\n", service.id);
+        text += &format!("      types: {}\n", service.types.join(", "));
+        for url in service.urls.iter() {
+            text += &format!("      url:   {}\n", url);
+        }
+    }
+    text
+}
+
+fn key_type(key: &DidPublicKey) -> &'static str {
+    match key {
+        DidPublicKey::PublicVerificationKey(DidVerificationKey::Ed25519(_)) => "ed25519",
+        DidPublicKey::PublicVerificationKey(DidVerificationKey::Sr25519(_)) => "sr25519",
+        DidPublicKey::PublicVerificationKey(DidVerificationKey::Ecdsa(_)) => "ecdsa",
+        DidPublicKey::PublicEncryptionKey(DidEncryptionKey::X25519(_)) => "x25519",
+    }
+}
+
+// a key of the DID document as its fragment with the key type
+fn format_key(details: &DidDetails, key_id: &H256) -> String {
+    let key_type = details
+        .public_keys
+        .0
+        .iter()
+        .find(|(id, _)| id == key_id)
+        .map(|(_, details)| key_type(&details.key))
+        .unwrap_or("missing");
+    format!("#{} ({})", hex_encode(key_id), key_type)
+}
+
+/// All keys of a DID document as their fragment with the key type and what they are used for,
+/// i.e. "#0x1234... (sr25519, authentication)"
+pub fn describe_keys(details: &DidDetails) -> Vec<String> {
+    details
+        .public_keys
+        .0
+        .iter()
+        .map(|(id, key)| {
+            let mut uses = vec![key_type(&key.key)];
+            if *id == details.authentication_key {
+                uses.push("authentication");
+            }
+            if details.attestation_key.as_ref() == Some(id) {
+                uses.push("attestation");
+            }
+            if details.delegation_key.as_ref() == Some(id) {
+                uses.push("delegation");
+            }
+            if details.key_agreement_keys.0.contains(id) {
+                uses.push("key agreement");
+            }
+            format!("#{} ({})", hex_encode(id), uses.join(", "))
+        })
+        .collect()
+}
+
+/// The block the newest key of a DID document was added at, `None` if all keys are from the
+/// genesis block. A credential signed before it may name a key that was replaced then.
+pub fn keys_changed_at(details: &DidDetails) -> Option<u64> {
+    details
+        .public_keys
+        .0
+        .iter()
+        .map(|(_, key)| key.block_number)
+        .max()
+        .filter(|block| *block > 0)
+}
+
+/// The DID document of a DID for people: its keys, web3name and service endpoints
+pub async fn resolve(backend: &dyn ChainBackend, did: &str) -> Result<String, Error> {
+    let account = get_did_account_id(did)?;
+    let details = backend
+        .did(&account, None)
+        .await?
+        .ok_or(Error::DidNotFound)?;
+    let name = backend.web3_name(&account, None).await?;
+    let services = service_endpoints(backend, &account, None).await?;
+
+    let optional_key = |key: &Option<H256>| match key {
+        Some(key) => format_key(&details, key),
+        None => "none".to_string(),
+    };
+    let mut text = format!("{}\n", did);
+    text += &format!(
+        "  web3name:           {}\n",
+        name.map(|name| format!("w3n:{}", name))
+            .unwrap_or_else(|| "none".to_string())
+    );
+    text += &format!(
+        "  authentication key: {}\n",
+        format_key(&details, &details.authentication_key)
+    );
+    text += &format!(
+        "  attestation key:    {}\n",
+        optional_key(&details.attestation_key)
+    );
+    text += &format!(
+        "  delegation key:     {}\n",
+        optional_key(&details.delegation_key)
+    );
+    let agreement_keys: Vec<_> = details
+        .key_agreement_keys
+        .0
+        .iter()
+        .map(|key| format_key(&details, key))
+        .collect();
+    text += &format!(
+        "  key agreement keys: {}\n",
+        if agreement_keys.is_empty() {
+            "none".to_string()
+        } else {
+            agreement_keys.join(", ")
+        }
+    );
+    if services.is_empty() {
+        text += "  services:           none\n";
+    } else {
+        text += "  services:\n";
+        text += &format_services(&services);
+    }
+    Ok(text)
+}
+
+pub async fn did(backend: &dyn ChainBackend, args: &DidArgs) -> Result<(), Error> {
+    match &args.command {
+        DidCommand::Resolve(args) => {
+            print!("{}", resolve(backend, &args.did).await?);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    fn endpoint(id: &[u8], types: &[&[u8]], urls: &[&[u8]]) -> DidEndpoint {
+        let bounded = |values: &[&[u8]]| {
+            BoundedVec(
+                values
+                    .iter()
+                    .map(|value| BoundedVec(value.to_vec()))
+                    .collect(),
+            )
+        };
+        DidEndpoint {
+            id: BoundedVec(id.to_vec()),
+            service_types: bounded(types),
+            urls: bounded(urls),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let mut backend = fixtures::backend();
+        let owner = fixtures::owner_key().did();
+        let account = get_did_account_id(&owner).unwrap();
+        let state = backend.latest_mut();
+        state.insert_web3_name("alice", account.clone());
+        state.insert_service_endpoint(
+            account.clone(),
+            &endpoint(
+                b"messaging",
+                &[b"KiltMessagingService"],
+                &[b"https://alice.example.com/messages"],
+            ),
+        );
+        state.insert_service_endpoint(
+            account.clone(),
+            &endpoint(b"broken", &[b"\xff\xfe"], &[b"https://example.com"]),
+        );
+        state.insert_service_endpoint(
+            account.clone(),
+            &endpoint(
+                b"exchange",
+                &[b"A", b"B"],
+                &[b"https://a.example.com", b"https://b.example.com"],
+            ),
+        );
+
+        let services = service_endpoints(&backend, &account, None).await.unwrap();
+        let ids: Vec<_> = services.iter().map(|service| service.id.as_str()).collect();
+        assert_eq!(ids, vec!["exchange", "messaging"]);
+        assert_eq!(services[0].types, vec!["A", "B"]);
+
+        let text = resolve(&backend, &owner).await.unwrap();
+        let key = hex_encode(fixtures::owner_key().key_id());
+        assert!(text.starts_with(&format!("{}\n", owner)), "{}", text);
+        assert!(text.contains("web3name:           w3n:alice\n"), "{}", text);
+        assert!(
+            text.contains(&format!("authentication key: #{} (sr25519)\n", key)),
+            "{}",
+            text
+        );
+        assert!(text.contains("delegation key:     none\n"), "{}", text);
+        assert!(
+            text.ends_with(
+                "  services:\n    #exchange\n      types: A, B\n      url:   https://a.example.com\n      url:   https://b.example.com\n    #messaging\n      types: KiltMessagingService\n      url:   https://alice.example.com/messages\n"
+            ),
+            "{}",
+            text
+        );
+
+        let text = resolve(&backend, &fixtures::attester_did()).await.unwrap();
+        assert!(text.ends_with("  services:           none\n"), "{}", text);
+        let res = resolve(&backend, "did:kilt:4notadid").await;
+        assert!(matches!(res, Err(Error::InvalidDid)), "{:?}", res);
+    }
+}
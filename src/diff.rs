@@ -0,0 +1,536 @@
+use clap::Args;
+use dialoguer::console::style;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+use crate::{
+    credential::{Credential, REDACTED},
+    errors::Error,
+    metrics::Check,
+    utils::{read_credential, to_sorted_json},
+};
+
+/// Compare two credentials, i.e. before and after a re-export by a wallet
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The credential to compare against
+    #[clap(value_parser)]
+    a: String,
+
+    /// The credential that is compared
+    #[clap(value_parser)]
+    b: String,
+
+    /// Print the differences as json for tools
+    #[clap(long, value_parser, default_value_t = false)]
+    json: bool,
+}
+
+/// How a field differs from `a` to `b`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    /// Only in `b`
+    Added,
+    /// Only in `a`
+    Removed,
+    Changed,
+    /// The same values in another order
+    Reordered,
+}
+
+/// A field that differs, named by its json path like `claim.contents.Email`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Difference {
+    pub field: String,
+    pub change: Change,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b: Option<Value>,
+}
+
+/// A check of the verification that fails or behaves differently because of the differences
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AffectedCheck {
+    /// Label of the check, `requirements` for the checks of `--expected-owner` and `--require-property`
+    pub check: &'static str,
+    pub note: String,
+}
+
+/// The result of a check that needs no chain on both credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocalCheck {
+    pub check: &'static str,
+    /// Error code, `None` if the check passes
+    pub a: Option<&'static str>,
+    pub b: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CredentialDiff {
+    pub differences: Vec<Difference>,
+    pub affected_checks: Vec<AffectedCheck>,
+    pub local_checks: Vec<LocalCheck>,
+}
+
+fn string(value: &str) -> Value {
+    Value::String(value.to_string())
+}
+
+fn compare(differences: &mut Vec<Difference>, field: &str, a: Option<Value>, b: Option<Value>) {
+    let change = match (&a, &b) {
+        (Some(a), Some(b)) if a == b => return,
+        (Some(_), Some(_)) => Change::Changed,
+        (Some(_), None) => Change::Removed,
+        (None, Some(_)) => Change::Added,
+        (None, None) => return,
+    };
+    differences.push(Difference {
+        field: field.to_string(),
+        change,
+        a,
+        b,
+    });
+}
+
+// the properties of the claim contents, a single unnamed property if they are not an object
+fn properties(contents: &Value) -> Vec<(String, &Value)> {
+    match contents {
+        Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        value => vec![(String::new(), value)],
+    }
+}
+
+fn claim_hashes(differences: &mut Vec<Difference>, a: &[String], b: &[String]) {
+    let (set_a, set_b): (BTreeSet<_>, BTreeSet<_>) = (a.iter().collect(), b.iter().collect());
+    for hash in set_a.difference(&set_b) {
+        compare(differences, "claimHashes", Some(string(hash)), None);
+    }
+    for hash in set_b.difference(&set_a) {
+        compare(differences, "claimHashes", None, Some(string(hash)));
+    }
+    // the root hash is computed over the claim hashes in their order
+    if set_a == set_b && a != b {
+        differences.push(Difference {
+            field: "claimHashes".to_string(),
+            change: Change::Reordered,
+            a: None,
+            b: None,
+        });
+    }
+}
+
+fn differences(a: &Credential, b: &Credential) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    compare(
+        &mut differences,
+        "claim.cTypeHash",
+        Some(string(&a.claim.ctype_hash)),
+        Some(string(&b.claim.ctype_hash)),
+    );
+    compare(
+        &mut differences,
+        "claim.owner",
+        Some(string(&a.claim.owner)),
+        Some(string(&b.claim.owner)),
+    );
+    let (props_a, props_b) = (properties(&a.claim.contents), properties(&b.claim.contents));
+    let names: BTreeSet<_> = props_a
+        .iter()
+        .chain(props_b.iter())
+        .map(|(k, _)| k)
+        .collect();
+    for name in names {
+        let value = |props: &[(String, &Value)]| {
+            props
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| (*v).clone())
+        };
+        let field = match name.as_str() {
+            "" => "claim.contents".to_string(),
+            name => format!("claim.contents.{}", name),
+        };
+        compare(&mut differences, &field, value(&props_a), value(&props_b));
+    }
+    claim_hashes(&mut differences, &a.claim_hashes, &b.claim_hashes);
+    let hashes: BTreeSet<_> = a
+        .claim_nonce_map
+        .keys()
+        .chain(b.claim_nonce_map.keys())
+        .collect();
+    for hash in hashes {
+        compare(
+            &mut differences,
+            &format!("claimNonceMap.{}", hash),
+            a.claim_nonce_map.get(hash).map(|nonce| string(nonce)),
+            b.claim_nonce_map.get(hash).map(|nonce| string(nonce)),
+        );
+    }
+    compare(
+        &mut differences,
+        "rootHash",
+        Some(string(&a.root_hash)),
+        Some(string(&b.root_hash)),
+    );
+    let (sig_a, sig_b) = (&a.claimer_signature, &b.claimer_signature);
+    compare(
+        &mut differences,
+        "claimerSignature.signature",
+        Some(string(&sig_a.signature)),
+        Some(string(&sig_b.signature)),
+    );
+    compare(
+        &mut differences,
+        "claimerSignature.keyUri",
+        Some(string(&sig_a.key_uri)),
+        Some(string(&sig_b.key_uri)),
+    );
+    let challenge = |challenge: &str| (!challenge.is_empty()).then(|| string(challenge));
+    compare(
+        &mut differences,
+        "claimerSignature.challenge",
+        challenge(&sig_a.challenge),
+        challenge(&sig_b.challenge),
+    );
+    differences
+}
+
+// what the differences mean for each check in the order of the checks, the results of the local
+// checks tell if changed hashes were recomputed
+fn affected_checks(differences: &[Difference], local: &[LocalCheck]) -> Vec<AffectedCheck> {
+    let changed = |field: &str| differences.iter().any(|d| d.field == field);
+    let claim_changed = differences.iter().any(|d| d.field.starts_with("claim."));
+    let nonces_changed = differences
+        .iter()
+        .any(|d| d.field.starts_with("claimNonceMap."));
+    let hashes_changed = changed("claimHashes");
+    let root_hash_changed = changed("rootHash");
+
+    let mut checks = Vec::new();
+    let mut note = |check: Check, note: &str| {
+        checks.push(AffectedCheck {
+            check: check.label(),
+            note: note.to_string(),
+        })
+    };
+    if claim_changed || nonces_changed || hashes_changed {
+        match local[0].b {
+            Some(_) => note(
+                Check::ClaimContents,
+                "the claim, its nonces or claim hashes changed and do not match anymore, claim_contents fails on b",
+            ),
+            // i.e. a presentation that discloses fewer properties
+            None => note(
+                Check::ClaimContents,
+                "the claim, its nonces or claim hashes changed but still match, claim_contents passes on b",
+            ),
+        }
+    }
+    match (hashes_changed, root_hash_changed, local[1].b) {
+        (false, false, _) => {}
+        (true, false, _) => note(
+            Check::RootHash,
+            "claimHashes changed but rootHash was not recomputed, root_hash fails on b",
+        ),
+        (false, true, _) => note(
+            Check::RootHash,
+            "rootHash changed but claimHashes did not, root_hash fails on b",
+        ),
+        (true, true, Some(_)) => note(
+            Check::RootHash,
+            "rootHash does not match the changed claimHashes, root_hash fails on b",
+        ),
+        (true, true, None) => note(
+            Check::RootHash,
+            "rootHash was recomputed from the changed claimHashes, root_hash passes on b",
+        ),
+    }
+    if root_hash_changed && !changed("claimerSignature.signature") {
+        note(
+            Check::Signature,
+            "rootHash changed but the signature did not, signature fails on b",
+        );
+    } else if root_hash_changed || changed("claimerSignature.signature") {
+        note(
+            Check::Signature,
+            "the signature changed, signature passes on b only if the owner signed its rootHash",
+        );
+    }
+    if changed("claimerSignature.keyUri") {
+        note(
+            Check::Signature,
+            "b is signed with another key, it has to be a key of the owner DID",
+        );
+    }
+    if changed("claim.owner") {
+        note(
+            Check::Signature,
+            "the owner changed, the signature of b is checked against the keys of another DID",
+        );
+    }
+    if changed("claimerSignature.challenge") {
+        note(
+            Check::Signature,
+            "the challenge changed, a verifier that asks for the challenge of a rejects b",
+        );
+    }
+    if root_hash_changed {
+        note(
+            Check::Attestation,
+            "rootHash changed, attestation looks up b under another root hash that needs its own attestation",
+        );
+    }
+
+    if changed("claim.owner") {
+        checks.push(AffectedCheck {
+            check: "requirements",
+            note: "the owner changed, --expected-owner matches at most one of a and b".to_string(),
+        });
+    }
+    for difference in differences.iter() {
+        let property = match difference.field.strip_prefix("claim.contents.") {
+            Some(property) if difference.change == Change::Removed => property,
+            _ => continue,
+        };
+        checks.push(AffectedCheck {
+            check: "requirements",
+            note: format!(
+                "{} is not disclosed in b, --require-property {} fails on b",
+                property, property
+            ),
+        });
+    }
+    checks
+}
+
+fn local_checks(a: &Credential, b: &Credential) -> Vec<LocalCheck> {
+    let code = |res: Result<(), Error>| res.err().map(|err| err.code());
+    vec![
+        LocalCheck {
+            check: Check::ClaimContents.label(),
+            a: code(a.check_claim_contents()),
+            b: code(b.check_claim_contents()),
+        },
+        LocalCheck {
+            check: Check::RootHash.label(),
+            a: code(a.check_root_hash()),
+            b: code(b.check_root_hash()),
+        },
+    ]
+}
+
+/// Compare two credentials field by field and note which checks the differences affect
+pub fn diff(a: &Credential, b: &Credential) -> CredentialDiff {
+    let differences = differences(a, b);
+    let local_checks = local_checks(a, b);
+    CredentialDiff {
+        affected_checks: affected_checks(&differences, &local_checks),
+        differences,
+        local_checks,
+    }
+}
+
+fn format_value(value: &Option<Value>) -> String {
+    match value {
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+        None => String::new(),
+    }
+}
+
+impl CredentialDiff {
+    /// Replace the values of the claim contents with `[redacted]`, the fields stay
+    pub fn redact_claims(&mut self) {
+        for difference in self.differences.iter_mut() {
+            if difference.field.starts_with("claim.contents") {
+                for value in [&mut difference.a, &mut difference.b].into_iter().flatten() {
+                    *value = string(REDACTED);
+                }
+            }
+        }
+    }
+
+    /// The differences for people, colored like a unified diff if `color` is set
+    pub fn format(&self, a: &str, b: &str, color: bool) -> String {
+        let mut text = format!("--- {}\n+++ {}\n", a, b);
+        if self.differences.is_empty() {
+            text += "The credentials are identical\n";
+        }
+        for difference in self.differences.iter() {
+            let field = &difference.field;
+            let line = match difference.change {
+                Change::Added => {
+                    style(format!("+ {}: {}", field, format_value(&difference.b))).green()
+                }
+                Change::Removed => {
+                    style(format!("- {}: {}", field, format_value(&difference.a))).red()
+                }
+                Change::Changed => style(format!(
+                    "~ {}: {} → {}",
+                    field,
+                    format_value(&difference.a),
+                    format_value(&difference.b)
+                ))
+                .yellow(),
+                Change::Reordered => style(format!("~ {}: reordered", field)).yellow(),
+            };
+            text += &format!("{}\n", line.force_styling(color));
+        }
+        if !self.affected_checks.is_empty() {
+            text += "\nAffected checks:\n";
+            for affected in self.affected_checks.iter() {
+                text += &format!("  {}: {}\n", affected.check, affected.note);
+            }
+        }
+        text += "\nChecks without chain:\n";
+        let result = |code: Option<&str>| match code {
+            None => "✅".to_string(),
+            Some(code) => format!("❌ {}", code),
+        };
+        for check in self.local_checks.iter() {
+            text += &format!(
+                "  {:<15} a {}  b {}\n",
+                check.check,
+                result(check.a),
+                result(check.b)
+            );
+        }
+        text
+    }
+}
+
+pub fn run(args: &DiffArgs, redact_pii: bool) -> Result<(), Error> {
+    let a = read_credential(&args.a)?;
+    let b = read_credential(&args.b)?;
+    let mut diff = diff(&a, &b);
+    if redact_pii {
+        diff.redact_claims();
+    }
+    if args.json {
+        println!("{}", to_sorted_json(&diff)?);
+    } else {
+        let color = dialoguer::console::colors_enabled();
+        print!("{}", diff.format(&args.a, &args.b, color));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use serde_json::json;
+
+    fn fields(diff: &CredentialDiff) -> Vec<(&str, Change)> {
+        diff.differences
+            .iter()
+            .map(|d| (d.field.as_str(), d.change))
+            .collect()
+    }
+
+    fn checks(diff: &CredentialDiff) -> Vec<&str> {
+        diff.affected_checks.iter().map(|c| c.check).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = fixtures::credential();
+        let same = diff(&a, &a.clone());
+        assert!(same.differences.is_empty());
+        assert!(same.affected_checks.is_empty());
+        let text = same.format("a.json", "b.json", false);
+        assert!(
+            text.starts_with("--- a.json\n+++ b.json\nThe credentials are identical\n"),
+            "{}",
+            text
+        );
+
+        // an edited claim without new hashes
+        let mut b = a.clone();
+        b.claim.contents = json!({"Email": "mallory@example.com", "Age": 30});
+        let edited = diff(&a, &b);
+        assert_eq!(
+            fields(&edited),
+            vec![
+                ("claim.contents.Age", Change::Added),
+                ("claim.contents.Email", Change::Changed),
+                ("claim.contents.Name", Change::Removed),
+            ]
+        );
+        assert_eq!(checks(&edited), vec!["claim_contents", "requirements"]);
+        assert!(edited.affected_checks[0].note.contains("fails on b"));
+        assert_eq!(edited.local_checks[0].a, None);
+        assert_eq!(edited.local_checks[0].b, Some("invalid_claim_contents"));
+        let text = edited.format("a.json", "b.json", false);
+        assert!(
+            text.contains("~ claim.contents.Email: alice@example.com → mallory@example.com\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("+ claim.contents.Age: 30\n"), "{}", text);
+        assert!(text.contains("- claim.contents.Name: Alice\n"), "{}", text);
+        assert!(
+            text.contains("  claim_contents  a ✅  b ❌ invalid_claim_contents\n"),
+            "{}",
+            text
+        );
+        let colored = edited.format("a.json", "b.json", true);
+        assert!(
+            colored.contains("\u{1b}[32m+ claim.contents.Age: 30"),
+            "{}",
+            colored
+        );
+        let mut redacted = edited.clone();
+        redacted.redact_claims();
+        let text = redacted.format("a.json", "b.json", false);
+        assert!(
+            text.contains("~ claim.contents.Email: [redacted] → [redacted]\n"),
+            "{}",
+            text
+        );
+        assert!(
+            !text.contains("mallory") && !text.contains("Alice"),
+            "{}",
+            text
+        );
+
+        // claim hashes in another order change the root hash
+        let mut b = a.clone();
+        b.claim_hashes.reverse();
+        b.root_hash = b.compute_root_hash().unwrap();
+        let reordered = diff(&a, &b);
+        assert_eq!(
+            fields(&reordered),
+            vec![
+                ("claimHashes", Change::Reordered),
+                ("rootHash", Change::Changed)
+            ]
+        );
+        assert_eq!(
+            checks(&reordered),
+            vec!["claim_contents", "root_hash", "signature", "attestation"]
+        );
+        assert_eq!(reordered.local_checks[1].b, None);
+
+        // a new root hash only
+        let mut b = a.clone();
+        b.root_hash = format!("0x{}", "ab".repeat(32));
+        b.claimer_signature.key_uri = format!("{}#0x12", a.claim.owner);
+        let rehashed = diff(&a, &b);
+        assert_eq!(
+            checks(&rehashed),
+            vec!["root_hash", "signature", "signature", "attestation"]
+        );
+        assert_eq!(rehashed.local_checks[1].b, Some("invalid_root_hash"));
+
+        let json = serde_json::to_value(&rehashed).unwrap();
+        assert_eq!(json["differences"][0]["field"], "rootHash");
+        assert_eq!(json["differences"][0]["change"], "changed");
+        assert_eq!(json["affected_checks"][0]["check"], "root_hash");
+        assert_eq!(json["local_checks"][1]["b"], "invalid_root_hash");
+        assert!(json["local_checks"][1]["a"].is_null());
+    }
+}
@@ -0,0 +1,711 @@
+use async_trait::async_trait;
+use clap::Args;
+use futures::Future;
+use serde::Serialize;
+use std::{
+    io::Write,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use subxt::sp_core::H256;
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    history::BlockHistory,
+    kilt::{connect, KiltRuntimeApi, Network, NETWORKS},
+    structure,
+    utils::{get_did_account_id, read_credential_data, ParseOptions},
+};
+
+/// Check the environment and the connection to the endpoint, i.e. before opening a support ticket
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    /// Credential file that is parsed and checked for structural problems, it is not verified
+    #[clap(long, value_parser)]
+    file: Option<String>,
+
+    #[clap(long, value_enum, default_value_t = DoctorOutput::Human)]
+    output: DoctorOutput,
+
+    /// Upper bound for each probe, a probe that takes longer fails and the others still run
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    probe_timeout: Duration,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DoctorOutput {
+    Human,
+    Json,
+}
+
+/// Blocks of the best and the finalized chain further apart than this are reported
+pub const MAX_FINALITY_LAG: u32 = 10;
+/// Finality is considered stalled beyond this many blocks
+pub const STALLED_FINALITY_LAG: u32 = 100;
+/// The latest block is about a block time old, a clock that is off by more than this is reported
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+/// A clock that is off by more than this resolves `--valid-at` to the wrong blocks
+pub const FAILING_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+    /// The check could not run because an earlier one failed
+    Skipped,
+}
+
+/// The outcome of one check
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// How long the probes of the check took, `None` for checks that send nothing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        CheckResult {
+            name,
+            status,
+            detail: detail.into(),
+            latency_ms: None,
+        }
+    }
+
+    fn timed(mut self, latency: Duration) -> Self {
+        self.latency_ms = Some(latency.as_secs_f64() * 1000.0);
+        self
+    }
+}
+
+/// All checks in the order they ran
+#[derive(Debug, Default, Serialize)]
+pub struct DoctorReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    pub fn failed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fail)
+            .count()
+    }
+
+    fn push(&mut self, check: CheckResult) {
+        self.checks.push(check);
+    }
+
+    // the checks that need a connection, skipped because there is none
+    fn skip(&mut self, names: &[&'static str], reason: &str) {
+        for name in names {
+            self.push(CheckResult::new(name, CheckStatus::Skipped, reason));
+        }
+    }
+
+    pub fn write(&self, out: &mut dyn Write, output: DoctorOutput) -> Result<(), Error> {
+        match output {
+            DoctorOutput::Json => {
+                #[derive(Serialize)]
+                struct JsonReport<'a> {
+                    checks: &'a [CheckResult],
+                    failed: usize,
+                }
+                let report = JsonReport {
+                    checks: &self.checks,
+                    failed: self.failed(),
+                };
+                writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+            }
+            DoctorOutput::Human => {
+                for check in self.checks.iter() {
+                    let status = match check.status {
+                        CheckStatus::Ok => "✅",
+                        CheckStatus::Warn => "⚠️ ",
+                        CheckStatus::Fail => "❌",
+                        CheckStatus::Skipped => "➖",
+                    };
+                    write!(out, "{} {:<12}{}", status, check.name, check.detail)?;
+                    match check.latency_ms {
+                        Some(ms) => writeln!(out, " ({:.1} ms)", ms)?,
+                        None => writeln!(out)?,
+                    }
+                }
+                writeln!(
+                    out,
+                    "{} checks, {} failed",
+                    self.checks.len(),
+                    self.failed()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the doctor asks a connected node besides the chain state and its blocks
+#[async_trait]
+pub trait NodeInfo: ChainBackend + BlockHistory {
+    fn genesis_hash(&self) -> H256;
+
+    async fn spec_version(&self) -> Result<u32, Error>;
+
+    /// The metadata of the runtime is the one the verifier was built with
+    fn metadata_matches(&self) -> bool;
+}
+
+#[async_trait]
+impl NodeInfo for KiltRuntimeApi {
+    fn genesis_hash(&self) -> H256 {
+        *self.client.genesis()
+    }
+
+    async fn spec_version(&self) -> Result<u32, Error> {
+        Ok(self.client.rpc().runtime_version(None).await?.spec_version)
+    }
+
+    fn metadata_matches(&self) -> bool {
+        self.validate_metadata().is_ok()
+    }
+}
+
+// run a probe with the timeout, how long it took is returned either way
+async fn probe<T, E: std::fmt::Display>(
+    timeout: Duration,
+    probe: impl Future<Output = Result<T, E>>,
+) -> (Result<T, String>, Duration) {
+    let start = Instant::now();
+    let res = match tokio::time::timeout(timeout, probe).await {
+        Ok(res) => res.map_err(|err| err.to_string()),
+        Err(_) => Err(format!(
+            "timed out after {}",
+            humantime::format_duration(timeout)
+        )),
+    };
+    (res, start.elapsed())
+}
+
+/// The host and port of a websocket endpoint, i.e. "spiritnet.kilt.io:443"
+pub fn endpoint_address(endpoint: &str) -> Result<String, String> {
+    let (rest, port) = if let Some(rest) = endpoint.strip_prefix("wss://") {
+        (rest, 443)
+    } else if let Some(rest) = endpoint.strip_prefix("ws://") {
+        (rest, 80)
+    } else {
+        return Err(format!("{} is not a ws:// or wss:// endpoint", endpoint));
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    if host.is_empty() {
+        return Err(format!("{} has no host", endpoint));
+    }
+    if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        Ok(host.to_string())
+    } else {
+        Ok(format!("{}:{}", host, port))
+    }
+}
+
+/// The network of the genesis hash, against the one that was asked for
+pub fn check_network(genesis: &H256, expected: Option<&Network>) -> CheckResult {
+    let hash = format!("{:?}", genesis);
+    let detected = NETWORKS.iter().find(|network| network.genesis_hash == hash);
+    match (detected, expected) {
+        (_, Some(expected)) if expected.check_genesis(genesis).is_err() => CheckResult::new(
+            "network",
+            CheckStatus::Fail,
+            format!(
+                "genesis {} is {}, not {}",
+                hash,
+                detected.map_or("an unknown chain", |network| network.name),
+                expected.name
+            ),
+        ),
+        (Some(network), _) => CheckResult::new(
+            "network",
+            CheckStatus::Ok,
+            format!("genesis {} is {}", hash, network.name),
+        ),
+        (None, _) => CheckResult::new(
+            "network",
+            CheckStatus::Warn,
+            format!(
+                "genesis {} is not a known network, give --network to use its issuers",
+                hash
+            ),
+        ),
+    }
+}
+
+pub fn check_finality(best: u32, finalized: u32) -> CheckResult {
+    let lag = best.saturating_sub(finalized);
+    let status = if lag > STALLED_FINALITY_LAG {
+        CheckStatus::Fail
+    } else if lag > MAX_FINALITY_LAG {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Ok
+    };
+    CheckResult::new(
+        "finality",
+        status,
+        format!(
+            "best block #{}, finalized #{}, {} blocks behind",
+            best, finalized, lag
+        ),
+    )
+}
+
+/// The local clock against the timestamp of the latest block, both in milliseconds since the
+/// unix epoch
+pub fn check_clock(now: u64, block_timestamp: u64) -> CheckResult {
+    let skew = Duration::from_millis(now.abs_diff(block_timestamp));
+    let direction = if now >= block_timestamp {
+        "behind the local clock"
+    } else {
+        "ahead of the local clock"
+    };
+    let status = if skew > FAILING_CLOCK_SKEW {
+        CheckStatus::Fail
+    } else if skew > MAX_CLOCK_SKEW {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Ok
+    };
+    let skew = Duration::from_secs(skew.as_secs());
+    CheckResult::new(
+        "clock",
+        status,
+        format!(
+            "the latest block is {} {}",
+            humantime::format_duration(skew),
+            direction
+        ),
+    )
+}
+
+/// Parse the credential and look for the structural problems that fail a verification before
+/// the chain is asked
+pub fn check_credential(file: &str, parse: &ParseOptions) -> CheckResult {
+    let fail = |detail: String| CheckResult::new("credential", CheckStatus::Fail, detail);
+    let cred = match read_credential_data(file).and_then(|data| parse.parse(file, &data)) {
+        Ok(cred) => cred,
+        Err(err) => return fail(format!("{} cannot be parsed: {}", file, err)),
+    };
+    let structure = match structure::explain(&cred) {
+        Ok(structure) => structure,
+        Err(err) => return fail(format!("{}: {}", file, err)),
+    };
+    let mut problems = Vec::new();
+    for statement in structure.statements.iter() {
+        let property = statement.property.as_deref().unwrap_or("owner");
+        if statement.nonce.is_none() {
+            problems.push(format!("statement {} has no nonce", property));
+        } else if statement.claim_hashes_position.is_none() {
+            problems.push(format!("statement {} is not in claimHashes", property));
+        }
+    }
+    if !structure.root_hash.matches {
+        problems.push(format!(
+            "the root hash of the claim hashes is {}",
+            structure.root_hash.computed
+        ));
+    }
+    if !problems.is_empty() {
+        return fail(format!("{}: {}", file, problems.join(", ")));
+    }
+    if let Err(err) = cred.check_claim_contents() {
+        return fail(format!("{}: {}", file, err));
+    }
+    let summary = format!(
+        "{} parsed, {} statements and {} claim hashes",
+        file,
+        structure.statements.len(),
+        structure.claim_hashes.len()
+    );
+    if cred.key_inferred() {
+        CheckResult::new(
+            "credential",
+            CheckStatus::Warn,
+            format!("{}, it names no key", summary),
+        )
+    } else {
+        CheckResult::new("credential", CheckStatus::Ok, summary)
+    }
+}
+
+// everything the doctor asks a connected node, each probe with its own timeout
+async fn check_node(
+    report: &mut DoctorReport,
+    node: &dyn NodeInfo,
+    network: Option<&'static Network>,
+    timeout: Duration,
+) {
+    let network_check = check_network(&node.genesis_hash(), network);
+    let hash = format!("{:?}", node.genesis_hash());
+    let network = network.or_else(|| NETWORKS.iter().find(|network| network.genesis_hash == hash));
+    report.push(network_check);
+
+    let (version, latency) = probe(timeout, node.spec_version()).await;
+    report.push(
+        match version {
+            Ok(version) if node.metadata_matches() => CheckResult::new(
+                "runtime",
+                CheckStatus::Ok,
+                format!("spec version {}, the metadata is the embedded one", version),
+            ),
+            Ok(version) => CheckResult::new(
+                "runtime",
+                CheckStatus::Warn,
+                format!(
+                    "spec version {}, the metadata differs from the embedded one, lookups of changed storage fail",
+                    version
+                ),
+            ),
+            Err(err) => CheckResult::new("runtime", CheckStatus::Fail, err),
+        }
+        .timed(latency),
+    );
+
+    let (blocks, latency) = probe(timeout, async {
+        Ok::<_, Error>((node.best_block().await?, node.finalized_block().await?))
+    })
+    .await;
+    report.push(
+        match blocks {
+            Ok((best, finalized)) => check_finality(best, finalized),
+            Err(err) => CheckResult::new("finality", CheckStatus::Fail, err),
+        }
+        .timed(latency),
+    );
+
+    match network {
+        Some(network) => {
+            let start = Instant::now();
+            let mut problems = Vec::new();
+            for issuer in network.issuers {
+                let (did, _) = probe(timeout, async {
+                    node.did(&get_did_account_id(issuer)?, None).await
+                })
+                .await;
+                match did {
+                    Ok(Some(_)) => {}
+                    Ok(None) => problems.push(format!("{} does not exist", issuer)),
+                    Err(err) => problems.push(format!("{}: {}", issuer, err)),
+                }
+            }
+            let check = if problems.is_empty() {
+                CheckResult::new(
+                    "issuers",
+                    CheckStatus::Ok,
+                    format!(
+                        "the {} default issuers of {} exist",
+                        network.issuers.len(),
+                        network.name
+                    ),
+                )
+            } else {
+                CheckResult::new("issuers", CheckStatus::Fail, problems.join(", "))
+            };
+            report.push(check.timed(start.elapsed()));
+        }
+        None => report.skip(&["issuers"], "the network is not known"),
+    }
+
+    let (timestamp, latency) = probe(timeout, async {
+        let best = node.best_block().await?;
+        let hash = node.block_hash(best).await?.ok_or(Error::BlockNotFound)?;
+        node.block_timestamp(hash).await
+    })
+    .await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    report.push(
+        match timestamp {
+            Ok(timestamp) => check_clock(now, timestamp),
+            Err(err) => CheckResult::new("clock", CheckStatus::Fail, err),
+        }
+        .timed(latency),
+    );
+}
+
+// the checks of a node, in the order they run
+const NODE_CHECKS: [&str; 5] = ["network", "runtime", "finality", "issuers", "clock"];
+
+/// Run all checks against the endpoint, a check whose prerequisite failed is skipped
+pub async fn run_checks(
+    endpoint: &str,
+    network: Option<&'static Network>,
+    args: &DoctorArgs,
+    parse: &ParseOptions,
+) -> DoctorReport {
+    let timeout = args.probe_timeout;
+    let mut report = DoctorReport::default();
+    let connected = async {
+        let address = match endpoint_address(endpoint) {
+            Ok(address) => address,
+            Err(err) => {
+                report.push(CheckResult::new("dns", CheckStatus::Fail, err));
+                return None;
+            }
+        };
+        let (addresses, latency) = probe(timeout, async {
+            tokio::net::lookup_host(&address)
+                .await
+                .map(|addresses| addresses.collect::<Vec<_>>())
+        })
+        .await;
+        let addresses = match addresses {
+            Ok(addresses) if !addresses.is_empty() => addresses,
+            res => {
+                let err = res.err().unwrap_or_else(|| "no addresses".to_string());
+                report.push(
+                    CheckResult::new("dns", CheckStatus::Fail, format!("{}: {}", address, err))
+                        .timed(latency),
+                );
+                return None;
+            }
+        };
+        report.push(
+            CheckResult::new(
+                "dns",
+                CheckStatus::Ok,
+                format!("{} resolved to {}", address, addresses[0].ip()),
+            )
+            .timed(latency),
+        );
+
+        let (tcp, latency) = probe(timeout, tokio::net::TcpStream::connect(addresses[0])).await;
+        let check = match tcp {
+            Ok(_) => CheckResult::new(
+                "tcp",
+                CheckStatus::Ok,
+                format!("connected to {}", addresses[0]),
+            ),
+            Err(err) => CheckResult::new(
+                "tcp",
+                CheckStatus::Fail,
+                format!("{}: {}", addresses[0], err),
+            ),
+        };
+        let failed = check.status == CheckStatus::Fail;
+        report.push(check.timed(latency));
+        if failed {
+            return None;
+        }
+
+        // the handshake includes TLS for wss:// endpoints
+        let (cli, latency) = probe(timeout, connect(endpoint)).await;
+        let protocol = if endpoint.starts_with("wss://") {
+            "websocket over TLS"
+        } else {
+            "websocket"
+        };
+        match cli {
+            Ok(cli) => {
+                report.push(
+                    CheckResult::new(
+                        "websocket",
+                        CheckStatus::Ok,
+                        format!("{} connected to {}", protocol, endpoint),
+                    )
+                    .timed(latency),
+                );
+                Some(cli)
+            }
+            Err(err) => {
+                report.push(
+                    CheckResult::new(
+                        "websocket",
+                        CheckStatus::Fail,
+                        format!("{} to {}: {}", protocol, endpoint, err),
+                    )
+                    .timed(latency),
+                );
+                None
+            }
+        }
+    }
+    .await;
+
+    match connected {
+        Some(cli) => check_node(&mut report, &cli, network, timeout).await,
+        None => {
+            let done: Vec<_> = report.checks.iter().map(|check| check.name).collect();
+            let remaining: Vec<_> = ["dns", "tcp", "websocket"]
+                .into_iter()
+                .filter(|name| !done.contains(name))
+                .collect();
+            report.skip(&remaining, "not connected");
+            report.skip(&NODE_CHECKS, "not connected");
+        }
+    }
+    if let Some(file) = &args.file {
+        report.push(check_credential(file, parse));
+    }
+    report
+}
+
+pub async fn run(
+    endpoint: &str,
+    network: Option<&'static Network>,
+    args: &DoctorArgs,
+    parse: &ParseOptions,
+) -> Result<(), Error> {
+    let report = run_checks(endpoint, network, args, parse).await;
+    report.write(&mut std::io::stdout(), args.output)?;
+    match report.failed() {
+        0 => Ok(()),
+        failed => Err(Error::ChecksFailed(failed)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    fn write_credential(name: &str, json: &serde_json::Value) -> String {
+        let file = std::env::temp_dir().join(format!(
+            "kilt-verify-doctor-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&file, serde_json::to_vec(json).unwrap()).unwrap();
+        file.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_checks() {
+        assert_eq!(
+            endpoint_address("wss://spiritnet.kilt.io/").unwrap(),
+            "spiritnet.kilt.io:443"
+        );
+        assert_eq!(
+            endpoint_address("ws://127.0.0.1:9944").unwrap(),
+            "127.0.0.1:9944"
+        );
+        assert!(endpoint_address("https://spiritnet.kilt.io").is_err());
+
+        assert_eq!(check_finality(110, 100).status, CheckStatus::Ok);
+        assert_eq!(check_finality(111, 100).status, CheckStatus::Warn);
+        assert_eq!(check_finality(201, 100).status, CheckStatus::Fail);
+
+        let block = 1_700_000_000_000;
+        assert_eq!(check_clock(block + 12_000, block).status, CheckStatus::Ok);
+        let ahead = check_clock(block, block + 120_000);
+        assert_eq!(ahead.status, CheckStatus::Warn);
+        assert_eq!(
+            ahead.detail,
+            "the latest block is 2m ahead of the local clock"
+        );
+        assert_eq!(
+            check_clock(block + 600_000, block).status,
+            CheckStatus::Fail
+        );
+
+        let spiritnet = &NETWORKS[0];
+        let genesis: H256 = spiritnet.genesis_hash.parse().unwrap();
+        assert_eq!(check_network(&genesis, None).status, CheckStatus::Ok);
+        assert_eq!(
+            check_network(&genesis, Some(spiritnet)).status,
+            CheckStatus::Ok
+        );
+        assert_eq!(
+            check_network(&genesis, Some(&NETWORKS[1])).status,
+            CheckStatus::Fail
+        );
+        assert_eq!(check_network(&H256::zero(), None).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_check_credential() {
+        let parse = ParseOptions::default();
+        let json = serde_json::to_value(fixtures::credential()).unwrap();
+        let check = check_credential(&write_credential("ok", &json), &parse);
+        assert_eq!(check.status, CheckStatus::Ok, "{}", check.detail);
+
+        let mut root_hash = json.clone();
+        root_hash["rootHash"] = format!("{:?}", H256::zero()).into();
+        let check = check_credential(&write_credential("root", &root_hash), &parse);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("the root hash"), "{}", check.detail);
+
+        let mut nonce = json.clone();
+        let nonces = nonce["claimNonceMap"].as_object_mut().unwrap();
+        let first = nonces.keys().next().unwrap().clone();
+        nonces.remove(&first);
+        let check = check_credential(&write_credential("nonce", &nonce), &parse);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("has no nonce"), "{}", check.detail);
+
+        let check = check_credential("/nonexistent/cred.json", &parse);
+        assert_eq!(check.status, CheckStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_not_connected() {
+        // accepts connections and closes them, so the websocket handshake fails
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let args = DoctorArgs {
+            file: None,
+            output: DoctorOutput::Json,
+            probe_timeout: Duration::from_secs(5),
+        };
+        let report = run_checks(&endpoint, None, &args, &ParseOptions::default()).await;
+        let statuses: Vec<_> = report
+            .checks
+            .iter()
+            .map(|check| (check.name, check.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("dns", CheckStatus::Ok),
+                ("tcp", CheckStatus::Ok),
+                ("websocket", CheckStatus::Fail),
+                ("network", CheckStatus::Skipped),
+                ("runtime", CheckStatus::Skipped),
+                ("finality", CheckStatus::Skipped),
+                ("issuers", CheckStatus::Skipped),
+                ("clock", CheckStatus::Skipped),
+            ]
+        );
+        assert_eq!(report.failed(), 1);
+
+        let mut out = Vec::new();
+        report.write(&mut out, DoctorOutput::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["checks"][0]["name"], "dns");
+        assert!(json["checks"][0]["latencyMs"].is_number());
+        assert!(json["checks"][3].get("latencyMs").is_none());
+
+        let mut out = Vec::new();
+        report.write(&mut out, DoctorOutput::Human).unwrap();
+        let human = String::from_utf8(out).unwrap();
+        assert!(
+            human.lines().next().unwrap().starts_with("✅ dns"),
+            "{}",
+            human
+        );
+        assert!(human.ends_with("8 checks, 1 failed\n"), "{}", human);
+    }
+}
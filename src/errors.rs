@@ -1,17 +1,203 @@
+use std::time::Duration;
+
+use crate::{
+    kilt::runtime_types::sp_runtime::DispatchError,
+    utils::{other_network_hint, KILT_SS58_PREFIX},
+    wizard::ABORT_EXIT_CODE,
+};
+
+/// Exit code of a run that hit `--timeout`, the same as coreutils' timeout uses
+pub const TIMEOUT_EXIT_CODE: u8 = 124;
+
+/// Exit code of usage errors, the same as clap uses for invalid arguments
+pub const USAGE_EXIT_CODE: u8 = 2;
+
+/// Exit code of a credential whose attestation was removed, to tell it from other invalid ones
+pub const REMOVED_EXIT_CODE: u8 = 3;
+
+/// Exit code of a batch with more invalid credentials than `--max-failures` allows
+pub const INVALID_EXIT_CODE: u8 = 4;
+
+/// Exit code of a run that could not verify everything because the node failed or could not be
+/// reached, the credentials may well be valid
+pub const INFRASTRUCTURE_EXIT_CODE: u8 = 5;
+
+/// One try of a storage query, see `backend::QueryBackend`
+#[derive(Debug)]
+pub struct QueryAttempt {
+    pub endpoint: String,
+    pub duration: Duration,
+    /// What went wrong, "timed out" if the query did not answer within the query timeout
+    pub error: String,
+}
+
+impl std::fmt::Display for QueryAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} after {}",
+            self.endpoint,
+            self.error,
+            humantime::format_duration(Duration::from_millis(self.duration.as_millis() as u64))
+        )
+    }
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     Io(std::io::Error),
     Serde(serde_json::Error),
+    /// Input parsed with `--lenient-json` that is not even JSON5
+    Json5(json5::Error),
+    Yaml(serde_yaml::Error),
+    /// CBOR that is not valid or has no json form, i.e. byte strings
+    Cbor(String),
+    /// Gzip or zstd input that is broken or larger than `--max-decompressed-size`
+    Decompression(String),
+    /// A deep link with an unknown scheme or without a credential, see `uri::decode`
+    InvalidUri(String),
     InvalidClaimContents,
+    /// A credential is larger than one of its `limits::Limits`, i.e. has too many claim hashes
+    LimitExceeded {
+        which: &'static str,
+        limit: u64,
+        actual: u64,
+    },
     InvalidHex(hex::FromHexError),
     InvalidRootHash,
     ConnectionError(subxt::BasicError),
     InvalidDid,
+    /// A DID whose address is the public key encoded for another network than KILT
+    ForeignAddress {
+        address: String,
+        prefix: u16,
+        /// The KILT address of the same public key
+        kilt: String,
+    },
+    /// A DID URL that cannot be split into DID, query and fragment, i.e. with two fragments
+    InvalidDidUrl(String),
+    /// The fragment of a key uri is not a key id in a supported encoding
+    InvalidKeyId(String),
     DidNotFound,
+    /// The key uri of the signature names a key the DID document does not have, i.e. after the key
+    /// was replaced. `available` lists the keys it has with their type and use
+    KeyNotFound {
+        key_id: String,
+        available: Vec<String>,
+        /// The block the newest key was added at, see `did::keys_changed_at`
+        keys_changed_at: Option<u64>,
+    },
+    /// The key uri of the signature names the DID of another account than the owner
+    KeyOfOtherDid {
+        key_uri: String,
+        owner: String,
+    },
+    /// The owner is a light DID with no full DID on chain, its keys are not looked up in the DID
+    LightDidNotUpgraded(String),
+    /// The owner is a light DID, but `--require-full-did` asks for the full DID
+    LightDidOwner(String),
     InvalidSignature,
     AttestationNotFound,
     AttestationRevoked,
+    /// The attestation existed but was removed, it was last seen at this block number
+    AttestationRemoved {
+        last_seen_block: u32,
+    },
+    /// The attestation is not `required` blocks below the block it was verified at, see
+    /// `--min-confirmations`. `confirmations` is known if the block it was created in is
+    AttestationTooRecent {
+        required: u32,
+        confirmations: Option<u32>,
+    },
     InvalidIssuer,
+    /// An entry of the allowed issuers is neither a KILT DID nor a KILT address
+    InvalidIssuerEntry(String),
+    /// The issuer registry does not exist, lists no issuers or could not be read and is not cached
+    IssuerRegistry(String),
+    Metrics(prometheus::Error),
+    InvalidSeed,
+    InvalidDidKey,
+    BlockNotFound,
+    TransactionFailed(Box<subxt::Error<DispatchError>>),
+    EventNotFound(&'static str),
+    PropertyNotFound(String),
+    InvalidChallenge,
+    InvalidCredentials(usize),
+    /// Presentations of a session failed, the session is not valid as a whole
+    InvalidSession {
+        failed: usize,
+        total: usize,
+    },
+    /// A webhook URL is not supported or the hook did not accept a request
+    Webhook(String),
+    /// An OTLP endpoint or a W3C traceparent that cannot be used for tracing
+    Telemetry(String),
+    /// The HTTP DID resolver could not be reached or did not answer with a usable DID document
+    DidResolver(String),
+    /// The indexer could not be reached or its answer could not be read
+    Indexer(String),
+    /// The attestations of this many accepted credentials could not be checked again
+    RecheckFailed(usize),
+    /// This many checks of `doctor` failed
+    ChecksFailed(usize),
+    /// This many lint findings are of denied rules
+    LintFailed(usize),
+    /// The `--ctype-names` file is not a TOML table of ctype hashes and names
+    CtypeNames(String),
+    /// An error whose message could quote claim values, with the message it has without them,
+    /// see `without_pii`
+    Redacted {
+        code: &'static str,
+        message: String,
+    },
+    /// A warning failed the run because of `--deny-warnings`
+    DeniedWarning(String),
+    /// A server was given a plaintext endpoint without `--allow-insecure`
+    InsecureEndpoint(String),
+    /// This many credentials of a batch could not be verified because the node failed
+    Incomplete(usize),
+    /// A storage query failed or timed out on every attempt, listed in the order they were made
+    QueryFailed {
+        storage: &'static str,
+        attempts: Vec<QueryAttempt>,
+    },
+    /// The endpoints of a quorum gave different answers, the endpoints that differ from most
+    EndpointDisagreement {
+        storage: &'static str,
+        endpoints: Vec<String>,
+    },
+    /// The quorum does not fit the number of endpoints
+    InvalidQuorum(String),
+    /// The audit log cannot be appended to or its hash chain is broken
+    AuditLog(String),
+    /// A signed report is malformed or its signature has an unsupported version
+    InvalidReport(String),
+    /// The credentials of a batch have different owners or keys, listed with their credentials
+    OwnerInconsistency(String),
+    InvalidArchive(String),
+    /// A proof bundle is malformed, its proof does not match its header or it does not support
+    /// its report
+    InvalidBundle(String),
+    /// A `--publish` URL that cannot be published to
+    Publish(String),
+    /// Results that the message queue did not confirm, even after retrying
+    PublishFailed {
+        subject: String,
+        failed: usize,
+    },
+    InvalidGlob(globset::Error),
+    InvalidManifest(String),
+    NotArchiveNode(String),
+    /// The endpoint is not on the network that was asked for
+    NetworkMismatch(String),
+    TimestampOutOfRange(String),
+    Timeout,
+    NoInput,
+    UnexpectedOwner,
+    Aborted,
+    /// `monitor status` was asked for a root hash the state file does not know
+    NotMonitored(String),
 }
 
 impl std::fmt::Display for Error {
@@ -19,16 +205,337 @@ impl std::fmt::Display for Error {
         match self {
             Error::Io(err) => write!(f, "IO error: {}", err),
             Error::Serde(err) => write!(f, "Serde error: {}", err),
+            Error::Json5(json5::Error::Message {
+                msg,
+                location: Some(location),
+            }) => {
+                // the parser draws the input and puts what it expected on the last line
+                let expected = msg.rsplit("= ").next().unwrap_or(msg);
+                write!(
+                    f,
+                    "JSON5 error: {} at line {} column {}",
+                    expected.replace("EOI", "end of input"),
+                    location.line,
+                    location.column
+                )
+            }
+            Error::Json5(err) => write!(f, "JSON5 error: {}", err),
+            Error::Yaml(err) => write!(f, "YAML error: {}", err),
+            Error::Cbor(msg) => write!(f, "CBOR error: {}", msg),
+            Error::Decompression(msg) => write!(f, "Decompression error: {}", msg),
+            Error::InvalidUri(msg) => write!(f, "Invalid URI: {}", msg),
             Error::InvalidClaimContents => write!(f, "Invalid claim contents"),
+            Error::LimitExceeded {
+                which,
+                limit,
+                actual,
+            } => write!(
+                f,
+                "Limit exceeded: {} {}, more than the limit of {}",
+                actual, which, limit
+            ),
             Error::InvalidHex(err) => write!(f, "Invalid hex: {}", err),
             Error::InvalidRootHash => write!(f, "Invalid root hash"),
             Error::ConnectionError(err) => write!(f, "Connection error: {}", err),
             Error::InvalidDid => write!(f, "Invalid DID"),
+            Error::ForeignAddress {
+                address,
+                prefix,
+                kilt,
+            } => write!(
+                f,
+                "Invalid DID: {} has the ss58 prefix {} instead of {}, {}",
+                address,
+                prefix,
+                KILT_SS58_PREFIX,
+                other_network_hint(kilt)
+            ),
+            Error::InvalidDidUrl(msg) => write!(f, "Invalid DID URL: {}", msg),
+            Error::InvalidKeyId(msg) => write!(f, "Invalid key id: {}", msg),
+            Error::KeyNotFound {
+                key_id, available, ..
+            } if available.is_empty() => {
+                write!(f, "Key #{} not found, the DID has no keys", key_id)
+            }
+            Error::KeyNotFound {
+                key_id, available, ..
+            } => write!(
+                f,
+                "Key #{} not found, the DID has {}",
+                key_id,
+                available.join(", ")
+            ),
+            Error::KeyOfOtherDid { key_uri, owner } => write!(
+                f,
+                "The key {} is not one of the owner {}",
+                key_uri, owner
+            ),
+            Error::LightDidNotUpgraded(did) => write!(
+                f,
+                "The owner {} is a light DID that was not upgraded to a full DID, only full DIDs are looked up",
+                did
+            ),
+            Error::LightDidOwner(did) => write!(
+                f,
+                "The owner {} is a light DID, a full DID is required",
+                did
+            ),
             Error::DidNotFound => write!(f, "DID not found"),
             Error::InvalidSignature => write!(f, "Invalid signature"),
             Error::AttestationNotFound => write!(f, "Attestation not found"),
             Error::AttestationRevoked => write!(f, "Attestation revoked"),
+            Error::AttestationRemoved { last_seen_block } => write!(
+                f,
+                "Attestation removed, it was last seen at block #{}",
+                last_seen_block
+            ),
+            Error::AttestationTooRecent {
+                required,
+                confirmations: Some(confirmations),
+            } => write!(
+                f,
+                "Attestation too recent, it has {} of {} confirmations",
+                confirmations, required
+            ),
+            Error::AttestationTooRecent { required, .. } => write!(
+                f,
+                "Attestation too recent, it is not on chain {} blocks back",
+                required
+            ),
             Error::InvalidIssuer => write!(f, "Invalid issuer"),
+            Error::InvalidIssuerEntry(msg) => write!(f, "Invalid allowed issuer {}", msg),
+            Error::IssuerRegistry(msg) => write!(f, "Issuer registry {}", msg),
+            Error::Metrics(err) => write!(f, "Metrics error: {}", err),
+            Error::InvalidSeed => write!(f, "Invalid seed"),
+            Error::InvalidDidKey => write!(f, "Seed does not match the DID key"),
+            Error::BlockNotFound => write!(f, "Block not found"),
+            Error::TransactionFailed(err) => write!(f, "Transaction failed: {}", err),
+            Error::EventNotFound(event) => write!(f, "Event {} not found", event),
+            Error::PropertyNotFound(key) => write!(f, "Property {} not found in claim", key),
+            Error::InvalidChallenge => write!(f, "Invalid challenge"),
+            Error::InvalidCredentials(count) => write!(f, "{} credentials are invalid", count),
+            Error::InvalidSession { failed, total } => write!(
+                f,
+                "Session is invalid, {} of {} credentials failed",
+                failed, total
+            ),
+            Error::Webhook(msg) => write!(f, "Webhook error: {}", msg),
+            Error::Telemetry(msg) => write!(f, "Telemetry error: {}", msg),
+            Error::DidResolver(msg) => write!(f, "DID resolver error: {}", msg),
+            Error::Indexer(msg) => write!(f, "Indexer error: {}", msg),
+            Error::ChecksFailed(count) => write!(f, "{} doctor checks failed", count),
+            Error::LintFailed(count) => write!(f, "{} lint findings are errors", count),
+            Error::CtypeNames(msg) => write!(f, "Invalid ctype names: {}", msg),
+            Error::Redacted { message, .. } => write!(f, "{}", message),
+            Error::DeniedWarning(msg) => write!(f, "{} (warnings are denied)", msg),
+            Error::InsecureEndpoint(endpoint) => write!(
+                f,
+                "{} is not encrypted, give --allow-insecure to use it",
+                endpoint
+            ),
+            Error::QueryFailed { storage, attempts } => {
+                write!(f, "Query of {} failed after {} attempts: ", storage, attempts.len())?;
+                for (i, attempt) in attempts.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", attempt)?;
+                }
+                Ok(())
+            }
+            Error::Incomplete(count) => write!(
+                f,
+                "{} credentials could not be verified because of infrastructure errors",
+                count
+            ),
+            Error::RecheckFailed(count) => write!(
+                f,
+                "{} attestations could not be checked, run again with the same checkpoint to check them",
+                count
+            ),
+            Error::EndpointDisagreement { storage, endpoints } => write!(
+                f,
+                "Endpoints disagree on {}: {} answered differently",
+                storage,
+                endpoints.join(", ")
+            ),
+            Error::InvalidQuorum(reason) => write!(f, "Invalid quorum: {}", reason),
+            Error::AuditLog(msg) => write!(f, "Audit log error: {}", msg),
+            Error::InvalidReport(reason) => write!(f, "Invalid report: {}", reason),
+            Error::OwnerInconsistency(msg) => write!(f, "Credentials are inconsistent: {}", msg),
+            Error::InvalidArchive(reason) => write!(f, "Invalid archive: {}", reason),
+            Error::InvalidBundle(reason) => write!(f, "Invalid proof bundle: {}", reason),
+            Error::Publish(msg) => write!(f, "Publish error: {}", msg),
+            Error::PublishFailed { subject, failed } => {
+                write!(f, "{} results could not be published to {}", failed, subject)
+            }
+            Error::InvalidGlob(err) => write!(f, "Invalid glob: {}", err),
+            Error::InvalidManifest(reason) => write!(f, "Invalid manifest: {}", reason),
+            Error::NotArchiveNode(err) => write!(
+                f,
+                "Endpoint is not an archive node, the state of old blocks is not available: {}",
+                err
+            ),
+            Error::NetworkMismatch(reason) => write!(f, "Wrong network: {}", reason),
+            Error::TimestampOutOfRange(reason) => write!(f, "Timestamp out of range: {}", reason),
+            Error::Timeout => write!(f, "Timed out"),
+            Error::UnexpectedOwner => write!(f, "Claim is not owned by the expected DID"),
+            Error::Aborted => write!(f, "Aborted"),
+            Error::NotMonitored(root_hash) => {
+                write!(f, "No credential with root hash {} is monitored", root_hash)
+            }
+            Error::NoInput => write!(f, "No credential given, pipe it to stdin or use --file"),
+        }
+    }
+}
+
+impl Error {
+    /// A stable machine readable code of the error kind, part of the `--porcelain` output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::Serde(_) | Error::Json5(_) => "invalid_json",
+            Error::Yaml(_) => "invalid_yaml",
+            Error::Cbor(_) => "invalid_cbor",
+            Error::Decompression(_) => "invalid_compression",
+            Error::InvalidUri(_) => "invalid_uri",
+            Error::InvalidClaimContents => "invalid_claim_contents",
+            Error::LimitExceeded { .. } => "limit_exceeded",
+            Error::InvalidHex(_) => "invalid_hex",
+            Error::InvalidRootHash => "invalid_root_hash",
+            Error::ConnectionError(_) => "connection_error",
+            Error::InvalidDid => "invalid_did",
+            Error::ForeignAddress { .. } => "foreign_address",
+            Error::InvalidDidUrl(_) => "invalid_did_url",
+            Error::InvalidKeyId(_) => "invalid_key_id",
+            Error::KeyNotFound { .. } => "key_not_found",
+            Error::KeyOfOtherDid { .. } => "key_of_other_did",
+            Error::LightDidNotUpgraded(_) => "light_did_not_upgraded",
+            Error::LightDidOwner(_) => "light_did_owner",
+            Error::DidNotFound => "did_not_found",
+            Error::InvalidSignature => "invalid_signature",
+            Error::AttestationNotFound => "attestation_not_found",
+            Error::AttestationRevoked => "attestation_revoked",
+            Error::AttestationRemoved { .. } => "attestation_removed",
+            Error::AttestationTooRecent { .. } => "attestation_too_recent",
+            Error::InvalidIssuer => "invalid_issuer",
+            Error::InvalidIssuerEntry(_) => "invalid_issuer_entry",
+            Error::IssuerRegistry(_) => "issuer_registry",
+            Error::Metrics(_) => "metrics",
+            Error::InvalidSeed => "invalid_seed",
+            Error::InvalidDidKey => "invalid_did_key",
+            Error::BlockNotFound => "block_not_found",
+            Error::TransactionFailed(_) => "transaction_failed",
+            Error::EventNotFound(_) => "event_not_found",
+            Error::PropertyNotFound(_) => "property_not_found",
+            Error::InvalidChallenge => "invalid_challenge",
+            Error::InvalidCredentials(_) => "invalid_credentials",
+            Error::InvalidSession { .. } => "invalid_session",
+            Error::Webhook(_) => "webhook",
+            Error::Telemetry(_) => "telemetry",
+            Error::DidResolver(_) => "did_resolver",
+            Error::Indexer(_) => "indexer",
+            Error::RecheckFailed(_) => "recheck_failed",
+            Error::ChecksFailed(_) => "checks_failed",
+            Error::LintFailed(_) => "lint_failed",
+            Error::CtypeNames(_) => "ctype_names",
+            Error::Redacted { code, .. } => code,
+            Error::DeniedWarning(_) => "denied_warning",
+            Error::InsecureEndpoint(_) => "insecure_endpoint",
+            Error::Incomplete(_) => "incomplete",
+            Error::QueryFailed { .. } => "query_failed",
+            Error::EndpointDisagreement { .. } => "endpoint_disagreement",
+            Error::InvalidQuorum(_) => "invalid_quorum",
+            Error::AuditLog(_) => "audit_log",
+            Error::InvalidReport(_) => "invalid_report",
+            Error::OwnerInconsistency(_) => "owner_inconsistency",
+            Error::InvalidArchive(_) => "invalid_archive",
+            Error::InvalidBundle(_) => "invalid_bundle",
+            Error::Publish(_) => "publish",
+            Error::PublishFailed { .. } => "publish_failed",
+            Error::InvalidGlob(_) => "invalid_glob",
+            Error::InvalidManifest(_) => "invalid_manifest",
+            Error::NotArchiveNode(_) => "not_archive_node",
+            Error::NetworkMismatch(_) => "network_mismatch",
+            Error::TimestampOutOfRange(_) => "timestamp_out_of_range",
+            Error::Timeout => "timeout",
+            Error::NoInput => "no_input",
+            Error::UnexpectedOwner => "unexpected_owner",
+            Error::Aborted => "aborted",
+            Error::NotMonitored(_) => "not_monitored",
+        }
+    }
+
+    /// The error without the parts of its message that can quote the input, i.e. the claim values
+    /// a parser names. Where in the input the error is stays
+    pub fn without_pii(self) -> Self {
+        self.redacted().unwrap_or(self)
+    }
+
+    /// The `Redacted` error of an error whose message can quote the input, see `without_pii`
+    pub fn redacted(&self) -> Option<Self> {
+        let redacted = |message: String| {
+            Some(Error::Redacted {
+                code: self.code(),
+                message: format!("{}, the details are redacted", message),
+            })
+        };
+        match self {
+            Error::Serde(err) => redacted(format!(
+                "Serde error at line {} column {}",
+                err.line(),
+                err.column()
+            )),
+            Error::Json5(json5::Error::Message {
+                location: Some(location),
+                ..
+            }) => redacted(format!(
+                "JSON5 error at line {} column {}",
+                location.line, location.column
+            )),
+            Error::Json5(_) => redacted("JSON5 error".to_string()),
+            Error::Yaml(err) => match err.location() {
+                Some(location) => redacted(format!(
+                    "YAML error at line {} column {}",
+                    location.line(),
+                    location.column()
+                )),
+                None => redacted("YAML error".to_string()),
+            },
+            Error::Cbor(_) => redacted("CBOR error".to_string()),
+            Error::InvalidUri(_) => redacted("Invalid URI".to_string()),
+            _ => None,
+        }
+    }
+
+    /// The node or a service next to it, like the message queue, failed or could not be reached,
+    /// which says nothing about the credential
+    pub fn is_infrastructure(&self) -> bool {
+        matches!(
+            self,
+            Error::ConnectionError(_)
+                | Error::BlockNotFound
+                | Error::NotArchiveNode(_)
+                | Error::Incomplete(_)
+                | Error::QueryFailed { .. }
+                | Error::DidResolver(_)
+                | Error::Indexer(_)
+                | Error::PublishFailed { .. }
+        )
+    }
+
+    /// The exit code of a run that ended with this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Timeout => TIMEOUT_EXIT_CODE,
+            Error::NoInput | Error::InvalidQuorum(_) | Error::InsecureEndpoint(_) => {
+                USAGE_EXIT_CODE
+            }
+            Error::Aborted => ABORT_EXIT_CODE as u8,
+            Error::AttestationRemoved { .. } => REMOVED_EXIT_CODE,
+            Error::InvalidCredentials(_) | Error::InvalidSession { .. } => INVALID_EXIT_CODE,
+            err if err.is_infrastructure() => INFRASTRUCTURE_EXIT_CODE,
+            _ => 1,
         }
     }
 }
@@ -45,6 +552,18 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<json5::Error> for Error {
+    fn from(err: json5::Error) -> Self {
+        Error::Json5(err)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self {
+        Error::Yaml(err)
+    }
+}
+
 impl From<hex::FromHexError> for Error {
     fn from(err: hex::FromHexError) -> Self {
         Error::InvalidHex(err)
@@ -56,3 +575,35 @@ impl From<subxt::BasicError> for Error {
         Error::ConnectionError(err)
     }
 }
+
+impl From<prometheus::Error> for Error {
+    fn from(err: prometheus::Error) -> Self {
+        Error::Metrics(err)
+    }
+}
+
+impl From<subxt::Error<DispatchError>> for Error {
+    fn from(err: subxt::Error<DispatchError>) -> Self {
+        Error::TransactionFailed(Box::new(err))
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(err: zip::result::ZipError) -> Self {
+        Error::InvalidArchive(err.to_string())
+    }
+}
+
+impl From<globset::Error> for Error {
+    fn from(err: globset::Error) -> Self {
+        Error::InvalidGlob(err)
+    }
+}
+
+impl From<dialoguer::Error> for Error {
+    fn from(err: dialoguer::Error) -> Self {
+        match err {
+            dialoguer::Error::IO(err) => Error::Io(err),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Deterministic test fixtures generated from the well-known dev seeds.
+// Alice owns the credential and Bob attests it, both as full DIDs with a single sr25519 key.
+
+use lazy_static::lazy_static;
+use rand::{rngs::StdRng, SeedableRng};
+use serde_json::json;
+
+use crate::{
+    credential::{Claim, Credential},
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::did_details::{DidDetails, DidPublicKeyDetails},
+        frame_support::storage::{
+            bounded_btree_map::BoundedBTreeMap, bounded_btree_set::BoundedBTreeSet,
+        },
+        kilt_support::deposit::Deposit,
+    },
+    mock::{MockBackend, MockState},
+    utils::{get_did_account_id, hex_decode_h256, DidKeyPair, KeyType},
+};
+
+pub const OWNER_SEED: &str = "//Alice";
+pub const ATTESTER_SEED: &str = "//Bob";
+pub const CTYPE_HASH: &str = "0x3291bb126e33b4862d421bfaa1d2f272e6cdfc4f96658988fbcffea8914bd9ac";
+
+// Deriving keys from dev seeds is slow without optimizations, so it is only done once
+lazy_static! {
+    static ref OWNER_KEY: DidKeyPair = DidKeyPair::from_seed(OWNER_SEED, KeyType::Sr25519).unwrap();
+    static ref ATTESTER_KEY: DidKeyPair =
+        DidKeyPair::from_seed(ATTESTER_SEED, KeyType::Sr25519).unwrap();
+}
+
+pub fn owner_key() -> DidKeyPair {
+    OWNER_KEY.clone()
+}
+
+pub fn attester_key() -> DidKeyPair {
+    ATTESTER_KEY.clone()
+}
+
+/// The allowed issuers for the fixtures, which is just Bob
+pub fn attester_did() -> String {
+    attester_key().did()
+}
+
+/// An email credential of Alice with nonces from a fixed rng seed.
+/// It goes through json just like a credential file would.
+/// sr25519 signatures are randomized, so only the signature differs between calls.
+pub fn credential() -> Credential {
+    let owner_key = owner_key();
+    let claim = Claim {
+        ctype_hash: CTYPE_HASH.to_string(),
+        contents: json!({"Email": "alice@example.com", "Name": "Alice"}),
+        owner: owner_key.did(),
+        ..Default::default()
+    };
+    let credential = Credential::create(claim, &owner_key, &mut StdRng::seed_from_u64(0)).unwrap();
+    serde_json::from_str(&serde_json::to_string(&credential).unwrap()).unwrap()
+}
+
+/// A DID document with the key as authentication and attestation key
+pub fn did_details(key: &DidKeyPair) -> DidDetails {
+    let key_id = subxt::sp_core::H256(key.key_id());
+    DidDetails {
+        authentication_key: key_id,
+        key_agreement_keys: BoundedBTreeSet(vec![]),
+        delegation_key: None,
+        attestation_key: Some(key_id),
+        public_keys: BoundedBTreeMap(vec![(
+            key_id,
+            DidPublicKeyDetails {
+                key: key.public_key(),
+                block_number: 1,
+            },
+        )]),
+        last_tx_counter: 0,
+        deposit: Deposit {
+            owner: get_did_account_id(&key.did()).unwrap(),
+            amount: 2_000_000_000_000_000,
+        },
+    }
+}
+
+/// A DID document with the key as authentication key and another key as attestation key
+pub fn did_details_with_attestation_key(
+    key: &DidKeyPair,
+    attestation_key: &DidKeyPair,
+) -> DidDetails {
+    let mut details = did_details(key);
+    let key_id = subxt::sp_core::H256(attestation_key.key_id());
+    details.attestation_key = Some(key_id);
+    details.public_keys.0.push((
+        key_id,
+        DidPublicKeyDetails {
+            key: attestation_key.public_key(),
+            block_number: 1,
+        },
+    ));
+    details
+}
+
+/// An attestation of the fixture credential by Bob
+pub fn attestation_details(revoked: bool) -> AttestationDetails {
+    let attester = get_did_account_id(&attester_did()).unwrap();
+    AttestationDetails {
+        ctype_hash: hex_decode_h256(CTYPE_HASH).unwrap(),
+        attester: attester.clone(),
+        delegation_id: None,
+        revoked,
+        deposit: Deposit {
+            owner: attester,
+            amount: 120_950_000_000_000,
+        },
+    }
+}
+
+/// A backend with the DIDs of Alice and Bob and the attestation of the credential.
+/// Every fixture credential has the same root hash, so the attestation applies to all of them.
+pub fn backend() -> MockBackend {
+    backend_for(&credential())
+}
+
+/// The DIDs of Alice and Bob with an attestation by Bob for any other credential
+pub fn backend_for(credential: &Credential) -> MockBackend {
+    let mut backend = MockBackend::default();
+    for key in [owner_key(), attester_key()] {
+        backend.insert_did(get_did_account_id(&key.did()).unwrap(), &did_details(&key));
+    }
+    backend.insert_attestation(
+        hex_decode_h256(&credential.root_hash).unwrap(),
+        &attestation_details(false),
+    );
+    backend
+}
+
+/// The DIDs of Alice and Bob and the attestation of the credential as the state of a block
+pub fn state(revoked: bool) -> MockState {
+    let mut state = MockState::default();
+    for key in [owner_key(), attester_key()] {
+        state.insert_did(get_did_account_id(&key.did()).unwrap(), &did_details(&key));
+    }
+    state.insert_attestation(
+        hex_decode_h256(&credential().root_hash).unwrap(),
+        &attestation_details(revoked),
+    );
+    state
+}
@@ -0,0 +1,383 @@
+use async_trait::async_trait;
+use std::{collections::BTreeMap, sync::Mutex};
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::{AttestationHistory, ChainBackend, RemovedKey},
+    batch::BatchResult,
+    errors::Error,
+    history::BlockHistory,
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+    },
+    progress::CheckStep,
+    status::CheckReport,
+    utils::{DidUrl, Token},
+};
+
+/// Accepts signatures of keys that were removed from the DID document of the owner within the
+/// last `blocks` blocks, i.e. a key that was rotated right after the credential was signed. The
+/// DID document is read again at the start of the window and the first block without the key is
+/// bisected between it and the end of the window. Windows beyond the state a node keeps need an
+/// archive node.
+pub struct KeyGraceBackend<'a> {
+    inner: &'a dyn ChainBackend,
+    history: &'a dyn BlockHistory,
+    blocks: u32,
+    at: Option<u32>,
+    /// The removed keys looked up, by DID and key id
+    removed: Mutex<BTreeMap<(AccountId32, H256), u32>>,
+}
+
+impl<'a> KeyGraceBackend<'a> {
+    pub fn new(inner: &'a dyn ChainBackend, history: &'a dyn BlockHistory, blocks: u32) -> Self {
+        KeyGraceBackend {
+            inner,
+            history,
+            blocks,
+            at: None,
+            removed: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The window ends at the block with the number instead of the latest block, i.e. the block
+    /// of --valid-at
+    pub fn at(self, number: Option<u32>) -> Self {
+        KeyGraceBackend { at: number, ..self }
+    }
+
+    /// The first block the key of the key uri was missing at, if the signature it made was
+    /// accepted within the window, i.e. the signature check of the credential passed
+    pub fn removed_at(&self, key_uri: &str, checks: &CheckReport) -> Option<u32> {
+        if !checks.passed(CheckStep::Signature) {
+            return None;
+        }
+        let key_uri = DidUrl::parse(key_uri).ok()?;
+        let key = (key_uri.account_id().ok()?, H256(key_uri.key_id().ok()?.0));
+        self.removed
+            .lock()
+            .expect("removed keys are not poisoned")
+            .get(&key)
+            .copied()
+    }
+
+    /// Set the block the signing key was removed at of the results a removed key was accepted for
+    pub fn mark_removed_keys(&self, results: &mut [BatchResult]) {
+        for result in results {
+            result.key_removed_at = match (&result.key_uri, &result.checks) {
+                (Some(key_uri), Some(checks)) => self.removed_at(key_uri, checks),
+                _ => None,
+            };
+        }
+    }
+
+    // the DID document at the block with the number
+    async fn did_at(&self, did: &AccountId32, number: u32) -> Result<Option<DidDetails>, Error> {
+        let block = self
+            .history
+            .block_hash(number)
+            .await?
+            .ok_or(Error::BlockNotFound)?;
+        self.inner.did(did, Some(block)).await
+    }
+
+    async fn has_key(&self, did: &AccountId32, key_id: &H256, number: u32) -> Result<bool, Error> {
+        Ok(self
+            .did_at(did, number)
+            .await?
+            .is_some_and(|details| details.public_keys.0.iter().any(|(id, _)| id == key_id)))
+    }
+}
+
+#[async_trait]
+impl ChainBackend for KeyGraceBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.inner.did(did, at).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        self.inner.attestation(root_hash, at).await
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.inner.attestation_history(root_hash).await
+    }
+
+    async fn removed_key(
+        &self,
+        did: &AccountId32,
+        key_id: &H256,
+    ) -> Result<Option<RemovedKey>, Error> {
+        let end = match self.at {
+            Some(number) => number,
+            None => self.history.best_block().await?,
+        };
+        let start = end.saturating_sub(self.blocks);
+        let details = match self.did_at(did, start).await? {
+            Some(details) => details,
+            None => return Ok(None),
+        };
+        let details = match details
+            .public_keys
+            .0
+            .into_iter()
+            .find(|(id, _)| id == key_id)
+        {
+            Some((_, details)) => details,
+            None => return Ok(None),
+        };
+        // the key is in the document at `found` and was missing at the end of the window
+        let (mut found, mut missing) = (start, end);
+        while missing - found > 1 {
+            let mid = found + (missing - found) / 2;
+            if self.has_key(did, key_id, mid).await? {
+                found = mid;
+            } else {
+                missing = mid;
+            }
+        }
+        self.removed
+            .lock()
+            .expect("removed keys are not poisoned")
+            .insert((did.clone(), *key_id), missing);
+        Ok(Some(RemovedKey {
+            details,
+            removed_at: missing,
+        }))
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.web3_name_owner(name, at).await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.inner.web3_name(owner, at).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.inner.service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.inner.token().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        batch::{self, BatchInput, BatchOptions, OutputFormat},
+        credential::{ClaimRequirements, ClaimerSignature, Credential},
+        ctypes::CtypeNames,
+        fixtures,
+        mock::{MockBackend, MockState},
+        status::CheckRecorder,
+        utils::{get_did_account_id, hex_decode, hex_encode},
+    };
+    use tokio_util::sync::CancellationToken;
+
+    // ten blocks, the key of Alice is replaced by the one of Bob at block 7
+    struct Rotation {
+        chain: MockBackend,
+    }
+
+    #[async_trait]
+    impl BlockHistory for Rotation {
+        async fn best_block(&self) -> Result<u32, Error> {
+            Ok(10)
+        }
+
+        async fn finalized_block(&self) -> Result<u32, Error> {
+            Ok(10)
+        }
+
+        async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+            Ok((number <= 10).then(|| H256::from_low_u64_be(number as u64)))
+        }
+
+        async fn block_timestamp(&self, _: H256) -> Result<u64, Error> {
+            Ok(0)
+        }
+    }
+
+    fn rotation() -> Rotation {
+        let alice = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let rotated = |state: &mut MockState| {
+            state.insert_did(
+                alice.clone(),
+                &fixtures::did_details(&fixtures::attester_key()),
+            )
+        };
+        let mut chain = fixtures::backend();
+        rotated(chain.latest_mut());
+        for number in 0..=10 {
+            let mut state = fixtures::state(false);
+            if number >= 7 {
+                rotated(&mut state);
+            }
+            chain.insert_block(H256::from_low_u64_be(number), state);
+        }
+        Rotation { chain }
+    }
+
+    // the verification of the credential and the block its removed key was accepted at
+    async fn verify(
+        grace: &KeyGraceBackend<'_>,
+        cred: &Credential,
+    ) -> (Result<(), Error>, Option<u32>) {
+        let checks = CheckRecorder::default();
+        let attester = fixtures::attester_did();
+        let res = cred
+            .verify_observed(grace, &[&attester], None, &checks)
+            .await
+            .map(|_| ());
+        let report = checks.report(cred, None, &ClaimRequirements::default());
+        let removed_at = grace.removed_at(&cred.claimer_signature.key_uri, &report);
+        (res, removed_at)
+    }
+
+    // a credential of Alice signed with the key that replaced hers
+    fn signed_with_current_key() -> Credential {
+        let mut cred = fixtures::credential();
+        let key = fixtures::attester_key();
+        let signature = key.sign(&hex_decode(&cred.root_hash).unwrap());
+        cred.claimer_signature = ClaimerSignature {
+            signature: hex_encode(signature),
+            challenge: String::new(),
+            key_uri: format!("{}#{}", cred.claim.owner, hex_encode(key.key_id())),
+            legacy: false,
+        };
+        cred
+    }
+
+    #[tokio::test]
+    async fn test_key_grace() {
+        let rotation = rotation();
+        let cred = fixtures::credential();
+        let attester = fixtures::attester_did();
+        let res = cred.verify(&rotation.chain, &[&attester], None).await;
+        assert!(matches!(res, Err(Error::KeyNotFound { .. })), "{:?}", res);
+
+        // the key was removed 4 blocks ago
+        let grace = KeyGraceBackend::new(&rotation.chain, &rotation, 5);
+        let (res, removed_at) = verify(&grace, &cred).await;
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(removed_at, Some(7));
+
+        // the current key of the owner was not removed
+        let (res, removed_at) = verify(&grace, &signed_with_current_key()).await;
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(removed_at, None);
+
+        // a window that starts after the removal
+        let grace = KeyGraceBackend::new(&rotation.chain, &rotation, 3);
+        let (res, removed_at) = verify(&grace, &cred).await;
+        assert!(matches!(res, Err(Error::KeyNotFound { .. })), "{:?}", res);
+        assert_eq!(removed_at, None);
+
+        // the window of a verification at a block ends there
+        let grace = KeyGraceBackend::new(&rotation.chain, &rotation, 1).at(Some(7));
+        let (res, removed_at) = verify(&grace, &cred).await;
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(removed_at, Some(7));
+
+        // a forged signature is not accepted by the removed key either
+        let mut forged = cred.clone();
+        forged.claimer_signature.signature =
+            hex_encode(fixtures::attester_key().sign(&hex_decode(&cred.root_hash).unwrap()));
+        let grace = KeyGraceBackend::new(&rotation.chain, &rotation, 5);
+        let (res, removed_at) = verify(&grace, &forged).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+        assert_eq!(removed_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_key_grace_batch() {
+        let rotation = rotation();
+        let cred = fixtures::credential();
+        let mut forged = cred.clone();
+        forged.claimer_signature.signature =
+            hex_encode(fixtures::attester_key().sign(&hex_decode(&cred.root_hash).unwrap()));
+        let inputs: Vec<_> = [
+            ("removed", cred),
+            ("current", signed_with_current_key()),
+            ("forged", forged),
+        ]
+        .into_iter()
+        .map(|(source, cred)| BatchInput {
+            source: source.to_string(),
+            data: serde_json::to_vec(&cred).unwrap(),
+        })
+        .collect();
+
+        let attester = fixtures::attester_did();
+        let allowed_issuers = [attester.as_str()];
+        let requirements = ClaimRequirements::default();
+        let options = BatchOptions {
+            allowed_issuers: &allowed_issuers,
+            challenge: None,
+            requirements: &requirements,
+            concurrency: 1,
+            output: OutputFormat::Json,
+            quiet: true,
+            summary_only: false,
+            sort_by: None,
+            include_services: false,
+            redact_claims: false,
+            redact_pii: false,
+            parse: Default::default(),
+            require_same_owner: false,
+            require_same_key: false,
+            cancel: CancellationToken::new(),
+            ctype_names: &CtypeNames::builtin(),
+            report_version: batch::REPORT_VERSION,
+        };
+        let grace = KeyGraceBackend::new(&rotation.chain, &rotation, 5);
+        let mut report = batch::verify_batch(&grace, &inputs, &options).await;
+        grace.mark_removed_keys(&mut report.results);
+
+        // only the credential signed with the removed key is marked, all have the same owner
+        let marked: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| (r.source.as_str(), r.result.is_ok(), r.key_removed_at))
+            .collect();
+        assert_eq!(
+            marked,
+            vec![
+                ("removed", true, Some(7)),
+                ("current", true, None),
+                ("forged", false, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,615 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt;
+use subxt::sp_core::H256;
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    kilt::{runtime_types::attestation::attestations::AttestationDetails, KiltRuntimeApi},
+    utils::{hex_decode_h256, hex_encode},
+};
+
+/// The blocks of a chain and when they were produced, to find the block that was current at a
+/// point in time. Reading the timestamps of old blocks needs an archive node.
+#[async_trait]
+pub trait BlockHistory: Send + Sync {
+    /// Number of the latest block
+    async fn best_block(&self) -> Result<u32, Error>;
+
+    /// Number of the latest finalized block, blocks above it can still be replaced by a fork
+    async fn finalized_block(&self) -> Result<u32, Error>;
+
+    /// Hash of the block with the number, `None` if there is no such block yet
+    async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error>;
+
+    /// Time the block was produced at in milliseconds since the unix epoch, from the `timestamp.now` storage
+    async fn block_timestamp(&self, block: H256) -> Result<u64, Error>;
+}
+
+#[async_trait]
+impl BlockHistory for KiltRuntimeApi {
+    async fn best_block(&self) -> Result<u32, Error> {
+        let header = self.client.rpc().header(None).await?;
+        Ok(header.ok_or(Error::BlockNotFound)?.number)
+    }
+
+    async fn finalized_block(&self) -> Result<u32, Error> {
+        let hash = self.client.rpc().finalized_head().await?;
+        let header = self.client.rpc().header(Some(hash)).await?;
+        Ok(header.ok_or(Error::BlockNotFound)?.number)
+    }
+
+    async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+        Ok(self.client.rpc().block_hash(Some(number.into())).await?)
+    }
+
+    async fn block_timestamp(&self, block: H256) -> Result<u64, Error> {
+        Ok(self.storage().timestamp().now(Some(block)).await?)
+    }
+}
+
+/// A block that was found for a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedBlock {
+    pub number: u32,
+    pub hash: H256,
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl fmt::Display for ResolvedBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} ({}, produced at {})",
+            self.number,
+            hex_encode(self.hash),
+            format_millis(self.timestamp)
+        )
+    }
+}
+
+/// A timestamp in milliseconds since the unix epoch as RFC 3339 in UTC
+pub fn format_millis(millis: u64) -> String {
+    match DateTime::<Utc>::from_timestamp_millis(millis as i64) {
+        Some(time) => time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        None => format!("{}ms", millis),
+    }
+}
+
+/// The hash and timestamp of the block with the number
+pub async fn block_at(history: &dyn BlockHistory, number: u32) -> Result<ResolvedBlock, Error> {
+    let hash = history
+        .block_hash(number)
+        .await?
+        .ok_or(Error::BlockNotFound)?;
+    Ok(ResolvedBlock {
+        number,
+        hash,
+        timestamp: history.block_timestamp(hash).await?,
+    })
+}
+
+/// Find the block whose state was current at `time`, the last block produced at or before it.
+/// It is searched by bisection over the block timestamps, a few dozen lookups on chains with
+/// millions of blocks. Times after the latest block resolve to the latest block.
+pub async fn resolve_block(
+    history: &dyn BlockHistory,
+    time: DateTime<Utc>,
+) -> Result<ResolvedBlock, Error> {
+    // the genesis block has no timestamp, the first block has the earliest one
+    let first = match history.block_hash(1).await? {
+        Some(hash) => match history.block_timestamp(hash).await {
+            Ok(timestamp) => ResolvedBlock {
+                number: 1,
+                hash,
+                timestamp,
+            },
+            // nodes that prune their state only keep the state of recent blocks
+            Err(err) => return Err(Error::NotArchiveNode(err.to_string())),
+        },
+        None => return Err(Error::BlockNotFound),
+    };
+    let millis = u64::try_from(time.timestamp_millis()).unwrap_or(0);
+    if millis < first.timestamp {
+        return Err(Error::TimestampOutOfRange(format!(
+            "{} is before the first block {}",
+            time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            first
+        )));
+    }
+
+    let best = block_at(history, history.best_block().await?).await?;
+    if best.timestamp <= millis {
+        return Ok(best);
+    }
+    // the block at `low` is produced at or before the time, the block at `high` after it
+    let (mut low, mut high) = (first, best);
+    while high.number - low.number > 1 {
+        let mid = block_at(history, low.number + (high.number - low.number) / 2).await?;
+        if mid.timestamp <= millis {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// Number of past blocks probed for an attestation before it is taken as never existing
+pub const MAX_PROBES: usize = 32;
+
+// the attestation exists at the block and is in the state
+async fn attested_at(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &H256,
+    number: u32,
+    state: fn(&AttestationDetails) -> bool,
+) -> Result<bool, Error> {
+    let block = history
+        .block_hash(number)
+        .await?
+        .ok_or(Error::BlockNotFound)?;
+    Ok(backend
+        .attestation(root_hash, Some(block))
+        .await?
+        .is_some_and(|attestation| state(&attestation)))
+}
+
+/// The number of the last block an attestation that is not on chain anymore existed at.
+/// Past blocks are probed going back from the latest block in doubling steps, so attestations
+/// removed soon after they were created can be missed. Once a block with the attestation is
+/// found, the last one is bisected between it and the next probed block without it.
+pub async fn last_seen(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &H256,
+) -> Result<Option<u32>, Error> {
+    last_attested(history, backend, root_hash, |_| true).await
+}
+
+/// The number of the first block an attestation is on chain at, bisected between the chain
+/// start and the latest block. An attestation that was removed and created again is found at one
+/// of its creations. `None` if it is not on chain at the latest block
+pub async fn first_seen(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &H256,
+) -> Result<Option<u32>, Error> {
+    let best = history.best_block().await?;
+    if !attested_at(history, backend, root_hash, best, |_| true).await? {
+        return Ok(None);
+    }
+    let (mut missing, mut found) = (0, best);
+    while found - missing > 1 {
+        let mid = missing + (found - missing) / 2;
+        if attested_at(history, backend, root_hash, mid, |_| true).await? {
+            found = mid;
+        } else {
+            missing = mid;
+        }
+    }
+    Ok(Some(found))
+}
+
+/// The number of the last block a revoked attestation was not revoked at yet, probed like `last_seen`
+pub async fn last_unrevoked(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &H256,
+) -> Result<Option<u32>, Error> {
+    last_attested(history, backend, root_hash, |attestation| {
+        !attestation.revoked
+    })
+    .await
+}
+
+// the last block the attestation was in the state at, if it is not in it at the latest block
+async fn last_attested(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &H256,
+    state: fn(&AttestationDetails) -> bool,
+) -> Result<Option<u32>, Error> {
+    let best = history.best_block().await?;
+    let mut missing = best;
+    let mut step = 1;
+    for _ in 0..MAX_PROBES {
+        if missing <= 1 {
+            break;
+        }
+        let number = best.saturating_sub(step).max(1);
+        if attested_at(history, backend, root_hash, number, state).await? {
+            let mut found = number;
+            while missing - found > 1 {
+                let mid = found + (missing - found) / 2;
+                if attested_at(history, backend, root_hash, mid, state).await? {
+                    found = mid;
+                } else {
+                    missing = mid;
+                }
+            }
+            return Ok(Some(found));
+        }
+        missing = number;
+        step = step.saturating_mul(2);
+    }
+    Ok(None)
+}
+
+/// Fail with `AttestationTooRecent` if the attestation is not at least `min` blocks below the
+/// block with the number `block`, or the latest finalized block if there is none. Blocks above the
+/// finalized head do not count, a fork can still drop them. The block it was created in is taken
+/// from the backend if it knows it, i.e. an indexer, otherwise the attestation is looked up again
+/// at `block - min`
+pub async fn check_confirmations(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &H256,
+    block: Option<u32>,
+    min: u32,
+) -> Result<(), Error> {
+    if min == 0 {
+        return Ok(());
+    }
+    let block = match block {
+        Some(block) => block,
+        None => history.finalized_block().await?,
+    };
+    let too_recent = |confirmations| Error::AttestationTooRecent {
+        required: min,
+        confirmations,
+    };
+    if let Some(history) = backend.attestation_history(root_hash).await? {
+        let created = u32::try_from(history.created).unwrap_or(u32::MAX);
+        let confirmations = block.saturating_sub(created);
+        return match confirmations >= min {
+            true => Ok(()),
+            false => Err(too_recent(Some(confirmations))),
+        };
+    }
+    match block.checked_sub(min) {
+        Some(deep) if attested_at(history, backend, root_hash, deep, |_| true).await? => Ok(()),
+        _ => Err(too_recent(None)),
+    }
+}
+
+/// The error of an attestation that was not found: `AttestationRemoved` if it existed before,
+/// `AttestationNotFound` if it never did or the history cannot be read
+pub async fn explain_missing(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    root_hash: &str,
+) -> Error {
+    let probe = async { last_seen(history, backend, &hex_decode_h256(root_hash)?).await };
+    match probe.await {
+        Ok(Some(last_seen_block)) => Error::AttestationRemoved { last_seen_block },
+        Ok(None) => Error::AttestationNotFound,
+        Err(err) => {
+            eprintln!(
+                "Warning: cannot tell if the attestation of {} was removed: {}",
+                root_hash, err
+            );
+            Error::AttestationNotFound
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::{AttestationHistory, PinnedBackend},
+        fixtures,
+        kilt::runtime_types::{
+            attestation::attestations::AttestationDetails,
+            did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+        },
+        mock::MockBackend,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use subxt::sp_runtime::AccountId32;
+
+    // blocks every 12 seconds starting at `start`, the state of blocks before `pruned` is gone
+    struct MockHistory {
+        start: u64,
+        blocks: u32,
+        pruned: u32,
+        finalized: u32,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BlockHistory for MockHistory {
+        async fn best_block(&self) -> Result<u32, Error> {
+            Ok(self.blocks)
+        }
+
+        async fn finalized_block(&self) -> Result<u32, Error> {
+            Ok(self.finalized)
+        }
+
+        async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+            Ok((number <= self.blocks).then(|| H256::from_low_u64_be(number as u64)))
+        }
+
+        async fn block_timestamp(&self, block: H256) -> Result<u64, Error> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            let number = block.to_low_u64_be();
+            if number < self.pruned as u64 {
+                return Err(Error::BlockNotFound);
+            }
+            Ok(self.start + (number - 1) * 12_000)
+        }
+    }
+
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().into()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_block() {
+        let history = MockHistory {
+            // 2021-06-01T00:00:00Z
+            start: 1_622_505_600_000,
+            blocks: 1_000_000,
+            pruned: 0,
+            finalized: 1_000_000,
+            lookups: AtomicUsize::new(0),
+        };
+
+        let block = resolve_block(&history, time("2021-06-01T00:00:30Z"))
+            .await
+            .unwrap();
+        assert_eq!(block.number, 3);
+        assert_eq!(block.hash, H256::from_low_u64_be(3));
+        assert_eq!(format_millis(block.timestamp), "2021-06-01T00:00:24.000Z");
+        // any offset is the same point in time
+        let block = resolve_block(&history, time("2021-06-01T02:00:24+02:00"))
+            .await
+            .unwrap();
+        assert_eq!(block.number, 3);
+        assert!(history.lookups.load(Ordering::Relaxed) < 60);
+
+        let block = resolve_block(&history, time("2021-06-01T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(block.number, 1);
+        let block = resolve_block(&history, time("2030-01-01T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(block.number, 1_000_000);
+
+        let res = resolve_block(&history, time("2021-05-31T23:59:59Z")).await;
+        assert!(
+            matches!(&res, Err(Error::TimestampOutOfRange(msg)) if msg.contains("before the first block #1")),
+            "{:?}",
+            res
+        );
+
+        let pruned = MockHistory {
+            pruned: 999_744,
+            ..history
+        };
+        let res = resolve_block(&pruned, time("2021-06-01T00:00:30Z")).await;
+        assert!(matches!(res, Err(Error::NotArchiveNode(_))), "{:?}", res);
+    }
+
+    // a chain where the attestation existed from block `created` to block `removed` (exclusive),
+    // nothing else is on chain
+    struct RemovedAttestation {
+        history: MockHistory,
+        created: u64,
+        removed: u64,
+        /// The backend knows the block it was created in, like an indexer
+        indexed: bool,
+    }
+
+    #[async_trait]
+    impl BlockHistory for RemovedAttestation {
+        async fn best_block(&self) -> Result<u32, Error> {
+            self.history.best_block().await
+        }
+
+        async fn finalized_block(&self) -> Result<u32, Error> {
+            self.history.finalized_block().await
+        }
+
+        async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+            self.history.block_hash(number).await
+        }
+
+        async fn block_timestamp(&self, block: H256) -> Result<u64, Error> {
+            self.history.block_timestamp(block).await
+        }
+    }
+
+    #[async_trait]
+    impl ChainBackend for RemovedAttestation {
+        async fn did(&self, _: &AccountId32, _: Option<H256>) -> Result<Option<DidDetails>, Error> {
+            Ok(None)
+        }
+
+        async fn attestation(
+            &self,
+            _: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            self.history.lookups.fetch_add(1, Ordering::Relaxed);
+            let number = at.map_or(self.history.blocks as u64, |at| at.to_low_u64_be());
+            Ok((self.created..self.removed)
+                .contains(&number)
+                .then(|| fixtures::attestation_details(false)))
+        }
+
+        async fn attestation_history(&self, _: &H256) -> Result<Option<AttestationHistory>, Error> {
+            Ok(self.indexed.then_some(AttestationHistory {
+                created: self.created,
+                revoked: None,
+            }))
+        }
+
+        async fn web3_name_owner(
+            &self,
+            _: &str,
+            _: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            Ok(None)
+        }
+
+        async fn web3_name(
+            &self,
+            _: &AccountId32,
+            _: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            Ok(None)
+        }
+
+        async fn ctype_creator(
+            &self,
+            _: &H256,
+            _: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            Ok(None)
+        }
+
+        async fn service_endpoints(
+            &self,
+            _: &AccountId32,
+            _: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn removed_attestation(created: u64, removed: u64) -> RemovedAttestation {
+        RemovedAttestation {
+            history: MockHistory {
+                start: 0,
+                blocks: 1_000_000,
+                pruned: 0,
+                finalized: 999_998,
+                lookups: AtomicUsize::new(0),
+            },
+            created,
+            removed,
+            indexed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_missing() {
+        let root_hash = fixtures::credential().root_hash;
+
+        let chain = removed_attestation(200_000, 712_345);
+        let err = explain_missing(&chain, &chain, &root_hash).await;
+        assert!(
+            matches!(
+                err,
+                Error::AttestationRemoved {
+                    last_seen_block: 712_344
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.code(), "attestation_removed");
+        assert!(chain.history.lookups.load(Ordering::Relaxed) < 2 * MAX_PROBES);
+
+        // it was removed in the block after it was created
+        let chain = removed_attestation(999_999, 1_000_000);
+        let err = explain_missing(&chain, &chain, &root_hash).await;
+        assert!(
+            matches!(
+                err,
+                Error::AttestationRemoved {
+                    last_seen_block: 999_999
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let chain = removed_attestation(0, 0);
+        let err = explain_missing(&chain, &chain, &root_hash).await;
+        assert!(matches!(err, Error::AttestationNotFound), "{:?}", err);
+
+        let err = explain_missing(&chain, &chain, "0x12").await;
+        assert!(matches!(err, Error::AttestationNotFound), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_first_seen() {
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        for created in [1, 2, 200_000, 999_999, 1_000_000] {
+            let chain = removed_attestation(created, 2_000_000);
+            let first = first_seen(&chain, &chain, &root_hash).await.unwrap();
+            assert_eq!(first, Some(created as u32));
+            assert!(chain.history.lookups.load(Ordering::Relaxed) <= 22);
+        }
+        let chain = removed_attestation(200_000, 712_345);
+        assert_eq!(first_seen(&chain, &chain, &root_hash).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_check_confirmations() {
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        for indexed in [false, true] {
+            let chain = RemovedAttestation {
+                indexed,
+                ..removed_attestation(999_990, 2_000_000)
+            };
+            // the latest block is #1,000,000 but only #999,998 is finalized, the attestation has
+            // 8 confirmations there
+            for min in [0, 1, 8] {
+                let res = check_confirmations(&chain, &chain, &root_hash, None, min).await;
+                assert!(res.is_ok(), "{} {}: {:?}", indexed, min, res);
+            }
+            let err = check_confirmations(&chain, &chain, &root_hash, None, 9)
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), "attestation_too_recent");
+            let confirmations = indexed.then_some(8);
+            assert!(
+                matches!(err, Error::AttestationTooRecent { required: 9, confirmations: c } if c == confirmations),
+                "{:?}",
+                err
+            );
+            // the block of --valid-at or of a quorum counts from there, finalized or not
+            let best = chain.best_block().await.unwrap();
+            let res = check_confirmations(&chain, &chain, &root_hash, Some(best), 10).await;
+            assert!(res.is_ok(), "{:?}", res);
+            let res = check_confirmations(&chain, &chain, &root_hash, Some(999_995), 5).await;
+            assert!(res.is_ok(), "{:?}", res);
+            let res = check_confirmations(&chain, &chain, &root_hash, Some(999_995), 6).await;
+            assert!(matches!(res, Err(Error::AttestationTooRecent { .. })));
+            // deeper than the chain
+            let res = check_confirmations(&chain, &chain, &root_hash, Some(best), best + 1).await;
+            assert!(matches!(res, Err(Error::AttestationTooRecent { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pinned_verification() {
+        // the attestation is revoked now, but was not at the historical block
+        let backend = MockBackend::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/scenarios/historical.json"
+        ))
+        .unwrap();
+        let credential = fixtures::credential();
+        let attester = fixtures::attester_did();
+
+        let res = credential.verify(&backend, &[&attester], None).await;
+        assert!(matches!(res, Err(Error::AttestationRevoked)), "{:?}", res);
+        let pinned = PinnedBackend::new(&backend, H256([2; 32]));
+        let res = credential.verify(&pinned, &[&attester], None).await;
+        assert!(res.is_ok(), "{:?}", res);
+
+        let pinned = PinnedBackend::new(&backend, H256([3; 32]));
+        let res = credential.verify(&pinned, &[&attester], None).await;
+        assert!(matches!(res, Err(Error::BlockNotFound)), "{:?}", res);
+    }
+}
@@ -0,0 +1,193 @@
+use clap::{Args, Subcommand};
+
+use crate::{
+    backend::ChainBackend,
+    credential::{self, Claim, Credential, StatementKeys},
+    errors::Error,
+    kilt::connect,
+    utils::{
+        get_did_account_id, parse_credential, read_credential, read_seed, DidKeyPair, KeyType,
+    },
+};
+
+/// Create credentials locally
+#[derive(Args, Debug)]
+pub struct CredentialArgs {
+    #[clap(subcommand)]
+    command: CredentialCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CredentialCommand {
+    /// Create a credential for a claim and sign it with the owner key
+    Create(CreateArgs),
+    /// Hash the claim of a credential file again with fresh nonces, i.e. after editing its contents
+    Rehash(RehashArgs),
+}
+
+#[derive(Args, Debug)]
+struct CreateArgs {
+    /// Hash of the ctype of the claim
+    #[clap(long, value_parser)]
+    ctype: String,
+
+    /// Contents of the claim as json object, i.e. '{"Email":"a@b.c"}'
+    #[clap(long, value_parser)]
+    claim: String,
+
+    /// Seed of the owner's authentication key (mnemonic, hex seed or dev seed like //Alice)
+    #[clap(long, value_parser, required_unless_present = "owner-seed-file")]
+    owner_seed: Option<String>,
+
+    /// File containing the seed of the owner's authentication key
+    #[clap(long, value_parser)]
+    owner_seed_file: Option<String>,
+
+    /// Type of the owner's authentication key
+    #[clap(long, value_enum, default_value_t = KeyType::Sr25519)]
+    key_type: KeyType,
+
+    /// File to write the credential to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+}
+
+#[derive(Args, Debug)]
+struct RehashArgs {
+    /// Credential file to rewrite in place
+    #[clap(short, long, value_parser)]
+    file: String,
+}
+
+/// Create presentations of credentials
+#[derive(Args, Debug)]
+pub struct PresentationArgs {
+    #[clap(subcommand)]
+    command: PresentationCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum PresentationCommand {
+    /// Create a presentation that discloses selected properties and is signed for a challenge
+    Create(PresentArgs),
+}
+
+#[derive(Args, Debug)]
+struct PresentArgs {
+    /// File containing the credential to present
+    #[clap(short, long, value_parser, default_value = "stdin")]
+    file: String,
+
+    /// Property of the claim to disclose, can be given multiple times
+    #[clap(long, value_parser)]
+    reveal: Vec<String>,
+
+    /// Challenge of the verifier to sign together with the root hash
+    #[clap(long, value_parser)]
+    challenge: String,
+
+    /// Seed of the owner's authentication key (mnemonic, hex seed or dev seed like //Alice)
+    #[clap(long, value_parser, required_unless_present = "owner-seed-file")]
+    owner_seed: Option<String>,
+
+    /// File containing the seed of the owner's authentication key
+    #[clap(long, value_parser)]
+    owner_seed_file: Option<String>,
+
+    /// Type of the owner's authentication key
+    #[clap(long, value_enum, default_value_t = KeyType::Sr25519)]
+    key_type: KeyType,
+
+    /// Don't check on chain that the key is the owner's current authentication key
+    #[clap(long, value_parser, default_value_t = false)]
+    no_chain_check: bool,
+
+    /// File to write the presentation to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+}
+
+/// Run the credential subcommands, these work without a chain connection. The claims are
+/// normalized with the statement keys
+pub fn credential(args: &CredentialArgs, statement_keys: &StatementKeys) -> Result<(), Error> {
+    match &args.command {
+        CredentialCommand::Create(args) => {
+            let seed = read_seed(args.owner_seed.as_deref(), args.owner_seed_file.as_deref())?;
+            let owner_key = DidKeyPair::from_seed(&seed, args.key_type)?;
+            let claim = Claim {
+                ctype_hash: args.ctype.clone(),
+                contents: serde_json::from_str(&args.claim)?,
+                owner: owner_key.did(),
+                statement_keys: statement_keys.clone(),
+            };
+
+            let cred = Credential::create(claim, &owner_key, &mut rand::thread_rng())?;
+            write_credential(&cred, &args.output)
+        }
+        CredentialCommand::Rehash(args) => rehash_file(&args.file, statement_keys),
+    }
+}
+
+// replace the claim hashes, nonces and root hash of a credential file, other fields are kept
+fn rehash_file(file: &str, statement_keys: &StatementKeys) -> Result<(), Error> {
+    let data = std::fs::read(file)?;
+    let mut cred = parse_credential(&data)?;
+    cred.claim.statement_keys = statement_keys.clone();
+    let (claim_hashes, claim_nonce_map, root_hash) =
+        credential::rehash(&cred.claim, &mut rand::thread_rng())?;
+    let mut json: serde_json::Value = serde_json::from_slice(&data)?;
+    json["claimHashes"] = serde_json::to_value(claim_hashes)?;
+    json["claimNonceMap"] = serde_json::to_value(claim_nonce_map)?;
+    json["rootHash"] = serde_json::Value::String(root_hash);
+    std::fs::write(file, serde_json::to_string_pretty(&json)?)?;
+    eprintln!(
+        "Warning: the claimer signature of {} is of the old root hash and no longer valid, \
+         the owner has to sign the credential again",
+        file
+    );
+    Ok(())
+}
+
+/// Run the presentation subcommands, the claims are normalized with the statement keys
+pub async fn presentation(
+    args: &PresentationArgs,
+    endpoint: &str,
+    statement_keys: &StatementKeys,
+) -> Result<(), Error> {
+    match &args.command {
+        PresentationCommand::Create(args) => {
+            let mut cred = read_credential(&args.file)?;
+            cred.claim.statement_keys = statement_keys.clone();
+            let seed = read_seed(args.owner_seed.as_deref(), args.owner_seed_file.as_deref())?;
+            let owner_key = DidKeyPair::from_seed(&seed, args.key_type)?;
+
+            // A verifier would reject a signature of a key that is no longer
+            // the authentication key of the owner, so we fail early
+            if !args.no_chain_check {
+                let cli = connect(endpoint).await?;
+                let did_doc = cli
+                    .did(&get_did_account_id(&cred.claim.owner)?, None)
+                    .await?
+                    .ok_or(Error::DidNotFound)?;
+                if did_doc.authentication_key.0 != owner_key.key_id() {
+                    return Err(Error::InvalidDidKey);
+                }
+            }
+
+            let presentation =
+                cred.create_presentation(&args.reveal, &args.challenge, &owner_key)?;
+            write_credential(&presentation, &args.output)
+        }
+    }
+}
+
+// write a credential as pretty printed json to a file or stdout
+fn write_credential(cred: &Credential, output: &str) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(cred)?;
+    if output == "stdout" {
+        println!("{}", json);
+    } else {
+        std::fs::write(output, json)?;
+    }
+    Ok(())
+}
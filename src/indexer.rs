@@ -0,0 +1,465 @@
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use sp_core::crypto::Ss58Codec;
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::{AttestationHistory, ChainBackend},
+    errors::Error,
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+        kilt_support::deposit::Deposit,
+    },
+    utils::{get_did_account_id, hex_decode_h256, hex_encode, Token},
+    webhook::{Webhook, TIMEOUT},
+};
+
+/// Where attestations are read from
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyAgainst {
+    Chain,
+    Indexer,
+    /// Read from both and fail if they disagree
+    Both,
+}
+
+const ATTESTATION_QUERY: &str = "query Attestation($claimHash: String!) { \
+    attestations(filter: {claimHash: {equalTo: $claimHash}}, first: 1) { nodes { \
+    claimHash cTypeId issuerId payer delegationID valid \
+    creationBlock { id } revocationBlock { id } removalBlock { id } } } }";
+
+const BLOCK_QUERY: &str = "query Block($hash: String!) { \
+    blocks(filter: {hash: {equalTo: $hash}}, first: 1) { nodes { id } } }";
+
+/// Reads attestations from the GraphQL api of a chain indexer, i.e. the KILT SubQuery indexer,
+/// which also knows the blocks they were created and revoked in. Everything else, DID documents
+/// in particular, is read from the chain. With `VerifyAgainst::Both` attestations are read from
+/// the chain as well and have to be the same.
+pub struct IndexerBackend<'a> {
+    chain: &'a dyn ChainBackend,
+    url: String,
+    indexer: Webhook,
+    against: VerifyAgainst,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Nodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Attestations {
+    attestations: Nodes<IndexedAttestation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Blocks {
+    blocks: Nodes<BlockRef>,
+}
+
+/// An attestation as the indexer has it, the blocks are referenced by their number
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedAttestation {
+    /// i.e. "kilt:ctype:0x12..."
+    c_type_id: String,
+    /// DID of the attester
+    issuer_id: String,
+    payer: String,
+    #[serde(rename = "delegationID")]
+    delegation_id: Option<String>,
+    valid: bool,
+    creation_block: BlockRef,
+    revocation_block: Option<BlockRef>,
+    removal_block: Option<BlockRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockRef {
+    id: String,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::Indexer(msg)
+}
+
+impl BlockRef {
+    fn number(&self) -> Result<u64, Error> {
+        self.id
+            .parse()
+            .map_err(|_| invalid(format!("{} is not a block number", self.id)))
+    }
+}
+
+impl IndexedAttestation {
+    fn history(&self) -> Result<AttestationHistory, Error> {
+        Ok(AttestationHistory {
+            created: self.creation_block.number()?,
+            revoked: self
+                .revocation_block
+                .as_ref()
+                .map(BlockRef::number)
+                .transpose()?,
+        })
+    }
+
+    /// The attestation as it was stored at the block, `None` before it was created and after it
+    /// was removed. The indexer does not know the amount of the deposit.
+    fn to_details(&self, block: Option<u64>) -> Result<Option<AttestationDetails>, Error> {
+        let history = self.history()?;
+        let removed = self
+            .removal_block
+            .as_ref()
+            .map(BlockRef::number)
+            .transpose()?;
+        let block = block.unwrap_or(u64::MAX);
+        if history.created > block || removed.is_some_and(|removed| removed <= block) {
+            return Ok(None);
+        }
+        let revoked = match history.revoked {
+            Some(revoked) => revoked <= block,
+            // an attestation can only be revoked in a block, unless the indexer missed it
+            None => !self.valid,
+        };
+        let ctype_hash = self
+            .c_type_id
+            .strip_prefix("kilt:ctype:")
+            .unwrap_or(&self.c_type_id);
+        Ok(Some(AttestationDetails {
+            ctype_hash: hex_decode_h256(ctype_hash)
+                .map_err(|_| invalid(format!("{} is not a ctype", self.c_type_id)))?,
+            attester: get_did_account_id(&self.issuer_id)
+                .map_err(|_| invalid(format!("{} is not a DID", self.issuer_id)))?,
+            delegation_id: self
+                .delegation_id
+                .as_ref()
+                .map(|id| {
+                    hex_decode_h256(id)
+                        .map_err(|_| invalid(format!("{} is not a delegation id", id)))
+                })
+                .transpose()?,
+            revoked,
+            deposit: Deposit {
+                owner: AccountId32::from_ss58check(&self.payer)
+                    .map_err(|_| invalid(format!("{} is not an address", self.payer)))?,
+                amount: 0,
+            },
+        }))
+    }
+}
+
+// the deposit is left out, the indexer does not know its amount
+fn same(chain: &Option<AttestationDetails>, indexer: &Option<AttestationDetails>) -> bool {
+    match (chain, indexer) {
+        (Some(chain), Some(indexer)) => {
+            chain.ctype_hash == indexer.ctype_hash
+                && chain.attester == indexer.attester
+                && chain.delegation_id == indexer.delegation_id
+                && chain.revoked == indexer.revoked
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl<'a> IndexerBackend<'a> {
+    /// An indexer at a plain `http://` GraphQL endpoint, i.e. `http://localhost:3000/graphql`
+    pub fn new(
+        chain: &'a dyn ChainBackend,
+        url: &str,
+        against: VerifyAgainst,
+    ) -> Result<Self, Error> {
+        let indexer = Webhook::parse(url).map_err(|err| match err {
+            Error::Webhook(msg) => Error::Indexer(msg),
+            err => err,
+        })?;
+        Ok(IndexerBackend {
+            chain,
+            url: url.to_string(),
+            indexer,
+            against,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // POST a GraphQL query and return its data
+    async fn query<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<T, Error> {
+        let body = json!({"query": query, "variables": variables}).to_string();
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            self.indexer.path,
+            self.indexer.host,
+            body.len(),
+            body
+        );
+        let (status, body) =
+            tokio::time::timeout(TIMEOUT, self.indexer.exchange(request.as_bytes()))
+                .await
+                .map_err(|_| invalid(format!("{} did not answer in time", self.indexer.host)))?
+                .map_err(|err| invalid(format!("cannot reach {}: {}", self.indexer.host, err)))?;
+        if !status
+            .split_whitespace()
+            .nth(1)
+            .is_some_and(|code| code.starts_with('2'))
+        {
+            return Err(invalid(format!(
+                "{} answered {:?}",
+                self.indexer.host, status
+            )));
+        }
+        let response: Response<T> = serde_json::from_slice(&body)
+            .map_err(|err| invalid(format!("unexpected answer: {}", err)))?;
+        if let Some(err) = response.errors.first() {
+            return Err(invalid(err.message.clone()));
+        }
+        response
+            .data
+            .ok_or_else(|| invalid("the answer has no data".to_string()))
+    }
+
+    async fn indexed(&self, root_hash: &H256) -> Result<Option<IndexedAttestation>, Error> {
+        let data: Attestations = self
+            .query(
+                ATTESTATION_QUERY,
+                json!({"claimHash": hex_encode(root_hash)}),
+            )
+            .await?;
+        Ok(data.attestations.nodes.into_iter().next())
+    }
+
+    // the number of the block with the hash, the indexer has to have indexed it
+    async fn block_number(&self, hash: &H256) -> Result<u64, Error> {
+        let data: Blocks = self
+            .query(BLOCK_QUERY, json!({"hash": hex_encode(hash)}))
+            .await?;
+        match data.blocks.nodes.first() {
+            Some(block) => block.number(),
+            None => Err(invalid(format!(
+                "block {} is not indexed",
+                hex_encode(hash)
+            ))),
+        }
+    }
+
+    async fn indexed_details(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        let block = match at {
+            Some(hash) => Some(self.block_number(&hash).await?),
+            None => None,
+        };
+        match self.indexed(root_hash).await? {
+            Some(attestation) => attestation.to_details(block),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainBackend for IndexerBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.chain.did(did, at).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        match self.against {
+            VerifyAgainst::Chain => self.chain.attestation(root_hash, at).await,
+            VerifyAgainst::Indexer => self.indexed_details(root_hash, at).await,
+            VerifyAgainst::Both => {
+                let (chain, indexer) = futures::join!(
+                    self.chain.attestation(root_hash, at),
+                    self.indexed_details(root_hash, at)
+                );
+                let chain = chain?;
+                if !same(&chain, &indexer?) {
+                    return Err(Error::EndpointDisagreement {
+                        storage: "attestation.attestations",
+                        endpoints: vec![self.url.clone()],
+                    });
+                }
+                Ok(chain)
+            }
+        }
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.indexed(root_hash)
+            .await?
+            .map(|attestation| attestation.history())
+            .transpose()
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.chain.web3_name_owner(name, at).await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.chain.web3_name(owner, at).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.chain.ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.chain.service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.chain.token().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    // the attestation of the fixture credential as the indexer has it
+    fn indexed(revocation_block: Option<&str>) -> serde_json::Value {
+        json!({
+            "claimHash": fixtures::credential().root_hash,
+            "cTypeId": format!("kilt:ctype:{}", fixtures::CTYPE_HASH),
+            "issuerId": fixtures::attester_did(),
+            "payer": fixtures::attester_did().trim_start_matches("did:kilt:"),
+            "delegationID": null,
+            "valid": revocation_block.is_none(),
+            "creationBlock": {"id": "5"},
+            "revocationBlock": revocation_block.map(|id| json!({"id": id})),
+            "removalBlock": null,
+        })
+    }
+
+    // an indexer that has the attestation and block 3 for any block hash
+    async fn serve(attestation: serde_json::Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).into_owned();
+                assert!(
+                    request.starts_with("POST /graphql HTTP/1.0\r\n"),
+                    "{}",
+                    request
+                );
+                let data = if request.contains("blocks(") {
+                    json!({"blocks": {"nodes": [{"id": "3"}]}})
+                } else {
+                    json!({"attestations": {"nodes": [attestation]}})
+                };
+                let body = json!({ "data": data }).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_indexer_backend() {
+        let cred = fixtures::credential();
+        let attester = fixtures::attester_did();
+        let root_hash = hex_decode_h256(&cred.root_hash).unwrap();
+        let chain = fixtures::backend();
+        let url = serve(indexed(None)).await;
+
+        for against in [VerifyAgainst::Indexer, VerifyAgainst::Both] {
+            let indexer = IndexerBackend::new(&chain, &url, against).unwrap();
+            let res = cred.verify(&indexer, &[&attester], None).await;
+            assert!(res.is_ok(), "{:?}: {:?}", against, res);
+        }
+        let indexer = IndexerBackend::new(&chain, &url, VerifyAgainst::Indexer).unwrap();
+        assert_eq!(
+            indexer.attestation_history(&root_hash).await.unwrap(),
+            Some(AttestationHistory {
+                created: 5,
+                revoked: None
+            })
+        );
+        // block 3 is before the attestation was created
+        let res = indexer.attestation(&root_hash, Some(H256::zero())).await;
+        assert!(res.unwrap().is_none());
+
+        let url = serve(indexed(Some("7"))).await;
+        let indexer = IndexerBackend::new(&chain, &url, VerifyAgainst::Indexer).unwrap();
+        let res = cred.verify(&indexer, &[&attester], None).await;
+        assert!(matches!(res, Err(Error::AttestationRevoked)), "{:?}", res);
+        assert_eq!(
+            indexer.attestation_history(&root_hash).await.unwrap(),
+            Some(AttestationHistory {
+                created: 5,
+                revoked: Some(7)
+            })
+        );
+        // the chain has the attestation unrevoked
+        let both = IndexerBackend::new(&chain, &url, VerifyAgainst::Both).unwrap();
+        let res = cred.verify(&both, &[&attester], None).await;
+        assert!(
+            matches!(&res, Err(Error::EndpointDisagreement { storage: "attestation.attestations", endpoints }) if *endpoints == [url.clone()]),
+            "{:?}",
+            res
+        );
+
+        assert!(matches!(
+            IndexerBackend::new(&chain, "https://indexer.example.com", VerifyAgainst::Both),
+            Err(Error::Indexer(_))
+        ));
+    }
+}
@@ -0,0 +1,372 @@
+use clap::{Args, Subcommand};
+use codec::Encode;
+use subxt::{
+    sp_core::{
+        crypto::{Ss58AddressFormat, Ss58Codec},
+        sr25519, Pair,
+    },
+    sp_runtime::AccountId32,
+    Event,
+};
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    kilt::{
+        attestation::events::{AttestationCreated, AttestationRemoved, AttestationRevoked},
+        runtime_types::attestation::attestations::AttestationDetails,
+        runtime_types::{
+            attestation::pallet::Call as AttestationCall,
+            did::did_details::{DidPublicKey::PublicVerificationKey, DidVerificationKey},
+            spiritnet_runtime::Call,
+        },
+        submit_did_call, KiltRuntimeApi, KiltSigner,
+    },
+    submission::{describe_submission, find_submission},
+    utils::{
+        get_did_account_id, hex_decode_h256, hex_encode, kilt_address, read_credential, read_seed,
+        sr25519_pair,
+    },
+};
+
+/// Arguments identifying the attester DID and the keys used to sign and pay
+#[derive(Args, Debug)]
+pub struct SignerArgs {
+    /// DID of the attester
+    #[clap(long, value_parser)]
+    did: String,
+
+    /// Seed of the attester's attestation key (mnemonic, hex seed or dev seed like //Alice)
+    #[clap(long, value_parser, required_unless_present_any = &["seed-file", "dry-run"])]
+    seed: Option<String>,
+
+    /// File containing the seed of the attester's attestation key
+    #[clap(long, value_parser)]
+    seed_file: Option<String>,
+
+    /// Seed of the account paying the transaction fees, defaults to the attester seed
+    #[clap(long, value_parser)]
+    payment_seed: Option<String>,
+
+    /// Wait until the extrinsic is finalized instead of only included in a block
+    #[clap(long, value_parser, default_value_t = false)]
+    wait_finalized: bool,
+
+    /// Only print the encoded call without signing or submitting anything
+    #[clap(long, value_parser, default_value_t = false)]
+    dry_run: bool,
+}
+
+/// Create an attestation for a credential on chain
+#[derive(Args, Debug)]
+pub struct AttestArgs {
+    /// File containing the credential to attest
+    #[clap(short, long, value_parser, required_unless_present = "root-hash")]
+    file: Option<String>,
+
+    /// Root hash of the credential to attest, instead of reading it from a file
+    #[clap(long, value_parser, conflicts_with = "file", requires = "ctype-hash")]
+    root_hash: Option<String>,
+
+    /// Hash of the ctype of the credential, required together with --root-hash
+    #[clap(long, value_parser, conflicts_with = "file")]
+    ctype_hash: Option<String>,
+
+    #[clap(flatten)]
+    signer: SignerArgs,
+}
+
+/// Manage existing attestations on chain
+#[derive(Args, Debug)]
+pub struct AttestationArgs {
+    #[clap(subcommand)]
+    command: AttestationCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AttestationCommand {
+    /// Print an attestation with its status and who holds its deposit
+    Show(ShowArgs),
+    /// Revoke an attestation, it stays on chain but is no longer valid
+    Revoke(RootHashArgs),
+    /// Remove an attestation from chain and free its deposit
+    Remove(RootHashArgs),
+}
+
+#[derive(Args, Debug)]
+struct ShowArgs {
+    /// Root hash of the attested credential
+    #[clap(value_parser)]
+    root_hash: String,
+
+    /// Also find the extrinsic that created the attestation, the account that submitted it and the
+    /// fee it paid. Needs an archive node for old attestations
+    #[clap(long, value_parser, default_value_t = false)]
+    submitter: bool,
+}
+
+#[derive(Args, Debug)]
+struct RootHashArgs {
+    /// Root hash of the attested credential
+    #[clap(value_parser)]
+    root_hash: String,
+
+    /// Maximum number of delegation nodes to check when revoking as a delegator
+    #[clap(long, value_parser, default_value_t = 0)]
+    max_parent_checks: u32,
+
+    #[clap(flatten)]
+    signer: SignerArgs,
+}
+
+/// The attester DID together with its attestation key and the paying account
+struct Attester {
+    did: AccountId32,
+    key: sr25519::Pair,
+    payer: KiltSigner,
+}
+
+impl SignerArgs {
+    /// Load the keys and make sure the seed belongs to the attestation key of the DID
+    /// before paying for anything
+    async fn attester(&self, cli: &KiltRuntimeApi) -> Result<Attester, Error> {
+        let did = get_did_account_id(&self.did)?;
+        let seed = read_seed(self.seed.as_deref(), self.seed_file.as_deref())?;
+        let key = sr25519_pair(&seed)?;
+        let payer = KiltSigner::new(match &self.payment_seed {
+            Some(payment_seed) => sr25519_pair(payment_seed)?,
+            None => key.clone(),
+        });
+
+        let did_doc = cli.did(&did, None).await?.ok_or(Error::DidNotFound)?;
+        let attestation_key = did_doc.attestation_key.ok_or(Error::InvalidDidKey)?;
+        let details = &did_doc
+            .public_keys
+            .0
+            .iter()
+            .find(|(key, _)| key.0 == attestation_key.0)
+            .ok_or(Error::InvalidDidKey)?
+            .1;
+
+        match &details.key {
+            PublicVerificationKey(DidVerificationKey::Sr25519(public))
+                if public.0 == key.public().0 =>
+            {
+                Ok(Attester { did, key, payer })
+            }
+            _ => Err(Error::InvalidDidKey),
+        }
+    }
+}
+
+/// Submit an `attestation.add` call on behalf of the attester DID
+pub async fn attest(cli: &KiltRuntimeApi, args: &AttestArgs) -> Result<(), Error> {
+    // Get the root hash and ctype hash either from the credential or from the args
+    let (root_hash, ctype_hash) = match &args.file {
+        Some(file) => {
+            let cred = read_credential(file)?;
+            (cred.root_hash, cred.claim.ctype_hash)
+        }
+        None => (
+            args.root_hash.clone().ok_or(Error::InvalidRootHash)?,
+            args.ctype_hash.clone().ok_or(Error::InvalidRootHash)?,
+        ),
+    };
+
+    let call = Call::Attestation(AttestationCall::add {
+        claim_hash: hex_decode_h256(&root_hash)?,
+        ctype_hash: hex_decode_h256(&ctype_hash)?,
+        delegation_id: None,
+    });
+
+    submit::<AttestationCreated>(cli, &args.signer, call).await
+}
+
+/// Who holds the deposit of an attestation and how much it is.
+/// The deposit usually belongs to the account of the attester DID, anything else is pointed out.
+pub async fn describe_deposit(
+    backend: &dyn ChainBackend,
+    attestation: &AttestationDetails,
+) -> Result<String, Error> {
+    let token = backend.token().await?;
+    let deposit = &attestation.deposit;
+    let mut text = format!(
+        "  deposit:     {} held by {}\n",
+        token.format(deposit.amount),
+        kilt_address(&deposit.owner)
+    );
+    if deposit.owner != attestation.attester {
+        text += "  ⚠️ The deposit is not held by the account of the attester DID\n";
+    }
+    Ok(text)
+}
+
+/// An attestation for people, with the deposit
+pub async fn describe_attestation(
+    backend: &dyn ChainBackend,
+    root_hash: &str,
+) -> Result<String, Error> {
+    let attestation = backend
+        .attestation(&hex_decode_h256(root_hash)?, None)
+        .await?
+        .ok_or(Error::AttestationNotFound)?;
+    let mut text = format!("Attestation {}\n", root_hash);
+    text += &format!(
+        "  attester:    did:kilt:{}\n",
+        kilt_address(&attestation.attester)
+    );
+    text += &format!("  ctype:       {}\n", hex_encode(attestation.ctype_hash));
+    text += &format!(
+        "  delegation:  {}\n",
+        attestation
+            .delegation_id
+            .map(hex_encode)
+            .unwrap_or_else(|| "none".to_string())
+    );
+    text += &format!(
+        "  revoked:     {}\n",
+        if attestation.revoked { "yes" } else { "no" }
+    );
+    text += &describe_deposit(backend, &attestation).await?;
+    Ok(text)
+}
+
+/// Revoke or remove an existing attestation on behalf of the attester DID
+pub async fn attestation(cli: &KiltRuntimeApi, args: &AttestationArgs) -> Result<(), Error> {
+    match &args.command {
+        AttestationCommand::Show(args) => {
+            print!("{}", describe_attestation(cli, &args.root_hash).await?);
+            if args.submitter {
+                match find_submission(cli, cli, &args.root_hash).await? {
+                    Some(submission) => print!("{}", describe_submission(cli, &submission).await?),
+                    None => println!("  submitted:   not found"),
+                }
+            }
+            Ok(())
+        }
+        AttestationCommand::Revoke(args) => {
+            let call = Call::Attestation(AttestationCall::revoke {
+                claim_hash: hex_decode_h256(&args.root_hash)?,
+                max_parent_checks: args.max_parent_checks,
+            });
+            submit::<AttestationRevoked>(cli, &args.signer, call).await
+        }
+        AttestationCommand::Remove(args) => {
+            let call = Call::Attestation(AttestationCall::remove {
+                claim_hash: hex_decode_h256(&args.root_hash)?,
+                max_parent_checks: args.max_parent_checks,
+            });
+            submit::<AttestationRemoved>(cli, &args.signer, call).await
+        }
+    }
+}
+
+/// Submit the call as the attester DID and confirm that the expected event was emitted
+async fn submit<E: Event>(
+    cli: &KiltRuntimeApi,
+    signer: &SignerArgs,
+    call: Call,
+) -> Result<(), Error> {
+    if signer.dry_run {
+        println!("{}", hex_encode(call.encode()));
+        return Ok(());
+    }
+
+    let attester = signer.attester(cli).await?;
+    println!(
+        "Submitting {}.{} by {} paid by {}",
+        E::PALLET,
+        E::EVENT,
+        signer.did,
+        attester
+            .payer
+            .account_id()
+            .to_ss58check_with_version(Ss58AddressFormat::custom(38))
+    );
+
+    let events = submit_did_call(
+        cli,
+        &attester.did,
+        &attester.key,
+        &attester.payer,
+        call,
+        signer.wait_finalized,
+    )
+    .await?;
+
+    let status = if signer.wait_finalized {
+        "Finalized"
+    } else {
+        "Included"
+    };
+    println!("{} in block {}", status, hex_encode(events.block_hash()));
+    for event in events.iter_raw() {
+        let event = event?;
+        println!("  {}.{}", event.pallet, event.variant);
+    }
+
+    if events.has::<E>()? {
+        println!("✅ {}.{}", E::PALLET, E::EVENT);
+        Ok(())
+    } else {
+        Err(Error::EventNotFound(E::EVENT))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    #[tokio::test]
+    async fn test_describe_attestation() {
+        let mut backend = fixtures::backend();
+        let root_hash = fixtures::credential().root_hash;
+        let attester = fixtures::attester_did();
+
+        let text = describe_attestation(&backend, &root_hash).await.unwrap();
+        assert!(
+            text.contains(&format!("  attester:    {}\n", attester)),
+            "{}",
+            text
+        );
+        assert!(text.contains("  revoked:     no\n"), "{}", text);
+        assert!(
+            text.ends_with(&format!(
+                "  deposit:     0.12095 KILT held by {}\n",
+                attester.trim_start_matches("did:kilt:")
+            )),
+            "{}",
+            text
+        );
+
+        // someone else paid the deposit
+        let mut attestation = fixtures::attestation_details(true);
+        attestation.deposit.owner = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        backend.insert_attestation(hex_decode_h256(&root_hash).unwrap(), &attestation);
+        let text = describe_attestation(&backend, &root_hash).await.unwrap();
+        assert!(text.contains("  revoked:     yes\n"), "{}", text);
+        assert!(
+            text.ends_with("  ⚠️ The deposit is not held by the account of the attester DID\n"),
+            "{}",
+            text
+        );
+
+        let res = describe_attestation(&backend, &format!("0x{}", "00".repeat(32))).await;
+        assert!(matches!(res, Err(Error::AttestationNotFound)), "{:?}", res);
+    }
+
+    #[test]
+    fn test_encode_revoke_call() {
+        let root_hash = "0xf69ce26ca50b5d5f38cd32a99d031cd52fff42f17b9afb32895ffba260fb616a";
+        let call = Call::Attestation(AttestationCall::revoke {
+            claim_hash: hex_decode_h256(root_hash).unwrap(),
+            max_parent_checks: 0,
+        });
+        // pallet index 62, call index 1, claim hash, max parent checks as u32
+        assert_eq!(
+            hex_encode(call.encode()),
+            format!("0x3e01{}00000000", root_hash.trim_start_matches("0x"))
+        );
+    }
+}
@@ -1,4 +1,30 @@
-use subxt::{ClientBuilder, Config, DefaultConfig, PolkadotExtrinsicParams};
+use async_trait::async_trait;
+use codec::Encode;
+use futures::{
+    future::{join_all, BoxFuture},
+    FutureExt,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use subxt::{
+    sp_core::{sr25519, Pair, H256},
+    sp_runtime::AccountId32,
+    ClientBuilder, Config, DefaultConfig, PairSigner, PolkadotExtrinsicParams, TransactionEvents,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    backend::{AttestationHistory, ChainBackend},
+    errors::Error,
+    metrics,
+    utils::Token,
+    warnings::Warnings,
+};
 
 // Generate the KILT runtime API
 #[subxt::subxt(runtime_metadata_path = "metadata-spiritnet.scale")]
@@ -25,13 +51,366 @@ impl Config for KiltConfig {
 
 pub type KiltRuntimeApi = kilt::RuntimeApi<KiltConfig, PolkadotExtrinsicParams<KiltConfig>>;
 
+/// Signer for the account that submits (and pays for) extrinsics
+pub type KiltSigner = PairSigner<KiltConfig, sr25519::Pair>;
+
+/// A public KILT network and what a verifier uses by default on it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Network {
+    pub name: &'static str,
+    /// Websocket endpoint of the public nodes
+    pub endpoint: &'static str,
+    /// Hash of the genesis block, tells the networks apart once connected
+    pub genesis_hash: &'static str,
+    /// Issuers trusted when no issuers are given
+    pub issuers: &'static [&'static str],
+}
+
+/// Public KILT networks, the first is the default
+pub const NETWORKS: [Network; 2] = [
+    Network {
+        name: "spiritnet",
+        endpoint: "wss://spiritnet.kilt.io:443",
+        genesis_hash: "0x411f057b9107718c9624d6aa4a3f23c1653898297f3d4d529d9bb6511a39dd21",
+        issuers: &[
+            // socialkyc.io
+            "did:kilt:4pnfkRn5UurBJTW92d9TaVLR2CqJdY4z5HPjrEbpGyBykare",
+            // logion
+            "did:kilt:4pvWYQi953KFwPoCo9qaneoBGSCAdWxME9y4BapKaFXiiuWf",
+        ],
+    },
+    Network {
+        name: "peregrine",
+        endpoint: "wss://peregrine.kilt.io:443/parachain-public-ws",
+        genesis_hash: "0xa0c6e3bac382b316a68bca7141af1fba507207594c761076847ce358aeedcc21",
+        issuers: &[
+            // test.socialkyc.io
+            "did:kilt:4pehddkhEanexVTTzWAtrrfo2R7xPnePpuiJLC7shQU894aY",
+        ],
+    },
+];
+
+impl Network {
+    /// The known network of a name
+    pub fn named(name: &str) -> Option<&'static Network> {
+        NETWORKS.iter().find(|network| network.name == name)
+    }
+
+    /// The known network whose public nodes are at the endpoint
+    pub fn of_endpoint(endpoint: &str) -> Option<&'static Network> {
+        NETWORKS.iter().find(|network| network.endpoint == endpoint)
+    }
+
+    /// The names of the known networks, i.e. for error messages
+    pub fn names() -> String {
+        let names: Vec<_> = NETWORKS.iter().map(|network| network.name).collect();
+        names.join(", ")
+    }
+
+    /// An endpoint given for the network is rejected if it is the endpoint of another network
+    pub fn check_endpoint(&self, endpoint: &str) -> Result<(), Error> {
+        match Network::of_endpoint(endpoint) {
+            Some(other) if other != self => Err(Error::NetworkMismatch(format!(
+                "--endpoint {} is {}, not {}",
+                endpoint, other.name, self.name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The genesis hash of the chain a node is connected to has to be the one of the network
+    pub fn check_genesis(&self, genesis_hash: &H256) -> Result<(), Error> {
+        let hash = format!("{:?}", genesis_hash);
+        if hash == self.genesis_hash {
+            return Ok(());
+        }
+        let chain = NETWORKS
+            .iter()
+            .find(|network| network.genesis_hash == hash)
+            .map(|network| network.name.to_string())
+            .unwrap_or_else(|| format!("a chain with genesis hash {}", hash));
+        Err(Error::NetworkMismatch(format!(
+            "the endpoint is connected to {}, not {}",
+            chain, self.name
+        )))
+    }
+}
+
 /// Connect to a websocket endpoint using the KiltConfig
 pub async fn connect<U: Into<String>>(url: U) -> Result<KiltRuntimeApi, subxt::BasicError> {
-    Ok(ClientBuilder::new()
-        .set_url(url)
-        .build()
+    let client = ClientBuilder::new().set_url(url).build().await;
+    metrics::set_connected(client.is_ok());
+    Ok(client?.to_runtime_api::<KiltRuntimeApi>())
+}
+
+/// How long a connection of a pool may take to answer its health check
+pub const HEALTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connects to the endpoint of a pool, to a node or to a mock backend in tests
+pub type PoolConnector<B> = Box<dyn Fn(&str) -> BoxFuture<'static, Result<B, Error>> + Send + Sync>;
+
+/// Several connections to the same endpoint, so the queries of a busy verifier are not all
+/// multiplexed over one websocket. Each query takes the next connection in turn. A connection
+/// that a query failed on because of the node, or that fails `check_health`, is dropped and
+/// connected again by the next query that takes it.
+pub struct NodePool<B> {
+    endpoint: String,
+    connect: PoolConnector<B>,
+    connections: Vec<Mutex<Option<Arc<B>>>>,
+    next: AtomicUsize,
+    live: AtomicUsize,
+}
+
+impl<B: ChainBackend + 'static> NodePool<B> {
+    /// `size` connections to the endpoint, at least one. The first is the connection given, the
+    /// others are connected when a query first takes them
+    pub fn new(endpoint: &str, first: B, size: usize, connect: PoolConnector<B>) -> Self {
+        let mut connections = vec![Mutex::new(Some(Arc::new(first)))];
+        connections.extend((1..size).map(|_| Mutex::new(None)));
+        metrics::set_pool_connections(1);
+        NodePool {
+            endpoint: endpoint.to_string(),
+            connect,
+            connections,
+            next: AtomicUsize::new(0),
+            live: AtomicUsize::new(1),
+        }
+    }
+
+    /// Number of connections that are up
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    // the connection at the index, connecting it if it is down. Queries that take it meanwhile
+    // wait for the connection instead of connecting as well
+    async fn connection(&self, at: usize) -> Result<Arc<B>, Error> {
+        let mut slot = self.connections[at].lock().await;
+        if let Some(connection) = slot.as_ref() {
+            return Ok(connection.clone());
+        }
+        metrics::record_pool_reconnect();
+        let connection = Arc::new((self.connect)(&self.endpoint).await?);
+        *slot = Some(connection.clone());
+        let live = self.live.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics::set_pool_connections(live);
+        Ok(connection)
+    }
+
+    // drop the connection at the index unless it was replaced already
+    async fn disconnect(&self, at: usize, connection: &Arc<B>) {
+        let mut slot = self.connections[at].lock().await;
+        if slot.as_ref().is_some_and(|c| Arc::ptr_eq(c, connection)) {
+            *slot = None;
+            let live = self.live.fetch_sub(1, Ordering::Relaxed) - 1;
+            metrics::set_pool_connections(live);
+        }
+    }
+
+    async fn query<'f, T>(
+        &'f self,
+        lookup: impl FnOnce(Arc<B>) -> BoxFuture<'f, Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let at = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        let connection = self.connection(at).await?;
+        let res = lookup(connection.clone()).await;
+        if res.as_ref().is_err_and(Error::is_infrastructure) {
+            self.disconnect(at, &connection).await;
+        }
+        res
+    }
+
+    /// Look up a DID on every connection that is up, the ones that fail or do not answer in time
+    /// are dropped. Returns the number of connections that answered
+    pub async fn check_health(&self, timeout: Duration) -> usize {
+        // any DID will do, the node answers that it has none
+        let nobody = AccountId32::new([0; 32]);
+        let nobody = &nobody;
+        let checks = (0..self.connections.len()).map(|at| async move {
+            let connection = match self.connections[at].lock().await.clone() {
+                Some(connection) => connection,
+                None => return false,
+            };
+            let probe = connection.did(nobody, None);
+            let healthy = matches!(tokio::time::timeout(timeout, probe).await, Ok(Ok(_)));
+            if !healthy {
+                self.disconnect(at, &connection).await;
+            }
+            healthy
+        });
+        join_all(checks).await.into_iter().filter(|&up| up).count()
+    }
+}
+
+#[async_trait]
+impl<B: ChainBackend + 'static> ChainBackend for NodePool<B> {
+    async fn did(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<runtime_types::did::did_details::DidDetails>, Error> {
+        self.query(|c| async move { c.did(did, at).await }.boxed())
+            .await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<runtime_types::attestation::attestations::AttestationDetails>, Error> {
+        self.query(|c| async move { c.attestation(root_hash, at).await }.boxed())
+            .await
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.query(|c| async move { c.attestation_history(root_hash).await }.boxed())
+            .await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.query(|c| async move { c.web3_name_owner(name, at).await }.boxed())
+            .await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.query(|c| async move { c.web3_name(owner, at).await }.boxed())
+            .await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.query(|c| async move { c.ctype_creator(ctype_hash, at).await }.boxed())
+            .await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<runtime_types::did::service_endpoints::DidEndpoint>, Error> {
+        self.query(|c| async move { c.service_endpoints(did, at).await }.boxed())
+            .await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.query(|c| async move { c.token().await }.boxed()).await
+    }
+}
+
+/// The endpoint is a ws:// or http:// URL of a host other than this one, what is sent to it can
+/// be read and changed on the way
+pub fn is_plaintext(endpoint: &str) -> bool {
+    let rest = match endpoint
+        .strip_prefix("ws://")
+        .or_else(|| endpoint.strip_prefix("http://"))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    let loopback = host.eq_ignore_ascii_case("localhost")
+        || host.to_ascii_lowercase().ends_with(".localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback());
+    !loopback
+}
+
+/// Warn about a plaintext endpoint unless `allow_insecure` is given. Servers, which run
+/// unattended, refuse to use one without it.
+pub fn check_transport(
+    endpoint: &str,
+    allow_insecure: bool,
+    server: bool,
+    warnings: &Warnings,
+) -> Result<(), Error> {
+    if allow_insecure || !is_plaintext(endpoint) {
+        Ok(())
+    } else if server {
+        Err(Error::InsecureEndpoint(endpoint.to_string()))
+    } else {
+        warnings.warn(format!(
+            "{} is not encrypted, root hashes and DID queries can be read and changed on the way. \
+             Give --allow-insecure if this is intended",
+            endpoint
+        ))
+    }
+}
+
+/// Wrap a runtime call into a DID authorized operation, sign it with the DID key
+/// and submit it with the payer account. Returns the events once the extrinsic is in a block
+/// or, if `wait_finalized` is set, once that block is finalized.
+pub async fn submit_did_call(
+    cli: &KiltRuntimeApi,
+    did: &AccountId32,
+    did_key: &sr25519::Pair,
+    payer: &KiltSigner,
+    call: runtime_types::spiritnet_runtime::Call,
+    wait_finalized: bool,
+) -> Result<TransactionEvents<KiltConfig, Event>, Error> {
+    // The DID must exist and its tx counter is incremented with every operation
+    let did_doc = cli
+        .storage()
+        .did()
+        .did(did, None)
         .await?
-        .to_runtime_api::<KiltRuntimeApi>())
+        .ok_or(Error::DidNotFound)?;
+    let tx_counter = did_doc
+        .last_tx_counter
+        .checked_add(1)
+        .ok_or(Error::InvalidDid)?;
+
+    // The operation is only valid for a limited number of blocks after this one
+    let block_number = cli
+        .client
+        .rpc()
+        .header(None)
+        .await?
+        .ok_or(Error::BlockNotFound)?
+        .number;
+
+    let operation = runtime_types::did::did_details::DidAuthorizedCallOperation {
+        did: did.clone(),
+        tx_counter,
+        call,
+        block_number: block_number.into(),
+        submitter: payer.account_id().clone(),
+    };
+    let signature = runtime_types::did::did_details::DidSignature::Sr25519(
+        runtime_types::sp_core::sr25519::Signature(did_key.sign(&operation.encode()).0),
+    );
+
+    let progress = cli
+        .tx()
+        .did()
+        .submit_did_call(operation, signature)?
+        .sign_and_submit_then_watch_default(payer)
+        .await?;
+    let in_block = if wait_finalized {
+        progress.wait_for_finalized().await?
+    } else {
+        progress.wait_for_in_block().await?
+    };
+    Ok(in_block.wait_for_success().await?)
 }
 
 #[cfg(test)]
@@ -39,14 +418,221 @@ mod tests {
     use subxt::sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
 
     use super::*;
+    use crate::{fixtures, mock::MockBackend, utils::get_did_account_id};
+    use kilt::runtime_types::attestation::attestations::AttestationDetails;
+    use kilt::runtime_types::did::{did_details::DidDetails, service_endpoints::DidEndpoint};
     use kilt::runtime_types::frame_support::storage::bounded_vec::BoundedVec;
     use kilt::runtime_types::pallet_web3_names::web3_name::AsciiWeb3Name;
 
+    #[test]
+    fn test_networks() {
+        for network in NETWORKS.iter() {
+            for issuer in network.issuers {
+                crate::utils::normalize_issuer(issuer).unwrap();
+            }
+            assert_eq!(Network::named(network.name), Some(network));
+        }
+        let [spiritnet, peregrine] = &NETWORKS;
+        assert!(peregrine.check_endpoint(peregrine.endpoint).is_ok());
+        assert!(peregrine.check_endpoint("ws://127.0.0.1:9944").is_ok());
+        let res = peregrine.check_endpoint(spiritnet.endpoint);
+        assert!(matches!(res, Err(Error::NetworkMismatch(_))), "{:?}", res);
+
+        let genesis: H256 = spiritnet.genesis_hash.parse().unwrap();
+        assert!(spiritnet.check_genesis(&genesis).is_ok());
+        let res = peregrine.check_genesis(&genesis);
+        assert!(
+            matches!(&res, Err(Error::NetworkMismatch(msg)) if msg.contains("connected to spiritnet")),
+            "{:?}",
+            res
+        );
+        let res = spiritnet.check_genesis(&H256([1; 32]));
+        assert!(matches!(res, Err(Error::NetworkMismatch(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn test_plaintext_endpoints() {
+        for endpoint in [
+            "ws://spiritnet.example:9944",
+            "http://10.0.0.1:3000/graphql",
+            "ws://user@node.example",
+            "ws://[2001:db8::1]:9944",
+        ] {
+            assert!(is_plaintext(endpoint), "{}", endpoint);
+        }
+        // local dev nodes and encrypted endpoints
+        for endpoint in [
+            "ws://127.0.0.1:9944",
+            "ws://127.0.1.1",
+            "ws://localhost:9944/",
+            "http://LOCALHOST:3000/graphql",
+            "ws://node.localhost",
+            "ws://[::1]:9944",
+            NETWORKS[0].endpoint,
+            "https://resolver.example/1.0/identifiers",
+        ] {
+            assert!(!is_plaintext(endpoint), "{}", endpoint);
+        }
+
+        let warnings = Warnings::default();
+        let denied = Warnings { deny: true };
+        let remote = "ws://node.example:9944";
+        assert!(check_transport(remote, false, false, &warnings).is_ok());
+        let res = check_transport(remote, false, false, &denied);
+        assert!(
+            matches!(&res, Err(Error::DeniedWarning(msg)) if msg.contains("is not encrypted")),
+            "{:?}",
+            res
+        );
+        let res = check_transport(remote, false, true, &warnings);
+        assert!(matches!(res, Err(Error::InsecureEndpoint(_))), "{:?}", res);
+        assert!(check_transport(remote, true, true, &denied).is_ok());
+        assert!(check_transport("ws://127.0.0.1:9944", false, true, &denied).is_ok());
+    }
+
+    // one websocket to a node, its queries are answered one after the other. A closed one
+    // fails every query
+    struct Socket {
+        inner: MockBackend,
+        busy: Mutex<()>,
+        closed: bool,
+    }
+
+    impl Socket {
+        fn new(closed: bool) -> Self {
+            Socket {
+                inner: fixtures::backend(),
+                busy: Mutex::new(()),
+                closed,
+            }
+        }
+
+        async fn answer(&self) -> Result<(), Error> {
+            if self.closed {
+                return Err(Error::ConnectionError(subxt::BasicError::Other(
+                    "connection closed".to_string(),
+                )));
+            }
+            let _busy = self.busy.lock().await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl ChainBackend for Socket {
+        async fn did(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<DidDetails>, Error> {
+            self.answer().await?;
+            self.inner.did(did, at).await
+        }
+
+        async fn attestation(
+            &self,
+            root_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            self.answer().await?;
+            self.inner.attestation(root_hash, at).await
+        }
+
+        async fn web3_name_owner(
+            &self,
+            name: &str,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.answer().await?;
+            self.inner.web3_name_owner(name, at).await
+        }
+
+        async fn web3_name(
+            &self,
+            owner: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            self.answer().await?;
+            self.inner.web3_name(owner, at).await
+        }
+
+        async fn ctype_creator(
+            &self,
+            ctype_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.answer().await?;
+            self.inner.ctype_creator(ctype_hash, at).await
+        }
+
+        async fn service_endpoints(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            self.answer().await?;
+            self.inner.service_endpoints(did, at).await
+        }
+    }
+
+    // a pool whose connections after the first are new sockets, counting how many were connected
+    fn node_pool(first: Socket, size: usize) -> (NodePool<Socket>, Arc<AtomicUsize>) {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counted = connects.clone();
+        let connect: PoolConnector<Socket> = Box::new(move |_: &str| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            async { Ok(Socket::new(false)) }.boxed()
+        });
+        (NodePool::new("ws://node", first, size, connect), connects)
+    }
+
+    #[tokio::test]
+    async fn test_node_pool_throughput() {
+        let owner = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let mut elapsed = Vec::new();
+        for size in [1, 4] {
+            let (pool, connects) = node_pool(Socket::new(false), size);
+            let start = std::time::Instant::now();
+            let lookups = join_all((0..16).map(|_| pool.did(&owner, None))).await;
+            elapsed.push(start.elapsed());
+            assert!(lookups.iter().all(|did| matches!(did, Ok(Some(_)))));
+            assert_eq!(pool.live(), size);
+            assert_eq!(connects.load(Ordering::Relaxed), size - 1);
+        }
+        // the queries of one connection wait for each other, four connections answer at once
+        assert!(elapsed[1] * 2 < elapsed[0], "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_node_pool_replaces_dead_connections() {
+        let owner = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let (pool, connects) = node_pool(Socket::new(true), 2);
+        let res = pool.did(&owner, None).await;
+        assert!(matches!(res, Err(Error::ConnectionError(_))), "{:?}", res);
+        assert_eq!(pool.live(), 0);
+        // the next query takes the other connection, the one after replaces the dead one
+        assert!(pool.did(&owner, None).await.is_ok());
+        assert!(pool.did(&owner, None).await.is_ok());
+        assert_eq!((pool.live(), connects.load(Ordering::Relaxed)), (2, 2));
+
+        // a connection that fails its health check is dropped before a query takes it
+        let (pool, connects) = node_pool(Socket::new(true), 2);
+        assert_eq!(pool.check_health(HEALTH_TIMEOUT).await, 0);
+        assert_eq!(pool.live(), 0);
+        assert!(pool.did(&owner, None).await.is_ok());
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+    }
+
     fn w3n<S: AsRef<str>>(s: S) -> AsciiWeb3Name {
         AsciiWeb3Name(BoundedVec(String::from(s.as_ref()).as_bytes().to_vec()))
     }
 
     #[tokio::test]
+    #[cfg_attr(
+        not(feature = "live-tests"),
+        ignore = "requires a connection to spiritnet"
+    )]
     async fn lookup_w3n() {
         let api = connect("wss://spiritnet.kilt.io:443").await.unwrap();
 
@@ -0,0 +1,110 @@
+pub mod errors;
+
+pub mod warnings;
+
+pub mod utils;
+
+pub mod kilt;
+
+pub mod backend;
+
+pub mod throttle;
+
+pub mod cache;
+
+pub mod mock;
+
+pub mod history;
+
+pub mod submission;
+
+pub mod revocation;
+
+pub mod credential;
+
+pub mod ctypes;
+
+pub mod limits;
+
+pub mod progress;
+
+pub mod status;
+
+pub mod did;
+
+pub mod registry;
+
+pub mod web3names;
+
+pub mod quorum;
+
+pub mod diff;
+
+pub mod minimize;
+
+pub mod canonicalize;
+
+pub mod structure;
+
+pub mod lint;
+
+pub mod yaml;
+
+pub mod cbor;
+
+pub mod decompress;
+
+pub mod uri;
+
+pub mod convert;
+
+pub mod archive;
+
+pub mod bundle;
+
+pub mod batch;
+
+pub mod manifest;
+
+pub mod session;
+
+pub mod stats;
+
+pub mod plan;
+
+pub mod porcelain;
+
+pub mod timeout;
+
+pub mod wizard;
+
+pub mod metrics;
+
+pub mod audit;
+
+pub mod report;
+
+pub mod monitor;
+
+pub mod webhook;
+
+#[cfg(feature = "nats")]
+pub mod publish;
+
+#[cfg(feature = "otel")]
+pub mod otel;
+
+pub mod resolver;
+
+pub mod grace;
+
+pub mod indexer;
+
+pub mod doctor;
+
+pub mod issuer;
+
+pub mod holder;
+
+#[cfg(test)]
+mod fixtures;
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::io::Write;
+
+use crate::{
+    credential::{Claim, Credential},
+    errors::Error,
+};
+
+/// Bounds of a credential, so an adversarial one cannot make normalization and hashing use
+/// unbounded CPU or memory. Sizes are in bytes of compact json.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_properties: u64,
+    /// Levels of objects and arrays in the contents, the contents themselves are one level
+    pub max_depth: u64,
+    pub max_property_size: u64,
+    pub max_contents_size: u64,
+    pub max_claim_hashes: u64,
+    pub max_nonces: u64,
+    /// Levels of legitimations, legitimations without legitimations of their own are one level
+    pub max_legitimation_depth: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_properties: 1000,
+            max_depth: 32,
+            max_property_size: 16 * 1024 * 1024,
+            max_contents_size: 32 * 1024 * 1024,
+            max_claim_hashes: 1024,
+            max_nonces: 1024,
+            max_legitimation_depth: 4,
+        }
+    }
+}
+
+fn check(which: &'static str, limit: u64, actual: u64) -> Result<(), Error> {
+    if actual > limit {
+        Err(Error::LimitExceeded {
+            which,
+            limit,
+            actual,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+impl Limits {
+    /// Check a parsed credential, the counts first and the sizes of the claim last
+    pub fn check(&self, cred: &Credential) -> Result<(), Error> {
+        check(
+            "claim hashes",
+            self.max_claim_hashes,
+            cred.claim_hashes.len() as u64,
+        )?;
+        check(
+            "nonce map entries",
+            self.max_nonces,
+            cred.claim_nonce_map.len() as u64,
+        )?;
+        check(
+            "levels of legitimations",
+            self.max_legitimation_depth,
+            cred.legitimation_depth as u64,
+        )?;
+        self.check_claim(&cred.claim)
+    }
+
+    /// Check the contents of a claim. The sizes are counted without writing the json.
+    pub fn check_claim(&self, claim: &Claim) -> Result<(), Error> {
+        let properties = claim
+            .contents
+            .as_object()
+            .ok_or(Error::InvalidClaimContents)?;
+        check(
+            "claim properties",
+            self.max_properties,
+            properties.len() as u64,
+        )?;
+        check(
+            "levels of nesting in the claim",
+            self.max_depth,
+            depth(&claim.contents),
+        )?;
+        let mut total = 0;
+        for value in properties.values() {
+            let mut counter = CountingWriter(0);
+            serde_json::to_writer(&mut counter, value)?;
+            check(
+                "bytes of a claim property",
+                self.max_property_size,
+                counter.0,
+            )?;
+            total += counter.0;
+        }
+        check("bytes of claim contents", self.max_contents_size, total)
+    }
+}
+
+// serde_json refuses documents nested deeper than 128 levels, so this cannot overflow the stack
+// for parsed credentials
+fn depth(value: &Value) -> u64 {
+    let children: Box<dyn Iterator<Item = &Value>> = match value {
+        Value::Array(values) => Box::new(values.iter()),
+        Value::Object(members) => Box::new(members.values()),
+        _ => return 0,
+    };
+    1 + children.map(depth).max().unwrap_or(0)
+}
+
+/// How deep the `legitimations` of a credential nest, the legitimations themselves are not kept
+pub(crate) fn legitimation_depth<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<usize, D::Error> {
+    fn levels(legitimations: &Value) -> usize {
+        match legitimations.as_array() {
+            Some(legitimations) if !legitimations.is_empty() => {
+                let inner = legitimations
+                    .iter()
+                    .filter_map(|legitimation| legitimation.get("legitimations"))
+                    .map(levels);
+                1 + inner.max().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+    Ok(levels(&Value::deserialize(deserializer)?))
+}
+
+// counts what is written and forgets it
+struct CountingWriter(u64);
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, utils::ParseOptions};
+    use serde_json::json;
+
+    fn exceeded(res: Result<(), Error>) -> (&'static str, u64, u64) {
+        match res {
+            Err(Error::LimitExceeded {
+                which,
+                limit,
+                actual,
+            }) => (which, limit, actual),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let claim = Claim {
+            ctype_hash: "0x12".to_string(),
+            contents: json!({"Scan": "a".repeat(100), "Name": "Alice \"A\"", "Address": {"Geo": [1, 2]}}),
+            owner: "did:kilt:4abc".to_string(),
+            ..Default::default()
+        };
+        // sizes of the compact json including quotes and escapes
+        let fitting = Limits {
+            max_properties: 3,
+            max_depth: 3,
+            max_property_size: 102,
+            max_contents_size: 128,
+            ..Default::default()
+        };
+        assert!(fitting.check_claim(&claim).is_ok());
+        for (limits, expected) in [
+            (
+                Limits {
+                    max_properties: 2,
+                    ..fitting
+                },
+                ("claim properties", 2, 3),
+            ),
+            (
+                Limits {
+                    max_depth: 2,
+                    ..fitting
+                },
+                ("levels of nesting in the claim", 2, 3),
+            ),
+            (
+                Limits {
+                    max_property_size: 101,
+                    ..fitting
+                },
+                ("bytes of a claim property", 101, 102),
+            ),
+            (
+                Limits {
+                    max_contents_size: 127,
+                    ..fitting
+                },
+                ("bytes of claim contents", 127, 128),
+            ),
+        ] {
+            assert_eq!(exceeded(limits.check_claim(&claim)), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limited_credential() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let mut json = serde_json::to_value(fixtures::credential()).unwrap();
+        let legitimation = |inner: Value| json!({"claim": {}, "legitimations": [inner]});
+        json["legitimations"] = json!([{"claim": {}}, legitimation(legitimation(json!({})))]);
+        let data = serde_json::to_vec(&json).unwrap();
+        let parse = ParseOptions::default();
+        let cred = parse.parse("cred.json", &data).unwrap();
+        assert_eq!(cred.legitimation_depth, 3);
+        // the legitimations are not kept
+        assert!(serde_json::to_value(&cred)
+            .unwrap()
+            .get("legitimations")
+            .is_none());
+        let res = cred.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "{:?}", res);
+
+        for (limits, expected) in [
+            (
+                Limits {
+                    max_legitimation_depth: 2,
+                    ..Default::default()
+                },
+                ("levels of legitimations", 2, 3),
+            ),
+            (
+                Limits {
+                    max_claim_hashes: 2,
+                    ..Default::default()
+                },
+                ("claim hashes", 2, 3),
+            ),
+            (
+                Limits {
+                    max_nonces: 1,
+                    ..Default::default()
+                },
+                ("nonce map entries", 1, 3),
+            ),
+        ] {
+            let parse = ParseOptions {
+                limits,
+                ..Default::default()
+            };
+            let res = parse.parse("cred.json", &data).map(|_| ());
+            assert_eq!(exceeded(res), expected);
+            // and when the claim contents are checked
+            let mut limited = cred.clone();
+            limited.limits = limits;
+            assert_eq!(exceeded(limited.check_claim_contents()), expected);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use clap::Args;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+use crate::{
+    errors::Error,
+    utils::{read_credential_data, DidUrl, ParseOptions},
+};
+
+/// Report suspicious but not fatal issues of a credential, it is neither parsed nor verified
+#[derive(Args, Debug)]
+pub struct LintArgs {
+    /// File containing the credential, its format is taken from the extension or --input-format
+    #[clap(short, long, value_parser, default_value = "stdin")]
+    file: String,
+
+    #[clap(long, value_enum, default_value_t = LintOutput::Human)]
+    output: LintOutput,
+
+    /// Rule whose findings are errors, the run fails if there are any. Can be given repeatedly
+    #[clap(long, value_parser = parse_rule)]
+    deny: Vec<&'static str>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintOutput {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    /// A denied rule
+    Error,
+}
+
+/// A rule checks the json of a credential and describes every issue it finds
+pub struct Rule {
+    /// Stable id, used with `--deny` and in the json output
+    pub id: &'static str,
+    pub description: &'static str,
+    check: fn(&Value) -> Vec<String>,
+}
+
+/// One issue of a credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// All rules, in the order their findings are reported
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "unknown-field",
+        description: "top-level field the credential format does not have",
+        check: unknown_fields,
+    },
+    Rule {
+        id: "empty-contents",
+        description: "claim contents without properties",
+        check: empty_contents,
+    },
+    Rule {
+        id: "non-lowercase-hex",
+        description: "hex value with uppercase digits or prefix",
+        check: non_lowercase_hex,
+    },
+    Rule {
+        id: "mixed-hex-prefix",
+        description: "some hex values have a 0x prefix and some do not",
+        check: mixed_hex_prefix,
+    },
+    Rule {
+        id: "claim-hash-length",
+        description: "claim hash that is not 32 bytes",
+        check: claim_hash_length,
+    },
+    Rule {
+        id: "nonce-not-uuid",
+        description: "nonce that is not a hyphenated uuid, like the KILT SDK creates",
+        check: nonce_not_uuid,
+    },
+    Rule {
+        id: "empty-legitimation",
+        description: "legitimation that is an empty object",
+        check: empty_legitimations,
+    },
+    Rule {
+        id: "key-owner-mismatch",
+        description: "the DID of the signing key is not the owner of the claim",
+        check: key_owner_mismatch,
+    },
+];
+
+const KNOWN_FIELDS: [&str; 7] = [
+    "claim",
+    "claimHashes",
+    "claimNonceMap",
+    "claimerSignature",
+    "rootHash",
+    "legitimations",
+    "delegationId",
+];
+
+fn parse_rule(arg: &str) -> Result<&'static str, String> {
+    RULES
+        .iter()
+        .find(|rule| rule.id == arg)
+        .map(|rule| rule.id)
+        .ok_or_else(|| {
+            let ids: Vec<_> = RULES.iter().map(|rule| rule.id).collect();
+            format!("known rules are {}", ids.join(", "))
+        })
+}
+
+/// Check the json of a credential with all rules, the findings of denied rules are errors
+pub fn lint(json: &Value, deny: &[&str]) -> Vec<Finding> {
+    RULES
+        .iter()
+        .flat_map(|rule| {
+            let severity = if deny.contains(&rule.id) {
+                Severity::Error
+            } else {
+                Severity::Warning
+            };
+            (rule.check)(json).into_iter().map(move |message| Finding {
+                rule: rule.id,
+                severity,
+                message,
+            })
+        })
+        .collect()
+}
+
+fn unknown_fields(json: &Value) -> Vec<String> {
+    json.as_object()
+        .into_iter()
+        .flat_map(|fields| fields.keys())
+        .filter(|field| !KNOWN_FIELDS.contains(&field.as_str()))
+        .map(|field| format!("{} is not a field of a credential", field))
+        .collect()
+}
+
+fn empty_contents(json: &Value) -> Vec<String> {
+    match json["claim"]["contents"].as_object() {
+        Some(contents) if contents.is_empty() => {
+            vec!["the claim has no contents, it only attests the owner".to_string()]
+        }
+        _ => vec![],
+    }
+}
+
+// the hex values of a credential with where they are, values that are not hex are left out
+fn hex_values(json: &Value) -> Vec<(String, &str)> {
+    let mut fields = vec![
+        ("claim.cTypeHash".to_string(), &json["claim"]["cTypeHash"]),
+        ("rootHash".to_string(), &json["rootHash"]),
+        ("delegationId".to_string(), &json["delegationId"]),
+        (
+            "claimerSignature.signature".to_string(),
+            &json["claimerSignature"]["signature"],
+        ),
+    ];
+    if let Some(hashes) = json["claimHashes"].as_array() {
+        fields.extend(
+            hashes
+                .iter()
+                .enumerate()
+                .map(|(i, hash)| (format!("claimHashes[{}]", i), hash)),
+        );
+    }
+    let mut values: Vec<_> = fields
+        .into_iter()
+        .filter_map(|(path, value)| Some((path, value.as_str()?)))
+        .collect();
+    if let Some(key_id) = json["claimerSignature"]["keyUri"]
+        .as_str()
+        .and_then(|uri| uri.split_once('#'))
+        .map(|(_, key_id)| key_id)
+    {
+        values.push(("the key id of claimerSignature.keyUri".to_string(), key_id));
+    }
+    if let Some(nonces) = json["claimNonceMap"].as_object() {
+        for hash in nonces.keys() {
+            values.push((format!("claimNonceMap key {}", hash), hash));
+        }
+    }
+    values.retain(|(_, value)| {
+        let digits = strip_hex_prefix(value);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit())
+    });
+    values
+}
+
+fn strip_hex_prefix(value: &str) -> &str {
+    value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value)
+}
+
+fn non_lowercase_hex(json: &Value) -> Vec<String> {
+    hex_values(json)
+        .into_iter()
+        .filter(|(_, value)| value.chars().any(|c| c.is_ascii_uppercase()))
+        .map(|(path, _)| format!("{} is not lowercase hex", path))
+        .collect()
+}
+
+fn mixed_hex_prefix(json: &Value) -> Vec<String> {
+    let values = hex_values(json);
+    let (prefixed, bare): (Vec<_>, Vec<_>) = values
+        .iter()
+        .partition(|(_, value)| strip_hex_prefix(value).len() < value.len());
+    if prefixed.is_empty() || bare.is_empty() {
+        return vec![];
+    }
+    let bare: Vec<_> = bare.iter().map(|(path, _)| path.as_str()).collect();
+    vec![format!(
+        "{} hex values have a 0x prefix, {} do not",
+        prefixed.len(),
+        bare.join(", ")
+    )]
+}
+
+fn claim_hash_length(json: &Value) -> Vec<String> {
+    json["claimHashes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(|(i, hash)| {
+            let length = hash.as_str().map(|hash| strip_hex_prefix(hash).len());
+            match length {
+                Some(64) => None,
+                Some(length) => Some(format!(
+                    "claimHashes[{}] has {} hex digits, not 64",
+                    i, length
+                )),
+                None => Some(format!("claimHashes[{}] is not a string", i)),
+            }
+        })
+        .collect()
+}
+
+fn nonce_not_uuid(json: &Value) -> Vec<String> {
+    json["claimNonceMap"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, nonce)| {
+            !nonce
+                .as_str()
+                .is_some_and(|nonce| nonce.len() == 36 && uuid::Uuid::try_parse(nonce).is_ok())
+        })
+        .map(|(hash, nonce)| format!("the nonce {} of {} is not a uuid", nonce, hash))
+        .collect()
+}
+
+fn empty_legitimations(json: &Value) -> Vec<String> {
+    json["legitimations"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, legitimation)| legitimation.as_object().is_some_and(|l| l.is_empty()))
+        .map(|(i, _)| format!("legitimations[{}] is an empty object", i))
+        .collect()
+}
+
+// the full DID of a DID URL, as it is if it cannot be parsed. A light DID owner signs with the
+// keys of its full DID
+fn did_of(url: &str) -> String {
+    match DidUrl::parse(url) {
+        Ok(url) => url.full_did(),
+        Err(_) => url.split('#').next().unwrap_or_default().to_string(),
+    }
+}
+
+fn key_owner_mismatch(json: &Value) -> Vec<String> {
+    let owner = json["claim"]["owner"].as_str();
+    let key_did = json["claimerSignature"]["keyUri"].as_str().map(did_of);
+    match (owner, key_did) {
+        (Some(owner), Some(key_did)) if did_of(owner) != key_did => vec![format!(
+            "the key is one of {}, the owner is {}",
+            key_did, owner
+        )],
+        _ => vec![],
+    }
+}
+
+/// Write the findings, one per line or as a json document
+pub fn write_findings(
+    out: &mut dyn Write,
+    findings: &[Finding],
+    output: LintOutput,
+) -> Result<(), Error> {
+    let errors = errors(findings);
+    match output {
+        LintOutput::Json => {
+            #[derive(Serialize)]
+            struct JsonFindings<'a> {
+                findings: &'a [Finding],
+                errors: usize,
+            }
+            let json = JsonFindings { findings, errors };
+            writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+        }
+        LintOutput::Human => {
+            for finding in findings {
+                let severity = match finding.severity {
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                };
+                writeln!(out, "{}[{}]: {}", severity, finding.rule, finding.message)?;
+            }
+            writeln!(out, "{} findings, {} errors", findings.len(), errors)?;
+        }
+    }
+    Ok(())
+}
+
+fn errors(findings: &[Finding]) -> usize {
+    findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count()
+}
+
+pub fn run(args: &LintArgs, parse: &ParseOptions) -> Result<(), Error> {
+    let data = read_credential_data(&args.file)?;
+    let json = parse.to_json(&args.file, &data)?;
+    let findings = lint(&json, &args.deny);
+    write_findings(&mut std::io::stdout(), &findings, args.output)?;
+    match errors(&findings) {
+        0 => Ok(()),
+        errors => Err(Error::LintFailed(errors)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use serde_json::json;
+
+    type Change<'a> = Box<dyn Fn(&mut Value) + 'a>;
+
+    #[test]
+    fn test_rules() {
+        let clean = serde_json::to_value(fixtures::credential()).unwrap();
+        assert_eq!(lint(&clean, &[]), []);
+
+        let hash = clean["claimHashes"][0].as_str().unwrap().to_string();
+        let nonce_key = clean["claimNonceMap"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .next()
+            .unwrap()
+            .clone();
+        // each change with the rule it breaks and how often
+        let cases: Vec<(&str, Change, usize)> = vec![
+            (
+                "unknown-field",
+                Box::new(|cred| {
+                    cred["proof"] = json!({});
+                    cred["version"] = json!(1);
+                }),
+                2,
+            ),
+            (
+                "empty-contents",
+                Box::new(|cred| cred["claim"]["contents"] = json!({})),
+                1,
+            ),
+            (
+                "non-lowercase-hex",
+                Box::new(|cred| {
+                    cred["rootHash"] = cred["rootHash"].as_str().unwrap().to_uppercase().into()
+                }),
+                1,
+            ),
+            (
+                "mixed-hex-prefix",
+                Box::new(|cred| cred["claimHashes"][0] = hash.trim_start_matches("0x").into()),
+                1,
+            ),
+            (
+                "claim-hash-length",
+                Box::new(|cred| {
+                    cred["claimHashes"][0] = "0x1234".into();
+                    cred["claimHashes"][1] = json!(12);
+                }),
+                2,
+            ),
+            (
+                "nonce-not-uuid",
+                Box::new(|cred| cred["claimNonceMap"][&nonce_key] = "1234".into()),
+                1,
+            ),
+            (
+                "empty-legitimation",
+                Box::new(|cred| cred["legitimations"] = json!([{}, {"claim": {}}, {}])),
+                2,
+            ),
+            (
+                "key-owner-mismatch",
+                Box::new(|cred| cred["claim"]["owner"] = fixtures::attester_did().into()),
+                1,
+            ),
+        ];
+        assert_eq!(cases.len(), RULES.len());
+        for (rule, change, count) in cases {
+            let mut cred = clean.clone();
+            change(&mut cred);
+            let findings = lint(&cred, &[]);
+            assert!(
+                findings.iter().all(|finding| finding.rule == rule),
+                "{}: {:?}",
+                rule,
+                findings
+            );
+            assert_eq!(findings.len(), count, "{}: {:?}", rule, findings);
+            assert!(findings.iter().all(|f| f.severity == Severity::Warning));
+            assert!(lint(&cred, &[rule])
+                .iter()
+                .all(|f| f.severity == Severity::Error));
+        }
+
+        // the same DID written as a DID URL with a query is no mismatch
+        let mut cred = clean.clone();
+        let owner = format!("{}?version=1", cred["claim"]["owner"].as_str().unwrap());
+        cred["claim"]["owner"] = owner.into();
+        assert!(key_owner_mismatch(&cred).is_empty());
+    }
+
+    #[test]
+    fn test_write_findings() {
+        let mut cred = serde_json::to_value(fixtures::credential()).unwrap();
+        cred["claim"]["contents"] = json!({});
+        cred["extra"] = json!(true);
+        let findings = lint(&cred, &["empty-contents"]);
+
+        let mut out = Vec::new();
+        write_findings(&mut out, &findings, LintOutput::Human).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "warning[unknown-field]: extra is not a field of a credential\n\
+             error[empty-contents]: the claim has no contents, it only attests the owner\n\
+             2 findings, 1 errors\n"
+        );
+
+        let mut out = Vec::new();
+        write_findings(&mut out, &findings, LintOutput::Json).unwrap();
+        let json: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["errors"], 1);
+        assert_eq!(json["findings"][1]["rule"], "empty-contents");
+        assert_eq!(json["findings"][1]["severity"], "error");
+
+        assert!(parse_rule("nonce-not-uuid").is_ok());
+        assert!(parse_rule("no-such-rule").is_err());
+    }
+}
@@ -1,77 +1,1870 @@
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use futures::{FutureExt, TryFutureExt};
+use std::{
+    collections::BTreeSet,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-mod errors;
-use errors::Error;
+use kilt_verify::{
+    archive::ArchiveOptions,
+    audit::{self, AuditLog, AuditRecord, ChainPolicy, FlushPolicy},
+    backend::{AttestationHistory, ChainBackend, PinnedBackend, QueryBackend},
+    batch::{
+        self, BatchOptions, BatchReport, BatchResult, MaxFailures, OutputFormat, SortBy,
+        MIN_REPORT_VERSION, REPORT_VERSION,
+    },
+    bundle::{self, BundleReport},
+    canonicalize, convert,
+    credential::{self, ClaimRequirements, Credential, StatementKeys},
+    ctypes::{self, CtypeNames},
+    decompress, did, diff, doctor,
+    errors::Error,
+    grace::KeyGraceBackend,
+    history::{self, BlockHistory, ResolvedBlock},
+    holder,
+    indexer::{IndexerBackend, VerifyAgainst},
+    issuer,
+    kilt::{self, connect, KiltRuntimeApi, Network, NodePool, NETWORKS},
+    limits::Limits,
+    lint,
+    manifest::{self, ConnectionPool},
+    metrics, minimize, monitor,
+    plan::{PlanInput, VerificationPlan},
+    porcelain,
+    progress::{self, CheckStep, StepStatus, VerificationObserver},
+    quorum::{self, QuorumBackend},
+    registry::{self, IssuerRegistry},
+    report::{self, ReportSigner, SignArgs},
+    resolver::{ResolverBackend, RESOLVED_VIA},
+    revocation, session,
+    status::CheckRecorder,
+    structure, submission,
+    throttle::{Priority, RpcLimiter, Throttle},
+    timeout::{cancellable, with_timeout},
+    utils::{self, get_did_account_id, hex_decode_h256, DidUrl, InputFormat, ParseOptions},
+    warnings::Warnings,
+    web3names::{self, Web3NameBackend, Web3NameCache},
+    wizard::{self, WizardAnswers},
+};
 
-mod utils;
-use utils::read_credential;
+#[cfg(feature = "nats")]
+use kilt_verify::publish::{self, Publisher, Sink};
 
-mod kilt;
-use kilt::connect;
-
-mod credential;
-
-const ALLOWED_ISSUERS: [&str; 2] = [
-    // socialkyc.io
-    "did:kilt:4pnfkRn5UurBJTW92d9TaVLR2CqJdY4z5HPjrEbpGyBykare",
-    // logion
-    "did:kilt:4pvWYQi953KFwPoCo9qaneoBGSCAdWxME9y4BapKaFXiiuWf",
-];
+#[cfg(feature = "otel")]
+use kilt_verify::otel::{OtelLayer, OtlpExporter, TraceContext};
+#[cfg(feature = "otel")]
+use std::sync::Arc;
 
 /// Command line tool to verify KILT credentials
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// File containing the credential to verify
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// File containing the credential to verify.
+    /// A directory of .json files, a .ndjson file with one credential per line
+    /// or a .zip, .tar or .tar.gz archive is verified as a batch
     #[clap(short, long, value_parser, default_value = "stdin")]
     file: String,
 
+    /// Files to verify one after another, each with its own result, instead of --file
+    #[clap(value_parser, conflicts_with_all = &["file", "uri", "manifest", "interactive"])]
+    files: Vec<String>,
+
+    /// Deep link of a wallet to verify the credential of, `kilt://verify?credential=<base64url>`
+    /// or an https link with `#credential=<base64url>`. A --file value with a scheme is read the
+    /// same way
+    #[clap(long, value_parser, conflicts_with_all = &["file", "manifest"])]
+    uri: Option<String>,
+
+    /// Json array of credential files to verify as a batch, each with optional options of its own:
+    /// `{"path", "network" or "endpoint", "expected_owner", "allowed_issuers"}`.
+    /// Paths are relative to the manifest, options that are not given are taken from the flags
+    #[clap(long, value_parser, conflicts_with_all = &["file", "interactive"])]
+    manifest: Option<String>,
+
     /// Use verbose output
     #[clap(short, long, value_parser, default_value_t = false)]
     verbose: bool,
 
-    /// kilt node endpoint
-    /// testnet: wss://peregrine.kilt.io:443/parachain-public-ws
+    /// kilt node endpoint, defaults to the public endpoint of --network.
+    /// Given multiple times with --quorum, the endpoints are asked the same questions
+    #[clap(short, long = "endpoint", value_parser, global = true)]
+    endpoints: Vec<String>,
+
+    /// Read DID documents and attestations from all --endpoint values at the same block, the one
+    /// of --valid-at or the latest block all of them have finalized, and accept them only if the
+    /// endpoints agree. At least this many endpoints have to answer
+    #[clap(long, value_parser, conflicts_with_all = &["manifest", "fallback-endpoints"])]
+    quorum: Option<usize>,
+
+    /// Network to verify on: spiritnet or peregrine (testnet). Selects the default endpoint and
+    /// the default issuers of the network, and the endpoint has to be a node of this network
+    #[clap(long, value_parser = parse_network, global = true)]
+    network: Option<&'static Network>,
+
+    /// Use ws:// and http:// endpoints of other hosts without a warning, `monitor` refuses them
+    /// without it. Endpoints on this host are always allowed
+    #[clap(long, value_parser, default_value_t = false, global = true)]
+    allow_insecure: bool,
+
+    /// Fail the run on warnings about its setup, like an endpoint that is not encrypted
+    #[clap(long, value_parser, default_value_t = false, global = true)]
+    deny_warnings: bool,
+
+    /// Upper bound for the whole run including connecting, i.e. "60s" or "2m 30s".
+    /// Completed results and the partial batch summary are printed before exiting with code 124
+    #[clap(long, value_parser = humantime::parse_duration, global = true)]
+    timeout: Option<Duration>,
+
+    /// Upper bound for each storage query, unlike --timeout a query that takes longer does not
+    /// end the run but is tried again, see --query-retries
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    query_timeout: Duration,
+
+    /// How many times a storage query that timed out or failed because of the node is tried
+    /// again. The attempts go to --endpoint and the fallback endpoints in turn
+    #[clap(long, value_parser, default_value_t = 2)]
+    query_retries: usize,
+
+    /// Storage queries that may be in flight at the same time, shared by the verifications and
+    /// the refreshes of issuer registries. Not limited by default
+    #[clap(long, value_parser)]
+    rpc_concurrency: Option<usize>,
+
+    /// Permits of --rpc-concurrency refreshes may take, so they cannot hold up verifications.
+    /// A quarter of them by default
+    #[clap(long, value_parser, requires = "rpc-concurrency")]
+    rpc_background_concurrency: Option<usize>,
+
+    /// Connections to --endpoint the storage queries are spread over in turn, for verifiers
+    /// that make more queries than one websocket keeps up with. Dead connections are replaced
+    #[clap(long, value_parser, default_value_t = 1, conflicts_with = "manifest")]
+    rpc_connections: usize,
+
+    /// Endpoint that failed queries are tried again on, can be given multiple times.
+    /// Not used for manifests, whose entries name their own endpoints
+    #[clap(long = "fallback-endpoint", value_parser, conflicts_with = "manifest")]
+    fallback_endpoints: Vec<String>,
+
+    /// Universal resolver the DID documents of owners are read from when the chain cannot be
+    /// queried, a plain http:// URL the DID is appended to. Attestations are only read from the chain
+    #[clap(long, value_parser, conflicts_with_all = &["manifest", "quorum"])]
+    did_resolver_url: Option<String>,
+
+    /// GraphQL endpoint of an indexer of the chain that attestations are read from, a plain
+    /// http:// URL, i.e. "http://localhost:3000/graphql". DID documents are read from the chain
+    #[clap(long, value_parser, conflicts_with_all = &["manifest", "quorum"])]
+    indexer_url: Option<String>,
+
+    /// Read attestations from the chain, the indexer or both, which fails if they disagree.
+    /// Defaults to the indexer if --indexer-url is given
+    #[clap(long, value_enum, requires_ifs = &[("indexer", "indexer-url"), ("both", "indexer-url")])]
+    verify_against: Option<VerifyAgainst>,
+
+    /// How long a resolved web3name or the web3name of a DID is used before it is read again.
+    /// Names can be released and claimed by another DID, so keep this short
+    #[clap(long = "w3n-cache-ttl", value_parser = humantime::parse_duration, global = true, default_value = "5m")]
+    w3n_cache_ttl: Duration,
+
+    /// File the web3name cache is kept in between runs, defaults to w3n.json in the cache directory
+    #[clap(long = "w3n-cache", value_parser, global = true)]
+    w3n_cache: Option<PathBuf>,
+
+    /// TOML file of `"<ctype hash>" = "<name>"` lines naming ctypes in the output, on top of and
+    /// replacing the built-in names
+    #[clap(long, value_parser, global = true)]
+    ctype_names: Option<String>,
+
+    /// Trusted issuer DID or KILT address, can be given multiple times and replaces the built-in issuers
+    #[clap(long = "issuer", value_parser)]
+    issuers: Vec<String>,
+
+    /// DID of a registry whose KiltTrustedIssuers service endpoints list the trusted issuers.
+    /// Its members replace the built-in issuers and are trusted along with the --issuer ones.
+    /// If the registry cannot be read the members cached by an earlier run are used
+    #[clap(long, value_parser, conflicts_with = "interactive")]
+    issuer_registry: Option<String>,
+
+    /// Cache file of the registry members, defaults to ~/.cache/kilt-verify/registry-<address>.json
+    #[clap(long, value_parser)]
+    issuer_registry_cache: Option<String>,
+
+    /// Maximum age of the cached registry members that are used if the registry cannot be read
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "24h")]
+    issuer_registry_max_age: Duration,
+
+    /// DID the claim of the credential must be owned by
+    #[clap(long, value_parser)]
+    expected_owner: Option<String>,
+
+    /// Reject owners that are light DIDs, even upgraded ones that sign with the keys of their full
+    /// DID, i.e. for flows that need the owner to be on chain
+    #[clap(long, value_parser, default_value_t = false)]
+    require_full_did: bool,
+
+    /// Property that must be disclosed in the claim, can be given multiple times
+    #[clap(long = "require-property", value_parser)]
+    required_properties: Vec<String>,
+
+    /// Verify as of this RFC 3339 time, i.e. "2023-05-01T14:00:00+02:00". All state is read at the
+    /// last block produced at or before it, so a credential revoked later is still valid.
+    /// Needs an archive node
+    #[clap(long, value_parser = parse_valid_at, conflicts_with = "manifest")]
+    valid_at: Option<DateTime<Utc>>,
+
+    /// Probe the history of attestations that are not found, to tell removed attestations from
+    /// ones that never existed, and tell the account that submitted found ones. Needs an archive
+    /// node
+    #[clap(long, value_parser, default_value_t = false, conflicts_with_all = &["manifest", "valid-at"])]
+    explain: bool,
+
+    /// Fail credentials whose attestation is not this many blocks below the block they are
+    /// verified at, the one of --valid-at or of the quorum if given and the latest finalized block
+    /// otherwise
+    #[clap(long, value_parser, default_value_t = 0, conflicts_with = "manifest")]
+    min_confirmations: u32,
+
+    /// Accept signatures of keys that were removed from the DID document of the owner within this
+    /// many blocks before the block the credential is verified at, i.e. a key rotated right after
+    /// signing. The signature check passes with a warning that tells when the key was removed
+    #[clap(long, value_parser, conflicts_with = "manifest")]
+    key_grace_blocks: Option<u32>,
+
+    /// Write the credential, the header of the block it was verified at, proofs of the storage it
+    /// was verified against and the result to this .tar.gz, to be checked with `audit-bundle`.
+    /// Without --valid-at the lookups are pinned to the latest finalized block, a bundle of a block
+    /// that a fork dropped again could not be checked against the chain later
+    #[clap(long, value_parser = parse_bundle_path, conflicts_with_all = &["manifest", "files", "interactive", "explain", "min-confirmations", "key-grace-blocks"])]
+    export_proof_bundle: Option<String>,
+
+    /// Ask for the credential and the verification options step by step, the flags are the defaults
+    #[clap(long, value_parser, default_value_t = false, conflicts_with_all = &["porcelain", "dry-run", "no-input"])]
+    interactive: bool,
+
+    /// Challenge the credential must have been signed for
+    #[clap(short, long, value_parser)]
+    challenge: Option<String>,
+
+    /// Number of credentials of a batch that are verified at the same time
+    #[clap(long, value_parser, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Output format of batch results, porcelain also applies to single credentials
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human)]
+    output: OutputFormat,
+
+    /// Write the json report in the shape of an older version, for consumers that were not
+    /// upgraded yet. The latest version by default
     #[clap(
-        short,
         long,
-        value_parser,
-        default_value = "wss://spiritnet.kilt.io:443"
+        value_parser = clap::value_parser!(u32).range(MIN_REPORT_VERSION as i64..=REPORT_VERSION as i64),
+        default_value_t = REPORT_VERSION
     )]
-    endpoint: String,
+    report_version: u32,
+
+    /// Only verify the archive entries whose path matches one of these globs, defaults to *.json
+    #[clap(long, value_parser)]
+    include: Vec<String>,
+
+    /// Skip the archive entries whose path matches one of these globs
+    #[clap(long, value_parser)]
+    exclude: Vec<String>,
+
+    /// Maximum decompressed size of an archive entry in bytes
+    #[clap(long, value_parser, default_value_t = ArchiveOptions::default().max_entry_size)]
+    max_entry_size: u64,
+
+    /// Maximum decompressed size of all verified archive entries in bytes
+    #[clap(long, value_parser, default_value_t = ArchiveOptions::default().max_total_size)]
+    max_archive_size: u64,
+
+    /// Write the end-to-end latency histogram of a batch to this file in the HdrHistogram V2 format
+    #[clap(long, value_parser)]
+    hdr_histogram: Option<String>,
+
+    /// Print one stable, tab separated line per credential for scripts, same as `--output porcelain`:
+    /// `VALID <root hash> <owner> <attester>` or `INVALID <root hash> <error code>`
+    #[clap(long, value_parser, default_value_t = false, conflicts_with_all = &["verbose", "output"])]
+    porcelain: bool,
+
+    /// Only print the summary of a batch, without progress bar and per credential results
+    #[clap(short, long, value_parser, default_value_t = false)]
+    quiet: bool,
+
+    /// Only report the failures of a batch with their error codes and the summary,
+    /// the progress bar is still drawn
+    #[clap(long, value_parser, default_value_t = false, conflicts_with = "quiet")]
+    summary_only: bool,
+
+    /// Report the results of a batch sorted instead of in input order, for human and csv output
+    #[clap(long, value_enum)]
+    sort_by: Option<SortBy>,
+
+    /// Number like 10 or percentage like 0.5% of invalid credentials a batch may have and still
+    /// succeed. Above it the batch exits with 4, if the node failed it exits with 5 regardless
+    #[clap(long, value_parser, default_value = "0")]
+    max_failures: MaxFailures,
+
+    /// Write the report of a batch to this file instead of stdout
+    #[clap(long, value_parser)]
+    output_file: Option<String>,
+
+    /// Never wait for input on a terminal, fail if the credential would have to be typed or pasted
+    #[clap(long, value_parser, default_value_t = false)]
+    no_input: bool,
+
+    /// Also report the service endpoints the owner DIDs of valid credentials publish,
+    /// for single credentials and in the json output of batches
+    #[clap(long, value_parser, default_value_t = false)]
+    include_services: bool,
+
+    /// Keep the attested claim out of the output: verbose mode lists the property names without
+    /// their values and the json output of batches has no claims, i.e. for logs
+    #[clap(long, value_parser, default_value_t = false)]
+    redact_claims: bool,
+
+    /// Keep claim values out of all output, including errors that would quote the input: claims
+    /// are shown as [redacted] and parse errors only say where they are. Always on for `monitor`
+    #[clap(long, value_parser, default_value_t = false, global = true)]
+    redact_pii: bool,
+
+    /// Accept credentials with comments, trailing commas and the rest of JSON5, i.e. templated
+    /// ones. They are turned into plain json before the claim is normalized
+    #[clap(long, value_parser, default_value_t = false, global = true)]
+    lenient_json: bool,
+
+    /// Format of the credentials, by default YAML for `.yaml` and `.yml` files, CBOR for `.cbor`
+    /// files and json otherwise
+    #[clap(long, value_enum, global = true)]
+    input_format: Option<InputFormat>,
+
+    /// Template of the keys the claim statements are normalized with, `{ctype}` is replaced by
+    /// the ctype hash and `{property}` by the property. For forks that do not use the KILT keys
+    /// `kilt:ctype:{ctype}#{property}`, applies to verifying, explain-structure and creating
+    /// credentials and presentations
+    #[clap(long = "statement-prefix", value_parser, global = true)]
+    statement_keys: Option<StatementKeys>,
+
+    /// Read gzip and zstd input as it is, instead of decompressing input that starts like them
+    #[clap(long, value_parser, default_value_t = false, global = true)]
+    no_decompress: bool,
+
+    /// Limit of a compressed input once decompressed, in bytes. Larger input is refused
+    #[clap(long, value_parser, default_value_t = decompress::DEFAULT_MAX_SIZE, global = true, conflicts_with = "no-decompress")]
+    max_decompressed_size: u64,
+
+    /// Limit of the properties of a claim
+    #[clap(long, value_parser, default_value_t = Limits::default().max_properties, global = true)]
+    max_properties: u64,
+
+    /// Limit of the levels of objects and arrays in the claim contents
+    #[clap(long, value_parser, default_value_t = Limits::default().max_depth, global = true)]
+    max_depth: u64,
+
+    /// Limit of a single property of the claim contents as compact json, in bytes. Larger
+    /// credentials are refused before their statements are hashed
+    #[clap(long, value_parser, default_value_t = Limits::default().max_property_size, global = true)]
+    max_property_size: u64,
+
+    /// Limit of all properties of the claim contents together as compact json, in bytes
+    #[clap(long, value_parser, default_value_t = Limits::default().max_contents_size, global = true)]
+    max_contents_size: u64,
+
+    /// Limit of the claim hashes of a credential
+    #[clap(long, value_parser, default_value_t = Limits::default().max_claim_hashes, global = true)]
+    max_claim_hashes: u64,
+
+    /// Limit of the entries of the nonce map of a credential
+    #[clap(long, value_parser, default_value_t = Limits::default().max_nonces, global = true)]
+    max_nonces: u64,
+
+    /// Limit of the levels of legitimations within legitimations
+    #[clap(long, value_parser, default_value_t = Limits::default().max_legitimation_depth, global = true)]
+    max_legitimation_depth: u64,
+
+    /// Fail a batch if its credentials do not all have the same owner, i.e. credentials that are
+    /// presented together. The report includes the common owner
+    #[clap(long, value_parser, default_value_t = false)]
+    require_same_owner: bool,
+
+    /// Fail a batch if its credentials are not all signed with the same key of the same owner
+    #[clap(long, value_parser, default_value_t = false)]
+    require_same_key: bool,
+
+    /// Verify the files as one session, i.e. the presentations of a login: each must be signed for
+    /// --challenge and all by the same owner. The session only passes if all of them do
+    #[clap(long, value_parser, default_value_t = false, requires = "challenge", conflicts_with_all = &["manifest", "interactive", "dry-run", "quorum", "valid-at", "issuer-registry", "export-proof-bundle"])]
+    session: bool,
+
+    /// Only read the credentials and print what the verification would do, without connecting
+    #[clap(long, value_parser, default_value_t = false)]
+    dry_run: bool,
+
+    /// Print the collected metrics in prometheus text format before exiting
+    #[clap(long, value_parser, default_value_t = false)]
+    print_metrics: bool,
+
+    /// Append one hash chained json line per verified credential to this file: its root hash, the
+    /// decision and error code, the endpoint, the pinned block and the hash of the policy.
+    /// Claim contents are never written, `audit verify <file>` checks the chain
+    #[clap(long, value_parser)]
+    audit_log: Option<String>,
+
+    /// When the audit log is synced to disk, after every entry or once at the end of the run
+    #[clap(long, value_enum, default_value_t = FlushPolicy::Entry)]
+    audit_flush: FlushPolicy,
+
+    /// Publish the json of each result, as it is in the batch report, on nats://host[:port]/subject.
+    /// Results the server does not confirm are sent again, a run whose results cannot be
+    /// published fails like one whose node failed
+    #[cfg(feature = "nats")]
+    #[clap(long, value_parser)]
+    publish: Option<Sink>,
+
+    /// Number of results kept in memory while the --publish server is slow
+    #[cfg(feature = "nats")]
+    #[clap(long, value_parser, default_value_t = publish::BUFFER)]
+    publish_buffer: usize,
+
+    /// Export traces of the run to the OTLP/HTTP collector at this http:// URL, i.e.
+    /// "http://127.0.0.1:4318", experimental. The spans carry the outcome, error code, attester, endpoint and
+    /// cache hits of the verification, never claim contents
+    #[cfg(feature = "otel")]
+    #[clap(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_parser)]
+    otel_endpoint: Option<String>,
+
+    /// W3C traceparent of the caller, i.e. of the request a service started this run for. The
+    /// spans of the run are attached to its trace
+    #[cfg(feature = "otel")]
+    #[clap(long, env = "TRACEPARENT", value_parser)]
+    traceparent: Option<TraceContext>,
+
+    #[clap(flatten)]
+    sign: SignArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create an attestation for a credential on chain
+    Attest(issuer::AttestArgs),
+    /// Revoke or remove attestations on chain
+    Attestation(issuer::AttestationArgs),
+    /// Create credentials locally
+    Credential(holder::CredentialArgs),
+    /// Create presentations of credentials
+    Presentation(holder::PresentationArgs),
+    /// Resolve DIDs on chain
+    Did(did::DidArgs),
+    /// Show the names of ctypes, the built-in ones and those of --ctype-names
+    Ctype(ctypes::CtypeArgs),
+    /// Show what changed between two credentials and which checks it affects
+    Diff(diff::DiffArgs),
+    /// Strip data a verifier does not need from a credential
+    Minimize(minimize::MinimizeArgs),
+    /// Write a credential in a canonical form, i.e. to deduplicate archived credentials by hash
+    Canonicalize(canonicalize::CanonicalizeArgs),
+    /// Write a credential as json, YAML or CBOR
+    Convert(convert::ConvertArgs),
+    /// Describe how the claim is committed to in the root hash, for auditors
+    ExplainStructure(structure::ExplainStructureArgs),
+    /// Report suspicious but not fatal issues of a credential
+    Lint(lint::LintArgs),
+    /// Check the attestations of accepted credentials again, i.e. to report the revoked ones
+    RevocationReport(revocation::RevocationReportArgs),
+    /// Verify a set of credentials on a schedule and report status changes
+    Monitor(monitor::MonitorArgs),
+    /// Check the audit log written with --audit-log
+    Audit(audit::AuditArgs),
+    /// Check a proof bundle written with --export-proof-bundle without a node
+    AuditBundle(bundle::AuditBundleArgs),
+    /// Check the signature of a report written with --sign-report
+    Report(report::ReportArgs),
+    /// Clear the caches kept between runs
+    Cache(web3names::CacheArgs),
+    /// Check the environment and the connection to the endpoint
+    Doctor(doctor::DoctorArgs),
+}
+
+impl Command {
+    /// The subcommand talks to the endpoint
+    fn connects(&self) -> bool {
+        matches!(
+            self,
+            Command::Attest(_)
+                | Command::Attestation(_)
+                | Command::Presentation(_)
+                | Command::Did(_)
+                | Command::RevocationReport(_)
+                | Command::Report(_)
+                | Command::Doctor(_)
+        ) || matches!(self, Command::Monitor(monitor_args) if monitor_args.connects())
+    }
+}
+
+impl Args {
+    fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            format: self.input_format,
+            lenient_json: self.lenient_json,
+            max_decompressed_size: (!self.no_decompress).then_some(self.max_decompressed_size),
+            limits: Limits {
+                max_properties: self.max_properties,
+                max_depth: self.max_depth,
+                max_property_size: self.max_property_size,
+                max_contents_size: self.max_contents_size,
+                max_claim_hashes: self.max_claim_hashes,
+                max_nonces: self.max_nonces,
+                max_legitimation_depth: self.max_legitimation_depth,
+            },
+            statement_keys: self.statement_keys(),
+        }
+    }
+
+    /// The statement keys of --statement-prefix, the KILT ones by default
+    fn statement_keys(&self) -> StatementKeys {
+        self.statement_keys.clone().unwrap_or_default()
+    }
+
+    /// The network given with --network, spiritnet by default
+    fn network(&self) -> &'static Network {
+        self.network.unwrap_or(&NETWORKS[0])
+    }
+
+    /// The first endpoint given with --endpoint or the public endpoint of the network
+    fn endpoint(&self) -> Result<String, Error> {
+        match (self.endpoints.first(), self.network) {
+            (Some(endpoint), Some(network)) => {
+                network.check_endpoint(endpoint)?;
+                Ok(endpoint.clone())
+            }
+            (Some(endpoint), None) => Ok(endpoint.clone()),
+            (None, _) => Ok(self.network().endpoint.to_string()),
+        }
+    }
+
+    /// The built-in ctype names with those of --ctype-names
+    fn ctype_names(&self) -> Result<CtypeNames, Error> {
+        match &self.ctype_names {
+            Some(file) => CtypeNames::load(file),
+            None => Ok(CtypeNames::builtin()),
+        }
+    }
+
+    /// Keep claim values out of the output, servers never print them
+    fn redact_pii(&self) -> bool {
+        self.redact_pii || matches!(self.command, Some(Command::Monitor(_)))
+    }
+
+    /// Warn about an endpoint that is not encrypted, or refuse it in a server
+    fn check_transport(&self, endpoint: &str, server: bool) -> Result<(), Error> {
+        let warnings = Warnings {
+            deny: self.deny_warnings,
+        };
+        kilt::check_transport(endpoint, self.allow_insecure, server, &warnings)
+    }
+
+    /// Where attestations are read from, the indexer if there is one
+    fn verify_against(&self) -> VerifyAgainst {
+        match (self.verify_against, &self.indexer_url) {
+            (Some(against), _) => against,
+            (None, Some(_)) => VerifyAgainst::Indexer,
+            (None, None) => VerifyAgainst::Chain,
+        }
+    }
+
+    /// The endpoints of the quorum after the first one, there are none without --quorum
+    fn quorum_endpoints(&self) -> Result<&[String], Error> {
+        let others = self.endpoints.get(1..).unwrap_or_default();
+        match self.quorum {
+            None if !others.is_empty() => Err(Error::InvalidQuorum(
+                "--endpoint can only be given multiple times with --quorum".to_string(),
+            )),
+            Some(quorum) if quorum == 0 || quorum > self.endpoints.len().max(1) => {
+                Err(Error::InvalidQuorum(format!(
+                    "--quorum {} needs as many --endpoint values, {} are given",
+                    quorum,
+                    self.endpoints.len()
+                )))
+            }
+            _ => {
+                if let Some(network) = self.network {
+                    others
+                        .iter()
+                        .try_for_each(|endpoint| network.check_endpoint(endpoint))?;
+                }
+                Ok(others)
+            }
+        }
+    }
+}
+
+/// The blocks the attestation of the credential was created and revoked in if the backend knows
+/// them, they are only informative so a failed lookup is left out
+async fn attestation_history(
+    backend: &dyn ChainBackend,
+    cred: &Credential,
+) -> Option<AttestationHistory> {
+    let root_hash = hex_decode_h256(&cred.root_hash).ok()?;
+    backend.attestation_history(&root_hash).await.ok().flatten()
+}
+
+/// Tell how long the endpoints of a quorum took to answer
+fn print_timings(quorum: Option<&QuorumBackend>) {
+    for timings in quorum.map(QuorumBackend::timings).unwrap_or_default() {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        eprintln!(
+            "{}: {} queries, {:.1} ms in total, {:.1} ms the slowest",
+            timings.endpoint,
+            timings.queries,
+            millis(timings.total),
+            millis(timings.slowest)
+        );
+    }
+}
+
+fn parse_network(arg: &str) -> Result<&'static Network, String> {
+    Network::named(arg).ok_or_else(|| format!("known networks are {}", Network::names()))
+}
+
+/// Connect to an endpoint, which has to be a node of the network if one was given
+async fn connect_to(endpoint: &str, network: Option<&Network>) -> Result<KiltRuntimeApi, Error> {
+    let cli = connect(endpoint).await?;
+    if let Some(network) = network {
+        network.check_genesis(cli.client.genesis())?;
+    }
+    Ok(cli)
+}
+
+/// Parse an RFC 3339 time with any offset, times in the future have no block yet
+fn parse_valid_at(arg: &str) -> Result<DateTime<Utc>, String> {
+    let time = DateTime::parse_from_rfc3339(arg).map_err(|err| err.to_string())?;
+    if SystemTime::from(time) > SystemTime::now() {
+        return Err("the time is in the future".to_string());
+    }
+    Ok(time.into())
+}
+
+/// Bundles are gzipped tar archives, which `audit-bundle` tells by their extension
+fn parse_bundle_path(arg: &str) -> Result<String, String> {
+    match arg.ends_with(".tar.gz") || arg.ends_with(".tgz") {
+        true => Ok(arg.to_string()),
+        false => Err("the bundle has to end in .tar.gz or .tgz".to_string()),
+    }
+}
+
+/// Run a subcommand, only the ones that need the chain connect to the endpoint
+async fn run_command(command: &Command, args: &Args, w3n: &Web3NameCache) -> Result<(), Error> {
+    let endpoint = &args.endpoint()?;
+    let network = args.network;
+    if command.connects() {
+        args.check_transport(endpoint, matches!(command, Command::Monitor(_)))?;
+    }
+    match command {
+        Command::Attest(attest_args) => {
+            issuer::attest(&connect_to(endpoint, network).await?, attest_args).await
+        }
+        Command::Attestation(attestation_args) => {
+            issuer::attestation(&connect_to(endpoint, network).await?, attestation_args).await
+        }
+        Command::Credential(credential_args) => {
+            holder::credential(credential_args, &args.statement_keys())
+        }
+        Command::Presentation(presentation_args) => {
+            holder::presentation(presentation_args, endpoint, &args.statement_keys()).await
+        }
+        Command::Did(did_args) => {
+            let cli = connect_to(endpoint, network).await?;
+            did::did(&Web3NameBackend::new(&cli, w3n), did_args).await
+        }
+        Command::Ctype(ctype_args) => ctypes::run(&args.ctype_names()?, ctype_args),
+        Command::Diff(diff_args) => diff::run(diff_args, args.redact_pii()),
+        Command::Minimize(minimize_args) => minimize::run(minimize_args, &args.statement_keys()),
+        Command::Canonicalize(canonicalize_args) => {
+            canonicalize::run(canonicalize_args, &args.statement_keys())
+        }
+        Command::ExplainStructure(structure_args) => {
+            structure::run(structure_args, args.redact_pii(), &args.statement_keys())
+        }
+        Command::Convert(convert_args) => convert::run(convert_args, &args.parse_options()),
+        Command::Lint(lint_args) => lint::run(lint_args, &args.parse_options()),
+        Command::RevocationReport(report_args) => {
+            revocation::run(&connect_to(endpoint, network).await?, report_args).await
+        }
+        Command::Monitor(monitor_args) => {
+            monitor::run(
+                endpoint,
+                args.network().issuers,
+                monitor_args,
+                &args.statement_keys(),
+            )
+            .await
+        }
+        Command::Audit(audit_args) => audit::run(audit_args),
+        Command::AuditBundle(bundle_args) => bundle::run(bundle_args).await,
+        Command::Report(report_args) => {
+            report::run(&connect_to(endpoint, network).await?, report_args).await
+        }
+        Command::Cache(cache_args) => web3names::run(w3n, cache_args),
+        Command::Doctor(doctor_args) => {
+            doctor::run(endpoint, network, doctor_args, &args.parse_options()).await
+        }
+    }
+}
+
+/// Prints each passed step of a verification
+struct VerbosePrinter<'a> {
+    root_hash_derived: bool,
+    key_inferred: bool,
+    /// The verifier expects something of the claim
+    requirements: bool,
+    checks: &'a CheckRecorder,
+    out: Mutex<&'a mut (dyn Write + Send)>,
+}
+
+impl VerbosePrinter<'_> {
+    fn print(&self, text: &str) -> Result<(), Error> {
+        Ok(self.out.lock().unwrap().write_all(text.as_bytes())?)
+    }
+}
+
+impl VerificationObserver for VerbosePrinter<'_> {
+    fn on_step(&self, step: CheckStep, status: StepStatus<'_>) {
+        self.checks.on_step(step, status);
+        if !matches!(status, StepStatus::Passed { .. }) {
+            return;
+        }
+        let line = match step {
+            CheckStep::ClaimContents => "[1/4] ✅ Claim contents are valid",
+            CheckStep::RootHash if self.root_hash_derived => {
+                "[2/4] ✅ Root hash is derived from the claim hashes, the credential has none"
+            }
+            CheckStep::RootHash => "[2/4] ✅ Root hash is valid",
+            CheckStep::Signature if self.key_inferred => {
+                "[3/4] ✅ Signature is valid, the credential names no key so it was inferred"
+            }
+            CheckStep::Signature => "[3/4] ✅ Signature is valid",
+            CheckStep::Attestation => "[4/4] ✅ Attestation is valid",
+            CheckStep::Requirements if self.requirements => "✅ Claim meets the requirements",
+            CheckStep::Challenge | CheckStep::Requirements => return,
+        };
+        // the verification goes on if the output is gone, its result is what counts
+        self.print(&format!("{}\n", line)).ok();
+    }
+}
+
+/// Run all checks one after another and write each passed step and the attested claim to `out`
+#[allow(clippy::too_many_arguments)]
+async fn verify_verbose(
+    cred: &Credential,
+    cli: &dyn ChainBackend,
+    allowed_issuers: &[&str],
+    challenge: Option<&str>,
+    requirements: &ClaimRequirements,
+    redact_claims: bool,
+    ctype_names: &CtypeNames,
+    checks: &CheckRecorder,
+    out: &mut (dyn Write + Send),
+) -> Result<(), Error> {
+    let printer = VerbosePrinter {
+        root_hash_derived: cred.root_hash_derived,
+        key_inferred: cred.key_inferred(),
+        requirements: requirements.owner.is_some() || !requirements.properties.is_empty(),
+        checks,
+        out: Mutex::new(out),
+    };
+    cred.verify_observed(cli, allowed_issuers, challenge, &printer)
+        .await?;
+    if let Some(attestation) = cli
+        .attestation(&hex_decode_h256(&cred.root_hash)?, None)
+        .await?
+    {
+        printer.print(&issuer::describe_deposit(cli, &attestation).await?)?;
+    }
+
+    // Check what the verifier expects of the claim
+    progress::observe(&printer, CheckStep::Requirements, || {
+        cred.check_requirements(requirements)
+    })?;
+    printer.print(&format!(
+        "CType: {}\nAttested claim:\n{}",
+        ctype_names.describe(&cred.claim.ctype_hash),
+        credential::describe_claim(&cred.claim, redact_claims)
+    ))
+}
+
+/// Tell a removed attestation from one that never existed, other errors are kept
+async fn explain(
+    cli: &KiltRuntimeApi,
+    token: &CancellationToken,
+    root_hash: &str,
+    err: Error,
+) -> Error {
+    match err {
+        Error::AttestationNotFound => cancellable(token, async {
+            Ok::<_, Error>(history::explain_missing(cli, cli, root_hash).await)
+        })
+        .await
+        .unwrap_or_else(|err| err),
+        err => err,
+    }
+}
+
+/// The account that submitted the attestation of the credential and the fee it paid, they are
+/// only informative so a failed search is a warning
+async fn submission(
+    cli: &KiltRuntimeApi,
+    token: &CancellationToken,
+    backend: &dyn ChainBackend,
+    root_hash: &str,
+) -> Option<String> {
+    let res = cancellable(token, async {
+        match submission::find_submission(cli, backend, root_hash).await? {
+            Some(found) => submission::describe_submission(backend, &found)
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    })
+    .await;
+    res.unwrap_or_else(|err| {
+        eprintln!(
+            "Warning: cannot find the submitter of the attestation: {}",
+            err
+        );
+        None
+    })
+}
+
+/// Fail a valid credential whose attestation is not `--min-confirmations` blocks below the block
+/// it was verified at, the pinned block or the latest finalized one
+async fn confirmed(
+    cli: &KiltRuntimeApi,
+    token: &CancellationToken,
+    backend: &dyn ChainBackend,
+    block: Option<ResolvedBlock>,
+    root_hash: &str,
+    min: u32,
+) -> Result<(), Error> {
+    if min == 0 {
+        return Ok(());
+    }
+    cancellable(token, async {
+        let number = block.map(|block| block.number);
+        history::check_confirmations(cli, backend, &hex_decode_h256(root_hash)?, number, min).await
+    })
+    .await
+}
+
+/// Read the members of the issuer registry, or the cached ones if it cannot be read
+async fn load_registry(
+    args: &Args,
+    token: &CancellationToken,
+    endpoint: &str,
+    did: &str,
+    limiter: Option<&RpcLimiter>,
+) -> Result<IssuerRegistry, Error> {
+    let resolved = cancellable(token, async {
+        let cli = connect(endpoint).await?;
+        let queries = QueryBackend::new(endpoint, &cli).limited(limiter, Priority::Background);
+        registry::resolve(&queries, did, SystemTime::now()).await
+    })
+    .await;
+    let cache = match &args.issuer_registry_cache {
+        Some(file) => PathBuf::from(file),
+        None => registry::default_cache_file(did),
+    };
+    registry::resolve_or_cached(
+        did,
+        resolved,
+        &cache,
+        args.issuer_registry_max_age,
+        SystemTime::now(),
+    )
+}
+
+/// Append the decisions to the audit log, if there is one, and sync it
+fn audit(
+    log: &mut Option<AuditLog>,
+    records: impl IntoIterator<Item = AuditRecord>,
+) -> Result<(), Error> {
+    if let Some(log) = log {
+        for record in records {
+            log.append(record, SystemTime::now())?;
+        }
+        log.flush()?;
+    }
+    Ok(())
+}
+
+/// Write the report of a batch to the output file or stdout, signed and with the histogram and
+/// metrics if asked for
+fn write_report(
+    args: &Args,
+    report: &BatchReport,
+    options: &BatchOptions,
+    signer: Option<&ReportSigner>,
+) -> Result<(), Error> {
+    let mut out: Box<dyn Write> = match &args.output_file {
+        Some(file) => Box::new(std::fs::File::create(file)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match signer {
+        Some(signer) => {
+            let mut json = Vec::new();
+            report.write(&mut json, options)?;
+            let mut json = serde_json::from_slice(&json)?;
+            signer.sign(&mut json)?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+        }
+        None => report.write(&mut out, options)?,
+    }
+    if let Some(file) = &args.hdr_histogram {
+        report.stats().write_hdr_histogram(file)?;
+    }
+    if args.print_metrics {
+        print!("{}", metrics::gather()?);
+    }
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> ExitCode {
     // parse args
     let args = Args::parse();
 
-    // Connect to chain
-    let cli = connect(&args.endpoint).await?;
+    let token = CancellationToken::new();
+    let w3n_cache = args
+        .w3n_cache
+        .clone()
+        .unwrap_or_else(Web3NameCache::default_file);
+    let w3n = Web3NameCache::open(&w3n_cache, args.w3n_cache_ttl);
+    #[cfg(feature = "otel")]
+    let exporter = match start_tracing(&args) {
+        Ok(exporter) => exporter,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            return ExitCode::from(err.exit_code());
+        }
+    };
+    let span = tracing::info_span!("run", endpoint = %args.endpoint().unwrap_or_default());
+    let res = with_timeout(
+        args.timeout,
+        &token,
+        run(&args, &token, &w3n).instrument(span),
+    )
+    .await;
+    // the run span is closed, so all spans of the run are exported
+    #[cfg(feature = "otel")]
+    if let Some(exporter) = exporter {
+        exporter.finish().await;
+    }
+    // the cache only saves lookups, a run does not fail because it cannot be written
+    if let Err(err) = w3n.save() {
+        eprintln!(
+            "Warning: cannot write web3name cache {}: {}",
+            w3n_cache.display(),
+            err
+        );
+    }
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if args.redact_pii() => {
+            let err = err.without_pii();
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(err.exit_code())
+        }
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
 
-    // Read credential from stdin
-    let cred = read_credential(&args.file)?;
+/// Export the spans of the run with --otel-endpoint, in the trace of --traceparent if given
+#[cfg(feature = "otel")]
+fn start_tracing(args: &Args) -> Result<Option<Arc<OtlpExporter>>, Error> {
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
 
-    if args.verbose {
-        // Check claim contents
-        cred.check_claim_contents()?;
-        println!("[1/4] ✅ Claim contents are valid");
+    let endpoint = match &args.otel_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let exporter = Arc::new(OtlpExporter::start(endpoint)?);
+    let layer = OtelLayer::new(exporter.clone()).parent(args.traceparent);
+    tracing::subscriber::set_global_default(Registry::default().with(layer))
+        .map_err(|err| Error::Telemetry(err.to_string()))?;
+    Ok(Some(exporter))
+}
 
-        // Check if root hash is valid
-        cred.check_root_hash()?;
-        println!("[2/4] ✅ Root hash is valid");
+/// Run the subcommand or the verification, stopping early once the token is cancelled
+async fn run(args: &Args, token: &CancellationToken, w3n: &Web3NameCache) -> Result<(), Error> {
+    if let Some(command) = &args.command {
+        return cancellable(token, run_command(command, args, w3n)).await;
+    }
+    if args.session {
+        let files = match &args.files[..] {
+            [] => std::slice::from_ref(args.uri.as_ref().unwrap_or(&args.file)),
+            files => files,
+        };
+        return verify_session(args, files, token, w3n).await;
+    }
+    match &args.files[..] {
+        [] => verify(args, args.uri.as_ref().unwrap_or(&args.file), token, w3n).await,
+        [file] => verify(args, file, token, w3n).await,
+        files => verify_files(args, files, token, w3n).await,
+    }
+}
+
+/// Verify the files one after another and report the errors of each. A run that is cancelled
+/// stops, otherwise it fails with the first error of a node or the number of failed files
+async fn verify_files(
+    args: &Args,
+    files: &[String],
+    token: &CancellationToken,
+    w3n: &Web3NameCache,
+) -> Result<(), Error> {
+    let headers = !args.porcelain && args.output == OutputFormat::Human;
+    let mut errors = Vec::new();
+    for file in files {
+        if headers {
+            println!("{}:", file);
+        }
+        match verify(args, file, token, w3n).await {
+            Ok(()) => {}
+            Err(err @ (Error::Timeout | Error::Aborted)) => return Err(err),
+            Err(err) => {
+                let err = if args.redact_pii() {
+                    err.without_pii()
+                } else {
+                    err
+                };
+                eprintln!("Error: {}: {:?}", file, err);
+                errors.push(err);
+            }
+        }
+    }
+    match errors.iter().position(Error::is_infrastructure) {
+        Some(at) => Err(errors.swap_remove(at)),
+        None if errors.is_empty() => Ok(()),
+        None => Err(Error::InvalidCredentials(errors.len())),
+    }
+}
+
+/// Verify the presentations of the files as one session for the challenge
+async fn verify_session(
+    args: &Args,
+    files: &[String],
+    token: &CancellationToken,
+    w3n: &Web3NameCache,
+) -> Result<(), Error> {
+    let challenge = args
+        .challenge
+        .as_deref()
+        .expect("--session requires --challenge");
+    if let Some(owner) = &args.expected_owner {
+        get_did_account_id(owner)?;
+    }
+    // a directory or archive holds presentations of the session as well
+    let parse = args.parse_options();
+    let mut sources = Vec::new();
+    let mut credentials = Vec::new();
+    for file in files {
+        match PlanInput::read(file, &ArchiveOptions::default(), &parse)? {
+            PlanInput::Single(cred) => {
+                sources.push(file.clone());
+                credentials.push(*cred);
+            }
+            PlanInput::Batch(inputs) => {
+                for input in inputs {
+                    credentials.push(parse.parse(&input.source, &input.data)?);
+                    sources.push(input.source);
+                }
+            }
+            PlanInput::Manifest(_) => unreachable!("manifests are not read from files"),
+        }
+    }
+    let issuers: Vec<String> = if args.issuers.is_empty() {
+        let issuers = args.network().issuers.iter();
+        issuers.map(|s| s.to_string()).collect()
+    } else {
+        args.issuers.clone()
+    };
+    let allowed_issuers = issuers
+        .iter()
+        .map(|issuer| utils::normalize_issuer(issuer))
+        .collect::<Result<Vec<_>, _>>()?;
+    let allowed_issuers: Vec<&str> = allowed_issuers.iter().map(String::as_str).collect();
+    let requirements = ClaimRequirements {
+        owner: args.expected_owner.clone(),
+        properties: args.required_properties.clone(),
+        full_did: args.require_full_did,
+    };
+
+    let endpoint = args.endpoint()?;
+    args.check_transport(&endpoint, false)?;
+    let limiter = args
+        .rpc_concurrency
+        .map(|concurrency| RpcLimiter::new(concurrency, args.rpc_background_concurrency));
+    let throttle = Throttle::default();
+    let cli = cancellable(token, connect_to(&endpoint, args.network)).await?;
+    let queries = QueryBackend::new(&endpoint, &cli)
+        .query_timeout(Some(args.query_timeout))
+        .retries(args.query_retries)
+        .limited(limiter.as_ref(), Priority::Interactive)
+        .throttled(Some(&throttle));
+    let names = Web3NameBackend::new(&queries, w3n);
+    let verified = session::verify_session(
+        &names,
+        &credentials,
+        &allowed_issuers,
+        challenge,
+        &requirements,
+    );
+    let mut report = cancellable(token, verified.map(Ok::<_, Error>)).await?;
+    if args.redact_pii() {
+        for result in report.results.iter_mut() {
+            result.result =
+                std::mem::replace(&mut result.result, Ok(())).map_err(Error::without_pii);
+        }
+    }
+
+    let policy_hash = audit::policy_hash(
+        &allowed_issuers,
+        Some(challenge),
+        &requirements,
+        &Default::default(),
+    );
+    let mut audit_log = args
+        .audit_log
+        .as_ref()
+        .map(|file| AuditLog::open(Path::new(file), args.audit_flush))
+        .transpose()?;
+    let records = report.results.iter().map(|result| {
+        AuditRecord::new(
+            Some(&result.root_hash),
+            &result.result,
+            &endpoint,
+            None,
+            &policy_hash,
+        )
+    });
+    audit(&mut audit_log, records)?;
+    let mut out: Box<dyn Write> = match &args.output_file {
+        Some(file) => Box::new(std::fs::File::create(file)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let output = if args.porcelain {
+        OutputFormat::Porcelain
+    } else {
+        args.output
+    };
+    report.write(&mut out, &sources, output)?;
+    report.into_result()
+}
+
+/// Verify the credentials of one file, deep link or manifest
+async fn verify(
+    args: &Args,
+    file: &str,
+    token: &CancellationToken,
+    w3n: &Web3NameCache,
+) -> Result<(), Error> {
+    if args.no_input && file == "stdin" && std::io::stdin().is_terminal() {
+        return Err(Error::NoInput);
+    }
+    args.quorum_endpoints()?;
+    let ctype_names = args.ctype_names()?;
+    if let Some(owner) = &args.expected_owner {
+        get_did_account_id(owner)?;
+    }
+
+    let issuers: Vec<String> = if args.issuers.is_empty() && args.issuer_registry.is_none() {
+        let issuers = args.network().issuers.iter();
+        issuers.map(|s| s.to_string()).collect()
+    } else {
+        args.issuers.clone()
+    };
+    let mut answers = WizardAnswers {
+        file: file.to_string(),
+        credential: None,
+        endpoint: args.endpoint()?,
+        allowed_issuers: issuers,
+        requirements: ClaimRequirements {
+            owner: args.expected_owner.clone(),
+            properties: args.required_properties.clone(),
+            full_did: args.require_full_did,
+        },
+    };
+    if args.interactive {
+        wizard::install_abort_handler()?;
+        answers = wizard::run(&answers)?;
+        eprintln!(
+            "\nTo verify the same way without questions run\n  {}\n",
+            answers.command_line()
+        );
+    }
+
+    // Read the credentials and resolve what to do with them
+    let source = args.manifest.as_ref().unwrap_or(&answers.file);
+    let input = match (answers.credential, &args.manifest) {
+        (Some(credential), _) => PlanInput::Single(Box::new(credential)),
+        (None, Some(manifest)) => {
+            PlanInput::Manifest(manifest::read_manifest(manifest, &answers.endpoint)?)
+        }
+        (None, None) => PlanInput::read(
+            &answers.file,
+            &ArchiveOptions {
+                include: args.include.clone(),
+                exclude: args.exclude.clone(),
+                max_entry_size: args.max_entry_size,
+                max_total_size: args.max_archive_size,
+            },
+            &args.parse_options(),
+        )?,
+    };
+    let allowed_issuers = answers
+        .allowed_issuers
+        .iter()
+        .map(|issuer| utils::normalize_issuer(issuer))
+        .collect::<Result<Vec<_>, _>>()?;
+    let allowed_issuers: Vec<&str> = allowed_issuers.iter().map(String::as_str).collect();
+    let mut plan = VerificationPlan::new(
+        source,
+        input,
+        &answers.endpoint,
+        &allowed_issuers,
+        args.challenge.as_deref(),
+        args.concurrency,
+        answers.requirements,
+    );
+    plan.valid_at = args.valid_at;
+    plan.min_confirmations = args.min_confirmations;
+    plan.issuer_registry = args.issuer_registry.clone();
+    plan.parse = args.parse_options();
+    if args.dry_run {
+        print!("{}", plan);
+        return Ok(());
+    }
+    let endpoints = match &plan.input {
+        PlanInput::Manifest(inputs) => inputs.iter().map(|input| &input.endpoint).collect(),
+        _ => vec![&plan.endpoint],
+    };
+    endpoints
+        .into_iter()
+        .chain(args.quorum_endpoints()?)
+        .chain(args.fallback_endpoints.iter())
+        .chain(args.did_resolver_url.iter())
+        .chain(args.indexer_url.iter())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .try_for_each(|endpoint| args.check_transport(endpoint, false))?;
+    let limiter = args
+        .rpc_concurrency
+        .map(|concurrency| RpcLimiter::new(concurrency, args.rpc_background_concurrency));
+    // an endpoint that throttles one query is paused for all of them
+    let throttle = Throttle::default();
+    let registry = match &plan.issuer_registry {
+        Some(did) => {
+            let registry =
+                load_registry(args, token, &plan.endpoint, did, limiter.as_ref()).await?;
+            eprintln!("{}", registry.describe());
+            plan.allowed_issuers
+                .extend(registry.members.iter().cloned());
+            Some(registry)
+        }
+        None => None,
+    };
+    // the entries of a manifest are given the registries of their trust policies below
+    let mut manifest = match &mut plan.input {
+        PlanInput::Manifest(inputs) => Some(std::mem::take(inputs)),
+        _ => None,
+    };
+    let allowed_issuers = plan.allowed_issuers();
+    let challenge = plan.challenge.as_deref();
+    // the entries of a manifest are checked without them
+    let chain_policy = ChainPolicy {
+        key_grace_blocks: args.key_grace_blocks,
+        min_confirmations: plan.min_confirmations,
+    };
+    let policy_hash = audit::policy_hash(
+        &allowed_issuers,
+        challenge,
+        &plan.requirements,
+        &chain_policy,
+    );
+    let mut audit_log = args
+        .audit_log
+        .as_ref()
+        .map(|file| AuditLog::open(Path::new(file), args.audit_flush))
+        .transpose()?;
+    let output = if args.porcelain {
+        OutputFormat::Porcelain
+    } else {
+        args.output
+    };
+    // only the json report can carry a signature
+    if args.sign.sign_report && output != OutputFormat::Json {
+        return Err(Error::InvalidReport(
+            "--sign-report needs --output json".to_string(),
+        ));
+    }
+    let signer = args.sign.signer()?;
+
+    let options = BatchOptions {
+        allowed_issuers: &allowed_issuers,
+        challenge,
+        requirements: &plan.requirements,
+        concurrency: plan.concurrency,
+        output,
+        quiet: args.quiet,
+        summary_only: args.summary_only,
+        sort_by: args.sort_by,
+        include_services: args.include_services,
+        redact_claims: args.redact_claims || args.redact_pii(),
+        redact_pii: args.redact_pii(),
+        parse: args.parse_options(),
+        require_same_owner: args.require_same_owner,
+        require_same_key: args.require_same_key,
+        cancel: token.clone(),
+        ctype_names: &ctype_names,
+        report_version: args.report_version,
+    };
+
+    // A manifest connects to each of its endpoints once it is needed
+    if let Some(inputs) = &mut manifest {
+        let pool = ConnectionPool::new(
+            inputs,
+            Box::new(|endpoint: &str| connect(endpoint.to_string()).err_into().boxed_local()),
+        )
+        .queries(Some(args.query_timeout), args.query_retries)
+        .limited(limiter.as_ref())
+        .throttled(&throttle);
+        let cached = |did: &str, resolved| {
+            let cache = registry::default_cache_file(did);
+            let max_age = args.issuer_registry_max_age;
+            let registry =
+                registry::resolve_or_cached(did, resolved, &cache, max_age, SystemTime::now())?;
+            eprintln!("{}", registry.describe());
+            Ok(registry)
+        };
+        let resolve = manifest::resolve_trust_policies(&pool, inputs, SystemTime::now(), cached);
+        cancellable(token, resolve).await?;
+        let mut report = manifest::verify_manifest(&pool, inputs, &options).await;
+        report.registry = registry;
+        report.throttled = throttle.throttled();
+        // manifest entries can have their own issuers and owner, so their own policy
+        let records = inputs
+            .iter()
+            .zip(report.results.iter())
+            .map(|(input, result)| {
+                let policy_hash = audit::policy_hash(
+                    &input.allowed_issuers(options.allowed_issuers),
+                    challenge,
+                    &input.entry.requirements(options.requirements),
+                    &Default::default(),
+                );
+                AuditRecord::new(
+                    result.root_hash.as_deref(),
+                    &result.result,
+                    &input.endpoint,
+                    None,
+                    &policy_hash,
+                )
+            });
+        audit(&mut audit_log, records)?;
+        write_report(args, &report, &options, signer.as_ref())?;
+        let published = publish(args, &report.results, &ctype_names).await;
+        return unpublished(report.into_result(args.max_failures), published);
+    }
+
+    // Connect to chain
+    let cli = cancellable(token, connect_to(&plan.endpoint, args.network)).await?;
+    // a fallback that cannot be connected is left out, the endpoint is enough to start with
+    let mut fallbacks = Vec::new();
+    for endpoint in args.fallback_endpoints.iter() {
+        match cancellable(token, connect_to(endpoint, args.network)).await {
+            Ok(fallback) => fallbacks.push((endpoint, fallback)),
+            Err(err @ (Error::Timeout | Error::NetworkMismatch(_))) => return Err(err),
+            Err(err) => eprintln!("Warning: cannot connect to fallback {}: {}", endpoint, err),
+        }
+    }
+    let network = args.network;
+    let pool = NodePool::new(
+        &plan.endpoint,
+        cli.clone(),
+        args.rpc_connections,
+        Box::new(move |endpoint: &str| {
+            let endpoint = endpoint.to_string();
+            async move { connect_to(&endpoint, network).await }.boxed()
+        }),
+    );
+    let queries = fallbacks.iter().fold(
+        QueryBackend::new(&plan.endpoint, &pool)
+            .query_timeout(Some(args.query_timeout))
+            .retries(args.query_retries)
+            .limited(limiter.as_ref(), Priority::Interactive)
+            .throttled(Some(&throttle)),
+        |queries, (endpoint, fallback)| queries.fallback(endpoint, fallback),
+    );
+    // the other endpoints of a quorum all have to be connected, they are asked every question
+    let mut members = Vec::new();
+    for endpoint in args.quorum_endpoints()? {
+        let member = cancellable(token, connect_to(endpoint, args.network)).await?;
+        members.push((endpoint, member));
+    }
+    let member_queries: Vec<_> = members
+        .iter()
+        .map(|(endpoint, member)| {
+            QueryBackend::new(endpoint, member)
+                .query_timeout(Some(args.query_timeout))
+                .retries(args.query_retries)
+                .limited(limiter.as_ref(), Priority::Interactive)
+                .throttled(Some(&throttle))
+        })
+        .collect();
+    let names = Web3NameBackend::new(&queries, w3n);
+    let indexer = match (args.verify_against(), &args.indexer_url) {
+        (VerifyAgainst::Chain, _) | (_, None) => None,
+        (against, Some(url)) => Some(IndexerBackend::new(&names, url, against)?),
+    };
+    let attestations: &dyn ChainBackend = match &indexer {
+        Some(indexer) => indexer,
+        None => &names,
+    };
+    let resolver = args
+        .did_resolver_url
+        .as_deref()
+        .map(|url| ResolverBackend::new(attestations, url))
+        .transpose()?;
+    let chain: &dyn ChainBackend = match &resolver {
+        Some(resolver) => resolver,
+        None => attestations,
+    };
+
+    // A quorum reads the same block from all endpoints, the one at the time or the latest they have
+    let mut quorum = None;
+    if let Some(size) = args.quorum {
+        let number = match plan.valid_at {
+            Some(time) => Some(
+                cancellable(token, history::resolve_block(&cli, time))
+                    .await?
+                    .number,
+            ),
+            None => None,
+        };
+        let histories: Vec<(&str, &dyn BlockHistory)> = std::iter::once((&plan.endpoint, &cli))
+            .chain(members.iter().map(|(endpoint, member)| (*endpoint, member)))
+            .map(|(endpoint, cli)| (endpoint.as_str(), cli as &dyn BlockHistory))
+            .collect();
+        let block = cancellable(token, quorum::pin_block(&histories, number)).await?;
+        eprintln!(
+            "Verifying at block {} on {} endpoints",
+            block,
+            histories.len()
+        );
+        let backends = std::iter::once((plan.endpoint.as_str(), &queries as &dyn ChainBackend))
+            .chain(
+                members
+                    .iter()
+                    .zip(member_queries.iter())
+                    .map(|((endpoint, _), queries)| {
+                        (endpoint.as_str(), queries as &dyn ChainBackend)
+                    }),
+            )
+            .collect();
+        quorum = Some((QuorumBackend::new(backends, size, block.hash), block));
+    }
+    let quorum_names = quorum
+        .as_ref()
+        .map(|(quorum, block)| (Web3NameBackend::new(quorum, w3n), *block));
 
-        // Check if the owner signed the credential
-        cred.check_signature(&cli).await?;
-        println!("[3/4] ✅ Signature is valid");
+    // Pin all lookups to the block that was current at the time
+    let pinned;
+    let (backend, block): (&dyn ChainBackend, _) = match (&quorum_names, plan.valid_at) {
+        (Some((quorum_names, block)), _) => (quorum_names, Some(*block)),
+        (None, Some(time)) => {
+            let block = cancellable(token, history::resolve_block(&cli, time)).await?;
+            eprintln!("Verifying at block {}", block);
+            pinned = PinnedBackend::new(chain, block.hash);
+            (&pinned, Some(block))
+        }
+        (None, None) if args.export_proof_bundle.is_some() => {
+            let finalized = cancellable(token, cli.finalized_block()).await?;
+            let block = cancellable(token, history::block_at(&cli, finalized)).await?;
+            eprintln!("Verifying at block {}", block);
+            pinned = PinnedBackend::new(chain, block.hash);
+            (&pinned, Some(block))
+        }
+        (None, None) => (chain, None),
+    };
+    let pinned_block = block.as_ref().map(|block| utils::hex_encode(block.hash));
+    let grace = args.key_grace_blocks.map(|blocks| {
+        KeyGraceBackend::new(backend, &cli, blocks).at(block.as_ref().map(|block| block.number))
+    });
+    let backend: &dyn ChainBackend = match &grace {
+        Some(grace) => grace,
+        None => backend,
+    };
 
-        // Check if the attestation of the credential is written to chain and not revoked
-        cred.check_attestation(&cli, &ALLOWED_ISSUERS).await?;
-        println!("[4/4] ✅ Attestation is valid");
+    let cred = match &plan.input {
+        PlanInput::Single(cred) => cred,
+        PlanInput::Batch(inputs) => {
+            if args.export_proof_bundle.is_some() {
+                return Err(Error::InvalidBundle(
+                    "a bundle holds a single credential, not a batch".to_string(),
+                ));
+            }
+            let mut report = batch::verify_batch(backend, inputs, &options).await;
+            report.registry = registry;
+            report.throttled = throttle.throttled();
+            if let Some(resolver) = &resolver {
+                for result in report.results.iter_mut() {
+                    result.resolved_via_http = result
+                        .owner
+                        .as_deref()
+                        .is_some_and(|owner| resolver.resolved(owner));
+                }
+            }
+            if let Some(grace) = &grace {
+                grace.mark_removed_keys(&mut report.results);
+            }
+            if args.explain {
+                for result in report.results.iter_mut() {
+                    if let (Err(Error::AttestationNotFound), Some(root_hash)) =
+                        (&result.result, &result.root_hash)
+                    {
+                        let err = explain(&cli, token, root_hash, Error::AttestationNotFound).await;
+                        result.result = Err(err);
+                        result.fail_check(CheckStep::Attestation);
+                    }
+                }
+            }
+            if plan.min_confirmations > 0 {
+                for result in report.results.iter_mut() {
+                    if let (Ok(()), Some(root_hash)) = (&result.result, &result.root_hash) {
+                        let min = plan.min_confirmations;
+                        result.result =
+                            confirmed(&cli, token, backend, block, root_hash, min).await;
+                        result.fail_check(CheckStep::Attestation);
+                    }
+                }
+            }
+            let records = report.results.iter().map(|result| {
+                AuditRecord::new(
+                    result.root_hash.as_deref(),
+                    &result.result,
+                    &plan.endpoint,
+                    pinned_block.clone(),
+                    &policy_hash,
+                )
+            });
+            audit(&mut audit_log, records)?;
+            print_timings(quorum.as_ref().map(|(quorum, _)| quorum));
+            write_report(args, &report, &options, signer.as_ref())?;
+            let published = publish(args, &report.results, &ctype_names).await;
+            return unpublished(report.into_result(args.max_failures), published);
+        }
+        PlanInput::Manifest(_) => unreachable!("manifests are verified above"),
+    };
+
+    // where the DID document of the owner came from if it was not the chain
+    let resolved_owner = || {
+        resolver
+            .as_ref()
+            .filter(|resolver| resolver.resolved(&cred.claim.owner))
+            .map(ResolverBackend::url)
+    };
+    let timer = metrics::time_verification();
+    let checks = CheckRecorder::default();
+    // the first block the signing key was missing at, if it was accepted within the grace window
+    let key_removed_at = || {
+        let report = checks.report(cred, challenge, &plan.requirements);
+        grace
+            .as_ref()
+            .and_then(|grace| grace.removed_at(&cred.claimer_signature.key_uri, &report))
+    };
+    let res = if args.verbose {
+        if args.lenient_json {
+            println!("Parsed leniently: the credential was read as JSON5");
+        }
+        let res = cancellable(
+            token,
+            verify_verbose(
+                cred,
+                backend,
+                &allowed_issuers,
+                challenge,
+                &plan.requirements,
+                args.redact_claims || args.redact_pii(),
+                &ctype_names,
+                &checks,
+                &mut std::io::stdout(),
+            ),
+        )
+        .await;
+        if let Some(url) = resolved_owner() {
+            println!(
+                "Owner DID document {} {}, not read from the chain",
+                RESOLVED_VIA, url
+            );
+        }
+        if let Some(block) = key_removed_at() {
+            println!(
+                "Signing key was removed from the DID document at block {}, accepted within --key-grace-blocks",
+                block
+            );
+        }
+        if let Some(history) = attestation_history(backend, cred).await {
+            println!("Attested at block {}", history.created);
+            if let Some(block) = history.revoked {
+                println!("Revoked at block {}", block);
+            }
+        }
+        if args.explain {
+            if let Some(text) = submission(&cli, token, backend, &cred.root_hash).await {
+                print!("{}", text);
+            }
+        }
+        if let Err(Error::KeyNotFound {
+            keys_changed_at: Some(block),
+            ..
+        }) = &res
+        {
+            println!(
+                "Hint: a key of the DID was added at block {}, the signing key may have been replaced then. Verify with --valid-at a time before it or accept recently removed keys with --key-grace-blocks",
+                block
+            );
+        }
+        let min = plan.min_confirmations;
+        // the attestation is checked again, the last outcome is the one reported
+        match res {
+            Ok(()) => {
+                let confirmed = confirmed(&cli, token, backend, block, &cred.root_hash, min);
+                progress::observe_async(&checks, CheckStep::Attestation, confirmed).await
+            }
+            Err(Error::AttestationNotFound) if args.explain => {
+                let err = explain(&cli, token, &cred.root_hash, Error::AttestationNotFound).await;
+                progress::observe(&checks, CheckStep::Attestation, || Err(err))
+            }
+            res => res,
+        }
     } else {
-        cred.verify(&cli, &ALLOWED_ISSUERS).await?;
-        println!("✅ Credential is valid");
+        let mut timings = Default::default();
+        let res = cancellable(token, async {
+            let attester = cred
+                .verify_timed(backend, &allowed_issuers, challenge, &mut timings, &checks)
+                .await?;
+            progress::observe(&checks, CheckStep::Requirements, || {
+                cred.check_requirements(&plan.requirements)
+            })?;
+            Ok::<_, Error>(attester)
+        })
+        .await;
+        let min = plan.min_confirmations;
+        let res = match res {
+            Ok(attester) => {
+                let confirmed = confirmed(&cli, token, backend, block, &cred.root_hash, min);
+                progress::observe_async(&checks, CheckStep::Attestation, confirmed)
+                    .await
+                    .map(|_| attester)
+            }
+            Err(Error::AttestationNotFound) if args.explain => {
+                let err = explain(&cli, token, &cred.root_hash, Error::AttestationNotFound).await;
+                progress::observe(&checks, CheckStep::Attestation, || Err(err))
+            }
+            res => res,
+        };
+        if output == OutputFormat::Porcelain {
+            match &res {
+                Ok(attester) => println!(
+                    "{}",
+                    porcelain::valid(&cred.root_hash, &cred.claim.owner, attester)
+                ),
+                Err(err) => println!("{}", porcelain::invalid(Some(&cred.root_hash), err)),
+            }
+        } else if args.interactive {
+            print!(
+                "{}",
+                wizard::result_screen(backend, cred, &res, args.redact_pii()).await
+            );
+        } else if res.is_ok() {
+            match block {
+                Some(block) => println!("✅ Credential was valid at block {}", block),
+                None => println!("✅ Credential is valid"),
+            }
+            println!("  ctype: {}", ctype_names.describe(&cred.claim.ctype_hash));
+            if cred.key_inferred() {
+                println!("  key inferred: the credential names no key, it was checked against the authentication key of the owner");
+            }
+            if cred.light_owner() {
+                println!(
+                    "  light DID owner: signed with a key of the full DID {}",
+                    DidUrl::parse(&cred.claim.owner)?.full_did()
+                );
+            }
+            if args.lenient_json {
+                println!("  parsed leniently: the credential was read as JSON5");
+            }
+            if let Some(url) = resolved_owner() {
+                println!(
+                    "  {}: the DID document of the owner was read from {}, not from the chain",
+                    RESOLVED_VIA, url
+                );
+            }
+            if let Some(block) = key_removed_at() {
+                println!("  key removed: the signing key was removed from the DID document at block {}, accepted within --key-grace-blocks", block);
+            }
+            if let Some(history) = attestation_history(backend, cred).await {
+                println!("  attested at block {}", history.created);
+            }
+            if args.explain {
+                if let Some(text) = submission(&cli, token, backend, &cred.root_hash).await {
+                    print!("{}", text);
+                }
+            }
+            if args.include_services {
+                let owner = get_did_account_id(&cred.claim.owner)?;
+                let services = did::service_endpoints(backend, &owner, None).await?;
+                println!("  services of the owner:");
+                print!("{}", did::format_services(&services));
+            }
+        }
+        res.map(|_| ())
+    };
+    timer.observe_duration();
+    metrics::record_outcome(&res);
+    audit(
+        &mut audit_log,
+        [AuditRecord::new(
+            Some(&cred.root_hash),
+            &res,
+            &plan.endpoint,
+            pinned_block,
+            &policy_hash,
+        )],
+    )?;
+    print_timings(quorum.as_ref().map(|(quorum, _)| quorum));
+
+    // a failed node or lookup is no result an auditor could check
+    let checked = !matches!(&res, Err(err) if err.is_infrastructure() || matches!(err, Error::Timeout | Error::Aborted));
+    if let (Some(path), Some(block), true) = (&args.export_proof_bundle, block, checked) {
+        let report = BundleReport::new(cred, &plan.endpoint, &policy_hash, &res);
+        let export = bundle::export(
+            &cli,
+            cred,
+            block.hash,
+            &allowed_issuers,
+            challenge,
+            &plan.requirements,
+            report,
+            path,
+        );
+        cancellable(token, export).await?;
+        eprintln!("Wrote the proof bundle to {}", path);
+    }
+
+    // the result as it is in a batch report
+    let checks = checks.report(cred, challenge, &plan.requirements);
+    let mut result = BatchResult::verified(source, cred, checks, res);
+    result.resolved_via_http = resolved_owner().is_some();
+    result.key_removed_at = key_removed_at();
+    if result.result.is_ok() && !(args.redact_claims || args.redact_pii()) {
+        result.claims = Some(cred.claim.flatten());
     }
+    let published = publish(args, std::slice::from_ref(&result), &ctype_names).await;
 
+    if args.print_metrics {
+        print!("{}", metrics::gather()?);
+    }
+
+    unpublished(result.result, published)
+}
+
+/// Publish the results with --publish and wait until the server confirmed them
+#[cfg(feature = "nats")]
+async fn publish(
+    args: &Args,
+    results: &[BatchResult],
+    ctype_names: &CtypeNames,
+) -> Result<(), Error> {
+    let sink = match &args.publish {
+        Some(sink) => sink.clone(),
+        None => return Ok(()),
+    };
+    let publisher = Publisher::start(sink, args.publish_buffer);
+    for result in results {
+        publisher.publish(result.to_json(ctype_names)?).await;
+    }
+    publisher.finish().await
+}
+
+#[cfg(not(feature = "nats"))]
+async fn publish(_: &Args, _: &[BatchResult], _: &CtypeNames) -> Result<(), Error> {
     Ok(())
 }
+
+/// A run whose results were not all published fails with that, unless it failed because the node
+/// failed or it was cut short
+fn unpublished(res: Result<(), Error>, published: Result<(), Error>) -> Result<(), Error> {
+    match (res, published) {
+        (Err(err), _)
+            if err.is_infrastructure() || matches!(err, Error::Timeout | Error::Aborted) =>
+        {
+            Err(err)
+        }
+        (_, Err(err)) => Err(err),
+        (res, Ok(())) => res,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+    }
+
+    #[tokio::test]
+    async fn test_verify_verbose_redacted() {
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+        let cred = utils::parse_credential(&std::fs::read(file).unwrap()).unwrap();
+        let backend = kilt_verify::mock::MockBackend::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/scenarios/spiritnet-example.json"
+        ))
+        .unwrap();
+        let socialkyc = "did:kilt:4pnfkRn5UurBJTW92d9TaVLR2CqJdY4z5HPjrEbpGyBykare";
+        for redact in [false, true] {
+            let mut out = Vec::new();
+            let res = verify_verbose(
+                &cred,
+                &backend,
+                &[socialkyc],
+                None,
+                &Default::default(),
+                redact,
+                &Default::default(),
+                &Default::default(),
+                &mut out,
+            )
+            .await;
+            assert!(res.is_ok(), "{:?}", res);
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.contains("[4/4] ✅ Attestation is valid"), "{}", out);
+            assert!(out.contains("Attested claim:"), "{}", out);
+            assert_eq!(out.contains("tino@kilt.io"), !redact, "{}", out);
+        }
+    }
+}
@@ -0,0 +1,683 @@
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::OnceCell;
+use tracing::Instrument;
+
+use crate::{
+    backend::{ChainBackend, CountingBackend, QueryBackend},
+    batch::{self, Allowlist, BatchInput, BatchOptions, BatchReport, BatchResult},
+    credential::ClaimRequirements,
+    errors::Error,
+    kilt::Network,
+    progress::VerificationObserver,
+    registry::{self, IssuerRegistry},
+    throttle::{Priority, RpcLimiter, Throttle},
+    utils::{get_did_account_id, normalize_issuer},
+};
+
+/// One credential of a manifest and the verification options that apply to it only.
+/// Options that are not given are taken from the flags of the run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// Credential file, relative to the manifest
+    pub path: String,
+    /// Name of a known network, i.e. "spiritnet" or "peregrine"
+    pub network: Option<String>,
+    pub endpoint: Option<String>,
+    pub expected_owner: Option<String>,
+    /// DIDs or KILT addresses, normalized to DIDs when the manifest is read
+    pub allowed_issuers: Option<Vec<String>>,
+    /// DID of an issuer registry whose members are trusted along with the allowed issuers of the
+    /// entry, like `--issuer-registry` does for the run
+    pub trust_policy: Option<String>,
+}
+
+impl ManifestEntry {
+    // the endpoint given by the entry, `None` if the entry has neither a network nor an endpoint
+    fn entry_endpoint(&self) -> Result<Option<&str>, Error> {
+        match (&self.network, &self.endpoint) {
+            (Some(_), Some(_)) => Err(Error::InvalidManifest(format!(
+                "{} has both a network and an endpoint",
+                self.path
+            ))),
+            (Some(network), None) => Network::named(network)
+                .map(|network| Some(network.endpoint))
+                .ok_or_else(|| {
+                    Error::InvalidManifest(format!(
+                        "unknown network {} of {}, known networks are {}",
+                        network,
+                        self.path,
+                        Network::names()
+                    ))
+                }),
+            (None, endpoint) => Ok(endpoint.as_deref()),
+        }
+    }
+
+    // the entry has its own issuers instead of the ones of the run
+    fn overrides_issuers(&self) -> bool {
+        self.allowed_issuers.is_some() || self.trust_policy.is_some()
+    }
+
+    /// The requirements of the run with the expected owner of the entry
+    pub fn requirements(&self, defaults: &ClaimRequirements) -> ClaimRequirements {
+        ClaimRequirements {
+            owner: self
+                .expected_owner
+                .clone()
+                .or_else(|| defaults.owner.clone()),
+            properties: defaults.properties.clone(),
+            full_did: defaults.full_did,
+        }
+    }
+}
+
+/// A credential of a manifest, read into memory with the endpoint it is verified against
+#[derive(Debug, Clone)]
+pub struct ManifestInput {
+    pub entry: ManifestEntry,
+    pub endpoint: String,
+    pub input: BatchInput,
+    /// Registry of the trust policy of the entry, see `resolve_trust_policies`
+    pub registry: Option<IssuerRegistry>,
+}
+
+impl ManifestInput {
+    /// The issuers of the entry and the members of its trust policy,
+    /// or the ones of the run if the entry has neither
+    pub fn allowed_issuers<'a>(&'a self, defaults: &[&'a str]) -> Vec<&'a str> {
+        if !self.entry.overrides_issuers() {
+            return defaults.to_vec();
+        }
+        let mut issuers: Vec<&str> = self
+            .entry
+            .allowed_issuers
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        for member in self.registry.iter().flat_map(|registry| &registry.members) {
+            if !issuers.contains(&member.as_str()) {
+                issuers.push(member);
+            }
+        }
+        issuers
+    }
+
+    /// The issuers the credential is checked against and where they come from, for the report
+    pub fn allowlist(&self, defaults: &[&str]) -> Allowlist {
+        let mut issuers: Vec<String> = self
+            .allowed_issuers(defaults)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        issuers.sort();
+        Allowlist {
+            overridden: self.entry.overrides_issuers(),
+            trust_policy: self.entry.trust_policy.clone(),
+            issuers,
+        }
+    }
+}
+
+impl AsRef<BatchInput> for ManifestInput {
+    fn as_ref(&self) -> &BatchInput {
+        &self.input
+    }
+}
+
+/// Read a manifest, a json array of entries, and the credential files it lists.
+/// Entries without a network or endpoint are verified against `default_endpoint`.
+/// The source of each input is its path as given in the manifest. The issuers, trust policies and
+/// expected owners of all entries are checked here, so a bad DID fails the manifest before anything
+/// is looked up.
+pub fn read_manifest(file: &str, default_endpoint: &str) -> Result<Vec<ManifestInput>, Error> {
+    let entries: Vec<ManifestEntry> = serde_json::from_slice(&std::fs::read(file)?)?;
+    let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+    entries
+        .into_iter()
+        .map(|mut entry| {
+            let endpoint = entry
+                .entry_endpoint()?
+                .unwrap_or(default_endpoint)
+                .to_string();
+            if let Some(issuers) = &mut entry.allowed_issuers {
+                for issuer in issuers.iter_mut() {
+                    *issuer = normalize_issuer(issuer)?;
+                }
+            }
+            if let Some(registry) = &mut entry.trust_policy {
+                *registry = normalize_issuer(registry)?;
+            }
+            if let Some(owner) = &entry.expected_owner {
+                get_did_account_id(owner)?;
+            }
+            let data = std::fs::read(dir.join(&entry.path)).map_err(|err| {
+                Error::InvalidManifest(format!("cannot read {}: {}", entry.path, err))
+            })?;
+            Ok(ManifestInput {
+                input: BatchInput {
+                    source: entry.path.clone(),
+                    data,
+                },
+                endpoint,
+                entry,
+                registry: None,
+            })
+        })
+        .collect()
+}
+
+/// Read the registry of each trust policy once, from the endpoint of the first entry naming it,
+/// and give it to every entry naming it. `cached` gets the result of `registry::resolve` and may
+/// replace a registry that could not be read, see `registry::resolve_or_cached`.
+/// A registry that cannot be had fails the manifest before any credential is verified.
+pub async fn resolve_trust_policies<B: ChainBackend>(
+    pool: &ConnectionPool<'_, B>,
+    inputs: &mut [ManifestInput],
+    now: SystemTime,
+    cached: impl Fn(&str, Result<IssuerRegistry, Error>) -> Result<IssuerRegistry, Error>,
+) -> Result<(), Error> {
+    let mut registries: HashMap<String, IssuerRegistry> = HashMap::new();
+    for input in inputs.iter_mut() {
+        let did = match &input.entry.trust_policy {
+            Some(did) => did,
+            None => continue,
+        };
+        if !registries.contains_key(did) {
+            let resolved = match pool.get(&input.endpoint).await {
+                Ok(backend) => registry::resolve(backend, did, now).await,
+                Err(err) => Err(err),
+            };
+            registries.insert(did.clone(), cached(did, resolved)?);
+        }
+        input.registry = Some(registries[did].clone());
+    }
+    Ok(())
+}
+
+/// Connects to an endpoint, to a node or to a mock backend in tests
+pub type Connector<'a, B> = Box<dyn Fn(&str) -> LocalBoxFuture<'a, Result<B, Error>> + 'a>;
+
+/// One connection per distinct endpoint of a manifest, shared by all of its credentials.
+/// Nothing is connected before the first credential of an endpoint is verified.
+pub struct ConnectionPool<'a, B> {
+    connect: Connector<'a, B>,
+    connections: HashMap<String, OnceCell<B>>,
+    /// Connection attempts by endpoint
+    attempts: HashMap<String, AtomicUsize>,
+    connects: AtomicUsize,
+    observer: Option<&'a dyn VerificationObserver>,
+    /// Timeout and retries of the storage queries, see `QueryBackend`
+    query_timeout: Option<Duration>,
+    query_retries: usize,
+    limiter: Option<&'a RpcLimiter>,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, B> ConnectionPool<'a, B> {
+    pub fn new(inputs: &[ManifestInput], connect: Connector<'a, B>) -> Self {
+        ConnectionPool {
+            connect,
+            connections: inputs
+                .iter()
+                .map(|input| (input.endpoint.clone(), OnceCell::new()))
+                .collect(),
+            attempts: inputs
+                .iter()
+                .map(|input| (input.endpoint.clone(), AtomicUsize::new(0)))
+                .collect(),
+            connects: AtomicUsize::new(0),
+            observer: None,
+            query_timeout: None,
+            query_retries: 0,
+            limiter: None,
+            throttle: None,
+        }
+    }
+
+    /// Bound every storage query and try the ones that fail again on the same endpoint
+    pub fn queries(self, query_timeout: Option<Duration>, query_retries: usize) -> Self {
+        ConnectionPool {
+            query_timeout,
+            query_retries,
+            ..self
+        }
+    }
+
+    /// Share the limit of queries in flight of the run between all endpoints
+    pub fn limited(self, limiter: Option<&'a RpcLimiter>) -> Self {
+        ConnectionPool { limiter, ..self }
+    }
+
+    /// Share the pauses of throttled endpoints of the run between all credentials
+    pub fn throttled(self, throttle: &'a Throttle) -> Self {
+        ConnectionPool {
+            throttle: Some(throttle),
+            ..self
+        }
+    }
+
+    /// Tell the observer whenever an endpoint is connected again after a failed attempt
+    pub fn observed(self, observer: &'a dyn VerificationObserver) -> Self {
+        ConnectionPool {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// The connection to the endpoint, connecting if it is the first use.
+    /// A failed connection is tried again by the next credential of the endpoint.
+    pub async fn get(&self, endpoint: &str) -> Result<&B, Error> {
+        let cell = self.connections.get(endpoint).ok_or_else(|| {
+            Error::InvalidManifest(format!("{} is not an endpoint of the manifest", endpoint))
+        })?;
+        cell.get_or_try_init(|| {
+            self.connects.fetch_add(1, Ordering::Relaxed);
+            let attempt = self.attempts[endpoint].fetch_add(1, Ordering::Relaxed) + 1;
+            if let (Some(observer), true) = (self.observer, attempt > 1) {
+                observer.on_retry(endpoint, attempt);
+            }
+            (self.connect)(endpoint)
+        })
+        .await
+    }
+
+    /// Number of connection attempts so far
+    pub fn connects(&self) -> usize {
+        self.connects.load(Ordering::Relaxed)
+    }
+}
+
+/// Verify all credentials of a manifest like a batch, each against its own endpoint and with
+/// its own options. Every result records the endpoint that served it.
+pub async fn verify_manifest<B: ChainBackend>(
+    pool: &ConnectionPool<'_, B>,
+    inputs: &[ManifestInput],
+    options: &BatchOptions<'_>,
+) -> BatchReport {
+    let rpc_calls = AtomicUsize::new(0);
+    let results = batch::run_batch(inputs, options, |input| {
+        let rpc_calls = &rpc_calls;
+        async move {
+            let allowed_issuers = input.allowed_issuers(options.allowed_issuers);
+            let requirements = input.entry.requirements(options.requirements);
+            let entry_options = BatchOptions {
+                allowed_issuers: &allowed_issuers,
+                requirements: &requirements,
+                cancel: options.cancel.clone(),
+                parse: options.parse.clone(),
+                ..*options
+            };
+            let mut result = match pool.get(&input.endpoint).await {
+                Ok(backend) => {
+                    // every attempt of a query is an RPC call
+                    let counting = CountingBackend::new(backend);
+                    let backend = QueryBackend::new(&input.endpoint, &counting)
+                        .query_timeout(pool.query_timeout)
+                        .retries(pool.query_retries)
+                        .limited(pool.limiter, Priority::Interactive)
+                        .throttled(pool.throttle);
+                    let result = batch::verify_input(&backend, &input.input, &entry_options).await;
+                    rpc_calls.fetch_add(counting.calls(), Ordering::Relaxed);
+                    result
+                }
+                Err(err) => BatchResult::failed(&input.input.source, err),
+            };
+            result.endpoint = Some(input.endpoint.clone());
+            result.allowlist = Some(input.allowlist(options.allowed_issuers));
+            result
+        }
+        .instrument(tracing::info_span!("manifest_entry", endpoint = %input.endpoint))
+    })
+    .await;
+    BatchReport::new(results, rpc_calls.into_inner(), options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        batch::OutputFormat,
+        ctypes::CtypeNames,
+        fixtures,
+        kilt::{
+            runtime_types::{
+                did::service_endpoints::DidEndpoint,
+                frame_support::storage::bounded_vec::BoundedVec,
+            },
+            NETWORKS,
+        },
+        mock::MockBackend,
+        progress::{CheckStep, StepStatus},
+        registry::REGISTRY_SERVICE_TYPE,
+        utils::get_did_account_id,
+    };
+    use futures::FutureExt;
+    use std::sync::Mutex;
+    use subxt::sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
+    use tokio_util::sync::CancellationToken;
+
+    fn write_manifest(name: &str, manifest: serde_json::Value) -> String {
+        let dir = std::env::temp_dir().join(format!("kilt-verify-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let credential = serde_json::to_vec(&fixtures::credential()).unwrap();
+        std::fs::write(dir.join("cred.json"), credential).unwrap();
+        let file = dir.join("manifest.json");
+        std::fs::write(&file, manifest.to_string()).unwrap();
+        file.display().to_string()
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        let file = write_manifest(
+            "read",
+            serde_json::json!([
+                {"path": "cred.json"},
+                {"path": "cred.json", "network": "peregrine"},
+                {"path": "cred.json", "endpoint": "ws://127.0.0.1:9944"},
+            ]),
+        );
+        let inputs = read_manifest(&file, "wss://default").unwrap();
+        let endpoints: Vec<_> = inputs.iter().map(|input| input.endpoint.as_str()).collect();
+        assert_eq!(
+            endpoints,
+            vec!["wss://default", NETWORKS[1].endpoint, "ws://127.0.0.1:9944"]
+        );
+        assert_eq!(inputs[0].input.source, "cred.json");
+
+        for (name, entry, reason) in [
+            (
+                "both",
+                serde_json::json!({"path": "cred.json", "network": "spiritnet", "endpoint": "ws://x"}),
+                "both",
+            ),
+            (
+                "unknown",
+                serde_json::json!({"path": "cred.json", "network": "kusama"}),
+                "unknown network kusama",
+            ),
+            (
+                "missing",
+                serde_json::json!({"path": "missing.json"}),
+                "cannot read missing.json",
+            ),
+        ] {
+            let file = write_manifest(name, serde_json::json!([entry]));
+            let res = read_manifest(&file, "wss://default");
+            assert!(
+                matches!(&res, Err(Error::InvalidManifest(msg)) if msg.contains(reason)),
+                "{:?}",
+                res
+            );
+        }
+        // issuers are normalized to DIDs
+        let attester = fixtures::attester_did();
+        let address = attester.strip_prefix("did:kilt:").unwrap();
+        let file = write_manifest(
+            "issuers",
+            serde_json::json!([{"path": "cred.json", "allowed_issuers": [address]}]),
+        );
+        let inputs = read_manifest(&file, "").unwrap();
+        assert_eq!(
+            inputs[0].entry.allowed_issuers,
+            Some(vec![attester.clone()])
+        );
+        let file = write_manifest(
+            "policy",
+            serde_json::json!([{"path": "cred.json", "trust_policy": address}]),
+        );
+        let inputs = read_manifest(&file, "").unwrap();
+        assert_eq!(inputs[0].entry.trust_policy, Some(attester));
+        let file = write_manifest(
+            "bad-policy",
+            serde_json::json!([{"path": "cred.json"}, {"path": "cred.json", "trust_policy": "4abc"}]),
+        );
+        assert!(matches!(
+            read_manifest(&file, ""),
+            Err(Error::InvalidIssuerEntry(_))
+        ));
+        let file = write_manifest(
+            "bad-issuer",
+            serde_json::json!([{"path": "cred.json", "allowed_issuers": ["4abc"]}]),
+        );
+        assert!(matches!(
+            read_manifest(&file, ""),
+            Err(Error::InvalidIssuerEntry(_))
+        ));
+
+        // the owner with the generic substrate prefix
+        let substrate = get_did_account_id(&fixtures::attester_did())
+            .unwrap()
+            .to_ss58check_with_version(Ss58AddressFormat::custom(42));
+        let file = write_manifest(
+            "foreign-owner",
+            serde_json::json!([{"path": "cred.json", "expected_owner": format!("did:kilt:{}", substrate)}]),
+        );
+        assert!(matches!(
+            read_manifest(&file, ""),
+            Err(Error::ForeignAddress { prefix: 42, .. })
+        ));
+
+        let file = write_manifest(
+            "field",
+            serde_json::json!([{"path": "cred.json", "owner": "x"}]),
+        );
+        assert!(matches!(read_manifest(&file, ""), Err(Error::Serde(_))));
+    }
+
+    #[derive(Default)]
+    struct Retries(Mutex<Vec<(String, usize)>>);
+
+    impl VerificationObserver for Retries {
+        fn on_step(&self, _step: CheckStep, _status: StepStatus<'_>) {}
+
+        fn on_retry(&self, what: &str, attempt: usize) {
+            self.0.lock().unwrap().push((what.to_string(), attempt));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_manifest() {
+        let owner = fixtures::owner_key().did();
+        let attester = fixtures::attester_did();
+        let file = write_manifest(
+            "verify",
+            serde_json::json!([
+                {"path": "cred.json", "endpoint": "ws://attested"},
+                {"path": "cred.json", "endpoint": "ws://empty"},
+                {"path": "cred.json", "endpoint": "ws://attested", "allowed_issuers": [owner]},
+                {"path": "cred.json", "endpoint": "ws://attested", "expected_owner": attester},
+                {"path": "cred.json", "endpoint": "ws://down"},
+            ]),
+        );
+        let inputs = read_manifest(&file, "").unwrap();
+        let retries = Retries::default();
+        let pool = ConnectionPool::new(
+            &inputs,
+            Box::new(|endpoint: &str| {
+                let backend = match endpoint {
+                    "ws://attested" => Ok(fixtures::backend()),
+                    "ws://empty" => Ok(MockBackend::default()),
+                    _ => Err(Error::Timeout),
+                };
+                async move { backend }.boxed_local()
+            }),
+        )
+        .observed(&retries);
+
+        let requirements = ClaimRequirements::default();
+        let allowed_issuers = [attester.as_str()];
+        let options = BatchOptions {
+            allowed_issuers: &allowed_issuers,
+            challenge: None,
+            requirements: &requirements,
+            concurrency: 3,
+            output: OutputFormat::Json,
+            quiet: true,
+            summary_only: false,
+            sort_by: None,
+            include_services: false,
+            redact_claims: false,
+            redact_pii: false,
+            parse: Default::default(),
+            require_same_owner: false,
+            require_same_key: false,
+            cancel: CancellationToken::new(),
+            ctype_names: &CtypeNames::builtin(),
+            report_version: batch::REPORT_VERSION,
+        };
+        let report = verify_manifest(&pool, &inputs, &options).await;
+        let summary: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| (r.endpoint.as_deref().unwrap(), format!("{:?}", r.result)))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("ws://attested", "Ok(())".to_string()),
+                ("ws://empty", "Err(DidNotFound)".to_string()),
+                ("ws://attested", "Err(InvalidIssuer)".to_string()),
+                ("ws://attested", "Err(UnexpectedOwner)".to_string()),
+                ("ws://down", "Err(Timeout)".to_string()),
+            ]
+        );
+        // one connection per endpoint that was used
+        assert_eq!(pool.connects(), 3);
+        assert!(report.rpc_calls > 0);
+        // an endpoint that failed is tried again
+        assert!(retries.0.lock().unwrap().is_empty());
+        assert!(pool.get("ws://down").await.is_err());
+        assert_eq!(
+            *retries.0.lock().unwrap(),
+            vec![("ws://down".to_string(), 2)]
+        );
+
+        let mut out = Vec::new();
+        report.write(&mut out, &options).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["results"][1]["endpoint"], "ws://empty");
+    }
+
+    #[tokio::test]
+    async fn test_trust_policies() {
+        let attester = fixtures::attester_did();
+        // the owner is a registry that lists the attester
+        let registry = fixtures::owner_key().did();
+        let file = write_manifest(
+            "policies",
+            serde_json::json!([
+                {"path": "cred.json"},
+                {"path": "cred.json", "trust_policy": registry},
+                {"path": "cred.json", "trust_policy": registry, "allowed_issuers": [registry]},
+                {"path": "cred.json", "allowed_issuers": [registry]},
+            ]),
+        );
+        let mut inputs = read_manifest(&file, "ws://node").unwrap();
+        let connect = || {
+            let mut backend = fixtures::backend();
+            backend.latest_mut().insert_service_endpoint(
+                get_did_account_id(&registry).unwrap(),
+                &DidEndpoint {
+                    id: BoundedVec(b"members".to_vec()),
+                    service_types: BoundedVec(vec![BoundedVec(
+                        REGISTRY_SERVICE_TYPE.as_bytes().to_vec(),
+                    )]),
+                    urls: BoundedVec(vec![BoundedVec(attester.as_bytes().to_vec())]),
+                },
+            );
+            backend
+        };
+        let pool = ConnectionPool::new(
+            &inputs,
+            Box::new(|_: &str| {
+                let backend = connect();
+                async move { Ok(backend) }.boxed_local()
+            }),
+        );
+        let resolved = AtomicUsize::new(0);
+        resolve_trust_policies(&pool, &mut inputs, SystemTime::now(), |_, registry| {
+            resolved.fetch_add(1, Ordering::Relaxed);
+            registry
+        })
+        .await
+        .unwrap();
+        // every registry is read once
+        assert_eq!(resolved.into_inner(), 1);
+
+        // the run does not trust the attester, the trust policies do
+        let requirements = ClaimRequirements::default();
+        let options = BatchOptions {
+            allowed_issuers: &["did:kilt:4pnfkRn5UurBJTW92d9TaVLR2CqJdY4z5HPjrEbpGyBykare"],
+            challenge: None,
+            requirements: &requirements,
+            concurrency: 3,
+            output: OutputFormat::Json,
+            quiet: true,
+            summary_only: false,
+            sort_by: None,
+            include_services: false,
+            redact_claims: false,
+            redact_pii: false,
+            parse: Default::default(),
+            require_same_owner: false,
+            require_same_key: false,
+            cancel: CancellationToken::new(),
+            ctype_names: &CtypeNames::builtin(),
+            report_version: batch::REPORT_VERSION,
+        };
+        let report = verify_manifest(&pool, &inputs, &options).await;
+        let results: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| format!("{:?}", r.result))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                "Err(InvalidIssuer)",
+                "Ok(())",
+                "Ok(())",
+                "Err(InvalidIssuer)"
+            ]
+        );
+
+        let mut out = Vec::new();
+        report.write(&mut out, &options).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let allowlists: Vec<_> = json["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["allowlist"].clone())
+            .collect();
+        let mut both = vec![attester.clone(), registry.clone()];
+        both.sort();
+        assert_eq!(
+            allowlists,
+            vec![
+                serde_json::json!({"overridden": false, "issuers": [options.allowed_issuers[0]]}),
+                serde_json::json!({"overridden": true, "trustPolicy": registry, "issuers": [attester]}),
+                serde_json::json!({"overridden": true, "trustPolicy": registry, "issuers": both}),
+                serde_json::json!({"overridden": true, "issuers": [registry]}),
+            ]
+        );
+
+        // a registry that cannot be read fails before anything is verified
+        let mut inputs = read_manifest(&file, "ws://node").unwrap();
+        let pool = ConnectionPool::new(
+            &inputs,
+            Box::new(|_: &str| async { Ok(fixtures::backend()) }.boxed_local()),
+        );
+        let res = resolve_trust_policies(&pool, &mut inputs, SystemTime::now(), |_, r| r).await;
+        assert!(matches!(res, Err(Error::IssuerRegistry(_))), "{:?}", res);
+    }
+}
@@ -0,0 +1,280 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramTimer, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::errors::Error;
+
+lazy_static! {
+    /// Number of verifications by outcome
+    static ref VERIFICATIONS: IntCounterVec = register_int_counter_vec!(
+        "kilt_verify_verifications_total",
+        "Number of credential verifications by outcome",
+        &["outcome"]
+    )
+    .expect("metric can be registered");
+
+    /// End-to-end latency of a verification
+    static ref VERIFICATION_DURATION: Histogram = register_histogram!(
+        "kilt_verify_verification_duration_seconds",
+        "End-to-end duration of a credential verification"
+    )
+    .expect("metric can be registered");
+
+    /// Latency of the individual checks
+    static ref CHECK_DURATION: HistogramVec = register_histogram_vec!(
+        "kilt_verify_check_duration_seconds",
+        "Duration of the individual verification checks",
+        &["check"]
+    )
+    .expect("metric can be registered");
+
+    /// Whether we are connected to a chain endpoint
+    static ref CHAIN_CONNECTED: IntGauge = register_int_gauge!(
+        "kilt_verify_chain_connected",
+        "Whether the chain connection is established (1) or not (0)"
+    )
+    .expect("metric can be registered");
+
+    /// Number of monitored credentials by status
+    static ref MONITORED: IntGaugeVec = register_int_gauge_vec!(
+        "kilt_verify_monitored_credentials",
+        "Number of monitored credentials by their last status",
+        &["status"]
+    )
+    .expect("metric can be registered");
+
+    /// Number of monitored credentials that were valid and are not anymore
+    static ref DOWNGRADES: IntCounterVec = register_int_counter_vec!(
+        "kilt_verify_monitor_downgrades_total",
+        "Number of monitored credentials that stopped being valid by their new status",
+        &["status"]
+    )
+    .expect("metric can be registered");
+
+    /// Number of web3name lookups by whether the cache answered them
+    static ref W3N_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "kilt_verify_w3n_cache_lookups_total",
+        "Number of web3name lookups by cache result (hit or miss)",
+        &["result"]
+    )
+    .expect("metric can be registered");
+
+    /// Number of chain queries waiting for a permit of the RPC limit
+    static ref RPC_QUEUE_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        "kilt_verify_rpc_queue_depth",
+        "Number of chain queries waiting for a permit of --rpc-concurrency by class",
+        &["class"]
+    )
+    .expect("metric can be registered");
+
+    /// Time chain queries waited for a permit of the RPC limit
+    static ref RPC_WAIT: HistogramVec = register_histogram_vec!(
+        "kilt_verify_rpc_wait_seconds",
+        "Time chain queries waited for a permit of --rpc-concurrency by class",
+        &["class"]
+    )
+    .expect("metric can be registered");
+
+    /// Number of connections of the node pool that are up
+    static ref POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "kilt_verify_pool_connections",
+        "Number of connections of --rpc-connections that are up"
+    )
+    .expect("metric can be registered");
+
+    /// Number of connections of the node pool that were connected after the first
+    static ref POOL_RECONNECTS: IntCounter = register_int_counter!(
+        "kilt_verify_pool_reconnects_total",
+        "Number of connections of --rpc-connections that were connected or replaced"
+    )
+    .expect("metric can be registered");
+
+    /// Number of results sent to the message queue by whether the server confirmed them
+    static ref PUBLISHED: IntCounterVec = register_int_counter_vec!(
+        "kilt_verify_published_results_total",
+        "Number of results sent to the message queue by result (published or failed)",
+        &["result"]
+    )
+    .expect("metric can be registered");
+
+    /// Number of chain queries the RPC provider throttled by endpoint
+    static ref RPC_THROTTLED: IntCounterVec = register_int_counter_vec!(
+        "kilt_verify_rpc_throttled_total",
+        "Number of chain queries the RPC provider answered with a rate limit by endpoint",
+        &["endpoint"]
+    )
+    .expect("metric can be registered");
+}
+
+/// The individual checks of a verification, used as the `check` label
+#[derive(Debug, Clone, Copy)]
+pub enum Check {
+    ClaimContents,
+    RootHash,
+    Signature,
+    Attestation,
+}
+
+impl Check {
+    pub const ALL: [Check; 4] = [
+        Check::ClaimContents,
+        Check::RootHash,
+        Check::Signature,
+        Check::Attestation,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Check::ClaimContents => "claim_contents",
+            Check::RootHash => "root_hash",
+            Check::Signature => "signature",
+            Check::Attestation => "attestation",
+        }
+    }
+}
+
+/// Durations of the checks of a single verification, checks that didn't run have none
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CheckTimings([Option<Duration>; 4]);
+
+impl CheckTimings {
+    pub fn record(&mut self, check: Check, duration: Duration) {
+        self.0[check as usize] = Some(duration);
+    }
+
+    pub fn get(&self, check: Check) -> Option<Duration> {
+        self.0[check as usize]
+    }
+}
+
+/// Map a verification result to a bounded set of outcome labels
+fn outcome(res: &Result<(), Error>) -> &'static str {
+    match res {
+        Ok(()) => "valid",
+        Err(Error::AttestationRevoked) => "revoked",
+        Err(Error::AttestationRemoved { .. }) => "removed",
+        Err(Error::InvalidSignature) => "bad_signature",
+        Err(Error::InvalidIssuer) => "untrusted_issuer",
+        Err(_) => "error",
+    }
+}
+
+/// Start a timer for a single check, the duration is recorded when the timer is dropped
+pub fn time_check(check: Check) -> HistogramTimer {
+    CHECK_DURATION
+        .with_label_values(&[check.label()])
+        .start_timer()
+}
+
+/// Start a timer for a whole verification
+pub fn time_verification() -> HistogramTimer {
+    VERIFICATION_DURATION.start_timer()
+}
+
+/// Count a finished verification by its outcome
+pub fn record_outcome(res: &Result<(), Error>) {
+    VERIFICATIONS.with_label_values(&[outcome(res)]).inc();
+}
+
+/// Set the chain connection state
+pub fn set_connected(connected: bool) {
+    CHAIN_CONNECTED.set(connected as i64);
+}
+
+/// Set the number of monitored credentials of each status, statuses without credentials are dropped
+pub fn set_monitored(counts: &BTreeMap<&str, usize>) {
+    MONITORED.reset();
+    for (status, count) in counts {
+        MONITORED.with_label_values(&[status]).set(*count as i64);
+    }
+}
+
+/// Count a monitored credential that stopped being valid, the status is an error code
+pub fn record_downgrade(status: &str) {
+    DOWNGRADES.with_label_values(&[status]).inc();
+}
+
+/// Count a web3name lookup that was answered by the cache or had to query the chain
+pub fn record_w3n_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    W3N_LOOKUPS.with_label_values(&[result]).inc();
+}
+
+/// Count results the message queue confirmed or that were given up after retrying
+pub fn record_published(count: usize, confirmed: bool) {
+    let result = if confirmed { "published" } else { "failed" };
+    PUBLISHED.with_label_values(&[result]).inc_by(count as u64);
+}
+
+/// Count a query the provider at the endpoint throttled
+pub fn record_rpc_throttled(endpoint: &str) {
+    RPC_THROTTLED.with_label_values(&[endpoint]).inc();
+}
+
+/// The number of queries of the class waiting for a permit of the RPC limit
+pub fn rpc_queue_depth(class: &str) -> IntGauge {
+    RPC_QUEUE_DEPTH.with_label_values(&[class])
+}
+
+/// Record how long a query of the class waited for its permit
+pub fn record_rpc_wait(class: &str, wait: Duration) {
+    RPC_WAIT
+        .with_label_values(&[class])
+        .observe(wait.as_secs_f64());
+}
+
+/// Set the number of connections of the node pool that are up
+pub fn set_pool_connections(live: usize) {
+    POOL_CONNECTIONS.set(live as i64);
+}
+
+/// Count a connection of the node pool that was connected, i.e. to replace a dead one
+pub fn record_pool_reconnect() {
+    POOL_RECONNECTS.inc();
+}
+
+/// Render all collected metrics in the prometheus text format
+pub fn gather() -> Result<String, Error> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome(&Ok(())), "valid");
+        assert_eq!(outcome(&Err(Error::AttestationRevoked)), "revoked");
+        assert_eq!(outcome(&Err(Error::InvalidSignature)), "bad_signature");
+        assert_eq!(outcome(&Err(Error::InvalidIssuer)), "untrusted_issuer");
+        assert_eq!(outcome(&Err(Error::InvalidRootHash)), "error");
+    }
+
+    #[test]
+    fn test_check_timings() {
+        let mut timings = CheckTimings::default();
+        timings.record(Check::Signature, Duration::from_millis(3));
+        assert_eq!(
+            timings.get(Check::Signature),
+            Some(Duration::from_millis(3))
+        );
+        assert_eq!(timings.get(Check::Attestation), None);
+    }
+
+    #[test]
+    fn test_gather() {
+        record_outcome(&Ok(()));
+        drop(time_check(Check::RootHash));
+        let text = gather().expect("Failed to gather metrics");
+        assert!(text.contains("kilt_verify_verifications_total{outcome=\"valid\"}"));
+        assert!(text.contains("kilt_verify_check_duration_seconds_count{check=\"root_hash\"}"));
+    }
+}
@@ -0,0 +1,174 @@
+use clap::Args;
+use serde_json::Value;
+
+use crate::{
+    credential::{Credential, StatementKeys},
+    errors::Error,
+    utils::{parse_credential, read_credential_json},
+};
+
+/// Strip data a verifier does not need from a credential, i.e. to archive it
+#[derive(Args, Debug)]
+pub struct MinimizeArgs {
+    /// File containing the credential to minimize
+    #[clap(short, long, value_parser, default_value = "stdin")]
+    file: String,
+
+    /// Drop the legitimations, they are not checked by the verification
+    #[clap(long, value_parser, default_value_t = false)]
+    drop_legitimations: bool,
+
+    /// Drop the nonces of statements that are not disclosed in the claim
+    #[clap(long, value_parser, default_value_t = false)]
+    drop_undisclosed_nonces: bool,
+
+    /// File to write the minimized credential to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+}
+
+/// What may be dropped on top of the whitespace
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinimizeOptions {
+    pub drop_legitimations: bool,
+    pub drop_undisclosed_nonces: bool,
+}
+
+/// A minimized credential as compact json and what was dropped from it
+#[derive(Debug, Clone)]
+pub struct Minimized {
+    pub json: Vec<u8>,
+    pub dropped_legitimations: usize,
+    pub dropped_nonces: usize,
+}
+
+// the credential with the checks that need no chain, the signature is not touched and stays
+// valid with the root hash
+fn check_offline(data: &[u8], statement_keys: &StatementKeys) -> Result<Credential, Error> {
+    let mut cred = parse_credential(data)?;
+    cred.claim.statement_keys = statement_keys.clone();
+    cred.check_claim_contents()?;
+    cred.check_root_hash()?;
+    Ok(cred)
+}
+
+/// Remove the selected data from a credential and write it without whitespace. Fields that are
+/// not selected and that this tool does not know are kept. The input has to pass the checks that
+/// need no chain, and the output is checked the same way before it is returned, so minimizing
+/// never turns a valid credential into an invalid one. The claim is normalized with the statement
+/// keys.
+pub fn minimize(
+    data: &[u8],
+    options: &MinimizeOptions,
+    statement_keys: &StatementKeys,
+) -> Result<Minimized, Error> {
+    let cred = check_offline(data, statement_keys)?;
+
+    let mut json: Value = serde_json::from_slice(data)?;
+    let fields = json.as_object_mut().ok_or(Error::InvalidClaimContents)?;
+    let mut dropped_legitimations = 0;
+    if options.drop_legitimations {
+        if let Some(legitimations) = fields.remove("legitimations") {
+            dropped_legitimations = legitimations.as_array().map_or(1, Vec::len);
+        }
+    }
+    let mut dropped_nonces = 0;
+    if options.drop_undisclosed_nonces {
+        // the nonces of disclosed statements are needed to check the claim contents
+        let disclosed = cred.claim.hash_statements()?;
+        if let Some(Value::Object(nonces)) = fields.get_mut("claimNonceMap") {
+            let before = nonces.len();
+            nonces.retain(|hash, _| disclosed.contains(hash));
+            dropped_nonces = before - nonces.len();
+        }
+    }
+    let json = serde_json::to_vec(&json)?;
+
+    let minimized = check_offline(&json, statement_keys)?;
+    if minimized.root_hash != cred.root_hash {
+        return Err(Error::InvalidRootHash);
+    }
+    Ok(Minimized {
+        json,
+        dropped_legitimations,
+        dropped_nonces,
+    })
+}
+
+pub fn run(args: &MinimizeArgs, statement_keys: &StatementKeys) -> Result<(), Error> {
+    let data = read_credential_json(&args.file)?.into_bytes();
+    let options = MinimizeOptions {
+        drop_legitimations: args.drop_legitimations,
+        drop_undisclosed_nonces: args.drop_undisclosed_nonces,
+    };
+    let minimized = minimize(&data, &options, statement_keys)?;
+    if args.output == "stdout" {
+        println!("{}", String::from_utf8_lossy(&minimized.json));
+    } else {
+        std::fs::write(&args.output, &minimized.json)?;
+    }
+    let saved = data.len().saturating_sub(minimized.json.len());
+    eprintln!(
+        "Minimized from {} to {} bytes, saved {} bytes ({:.1}%): dropped {} legitimations and {} nonces",
+        data.len(),
+        minimized.json.len(),
+        saved,
+        100.0 * saved as f64 / data.len().max(1) as f64,
+        minimized.dropped_legitimations,
+        minimized.dropped_nonces
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_minimize() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        // a presentation that only discloses the email and still has the nonce of the name
+        let mut json = serde_json::to_value(fixtures::credential()).unwrap();
+        json["claim"]["contents"] = json!({"Email": "alice@example.com"});
+        json["legitimations"] = json!([{"claim": {}}, {"claim": {}}]);
+        json["delegationId"] = Value::Null;
+        let data = serde_json::to_vec_pretty(&json).unwrap();
+
+        let none = minimize(&data, &MinimizeOptions::default(), &Default::default()).unwrap();
+        assert!(none.json.len() < data.len());
+        assert_eq!((none.dropped_legitimations, none.dropped_nonces), (0, 0));
+
+        let all = MinimizeOptions {
+            drop_legitimations: true,
+            drop_undisclosed_nonces: true,
+        };
+        let minimized = minimize(&data, &all, &Default::default()).unwrap();
+        assert_eq!(minimized.dropped_legitimations, 2);
+        assert_eq!(minimized.dropped_nonces, 1);
+        assert!(minimized.json.len() < none.json.len());
+        let output: Value = serde_json::from_slice(&minimized.json).unwrap();
+        assert!(output.get("legitimations").is_none());
+        assert!(output.get("delegationId").is_some());
+        assert_eq!(output["claimNonceMap"].as_object().unwrap().len(), 2);
+
+        let cred = parse_credential(&minimized.json).unwrap();
+        let res = cred.verify(&backend, &[&attester], None).await;
+        assert!(res.is_ok(), "Failed to verify: {:?}", res);
+
+        // minimizing twice changes nothing
+        let again = minimize(&minimized.json, &all, &Default::default()).unwrap();
+        assert_eq!(again.json, minimized.json);
+
+        // credentials that fail already are not minimized
+        json["claim"]["contents"] = json!({"Email": "mallory@example.com"});
+        let res = minimize(
+            &serde_json::to_vec(&json).unwrap(),
+            &all,
+            &Default::default(),
+        );
+        assert!(matches!(res, Err(Error::InvalidClaimContents)), "{:?}", res);
+    }
+}
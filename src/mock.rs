@@ -0,0 +1,480 @@
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::did_details::{
+            DidDetails, DidEncryptionKey, DidPublicKey, DidPublicKeyDetails, DidVerificationKey,
+        },
+        did::service_endpoints::DidEndpoint,
+        frame_support::storage::{
+            bounded_btree_map::BoundedBTreeMap, bounded_btree_set::BoundedBTreeSet,
+        },
+        kilt_support::deposit::Deposit,
+        sp_core as runtime_sp_core,
+    },
+    utils::{get_did_account_id, get_key_id, hex_decode, hex_decode_h256},
+};
+
+/// A backend that serves the storage entries inserted into it or loaded from a fixture file.
+/// The entries are kept SCALE encoded just like on chain, because the generated types are not `Clone`.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    latest: MockState,
+    blocks: HashMap<H256, MockState>,
+}
+
+/// The storage entries of the mock at one block
+#[derive(Debug, Default)]
+pub struct MockState {
+    dids: HashMap<AccountId32, Vec<u8>>,
+    attestations: HashMap<H256, Vec<u8>>,
+    web3_names: HashMap<String, AccountId32>,
+    ctypes: HashMap<H256, AccountId32>,
+    service_endpoints: HashMap<AccountId32, Vec<Vec<u8>>>,
+}
+
+impl MockState {
+    pub fn insert_did(&mut self, did: AccountId32, details: &DidDetails) {
+        self.dids.insert(did, details.encode());
+    }
+
+    pub fn insert_attestation(&mut self, root_hash: H256, details: &AttestationDetails) {
+        self.attestations.insert(root_hash, details.encode());
+    }
+
+    pub fn insert_web3_name(&mut self, name: &str, owner: AccountId32) {
+        self.web3_names.insert(name.to_string(), owner);
+    }
+
+    pub fn insert_ctype(&mut self, ctype_hash: H256, creator: AccountId32) {
+        self.ctypes.insert(ctype_hash, creator);
+    }
+
+    pub fn insert_service_endpoint(&mut self, did: AccountId32, endpoint: &DidEndpoint) {
+        self.service_endpoints
+            .entry(did)
+            .or_default()
+            .push(endpoint.encode());
+    }
+}
+
+impl MockBackend {
+    /// Load the chain state from a json fixture file
+    pub fn load(file: &str) -> Result<Self, Error> {
+        let fixture: StorageFixture = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+        Self::from_fixture(&fixture)
+    }
+
+    pub fn from_fixture(fixture: &StorageFixture) -> Result<Self, Error> {
+        let mut backend = MockBackend {
+            latest: fixture.latest.to_state()?,
+            blocks: HashMap::new(),
+        };
+        for (block_hash, state) in fixture.blocks.iter() {
+            backend.insert_block(hex_decode_h256(block_hash)?, state.to_state()?);
+        }
+        Ok(backend)
+    }
+
+    /// The state of the latest block, which is used when no block hash is given
+    pub fn latest_mut(&mut self) -> &mut MockState {
+        &mut self.latest
+    }
+
+    /// Add the state of a historical block
+    pub fn insert_block(&mut self, block_hash: H256, state: MockState) {
+        self.blocks.insert(block_hash, state);
+    }
+
+    pub fn insert_did(&mut self, did: AccountId32, details: &DidDetails) {
+        self.latest.insert_did(did, details);
+    }
+
+    pub fn insert_attestation(&mut self, root_hash: H256, details: &AttestationDetails) {
+        self.latest.insert_attestation(root_hash, details);
+    }
+
+    // the state at the given block, unknown blocks are an error like on chain
+    fn state(&self, at: Option<H256>) -> Result<&MockState, Error> {
+        match at {
+            None => Ok(&self.latest),
+            Some(block_hash) => self.blocks.get(&block_hash).ok_or(Error::BlockNotFound),
+        }
+    }
+}
+
+// decode a stored entry, a broken entry is reported like a broken response of a node
+fn decode<T: Decode>(data: Option<&Vec<u8>>) -> Result<Option<T>, Error> {
+    data.map(|data| T::decode(&mut &data[..]))
+        .transpose()
+        .map_err(|err| Error::ConnectionError(err.into()))
+}
+
+#[async_trait]
+impl ChainBackend for MockBackend {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        decode(self.state(at)?.dids.get(did))
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        decode(self.state(at)?.attestations.get(root_hash))
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        Ok(self.state(at)?.web3_names.get(name).cloned())
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        Ok(self
+            .state(at)?
+            .web3_names
+            .iter()
+            .filter(|(_, name_owner)| *name_owner == owner)
+            .map(|(name, _)| name.clone())
+            .min())
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        Ok(self.state(at)?.ctypes.get(ctype_hash).cloned())
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        let endpoints = self.state(at)?.service_endpoints.get(did);
+        endpoints
+            .into_iter()
+            .flatten()
+            .filter_map(|endpoint| decode(Some(endpoint)).transpose())
+            .collect()
+    }
+}
+
+/// Fixture file describing the chain state, i.e.
+/// `{"dids": {...}, "attestations": {...}, "blocks": {"0x1234...": {"dids": {...}}}}`.
+/// The top level entries are the latest state, `blocks` holds the state of historical blocks.
+#[derive(Debug, Default, Deserialize)]
+pub struct StorageFixture {
+    /// What the scenario of the fixture is about
+    #[serde(default)]
+    pub description: String,
+    #[serde(flatten)]
+    pub latest: StateFixture,
+    #[serde(default)]
+    pub blocks: HashMap<String, StateFixture>,
+}
+
+/// The storage entries at one block, keyed like on chain but written as DIDs and hex strings
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateFixture {
+    /// DID documents by DID, i.e. "did:kilt:4abc..."
+    #[serde(default)]
+    pub dids: HashMap<String, DidFixture>,
+    /// Attestations by root hash
+    #[serde(default)]
+    pub attestations: HashMap<String, AttestationFixture>,
+    /// Owner DID by web3name
+    #[serde(default)]
+    pub web3_names: HashMap<String, String>,
+    /// Creator DID by ctype hash
+    #[serde(default)]
+    pub ctypes: HashMap<String, String>,
+}
+
+/// A DID document, the key ids are calculated from the keys like on chain
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidFixture {
+    pub authentication_key: KeyFixture,
+    #[serde(default)]
+    pub attestation_key: Option<KeyFixture>,
+    #[serde(default)]
+    pub delegation_key: Option<KeyFixture>,
+    /// Hex encoded x25519 public keys
+    #[serde(default)]
+    pub key_agreement_keys: Vec<String>,
+    #[serde(default)]
+    pub last_tx_counter: u64,
+}
+
+/// A verification key like `{"type": "sr25519", "publicKey": "0x1234..."}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum KeyFixture {
+    Sr25519 {
+        #[serde(rename = "publicKey")]
+        public_key: String,
+    },
+    Ed25519 {
+        #[serde(rename = "publicKey")]
+        public_key: String,
+    },
+    Ecdsa {
+        #[serde(rename = "publicKey")]
+        public_key: String,
+    },
+}
+
+/// An attestation, the deposit is paid by the attester
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationFixture {
+    pub ctype_hash: String,
+    /// DID of the attester
+    pub attester: String,
+    /// Id of the delegation node the attestation was authorized by
+    #[serde(default)]
+    pub delegation_id: Option<String>,
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+// decode a public key with a fixed length
+fn public_key<const N: usize>(data: &str) -> Result<[u8; N], Error> {
+    hex_decode(data)?
+        .try_into()
+        .map_err(|_| Error::InvalidDidKey)
+}
+
+impl KeyFixture {
+    fn to_public_key(&self) -> Result<DidPublicKey, Error> {
+        let key = match self {
+            KeyFixture::Sr25519 { public_key: key } => {
+                DidVerificationKey::Sr25519(runtime_sp_core::sr25519::Public(public_key(key)?))
+            }
+            KeyFixture::Ed25519 { public_key: key } => {
+                DidVerificationKey::Ed25519(runtime_sp_core::ed25519::Public(public_key(key)?))
+            }
+            KeyFixture::Ecdsa { public_key: key } => {
+                DidVerificationKey::Ecdsa(runtime_sp_core::ecdsa::Public(public_key(key)?))
+            }
+        };
+        Ok(DidPublicKey::PublicVerificationKey(key))
+    }
+}
+
+impl DidFixture {
+    fn to_details(&self, did: &AccountId32) -> Result<DidDetails, Error> {
+        let mut public_keys = Vec::new();
+        // add a key to the public keys and return its key id
+        let mut add = |key: DidPublicKey| {
+            let key_id = H256(get_key_id(&key));
+            if !public_keys.iter().any(|(id, _)| *id == key_id) {
+                public_keys.push((
+                    key_id,
+                    DidPublicKeyDetails {
+                        key,
+                        block_number: 0,
+                    },
+                ));
+            }
+            key_id
+        };
+
+        let authentication_key = add(self.authentication_key.to_public_key()?);
+        let attestation_key = self
+            .attestation_key
+            .as_ref()
+            .map(|key| key.to_public_key().map(&mut add))
+            .transpose()?;
+        let delegation_key = self
+            .delegation_key
+            .as_ref()
+            .map(|key| key.to_public_key().map(&mut add))
+            .transpose()?;
+        let key_agreement_keys = self
+            .key_agreement_keys
+            .iter()
+            .map(|key| {
+                Ok(add(DidPublicKey::PublicEncryptionKey(
+                    DidEncryptionKey::X25519(public_key(key)?),
+                )))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(DidDetails {
+            authentication_key,
+            key_agreement_keys: BoundedBTreeSet(key_agreement_keys),
+            delegation_key,
+            attestation_key,
+            public_keys: BoundedBTreeMap(public_keys),
+            last_tx_counter: self.last_tx_counter,
+            deposit: Deposit {
+                owner: did.clone(),
+                amount: 0,
+            },
+        })
+    }
+}
+
+impl AttestationFixture {
+    fn to_details(&self) -> Result<AttestationDetails, Error> {
+        let attester = get_did_account_id(&self.attester)?;
+        Ok(AttestationDetails {
+            ctype_hash: hex_decode_h256(&self.ctype_hash)?,
+            attester: attester.clone(),
+            delegation_id: self
+                .delegation_id
+                .as_ref()
+                .map(hex_decode_h256)
+                .transpose()?,
+            revoked: self.revoked,
+            deposit: Deposit {
+                owner: attester,
+                amount: 0,
+            },
+        })
+    }
+}
+
+impl StateFixture {
+    fn to_state(&self) -> Result<MockState, Error> {
+        let mut state = MockState::default();
+        for (did, fixture) in self.dids.iter() {
+            let did = get_did_account_id(did)?;
+            let details = fixture.to_details(&did)?;
+            state.insert_did(did, &details);
+        }
+        for (root_hash, fixture) in self.attestations.iter() {
+            state.insert_attestation(hex_decode_h256(root_hash)?, &fixture.to_details()?);
+        }
+        for (name, owner) in self.web3_names.iter() {
+            state.insert_web3_name(name, get_did_account_id(owner)?);
+        }
+        for (ctype_hash, creator) in self.ctypes.iter() {
+            state.insert_ctype(hex_decode_h256(ctype_hash)?, get_did_account_id(creator)?);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    fn scenario(name: &str) -> MockBackend {
+        MockBackend::load(&format!(
+            "{}/fixtures/scenarios/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scenarios() {
+        let credential = fixtures::credential();
+        let attester = fixtures::attester_did();
+        let rotated = "Err(KeyNotFound { key_id: \"0x49d513f193ca2e6401be6bda48a9cfba479b41f4d8b0b68cb516c1229582f68c\", available: [\"#0x42eec4ed53d6498cfde0eebe99c610699e88d70d84c13ee3bbd0ee2348e1ab3c (sr25519, authentication)\"], keys_changed_at: None })";
+        let cases = [
+            ("valid", "Ok(())"),
+            ("revoked", "Err(AttestationRevoked)"),
+            ("rotated-key", rotated),
+            ("deleted-did", "Err(DidNotFound)"),
+            ("untrusted-attester", "Err(InvalidIssuer)"),
+            ("revoked-delegated-attestation", "Err(AttestationRevoked)"),
+            ("historical", "Err(AttestationRevoked)"),
+        ];
+        for (name, expected) in cases {
+            let res = credential.verify(&scenario(name), &[&attester], None).await;
+            assert_eq!(format!("{:?}", res), expected, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_historical_block() {
+        let backend = scenario("historical");
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        let block = Some(H256([2; 32]));
+
+        let latest = backend
+            .attestation(&root_hash, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(latest.revoked);
+        let past = backend
+            .attestation(&root_hash, block)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!past.revoked);
+
+        let res = backend.attestation(&root_hash, Some(H256([3; 32]))).await;
+        assert!(matches!(res, Err(Error::BlockNotFound)), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_web3_names_and_ctypes() {
+        let backend = scenario("valid");
+        let alice = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let bob = get_did_account_id(&fixtures::attester_did()).unwrap();
+
+        let owner = backend.web3_name_owner("alice", None).await.unwrap();
+        assert_eq!(owner, Some(alice.clone()));
+        let owner = backend.web3_name_owner("carol", None).await.unwrap();
+        assert_eq!(owner, None);
+        let name = backend.web3_name(&alice, None).await.unwrap();
+        assert_eq!(name.as_deref(), Some("alice"));
+        let name = backend.web3_name(&AccountId32::new([7; 32]), None).await;
+        assert_eq!(name.unwrap(), None);
+
+        let ctype_hash = hex_decode_h256(fixtures::CTYPE_HASH).unwrap();
+        let creator = backend.ctype_creator(&ctype_hash, None).await.unwrap();
+        assert_eq!(creator, Some(bob));
+    }
+
+    #[test]
+    fn test_did_fixture_key_ids() {
+        let fixture: StorageFixture = serde_json::from_str(
+            r#"{"dids": {"did:kilt:4siJtc4dYq2gPre8Xj6KJcSjVAdi1gmjctUzjf3AwrtNnhvy": {
+                "authenticationKey": {"type": "sr25519", "publicKey": "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"},
+                "attestationKey": {"type": "sr25519", "publicKey": "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"},
+                "keyAgreementKeys": ["0x0101010101010101010101010101010101010101010101010101010101010101"]
+            }}}"#,
+        )
+        .unwrap();
+        let (did, did_fixture) = fixture.latest.dids.iter().next().unwrap();
+        let details = did_fixture
+            .to_details(&get_did_account_id(did).unwrap())
+            .unwrap();
+
+        let key_id = H256(fixtures::owner_key().key_id());
+        assert_eq!(details.authentication_key, key_id);
+        assert_eq!(details.attestation_key, Some(key_id));
+        // the same key is only listed once
+        assert_eq!(details.public_keys.0.len(), 2);
+        assert_eq!(details.key_agreement_keys.0.len(), 1);
+
+        let short_key = r#"{"type": "ed25519", "publicKey": "0x0102"}"#;
+        let key: KeyFixture = serde_json::from_str(short_key).unwrap();
+        assert!(matches!(key.to_public_key(), Err(Error::InvalidDidKey)));
+    }
+}
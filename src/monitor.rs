@@ -0,0 +1,1042 @@
+use clap::{Args, Subcommand};
+use futures::{
+    future,
+    stream::{self, StreamExt},
+    FutureExt, TryFutureExt,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::{File, OpenOptions},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use subxt::sp_core::H256;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    backend::{ChainBackend, QueryBackend},
+    batch::{self, BatchInput},
+    cache::{attestation_events, CacheStatus, CachedBackend, RevocationCache},
+    credential::StatementKeys,
+    errors::Error,
+    history::{format_millis, BlockHistory},
+    kilt::{connect, KiltRuntimeApi, NodePool, HEALTH_TIMEOUT},
+    metrics,
+    throttle::{Priority, RpcLimiter, Throttle},
+    utils::{hex_decode_h256, hex_encode, normalize_issuer, parse_credential},
+    webhook::Webhook,
+};
+
+#[cfg(feature = "nats")]
+use crate::publish::{self, Publisher, Sink};
+
+/// Verify a set of credentials again and again and report the ones whose status changes
+#[derive(Args, Debug)]
+#[clap(subcommand_negates_reqs = true)]
+pub struct MonitorArgs {
+    #[clap(subcommand)]
+    command: Option<MonitorCommand>,
+
+    /// Directory, newline delimited json file or archive of the credentials to monitor,
+    /// it is read again before every round so new credentials are picked up
+    #[clap(long, value_parser, required = true)]
+    dir: Option<String>,
+
+    /// Time between two rounds, i.e. "1h" or "15m". Attestation events of the chain trigger a
+    /// check of the affected credentials right away, the rounds catch what they miss
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "1h")]
+    interval: Duration,
+
+    /// Random delay of up to this much added to each round, a tenth of the interval by default
+    #[clap(long, value_parser = humantime::parse_duration)]
+    jitter: Option<Duration>,
+
+    /// Number of credentials checked at the same time
+    #[clap(long, value_parser, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Trusted issuer DID or KILT address, can be given multiple times and replaces the built-in issuers
+    #[clap(long = "issuer", value_parser)]
+    issuers: Vec<String>,
+
+    /// Also check the claim contents, root hash and signature each round. They cannot change once a
+    /// credential was valid, so by default only the attestation is checked
+    #[clap(long, value_parser, default_value_t = false)]
+    full: bool,
+
+    /// File the status of each credential and all status changes are kept in across restarts
+    #[clap(long, value_parser, default_value = STATE_FILE)]
+    state: String,
+
+    /// How long status changes are kept in the state file, i.e. "30days". All are kept by default
+    #[clap(long, value_parser = humantime::parse_duration)]
+    retention: Option<Duration>,
+
+    /// http:// URL each downgrade of a valid credential is POSTed to as json
+    #[clap(long, value_parser)]
+    webhook: Option<String>,
+
+    /// Publish the status of each credential checked in a round on nats://host[:port]/subject.
+    /// Statuses the server does not confirm are sent again, Ctrl-C stops the monitor once they
+    /// are delivered
+    #[cfg(feature = "nats")]
+    #[clap(long, value_parser)]
+    publish: Option<Sink>,
+
+    /// Number of statuses kept in memory while the --publish server is slow
+    #[cfg(feature = "nats")]
+    #[clap(long, value_parser, default_value_t = publish::BUFFER)]
+    publish_buffer: usize,
+
+    /// File the metrics are written to in prometheus text format after each round,
+    /// i.e. for the textfile collector of the node exporter
+    #[clap(long, value_parser)]
+    metrics_file: Option<String>,
+
+    /// Storage queries that may be in flight at the same time. Not limited by default
+    #[clap(long, value_parser)]
+    rpc_concurrency: Option<usize>,
+
+    /// Permits of --rpc-concurrency the rounds may take, the rest is kept for the checks that
+    /// chain events trigger. A quarter of them by default
+    #[clap(long, value_parser, requires = "rpc-concurrency")]
+    rpc_background_concurrency: Option<usize>,
+
+    /// Connections to the endpoint the storage queries are spread over in turn. They are
+    /// checked before every round, dead connections are replaced
+    #[clap(long, value_parser, default_value_t = 1)]
+    rpc_connections: usize,
+
+    /// Run a single round and exit
+    #[clap(long, value_parser, default_value_t = false)]
+    once: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum MonitorCommand {
+    /// Show the status of the monitored credentials and how it changed, while the monitor runs
+    Status(StatusArgs),
+}
+
+#[derive(Args, Debug)]
+struct StatusArgs {
+    /// The --state file of the monitor
+    #[clap(long, value_parser, default_value = STATE_FILE)]
+    state: String,
+
+    /// Only show the credentials with this root hash
+    #[clap(long, value_parser = parse_root_hash)]
+    root_hash: Option<H256>,
+}
+
+fn parse_root_hash(arg: &str) -> Result<H256, String> {
+    hex_decode_h256(arg).map_err(|err| err.to_string())
+}
+
+impl MonitorArgs {
+    /// Running the monitor talks to the endpoint, `monitor status` only reads the state file
+    pub fn connects(&self) -> bool {
+        self.command.is_none()
+    }
+}
+
+const STATE_FILE: &str = "kilt-verify-monitor.json";
+
+/// Status of a credential that passes all checks
+pub const VALID: &str = "valid";
+
+/// The last known status of a credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tracked {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_hash: Option<String>,
+    /// "valid" or the code of the error the credential fails with
+    pub status: String,
+    /// Unix time in seconds the credential got the status
+    pub since: u64,
+    /// Unix time in seconds the credential was last checked
+    pub last_checked: u64,
+}
+
+/// A change of the status of a credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transition {
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_hash: Option<String>,
+    pub from: String,
+    pub to: String,
+    /// Unix time in seconds the change was noticed
+    pub at: u64,
+    /// Number of the block whose events triggered the check, or of the latest block when the
+    /// round started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<u32>,
+}
+
+impl Transition {
+    /// A credential that was valid is not anymore
+    pub fn is_downgrade(&self) -> bool {
+        self.from == VALID && self.to != VALID
+    }
+}
+
+/// What the monitor knows about the credentials, by their source
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorState {
+    pub credentials: BTreeMap<String, Tracked>,
+    pub transitions: Vec<Transition>,
+}
+
+impl MonitorState {
+    /// Read the state file, a file that does not exist yet is an empty state. The file is not
+    /// read while the monitor writes it
+    pub fn load(file: &Path) -> Result<Self, Error> {
+        let _lock = lock(file, false)?;
+        match std::fs::read(file) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Write the state file, replacing it only once it is written completely
+    pub fn save(&self, file: &Path) -> Result<(), Error> {
+        let data = serde_json::to_vec_pretty(self)?;
+        let _lock = lock(file, true)?;
+        write_replacing(file, &data)
+    }
+
+    /// Forget the status changes noticed before the unix time
+    pub fn prune(&mut self, before: u64) {
+        self.transitions
+            .retain(|transition| transition.at >= before);
+    }
+
+    /// Number of credentials by status
+    pub fn counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for tracked in self.credentials.values() {
+            *counts.entry(tracked.status.as_str()).or_default() += 1;
+        }
+        counts
+    }
+}
+
+// lock the state file for reading or writing until the returned file is dropped. The lock is
+// taken on a file next to it, the state file itself is replaced when it is written
+fn lock(file: &Path, exclusive: bool) -> Result<File, Error> {
+    let lock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(file.with_extension("lock"))?;
+    match exclusive {
+        true => lock.lock()?,
+        false => lock.lock_shared()?,
+    }
+    Ok(lock)
+}
+
+fn write_replacing(file: &Path, data: &[u8]) -> Result<(), Error> {
+    let partial = file.with_extension("partial");
+    std::fs::write(&partial, data)?;
+    std::fs::rename(&partial, file)?;
+    Ok(())
+}
+
+/// The status of the monitored credentials, or of the ones with the root hash, with their last
+/// change and the changes the state file keeps. Credentials that left the set are shown as long as
+/// their changes are kept
+pub fn describe_status(state: &MonitorState, root_hash: Option<H256>) -> Result<String, Error> {
+    let selected = |tracked: &Option<String>| match root_hash {
+        None => true,
+        Some(root_hash) => {
+            tracked
+                .as_deref()
+                .and_then(|tracked| hex_decode_h256(tracked).ok())
+                == Some(root_hash)
+        }
+    };
+    let sources: BTreeSet<&str> = state
+        .credentials
+        .iter()
+        .filter(|(_, tracked)| selected(&tracked.root_hash))
+        .map(|(source, _)| source.as_str())
+        .chain(
+            state
+                .transitions
+                .iter()
+                .filter(|transition| selected(&transition.root_hash))
+                .map(|transition| transition.source.as_str()),
+        )
+        .collect();
+    if sources.is_empty() {
+        return match root_hash {
+            Some(root_hash) => Err(Error::NotMonitored(hex_encode(root_hash))),
+            None => Ok("No credentials are monitored\n".to_string()),
+        };
+    }
+    let mut text = String::new();
+    for source in sources {
+        let tracked = state
+            .credentials
+            .get(source)
+            .filter(|tracked| selected(&tracked.root_hash));
+        match tracked {
+            Some(tracked) => {
+                text += &format!(
+                    "{}: {} since {}, last checked {}\n",
+                    source,
+                    tracked.status,
+                    format_secs(tracked.since),
+                    format_secs(tracked.last_checked)
+                );
+                if let Some(root_hash) = &tracked.root_hash {
+                    text += &format!("  Root hash: {}\n", root_hash);
+                }
+            }
+            None => text += &format!("{}: not monitored anymore\n", source),
+        }
+        let history: Vec<&Transition> = state
+            .transitions
+            .iter()
+            .filter(|transition| transition.source == source && selected(&transition.root_hash))
+            .collect();
+        match history.last() {
+            Some(last) => text += &format!("  Last change: {}\n  History:\n", describe(last)),
+            None => text += "  No changes recorded\n",
+        }
+        for transition in history {
+            text += &format!("    {}\n", describe(transition));
+        }
+    }
+    Ok(text)
+}
+
+// i.e. "valid -> attestation_revoked at 2024-01-01T00:00:00.000Z, block #123"
+fn describe(transition: &Transition) -> String {
+    format!(
+        "{} -> {} at {}{}",
+        transition.from,
+        transition.to,
+        format_secs(transition.at),
+        transition
+            .block
+            .map(|block| format!(", block #{}", block))
+            .unwrap_or_default()
+    )
+}
+
+fn format_secs(secs: u64) -> String {
+    format_millis(secs.saturating_mul(1000))
+}
+
+/// How the credentials are checked
+#[derive(Debug, Clone, Copy)]
+pub struct MonitorOptions<'a> {
+    pub allowed_issuers: &'a [&'a str],
+    pub full: bool,
+    pub concurrency: usize,
+    /// How the claims are normalized for full checks, see `Claim::statement_keys`
+    pub statement_keys: &'a StatementKeys,
+}
+
+/// The outcome of a round
+#[derive(Debug, Default)]
+pub struct RoundReport {
+    pub checked: usize,
+    /// Sources of the credentials whose status was looked up
+    pub sources: Vec<String>,
+    /// Credentials whose status could not be looked up, they keep their last status
+    pub unreachable: usize,
+    pub transitions: Vec<Transition>,
+    /// The attestation cache the round was checked with
+    pub cache: Option<CacheStatus>,
+}
+
+// the status a credential has now, `None` if the chain could not be asked
+async fn status(
+    backend: &dyn ChainBackend,
+    input: &BatchInput,
+    options: &MonitorOptions<'_>,
+) -> (Option<String>, Option<String>) {
+    let mut cred = match parse_credential(&input.data) {
+        Ok(cred) => cred,
+        Err(err) => return (None, Some(err.code().to_string())),
+    };
+    cred.claim.statement_keys = options.statement_keys.clone();
+    let res = if options.full {
+        cred.verify(backend, options.allowed_issuers, None).await
+    } else {
+        cred.check_attestation(backend, options.allowed_issuers)
+            .await
+            .map(|_| ())
+    };
+    let status = match res {
+        Ok(()) => Some(VALID.to_string()),
+        Err(Error::ConnectionError(_) | Error::Timeout) => None,
+        Err(err) => Some(err.code().to_string()),
+    };
+    (Some(cred.root_hash), status)
+}
+
+/// Check the credentials, or only the ones with a root hash in `only`, and update the state with
+/// their status at `now` and `block`. A full round forgets credentials that are not in the set
+/// anymore. Changes of known credentials are recorded as transitions, new credentials are only
+/// tracked.
+pub async fn round(
+    backend: &dyn ChainBackend,
+    inputs: &[BatchInput],
+    state: &mut MonitorState,
+    options: &MonitorOptions<'_>,
+    only: Option<&HashSet<H256>>,
+    now: u64,
+    block: Option<u32>,
+) -> RoundReport {
+    let selected = inputs.iter().filter(|input| match only {
+        None => true,
+        Some(only) => parse_credential(&input.data)
+            .and_then(|cred| hex_decode_h256(&cred.root_hash))
+            .is_ok_and(|root_hash| only.contains(&root_hash)),
+    });
+    let statuses: Vec<_> = stream::iter(selected)
+        .map(|input| async move { (input, status(backend, input, options).await) })
+        .buffered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut report = RoundReport::default();
+    for (input, (root_hash, status)) in statuses {
+        report.checked += 1;
+        let status = match status {
+            Some(status) => status,
+            None => {
+                report.unreachable += 1;
+                continue;
+            }
+        };
+        report.sources.push(input.source.clone());
+        match state.credentials.get_mut(&input.source) {
+            Some(tracked) => {
+                if tracked.status != status {
+                    report.transitions.push(Transition {
+                        source: input.source.clone(),
+                        root_hash: root_hash.clone(),
+                        from: tracked.status.clone(),
+                        to: status.clone(),
+                        at: now,
+                        block,
+                    });
+                    tracked.status = status;
+                    tracked.since = now;
+                }
+                tracked.root_hash = root_hash;
+                tracked.last_checked = now;
+            }
+            None => {
+                state.credentials.insert(
+                    input.source.clone(),
+                    Tracked {
+                        root_hash,
+                        status,
+                        since: now,
+                        last_checked: now,
+                    },
+                );
+            }
+        }
+    }
+    if only.is_none() {
+        let sources: HashSet<_> = inputs.iter().map(|input| &input.source).collect();
+        state
+            .credentials
+            .retain(|source, _| sources.contains(source));
+    }
+    state.transitions.extend(report.transitions.iter().cloned());
+    report
+}
+
+// the root hashes of the credentials, the attestation cache is synced with them
+fn root_hashes(inputs: &[BatchInput]) -> Vec<H256> {
+    inputs
+        .iter()
+        .filter_map(|input| parse_credential(&input.data).ok())
+        .filter_map(|cred| hex_decode_h256(&cred.root_hash).ok())
+        .collect()
+}
+
+// seconds since the unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Where the signals of a round go besides the state file and the metrics
+#[derive(Default)]
+struct Sinks {
+    webhook: Option<Webhook>,
+    #[cfg(feature = "nats")]
+    publisher: Option<Publisher>,
+}
+
+/// The status of a credential as it is published
+#[cfg(feature = "nats")]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Published<'a> {
+    source: &'a str,
+    #[serde(flatten)]
+    tracked: &'a Tracked,
+}
+
+/// Save the state without the status changes older than --retention and send the signals of a
+/// round: the metrics, the webhook for each downgrade and the status of each checked credential
+/// to the message queue
+async fn record(
+    report: &RoundReport,
+    state: &mut MonitorState,
+    args: &MonitorArgs,
+    sinks: &Sinks,
+) -> Result<(), Error> {
+    if let Some(retention) = args.retention {
+        state.prune(unix_now().saturating_sub(retention.as_secs()));
+    }
+    state.save(Path::new(&args.state))?;
+    metrics::set_monitored(&state.counts());
+    #[cfg(feature = "nats")]
+    if let Some(publisher) = &sinks.publisher {
+        for (source, tracked) in report
+            .sources
+            .iter()
+            .filter_map(|source| Some((source, state.credentials.get(source)?)))
+        {
+            publisher
+                .publish(serde_json::to_vec(&Published { source, tracked })?)
+                .await;
+        }
+    }
+    for transition in report.transitions.iter() {
+        eprintln!(
+            "{} {}: {} -> {}",
+            if transition.is_downgrade() {
+                "❌"
+            } else {
+                "ℹ️"
+            },
+            transition.source,
+            transition.from,
+            transition.to
+        );
+        if !transition.is_downgrade() {
+            continue;
+        }
+        metrics::record_downgrade(&transition.to);
+        if let Some(webhook) = &sinks.webhook {
+            if let Err(err) = webhook.post(&serde_json::to_vec(transition)?).await {
+                eprintln!("Warning: webhook for {} failed: {}", transition.source, err);
+            }
+        }
+    }
+    if let Some(file) = &args.metrics_file {
+        write_replacing(Path::new(file), metrics::gather()?.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Run rounds until the process is stopped. Between rounds the attestation events of finalized blocks
+/// are followed and the credentials they affect are checked right away. The events also keep an
+/// attestation cache up to date, which is synced again after every missed block and not used
+/// while the events are not followed. If the subscription fails the interval is all that is left,
+/// and if no credential can be looked up the node is connected again. With --publish, Ctrl-C
+/// stops the monitor after the statuses of the round are delivered.
+pub async fn run(
+    endpoint: &str,
+    default_issuers: &[&str],
+    args: &MonitorArgs,
+    statement_keys: &StatementKeys,
+) -> Result<(), Error> {
+    if let Some(MonitorCommand::Status(status_args)) = &args.command {
+        let state = MonitorState::load(Path::new(&status_args.state))?;
+        print!("{}", describe_status(&state, status_args.root_hash)?);
+        return Ok(());
+    }
+    let dir = args.dir.as_deref().ok_or(Error::NoInput)?;
+    let issuers = if args.issuers.is_empty() {
+        default_issuers
+            .iter()
+            .map(|issuer| issuer.to_string())
+            .collect()
+    } else {
+        args.issuers
+            .iter()
+            .map(|issuer| normalize_issuer(issuer))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let allowed_issuers: Vec<&str> = issuers.iter().map(String::as_str).collect();
+    let options = MonitorOptions {
+        allowed_issuers: &allowed_issuers,
+        full: args.full,
+        concurrency: args.concurrency,
+        statement_keys,
+    };
+    let sinks = Sinks {
+        webhook: args.webhook.as_deref().map(Webhook::parse).transpose()?,
+        #[cfg(feature = "nats")]
+        publisher: args
+            .publish
+            .clone()
+            .map(|sink| Publisher::start(sink, args.publish_buffer)),
+    };
+    let shutdown = CancellationToken::new();
+    #[cfg(feature = "nats")]
+    if sinks.publisher.is_some() {
+        let shutdown = shutdown.clone();
+        ctrlc::set_handler(move || shutdown.cancel())
+            .map_err(|err| Error::Io(std::io::Error::other(err)))?;
+    }
+    let res = rounds(endpoint, dir, args, &options, &sinks, &shutdown).await;
+    #[cfg(feature = "nats")]
+    if let Some(publisher) = sinks.publisher {
+        let published = publisher.finish().await;
+        return res.and(published);
+    }
+    res
+}
+
+// run the rounds until the monitor is stopped or fails
+async fn rounds(
+    endpoint: &str,
+    dir: &str,
+    args: &MonitorArgs,
+    options: &MonitorOptions<'_>,
+    sinks: &Sinks,
+    shutdown: &CancellationToken,
+) -> Result<(), Error> {
+    let jitter = args.jitter.unwrap_or(args.interval / 10);
+    let limiter = args
+        .rpc_concurrency
+        .map(|concurrency| RpcLimiter::new(concurrency, args.rpc_background_concurrency));
+    let throttle = Throttle::default();
+    let mut state = MonitorState::load(Path::new(&args.state))?;
+    let cache = RevocationCache::new();
+
+    loop {
+        let cli: KiltRuntimeApi = match connect(endpoint).await {
+            Ok(cli) => cli,
+            Err(err) if !args.once => {
+                eprintln!("Warning: cannot connect to {}: {}", endpoint, err);
+                tokio::select! {
+                    _ = tokio::time::sleep(args.interval) => continue,
+                    _ = shutdown.cancelled() => return Ok(()),
+                }
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let pool = NodePool::new(
+            endpoint,
+            cli.clone(),
+            args.rpc_connections,
+            Box::new(|endpoint: &str| connect(endpoint.to_string()).err_into().boxed()),
+        );
+        let round_queries = QueryBackend::new(endpoint, &pool)
+            .limited(limiter.as_ref(), Priority::Background)
+            .throttled(Some(&throttle));
+        let backend = CachedBackend::new(&round_queries, &cache);
+        let check_queries = QueryBackend::new(endpoint, &pool)
+            .limited(limiter.as_ref(), Priority::Interactive)
+            .throttled(Some(&throttle));
+        let triggered = CachedBackend::new(&check_queries, &cache);
+        let mut events = match cli.events().subscribe_finalized().await {
+            Ok(events) => Some(events),
+            Err(err) => {
+                eprintln!(
+                    "Warning: cannot follow the chain, only checking every {}: {}",
+                    humantime::format_duration(args.interval),
+                    err
+                );
+                None
+            }
+        };
+        loop {
+            pool.check_health(HEALTH_TIMEOUT).await;
+            let inputs = batch::read_inputs(dir, &Default::default(), &Default::default())?;
+            let block = cli.best_block().await.ok();
+            let mut report = round(
+                &backend,
+                &inputs,
+                &mut state,
+                options,
+                None,
+                unix_now(),
+                block,
+            )
+            .await;
+            report.cache = Some(cache.status());
+            record(&report, &mut state, args, sinks).await?;
+            eprintln!(
+                "Checked {} credentials: {} ({})",
+                report.checked,
+                state
+                    .counts()
+                    .iter()
+                    .map(|(status, count)| format!("{} {}", count, status))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                cache.status()
+            );
+            if args.once || shutdown.is_cancelled() {
+                return Ok(());
+            }
+            let reconnect = report.checked > 0 && report.unreachable == report.checked;
+
+            let delay = args.interval + jitter.mul_f64(rand::thread_rng().gen::<f64>());
+            let next_round = tokio::time::sleep(delay);
+            tokio::pin!(next_round);
+            loop {
+                let next_event = async {
+                    match events.as_mut() {
+                        Some(events) => events.next().await,
+                        None => future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = &mut next_round => break,
+                    _ = shutdown.cancelled() => return Ok(()),
+                    event = next_event => match event {
+                        Some(Ok(block)) => {
+                            let block_hash = block.block_hash();
+                            let number = match cli.client.rpc().header(Some(block_hash)).await {
+                                Ok(Some(header)) => header.number,
+                                Ok(None) | Err(_) => {
+                                    // the events cannot be placed, the cache cannot follow them
+                                    cache.invalidate();
+                                    continue;
+                                }
+                            };
+                            // an event that cannot be decoded may have revoked a cached
+                            // attestation, the cache is synced again like after a gap
+                            let events = attestation_events(&block).unwrap_or_else(|err| {
+                                eprintln!("Warning: cannot decode the events of block #{}: {}", number, err);
+                                cache.invalidate();
+                                Vec::new()
+                            });
+                            let only = match cache.apply(number, &events) {
+                                Ok(changed) if changed.is_empty() => continue,
+                                Ok(changed) => Some(changed),
+                                Err(gap) => {
+                                    if let Err(err) = cache.sync(&cli, &root_hashes(&inputs), number, block_hash).await {
+                                        eprintln!("Warning: cannot sync the attestation cache at block #{}: {}", number, err);
+                                        continue;
+                                    }
+                                    // the missed blocks may have revoked any of the credentials
+                                    if gap.last_block.is_none() {
+                                        continue;
+                                    }
+                                    None
+                                }
+                            };
+                            let mut report = round(&triggered, &inputs, &mut state, options, only.as_ref(), unix_now(), Some(number)).await;
+                            report.cache = Some(cache.status());
+                            record(&report, &mut state, args, sinks).await?;
+                        }
+                        Some(Err(err)) => {
+                            eprintln!("Warning: lost the chain events, only checking every {}: {}",
+                                humantime::format_duration(args.interval), err);
+                            cache.invalidate();
+                            events = None;
+                        }
+                        None => {
+                            cache.invalidate();
+                            events = None;
+                        }
+                    },
+                }
+            }
+            if reconnect {
+                eprintln!("Warning: no credential could be checked, connecting again");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, mock::MockBackend};
+
+    #[tokio::test]
+    async fn test_round() {
+        let attester = fixtures::attester_did();
+        let options = MonitorOptions {
+            allowed_issuers: &[&attester],
+            full: false,
+            concurrency: 2,
+            statement_keys: &StatementKeys::KiltCtype,
+        };
+        let valid = serde_json::to_vec(&fixtures::credential()).unwrap();
+        let mut tampered = fixtures::credential();
+        tampered.claim.contents = serde_json::json!({"Email": "mallory@example.com"});
+        let input = |source: &str, data: &[u8]| BatchInput {
+            source: source.to_string(),
+            data: data.to_vec(),
+        };
+        let inputs = vec![
+            input("a.json", &valid),
+            // the claim is not checked, it cannot change
+            input("b.json", &serde_json::to_vec(&tampered).unwrap()),
+            input("c.json", b"not json"),
+        ];
+
+        let mut state = MonitorState::default();
+        let report = round(
+            &fixtures::backend(),
+            &inputs,
+            &mut state,
+            &options,
+            None,
+            100,
+            Some(10),
+        )
+        .await;
+        assert_eq!((report.checked, report.unreachable), (3, 0));
+        assert!(report.transitions.is_empty());
+        assert_eq!(report.sources, vec!["a.json", "b.json", "c.json"]);
+        assert_eq!(
+            state.counts(),
+            BTreeMap::from([("invalid_json", 1), ("valid", 2)])
+        );
+
+        // with full checks the claim counts
+        let full = MonitorOptions {
+            full: true,
+            ..options
+        };
+        let mut full_state = MonitorState::default();
+        round(
+            &fixtures::backend(),
+            &inputs,
+            &mut full_state,
+            &full,
+            None,
+            100,
+            Some(10),
+        )
+        .await;
+        assert_eq!(
+            full_state.credentials["b.json"].status,
+            "invalid_claim_contents"
+        );
+
+        // the claim of a fork is normalized with its statement keys
+        let fork_data = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/vectors/custom-statement-keys.json"
+        ));
+        let fork_inputs = vec![input("fork.json", fork_data)];
+        let fork_keys = "fork:ctype:{ctype}#{property}".parse().unwrap();
+        let fork = MonitorOptions {
+            statement_keys: &fork_keys,
+            ..full
+        };
+        let backend = fixtures::backend_for(&parse_credential(fork_data).unwrap());
+        for (options, status) in [(&full, "invalid_claim_contents"), (&fork, "valid")] {
+            let mut fork_state = MonitorState::default();
+            round(
+                &backend,
+                &fork_inputs,
+                &mut fork_state,
+                options,
+                None,
+                100,
+                Some(10),
+            )
+            .await;
+            assert_eq!(fork_state.credentials["fork.json"].status, status);
+        }
+
+        // the attestation is revoked, both credentials with its root hash are downgraded
+        let mut revoked = fixtures::backend();
+        revoked.insert_attestation(
+            hex_decode_h256(&fixtures::credential().root_hash).unwrap(),
+            &fixtures::attestation_details(true),
+        );
+        let report = round(&revoked, &inputs, &mut state, &options, None, 200, Some(20)).await;
+        let changes: Vec<_> = report
+            .transitions
+            .iter()
+            .map(|t| (t.source.as_str(), t.to.as_str(), t.is_downgrade()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("a.json", "attestation_revoked", true),
+                ("b.json", "attestation_revoked", true)
+            ]
+        );
+        assert_eq!(report.transitions[0].block, Some(20));
+        assert_eq!(state.credentials["a.json"].since, 200);
+        assert_eq!(state.credentials["c.json"].since, 100);
+        assert_eq!(state.transitions.len(), 2);
+
+        // the state survives a restart
+        let file =
+            std::env::temp_dir().join(format!("kilt-verify-monitor-{}.json", std::process::id()));
+        state.save(&file).unwrap();
+        let mut state = MonitorState::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(MonitorState::load(&file).unwrap().credentials.is_empty());
+        std::fs::remove_file(file.with_extension("lock")).unwrap();
+
+        // an event only checks the credentials it affects, the attestation is gone now
+        let only = HashSet::from([H256::zero()]);
+        let report = round(
+            &MockBackend::default(),
+            &inputs[..1],
+            &mut state,
+            &options,
+            Some(&only),
+            300,
+            Some(30),
+        )
+        .await;
+        assert_eq!(report.checked, 0);
+        let only = HashSet::from([hex_decode_h256(&fixtures::credential().root_hash).unwrap()]);
+        let report = round(
+            &MockBackend::default(),
+            &inputs[..1],
+            &mut state,
+            &options,
+            Some(&only),
+            300,
+            Some(30),
+        )
+        .await;
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.transitions[0].from, "attestation_revoked");
+        assert!(!report.transitions[0].is_downgrade());
+        // it is not a full round, the other credentials are kept
+        assert_eq!(state.credentials.len(), 3);
+
+        // credentials that left the set are forgotten by a full round
+        round(
+            &revoked,
+            &inputs[..1],
+            &mut state,
+            &options,
+            None,
+            400,
+            Some(40),
+        )
+        .await;
+        assert_eq!(state.credentials.keys().collect::<Vec<_>>(), vec!["a.json"]);
+        assert_eq!(state.transitions.len(), 4);
+    }
+
+    #[test]
+    fn test_status() {
+        let root_hash = fixtures::credential().root_hash;
+        let gone = hex_encode(H256::repeat_byte(1));
+        let transition =
+            |source: &str, root_hash: &str, from: &str, to: &str, at, block| Transition {
+                source: source.to_string(),
+                root_hash: Some(root_hash.to_string()),
+                from: from.to_string(),
+                to: to.to_string(),
+                at,
+                block,
+            };
+        let mut state = MonitorState {
+            credentials: BTreeMap::from([(
+                "a.json".to_string(),
+                Tracked {
+                    root_hash: Some(root_hash.clone()),
+                    status: VALID.to_string(),
+                    since: 300,
+                    last_checked: 360,
+                },
+            )]),
+            transitions: vec![
+                transition("gone.json", &gone, VALID, "attestation_revoked", 150, None),
+                transition(
+                    "a.json",
+                    &root_hash,
+                    VALID,
+                    "attestation_revoked",
+                    200,
+                    Some(20),
+                ),
+                transition(
+                    "a.json",
+                    &root_hash,
+                    "attestation_revoked",
+                    VALID,
+                    300,
+                    Some(30),
+                ),
+            ],
+        };
+        assert_eq!(
+            describe_status(&state, None).unwrap(),
+            format!(
+                "a.json: valid since 1970-01-01T00:05:00.000Z, last checked 1970-01-01T00:06:00.000Z\n\
+                 \x20 Root hash: {}\n\
+                 \x20 Last change: attestation_revoked -> valid at 1970-01-01T00:05:00.000Z, block #30\n\
+                 \x20 History:\n\
+                 \x20   valid -> attestation_revoked at 1970-01-01T00:03:20.000Z, block #20\n\
+                 \x20   attestation_revoked -> valid at 1970-01-01T00:05:00.000Z, block #30\n\
+                 gone.json: not monitored anymore\n\
+                 \x20 Last change: valid -> attestation_revoked at 1970-01-01T00:02:30.000Z\n\
+                 \x20 History:\n\
+                 \x20   valid -> attestation_revoked at 1970-01-01T00:02:30.000Z\n",
+                root_hash
+            )
+        );
+        let only = describe_status(&state, Some(H256::repeat_byte(1))).unwrap();
+        assert!(
+            only.starts_with("gone.json: not monitored anymore\n"),
+            "{}",
+            only
+        );
+        assert!(!only.contains("a.json"), "{}", only);
+        let res = describe_status(&state, Some(H256::zero()));
+        assert!(matches!(res, Err(Error::NotMonitored(_))), "{:?}", res);
+
+        // changes older than the retention are forgotten, the status stays
+        state.prune(250);
+        let pruned = describe_status(&state, None).unwrap();
+        assert!(!pruned.contains("gone.json"), "{}", pruned);
+        assert!(pruned.contains("  History:\n    attestation_revoked -> valid at 1970-01-01T00:05:00.000Z, block #30\n"), "{}", pruned);
+        state.prune(400);
+        assert!(describe_status(&state, None)
+            .unwrap()
+            .ends_with("  No changes recorded\n"));
+
+        // the state is never read half written while the monitor saves it
+        let file = std::env::temp_dir().join(format!(
+            "kilt-verify-monitor-status-{}.json",
+            std::process::id()
+        ));
+        state.save(&file).unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..50 {
+                    state.save(&file).unwrap();
+                }
+            });
+            for _ in 0..50 {
+                assert_eq!(MonitorState::load(&file).unwrap(), state);
+            }
+        });
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_file(file.with_extension("lock")).unwrap();
+    }
+}
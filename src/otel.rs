@@ -0,0 +1,646 @@
+//! Experimental export of the verification spans to an OTLP/HTTP collector, behind the `otel`
+//! feature. Its shape can change between versions.
+//!
+//! The spans are encoded as OTLP JSON and posted over plain HTTP like the webhook, instead of
+//! going through the `opentelemetry` and `tracing-opentelemetry` crates: `sp-tracing` of subxt
+//! 0.22 pins `tracing-subscriber` to 0.2, and the releases of `tracing-opentelemetry` built on it
+//! (0.16 and older) pull in unmaintained `opentelemetry` versions whose OTLP exporter brings
+//! tonic and hyper along. Once subxt is upgraded the layer should be replaced by those crates.
+//! Only the spans of a run are exported, there are no metrics or logs, and spans that do not fit
+//! the buffer while the collector is slow are dropped.
+
+use rand::Rng;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+use crate::{errors::Error, webhook::Webhook};
+
+/// The `service.name` of the exported spans
+pub const SERVICE_NAME: &str = "kilt-verify";
+
+/// Number of finished spans kept in memory while the collector is slow, more are dropped
+pub const BUFFER: usize = 2048;
+
+/// Spans sent to the collector in one request
+const MAX_BATCH: usize = 512;
+
+/// The span fields that are exported and the attributes they become. Every other field, like the
+/// owner of a credential, stays out of the traces. Claim contents are never a span field
+const ATTRIBUTES: [(&str, &str); 7] = [
+    ("outcome", "kilt.outcome"),
+    ("code", "kilt.error_code"),
+    ("attester", "kilt.attester"),
+    ("endpoint", "kilt.endpoint"),
+    ("hit", "kilt.cache_hit"),
+    ("storage", "kilt.storage"),
+    ("key_inferred", "kilt.key_inferred"),
+];
+
+/// The trace of a caller and its span, as propagated in a W3C `traceparent` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// The caller records the trace, spans of unsampled traces are not exported
+    pub sampled: bool,
+}
+
+// decode a lowercase hex id that is not all zeros
+fn trace_id<const N: usize>(data: &str) -> Option<[u8; N]> {
+    if !data.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let id: [u8; N] = hex::decode(data).ok()?.try_into().ok()?;
+    id.iter().any(|b| *b != 0).then_some(id)
+}
+
+impl TraceContext {
+    /// Parse a `traceparent`, i.e. "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".
+    /// Versions after 00 may append fields, which are ignored
+    pub fn parse(header: &str) -> Result<Self, Error> {
+        let invalid = || Error::Telemetry(format!("{} is not a W3C traceparent", header));
+        let fields: Vec<&str> = header.trim().split('-').collect();
+        let (trace, span, flags) = match fields[..] {
+            ["00", trace, span, flags] => (trace, span, flags),
+            [version, trace, span, flags, ..]
+                if version.len() == 2 && version != "00" && version != "ff" =>
+            {
+                (trace, span, flags)
+            }
+            _ => return Err(invalid()),
+        };
+        let flags = match flags.len() {
+            2 => u8::from_str_radix(flags, 16).map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        Ok(TraceContext {
+            trace_id: trace_id(trace).ok_or_else(invalid)?,
+            span_id: trace_id(span).ok_or_else(invalid)?,
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = String;
+
+    fn from_str(header: &str) -> Result<Self, Self::Err> {
+        TraceContext::parse(header).map_err(|err| err.to_string())
+    }
+}
+
+/// The value of a span attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+/// A finished span as it is exported
+#[derive(Debug, Clone)]
+pub struct SpanData {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub parent_span_id: Option<[u8; 8]>,
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// The exported fields by their attribute key, see `ATTRIBUTES`
+    pub attributes: BTreeMap<&'static str, AttributeValue>,
+}
+
+// nanoseconds since the unix epoch, as a string like int64 values are in OTLP json
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+impl SpanData {
+    fn to_otlp_json(&self) -> serde_json::Value {
+        let attributes: Vec<_> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    AttributeValue::Bool(value) => json!({ "boolValue": value }),
+                    AttributeValue::Int(value) => json!({ "intValue": value.to_string() }),
+                    AttributeValue::String(value) => json!({ "stringValue": value }),
+                };
+                json!({ "key": key, "value": value })
+            })
+            .collect();
+        let mut span = json!({
+            "traceId": hex::encode(self.trace_id),
+            "spanId": hex::encode(self.span_id),
+            "name": self.name,
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": attributes,
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = hex::encode(parent).into();
+        }
+        span
+    }
+}
+
+/// The spans as an OTLP `ExportTraceServiceRequest` in the json encoding
+pub fn to_otlp_json(spans: &[SpanData]) -> serde_json::Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": SERVICE_NAME } }]
+            },
+            "scopeSpans": [{
+                "scope": { "name": SERVICE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(SpanData::to_otlp_json).collect::<Vec<_>>(),
+            }],
+        }]
+    })
+}
+
+/// Where the finished spans go
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, span: SpanData);
+}
+
+/// Keeps the finished spans in memory, i.e. to look at them in tests
+#[derive(Clone, Default)]
+pub struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl InMemoryExporter {
+    /// The spans exported so far, in the order they finished
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.0.lock().expect("spans are not poisoned").clone()
+    }
+}
+
+impl SpanExporter for InMemoryExporter {
+    fn export(&self, span: SpanData) {
+        self.0.lock().expect("spans are not poisoned").push(span);
+    }
+}
+
+/// Sends the spans to an OTLP/HTTP collector as json in the background. Tracing never fails a
+/// run, spans that do not fit into the buffer or that the collector does not accept are dropped
+/// with a warning.
+pub struct OtlpExporter {
+    collector: Webhook,
+    spans: Mutex<Option<mpsc::Sender<SpanData>>>,
+    delivery: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl OtlpExporter {
+    /// Export to the traces endpoint of the collector at the base URL, i.e. "http://127.0.0.1:4318"
+    pub fn start(endpoint: &str) -> Result<Self, Error> {
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let collector = Webhook::parse(&url).map_err(|_| {
+            Error::Telemetry(format!(
+                "{} is not a http:// URL of an OTLP collector, https is not supported",
+                endpoint
+            ))
+        })?;
+        let (spans, receiver) = mpsc::channel(BUFFER);
+        Ok(OtlpExporter {
+            delivery: Mutex::new(Some(tokio::spawn(deliver(collector.clone(), receiver)))),
+            collector,
+            spans: Mutex::new(Some(spans)),
+        })
+    }
+
+    /// Wait until the spans exported so far are sent, spans exported after are dropped
+    pub async fn finish(&self) {
+        self.spans.lock().expect("spans are not poisoned").take();
+        let delivery = self.delivery.lock().expect("spans are not poisoned").take();
+        if let Some(delivery) = delivery {
+            let _ = delivery.await;
+        }
+    }
+}
+
+impl SpanExporter for OtlpExporter {
+    fn export(&self, span: SpanData) {
+        if let Some(spans) = self.spans.lock().expect("spans are not poisoned").as_ref() {
+            if spans.try_send(span).is_err() {
+                eprintln!(
+                    "Warning: dropped a span, {} is too slow",
+                    self.collector.host
+                );
+            }
+        }
+    }
+}
+
+// send the spans in batches as they come in until the exporter is finished
+async fn deliver(collector: Webhook, mut spans: mpsc::Receiver<SpanData>) {
+    while let Some(span) = spans.recv().await {
+        let mut batch = vec![span];
+        while batch.len() < MAX_BATCH {
+            match spans.try_recv() {
+                Ok(span) => batch.push(span),
+                Err(_) => break,
+            }
+        }
+        let body = to_otlp_json(&batch).to_string();
+        if let Err(err) = collector.post(body.as_bytes()).await {
+            eprintln!("Warning: cannot export {} spans: {}", batch.len(), err);
+        }
+    }
+}
+
+// the state of an open span of this crate, kept in its extensions
+struct OpenSpan {
+    context: TraceContext,
+    parent_span_id: Option<[u8; 8]>,
+    start: SystemTime,
+    attributes: BTreeMap<&'static str, AttributeValue>,
+}
+
+struct Fields<'a>(&'a mut BTreeMap<&'static str, AttributeValue>);
+
+impl Fields<'_> {
+    fn insert(&mut self, field: &Field, value: AttributeValue) {
+        if let Some((_, key)) = ATTRIBUTES.iter().find(|(name, _)| *name == field.name()) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+impl Visit for Fields<'_> {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, AttributeValue::Bool(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, AttributeValue::Int(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.insert(field, AttributeValue::Int(value)),
+            Err(_) => self.insert(field, AttributeValue::String(value.to_string())),
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, AttributeValue::String(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.insert(field, AttributeValue::String(format!("{:?}", value)));
+    }
+}
+
+/// Turns the spans of this crate into OpenTelemetry spans and hands them to the exporter once
+/// they are closed. Spans of other crates are left out, their children attach to the closest
+/// span of this crate.
+pub struct OtelLayer {
+    exporter: Arc<dyn SpanExporter>,
+    parent: Option<TraceContext>,
+}
+
+impl OtelLayer {
+    pub fn new(exporter: Arc<dyn SpanExporter>) -> Self {
+        OtelLayer {
+            exporter,
+            parent: None,
+        }
+    }
+
+    /// Root spans continue the trace of the caller instead of starting a new one
+    pub fn parent(self, parent: Option<TraceContext>) -> Self {
+        OtelLayer { parent, ..self }
+    }
+}
+
+impl<S> Layer<S> for OtelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("the span is open");
+        if !span.metadata().target().starts_with("kilt_verify") {
+            return;
+        }
+        let mut ancestor = span.parent();
+        let parent = loop {
+            match ancestor {
+                Some(parent) => match parent.extensions().get::<OpenSpan>() {
+                    Some(open) => break Some(open.context),
+                    None => ancestor = parent.parent(),
+                },
+                None => break self.parent,
+            }
+        };
+        let mut rng = rand::thread_rng();
+        let context = TraceContext {
+            trace_id: parent.map_or_else(|| rng.gen(), |parent| parent.trace_id),
+            span_id: rng.gen(),
+            sampled: parent.is_none_or(|parent| parent.sampled),
+        };
+        let mut attributes = BTreeMap::new();
+        attrs.record(&mut Fields(&mut attributes));
+        span.extensions_mut().insert(OpenSpan {
+            context,
+            parent_span_id: parent.map(|parent| parent.span_id),
+            start: SystemTime::now(),
+            attributes,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("the span is open");
+        let mut extensions = span.extensions_mut();
+        if let Some(open) = extensions.get_mut::<OpenSpan>() {
+            values.record(&mut Fields(&mut open.attributes));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).expect("the span is open");
+        let open = span.extensions_mut().remove::<OpenSpan>();
+        if let Some(open) = open.filter(|open| open.context.sampled) {
+            self.exporter.export(SpanData {
+                trace_id: open.context.trace_id,
+                span_id: open.context.span_id,
+                parent_span_id: open.parent_span_id,
+                name: span.name(),
+                start: open.start,
+                end: SystemTime::now(),
+                attributes: open.attributes,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cache::{CachedBackend, RevocationCache},
+        fixtures,
+        mock::MockState,
+        utils::hex_decode_h256,
+    };
+    use subxt::sp_core::H256;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tracing::Instrument;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let context = TraceContext::parse(TRACEPARENT).unwrap();
+        assert_eq!(
+            hex::encode(context.trace_id),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(hex::encode(context.span_id), "00f067aa0ba902b7");
+        assert!(context.sampled);
+        let unsampled =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00");
+        assert!(!unsampled.unwrap().sampled);
+        // a later version with more fields
+        let future =
+            TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x");
+        assert_eq!(future.unwrap(), context);
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+        ] {
+            let res = TraceContext::parse(invalid);
+            assert!(matches!(res, Err(Error::Telemetry(_))), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spans() {
+        let cred = fixtures::credential();
+        let root_hash = hex_decode_h256(&cred.root_hash).unwrap();
+        let attester = fixtures::attester_did();
+        let block_hash = H256::repeat_byte(1);
+        let mut chain = fixtures::backend();
+        let mut block = MockState::default();
+        block.insert_attestation(root_hash, &fixtures::attestation_details(false));
+        chain.insert_block(block_hash, block);
+        let cache = RevocationCache::new();
+        cache
+            .sync(&chain, &[root_hash], 1, block_hash)
+            .await
+            .unwrap();
+        let mut tampered = cred.clone();
+        tampered.claim.contents = serde_json::json!({"Email": "mallory@example.com"});
+
+        let caller = TraceContext::parse(TRACEPARENT).unwrap();
+        let exporter = InMemoryExporter::default();
+        let layer = OtelLayer::new(Arc::new(exporter.clone())).parent(Some(caller));
+        let _guard = tracing::subscriber::set_default(Registry::default().with(layer));
+        let backend = CachedBackend::new(&chain, &cache);
+        async {
+            cred.verify(&backend, &[&attester], None).await.unwrap();
+            let res = tampered.verify(&backend, &[&attester], None).await;
+            assert!(matches!(res, Err(Error::InvalidClaimContents)), "{:?}", res);
+        }
+        .instrument(tracing::info_span!("run", endpoint = "ws://127.0.0.1:9944"))
+        .await;
+
+        let spans = exporter.spans();
+        let name_of = |id: Option<[u8; 8]>| {
+            id.map(|id| match spans.iter().find(|span| span.span_id == id) {
+                Some(span) => span.name,
+                None if id == caller.span_id => "caller",
+                None => "unknown",
+            })
+        };
+        let tree: Vec<_> = spans
+            .iter()
+            .map(|span| (span.name, name_of(span.parent_span_id)))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                ("check_claim_contents", Some("verify")),
+                ("check_root_hash", Some("verify")),
+                ("check_signature", Some("verify")),
+                ("cached_attestation", Some("check_attestation")),
+                ("check_attestation", Some("verify")),
+                ("verify", Some("run")),
+                ("check_claim_contents", Some("verify")),
+                ("verify", Some("run")),
+                ("run", Some("caller")),
+            ]
+        );
+        assert!(spans.iter().all(|span| span.trace_id == caller.trace_id));
+
+        let string = |value: &str| AttributeValue::String(value.to_string());
+        assert_eq!(
+            spans[5].attributes,
+            BTreeMap::from([
+                ("kilt.attester", string(&attester)),
+                ("kilt.outcome", string("valid")),
+            ])
+        );
+        assert_eq!(
+            spans[7].attributes,
+            BTreeMap::from([
+                ("kilt.error_code", string("invalid_claim_contents")),
+                ("kilt.outcome", string("invalid")),
+            ])
+        );
+        assert_eq!(
+            spans[8].attributes,
+            BTreeMap::from([("kilt.endpoint", string("ws://127.0.0.1:9944"))])
+        );
+        assert_eq!(
+            spans[3].attributes["kilt.cache_hit"],
+            AttributeValue::Bool(true)
+        );
+        assert_eq!(
+            spans[2].attributes["kilt.key_inferred"],
+            AttributeValue::Bool(false)
+        );
+        // neither the claim contents nor the owner are attached
+        for span in spans.iter() {
+            for value in span.attributes.values() {
+                let value = format!("{:?}", value);
+                assert!(!value.contains('@'), "{}: {}", span.name, value);
+                assert!(
+                    !value.contains(&cred.claim.owner),
+                    "{}: {}",
+                    span.name,
+                    value
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsampled_caller() {
+        let caller = TraceContext {
+            sampled: false,
+            ..TraceContext::parse(TRACEPARENT).unwrap()
+        };
+        let exporter = InMemoryExporter::default();
+        let layer = OtelLayer::new(Arc::new(exporter.clone())).parent(Some(caller));
+        let _guard = tracing::subscriber::set_default(Registry::default().with(layer));
+        let cred = fixtures::credential();
+        let attester = fixtures::attester_did();
+        cred.verify(&fixtures::backend(), &[&attester], None)
+            .await
+            .unwrap();
+        assert!(exporter.spans().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = String::new();
+            let mut buf = vec![0; 4096];
+            // read until the body is as long as the head says
+            loop {
+                let len = stream.read(&mut buf).await.unwrap();
+                request.push_str(std::str::from_utf8(&buf[..len]).unwrap());
+                if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap();
+                    if body.len() == length.parse::<usize>().unwrap() {
+                        break;
+                    }
+                }
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            request
+        });
+
+        let exporter = OtlpExporter::start(&endpoint).unwrap();
+        let caller = TraceContext::parse(TRACEPARENT).unwrap();
+        exporter.export(SpanData {
+            trace_id: caller.trace_id,
+            span_id: [1; 8],
+            parent_span_id: Some(caller.span_id),
+            name: "verify",
+            start: UNIX_EPOCH,
+            end: UNIX_EPOCH + std::time::Duration::from_millis(3),
+            attributes: BTreeMap::from([
+                ("kilt.outcome", AttributeValue::String("valid".to_string())),
+                ("kilt.cache_hit", AttributeValue::Bool(true)),
+            ]),
+        });
+        exporter.finish().await;
+
+        let request = collector.await.unwrap();
+        assert!(
+            request.starts_with("POST /v1/traces HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            SERVICE_NAME
+        );
+        assert_eq!(
+            resource["scopeSpans"][0]["spans"][0],
+            json!({
+                "traceId": "4bf92f3577b34da6a3ce929d0e0e4736",
+                "spanId": "0101010101010101",
+                "parentSpanId": "00f067aa0ba902b7",
+                "name": "verify",
+                "kind": 1,
+                "startTimeUnixNano": "0",
+                "endTimeUnixNano": "3000000",
+                "attributes": [
+                    {"key": "kilt.cache_hit", "value": {"boolValue": true}},
+                    {"key": "kilt.outcome", "value": {"stringValue": "valid"}},
+                ],
+            })
+        );
+
+        assert!(matches!(
+            OtlpExporter::start("https://collector:4318"),
+            Err(Error::Telemetry(_))
+        ));
+    }
+}
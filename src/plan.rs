@@ -0,0 +1,348 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt;
+
+use crate::{
+    archive::ArchiveOptions,
+    batch::{self, BatchInput},
+    credential::{ClaimRequirements, Credential},
+    errors::Error,
+    manifest::ManifestInput,
+    uri,
+    utils::{read_credential_data, InputFormat, ParseOptions},
+};
+
+/// The credentials a run verifies
+#[derive(Debug)]
+pub enum PlanInput {
+    Single(Box<Credential>),
+    Batch(Vec<BatchInput>),
+    /// Credentials of a manifest, each with its own endpoint and options
+    Manifest(Vec<ManifestInput>),
+}
+
+impl PlanInput {
+    /// Read a single credential, from a file or a deep link, or the credentials of a batch. The
+    /// credentials of a batch are parsed when they are verified
+    pub fn read(file: &str, archive: &ArchiveOptions, parse: &ParseOptions) -> Result<Self, Error> {
+        if uri::is_uri(file) {
+            let cred = parse.parse("uri", &uri::decode(file)?)?;
+            Ok(PlanInput::Single(Box::new(cred)))
+        } else if batch::is_batch(file) {
+            Ok(PlanInput::Batch(batch::read_inputs(file, archive, parse)?))
+        } else {
+            let cred = parse.parse(file, &read_credential_data(file)?)?;
+            Ok(PlanInput::Single(Box::new(cred)))
+        }
+    }
+}
+
+/// Everything a verification run does, resolved from the arguments before anything is sent to a node.
+/// Real runs and `--dry-run` both use it, so the printed plan is what a run would do.
+#[derive(Debug)]
+pub struct VerificationPlan {
+    /// File, directory, archive or "stdin" the credentials were read from
+    pub source: String,
+    pub input: PlanInput,
+    pub endpoint: String,
+    pub allowed_issuers: Vec<String>,
+    pub challenge: Option<String>,
+    /// Number of credentials of a batch that are verified at the same time
+    pub concurrency: usize,
+    pub requirements: ClaimRequirements,
+    /// Time the credential is verified at, the latest block if `None`
+    pub valid_at: Option<DateTime<Utc>>,
+    /// Number of blocks the attestation has to be below the block the credential is verified at
+    pub min_confirmations: u32,
+    /// DID of the registry whose members are trusted along with the allowed issuers
+    pub issuer_registry: Option<String>,
+    /// How the credentials are parsed
+    pub parse: ParseOptions,
+}
+
+/// A check of the plan and the reason it is skipped, if it is
+pub type PlannedCheck = (&'static str, Option<&'static str>);
+
+impl VerificationPlan {
+    /// Resolve the options of the run for the credentials read from `source`
+    pub fn new(
+        source: &str,
+        input: PlanInput,
+        endpoint: &str,
+        allowed_issuers: &[&str],
+        challenge: Option<&str>,
+        concurrency: usize,
+        requirements: ClaimRequirements,
+    ) -> Self {
+        VerificationPlan {
+            source: source.to_string(),
+            input,
+            endpoint: endpoint.to_string(),
+            allowed_issuers: allowed_issuers.iter().map(|s| s.to_string()).collect(),
+            challenge: challenge.map(str::to_string),
+            concurrency,
+            requirements,
+            valid_at: None,
+            min_confirmations: 0,
+            issuer_registry: None,
+            parse: ParseOptions::default(),
+        }
+    }
+
+    pub fn allowed_issuers(&self) -> Vec<&str> {
+        self.allowed_issuers.iter().map(String::as_str).collect()
+    }
+
+    /// The checks in the order they run, `Credential::verify` followed by the claim requirements
+    pub fn checks(&self) -> Vec<PlannedCheck> {
+        vec![
+            ("claim contents", None),
+            ("root hash", None),
+            (
+                "challenge",
+                self.challenge
+                    .is_none()
+                    .then_some("no challenge given, any challenge is accepted"),
+            ),
+            ("signature", None),
+            ("attestation", None),
+            (
+                "confirmations",
+                (self.min_confirmations == 0).then_some("no --min-confirmations given"),
+            ),
+            (
+                "owner",
+                self.requirements
+                    .owner
+                    .is_none()
+                    .then_some("no expected owner given"),
+            ),
+            (
+                "required properties",
+                self.requirements
+                    .properties
+                    .is_empty()
+                    .then_some("no required properties given"),
+            ),
+        ]
+    }
+}
+
+impl VerificationPlan {
+    fn fmt_batch<'a>(
+        &self,
+        f: &mut fmt::Formatter,
+        inputs: impl ExactSizeIterator<Item = &'a BatchInput>,
+    ) -> fmt::Result {
+        let total = inputs.len();
+        let unparsable = inputs
+            .filter(|input| self.parse.parse(&input.source, &input.data).is_err())
+            .count();
+        writeln!(
+            f,
+            "  input:       {} ({} credentials, {} not parsable)",
+            self.source, total, unparsable
+        )?;
+        writeln!(f, "  concurrency: {}", self.concurrency)
+    }
+}
+
+impl fmt::Display for VerificationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Verification plan")?;
+        match &self.input {
+            PlanInput::Single(cred) => {
+                writeln!(f, "  input:       {} (1 credential)", self.source)?;
+                if cred.root_hash_derived {
+                    writeln!(
+                        f,
+                        "  root hash:   {} (derived from the claim hashes)",
+                        cred.root_hash
+                    )?;
+                } else {
+                    writeln!(f, "  root hash:   {} (declared)", cred.root_hash)?;
+                }
+            }
+            PlanInput::Batch(inputs) => {
+                self.fmt_batch(f, inputs.iter())?;
+            }
+            PlanInput::Manifest(inputs) => {
+                self.fmt_batch(f, inputs.iter().map(|input| &input.input))?;
+                writeln!(f, "  entries:")?;
+                for input in inputs {
+                    write!(f, "    {} on {}", input.input.source, input.endpoint)?;
+                    if let Some(owner) = &input.entry.expected_owner {
+                        write!(f, ", owner {}", owner)?;
+                    }
+                    if let Some(issuers) = &input.entry.allowed_issuers {
+                        write!(f, ", issuers {}", issuers.join(" "))?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        if let Some(format) = self.parse.format.filter(|f| *f != InputFormat::Json) {
+            writeln!(f, "  parsing:     {}", format.name())?;
+        }
+        if self.parse.lenient_json {
+            writeln!(
+                f,
+                "  parsing:     lenient, as JSON5 with comments and trailing commas"
+            )?;
+        }
+        if self.parse.max_decompressed_size.is_none() {
+            writeln!(f, "  parsing:     gzip and zstd input is not decompressed")?;
+        }
+        match self.input {
+            PlanInput::Manifest(_) => writeln!(
+                f,
+                "  endpoint:    {} (entries without a network or endpoint)",
+                self.endpoint
+            )?,
+            _ => writeln!(f, "  endpoint:    {}", self.endpoint)?,
+        }
+        match self.valid_at {
+            Some(time) => writeln!(
+                f,
+                "  block:       last block produced at or before {}, resolved once connected",
+                time.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )?,
+            None => writeln!(f, "  block:       latest block at the time of each lookup")?,
+        }
+        if self.min_confirmations > 0 {
+            writeln!(
+                f,
+                "  depth:       attestations at least {} blocks below that block",
+                self.min_confirmations
+            )?;
+        }
+        writeln!(
+            f,
+            "  challenge:   {}",
+            self.challenge.as_deref().unwrap_or("none")
+        )?;
+        writeln!(
+            f,
+            "  owner:       {}",
+            self.requirements.owner.as_deref().unwrap_or("any")
+        )?;
+        if self.requirements.full_did {
+            writeln!(f, "  owner DID:   full, light DIDs are rejected")?;
+        }
+        if !self.requirements.properties.is_empty() {
+            writeln!(
+                f,
+                "  properties:  {}",
+                self.requirements.properties.join(", ")
+            )?;
+        }
+        writeln!(f, "  issuers:")?;
+        for issuer in self.allowed_issuers.iter() {
+            writeln!(f, "    {}", issuer)?;
+        }
+        if let Some(registry) = &self.issuer_registry {
+            writeln!(
+                f,
+                "    members of registry {}, resolved once connected",
+                registry
+            )?;
+        }
+        writeln!(f, "  checks:")?;
+        for (check, skipped) in self.checks() {
+            match skipped {
+                None => writeln!(f, "    [run]  {}", check)?,
+                Some(reason) => writeln!(f, "    [skip] {} ({})", check, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+        let input = || PlanInput::read(file, &Default::default(), &Default::default()).unwrap();
+        let plan = VerificationPlan::new(
+            file,
+            input(),
+            "ws://127.0.0.1:9944",
+            &["did:kilt:4abc"],
+            None,
+            8,
+            Default::default(),
+        );
+        assert!(matches!(plan.input, PlanInput::Single(_)));
+        assert_eq!(plan.allowed_issuers(), vec!["did:kilt:4abc"]);
+        let skipped: Vec<_> = plan
+            .checks()
+            .into_iter()
+            .filter(|(_, skipped)| skipped.is_some())
+            .map(|(check, _)| check)
+            .collect();
+        assert_eq!(
+            skipped,
+            vec!["challenge", "confirmations", "owner", "required properties"]
+        );
+        assert!(!plan.to_string().contains("depth:"));
+
+        let text = plan.to_string();
+        assert!(
+            text.contains("endpoint:    ws://127.0.0.1:9944"),
+            "{}",
+            text
+        );
+        assert!(text.contains("    did:kilt:4abc\n"), "{}", text);
+        assert!(text.contains("[skip] challenge"), "{}", text);
+        assert!(
+            text.contains("root hash:   0xf69ce26ca50b5d5f38cd32a99d031cd52fff42f17b9afb32895ffba260fb616a (declared)\n"),
+            "{}",
+            text
+        );
+
+        let requirements = ClaimRequirements {
+            owner: Some("did:kilt:4owner".to_string()),
+            properties: vec!["Email".to_string(), "Name".to_string()],
+            full_did: false,
+        };
+        let mut plan =
+            VerificationPlan::new(file, input(), "", &[], Some("0x1234"), 8, requirements);
+        plan.min_confirmations = 6;
+        assert!(plan.checks().iter().all(|(_, skipped)| skipped.is_none()));
+        let text = plan.to_string();
+        assert!(text.contains("challenge:   0x1234"), "{}", text);
+        assert!(text.contains("owner:       did:kilt:4owner"), "{}", text);
+        assert!(text.contains("properties:  Email, Name"), "{}", text);
+        assert!(
+            text.contains("depth:       attestations at least 6 blocks below that block"),
+            "{}",
+            text
+        );
+
+        let mut plan = VerificationPlan::new(file, input(), "", &[], None, 8, Default::default());
+        plan.valid_at = Some(DateTime::from_timestamp(1_622_505_600, 0).unwrap());
+        plan.issuer_registry = Some("did:kilt:4registry".to_string());
+        let text = plan.to_string();
+        assert!(
+            text.contains(
+                "  issuers:\n    members of registry did:kilt:4registry, resolved once connected\n"
+            ),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("block:       last block produced at or before 2021-06-01T00:00:00Z"),
+            "{}",
+            text
+        );
+
+        let res = PlanInput::read(
+            "does-not-exist.json",
+            &Default::default(),
+            &Default::default(),
+        );
+        assert!(matches!(res, Err(Error::Io(_))));
+    }
+}
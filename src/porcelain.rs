@@ -0,0 +1,85 @@
+//! The `--porcelain` output: one line of tab separated fields per credential.
+//!
+//! The format is a compatibility contract for scripts and does not change between versions:
+//!
+//! - `VALID\t<root hash>\t<owner DID>\t<attester DID>`
+//! - `INVALID\t<root hash>\t<error code>`
+//!
+//! The root hash is `-` if the credential could not be parsed. Values taken from the credential
+//! have backslashes and control characters escaped (`\\`, `\t`, `\n`, ...), so a line never
+//! contains more fields or line breaks than listed above.
+
+use crate::errors::Error;
+
+/// Placeholder for values that are not known, i.e. the root hash of unparsable input
+pub const MISSING: &str = "-";
+
+/// Escape a value so it neither contains tabs nor line breaks
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// The line of a valid credential
+pub fn valid(root_hash: &str, owner: &str, attester: &str) -> String {
+    format!(
+        "VALID\t{}\t{}\t{}",
+        escape(root_hash),
+        escape(owner),
+        escape(attester)
+    )
+}
+
+/// The line of an invalid credential, `root_hash` is `None` if it could not be parsed
+pub fn invalid(root_hash: Option<&str>, err: &Error) -> String {
+    format!(
+        "INVALID\t{}\t{}",
+        escape(root_hash.unwrap_or(MISSING)),
+        err.code()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(
+            valid("0x12", "did:kilt:4owner", "did:kilt:4attester"),
+            "VALID\t0x12\tdid:kilt:4owner\tdid:kilt:4attester"
+        );
+        assert_eq!(
+            invalid(Some("0x12"), &Error::AttestationRevoked),
+            "INVALID\t0x12\tattestation_revoked"
+        );
+        assert_eq!(
+            invalid(None, &Error::InvalidSignature),
+            "INVALID\t-\tinvalid_signature"
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("0x12"), "0x12");
+        assert_eq!(escape("a\tb\nc\r\\d"), "a\\tb\\nc\\r\\\\d");
+        assert_eq!(escape("\u{1b}[31m"), "\\u{1b}[31m");
+        assert_eq!(escape("ünïcode"), "ünïcode");
+
+        let line = invalid(Some("0x12\tVALID\n"), &Error::InvalidRootHash);
+        assert_eq!(line, "INVALID\t0x12\\tVALID\\n\tinvalid_root_hash");
+        assert_eq!(line.split('\t').count(), 3);
+        assert!(!line.contains('\n'));
+
+        let line = valid("0x12", "did:kilt:4a\tb", "did:kilt:4c\nd");
+        assert_eq!(line.split('\t').count(), 4);
+        assert!(!line.contains('\n'));
+    }
+}
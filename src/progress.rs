@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use subxt::sp_core::H256;
+
+use crate::{errors::Error, metrics::Check};
+
+/// A step of a verification, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStep {
+    ClaimContents,
+    RootHash,
+    Challenge,
+    Signature,
+    Attestation,
+    Requirements,
+}
+
+impl CheckStep {
+    pub const ALL: [CheckStep; 6] = [
+        CheckStep::ClaimContents,
+        CheckStep::RootHash,
+        CheckStep::Challenge,
+        CheckStep::Signature,
+        CheckStep::Attestation,
+        CheckStep::Requirements,
+    ];
+
+    /// The timed check of the step, the challenge and the requirements are not timed
+    pub fn check(&self) -> Option<Check> {
+        match self {
+            CheckStep::ClaimContents => Some(Check::ClaimContents),
+            CheckStep::RootHash => Some(Check::RootHash),
+            CheckStep::Signature => Some(Check::Signature),
+            CheckStep::Attestation => Some(Check::Attestation),
+            CheckStep::Challenge | CheckStep::Requirements => None,
+        }
+    }
+
+    /// What the step does, i.e. to show while it runs
+    pub fn describe(&self) -> &'static str {
+        match self {
+            CheckStep::ClaimContents => "checking claim contents",
+            CheckStep::RootHash => "checking root hash",
+            CheckStep::Challenge => "checking challenge",
+            CheckStep::Signature => "checking signature",
+            CheckStep::Attestation => "checking attestation",
+            CheckStep::Requirements => "checking requirements",
+        }
+    }
+}
+
+/// Where a step is at
+#[derive(Debug, Clone, Copy)]
+pub enum StepStatus<'a> {
+    Started,
+    Passed { elapsed: Duration },
+    Failed { elapsed: Duration, error: &'a Error },
+}
+
+impl StepStatus<'_> {
+    /// How long a finished step took
+    pub fn elapsed(&self) -> Option<Duration> {
+        match self {
+            StepStatus::Started => None,
+            StepStatus::Passed { elapsed } | StepStatus::Failed { elapsed, .. } => Some(*elapsed),
+        }
+    }
+}
+
+/// Gets told about the progress of verifications, i.e. to show it in a user interface.
+/// Closures taking a step and its status are observers as well.
+pub trait VerificationObserver: Send + Sync {
+    /// Called when a step starts and again when it passed or failed
+    fn on_step(&self, step: CheckStep, status: StepStatus<'_>);
+
+    /// Called before something that failed is tried again, `attempt` counts from 2
+    fn on_retry(&self, _what: &str, _attempt: usize) {}
+
+    /// Called when the attestation of a root hash is served from a cache instead of the node
+    fn on_cache_hit(&self, _root_hash: &H256) {}
+}
+
+impl<F> VerificationObserver for F
+where
+    F: Fn(CheckStep, StepStatus<'_>) + Send + Sync,
+{
+    fn on_step(&self, step: CheckStep, status: StepStatus<'_>) {
+        self(step, status)
+    }
+}
+
+// tell the observer how a step ended
+fn finish<T>(
+    observer: &dyn VerificationObserver,
+    step: CheckStep,
+    start: Instant,
+    res: &Result<T, Error>,
+) {
+    let elapsed = start.elapsed();
+    match res {
+        Ok(_) => observer.on_step(step, StepStatus::Passed { elapsed }),
+        Err(error) => observer.on_step(step, StepStatus::Failed { elapsed, error }),
+    }
+}
+
+/// Run a step and tell the observer when it starts and ends
+pub fn observe<T>(
+    observer: &dyn VerificationObserver,
+    step: CheckStep,
+    check: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    observer.on_step(step, StepStatus::Started);
+    let start = Instant::now();
+    let res = check();
+    finish(observer, step, start, &res);
+    res
+}
+
+/// Like `observe` for a step that queries the chain
+pub async fn observe_async<T>(
+    observer: &dyn VerificationObserver,
+    step: CheckStep,
+    check: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    observer.on_step(step, StepStatus::Started);
+    let start = Instant::now();
+    let res = check.await;
+    finish(observer, step, start, &res);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_observe() {
+        let seen = Mutex::new(Vec::new());
+        let observer = |step: CheckStep, status: StepStatus<'_>| {
+            let status = match status {
+                StepStatus::Started => "started".to_string(),
+                StepStatus::Passed { .. } => "passed".to_string(),
+                StepStatus::Failed { error, .. } => error.code().to_string(),
+            };
+            seen.lock().unwrap().push((step, status));
+        };
+
+        assert_eq!(
+            observe(&observer, CheckStep::RootHash, || Ok(1)).unwrap(),
+            1
+        );
+        let res: Result<(), _> = observe_async(&observer, CheckStep::Attestation, async {
+            Err(Error::AttestationRevoked)
+        })
+        .await;
+        assert!(matches!(res, Err(Error::AttestationRevoked)));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (CheckStep::RootHash, "started".to_string()),
+                (CheckStep::RootHash, "passed".to_string()),
+                (CheckStep::Attestation, "started".to_string()),
+                (CheckStep::Attestation, "attestation_revoked".to_string()),
+            ]
+        );
+    }
+}
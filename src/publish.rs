@@ -0,0 +1,353 @@
+use std::{str::FromStr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{errors::Error, metrics};
+
+/// How long the server may take to confirm the messages sent to it
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of messages kept in memory while the server is slow, publishing waits once it is full
+pub const BUFFER: usize = 1024;
+
+/// Times a message is sent before it is given up
+const ATTEMPTS: u32 = 3;
+
+/// Time before the first retry, each further retry waits longer
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Messages sent before waiting for the server to confirm them
+const MAX_BATCH: usize = 64;
+
+const DEFAULT_PORT: u16 = 4222;
+
+/// A `nats://` URL split into the address of the server and the subject to publish on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sink {
+    /// Host with the port, i.e. "127.0.0.1:4222"
+    pub address: String,
+    pub subject: String,
+}
+
+impl Sink {
+    /// Parse `nats://host[:port]/subject`, TLS and authentication are not supported
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let rest = url
+            .strip_prefix("nats://")
+            .ok_or_else(|| Error::Publish(format!("{} is not a nats:// URL", url)))?;
+        let (host, subject) = rest
+            .split_once('/')
+            .ok_or_else(|| Error::Publish(format!("{} names no subject", url)))?;
+        if host.is_empty() {
+            return Err(Error::Publish(format!("{} has no host", url)));
+        }
+        // wildcards can be subscribed to, not published on
+        if subject.split('.').any(|token| {
+            token.is_empty() || token == "*" || token == ">" || token.contains(char::is_whitespace)
+        }) {
+            return Err(Error::Publish(format!(
+                "{} is not a subject that can be published on",
+                subject
+            )));
+        }
+        let address = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+        {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        Ok(Sink {
+            address,
+            subject: subject.to_string(),
+        })
+    }
+}
+
+impl FromStr for Sink {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        Sink::parse(url).map_err(|err| err.to_string())
+    }
+}
+
+/// Publishes messages to a NATS server in the background. A message counts as published once the
+/// server answered a ping sent after it, messages it did not confirm are sent again, so they can
+/// arrive more than once. Messages that fail every attempt are counted in the metrics and by
+/// `finish`.
+pub struct Publisher {
+    subject: String,
+    messages: mpsc::Sender<Vec<u8>>,
+    delivery: JoinHandle<usize>,
+}
+
+impl Publisher {
+    /// Connect once the first message is published, up to `buffer` messages wait for the server
+    pub fn start(sink: Sink, buffer: usize) -> Self {
+        let (messages, receiver) = mpsc::channel(buffer.max(1));
+        Publisher {
+            subject: sink.subject.clone(),
+            messages,
+            delivery: tokio::spawn(deliver(sink, receiver)),
+        }
+    }
+
+    /// Queue a message, waits while the buffer is full
+    pub async fn publish(&self, message: Vec<u8>) {
+        // the delivery only stops once the publisher is finished
+        let _ = self.messages.send(message).await;
+    }
+
+    /// Wait until all queued messages are delivered or given up and close the connection
+    pub async fn finish(self) -> Result<(), Error> {
+        drop(self.messages);
+        let failed = self
+            .delivery
+            .await
+            .map_err(|err| Error::Publish(err.to_string()))?;
+        match failed {
+            0 => Ok(()),
+            failed => Err(Error::PublishFailed {
+                subject: self.subject,
+                failed,
+            }),
+        }
+    }
+}
+
+// send the messages as they come in until the publisher is finished, returns the number of
+// messages that were given up
+async fn deliver(sink: Sink, mut messages: mpsc::Receiver<Vec<u8>>) -> usize {
+    let mut connection = None;
+    let mut failed = 0;
+    while let Some(message) = messages.recv().await {
+        let mut pending = vec![message];
+        while pending.len() < MAX_BATCH {
+            match messages.try_recv() {
+                Ok(message) => pending.push(message),
+                Err(_) => break,
+            }
+        }
+        for attempt in 1..=ATTEMPTS {
+            let sent = tokio::time::timeout(TIMEOUT, send(&sink, &mut connection, &pending))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error::Publish(format!(
+                        "{} did not confirm in time",
+                        sink.address
+                    )))
+                });
+            match sent {
+                Ok(()) => {
+                    metrics::record_published(pending.len(), true);
+                    break;
+                }
+                Err(err) if attempt == ATTEMPTS => {
+                    eprintln!(
+                        "Warning: cannot publish {} results to {}: {}",
+                        pending.len(),
+                        sink.subject,
+                        err
+                    );
+                    metrics::record_published(pending.len(), false);
+                    failed += pending.len();
+                }
+                Err(_) => {
+                    // the server may have missed anything since it last confirmed
+                    connection = None;
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                }
+            }
+        }
+    }
+    failed
+}
+
+type Connection = BufReader<TcpStream>;
+
+// publish the messages and wait for the server to confirm them, connecting first if needed
+async fn send(
+    sink: &Sink,
+    connection: &mut Option<Connection>,
+    messages: &[Vec<u8>],
+) -> Result<(), Error> {
+    let stream = match connection {
+        Some(stream) => stream,
+        None => connection.insert(connect(&sink.address).await?),
+    };
+    let mut data = Vec::new();
+    for message in messages {
+        data.extend_from_slice(format!("PUB {} {}\r\n", sink.subject, message.len()).as_bytes());
+        data.extend_from_slice(message);
+        data.extend_from_slice(b"\r\n");
+    }
+    data.extend_from_slice(b"PING\r\n");
+    stream.get_mut().write_all(&data).await?;
+    pong(stream).await
+}
+
+async fn connect(address: &str) -> Result<Connection, Error> {
+    let mut stream = BufReader::new(TcpStream::connect(address).await?);
+    // the server introduces itself first
+    if !read_line(&mut stream).await?.starts_with("INFO ") {
+        return Err(Error::Publish(format!("{} is not a NATS server", address)));
+    }
+    stream
+        .get_mut()
+        .write_all(
+            b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"kilt-verify\"}\r\nPING\r\n",
+        )
+        .await?;
+    pong(&mut stream).await?;
+    Ok(stream)
+}
+
+// read until the server answers our ping, answering the pings of the server
+async fn pong(stream: &mut Connection) -> Result<(), Error> {
+    loop {
+        match read_line(stream).await?.as_str() {
+            "PONG" => return Ok(()),
+            "PING" => stream.get_mut().write_all(b"PONG\r\n").await?,
+            line if line.starts_with("-ERR") => {
+                return Err(Error::Publish(format!("the server answered {}", line)))
+            }
+            // +OK and updated INFO
+            _ => {}
+        }
+    }
+}
+
+async fn read_line(stream: &mut Connection) -> Result<String, Error> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(Error::Publish(
+            "the server closed the connection".to_string(),
+        ));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        collections::BTreeSet,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    #[test]
+    fn test_parse() {
+        let sink = Sink::parse("nats://127.0.0.1:4333/kilt.results").unwrap();
+        assert_eq!(sink.address, "127.0.0.1:4333");
+        assert_eq!(sink.subject, "kilt.results");
+        let sink = Sink::parse("nats://example.com/results").unwrap();
+        assert_eq!(sink.address, "example.com:4222");
+        for url in [
+            "tls://example.com/results",
+            "nats://example.com",
+            "nats:///results",
+            "nats://example.com/",
+            "nats://example.com/kilt..results",
+            "nats://example.com/kilt.*",
+            "nats://example.com/kilt results",
+        ] {
+            assert!(
+                matches!(Sink::parse(url), Err(Error::Publish(_))),
+                "{}",
+                url
+            );
+        }
+    }
+
+    // a NATS server that collects what is published, the first connection is closed before the
+    // first messages are confirmed
+    async fn serve(listener: TcpListener, received: Arc<Mutex<Vec<String>>>) {
+        for connection in 0.. {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream
+                .get_mut()
+                .write_all(b"INFO {\"server_id\":\"test\"}\r\n")
+                .await
+                .unwrap();
+            let mut pings = 0;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["PING"] => {
+                        pings += 1;
+                        if connection == 0 && pings == 2 {
+                            break;
+                        }
+                        stream.get_mut().write_all(b"PONG\r\n").await.unwrap();
+                    }
+                    ["PUB", subject, len] => {
+                        let mut payload = vec![0; len.parse::<usize>().unwrap() + 2];
+                        stream.read_exact(&mut payload).await.unwrap();
+                        payload.truncate(payload.len() - 2);
+                        let payload = String::from_utf8(payload).unwrap();
+                        received
+                            .lock()
+                            .unwrap()
+                            .push(format!("{} {}", subject, payload));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}/kilt.results", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server = tokio::spawn(serve(listener, received.clone()));
+
+        let publisher = Publisher::start(Sink::parse(&url).unwrap(), 2);
+        for message in ["{\"a\":1}", "{\"b\":2}", "{\"c\":3}"] {
+            publisher.publish(message.as_bytes().to_vec()).await;
+        }
+        publisher.finish().await.unwrap();
+        server.abort();
+
+        // the messages the first connection did not confirm are sent again
+        let received = received.lock().unwrap();
+        assert!(received.len() > 3, "{:?}", received);
+        assert_eq!(
+            received.iter().map(String::as_str).collect::<BTreeSet<_>>(),
+            BTreeSet::from([
+                "kilt.results {\"a\":1}",
+                "kilt.results {\"b\":2}",
+                "kilt.results {\"c\":3}"
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_failed() {
+        // nothing listens on the port anymore
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}/kilt.results", listener.local_addr().unwrap());
+        drop(listener);
+
+        let publisher = Publisher::start(Sink::parse(&url).unwrap(), 1);
+        publisher.publish(b"{}".to_vec()).await;
+        let res = publisher.finish().await;
+        assert!(
+            matches!(&res, Err(Error::PublishFailed { subject, failed: 1 }) if subject == "kilt.results"),
+            "{:?}",
+            res
+        );
+    }
+}
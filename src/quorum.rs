@@ -0,0 +1,354 @@
+use async_trait::async_trait;
+use codec::Encode;
+use futures::future::{join_all, BoxFuture};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    history::{self, BlockHistory, ResolvedBlock},
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+    },
+    utils::Token,
+};
+
+/// The block all endpoints of a quorum read: the block with the number, or the latest block all
+/// of them have finalized. The endpoints have to agree on its hash, otherwise they are on
+/// different chains or forks. Their best blocks are not used, endpoints that are in sync can still
+/// be on different forks above the finalized head.
+pub async fn pin_block(
+    histories: &[(&str, &dyn BlockHistory)],
+    number: Option<u32>,
+) -> Result<ResolvedBlock, Error> {
+    let number = match number {
+        Some(number) => number,
+        None => {
+            let finalized = join_all(
+                histories
+                    .iter()
+                    .map(|(_, history)| history.finalized_block()),
+            )
+            .await;
+            finalized
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .min()
+                .ok_or(Error::BlockNotFound)?
+        }
+    };
+    let hashes = join_all(
+        histories
+            .iter()
+            .map(|(_, history)| history.block_hash(number)),
+    )
+    .await
+    .into_iter()
+    .map(|hash| hash?.ok_or(Error::BlockNotFound))
+    .collect::<Result<Vec<_>, _>>()?;
+    let endpoints: Vec<&str> = histories.iter().map(|(endpoint, _)| *endpoint).collect();
+    if let Some(divergent) = divergent(&endpoints, &hashes) {
+        return Err(Error::EndpointDisagreement {
+            storage: "block hash",
+            endpoints: divergent,
+        });
+    }
+    history::block_at(histories[0].1, number).await
+}
+
+// the endpoints whose answer is not the one most of them gave, `None` if they all agree.
+// Ties go to the answer of the first endpoint
+fn divergent<T: PartialEq>(endpoints: &[&str], answers: &[T]) -> Option<Vec<String>> {
+    let agreeing = |answer: &T| answers.iter().filter(|other| *other == answer).count();
+    let majority = answers.iter().rev().max_by_key(|answer| agreeing(answer))?;
+    let divergent: Vec<String> = endpoints
+        .iter()
+        .zip(answers)
+        .filter(|(_, answer)| *answer != majority)
+        .map(|(endpoint, _)| endpoint.to_string())
+        .collect();
+    (!divergent.is_empty()).then_some(divergent)
+}
+
+/// Number and duration of the queries sent to an endpoint of a quorum
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndpointTimings {
+    pub endpoint: String,
+    pub queries: usize,
+    pub total: Duration,
+    pub slowest: Duration,
+}
+
+/// Reads the DID documents and attestations from all endpoints at the same time and only accepts
+/// an answer that all endpoints that answered agree on, compared by their SCALE encoding. At least
+/// `quorum` endpoints have to answer. Lookups that do not ask for a block read the pinned block,
+/// the other lookups only go to the first endpoint.
+pub struct QuorumBackend<'a> {
+    backends: Vec<(String, &'a dyn ChainBackend)>,
+    quorum: usize,
+    block: H256,
+    timings: Mutex<Vec<EndpointTimings>>,
+}
+
+impl<'a> QuorumBackend<'a> {
+    pub fn new(backends: Vec<(&str, &'a dyn ChainBackend)>, quorum: usize, block: H256) -> Self {
+        let timings = backends
+            .iter()
+            .map(|(endpoint, _)| EndpointTimings {
+                endpoint: endpoint.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        QuorumBackend {
+            backends: backends
+                .into_iter()
+                .map(|(endpoint, backend)| (endpoint.to_string(), backend))
+                .collect(),
+            quorum,
+            block,
+            timings: Mutex::new(timings),
+        }
+    }
+
+    /// The queries of each endpoint so far, in the order of the endpoints
+    pub fn timings(&self) -> Vec<EndpointTimings> {
+        self.timings
+            .lock()
+            .expect("timings are not poisoned")
+            .clone()
+    }
+
+    async fn agree<'f, T: Encode>(
+        &'f self,
+        storage: &'static str,
+        lookup: impl Fn(&'f dyn ChainBackend) -> BoxFuture<'f, Result<Option<T>, Error>>,
+    ) -> Result<Option<T>, Error> {
+        let answers = join_all(self.backends.iter().map(|(_, backend)| {
+            let start = Instant::now();
+            let query = lookup(*backend);
+            async move { (query.await, start.elapsed()) }
+        }))
+        .await;
+
+        let mut timings = self.timings.lock().expect("timings are not poisoned");
+        let mut answered = Vec::new();
+        let mut first_error = None;
+        for (((endpoint, _), (res, elapsed)), timings) in
+            self.backends.iter().zip(answers).zip(timings.iter_mut())
+        {
+            timings.queries += 1;
+            timings.total += elapsed;
+            timings.slowest = timings.slowest.max(elapsed);
+            match res {
+                Ok(answer) => answered.push((endpoint.as_str(), answer)),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        drop(timings);
+
+        let endpoints: Vec<&str> = answered.iter().map(|(endpoint, _)| *endpoint).collect();
+        let encoded: Vec<Vec<u8>> = answered.iter().map(|(_, answer)| answer.encode()).collect();
+        if let Some(divergent) = divergent(&endpoints, &encoded) {
+            return Err(Error::EndpointDisagreement {
+                storage,
+                endpoints: divergent,
+            });
+        }
+        match (answered.len() >= self.quorum, first_error) {
+            (false, Some(err)) => Err(err),
+            _ => Ok(answered.into_iter().next().and_then(|(_, answer)| answer)),
+        }
+    }
+
+    fn first(&self) -> &'a dyn ChainBackend {
+        self.backends[0].1
+    }
+}
+
+#[async_trait]
+impl ChainBackend for QuorumBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        let at = at.or(Some(self.block));
+        self.agree("did.did", |backend| backend.did(did, at)).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        let at = at.or(Some(self.block));
+        self.agree("attestation.attestations", |backend| {
+            backend.attestation(root_hash, at)
+        })
+        .await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        let at = at.or(Some(self.block));
+        self.first().web3_name_owner(name, at).await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        let at = at.or(Some(self.block));
+        self.first().web3_name(owner, at).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        let at = at.or(Some(self.block));
+        self.first().ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        let at = at.or(Some(self.block));
+        self.first().service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.first().token().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, mock::MockBackend, utils::hex_decode_h256};
+
+    const BLOCK: H256 = H256([7; 32]);
+
+    // the fixture state at the pinned block
+    fn endpoint(revoked: bool) -> MockBackend {
+        let mut backend = MockBackend::default();
+        backend.insert_block(BLOCK, fixtures::state(revoked));
+        backend
+    }
+
+    #[tokio::test]
+    async fn test_quorum() {
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        let (a, b, c) = (endpoint(false), endpoint(false), endpoint(true));
+
+        let quorum = QuorumBackend::new(vec![("wss://a", &a), ("wss://b", &b)], 2, BLOCK);
+        let attestation = quorum.attestation(&root_hash, None).await.unwrap();
+        assert!(!attestation.unwrap().revoked);
+        let cred = fixtures::credential();
+        let attester = fixtures::attester_did();
+        cred.verify(&quorum, &[attester.as_str()], None)
+            .await
+            .unwrap();
+        let timings = quorum.timings();
+        assert_eq!(timings.len(), 2);
+        assert_eq!(timings[0].endpoint, "wss://a");
+        assert_eq!(timings[0].queries, timings[1].queries);
+        assert!(timings[0].queries >= 3);
+
+        // the endpoint that sees the attestation revoked is named
+        let quorum = QuorumBackend::new(
+            vec![("wss://a", &a), ("wss://c", &c), ("wss://b", &b)],
+            2,
+            BLOCK,
+        );
+        let res = quorum.attestation(&root_hash, None).await;
+        assert!(
+            matches!(&res, Err(Error::EndpointDisagreement { endpoints, .. }) if endpoints == &["wss://c"]),
+            "{:?}",
+            res
+        );
+        // the DIDs are the same everywhere
+        let owner = crate::utils::get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        assert!(quorum.did(&owner, None).await.unwrap().is_some());
+
+        // an endpoint without the block does not answer, one answer is not a quorum of two
+        let empty = MockBackend::default();
+        let quorum = QuorumBackend::new(vec![("wss://a", &a), ("wss://x", &empty)], 2, BLOCK);
+        let res = quorum.attestation(&root_hash, None).await;
+        assert!(matches!(res, Err(Error::BlockNotFound)), "{:?}", res);
+        let quorum = QuorumBackend::new(vec![("wss://a", &a), ("wss://x", &empty)], 1, BLOCK);
+        assert!(quorum.attestation(&root_hash, None).await.is_ok());
+    }
+
+    struct Chain {
+        best: u32,
+        finalized: u32,
+        // the hash of a block is its number, plus the fork for blocks after 10
+        fork: u8,
+    }
+
+    #[async_trait]
+    impl BlockHistory for Chain {
+        async fn best_block(&self) -> Result<u32, Error> {
+            Ok(self.best)
+        }
+
+        async fn finalized_block(&self) -> Result<u32, Error> {
+            Ok(self.finalized)
+        }
+
+        async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+            let fork = if number > 10 { self.fork } else { 0 };
+            Ok((number <= self.best)
+                .then(|| H256::from_low_u64_be(number as u64 + fork as u64 * 1000)))
+        }
+
+        async fn block_timestamp(&self, _block: H256) -> Result<u64, Error> {
+            Ok(1_700_000_000_000)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pin_block() {
+        let chain = |best, finalized, fork| Chain {
+            best,
+            finalized,
+            fork,
+        };
+        let (a, b) = (chain(8, 6, 0), chain(9, 7, 0));
+        let block = pin_block(&[("wss://a", &a), ("wss://b", &b)], None)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 6);
+        let block = pin_block(&[("wss://a", &a), ("wss://b", &b)], Some(5))
+            .await
+            .unwrap();
+        assert_eq!(block.hash, H256::from_low_u64_be(5));
+
+        // the best blocks are on different forks, the finalized ones are not
+        let (c, d) = (chain(12, 10, 0), chain(13, 10, 1));
+        let block = pin_block(&[("wss://c", &c), ("wss://d", &d)], None)
+            .await
+            .unwrap();
+        assert_eq!(block.hash, H256::from_low_u64_be(10));
+
+        let (c, d) = (chain(12, 12, 0), chain(12, 12, 1));
+        let res = pin_block(&[("wss://c", &c), ("wss://d", &d)], None).await;
+        assert!(
+            matches!(&res, Err(Error::EndpointDisagreement { endpoints, .. }) if endpoints == &["wss://d"]),
+            "{:?}",
+            res
+        );
+    }
+}
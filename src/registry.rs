@@ -0,0 +1,309 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    backend::ChainBackend,
+    did::service_endpoints,
+    errors::Error,
+    utils::{cache_dir, get_did_account_id, normalize_issuer},
+};
+
+/// Type of the service endpoints of a registry DID whose URLs are the DIDs or KILT addresses of
+/// the trusted issuers
+pub const REGISTRY_SERVICE_TYPE: &str = "KiltTrustedIssuers";
+
+/// The trusted issuers a registry DID lists
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuerRegistry {
+    pub did: String,
+    /// DIDs of the trusted issuers in the order they are listed
+    pub members: Vec<String>,
+    /// Unix time in seconds the members were read from chain
+    pub fetched_at: u64,
+    /// The registry could not be read and the members are the cached ones
+    #[serde(skip)]
+    pub cached: bool,
+}
+
+impl IssuerRegistry {
+    /// The time the members were read from chain in RFC 3339
+    pub fn fetched_at(&self) -> String {
+        DateTime::<Utc>::from_timestamp(self.fetched_at as i64, 0)
+            .unwrap_or_default()
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    // age of the members at `now`, members from the future are fresh
+    fn age(&self, now: SystemTime) -> Duration {
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(self.fetched_at);
+        now.duration_since(fetched_at).unwrap_or_default()
+    }
+
+    /// Where the members are read from and when, i.e. for the report
+    pub fn describe(&self) -> String {
+        format!(
+            "Trusted issuers of registry {} as of {}{}",
+            self.did,
+            self.fetched_at(),
+            if self.cached {
+                " (cached, the registry could not be read)"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// The cache file of a registry, in the cache directory, see `utils::cache_dir`
+pub fn default_cache_file(did: &str) -> PathBuf {
+    let name = did.strip_prefix("did:kilt:").unwrap_or(did);
+    cache_dir().join(format!("registry-{}.json", name))
+}
+
+/// Read the members of a registry DID from its `KiltTrustedIssuers` service endpoints.
+/// Entries that are not a KILT DID or address are skipped with a warning, a registry that does not
+/// exist or lists no valid entry is an error.
+pub async fn resolve(
+    backend: &dyn ChainBackend,
+    did: &str,
+    now: SystemTime,
+) -> Result<IssuerRegistry, Error> {
+    let did = normalize_issuer(did)?;
+    let account = get_did_account_id(&did)?;
+    if backend.did(&account, None).await?.is_none() {
+        return Err(Error::IssuerRegistry(format!("{} does not exist", did)));
+    }
+    let mut members = Vec::new();
+    for service in service_endpoints(backend, &account, None).await? {
+        if !service.types.iter().any(|t| t == REGISTRY_SERVICE_TYPE) {
+            continue;
+        }
+        for url in service.urls.iter() {
+            match normalize_issuer(url) {
+                Ok(member) if !members.contains(&member) => members.push(member),
+                Ok(_) => {}
+                Err(err) => eprintln!(
+                    "Warning: skipping entry of registry {} in #{}: {}",
+                    did, service.id, err
+                ),
+            }
+        }
+    }
+    if members.is_empty() {
+        return Err(Error::IssuerRegistry(format!(
+            "{} lists no trusted issuers in a {} service",
+            did, REGISTRY_SERVICE_TYPE
+        )));
+    }
+    Ok(IssuerRegistry {
+        did,
+        members,
+        fetched_at: now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        cached: false,
+    })
+}
+
+/// Write the members to the cache file, replacing the file only once it is written completely
+pub fn write_cache(registry: &IssuerRegistry, file: &Path) -> Result<(), Error> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let partial = file.with_extension("json.partial");
+    std::fs::write(&partial, serde_json::to_vec_pretty(registry)?)?;
+    std::fs::rename(&partial, file)?;
+    Ok(())
+}
+
+pub fn read_cache(file: &Path) -> Result<IssuerRegistry, Error> {
+    Ok(serde_json::from_slice(&std::fs::read(file)?)?)
+}
+
+// only a registry that could not be read may be replaced by the cache, one that was read and is
+// gone or empty no longer trusts anyone
+fn unreachable(err: &Error) -> bool {
+    matches!(err, Error::ConnectionError(_) | Error::Timeout)
+}
+
+/// The members of a registry from the result of `resolve`, cached for later runs.
+/// If the registry could not be read the cached members of the same registry are used, as long as
+/// they are not older than `max_age` at `now`.
+pub fn resolve_or_cached(
+    did: &str,
+    resolved: Result<IssuerRegistry, Error>,
+    cache: &Path,
+    max_age: Duration,
+    now: SystemTime,
+) -> Result<IssuerRegistry, Error> {
+    let err = match resolved {
+        Ok(registry) => {
+            if let Err(err) = write_cache(&registry, cache) {
+                eprintln!(
+                    "Warning: could not cache registry {} in {}: {}",
+                    registry.did,
+                    cache.display(),
+                    err
+                );
+            }
+            return Ok(registry);
+        }
+        Err(err) if unreachable(&err) => err,
+        Err(err) => return Err(err),
+    };
+    let did = normalize_issuer(did)?;
+    let mut registry = match read_cache(cache) {
+        Ok(registry) if registry.did == did => registry,
+        Ok(_) | Err(_) => {
+            return Err(Error::IssuerRegistry(format!(
+                "{} could not be read and is not cached: {}",
+                did, err
+            )))
+        }
+    };
+    if registry.age(now) > max_age {
+        return Err(Error::IssuerRegistry(format!(
+            "{} could not be read and the cached members of {} are older than {}: {}",
+            did,
+            registry.fetched_at(),
+            humantime::format_duration(max_age),
+            err
+        )));
+    }
+    eprintln!(
+        "Warning: registry {} could not be read, using the members cached at {}: {}",
+        did,
+        registry.fetched_at(),
+        err
+    );
+    registry.cached = true;
+    Ok(registry)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures,
+        kilt::runtime_types::{
+            did::service_endpoints::DidEndpoint, frame_support::storage::bounded_vec::BoundedVec,
+        },
+        mock::MockBackend,
+    };
+
+    fn endpoint(id: &str, service_type: &str, urls: &[&str]) -> DidEndpoint {
+        DidEndpoint {
+            id: BoundedVec(id.as_bytes().to_vec()),
+            service_types: BoundedVec(vec![BoundedVec(service_type.as_bytes().to_vec())]),
+            urls: BoundedVec(
+                urls.iter()
+                    .map(|url| BoundedVec(url.as_bytes().to_vec()))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let registry = fixtures::owner_key().did();
+        let attester = fixtures::attester_did();
+        let address = attester.strip_prefix("did:kilt:").unwrap();
+        let other = "did:kilt:4pnfkRn5UurBJTW92d9TaVLR2CqJdY4z5HPjrEbpGyBykare";
+        let mut backend = fixtures::backend();
+        let account = get_did_account_id(&registry).unwrap();
+        backend.latest_mut().insert_service_endpoint(
+            account.clone(),
+            &endpoint(
+                "members",
+                REGISTRY_SERVICE_TYPE,
+                &[address, "not a did", other],
+            ),
+        );
+        backend.latest_mut().insert_service_endpoint(
+            account,
+            &endpoint(
+                "messaging",
+                "KiltMessagingService",
+                &["https://example.com"],
+            ),
+        );
+
+        let resolved = resolve(&backend, &registry, now).await.unwrap();
+        assert_eq!(resolved.did, registry);
+        assert_eq!(resolved.members, vec![attester.clone(), other.to_string()]);
+        assert_eq!(resolved.fetched_at(), "2023-11-14T22:13:20Z");
+        assert!(!resolved.cached);
+
+        // a registry without members or DID is not trusted
+        let res = resolve(&backend, &attester, now).await;
+        assert!(
+            matches!(&res, Err(Error::IssuerRegistry(msg)) if msg.contains("lists no trusted issuers")),
+            "{:?}",
+            res
+        );
+        let res = resolve(&MockBackend::default(), &registry, now).await;
+        assert!(
+            matches!(&res, Err(Error::IssuerRegistry(msg)) if msg.ends_with("does not exist")),
+            "{:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn test_resolve_or_cached() {
+        let dir = std::env::temp_dir().join(format!("kilt-verify-registry-{}", std::process::id()));
+        let cache = dir.join("nested").join("registry.json");
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let did = fixtures::owner_key().did();
+        let registry = IssuerRegistry {
+            did: did.clone(),
+            members: vec![fixtures::attester_did()],
+            fetched_at: 1_700_000_000,
+            cached: false,
+        };
+        let unreachable = || Err(Error::Timeout);
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        // nothing cached yet
+        let res = resolve_or_cached(&did, unreachable(), &cache, day, now);
+        assert!(
+            matches!(&res, Err(Error::IssuerRegistry(msg)) if msg.contains("is not cached")),
+            "{:?}",
+            res
+        );
+
+        let res = resolve_or_cached(&did, Ok(registry.clone()), &cache, day, now);
+        assert_eq!(res.unwrap(), registry);
+        assert_eq!(read_cache(&cache).unwrap(), registry);
+
+        // the cache is used while it is fresh enough
+        let later = now + Duration::from_secs(60 * 60);
+        let cached = resolve_or_cached(&did, unreachable(), &cache, day, later).unwrap();
+        assert!(cached.cached);
+        assert_eq!(cached.members, registry.members);
+        assert!(cached
+            .describe()
+            .ends_with("as of 2023-11-14T22:13:20Z (cached, the registry could not be read)"));
+        let res = resolve_or_cached(&did, unreachable(), &cache, Duration::from_secs(60), later);
+        assert!(
+            matches!(&res, Err(Error::IssuerRegistry(msg)) if msg.contains("are older than 1m")),
+            "{:?}",
+            res
+        );
+
+        // the cache of another registry is not used
+        let other = fixtures::attester_did();
+        let res = resolve_or_cached(&other, unreachable(), &cache, day, later);
+        assert!(matches!(res, Err(Error::IssuerRegistry(_))), "{:?}", res);
+
+        // a registry that was read and is empty does not fall back
+        let empty = Err(Error::IssuerRegistry("empty".to_string()));
+        let res = resolve_or_cached(&did, empty, &cache, day, later);
+        assert!(matches!(&res, Err(Error::IssuerRegistry(msg)) if msg == "empty"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use clap::{Args, Subcommand};
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    backend::ChainBackend,
+    credential::verifies,
+    errors::Error,
+    utils::{
+        get_did_account_id, get_did_key_uri, hex_decode, hex_encode, read_seed, DidKeyPair, KeyType,
+    },
+};
+
+/// Field of a signed report that holds the signature
+pub const SIGNATURE_FIELD: &str = "verifierSignature";
+
+/// Version of the signature format: the JCS canonicalization of the report with the signature
+/// field but without its `signature` member is signed. Reports of other versions are rejected.
+pub const SIGNATURE_VERSION: u64 = 1;
+
+const CANONICALIZATION: &str = "jcs";
+
+/// Serialize json the way RFC 8785 (JCS) does: object members sorted by the UTF-16 code units of
+/// their names, no whitespace and numbers formatted like ECMAScript does
+pub fn canonicalize(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Null | Value::Bool(_) | Value::String(_) => out.push_str(&value.to_string()),
+        Value::Number(number) => out.push_str(&canonical_number(number)),
+        Value::Array(values) => {
+            out.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        Value::Object(members) => {
+            let mut names: Vec<&String> = members.keys().collect();
+            names.sort_by(|a, b| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (index, name) in names.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(name.clone()).to_string());
+                out.push(':');
+                write_canonical(&members[name], out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+// ECMAScript prints the shortest digits that round trip, without exponent between 1e-6 and 1e21
+fn canonical_number(number: &Number) -> String {
+    let float = match number.as_f64() {
+        Some(float) if !number.is_i64() && !number.is_u64() => float,
+        _ => return number.to_string(),
+    };
+    if float == 0.0 {
+        return "0".to_string();
+    }
+    if (1e-6..1e21).contains(&float.abs()) {
+        return float.to_string();
+    }
+    let exponential = format!("{:e}", float);
+    match exponential.split_once('e') {
+        Some((digits, exponent)) if !exponent.starts_with('-') => {
+            format!("{}e+{}", digits, exponent)
+        }
+        _ => exponential,
+    }
+}
+
+/// The key a verifier signs its reports with
+pub struct ReportSigner {
+    key: DidKeyPair,
+    key_uri: String,
+}
+
+impl ReportSigner {
+    /// Create the key from a seed, the key uri defaults to the key in its own DID.
+    /// A key uri that names another key is rejected, reports signed with it could not be verified.
+    pub fn new(seed: &str, key_type: KeyType, key_uri: Option<&str>) -> Result<Self, Error> {
+        let key = DidKeyPair::from_seed(seed, key_type)?;
+        let key_uri = match key_uri {
+            Some(key_uri) if get_did_key_uri(key_uri)?.0 != key.key_id() => {
+                return Err(Error::InvalidDidKey)
+            }
+            Some(key_uri) => key_uri.to_string(),
+            None => key.key_uri(),
+        };
+        Ok(ReportSigner { key, key_uri })
+    }
+
+    /// Sign a json report and embed the key uri and the signature in it
+    pub fn sign(&self, report: &mut Value) -> Result<(), Error> {
+        let members = report
+            .as_object_mut()
+            .ok_or_else(|| Error::InvalidReport("a report is a json object".to_string()))?;
+        let mut signature = Map::new();
+        signature.insert("keyUri".to_string(), self.key_uri.clone().into());
+        signature.insert("canonicalization".to_string(), CANONICALIZATION.into());
+        signature.insert("version".to_string(), SIGNATURE_VERSION.into());
+        members.insert(SIGNATURE_FIELD.to_string(), Value::Object(signature));
+        let msg = canonicalize(report);
+        let signed = hex_encode(self.key.sign(msg.as_bytes()));
+        report[SIGNATURE_FIELD]["signature"] = signed.into();
+        Ok(())
+    }
+}
+
+/// Check the signature of a report against the key of the verifier DID on chain.
+/// Returns the key uri the report was signed with.
+pub async fn verify(backend: &dyn ChainBackend, report: &Value) -> Result<String, Error> {
+    let invalid = |reason: &str| Error::InvalidReport(reason.to_string());
+    let mut unsigned = report.clone();
+    let signature = unsigned
+        .get_mut(SIGNATURE_FIELD)
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| invalid("the report is not signed"))?;
+    if signature.get("version").and_then(Value::as_u64) != Some(SIGNATURE_VERSION)
+        || signature.get("canonicalization").and_then(Value::as_str) != Some(CANONICALIZATION)
+    {
+        return Err(invalid("the signature has an unsupported version"));
+    }
+    let signed = signature
+        .remove("signature")
+        .and_then(|signed| signed.as_str().map(hex_decode))
+        .ok_or_else(|| invalid("the signature is missing"))??;
+    let signed: [u8; 64] = signed
+        .try_into()
+        .map_err(|_| invalid("the signature is not 64 bytes"))?;
+    let key_uri = signature
+        .get("keyUri")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("the key uri is missing"))?
+        .to_string();
+
+    let did_doc = backend
+        .did(&get_did_account_id(&key_uri)?, None)
+        .await?
+        .ok_or(Error::DidNotFound)?;
+    let key_id = get_did_key_uri(&key_uri)?;
+    let details = &did_doc
+        .public_keys
+        .0
+        .iter()
+        .find(|(key, _)| key.0 == key_id.0)
+        .ok_or(Error::InvalidDidKey)?
+        .1;
+    if verifies(&details.key, canonicalize(&unsigned).as_bytes(), &signed)? {
+        Ok(key_uri)
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
+
+/// Sign options of the report of a run
+#[derive(Args, Debug)]
+pub struct SignArgs {
+    /// Sign the json report of a batch with a DID key of the verifier. The key uri and the
+    /// signature over the JCS canonicalized report are embedded as `verifierSignature`
+    #[clap(long, value_parser, default_value_t = false)]
+    pub sign_report: bool,
+
+    /// Seed of the verifier's signing key (mnemonic, hex seed or dev seed like //Alice)
+    #[clap(long, value_parser, requires = "sign-report")]
+    seed: Option<String>,
+
+    /// File containing the seed of the verifier's signing key
+    #[clap(long, value_parser, requires = "sign-report")]
+    seed_file: Option<String>,
+
+    /// Type of the signing key
+    #[clap(long, value_enum, default_value_t = KeyType::Sr25519)]
+    key_type: KeyType,
+
+    /// Key uri of the signing key in the verifier DID, i.e. "did:kilt:4abc...#0x1234...",
+    /// defaults to the key in its own DID
+    #[clap(long, value_parser, requires = "sign-report")]
+    key_uri: Option<String>,
+}
+
+impl SignArgs {
+    /// The signer if reports are signed, the seed is zeroized once the key is derived
+    pub fn signer(&self) -> Result<Option<ReportSigner>, Error> {
+        if !self.sign_report {
+            return Ok(None);
+        }
+        let seed = read_seed(self.seed.as_deref(), self.seed_file.as_deref())?;
+        ReportSigner::new(&seed, self.key_type, self.key_uri.as_deref()).map(Some)
+    }
+}
+
+/// Work with signed reports
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    #[clap(subcommand)]
+    command: ReportCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ReportCommand {
+    /// Check the signature of a report against the verifier DID on chain
+    Verify(VerifyArgs),
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// The signed json report
+    #[clap(value_parser)]
+    file: String,
+}
+
+pub async fn run(backend: &dyn ChainBackend, args: &ReportArgs) -> Result<(), Error> {
+    match &args.command {
+        ReportCommand::Verify(verify_args) => {
+            let report: Value = serde_json::from_slice(&std::fs::read(&verify_args.file)?)?;
+            let key_uri = verify(backend, &report).await?;
+            println!("✅ Report is signed by {}", key_uri);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_canonicalize() {
+        let value = serde_json::json!({
+            "b": [1, 2.5, "x\n\u{e9}"],
+            "a": {"z": null, "\u{20ac}": true, "\r": false},
+            "n": [1e-7, 1e21, 123456789.0, 0.000001, -0.0, 1e300],
+        });
+        assert_eq!(
+            canonicalize(&value),
+            r#"{"a":{"\r":false,"z":null,"€":true},"b":[1,2.5,"x\né"],"n":[1e-7,1e+21,123456789,0.000001,0,1e+300]}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sign_report() {
+        let backend = fixtures::backend();
+        let seed = fixtures::OWNER_SEED;
+        // the owner of the fixtures has a DID on chain
+        let signer = ReportSigner::new(seed, KeyType::Sr25519, None).unwrap();
+        assert_eq!(signer.key_uri, fixtures::owner_key().key_uri());
+        let mut report = serde_json::json!({
+            "results": [{"source": "a.json", "valid": true}],
+            "summary": {"total": 1, "latencyMs": {"total": {"p50": 0.25}}},
+        });
+        signer.sign(&mut report).unwrap();
+        assert_eq!(report[SIGNATURE_FIELD]["version"], SIGNATURE_VERSION);
+
+        // the order of the members and the whitespace do not matter
+        let reordered: Value =
+            serde_json::from_str(&serde_json::to_string_pretty(&report).unwrap()).unwrap();
+        assert_eq!(verify(&backend, &reordered).await.unwrap(), signer.key_uri);
+
+        let mut tampered = report.clone();
+        tampered["results"][0]["valid"] = false.into();
+        let res = verify(&backend, &tampered).await;
+        assert!(matches!(res, Err(Error::InvalidSignature)), "{:?}", res);
+        let mut tampered = report.clone();
+        tampered[SIGNATURE_FIELD]["version"] = 2.into();
+        let res = verify(&backend, &tampered).await;
+        assert!(matches!(res, Err(Error::InvalidReport(_))), "{:?}", res);
+        let res = verify(&backend, &serde_json::json!({"results": []})).await;
+        assert!(matches!(res, Err(Error::InvalidReport(_))), "{:?}", res);
+
+        // a key that is not on chain
+        let other = ReportSigner::new("//Charlie", KeyType::Sr25519, None).unwrap();
+        let mut report = report.clone();
+        other.sign(&mut report).unwrap();
+        let res = verify(&backend, &report).await;
+        assert!(matches!(res, Err(Error::DidNotFound)), "{:?}", res);
+
+        // the key uri has to name the key of the seed
+        let res = ReportSigner::new(seed, KeyType::Sr25519, Some(&other.key_uri));
+        assert!(matches!(res, Err(Error::InvalidDidKey)));
+    }
+}
@@ -0,0 +1,706 @@
+use async_trait::async_trait;
+use base58::FromBase58;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeSet, sync::Mutex};
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::{AttestationHistory, ChainBackend},
+    errors::Error,
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::did_details::{
+            DidDetails, DidEncryptionKey, DidPublicKey, DidPublicKeyDetails, DidVerificationKey,
+        },
+        did::service_endpoints::DidEndpoint,
+        frame_support::storage::{
+            bounded_btree_map::BoundedBTreeMap, bounded_btree_set::BoundedBTreeSet,
+        },
+        kilt_support::deposit::Deposit,
+        sp_core as runtime_sp_core,
+    },
+    utils::{get_did_account_id, get_key_id, hex_decode_h256, kilt_address, Token},
+    webhook::{Webhook, TIMEOUT},
+};
+
+/// How results of the fallback are marked, they are only as trustworthy as the resolver
+pub const RESOLVED_VIA: &str = "resolved via HTTP resolver";
+
+/// Reads DID documents from a universal resolver when the chain cannot be queried, i.e. a failed
+/// connection or a query that failed on every attempt. Only the latest state can be resolved, so
+/// lookups at a block are never sent to the resolver. Everything but DID documents, attestations
+/// in particular, is only read from the chain.
+pub struct ResolverBackend<'a> {
+    inner: &'a dyn ChainBackend,
+    url: String,
+    resolver: Webhook,
+    resolved: Mutex<BTreeSet<AccountId32>>,
+}
+
+impl<'a> ResolverBackend<'a> {
+    /// A resolver at a plain `http://` URL the DID is appended to, i.e.
+    /// `http://localhost:8080/1.0/identifiers/`
+    pub fn new(inner: &'a dyn ChainBackend, url: &str) -> Result<Self, Error> {
+        let resolver = Webhook::parse(url).map_err(|err| match err {
+            Error::Webhook(msg) => Error::DidResolver(msg),
+            err => err,
+        })?;
+        Ok(ResolverBackend {
+            inner,
+            url: url.to_string(),
+            resolver,
+            resolved: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The DID document of the DID was resolved via the resolver instead of read from the chain
+    pub fn resolved(&self, did: &str) -> bool {
+        get_did_account_id(did).is_ok_and(|account| {
+            self.resolved
+                .lock()
+                .expect("resolved DIDs are not poisoned")
+                .contains(&account)
+        })
+    }
+
+    async fn resolve(&self, did: &AccountId32) -> Result<Option<DidDetails>, Error> {
+        let did_uri = format!("did:kilt:{}", kilt_address(did));
+        let body = tokio::time::timeout(TIMEOUT, self.get(&did_uri))
+            .await
+            .map_err(|_| {
+                Error::DidResolver(format!("{} did not answer in time", self.resolver.host))
+            })??;
+        let body = match body {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let document: Value = serde_json::from_slice(&body)
+            .map_err(|err| Error::DidResolver(format!("the answer is not json: {}", err)))?;
+        // a resolution result has the document next to its metadata, a DID document stands alone
+        let document = match document.get("didDocument") {
+            Some(Value::Null) => return Ok(None),
+            Some(document) => document.clone(),
+            None => document,
+        };
+        let document: DidDocument = serde_json::from_value(document)
+            .map_err(|err| Error::DidResolver(format!("not a DID document: {}", err)))?;
+        document.to_details(did).map(Some)
+    }
+
+    // the body of a 200 answer, `None` if the resolver does not know the DID
+    async fn get(&self, did: &str) -> Result<Option<Vec<u8>>, Error> {
+        let separator = if self.resolver.path.ends_with('/') {
+            ""
+        } else {
+            "/"
+        };
+        let request = format!(
+            "GET {}{}{} HTTP/1.0\r\nHost: {}\r\nAccept: application/did+ld+json, application/json\r\n\r\n",
+            self.resolver.path, separator, did, self.resolver.host
+        );
+        let (status, body) = self
+            .resolver
+            .exchange(request.as_bytes())
+            .await
+            .map_err(|err| {
+                Error::DidResolver(format!("cannot reach {}: {}", self.resolver.host, err))
+            })?;
+        match status.split_whitespace().nth(1) {
+            Some("200") => Ok(Some(body)),
+            Some("404") | Some("410") => Ok(None),
+            _ => Err(Error::DidResolver(format!(
+                "{} answered {:?}",
+                self.resolver.host, status
+            ))),
+        }
+    }
+}
+
+// the chain could not be asked, which is the only reason to trust the resolver instead
+fn is_transport(err: &Error) -> bool {
+    matches!(err, Error::ConnectionError(_) | Error::QueryFailed { .. })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidDocument {
+    id: String,
+    #[serde(default)]
+    verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    authentication: Vec<String>,
+    #[serde(default)]
+    assertion_method: Vec<String>,
+    #[serde(default)]
+    capability_delegation: Vec<String>,
+    #[serde(default)]
+    key_agreement: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMethod {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    public_key_base58: Option<String>,
+    #[serde(default)]
+    public_key_multibase: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyKind {
+    Sr25519,
+    Ed25519,
+    Ecdsa,
+    X25519,
+}
+
+// the verification method types of each kind of key, the multicodec prefix of its multikeys
+// and the length of the key
+const KINDS: [(KeyKind, &[&str], [u8; 2], usize); 4] = [
+    (
+        KeyKind::Sr25519,
+        &["Sr25519VerificationKey2018", "Sr25519VerificationKey2020"],
+        [0xef, 0x01],
+        32,
+    ),
+    (
+        KeyKind::Ed25519,
+        &["Ed25519VerificationKey2018", "Ed25519VerificationKey2020"],
+        [0xed, 0x01],
+        32,
+    ),
+    (
+        KeyKind::Ecdsa,
+        &["EcdsaSecp256k1VerificationKey2019"],
+        [0xe7, 0x01],
+        33,
+    ),
+    (
+        KeyKind::X25519,
+        &["X25519KeyAgreementKey2019", "X25519KeyAgreementKey2020"],
+        [0xec, 0x01],
+        32,
+    ),
+];
+
+fn invalid(msg: String) -> Error {
+    Error::DidResolver(msg)
+}
+
+// the key id of a key uri or of a relative reference, i.e. "did:kilt:4abc#0x12..." or "#0x12..."
+fn fragment(did: &str, reference: &str) -> Result<H256, Error> {
+    let (owner, fragment) = reference
+        .split_once('#')
+        .ok_or_else(|| invalid(format!("{} is not a key uri", reference)))?;
+    if !owner.is_empty() && owner != did {
+        return Err(invalid(format!("{} is not a key of {}", reference, did)));
+    }
+    hex_decode_h256(fragment)
+        .map_err(|_| invalid(format!("{} does not end in a key id", reference)))
+}
+
+fn fixed<const N: usize>(id: &str, key: Vec<u8>) -> Result<[u8; N], Error> {
+    let len = key.len();
+    key.try_into()
+        .map_err(|_| invalid(format!("{} has {} bytes instead of {}", id, len, N)))
+}
+
+impl VerificationMethod {
+    fn public_key(&self) -> Result<DidPublicKey, Error> {
+        let base58 = match (&self.public_key_base58, &self.public_key_multibase) {
+            (Some(key), _) => key.as_str(),
+            (None, Some(key)) => key
+                .strip_prefix('z')
+                .ok_or_else(|| invalid(format!("{} is not multibase base58btc", self.id)))?,
+            (None, None) => {
+                return Err(invalid(format!(
+                    "{} has no publicKeyBase58 or publicKeyMultibase",
+                    self.id
+                )))
+            }
+        };
+        let mut key = base58
+            .from_base58()
+            .map_err(|err| invalid(format!("{} is not base58: {:?}", self.id, err)))?;
+        let named = KINDS
+            .iter()
+            .find(|(_, types, _, _)| types.contains(&self.kind.as_str()));
+        // multikeys start with the multicodec of the key type
+        let prefixed = KINDS.iter().find(|(_, _, prefix, len)| {
+            self.public_key_base58.is_none()
+                && key.len() == prefix.len() + len
+                && key.starts_with(prefix)
+        });
+        let kind = match (named, prefixed) {
+            (Some(named), Some(prefixed)) if named.0 != prefixed.0 => named.0,
+            (_, Some(prefixed)) if named.is_some() || self.kind == "Multikey" => {
+                key.drain(..prefixed.2.len());
+                prefixed.0
+            }
+            (Some(named), _) => named.0,
+            _ => {
+                return Err(invalid(format!(
+                    "{} has the unknown type {}",
+                    self.id, self.kind
+                )))
+            }
+        };
+        Ok(match kind {
+            KeyKind::Sr25519 => DidPublicKey::PublicVerificationKey(DidVerificationKey::Sr25519(
+                runtime_sp_core::sr25519::Public(fixed(&self.id, key)?),
+            )),
+            KeyKind::Ed25519 => DidPublicKey::PublicVerificationKey(DidVerificationKey::Ed25519(
+                runtime_sp_core::ed25519::Public(fixed(&self.id, key)?),
+            )),
+            KeyKind::Ecdsa => DidPublicKey::PublicVerificationKey(DidVerificationKey::Ecdsa(
+                runtime_sp_core::ecdsa::Public(fixed(&self.id, key)?),
+            )),
+            KeyKind::X25519 => {
+                DidPublicKey::PublicEncryptionKey(DidEncryptionKey::X25519(fixed(&self.id, key)?))
+            }
+        })
+    }
+}
+
+impl DidDocument {
+    /// The document as the chain would have it. The key ids are checked against the keys, the
+    /// block numbers and the deposit are not known and left at zero.
+    fn to_details(&self, did: &AccountId32) -> Result<DidDetails, Error> {
+        if get_did_account_id(&self.id).ok().as_ref() != Some(did) {
+            return Err(invalid(format!(
+                "asked for did:kilt:{}, the document is of {}",
+                kilt_address(did),
+                self.id
+            )));
+        }
+        let mut public_keys = Vec::new();
+        for method in self.verification_method.iter() {
+            let key = method.public_key()?;
+            let key_id = H256(get_key_id(&key));
+            if fragment(&self.id, &method.id)? != key_id {
+                return Err(invalid(format!("{} is not the id of its key", method.id)));
+            }
+            public_keys.push((
+                key_id,
+                DidPublicKeyDetails {
+                    key,
+                    block_number: 0,
+                },
+            ));
+        }
+        let key = |reference: &String| {
+            let key_id = fragment(&self.id, reference)?;
+            if public_keys.iter().any(|(id, _)| *id == key_id) {
+                Ok(key_id)
+            } else {
+                Err(invalid(format!(
+                    "{} is not a verification method",
+                    reference
+                )))
+            }
+        };
+        let authentication_key = match self.authentication.as_slice() {
+            [authentication] => key(authentication)?,
+            keys => {
+                return Err(invalid(format!(
+                    "{} authentication keys instead of one",
+                    keys.len()
+                )))
+            }
+        };
+        let attestation_key = self.assertion_method.first().map(key).transpose()?;
+        let delegation_key = self.capability_delegation.first().map(key).transpose()?;
+        let key_agreement_keys = self
+            .key_agreement
+            .iter()
+            .map(key)
+            .collect::<Result<_, _>>()?;
+        Ok(DidDetails {
+            authentication_key,
+            key_agreement_keys: BoundedBTreeSet(key_agreement_keys),
+            delegation_key,
+            attestation_key,
+            public_keys: BoundedBTreeMap(public_keys),
+            last_tx_counter: 0,
+            deposit: Deposit {
+                owner: did.clone(),
+                amount: 0,
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl ChainBackend for ResolverBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        match self.inner.did(did, at).await {
+            Err(err) if at.is_none() && is_transport(&err) => {
+                let details = self.resolve(did).await.map_err(|resolver_err| {
+                    Error::DidResolver(format!(
+                        "{}, after the chain query failed: {}",
+                        resolver_err, err
+                    ))
+                })?;
+                if details.is_some() {
+                    let first = self
+                        .resolved
+                        .lock()
+                        .expect("resolved DIDs are not poisoned")
+                        .insert(did.clone());
+                    if first {
+                        eprintln!(
+                            "Warning: the DID document of did:kilt:{} was {} {} because the chain query failed: {}",
+                            kilt_address(did),
+                            RESOLVED_VIA,
+                            self.url,
+                            err
+                        );
+                    }
+                }
+                Ok(details)
+            }
+            res => res,
+        }
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        self.inner.attestation(root_hash, at).await
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.inner.attestation_history(root_hash).await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.web3_name_owner(name, at).await
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        self.inner.web3_name(owner, at).await
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.inner.service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.inner.token().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, mock::MockBackend, utils::hex_encode};
+    use base58::ToBase58;
+    use codec::Encode;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    // a chain whose DID documents, and optionally attestations, cannot be queried
+    struct Unreachable {
+        chain: MockBackend,
+        attestations: bool,
+    }
+
+    fn unreachable(storage: &'static str) -> Error {
+        Error::QueryFailed {
+            storage,
+            attempts: Vec::new(),
+        }
+    }
+
+    #[async_trait]
+    impl ChainBackend for Unreachable {
+        async fn did(&self, _: &AccountId32, _: Option<H256>) -> Result<Option<DidDetails>, Error> {
+            Err(unreachable("did.did"))
+        }
+
+        async fn attestation(
+            &self,
+            root_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            if self.attestations {
+                return Err(unreachable("attestation.attestations"));
+            }
+            self.chain.attestation(root_hash, at).await
+        }
+
+        async fn web3_name_owner(
+            &self,
+            name: &str,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.chain.web3_name_owner(name, at).await
+        }
+
+        async fn web3_name(
+            &self,
+            owner: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            self.chain.web3_name(owner, at).await
+        }
+
+        async fn ctype_creator(
+            &self,
+            ctype_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            self.chain.ctype_creator(ctype_hash, at).await
+        }
+
+        async fn service_endpoints(
+            &self,
+            did: &AccountId32,
+            at: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            self.chain.service_endpoints(did, at).await
+        }
+    }
+
+    // the DID document of the owner fixture like a resolver exports it
+    fn owner_document() -> Value {
+        let key = fixtures::owner_key();
+        let public = match key.public_key() {
+            DidPublicKey::PublicVerificationKey(DidVerificationKey::Sr25519(public)) => public.0,
+            key => panic!("{:?}", key),
+        };
+        let did = key.did();
+        json!({
+            "id": did,
+            "verificationMethod": [{
+                "id": key.key_uri(),
+                "controller": did,
+                "type": "Sr25519VerificationKey2018",
+                "publicKeyBase58": public.to_base58(),
+            }],
+            "authentication": [key.key_uri()],
+            "assertionMethod": [format!("#{}", hex_encode(key.key_id()))],
+        })
+    }
+
+    fn document(value: Value) -> DidDocument {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_to_details() {
+        let key = fixtures::owner_key();
+        let owner = get_did_account_id(&key.did()).unwrap();
+        let details = document(owner_document()).to_details(&owner).unwrap();
+        let expected = fixtures::did_details(&key);
+        assert_eq!(details.authentication_key, expected.authentication_key);
+        assert_eq!(details.attestation_key, expected.attestation_key);
+        assert_eq!(
+            details.public_keys.0[0].1.key.encode(),
+            expected.public_keys.0[0].1.key.encode()
+        );
+
+        // the same key as multikey
+        let mut multikey = owner_document();
+        let public = multikey["verificationMethod"][0]["publicKeyBase58"]
+            .as_str()
+            .unwrap()
+            .from_base58()
+            .unwrap();
+        multikey["verificationMethod"][0] = json!({
+            "id": key.key_uri(),
+            "type": "Multikey",
+            "publicKeyMultibase": format!("z{}", [&[0xef, 0x01], &public[..]].concat().to_base58()),
+        });
+        let details = document(multikey).to_details(&owner).unwrap();
+        assert_eq!(
+            details.public_keys.0[0].1.key.encode(),
+            expected.public_keys.0[0].1.key.encode()
+        );
+
+        let attester = fixtures::attester_key();
+        let attester_key_id = format!("{}#{}", key.did(), hex_encode(attester.key_id()));
+        let mut wrong_id = owner_document();
+        wrong_id["verificationMethod"][0]["id"] = json!(attester_key_id);
+        let mut wrong_did = owner_document();
+        wrong_did["id"] = json!(attester.did());
+        let mut other_key = owner_document();
+        other_key["authentication"] = json!([attester.key_uri()]);
+        let mut unknown = owner_document();
+        unknown["verificationMethod"][0]["type"] = json!("RsaVerificationKey2018");
+        for (document_json, error) in [
+            (
+                wrong_id,
+                format!("{} is not the id of its key", attester_key_id),
+            ),
+            (
+                wrong_did,
+                format!(
+                    "asked for {}, the document is of {}",
+                    key.did(),
+                    attester.did()
+                ),
+            ),
+            (
+                other_key,
+                format!("{} is not a key of {}", attester.key_uri(), key.did()),
+            ),
+            (
+                unknown,
+                format!(
+                    "{} has the unknown type RsaVerificationKey2018",
+                    key.key_uri()
+                ),
+            ),
+        ] {
+            let res = document(document_json).to_details(&owner);
+            assert!(
+                matches!(&res, Err(Error::DidResolver(msg)) if *msg == error),
+                "{}: {:?}",
+                error,
+                res.map(|_| ())
+            );
+        }
+    }
+
+    // a resolver that answers every request with the document of the owner as resolution result,
+    // or 404 for other DIDs. Returns the URL and the number of requests
+    async fn serve() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/1.0/identifiers", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let owner = fixtures::owner_key().did();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::Relaxed);
+                let mut request = vec![0; 1024];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).into_owned();
+                let path = format!("GET /1.0/identifiers/{} HTTP/1.0\r\n", owner);
+                let response = if request.starts_with(&path) {
+                    let body = json!({
+                        "didDocument": owner_document(),
+                        "didDocumentMetadata": {},
+                        "didResolutionMetadata": {"contentType": "application/did+ld+json"},
+                    })
+                    .to_string();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_resolver_backend() {
+        let (url, requests) = serve().await;
+        let cred = fixtures::credential();
+        let attester = fixtures::attester_did();
+        let owner = get_did_account_id(&cred.claim.owner).unwrap();
+        let chain = Unreachable {
+            chain: fixtures::backend(),
+            attestations: false,
+        };
+        let resolver = ResolverBackend::new(&chain, &url).unwrap();
+        assert!(!resolver.resolved(&cred.claim.owner));
+        let res = cred.verify(&resolver, &[&attester], None).await;
+        assert!(res.is_ok(), "{:?}", res);
+        assert!(resolver.resolved(&cred.claim.owner));
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        // a DID the resolver does not know does not exist
+        let other = get_did_account_id(&attester).unwrap();
+        assert!(resolver.did(&other, None).await.unwrap().is_none());
+        assert!(!resolver.resolved(&attester));
+
+        // lookups at a block are not resolved
+        let res = resolver.did(&owner, Some(H256::zero())).await;
+        assert!(matches!(res, Err(Error::QueryFailed { .. })));
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+
+        // attestations are never resolved
+        let chain = Unreachable {
+            chain: fixtures::backend(),
+            attestations: true,
+        };
+        let resolver = ResolverBackend::new(&chain, &url).unwrap();
+        let res = cred.verify(&resolver, &[&attester], None).await;
+        assert!(
+            matches!(
+                &res,
+                Err(Error::QueryFailed {
+                    storage: "attestation.attestations",
+                    ..
+                })
+            ),
+            "{:?}",
+            res
+        );
+
+        // a resolver that cannot be reached fails like the chain
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let gone = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let resolver = ResolverBackend::new(&chain, &gone).unwrap();
+        let err = resolver.did(&owner, None).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::DidResolver(msg) if msg.contains("after the chain query failed: Query of did.did failed")),
+            "{:?}",
+            err
+        );
+        assert!(err.is_infrastructure());
+        assert!(matches!(
+            ResolverBackend::new(&chain, "https://resolver.example.com"),
+            Err(Error::DidResolver(_))
+        ));
+    }
+}
@@ -0,0 +1,614 @@
+use clap::Args;
+use futures::stream::{self, StreamExt};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    io::{IsTerminal, Write},
+    path::Path,
+    sync::Mutex,
+};
+use subxt::sp_core::H256;
+
+use crate::{
+    backend::ChainBackend,
+    batch::{self, csv_field},
+    errors::Error,
+    history::{self, format_millis, BlockHistory},
+    kilt::KiltRuntimeApi,
+    utils::{hex_decode_h256, hex_encode, kilt_address, parse_credential},
+};
+
+/// Check the attestations of accepted credentials again and report the ones that changed since
+#[derive(Args, Debug)]
+pub struct RevocationReportArgs {
+    /// Porcelain output of earlier runs or any file with a root hash on each line.
+    /// INVALID lines are skipped, those credentials were never accepted
+    #[clap(
+        long,
+        value_parser,
+        required_unless_present = "from-dir",
+        conflicts_with = "from-dir"
+    )]
+    results: Option<String>,
+
+    /// Directory, newline delimited json file or archive of the accepted credentials
+    #[clap(long, value_parser)]
+    from_dir: Option<String>,
+
+    /// Number of attestations that are checked at the same time
+    #[clap(long, value_parser, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Format of the report
+    #[clap(long, value_enum, default_value_t = ReportFormat::Csv)]
+    format: ReportFormat,
+
+    /// File to write the report to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+
+    /// File the status of each attestation is appended to once it is checked. A run with the same
+    /// checkpoint only checks the attestations that are not in it, i.e. to continue a run that
+    /// was interrupted
+    #[clap(long, value_parser)]
+    checkpoint: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+/// A root hash of an accepted credential and where it was read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accepted {
+    pub source: String,
+    pub root_hash: H256,
+}
+
+/// The status of an attestation at the latest block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Valid,
+    Revoked,
+    /// Not on chain anymore, accepted credentials were attested once
+    Removed,
+    /// The attestation could not be looked up, it is checked again by the next run
+    Failed,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Valid => "valid",
+            Status::Revoked => "revoked",
+            Status::Removed => "removed",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// The status of the attestation of an accepted credential and since when it has it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusChange {
+    pub root_hash: String,
+    pub source: String,
+    pub status: Status,
+    /// First block with the status, `None` for valid attestations and changes that could not be dated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since_block: Option<u32>,
+    /// Time that block was produced at in RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    /// DID of the attester while the attestation is on chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attester: Option<String>,
+    /// Why the attestation could not be checked or the change not be dated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The root hashes of a file with one on each line, i.e. the `--porcelain` output of earlier runs.
+/// The first `0x` hash of a line is taken, lines of invalid credentials and lines without a hash
+/// are skipped.
+pub fn read_results(file: &str) -> Result<Vec<Accepted>, Error> {
+    let text = std::fs::read_to_string(file)?;
+    let mut accepted = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("INVALID") {
+            continue;
+        }
+        let root_hash = line
+            .split_whitespace()
+            .filter(|word| word.starts_with("0x"))
+            .find_map(|word| hex_decode_h256(word).ok());
+        match root_hash {
+            Some(root_hash) => accepted.push(Accepted {
+                source: format!("{}:{}", file, number + 1),
+                root_hash,
+            }),
+            None => eprintln!(
+                "Warning: skipping {}:{}, it has no root hash",
+                file,
+                number + 1
+            ),
+        }
+    }
+    Ok(accepted)
+}
+
+/// The root hashes of the credentials of a directory, newline delimited json file or archive.
+/// Credentials that cannot be parsed are skipped with a warning.
+pub fn read_credentials(path: &str) -> Result<Vec<Accepted>, Error> {
+    let mut accepted = Vec::new();
+    for input in batch::read_inputs(path, &Default::default(), &Default::default())? {
+        let root_hash =
+            parse_credential(&input.data).and_then(|cred| hex_decode_h256(&cred.root_hash));
+        match root_hash {
+            Ok(root_hash) => accepted.push(Accepted {
+                source: input.source,
+                root_hash,
+            }),
+            Err(err) => eprintln!("Warning: skipping {}: {}", input.source, err),
+        }
+    }
+    Ok(accepted)
+}
+
+/// The statuses of a checkpoint file by root hash. A line that was cut off when a run was
+/// interrupted is ignored, failed checks are not kept so they are checked again.
+pub fn read_checkpoint(file: &Path) -> Result<HashMap<String, StatusChange>, Error> {
+    let text = match std::fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<StatusChange>(line).ok())
+        .filter(|change| change.status != Status::Failed)
+        .map(|change| (change.root_hash.clone(), change))
+        .collect())
+}
+
+// the first block an attestation had its status at, one after the last block it did not
+async fn first_block(
+    history: &dyn BlockHistory,
+    last_before: Result<Option<u32>, Error>,
+) -> Result<history::ResolvedBlock, Error> {
+    let number = last_before?.ok_or(Error::BlockNotFound)?;
+    history::block_at(history, number + 1).await
+}
+
+/// The status of an attestation at the latest block, with the block it changed in for revoked
+/// and removed attestations. The block is found by probing the history like `history::last_seen`,
+/// which needs an archive node, if it cannot be found the change is not dated.
+pub async fn check(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    accepted: &Accepted,
+) -> StatusChange {
+    let mut change = StatusChange {
+        root_hash: hex_encode(accepted.root_hash),
+        source: accepted.source.clone(),
+        status: Status::Valid,
+        since_block: None,
+        since: None,
+        attester: None,
+        error: None,
+    };
+    let root_hash = &accepted.root_hash;
+    let last_before = match backend.attestation(root_hash, None).await {
+        Ok(Some(attestation)) => {
+            change.attester = Some(format!("did:kilt:{}", kilt_address(&attestation.attester)));
+            if !attestation.revoked {
+                return change;
+            }
+            change.status = Status::Revoked;
+            history::last_unrevoked(history, backend, root_hash).await
+        }
+        Ok(None) => {
+            change.status = Status::Removed;
+            history::last_seen(history, backend, root_hash).await
+        }
+        Err(err) => {
+            change.status = Status::Failed;
+            change.error = Some(err.to_string());
+            return change;
+        }
+    };
+    match first_block(history, last_before).await {
+        Ok(block) => {
+            change.since_block = Some(block.number);
+            change.since = Some(format_millis(block.timestamp));
+        }
+        Err(err) => change.error = Some(format!("the change could not be dated: {}", err)),
+    }
+    change
+}
+
+/// Check all accepted attestations, `concurrency` of them at the same time, each root hash once.
+/// With a checkpoint the statuses in it are taken from it and the new ones appended to it as they
+/// are known. The statuses are returned in the order of the input.
+pub async fn recheck(
+    history: &dyn BlockHistory,
+    backend: &dyn ChainBackend,
+    accepted: &[Accepted],
+    concurrency: usize,
+    checkpoint: Option<&Path>,
+) -> Result<Vec<StatusChange>, Error> {
+    let mut seen = HashSet::new();
+    let accepted: Vec<_> = accepted
+        .iter()
+        .filter(|accepted| seen.insert(accepted.root_hash))
+        .collect();
+    let done = match checkpoint {
+        Some(file) => read_checkpoint(file)?,
+        None => HashMap::new(),
+    };
+    let writer = match checkpoint {
+        Some(file) => Some(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(file)?,
+        )),
+        None => None,
+    };
+    let pending = accepted
+        .iter()
+        .filter(|accepted| !done.contains_key(&hex_encode(accepted.root_hash)))
+        .count();
+    if !done.is_empty() {
+        eprintln!(
+            "Resuming: {} attestations are in the checkpoint, {} to check",
+            accepted.len() - pending,
+            pending
+        );
+    }
+
+    let bar = if std::io::stderr().is_terminal() {
+        let bar = ProgressBar::with_draw_target(Some(pending as u64), ProgressDrawTarget::stderr());
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} {per_sec} ETA {eta}")
+                .expect("valid progress template"),
+        );
+        bar
+    } else {
+        ProgressBar::hidden()
+    };
+    let changes = stream::iter(accepted)
+        .map(|accepted| {
+            let (bar, writer, done) = (&bar, &writer, &done);
+            async move {
+                if let Some(change) = done.get(&hex_encode(accepted.root_hash)) {
+                    return Ok(change.clone());
+                }
+                let change = check(history, backend, accepted).await;
+                if let (Some(writer), true) = (writer, change.status != Status::Failed) {
+                    let mut line = serde_json::to_vec(&change)?;
+                    line.push(b'\n');
+                    let mut writer = writer.lock().expect("checkpoint writer is not poisoned");
+                    writer.write_all(&line)?;
+                    writer.flush()?;
+                }
+                bar.inc(1);
+                Ok::<_, Error>(change)
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    bar.finish_and_clear();
+    changes.into_iter().collect()
+}
+
+/// Write the statuses as csv or as json with a summary of the counts per status
+pub fn write_report(
+    out: &mut dyn Write,
+    changes: &[StatusChange],
+    format: ReportFormat,
+) -> Result<(), Error> {
+    match format {
+        ReportFormat::Csv => {
+            writeln!(
+                out,
+                "root_hash,source,status,since_block,since,attester,error"
+            )?;
+            for change in changes {
+                let since_block = change.since_block.map(|block| block.to_string());
+                let fields = [
+                    change.root_hash.as_str(),
+                    change.source.as_str(),
+                    change.status.label(),
+                    since_block.as_deref().unwrap_or_default(),
+                    change.since.as_deref().unwrap_or_default(),
+                    change.attester.as_deref().unwrap_or_default(),
+                    change.error.as_deref().unwrap_or_default(),
+                ];
+                let row: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+        ReportFormat::Json => {
+            let count = |status| changes.iter().filter(|c| c.status == status).count();
+            let report = serde_json::json!({
+                "results": changes,
+                "summary": {
+                    "total": changes.len(),
+                    "valid": count(Status::Valid),
+                    "revoked": count(Status::Revoked),
+                    "removed": count(Status::Removed),
+                    "failed": count(Status::Failed),
+                },
+            });
+            writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(cli: &KiltRuntimeApi, args: &RevocationReportArgs) -> Result<(), Error> {
+    let accepted = match (&args.results, &args.from_dir) {
+        (Some(file), _) => read_results(file)?,
+        (None, Some(path)) => read_credentials(path)?,
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    let checkpoint = args.checkpoint.as_deref().map(Path::new);
+    let changes = recheck(cli, cli, &accepted, args.concurrency, checkpoint).await?;
+    if args.output == "stdout" {
+        write_report(&mut std::io::stdout().lock(), &changes, args.format)?;
+    } else {
+        write_report(
+            &mut std::fs::File::create(&args.output)?,
+            &changes,
+            args.format,
+        )?;
+    }
+    match changes
+        .iter()
+        .filter(|c| c.status == Status::Failed)
+        .count()
+    {
+        0 => Ok(()),
+        failed => Err(Error::RecheckFailed(failed)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures,
+        kilt::runtime_types::{
+            attestation::attestations::AttestationDetails,
+            did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+        },
+    };
+    use async_trait::async_trait;
+    use subxt::sp_runtime::AccountId32;
+
+    // a chain of 100_000 blocks, 12 seconds apart, where the attestations were created at block 1,
+    // were revoked at the first and are removed from the second of their blocks on
+    struct Timeline {
+        changes: HashMap<H256, (Option<u64>, Option<u64>)>,
+        unreachable: H256,
+    }
+
+    const BLOCKS: u32 = 100_000;
+
+    #[async_trait]
+    impl BlockHistory for Timeline {
+        async fn best_block(&self) -> Result<u32, Error> {
+            Ok(BLOCKS)
+        }
+
+        async fn finalized_block(&self) -> Result<u32, Error> {
+            Ok(BLOCKS)
+        }
+
+        async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+            Ok((number <= BLOCKS).then(|| H256::from_low_u64_be(number as u64)))
+        }
+
+        async fn block_timestamp(&self, block: H256) -> Result<u64, Error> {
+            Ok(1_622_505_600_000 + (block.to_low_u64_be() - 1) * 12_000)
+        }
+    }
+
+    #[async_trait]
+    impl ChainBackend for Timeline {
+        async fn did(&self, _: &AccountId32, _: Option<H256>) -> Result<Option<DidDetails>, Error> {
+            Ok(None)
+        }
+
+        async fn attestation(
+            &self,
+            root_hash: &H256,
+            at: Option<H256>,
+        ) -> Result<Option<AttestationDetails>, Error> {
+            if *root_hash == self.unreachable {
+                return Err(Error::Timeout);
+            }
+            let number = at.map_or(BLOCKS as u64, |at| at.to_low_u64_be());
+            let (revoked, removed) = self.changes.get(root_hash).copied().unwrap_or_default();
+            if removed.is_some_and(|removed| number >= removed) {
+                return Ok(None);
+            }
+            Ok(Some(fixtures::attestation_details(
+                revoked.is_some_and(|revoked| number >= revoked),
+            )))
+        }
+
+        async fn web3_name_owner(
+            &self,
+            _: &str,
+            _: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            Ok(None)
+        }
+
+        async fn web3_name(
+            &self,
+            _: &AccountId32,
+            _: Option<H256>,
+        ) -> Result<Option<String>, Error> {
+            Ok(None)
+        }
+
+        async fn ctype_creator(
+            &self,
+            _: &H256,
+            _: Option<H256>,
+        ) -> Result<Option<AccountId32>, Error> {
+            Ok(None)
+        }
+
+        async fn service_endpoints(
+            &self,
+            _: &AccountId32,
+            _: Option<H256>,
+        ) -> Result<Vec<DidEndpoint>, Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn hash(n: u64) -> H256 {
+        H256::from_low_u64_be(n)
+    }
+
+    #[tokio::test]
+    async fn test_recheck() {
+        let dir = std::env::temp_dir().join(format!("kilt-verify-recheck-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let results = dir.join("results.txt");
+        let attester = fixtures::attester_did();
+        std::fs::write(
+            &results,
+            format!(
+                "VALID\t{}\tdid:kilt:4owner\t{}\nINVALID\t{}\tinvalid_signature\n{}\n\nno hash\n{}\n{}\n",
+                hex_encode(hash(1)),
+                attester,
+                hex_encode(hash(9)),
+                hex_encode(hash(2)),
+                hex_encode(hash(3)),
+                // listed twice
+                hex_encode(hash(1)),
+            ),
+        )
+        .unwrap();
+        let accepted = read_results(results.to_str().unwrap()).unwrap();
+        let hashes: Vec<_> = accepted.iter().map(|a| a.root_hash).collect();
+        assert_eq!(hashes, vec![hash(1), hash(2), hash(3), hash(1)]);
+        assert!(accepted[1].source.ends_with("results.txt:3"));
+
+        let mut chain = Timeline {
+            changes: HashMap::from([
+                (hash(2), (Some(54_321), None)),
+                (hash(3), (Some(40_000), Some(76_543))),
+            ]),
+            unreachable: hash(3),
+        };
+        let checkpoint = dir.join("checkpoint.ndjson");
+        let changes = recheck(&chain, &chain, &accepted, 2, Some(&checkpoint))
+            .await
+            .unwrap();
+        let statuses: Vec<_> = changes.iter().map(|c| (c.status, c.since_block)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (Status::Valid, None),
+                (Status::Revoked, Some(54_321)),
+                (Status::Failed, None),
+            ]
+        );
+        assert_eq!(changes[0].attester.as_deref(), Some(attester.as_str()));
+        assert_eq!(
+            changes[1].since.as_deref(),
+            Some("2021-06-08T13:04:00.000Z")
+        );
+        assert_eq!(changes[2].error.as_deref(), Some("Timed out"));
+        // the failed check is not kept
+        assert_eq!(read_checkpoint(&checkpoint).unwrap().len(), 2);
+
+        // the next run only checks what failed
+        chain.unreachable = hash(0);
+        let text = std::fs::read_to_string(&checkpoint).unwrap();
+        std::fs::write(
+            &checkpoint,
+            text.replace(&attester, "did:kilt:4fromcheckpoint"),
+        )
+        .unwrap();
+        let changes = recheck(&chain, &chain, &accepted, 2, Some(&checkpoint))
+            .await
+            .unwrap();
+        assert_eq!(
+            changes[0].attester.as_deref(),
+            Some("did:kilt:4fromcheckpoint")
+        );
+        assert_eq!(
+            (changes[2].status, changes[2].since_block),
+            (Status::Removed, Some(76_543))
+        );
+        assert_eq!(read_checkpoint(&checkpoint).unwrap().len(), 3);
+
+        let mut csv = Vec::new();
+        write_report(&mut csv, &changes, ReportFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "root_hash,source,status,since_block,since,attester,error"
+        );
+        assert!(
+            lines[2].starts_with(&format!("{},", hex_encode(hash(2)))),
+            "{}",
+            csv
+        );
+        assert!(
+            lines[2].ends_with(&format!(
+                ",revoked,54321,2021-06-08T13:04:00.000Z,{},",
+                "did:kilt:4fromcheckpoint"
+            )),
+            "{}",
+            csv
+        );
+        let mut json = Vec::new();
+        write_report(&mut json, &changes, ReportFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            json["summary"],
+            serde_json::json!({"total": 3, "valid": 1, "revoked": 1, "removed": 1, "failed": 0})
+        );
+        assert_eq!(json["results"][2]["status"], "removed");
+        assert_eq!(json["results"][2]["sinceBlock"], 76_543);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_checkpoint() {
+        let file =
+            std::env::temp_dir().join(format!("kilt-verify-checkpoint-{}", std::process::id()));
+        let line = |status: &str, hash: &str| {
+            format!(
+                "{{\"rootHash\":\"{}\",\"source\":\"a\",\"status\":\"{}\"}}\n",
+                hash, status
+            )
+        };
+        // the last line was cut off
+        std::fs::write(
+            &file,
+            line("valid", "0x01") + &line("failed", "0x02") + "{\"rootHash\":\"0x03\",\"sou",
+        )
+        .unwrap();
+        let done = read_checkpoint(&file).unwrap();
+        assert_eq!(done.len(), 1);
+        assert_eq!(done["0x01"].status, Status::Valid);
+        std::fs::remove_file(&file).unwrap();
+        assert!(read_checkpoint(&file).unwrap().is_empty());
+    }
+}
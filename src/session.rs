@@ -0,0 +1,328 @@
+use futures::future::join_all;
+use serde::Serialize;
+use std::io::Write;
+
+use crate::{
+    backend::ChainBackend,
+    batch::OutputFormat,
+    credential::{ClaimRequirements, Credential},
+    errors::Error,
+};
+
+/// The result of one presentation of a session
+#[derive(Debug)]
+pub struct SessionResult {
+    pub root_hash: String,
+    pub owner: String,
+    pub result: Result<(), Error>,
+}
+
+/// Presentations a holder made for the same challenge, i.e. an email and a membership credential
+/// of one login. The session is valid only if every presentation is.
+#[derive(Debug)]
+pub struct SessionReport {
+    pub challenge: String,
+    /// The expected owner or else the owner of the first presentation, all of them must have it
+    pub owner: Option<String>,
+    /// In the order of the presentations
+    pub results: Vec<SessionResult>,
+}
+
+/// Verify that every presentation was signed by its owner for the challenge and that they all
+/// have the same owner. The presentations are verified at the same time.
+pub async fn verify_session(
+    backend: &dyn ChainBackend,
+    credentials: &[Credential],
+    allowed_issuers: &[&str],
+    challenge: &str,
+    requirements: &ClaimRequirements,
+) -> SessionReport {
+    let owner = requirements
+        .owner
+        .clone()
+        .or_else(|| credentials.first().map(|cred| cred.claim.owner.clone()));
+    let requirements = ClaimRequirements {
+        owner: owner.clone(),
+        properties: requirements.properties.clone(),
+        full_did: requirements.full_did,
+    };
+    let results = join_all(credentials.iter().map(|cred| async {
+        let result = match cred.verify(backend, allowed_issuers, Some(challenge)).await {
+            Ok(()) => cred.check_requirements(&requirements),
+            Err(err) => Err(err),
+        };
+        SessionResult {
+            root_hash: cred.root_hash.clone(),
+            owner: cred.claim.owner.clone(),
+            result,
+        }
+    }))
+    .await;
+    SessionReport {
+        challenge: challenge.to_string(),
+        owner,
+        results,
+    }
+}
+
+#[derive(Serialize)]
+struct JsonSession<'a> {
+    valid: bool,
+    challenge: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broken_by: Option<&'a str>,
+    credentials: Vec<JsonSessionResult<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonSessionResult<'a> {
+    source: &'a str,
+    root_hash: &'a str,
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+impl SessionReport {
+    pub fn is_valid(&self) -> bool {
+        !self.results.is_empty() && self.results.iter().all(|r| r.result.is_ok())
+    }
+
+    /// Index of the first presentation that failed
+    pub fn broken_by(&self) -> Option<usize> {
+        self.results.iter().position(|r| r.result.is_err())
+    }
+
+    /// Fails with the first error of a node, since the session was not verified then, or with
+    /// the number of presentations that failed
+    pub fn into_result(mut self) -> Result<(), Error> {
+        if let Some(at) = self
+            .results
+            .iter()
+            .position(|r| r.result.as_ref().is_err_and(Error::is_infrastructure))
+        {
+            return self.results.swap_remove(at).result;
+        }
+        let failed = self.results.iter().filter(|r| r.result.is_err()).count();
+        match failed {
+            0 if !self.results.is_empty() => Ok(()),
+            failed => Err(Error::InvalidSession {
+                failed,
+                total: self.results.len(),
+            }),
+        }
+    }
+
+    /// Write the report with the sources the presentations were read from, as text or json
+    pub fn write(
+        &self,
+        out: &mut dyn Write,
+        sources: &[String],
+        output: OutputFormat,
+    ) -> Result<(), Error> {
+        let source = |at: usize| sources.get(at).unwrap_or(&self.results[at].root_hash);
+        match output {
+            OutputFormat::Human => {
+                for (at, r) in self.results.iter().enumerate() {
+                    match &r.result {
+                        Ok(()) => writeln!(out, "✅ {}", source(at))?,
+                        Err(err) => writeln!(out, "❌ {} [{}]: {}", source(at), err.code(), err)?,
+                    }
+                }
+                match self.broken_by() {
+                    Some(at) => writeln!(
+                        out,
+                        "❌ Session is invalid, {} broke it: {}",
+                        source(at),
+                        self.results[at].result.as_ref().unwrap_err()
+                    )?,
+                    None => writeln!(
+                        out,
+                        "✅ Session of {} credentials is valid for challenge {}{}",
+                        self.results.len(),
+                        self.challenge,
+                        self.owner
+                            .as_ref()
+                            .map(|owner| format!(", owned by {}", owner))
+                            .unwrap_or_default()
+                    )?,
+                }
+            }
+            OutputFormat::Json => {
+                let report = JsonSession {
+                    valid: self.is_valid(),
+                    challenge: &self.challenge,
+                    owner: self.owner.as_deref(),
+                    broken_by: self.broken_by().map(|at| source(at).as_str()),
+                    credentials: self
+                        .results
+                        .iter()
+                        .enumerate()
+                        .map(|(at, r)| JsonSessionResult {
+                            source: source(at),
+                            root_hash: &r.root_hash,
+                            valid: r.result.is_ok(),
+                            error: r.result.as_ref().err().map(Error::to_string),
+                            code: r.result.as_ref().err().map(Error::code),
+                        })
+                        .collect(),
+                };
+                serde_json::to_writer_pretty(&mut *out, &report)?;
+                writeln!(out)?;
+            }
+            _ => {
+                return Err(Error::InvalidReport(
+                    "a session is reported as human or json output".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        credential::Claim,
+        fixtures,
+        utils::{get_did_account_id, hex_decode_h256},
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    fn presentation(properties: &[&str], challenge: &str) -> Credential {
+        let properties: Vec<String> = properties.iter().map(|p| p.to_string()).collect();
+        fixtures::credential()
+            .create_presentation(&properties, challenge, &fixtures::owner_key())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_session() {
+        let mut backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let requirements = ClaimRequirements::default();
+        let credentials = [
+            presentation(&["Email"], "0x1234"),
+            presentation(&["Name"], "0x1234"),
+        ];
+        let report = verify_session(
+            &backend,
+            &credentials,
+            &[&attester],
+            "0x1234",
+            &requirements,
+        )
+        .await;
+        assert!(report.is_valid(), "{:?}", report);
+        assert_eq!(report.owner, Some(fixtures::owner_key().did()));
+        let mut text = Vec::new();
+        let sources = ["email.json".to_string(), "name.json".to_string()];
+        report
+            .write(&mut text, &sources, OutputFormat::Human)
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(
+            text.starts_with("✅ email.json\n✅ name.json\n✅ Session of 2 credentials is valid for challenge 0x1234, owned by did:kilt:"),
+            "{}",
+            text
+        );
+        assert!(report.into_result().is_ok());
+
+        // a presentation for another challenge breaks the session
+        let credentials = [
+            presentation(&["Email"], "0x1234"),
+            presentation(&["Name"], "0x5678"),
+        ];
+        let report = verify_session(
+            &backend,
+            &credentials,
+            &[&attester],
+            "0x1234",
+            &requirements,
+        )
+        .await;
+        assert_eq!(report.broken_by(), Some(1));
+        let mut json = Vec::new();
+        report
+            .write(&mut json, &sources, OutputFormat::Json)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["valid"], false);
+        assert_eq!(json["broken_by"], "name.json");
+        assert_eq!(json["credentials"][0]["valid"], true);
+        assert_eq!(json["credentials"][1]["code"], "invalid_challenge");
+        let res = report.into_result();
+        assert!(
+            matches!(
+                res,
+                Err(Error::InvalidSession {
+                    failed: 1,
+                    total: 2
+                })
+            ),
+            "{:?}",
+            res
+        );
+
+        // so does a valid credential of another owner, Bob attests his own
+        let bob = fixtures::attester_key();
+        let claim = Claim {
+            ctype_hash: fixtures::CTYPE_HASH.to_string(),
+            contents: json!({"Email": "bob@example.com"}),
+            owner: bob.did(),
+            ..Default::default()
+        };
+        let other = Credential::create(claim, &bob, &mut StdRng::seed_from_u64(0))
+            .unwrap()
+            .create_presentation(&[], "0x1234", &bob)
+            .unwrap();
+        backend.insert_attestation(
+            hex_decode_h256(&other.root_hash).unwrap(),
+            &fixtures::attestation_details(false),
+        );
+        let credentials = [presentation(&["Email"], "0x1234"), other.clone()];
+        let report = verify_session(
+            &backend,
+            &credentials,
+            &[&attester],
+            "0x1234",
+            &requirements,
+        )
+        .await;
+        assert_eq!(report.broken_by(), Some(1));
+        assert!(matches!(
+            report.results[1].result,
+            Err(Error::UnexpectedOwner)
+        ));
+        assert!(other
+            .verify(&backend, &[&attester], Some("0x1234"))
+            .await
+            .is_ok());
+
+        // the expected owner applies to the first presentation as well
+        let requirements = ClaimRequirements {
+            owner: Some(bob.did()),
+            properties: vec![],
+            full_did: false,
+        };
+        let report = verify_session(
+            &backend,
+            &[other.clone(), presentation(&["Email"], "0x1234")],
+            &[&attester],
+            "0x1234",
+            &requirements,
+        )
+        .await;
+        assert_eq!(report.broken_by(), Some(1));
+        assert_eq!(
+            get_did_account_id(report.owner.as_deref().unwrap()).unwrap(),
+            get_did_account_id(&bob.did()).unwrap()
+        );
+    }
+}
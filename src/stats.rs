@@ -0,0 +1,115 @@
+use hdrhistogram::{
+    serialization::{Serializer, V2Serializer},
+    Histogram,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::{
+    errors::Error,
+    metrics::{Check, CheckTimings},
+};
+
+// durations are recorded in microseconds up to a minute with 3 significant digits
+const MAX_MICROS: u64 = 60_000_000;
+
+/// Latency distribution of a batch in milliseconds
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Latency {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// Latency histograms of a batch, end-to-end and per check.
+/// All memory is allocated up front, so recording doesn't distort the measurements.
+pub struct BatchStats {
+    total: Histogram<u64>,
+    checks: [Histogram<u64>; 4],
+}
+
+fn histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_MICROS, 3).expect("valid histogram bounds")
+}
+
+impl Default for BatchStats {
+    fn default() -> Self {
+        BatchStats {
+            total: histogram(),
+            checks: [histogram(), histogram(), histogram(), histogram()],
+        }
+    }
+}
+
+fn latency(histogram: &Histogram<u64>) -> Option<Latency> {
+    if histogram.is_empty() {
+        return None;
+    }
+    let millis = |micros: u64| micros as f64 / 1000.0;
+    Some(Latency {
+        min: millis(histogram.min()),
+        p50: millis(histogram.value_at_quantile(0.5)),
+        p90: millis(histogram.value_at_quantile(0.9)),
+        p99: millis(histogram.value_at_quantile(0.99)),
+        max: millis(histogram.max()),
+    })
+}
+
+impl BatchStats {
+    /// Record the end-to-end duration and the check durations of one verification
+    pub fn record(&mut self, duration: Duration, timings: &CheckTimings) {
+        self.total.saturating_record(duration.as_micros() as u64);
+        for check in Check::ALL {
+            if let Some(duration) = timings.get(check) {
+                self.checks[check as usize].saturating_record(duration.as_micros() as u64);
+            }
+        }
+    }
+
+    pub fn total(&self) -> Option<Latency> {
+        latency(&self.total)
+    }
+
+    pub fn check(&self, check: Check) -> Option<Latency> {
+        latency(&self.checks[check as usize])
+    }
+
+    /// Write the end-to-end histogram in the HdrHistogram V2 format for offline analysis
+    pub fn write_hdr_histogram(&self, file: &str) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        V2Serializer::new()
+            .serialize(&self.total, &mut buffer)
+            .map_err(|err| Error::Io(std::io::Error::other(format!("{:?}", err))))?;
+        std::fs::write(file, buffer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batch_stats() {
+        let mut stats = BatchStats::default();
+        assert_eq!(stats.total(), None);
+
+        for millis in 1..=100 {
+            let mut timings = CheckTimings::default();
+            timings.record(Check::RootHash, Duration::from_micros(millis * 10));
+            stats.record(Duration::from_millis(millis), &timings);
+        }
+        // the histogram has a precision of 3 significant digits
+        let approx = |value: f64, expected: f64| (value - expected).abs() <= expected / 1000.0;
+        let total = stats.total().unwrap();
+        assert!(approx(total.min, 1.0), "{:?}", total);
+        assert!(approx(total.p50, 50.0), "{:?}", total);
+        assert!(approx(total.p90, 90.0), "{:?}", total);
+        assert!(approx(total.p99, 99.0), "{:?}", total);
+        assert!(approx(total.max, 100.0), "{:?}", total);
+        assert!(approx(stats.check(Check::RootHash).unwrap().p50, 0.5));
+        assert_eq!(stats.check(Check::Signature), None);
+    }
+}
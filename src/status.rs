@@ -0,0 +1,316 @@
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::{
+    credential::{ClaimRequirements, Credential},
+    errors::Error,
+    progress::{CheckStep, StepStatus, VerificationObserver},
+};
+
+/// Why a check did not run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// No challenge was asked for, the presentation may be a replay
+    NoChallenge,
+    /// Nothing was required of the claim
+    NoRequirements,
+    /// The verification ended before the check, because an earlier one failed or it timed out
+    NotReached,
+}
+
+/// Why a check passed with less certainty than it could have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckWarning {
+    /// The credential has no root hash, it was derived from the claim hashes
+    RootHashDerived,
+    /// The credential names no key, any key of the owner may have signed it
+    KeyInferred,
+    /// The owner is a light DID, it signed with a key of the full DID it was upgraded to
+    LightDidOwner,
+    /// The DID document of the owner was read from the HTTP resolver, not from the chain
+    ResolvedViaHttp,
+    /// The owner signed with a key that was removed from its DID document within the grace window
+    KeyRemoved,
+}
+
+/// How a check of a verification ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    /// The code of the error the verification failed with, the error itself is its result
+    Failed {
+        code: &'static str,
+    },
+    Skipped {
+        reason: SkipReason,
+    },
+    /// Passed, but see the warning
+    Warning {
+        warning: CheckWarning,
+    },
+}
+
+/// What the checks of a verification add up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Valid,
+    /// No check failed, but one passed with a warning or one that would prove more was skipped
+    PartiallyVerified,
+    Invalid,
+}
+
+/// A check failed: invalid. A warning or a skipped challenge: partially verified, the
+/// presentation is valid but may be a replay or was checked against less than the chain.
+/// Skipping requirements nobody asked for takes nothing away, the checks not reached come with
+/// the one that failed.
+pub fn verdict<'a>(statuses: impl IntoIterator<Item = &'a CheckStatus>) -> Verdict {
+    let mut verdict = Verdict::Valid;
+    for status in statuses {
+        match status {
+            CheckStatus::Failed { .. } => return Verdict::Invalid,
+            CheckStatus::Warning { .. }
+            | CheckStatus::Skipped {
+                reason: SkipReason::NoChallenge,
+            } => verdict = Verdict::PartiallyVerified,
+            CheckStatus::Passed
+            | CheckStatus::Skipped {
+                reason: SkipReason::NoRequirements | SkipReason::NotReached,
+            } => {}
+        }
+    }
+    verdict
+}
+
+/// One check of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CheckEntry {
+    pub check: CheckStep,
+    #[serde(flatten)]
+    pub status: CheckStatus,
+}
+
+/// The status of every check of a verification, in the order they run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct CheckReport(pub Vec<CheckEntry>);
+
+impl CheckReport {
+    pub fn verdict(&self) -> Verdict {
+        verdict(self.0.iter().map(|entry| &entry.status))
+    }
+
+    pub fn status(&self, check: CheckStep) -> Option<CheckStatus> {
+        self.0
+            .iter()
+            .find(|entry| entry.check == check)
+            .map(|entry| entry.status)
+    }
+
+    /// The check passed, possibly with a warning
+    pub fn passed(&self, check: CheckStep) -> bool {
+        matches!(
+            self.status(check),
+            Some(CheckStatus::Passed | CheckStatus::Warning { .. })
+        )
+    }
+
+    /// The check failed after the verification, i.e. an attestation without enough confirmations.
+    /// A verification that failed in another check already is kept as it is
+    pub fn fail(&mut self, check: CheckStep, err: &Error) {
+        let failed = self
+            .0
+            .iter()
+            .find(|entry| matches!(entry.status, CheckStatus::Failed { .. }));
+        if failed.is_some_and(|entry| entry.check != check) {
+            return;
+        }
+        for entry in self.0.iter_mut().filter(|entry| entry.check == check) {
+            entry.status = CheckStatus::Failed { code: err.code() };
+        }
+    }
+
+    /// The check passed with less certainty, i.e. than a lookup on the chain would give
+    pub fn warn(&mut self, check: CheckStep, warning: CheckWarning) {
+        for entry in self.0.iter_mut().filter(|entry| entry.check == check) {
+            if entry.status == CheckStatus::Passed {
+                entry.status = CheckStatus::Warning { warning };
+            }
+        }
+    }
+}
+
+/// Records how the checks of a verification ended, see `report`. A check that runs again, i.e.
+/// the attestation for its confirmations, ends with its last outcome
+#[derive(Debug, Default)]
+pub struct CheckRecorder(Mutex<Vec<(CheckStep, Result<(), &'static str>)>>);
+
+impl VerificationObserver for CheckRecorder {
+    fn on_step(&self, step: CheckStep, status: StepStatus<'_>) {
+        let ended = match status {
+            StepStatus::Started => return,
+            StepStatus::Passed { .. } => Ok(()),
+            StepStatus::Failed { error, .. } => Err(error.code()),
+        };
+        self.0.lock().unwrap().push((step, ended));
+    }
+}
+
+impl CheckRecorder {
+    /// The report of the checks of the credential, with what the verifier asked for
+    pub fn report(
+        &self,
+        cred: &Credential,
+        challenge: Option<&str>,
+        requirements: &ClaimRequirements,
+    ) -> CheckReport {
+        let recorded = self.0.lock().unwrap();
+        let requirements = requirements.owner.is_some() || !requirements.properties.is_empty();
+        let checks = CheckStep::ALL.iter().map(|&check| {
+            let ended = recorded
+                .iter()
+                .rfind(|(step, _)| *step == check)
+                .map(|(_, ended)| ended);
+            let status = match (check, ended) {
+                (_, None) => CheckStatus::Skipped {
+                    reason: SkipReason::NotReached,
+                },
+                (_, Some(Err(code))) => CheckStatus::Failed { code },
+                (CheckStep::Challenge, _) if challenge.is_none() => CheckStatus::Skipped {
+                    reason: SkipReason::NoChallenge,
+                },
+                (CheckStep::Requirements, _) if !requirements => CheckStatus::Skipped {
+                    reason: SkipReason::NoRequirements,
+                },
+                (CheckStep::RootHash, _) if cred.root_hash_derived => CheckStatus::Warning {
+                    warning: CheckWarning::RootHashDerived,
+                },
+                (CheckStep::Signature, _) if cred.key_inferred() => CheckStatus::Warning {
+                    warning: CheckWarning::KeyInferred,
+                },
+                (CheckStep::Signature, _) if cred.light_owner() => CheckStatus::Warning {
+                    warning: CheckWarning::LightDidOwner,
+                },
+                _ => CheckStatus::Passed,
+            };
+            CheckEntry { check, status }
+        });
+        CheckReport(checks.collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, progress};
+
+    #[test]
+    fn test_verdict() {
+        let passed = CheckStatus::Passed;
+        let failed = CheckStatus::Failed {
+            code: "attestation_revoked",
+        };
+        let skipped = |reason| CheckStatus::Skipped { reason };
+        let warning = CheckStatus::Warning {
+            warning: CheckWarning::KeyInferred,
+        };
+        for (statuses, expected) in [
+            (vec![passed, passed], Verdict::Valid),
+            (
+                vec![passed, skipped(SkipReason::NoRequirements)],
+                Verdict::Valid,
+            ),
+            (
+                vec![passed, skipped(SkipReason::NoChallenge)],
+                Verdict::PartiallyVerified,
+            ),
+            (vec![warning, passed], Verdict::PartiallyVerified),
+            (
+                vec![warning, failed, skipped(SkipReason::NotReached)],
+                Verdict::Invalid,
+            ),
+        ] {
+            assert_eq!(verdict(&statuses), expected, "{:?}", statuses);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_report() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let cred = fixtures::credential();
+        let requirements = ClaimRequirements::default();
+        let recorder = CheckRecorder::default();
+        cred.verify_observed(&backend, &[&attester], None, &recorder)
+            .await
+            .unwrap();
+        let mut report = recorder.report(&cred, None, &requirements);
+        assert_eq!(report.verdict(), Verdict::PartiallyVerified);
+        assert_eq!(
+            report.status(CheckStep::Challenge),
+            Some(CheckStatus::Skipped {
+                reason: SkipReason::NoChallenge
+            })
+        );
+        // the requirements are checked by the caller, they are not reached here
+        assert_eq!(
+            report.status(CheckStep::Requirements),
+            Some(CheckStatus::Skipped {
+                reason: SkipReason::NotReached
+            })
+        );
+        report.warn(CheckStep::Signature, CheckWarning::ResolvedViaHttp);
+        report.fail(CheckStep::Attestation, &Error::AttestationRevoked);
+        assert_eq!(report.verdict(), Verdict::Invalid);
+        report.fail(CheckStep::Signature, &Error::InvalidSignature);
+        assert_eq!(
+            report.status(CheckStep::Signature),
+            Some(CheckStatus::Warning {
+                warning: CheckWarning::ResolvedViaHttp
+            })
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json[3],
+            serde_json::json!({"check": "signature", "status": "warning", "warning": "resolved_via_http"})
+        );
+        assert_eq!(
+            json[4],
+            serde_json::json!({"check": "attestation", "status": "failed", "code": "attestation_revoked"})
+        );
+
+        // a presentation for the challenge that meets the requirements
+        let presentation = cred
+            .create_presentation(&["Email".to_string()], "0x1234", &fixtures::owner_key())
+            .unwrap();
+        let requirements = ClaimRequirements {
+            owner: Some(fixtures::owner_key().did()),
+            properties: vec!["Name".to_string()],
+            full_did: false,
+        };
+        let recorder = CheckRecorder::default();
+        let res = presentation
+            .verify_observed(&backend, &[&attester], Some("0x1234"), &recorder)
+            .await;
+        assert!(res.is_ok(), "{:?}", res);
+        let res = progress::observe(&recorder, CheckStep::Requirements, || {
+            presentation.check_requirements(&requirements)
+        });
+        assert!(matches!(res, Err(Error::PropertyNotFound(_))));
+        let report = recorder.report(&presentation, Some("0x1234"), &requirements);
+        assert_eq!(
+            report.status(CheckStep::Challenge),
+            Some(CheckStatus::Passed)
+        );
+        assert_eq!(
+            report.status(CheckStep::Requirements),
+            Some(CheckStatus::Failed {
+                code: "property_not_found"
+            })
+        );
+        assert_eq!(report.verdict(), Verdict::Invalid);
+    }
+}
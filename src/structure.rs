@@ -0,0 +1,250 @@
+use clap::Args;
+use serde::Serialize;
+
+use crate::{
+    credential::{
+        calculate_root_hash, hash_normalized, salt_hash, Credential, StatementKeys, REDACTED,
+    },
+    errors::Error,
+    utils::{parse_credential, read_credential_json},
+};
+
+/// Version of the structure document, bumped when fields change their meaning
+pub const STRUCTURE_VERSION: u64 = 1;
+
+/// How the statements are normalized: the owner as `{"@id":<owner>}`, then every top-level
+/// property of the contents as `{"kilt:ctype:<ctype hash>#<property>":<value>}`, or keyed as
+/// `statementKeys` says, both as compact json with the members of nested objects sorted by name
+pub const NORMALIZATION: &str = "kilt-statements-v1";
+
+const HASH: &str = "blake2b-256";
+
+/// Describe the commitment structure of a credential for auditors, without a chain
+#[derive(Args, Debug)]
+pub struct ExplainStructureArgs {
+    /// File containing the credential
+    #[clap(short, long, value_parser, default_value = "stdin")]
+    file: String,
+
+    /// File to write the json description to
+    #[clap(short, long, value_parser, default_value = "stdout")]
+    output: String,
+}
+
+/// Every step from the claim contents to the root hash, in the order they are done
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Structure {
+    pub version: u64,
+    pub tool_version: &'static str,
+    pub normalization: &'static str,
+    /// The template of the statement keys, only if the claim is not keyed the KILT way
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_keys: Option<String>,
+    pub hash: &'static str,
+    pub ctype_hash: String,
+    pub statements: Vec<Statement>,
+    pub claim_hashes: Vec<ClaimHash>,
+    pub root_hash: RootHash,
+}
+
+/// A normalized statement of the disclosed claim
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Statement {
+    pub index: usize,
+    /// The property of the contents, `None` for the owner statement
+    pub property: Option<String>,
+    pub statement: String,
+    pub hash: String,
+    /// `None` if the credential has no nonce for the statement
+    pub nonce: Option<String>,
+    /// The hash of the nonce followed by the statement hash
+    pub salted_hash: Option<String>,
+    /// The position of the salted hash in `claimHashes`, `None` if it is not listed
+    pub claim_hashes_position: Option<usize>,
+}
+
+/// An entry of `claimHashes`, in the order they are concatenated for the root hash
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimHash {
+    pub position: usize,
+    pub hash: String,
+    /// The index of the statement it commits to, `None` for statements that are not disclosed
+    pub statement: Option<usize>,
+}
+
+/// The root hash over the raw bytes of all claim hashes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootHash {
+    pub declared: String,
+    pub computed: String,
+    pub matches: bool,
+}
+
+/// Describe how the claim of the credential is committed to in its root hash. Statements that
+/// do not match are described as well, so the description can show where a credential is broken.
+pub fn explain(cred: &Credential) -> Result<Structure, Error> {
+    let normalized = cred.claim.normalize()?;
+    let properties = cred
+        .claim
+        .contents
+        .as_object()
+        .ok_or(Error::InvalidClaimContents)?
+        .keys()
+        .map(|key| Some(key.clone()));
+    let statements: Vec<Statement> = std::iter::once(None)
+        .chain(properties)
+        .zip(normalized)
+        .enumerate()
+        .map(|(index, (property, statement))| {
+            let hash = hash_normalized(&statement);
+            let nonce = cred.claim_nonce_map.get(&hash).cloned();
+            let salted_hash = nonce.as_ref().map(|nonce| salt_hash(nonce, &hash));
+            let claim_hashes_position = salted_hash
+                .as_ref()
+                .and_then(|salted| cred.claim_hashes.iter().position(|h| h == salted));
+            Statement {
+                index,
+                property,
+                statement,
+                hash,
+                nonce,
+                salted_hash,
+                claim_hashes_position,
+            }
+        })
+        .collect();
+
+    let claim_hashes = cred
+        .claim_hashes
+        .iter()
+        .enumerate()
+        .map(|(position, hash)| ClaimHash {
+            position,
+            hash: hash.clone(),
+            statement: statements
+                .iter()
+                .find(|statement| statement.claim_hashes_position == Some(position))
+                .map(|statement| statement.index),
+        })
+        .collect();
+
+    let computed = calculate_root_hash(&cred.claim_hashes)?;
+    Ok(Structure {
+        version: STRUCTURE_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION"),
+        normalization: NORMALIZATION,
+        statement_keys: cred.claim.statement_keys.template().map(str::to_string),
+        hash: HASH,
+        ctype_hash: cred.claim.ctype_hash.clone(),
+        statements,
+        claim_hashes,
+        root_hash: RootHash {
+            matches: computed == cred.root_hash,
+            declared: cred.root_hash.clone(),
+            computed,
+        },
+    })
+}
+
+impl Structure {
+    /// Replace the statements of the claim contents with `[redacted]`, with their hashes and
+    /// nonces that a guessed value could be checked against. The salted hashes stay
+    pub fn redact_claims(&mut self) {
+        for statement in self.statements.iter_mut() {
+            if statement.property.is_some() {
+                statement.statement = REDACTED.to_string();
+                statement.hash = REDACTED.to_string();
+                if let Some(nonce) = statement.nonce.as_mut() {
+                    *nonce = REDACTED.to_string();
+                }
+            }
+        }
+    }
+}
+
+pub fn run(
+    args: &ExplainStructureArgs,
+    redact_pii: bool,
+    statement_keys: &StatementKeys,
+) -> Result<(), Error> {
+    let data = read_credential_json(&args.file)?;
+    let mut cred = parse_credential(data.as_bytes())?;
+    cred.claim.statement_keys = statement_keys.clone();
+    let mut structure = explain(&cred)?;
+    if redact_pii {
+        structure.redact_claims();
+    }
+    let json = serde_json::to_string_pretty(&structure)?;
+    if args.output == "stdout" {
+        println!("{}", json);
+    } else {
+        std::fs::write(&args.output, json)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+    use serde_json::json;
+
+    #[test]
+    fn test_explain_structure() {
+        let cred = fixtures::credential();
+        let structure = explain(&cred).unwrap();
+        assert!(structure.root_hash.matches);
+        assert_eq!(structure.normalization, NORMALIZATION);
+        let properties: Vec<_> = structure
+            .statements
+            .iter()
+            .map(|s| s.property.as_deref())
+            .collect();
+        assert_eq!(properties, [None, Some("Email"), Some("Name")]);
+        assert_eq!(
+            structure.statements[0].statement,
+            format!(r#"{{"@id":"{}"}}"#, cred.claim.owner)
+        );
+        let hashes = cred.claim.hash_statements().unwrap();
+        for statement in &structure.statements {
+            assert_eq!(statement.hash, hashes[statement.index]);
+            let position = statement.claim_hashes_position.unwrap();
+            assert_eq!(
+                structure.claim_hashes[position].statement,
+                Some(statement.index)
+            );
+            assert_eq!(
+                statement.salted_hash.as_ref(),
+                Some(&cred.claim_hashes[position])
+            );
+        }
+
+        // a statement that was changed after the attestation is not in the claim hashes,
+        // and the claim hash of the original statement is not explained
+        let mut json = serde_json::to_value(&cred).unwrap();
+        json["claim"]["contents"]["Email"] = json!("mallory@example.com");
+        let tampered = parse_credential(&serde_json::to_vec(&json).unwrap()).unwrap();
+        let structure = explain(&tampered).unwrap();
+        assert!(structure.root_hash.matches);
+        assert!(structure.statements[1].nonce.is_none());
+        assert!(structure.statements[1].claim_hashes_position.is_none());
+        let unexplained = structure
+            .claim_hashes
+            .iter()
+            .filter(|h| h.statement.is_none())
+            .count();
+        assert_eq!(unexplained, 1);
+
+        let mut structure = explain(&cred).unwrap();
+        structure.redact_claims();
+        let json = serde_json::to_string(&structure).unwrap();
+        assert!(!json.contains("alice@example.com"), "{}", json);
+        assert!(json.contains(&cred.claim.owner), "{}", json);
+        assert_eq!(structure.statements[1].hash, "[redacted]");
+        assert!(structure.statements[1].salted_hash.is_some());
+    }
+}
@@ -0,0 +1,360 @@
+use async_trait::async_trait;
+use codec::{Compact, Decode, Encode};
+use subxt::{sp_core::H256, sp_runtime::AccountId32, BasicError, Phase};
+
+use crate::{
+    backend::ChainBackend,
+    errors::Error,
+    history::{self, BlockHistory},
+    kilt::{
+        attestation::Event as AttestationPalletEvent, balances::Event as BalancesEvent,
+        runtime_types::sp_runtime::multiaddress::MultiAddress, Event, KiltRuntimeApi,
+    },
+    utils::{hex_decode_h256, kilt_address},
+};
+
+/// The extrinsics and events of past blocks. Reading them for old blocks needs an archive node
+#[async_trait]
+pub trait BlockContents: BlockHistory {
+    /// The SCALE encoded extrinsics of the block in their order
+    async fn extrinsics(&self, block: H256) -> Result<Vec<Vec<u8>>, Error>;
+
+    /// The events of the block with the index of the extrinsic that emitted them, `None` for the
+    /// events of the block itself
+    async fn events(&self, block: H256) -> Result<Vec<(Option<u32>, Event)>, Error>;
+}
+
+#[async_trait]
+impl BlockContents for KiltRuntimeApi {
+    async fn extrinsics(&self, block: H256) -> Result<Vec<Vec<u8>>, Error> {
+        let block = self
+            .client
+            .rpc()
+            .block(Some(block))
+            .await?
+            .ok_or(Error::BlockNotFound)?;
+        Ok(block.block.extrinsics.iter().map(Encode::encode).collect())
+    }
+
+    async fn events(&self, block: H256) -> Result<Vec<(Option<u32>, Event)>, Error> {
+        let events = self.events().at(block).await?;
+        events
+            .iter()
+            .map(|event| {
+                let event = event?;
+                let extrinsic = match event.phase {
+                    Phase::ApplyExtrinsic(index) => Some(index),
+                    _ => None,
+                };
+                Ok((extrinsic, event.event))
+            })
+            .collect()
+    }
+}
+
+/// The extrinsic an attestation was created by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submission {
+    pub block: u32,
+    /// Index of the extrinsic in the block
+    pub extrinsic: u32,
+    /// The account that signed the extrinsic, the submitter of the DID call who pays its fee.
+    /// `None` if the extrinsic is not signed by an account
+    pub submitter: Option<AccountId32>,
+    /// What was withdrawn from the submitter for the extrinsic less what was refunded
+    pub fee: Option<u128>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::ConnectionError(BasicError::Other(msg))
+}
+
+/// The account that signed an encoded extrinsic, `None` if it is unsigned or signed by an address
+/// that is no account
+pub fn extrinsic_signer(extrinsic: &[u8]) -> Result<Option<AccountId32>, Error> {
+    let decode_err = |err: codec::Error| invalid(format!("cannot decode extrinsic: {}", err));
+    let input = &mut &extrinsic[..];
+    Compact::<u32>::decode(input).map_err(decode_err)?;
+    let version = u8::decode(input).map_err(decode_err)?;
+    if version & 0b1000_0000 == 0 {
+        return Ok(None);
+    }
+    if version & 0b0111_1111 != 4 {
+        return Err(invalid(format!(
+            "extrinsic version {} is not supported",
+            version & 0b0111_1111
+        )));
+    }
+    let address = MultiAddress::<AccountId32, ()>::decode(input).map_err(decode_err)?;
+    Ok(match address {
+        MultiAddress::Id(account) => Some(account),
+        MultiAddress::Address32(bytes) => Some(AccountId32::from(bytes)),
+        _ => None,
+    })
+}
+
+// the index of the extrinsic that created the attestation
+fn created_by(events: &[(Option<u32>, Event)], root_hash: &H256) -> Option<u32> {
+    events.iter().find_map(|(extrinsic, event)| match event {
+        Event::Attestation(AttestationPalletEvent::AttestationCreated(_, hash, ..))
+            if hash == root_hash =>
+        {
+            *extrinsic
+        }
+        _ => None,
+    })
+}
+
+// the fee is withdrawn before the extrinsic is applied and what it did not need is deposited back
+fn fee_paid(
+    events: &[(Option<u32>, Event)],
+    extrinsic: u32,
+    submitter: &AccountId32,
+) -> Option<u128> {
+    let mut withdrawn = None;
+    let mut refunded = 0u128;
+    for (_, event) in events.iter().filter(|(index, _)| *index == Some(extrinsic)) {
+        match event {
+            Event::Balances(BalancesEvent::Withdraw { who, amount }) if who == submitter => {
+                withdrawn = Some(withdrawn.unwrap_or(0u128).saturating_add(*amount));
+            }
+            Event::Balances(BalancesEvent::Deposit { who, amount }) if who == submitter => {
+                refunded = refunded.saturating_add(*amount);
+            }
+            _ => {}
+        }
+    }
+    withdrawn.map(|withdrawn| withdrawn.saturating_sub(refunded))
+}
+
+/// Find the extrinsic that created the attestation of the root hash: the block it was created in
+/// is taken from the backend if it knows it, i.e. an indexer, and otherwise bisected on chain.
+/// `None` if the attestation is not on chain
+pub async fn find_submission(
+    contents: &dyn BlockContents,
+    backend: &dyn ChainBackend,
+    root_hash: &str,
+) -> Result<Option<Submission>, Error> {
+    let root_hash = hex_decode_h256(root_hash)?;
+    let created = match backend.attestation_history(&root_hash).await? {
+        Some(history) => Some(u32::try_from(history.created).map_err(|_| Error::BlockNotFound)?),
+        None => history::first_seen(contents, backend, &root_hash).await?,
+    };
+    let block = match created {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+    let block_hash = contents
+        .block_hash(block)
+        .await?
+        .ok_or(Error::BlockNotFound)?;
+    let events = contents.events(block_hash).await?;
+    let extrinsic =
+        created_by(&events, &root_hash).ok_or(Error::EventNotFound("AttestationCreated"))?;
+    let extrinsics = contents.extrinsics(block_hash).await?;
+    let encoded = extrinsics.get(extrinsic as usize).ok_or_else(|| {
+        invalid(format!(
+            "block #{} has no extrinsic {} that emitted the event",
+            block, extrinsic
+        ))
+    })?;
+    let submitter = extrinsic_signer(encoded)?;
+    let fee = submitter
+        .as_ref()
+        .and_then(|submitter| fee_paid(&events, extrinsic, submitter));
+    Ok(Some(Submission {
+        block,
+        extrinsic,
+        submitter,
+        fee,
+    }))
+}
+
+/// The submission for people, in the layout of `issuer::describe_attestation`
+pub async fn describe_submission(
+    backend: &dyn ChainBackend,
+    submission: &Submission,
+) -> Result<String, Error> {
+    let mut text = format!(
+        "  submitted:   extrinsic {}-{}\n",
+        submission.block, submission.extrinsic
+    );
+    match &submission.submitter {
+        Some(submitter) => text += &format!("  submitter:   {}\n", kilt_address(submitter)),
+        None => text += "  submitter:   none, the extrinsic is not signed by an account\n",
+    }
+    if let Some(fee) = submission.fee {
+        text += &format!("  fee:         {}\n", backend.token().await?.format(fee));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures,
+        mock::{MockBackend, MockState},
+        utils::get_did_account_id,
+    };
+
+    // a signed extrinsic of version 4, the signature and the call are not read
+    fn signed(address: MultiAddress<AccountId32, ()>) -> Vec<u8> {
+        let mut body = vec![0b1000_0100];
+        address.encode_to(&mut body);
+        body.extend([1u8; 65]);
+        body.encode()
+    }
+
+    // ten blocks, the attestation is created in block 5 by the third extrinsic after two inherents
+    struct Chain {
+        backend: MockBackend,
+        submitter: AccountId32,
+    }
+
+    #[async_trait]
+    impl BlockHistory for Chain {
+        async fn best_block(&self) -> Result<u32, Error> {
+            Ok(9)
+        }
+
+        async fn finalized_block(&self) -> Result<u32, Error> {
+            Ok(9)
+        }
+
+        async fn block_hash(&self, number: u32) -> Result<Option<H256>, Error> {
+            Ok((number <= 9).then(|| H256::from_low_u64_be(number as u64)))
+        }
+
+        async fn block_timestamp(&self, _: H256) -> Result<u64, Error> {
+            Ok(0)
+        }
+    }
+
+    #[async_trait]
+    impl BlockContents for Chain {
+        async fn extrinsics(&self, block: H256) -> Result<Vec<Vec<u8>>, Error> {
+            assert_eq!(block, H256::from_low_u64_be(5));
+            let inherent = vec![0b0000_0100, 1, 2].encode();
+            let submitter = MultiAddress::Id(self.submitter.clone());
+            Ok(vec![inherent.clone(), inherent, signed(submitter)])
+        }
+
+        async fn events(&self, block: H256) -> Result<Vec<(Option<u32>, Event)>, Error> {
+            assert_eq!(block, H256::from_low_u64_be(5));
+            Ok(events(&self.submitter))
+        }
+    }
+
+    fn chain() -> Chain {
+        let mut backend = MockBackend::default();
+        for number in 0..=9 {
+            let state = match number {
+                0..=4 => MockState::default(),
+                _ => fixtures::state(false),
+            };
+            backend.insert_block(H256::from_low_u64_be(number), state);
+        }
+        let submitter = AccountId32::from([9u8; 32]);
+        Chain { backend, submitter }
+    }
+
+    // the fee of the submitter and a transfer to another account
+    fn events(submitter: &AccountId32) -> Vec<(Option<u32>, Event)> {
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        let other = AccountId32::from([8u8; 32]);
+        let attester = get_did_account_id(&fixtures::attester_did()).unwrap();
+        let created = Event::Attestation(AttestationPalletEvent::AttestationCreated(
+            attester,
+            root_hash,
+            hex_decode_h256(fixtures::CTYPE_HASH).unwrap(),
+            None,
+        ));
+        let withdraw = |who: &AccountId32, amount| {
+            let who = who.clone();
+            Event::Balances(BalancesEvent::Withdraw { who, amount })
+        };
+        let deposit = |who: &AccountId32, amount| {
+            let who = who.clone();
+            Event::Balances(BalancesEvent::Deposit { who, amount })
+        };
+        vec![
+            (None, withdraw(submitter, 1)),
+            (Some(1), withdraw(submitter, 2)),
+            (Some(2), withdraw(submitter, 20_000_000_000_000)),
+            (Some(2), withdraw(&other, 5)),
+            (Some(2), created),
+            (Some(2), deposit(submitter, 4_000_000_000_000)),
+            (Some(2), deposit(&other, 3)),
+        ]
+    }
+
+    #[test]
+    fn test_extrinsic_signer() {
+        let account = AccountId32::from([7u8; 32]);
+        let res = extrinsic_signer(&signed(MultiAddress::Id(account.clone())));
+        assert_eq!(res.unwrap(), Some(account.clone()));
+        let res = extrinsic_signer(&signed(MultiAddress::Address32([7u8; 32])));
+        assert_eq!(res.unwrap(), Some(account));
+        let res = extrinsic_signer(&signed(MultiAddress::Address20([7u8; 20])));
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(
+            extrinsic_signer(&vec![0b0000_0100u8].encode()).unwrap(),
+            None
+        );
+
+        for invalid in [
+            vec![],
+            vec![0b1000_0011].encode(),
+            vec![0b1000_0100].encode(),
+        ] {
+            let res = extrinsic_signer(&invalid);
+            assert!(matches!(res, Err(Error::ConnectionError(_))), "{:?}", res);
+        }
+    }
+
+    #[test]
+    fn test_fee_paid() {
+        let submitter = AccountId32::from([9u8; 32]);
+        let events = events(&submitter);
+        let root_hash = hex_decode_h256(&fixtures::credential().root_hash).unwrap();
+        assert_eq!(created_by(&events, &root_hash), Some(2));
+        assert_eq!(created_by(&events, &H256::zero()), None);
+        assert_eq!(fee_paid(&events, 2, &submitter), Some(16_000_000_000_000));
+        assert_eq!(fee_paid(&events, 1, &submitter), Some(2));
+        assert_eq!(fee_paid(&events, 3, &submitter), None);
+    }
+
+    #[tokio::test]
+    async fn test_find_submission() {
+        let chain = chain();
+        let root_hash = fixtures::credential().root_hash;
+        let submission = find_submission(&chain, &chain.backend, &root_hash)
+            .await
+            .unwrap();
+        assert_eq!(
+            submission,
+            Some(Submission {
+                block: 5,
+                extrinsic: 2,
+                submitter: Some(chain.submitter.clone()),
+                fee: Some(16_000_000_000_000),
+            })
+        );
+        let text = describe_submission(&chain.backend, &submission.unwrap())
+            .await
+            .unwrap();
+        let address = kilt_address(&chain.submitter);
+        assert!(text.contains("  submitted:   extrinsic 5-2\n"), "{}", text);
+        assert!(
+            text.contains(&format!("  submitter:   {}\n", address)),
+            "{}",
+            text
+        );
+        assert!(text.contains("  fee:         0.016 KILT\n"), "{}", text);
+
+        let missing = format!("0x{}", "00".repeat(32));
+        let res = find_submission(&chain, &chain.backend, &missing).await;
+        assert_eq!(res.unwrap(), None);
+    }
+}
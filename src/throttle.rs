@@ -0,0 +1,314 @@
+use prometheus::IntGauge;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::metrics;
+
+/// Pause of an endpoint after a throttled query whose provider did not say how long to wait.
+/// Retrying right away only makes a provider that throttles throttle longer
+pub const THROTTLE_PENALTY: Duration = Duration::from_secs(5);
+
+/// Longest pause a provider can ask for, longer ones are cut so a run does not stall
+pub const MAX_THROTTLE_DELAY: Duration = Duration::from_secs(60);
+
+// parts of the errors public endpoints and RPC providers throttle with, lowercase
+const THROTTLE_MARKERS: [&str; 5] = [
+    "too many requests",
+    "rate limit",
+    "ratelimit",
+    "rate-limit",
+    "throttl",
+];
+
+// parts of the errors that introduce the delay a provider asks for, lowercase
+const RETRY_HINTS: [&str; 4] = ["retry-after", "retry after", "retry_after", "try again in"];
+
+/// Who a chain query is for, background work only gets a share of the permits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Queries someone waits for, i.e. of verifications and of the checks a new chain event triggers
+    Interactive,
+    /// Refreshes nothing waits for, i.e. of the rounds of the monitor and of issuer registries
+    Background,
+}
+
+impl Priority {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Background => "background",
+        }
+    }
+}
+
+/// Bounds the number of chain queries in flight, shared by everything that queries the node.
+/// Background queries take a permit of their share first, so however many of them wait, an
+/// interactive query never queues behind more than the share. Permits are handed out in the
+/// order they are asked for.
+#[derive(Debug)]
+pub struct RpcLimiter {
+    permits: Semaphore,
+    background: Semaphore,
+}
+
+/// Allows one query, the permits are given back when it is dropped
+#[derive(Debug)]
+pub struct RpcPermit<'a> {
+    _permit: SemaphorePermit<'a>,
+    _background: Option<SemaphorePermit<'a>>,
+}
+
+const CLOSED: &str = "semaphore is never closed";
+
+// counts a query in the queue until it has its permit
+struct Queued(IntGauge);
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl RpcLimiter {
+    /// At most `concurrency` queries at the same time and `background` of them for background
+    /// work, `None` gives background work a quarter of the permits. Both are at least one
+    pub fn new(concurrency: usize, background: Option<usize>) -> Self {
+        let concurrency = concurrency.max(1);
+        let background = background.unwrap_or(concurrency / 4);
+        RpcLimiter {
+            permits: Semaphore::new(concurrency),
+            background: Semaphore::new(background.clamp(1, concurrency)),
+        }
+    }
+
+    /// Wait for a permit, the wait is recorded in the metrics
+    pub async fn acquire(&self, priority: Priority) -> RpcPermit<'_> {
+        let queued = Queued(metrics::rpc_queue_depth(priority.label()));
+        queued.0.inc();
+        let start = Instant::now();
+        let background = match priority {
+            Priority::Interactive => None,
+            Priority::Background => Some(self.background.acquire().await.expect(CLOSED)),
+        };
+        let permit = self.permits.acquire().await.expect(CLOSED);
+        drop(queued);
+        metrics::record_rpc_wait(priority.label(), start.elapsed());
+        RpcPermit {
+            _permit: permit,
+            _background: background,
+        }
+    }
+}
+
+/// A query the RPC provider refused because too many were sent to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    /// The delay the provider asked for, i.e. with "retry after 2s"
+    pub retry_after: Option<Duration>,
+}
+
+// the message has the status code 429 on its own, not as part of a hash or a larger number
+fn has_status_429(message: &str) -> bool {
+    message.match_indices("429").any(|(at, _)| {
+        let alphanumeric = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+        !alphanumeric(message[..at].chars().next_back())
+            && !alphanumeric(message[at + 3..].chars().next())
+    })
+}
+
+// the delay of a retry hint in the lowercase message, in seconds unless it names another unit
+fn retry_after(message: &str) -> Option<Duration> {
+    RETRY_HINTS.iter().find_map(|hint| {
+        let rest = &message[message.find(hint)? + hint.len()..];
+        let rest = rest.trim_start_matches([' ', ':', '=', '"', '\'']);
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..end].parse().ok()?;
+        let unit = rest[end..].trim_start();
+        let seconds = if unit.starts_with("ms") || unit.starts_with("milli") {
+            value / 1000.0
+        } else if unit.starts_with("min") {
+            value * 60.0
+        } else {
+            value
+        };
+        Duration::try_from_secs_f64(seconds).ok()
+    })
+}
+
+impl Throttled {
+    /// Tell a throttling answer of the provider from other failures by the error message
+    pub fn detect(message: &str) -> Option<Self> {
+        let message = message.to_lowercase();
+        let throttled = has_status_429(&message)
+            || THROTTLE_MARKERS
+                .iter()
+                .any(|marker| message.contains(marker));
+        throttled.then(|| Throttled {
+            retry_after: retry_after(&message),
+        })
+    }
+
+    /// How long to pause, the delay the provider asked for or the penalty if it did not say
+    pub fn delay(&self, penalty: Duration) -> Duration {
+        self.retry_after
+            .map_or(penalty, |delay| delay.min(MAX_THROTTLE_DELAY))
+    }
+}
+
+/// The pauses of throttled endpoints, shared by every query of a run. Once the provider of an
+/// endpoint throttles a query, all queries to the endpoint wait until the pause is over, so
+/// concurrent workers slow down together instead of each retrying right away.
+#[derive(Debug)]
+pub struct Throttle {
+    penalty: Duration,
+    paused: Mutex<HashMap<String, Instant>>,
+    throttled: AtomicUsize,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle::new(THROTTLE_PENALTY)
+    }
+}
+
+impl Throttle {
+    /// Pause for `penalty` after a throttled query whose provider did not say how long to wait
+    pub fn new(penalty: Duration) -> Self {
+        Throttle {
+            penalty,
+            paused: Mutex::new(HashMap::new()),
+            throttled: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pause the queries to the endpoint after one was throttled, a pause that lasts longer is
+    /// kept. Returns how long this query asked to pause
+    pub fn pause(&self, endpoint: &str, throttled: Throttled) -> Duration {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        let delay = throttled.delay(self.penalty);
+        let until = Instant::now() + delay;
+        let mut paused = self.paused.lock().expect("pauses are not poisoned");
+        let paused = paused.entry(endpoint.to_string()).or_insert(until);
+        *paused = (*paused).max(until);
+        delay
+    }
+
+    /// Wait until the pause of the endpoint is over, including pauses made while waiting
+    pub async fn wait(&self, endpoint: &str) {
+        loop {
+            let until = self
+                .paused
+                .lock()
+                .expect("pauses are not poisoned")
+                .get(endpoint)
+                .copied();
+            match until.and_then(|until| until.checked_duration_since(Instant::now())) {
+                Some(left) if !left.is_zero() => tokio::time::sleep(left).await,
+                _ => return,
+            }
+        }
+    }
+
+    /// Number of queries the providers throttled
+    pub fn throttled(&self) -> usize {
+        self.throttled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rpc_limiter() {
+        let limiter = RpcLimiter::new(2, None);
+        let first = limiter.acquire(Priority::Background).await;
+        // the share of background work is used up, interactive queries still get the rest
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(20),
+            limiter.acquire(Priority::Background),
+        )
+        .await;
+        assert!(waiting.is_err());
+        let second = limiter.acquire(Priority::Interactive).await;
+        drop(first);
+        let third = limiter.acquire(Priority::Background).await;
+        drop((second, third));
+        assert_eq!(metrics::rpc_queue_depth("background").get(), 0);
+
+        // no permits at all would never answer a query
+        let limiter = RpcLimiter::new(0, Some(0));
+        drop(limiter.acquire(Priority::Background).await);
+    }
+
+    #[test]
+    fn test_detect_throttling() {
+        let cases = [
+            (
+                "Rpc error: HTTP status client error (429 Too Many Requests)",
+                Some(None),
+            ),
+            (
+                "Rpc error: Too many requests, retry after 2s",
+                Some(Some(2000)),
+            ),
+            ("rate limit exceeded, Retry-After: 1.5", Some(Some(1500))),
+            (
+                "{\"code\":-32029,\"message\":\"throttled\",\"retry_after\":250ms}",
+                Some(Some(250)),
+            ),
+            ("Rate-limited, try again in 2 minutes", Some(Some(120_000))),
+            ("429, retry after soon", Some(None)),
+            (
+                "Connection error: Networking or low-level protocol error",
+                None,
+            ),
+            ("Rpc error: block 0x4291ab not found", None),
+            ("Query of did.did failed after 14290 ms", None),
+        ];
+        for (message, expected) in cases {
+            let detected = Throttled::detect(message)
+                .map(|throttled| throttled.retry_after.map(|delay| delay.as_millis()));
+            assert_eq!(detected, expected, "{}", message);
+        }
+
+        let penalty = Duration::from_secs(3);
+        let hinted = Throttled::detect("429, retry after 600").unwrap();
+        assert_eq!(hinted.delay(penalty), MAX_THROTTLE_DELAY);
+        assert_eq!(Throttled { retry_after: None }.delay(penalty), penalty);
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let throttle = Throttle::new(Duration::from_millis(40));
+        let start = Instant::now();
+        throttle.wait("ws://a").await;
+        assert!(start.elapsed() < Duration::from_millis(20));
+
+        let hint = Throttled {
+            retry_after: Some(Duration::from_millis(20)),
+        };
+        assert_eq!(
+            throttle.pause("ws://a", Throttled { retry_after: None }),
+            Duration::from_millis(40)
+        );
+        // the longer pause is kept
+        assert_eq!(throttle.pause("ws://a", hint), Duration::from_millis(20));
+        assert_eq!(throttle.throttled(), 2);
+
+        // other endpoints are not paused
+        throttle.wait("ws://b").await;
+        assert!(start.elapsed() < Duration::from_millis(20));
+        throttle.wait("ws://a").await;
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
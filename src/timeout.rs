@@ -0,0 +1,86 @@
+use std::{future::Future, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+use crate::errors::Error;
+
+/// Time a run gets to wrap up after its timeout, i.e. to print the partial batch summary,
+/// before it is dropped without cooperation
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Run a future until it completes or the token is cancelled, whatever happens first.
+/// Dropping the future on cancellation stops everything it started, i.e. pending RPC requests.
+pub async fn cancellable<T, E, F>(token: &CancellationToken, fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, E>>,
+    Error: From<E>,
+{
+    tokio::select! {
+        res = fut => Ok(res?),
+        _ = token.cancelled() => Err(Error::Timeout),
+    }
+}
+
+/// Cancel the token once the timeout expires and give the future the grace period to return
+/// what it completed so far. Without a timeout the future runs to completion.
+pub async fn with_timeout<T, F>(
+    timeout: Option<Duration>,
+    token: &CancellationToken,
+    fut: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return fut.await,
+    };
+    let timer = {
+        let token = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            token.cancel();
+        })
+    };
+    let res = tokio::time::timeout(timeout + GRACE_PERIOD, fut)
+        .await
+        .unwrap_or(Err(Error::Timeout));
+    timer.abort();
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let token = CancellationToken::new();
+        let res = with_timeout(Some(Duration::from_secs(10)), &token, async { Ok(1) }).await;
+        assert!(matches!(res, Ok(1)));
+        assert!(!token.is_cancelled());
+
+        // a future that cooperates stops with the token
+        let token = CancellationToken::new();
+        let res: Result<(), _> = with_timeout(
+            Some(Duration::from_millis(10)),
+            &token,
+            cancellable(&token, std::future::pending::<Result<(), Error>>()),
+        )
+        .await;
+        assert!(matches!(res, Err(Error::Timeout)), "{:?}", res);
+        assert!(token.is_cancelled());
+
+        // after the cancellation it can still return what it has
+        let token = CancellationToken::new();
+        let res = with_timeout(Some(Duration::from_millis(10)), &token, async {
+            let partial = cancellable(&token, std::future::pending::<Result<(), Error>>()).await;
+            assert!(matches!(partial, Err(Error::Timeout)));
+            Ok("partial summary")
+        })
+        .await;
+        assert!(matches!(res, Ok("partial summary")));
+
+        let res = with_timeout(None, &CancellationToken::new(), async { Ok(2) }).await;
+        assert!(matches!(res, Ok(2)));
+    }
+}
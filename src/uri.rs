@@ -0,0 +1,147 @@
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+
+use crate::errors::Error;
+
+/// The query or fragment parameter that holds the credential of a deep link
+pub const PARAMETER: &str = "credential";
+
+// wallets write base64url with and without padding
+const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A `--file` value is read as a URI if it starts with a scheme, i.e. `kilt://`
+pub fn is_uri(file: &str) -> bool {
+    file.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    })
+}
+
+fn parameter(part: Option<&str>) -> Option<&str> {
+    part?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(PARAMETER)?.strip_prefix('='))
+}
+
+/// The credential of a deep link of a wallet, base64url decoded: `kilt://verify?credential=...`
+/// or an https link with the credential in the fragment, `https://host/path#credential=...`.
+/// The fragment of an https link is not sent to the host, so the query is not read.
+pub fn decode(uri: &str) -> Result<Vec<u8>, Error> {
+    let (scheme, rest) = uri
+        .split_once("://")
+        .ok_or_else(|| Error::InvalidUri("no scheme".to_string()))?;
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    let (path, query) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (rest, None),
+    };
+    let payload = match scheme.to_ascii_lowercase().as_str() {
+        "kilt" => {
+            let action = path.trim_end_matches('/');
+            if action != "verify" {
+                return Err(Error::InvalidUri(format!(
+                    "unknown action kilt://{}, expected kilt://verify",
+                    action
+                )));
+            }
+            parameter(query).or_else(|| parameter(fragment))
+        }
+        "https" => {
+            if fragment.is_none() && parameter(query).is_some() {
+                return Err(Error::InvalidUri(format!(
+                    "the {} of an https link has to be in the fragment",
+                    PARAMETER
+                )));
+            }
+            parameter(fragment)
+        }
+        scheme => {
+            return Err(Error::InvalidUri(format!(
+                "unknown scheme {}, expected kilt or https",
+                scheme
+            )))
+        }
+    };
+    let payload = payload
+        .ok_or_else(|| Error::InvalidUri(format!("no {} parameter", PARAMETER)))?
+        .replace("%3D", "=")
+        .replace("%3d", "=");
+    BASE64URL
+        .decode(payload)
+        .map_err(|err| Error::InvalidUri(format!("the {} is not base64url: {}", PARAMETER, err)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fixtures, plan::PlanInput};
+    use base64::engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD};
+
+    #[test]
+    fn test_decode() {
+        let cred = fixtures::credential();
+        let data = serde_json::to_vec(&cred).unwrap();
+        let padded = URL_SAFE.encode(&data).replace('=', "%3D");
+        let unpadded = URL_SAFE_NO_PAD.encode(&data);
+        for uri in [
+            format!("kilt://verify?credential={}", unpadded),
+            format!("kilt://verify/?challenge=0x12&credential={}", padded),
+            format!("https://wallet.example.com/present#credential={}", unpadded),
+            format!(
+                "HTTPS://wallet.example.com/?lang=en#a=b&credential={}",
+                padded
+            ),
+        ] {
+            assert!(is_uri(&uri));
+            assert_eq!(decode(&uri).unwrap(), data, "{}", uri);
+            // and through the normal pipeline
+            match PlanInput::read(&uri, &Default::default(), &Default::default()).unwrap() {
+                PlanInput::Single(read) => assert_eq!(read.root_hash, cred.root_hash),
+                _ => panic!("{} is not a single credential", uri),
+            }
+        }
+
+        for (uri, error) in [
+            (
+                "ftp://example.com/cred",
+                "unknown scheme ftp, expected kilt or https",
+            ),
+            (
+                "kilt://present?credential=e30",
+                "unknown action kilt://present, expected kilt://verify",
+            ),
+            ("kilt://verify?challenge=0x12", "no credential parameter"),
+            (
+                "https://wallet.example.com/present",
+                "no credential parameter",
+            ),
+            (
+                "https://wallet.example.com/?credential=e30",
+                "the credential of an https link has to be in the fragment",
+            ),
+        ] {
+            let res = decode(uri);
+            assert!(
+                matches!(&res, Err(Error::InvalidUri(msg)) if msg == error),
+                "{}: {:?}",
+                uri,
+                res
+            );
+        }
+        let res = decode("kilt://verify?credential=not+base64");
+        assert!(matches!(res, Err(Error::InvalidUri(_))), "{:?}", res);
+        assert!(!is_uri("creds/a.json"));
+        assert!(!is_uri("stdin"));
+    }
+}
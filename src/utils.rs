@@ -1,57 +1,446 @@
-use std::io::Read;
-use subxt::{sp_core::crypto::Ss58Codec, sp_runtime::AccountId32};
+use base58::FromBase58;
+use codec::Encode;
+use std::{
+    borrow::Cow,
+    io::{IsTerminal, Read},
+    path::{Path, PathBuf},
+};
+use subxt::{
+    sp_core::{
+        crypto::{PublicError, Ss58AddressFormat, Ss58Codec},
+        ed25519, sr25519, Pair,
+    },
+    sp_runtime::AccountId32,
+};
+use zeroize::Zeroizing;
 
-use crate::{credential::Credential, errors::Error, kilt::runtime_types::primitive_types::H256};
+use crate::{
+    credential::{Credential, StatementKeys},
+    decompress,
+    errors::Error,
+    kilt::runtime_types::{
+        did::did_details::{DidPublicKey, DidVerificationKey},
+        primitive_types::H256,
+        sp_core as runtime_sp_core,
+    },
+    limits::Limits,
+};
 
-// read a credential from stdin
+/// Shown when the credential is read from a terminal, otherwise the tool looks like it hangs
+pub const STDIN_GUIDANCE: &str =
+    "Reading the credential from stdin: paste the credential JSON and press Ctrl-D, or use --file";
+
+/// Where cached chain state is kept, `$XDG_CACHE_HOME/kilt-verify` or `~/.cache/kilt-verify`
+pub fn cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("kilt-verify")
+}
+
+/// Strip what text is wrapped in when it is saved by some editors or pasted from a chat: a UTF-8
+/// byte order mark and a markdown code fence like ```` ```json ````. Other input, like a line
+/// that only starts with a fence, is kept as it is
+pub fn strip_wrapping(data: &[u8]) -> &[u8] {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let fenced = data.trim_ascii().strip_prefix(b"```").and_then(|rest| {
+        // the language, if any, is the rest of the first line
+        let (info, body) = rest.split_at(rest.iter().position(|&b| b == b'\n')?);
+        if info.contains(&b'`') {
+            return None;
+        }
+        body.strip_suffix(b"```")
+    });
+    fenced.unwrap_or(data)
+}
+
+// read a credential from a file or stdin
 pub fn read_credential(file: &str) -> Result<Credential, Error> {
-    let mut s = String::new();
+    parse_credential(read_credential_json(file)?.as_bytes())
+}
+
+// read the json of a credential from a file or stdin without parsing it, gzip and zstd are
+// decompressed up to the default limit
+pub fn read_credential_json(file: &str) -> Result<String, Error> {
+    let data = read_credential_data(file)?;
+    let data = decompress::decompress(&data, decompress::DEFAULT_MAX_SIZE)?;
+    String::from_utf8(strip_wrapping(&data).to_vec()).map_err(|_| {
+        Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        ))
+    })
+}
+
+// read a credential in any format from a file or stdin without parsing it
+pub fn read_credential_data(file: &str) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
     if file == "stdin" {
-        std::io::stdin().read_to_string(&mut s)?;
+        if std::io::stdin().is_terminal() {
+            eprintln!("{}", STDIN_GUIDANCE);
+        }
+        std::io::stdin().read_to_end(&mut data)?;
+        if data.iter().all(u8::is_ascii_whitespace) {
+            return Err(Error::NoInput);
+        }
     } else {
-        s = std::fs::read_to_string(file)?;
+        data = std::fs::read(file)?;
     }
-    let claim = serde_json::from_str(s.as_str())?;
-    Ok(claim)
+    Ok(data)
 }
 
-// did should contain two colons `:` and one hashtag `#`
-// i.e. "did:kilt:1234#0x1234"
-fn get_did_parts(did: &str) -> Result<Vec<&str>, Error> {
-    let did_parts: Vec<&str> = did.split(':').collect();
-    if did_parts.len() != 3 {
-        Err(Error::InvalidDid)
-    } else {
-        Ok(did_parts)
+// parse a credential from untrusted json, this must never panic
+pub fn parse_credential(data: &[u8]) -> Result<Credential, Error> {
+    let mut credential: Credential = serde_json::from_slice(data)?;
+    // a missing or empty root hash is derived, the signature and attestation checks use it
+    if credential.root_hash.is_empty() {
+        credential.root_hash = credential.compute_root_hash()?;
+        credential.root_hash_derived = true;
+    }
+    Ok(credential)
+}
+
+/// Parse a credential that may be JSON5, i.e. with comments and trailing commas. The input is
+/// turned into plain json first, so the claim is normalized the same way as without. Values that
+/// json cannot express, like `NaN`, become null and fail the claim hash check.
+pub fn parse_credential_lenient(data: &[u8]) -> Result<Credential, Error> {
+    parse_credential(&serde_json::to_vec(&json5_to_json(data)?)?)
+}
+
+fn json5_to_json(data: &[u8]) -> Result<serde_json::Value, Error> {
+    let text = std::str::from_utf8(data).map_err(|err| json5::Error::Message {
+        msg: err.to_string(),
+        location: None,
+    })?;
+    Ok(json5::from_str(text)?)
+}
+
+/// Syntax of a credential file
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Json,
+    /// Parsed into the same credential, see `yaml::to_json` for how scalars are typed
+    Yaml,
+    /// Binary encoding of the json of a credential, see `cbor::to_json`
+    Cbor,
+}
+
+impl InputFormat {
+    /// The format of a file by its extension, json unless it ends in `.yaml`, `.yml` or `.cbor`.
+    /// A `.gz` or `.zst` after it is skipped.
+    pub fn of_file(file: &str) -> Self {
+        match Path::new(decompress::strip_extension(file))
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("yaml") | Some("yml") => InputFormat::Yaml,
+            Some("cbor") => InputFormat::Cbor,
+            _ => InputFormat::Json,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Json => "json",
+            InputFormat::Yaml => "yaml",
+            InputFormat::Cbor => "cbor",
+        }
+    }
+}
+
+/// How the credentials of a run are parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Format of all credentials, by default it is taken from the extension of each file
+    pub format: Option<InputFormat>,
+    /// Parse json as JSON5, see `parse_credential_lenient`
+    pub lenient_json: bool,
+    /// Limit of gzip and zstd input once decompressed, `None` with `--no-decompress`
+    pub max_decompressed_size: Option<u64>,
+    /// Limits of every credential, checked here and again with the claim contents
+    pub limits: Limits,
+    /// How the claims are normalized, see `Claim::statement_keys`
+    pub statement_keys: StatementKeys,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            format: None,
+            lenient_json: false,
+            max_decompressed_size: Some(decompress::DEFAULT_MAX_SIZE),
+            limits: Limits::default(),
+            statement_keys: StatementKeys::default(),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// The format of the file or batch entry `source`
+    pub fn format_of(&self, source: &str) -> InputFormat {
+        self.format.unwrap_or_else(|| InputFormat::of_file(source))
+    }
+
+    /// The data read from a file, decompressed if it starts like gzip or zstd
+    pub fn decompress<'d>(&self, data: &'d [u8]) -> Result<Cow<'d, [u8]>, Error> {
+        match self.max_decompressed_size {
+            Some(max_size) => decompress::decompress(data, max_size),
+            None => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// The json a credential read from `source` stands for, in every format it is what the claim
+    /// is normalized from
+    pub fn to_json(&self, source: &str, data: &[u8]) -> Result<serde_json::Value, Error> {
+        let data = &*self.decompress(data)?;
+        match self.format_of(source) {
+            InputFormat::Json if self.lenient_json => json5_to_json(strip_wrapping(data)),
+            InputFormat::Json => Ok(serde_json::from_slice(strip_wrapping(data))?),
+            InputFormat::Yaml => {
+                let data = strip_wrapping(data);
+                let text = std::str::from_utf8(data).map_err(|err| {
+                    <serde_yaml::Error as serde::de::Error>::custom(format!(
+                        "the input is not utf-8: {}",
+                        err
+                    ))
+                })?;
+                crate::yaml::to_json(text)
+            }
+            InputFormat::Cbor => crate::cbor::to_json(data),
+        }
+    }
+
+    /// Parse a credential read from the file or batch entry `source`
+    pub fn parse(&self, source: &str, data: &[u8]) -> Result<Credential, Error> {
+        let cred = match self.format_of(source) {
+            InputFormat::Json if !self.lenient_json => {
+                parse_credential(strip_wrapping(&self.decompress(data)?))
+            }
+            _ => parse_credential(&serde_json::to_vec(&self.to_json(source, data)?)?),
+        }?;
+        self.limits.check(&cred)?;
+        let mut cred = Credential {
+            limits: self.limits,
+            ..cred
+        };
+        cred.claim.statement_keys = self.statement_keys.clone();
+        Ok(cred)
+    }
+}
+
+/// A KILT DID or DID URL, i.e. `did:kilt:4abc…`, `did:kilt:4abc…?version=1#0x12…` or
+/// `did:kilt:4abc…%230x12…` with the `#` percent-encoded as some libraries write key uris
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DidUrl {
+    /// The address of `did:kilt:<address>`, not checked to be ss58
+    pub address: String,
+    pub form: DidForm,
+    /// The query without the `?`, kept as it is
+    pub query: Option<String>,
+    /// The percent-decoded fragment, the key id of a key uri
+    pub fragment: Option<String>,
+}
+
+// decode the %XX escapes of a DID URL part
+fn percent_decode(part: &str) -> Result<String, Error> {
+    let invalid = || Error::InvalidDidUrl(format!("{} is not percent-encoded", part));
+    let mut bytes = Vec::with_capacity(part.len());
+    let mut rest = part.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let escape = rest.get(..2).ok_or_else(invalid)?;
+        let escape = std::str::from_utf8(escape).map_err(|_| invalid())?;
+        bytes.push(u8::from_str_radix(escape, 16).map_err(|_| invalid())?);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// How a DID names its account
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DidForm {
+    /// `did:kilt:<address>`, the DID document is on chain
+    #[default]
+    Full,
+    /// `did:kilt:light:<key type><address>[:<details>]`, the keys are in the DID itself. A light
+    /// DID that was upgraded is the full DID of the same address
+    Light {
+        /// Two digits, i.e. "00" for sr25519
+        key_type: String,
+        details: Option<String>,
+    },
+}
+
+impl DidForm {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DidForm::Full => "full",
+            DidForm::Light { .. } => "light",
+        }
+    }
+}
+
+impl DidUrl {
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidDidUrl(format!("{} {}", url, reason));
+        let (did, fragment) = match url.split_once('#') {
+            Some((_, fragment)) if fragment.contains('#') => {
+                return Err(invalid("has more than one #"))
+            }
+            Some((did, fragment)) => (did, Some(fragment)),
+            // a KILT address has no %, so an encoded # can only start the fragment
+            None => match url.to_ascii_lowercase().find("%23") {
+                Some(at) => (&url[..at], Some(&url[at + 3..])),
+                None => (url, None),
+            },
+        };
+        let fragment = match fragment.map(percent_decode).transpose()? {
+            Some(fragment) if fragment.is_empty() => return Err(invalid("has an empty fragment")),
+            Some(fragment) if fragment.contains('#') => return Err(invalid("has more than one #")),
+            fragment => fragment,
+        };
+        let (did, query) = match did.split_once('?') {
+            Some((did, query)) => (did, (!query.is_empty()).then(|| query.to_string())),
+            None => (did, None),
+        };
+        let (address, form) = match did.split(':').collect::<Vec<_>>()[..] {
+            ["did", "kilt", "light", id, ref details @ ..] if details.len() <= 1 => {
+                match id.split_at_checked(2) {
+                    Some((key_type, address))
+                        if key_type.bytes().all(|b| b.is_ascii_digit()) && !address.is_empty() =>
+                    {
+                        let form = DidForm::Light {
+                            key_type: key_type.to_string(),
+                            details: details.first().map(|details| details.to_string()),
+                        };
+                        (address, form)
+                    }
+                    _ => return Err(Error::InvalidDid),
+                }
+            }
+            ["did", "kilt", address] if !address.is_empty() => (address, DidForm::Full),
+            _ => return Err(Error::InvalidDid),
+        };
+        Ok(DidUrl {
+            address: address.to_string(),
+            form,
+            query,
+            fragment,
+        })
+    }
+
+    /// The DID without query and fragment
+    pub fn did(&self) -> String {
+        match &self.form {
+            DidForm::Full => self.full_did(),
+            DidForm::Light { key_type, details } => format!(
+                "did:kilt:light:{}{}{}",
+                key_type,
+                self.address,
+                details
+                    .as_ref()
+                    .map(|details| format!(":{}", details))
+                    .unwrap_or_default()
+            ),
+        }
+    }
+
+    /// The full DID of the address, the same DID for a full DID
+    pub fn full_did(&self) -> String {
+        format!("did:kilt:{}", self.address)
+    }
+
+    pub fn is_light(&self) -> bool {
+        matches!(self.form, DidForm::Light { .. })
+    }
+
+    /// The account of the address, which has to be a KILT address
+    pub fn account_id(&self) -> Result<AccountId32, Error> {
+        match analyze_address(&self.address) {
+            AddressAnalysis::Kilt(account) => Ok(account),
+            AddressAnalysis::OtherNetwork { prefix, kilt } => Err(Error::ForeignAddress {
+                address: self.address.clone(),
+                prefix,
+                kilt,
+            }),
+            AddressAnalysis::BadChecksum | AddressAnalysis::Malformed => Err(Error::InvalidDid),
+        }
+    }
+
+    /// The key id of a key uri, see `decode_key_id`
+    pub fn key_id(&self) -> Result<H256, Error> {
+        match &self.fragment {
+            Some(fragment) => decode_key_id(fragment),
+            None => Err(Error::InvalidDid),
+        }
+    }
+}
+
+impl std::str::FromStr for DidUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self, Error> {
+        DidUrl::parse(url)
+    }
+}
+
+impl std::fmt::Display for DidUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.did())?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            let fragment = fragment.replace('%', "%25").replace('#', "%23");
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
     }
 }
 
 // take a DID string and return the account id of it
 pub fn get_did_account_id(did: &str) -> Result<AccountId32, Error> {
-    let did_parts = get_did_parts(did)?;
-    did_parts[2]
-        .split('#')
-        .next()
-        .ok_or(Error::InvalidDid)
-        .map(AccountId32::from_ss58check)?
-        .map_err(|_| Error::InvalidDid)
+    DidUrl::parse(did)?.account_id()
 }
 
 // take a key uri string and return the key id of it
 // i.e. "did:kilt:1234#0x05060708" -> [5,6,7,8]
 pub fn get_did_key_uri(did: &str) -> Result<H256, Error> {
-    let did_parts = get_did_parts(did)?;
+    DidUrl::parse(did)?.key_id()
+}
+
+/// Encodings of key ids in the fragment of a key uri, by the prefix they are told apart by
+pub const KEY_ID_ENCODINGS: &str = "0x (hex), z (multibase base58btc), f or F (multibase base16)";
 
-    let parts: Vec<&str> = did_parts[2].split('#').collect();
-    if parts.len() != 2 {
-        Err(Error::InvalidDid)
+/// Decode the fragment of a key uri to the 32 bytes of the key id.
+/// The KILT SDK writes them as 0x-hex, newer DID tooling as multibase.
+pub fn decode_key_id(fragment: &str) -> Result<H256, Error> {
+    // the fragment can be shorter than the prefix or start with a multi byte character,
+    // so it must not be sliced at a fixed position
+    let decoded = if let Some(hex) = fragment.strip_prefix("0x") {
+        hex::decode(hex).map_err(|err| format!("{} is not valid hex: {}", fragment, err))
+    } else if let Some(base58) = fragment.strip_prefix('z') {
+        base58
+            .from_base58()
+            .map_err(|err| format!("{} is not valid base58btc: {:?}", fragment, err))
+    } else if let Some(hex) = fragment.strip_prefix(['f', 'F']) {
+        hex::decode(hex).map_err(|err| format!("{} is not valid base16: {}", fragment, err))
     } else {
-        Ok(H256(
-            hex::decode(&parts[1][2..])?
-                .try_into()
-                .map_err(|_| Error::InvalidDid)?,
+        Err(format!(
+            "{:?} has no supported prefix, supported are {}",
+            fragment, KEY_ID_ENCODINGS
         ))
-    }
+    };
+    let bytes = decoded.map_err(Error::InvalidKeyId)?;
+    let len = bytes.len();
+    Ok(H256(bytes.try_into().map_err(|_| {
+        Error::InvalidKeyId(format!("{} is {} bytes instead of 32", fragment, len))
+    })?))
 }
 
 // hex encoding helper which adds '0x' as a prefix
@@ -62,6 +451,17 @@ where
     format!("0x{}", hex::encode(data.as_ref()))
 }
 
+/// Pretty json with the members of every object sorted by name, so the output does not follow
+/// the order fields are declared in. serde_json keeps object members sorted without `preserve_order`.
+pub fn to_sorted_json<T: serde::Serialize>(value: &T) -> Result<String, Error> {
+    Ok(serde_json::to_string_pretty(&serde_json::to_value(value)?)?)
+}
+
+/// The start of a hex encoded hash, enough to tell it apart in logs and traces
+pub fn short_hash(hash: &str) -> &str {
+    hash.get(..10).unwrap_or(hash)
+}
+
 // hex decoding helper which strips '0x' as a prefix
 pub fn hex_decode<T>(data: T) -> Result<Vec<u8>, Error>
 where
@@ -72,17 +472,480 @@ where
     Ok(hex::decode(normalized)?.to_vec())
 }
 
+// hex decoding helper for 32 byte hashes like root hashes and ctype hashes
+pub fn hex_decode_h256<T>(data: T) -> Result<subxt::sp_core::H256, Error>
+where
+    T: ToString,
+{
+    Ok(subxt::sp_core::H256(hex_decode(data)?.try_into().map_err(
+        |_| Error::InvalidHex(hex::FromHexError::InvalidStringLength),
+    )?))
+}
+
+// read a secret seed either from the command line or from a file, it is zeroized when dropped
+pub fn read_seed(seed: Option<&str>, seed_file: Option<&str>) -> Result<Zeroizing<String>, Error> {
+    match (seed, seed_file) {
+        (Some(seed), _) => Ok(Zeroizing::new(seed.to_string())),
+        (None, Some(file)) => {
+            let data = Zeroizing::new(std::fs::read_to_string(file)?);
+            Ok(Zeroizing::new(data.trim().to_string()))
+        }
+        (None, None) => Err(Error::InvalidSeed),
+    }
+}
+
+// create a sr25519 key pair from a mnemonic, a hex seed or a dev seed like `//Alice`
+pub fn sr25519_pair(seed: &str) -> Result<sr25519::Pair, Error> {
+    sr25519::Pair::from_string(seed, None).map_err(|_| Error::InvalidSeed)
+}
+
+// the key id of a DID public key is the blake2b256 hash of the encoded key
+pub fn get_key_id(key: &DidPublicKey) -> [u8; 32] {
+    subxt::sp_core::hashing::blake2_256(&key.encode())
+}
+
+/// Supported signature schemes of DID verification keys
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    Sr25519,
+    Ed25519,
+}
+
+/// A key pair that signs on behalf of a DID
+#[derive(Clone)]
+pub enum DidKeyPair {
+    Sr25519(sr25519::Pair),
+    Ed25519(ed25519::Pair),
+}
+
+impl DidKeyPair {
+    // create a key pair from a mnemonic, a hex seed or a dev seed like `//Alice`
+    pub fn from_seed(seed: &str, key_type: KeyType) -> Result<Self, Error> {
+        match key_type {
+            KeyType::Sr25519 => Ok(DidKeyPair::Sr25519(sr25519_pair(seed)?)),
+            KeyType::Ed25519 => Ok(DidKeyPair::Ed25519(
+                ed25519::Pair::from_string(seed, None).map_err(|_| Error::InvalidSeed)?,
+            )),
+        }
+    }
+
+    // the public key as it is stored in a DID document
+    pub fn public_key(&self) -> DidPublicKey {
+        match self {
+            DidKeyPair::Sr25519(pair) => DidPublicKey::PublicVerificationKey(
+                DidVerificationKey::Sr25519(runtime_sp_core::sr25519::Public(pair.public().0)),
+            ),
+            DidKeyPair::Ed25519(pair) => DidPublicKey::PublicVerificationKey(
+                DidVerificationKey::Ed25519(runtime_sp_core::ed25519::Public(pair.public().0)),
+            ),
+        }
+    }
+
+    // the full DID whose authentication key is this key pair, i.e. "did:kilt:4abc..."
+    pub fn did(&self) -> String {
+        let public = match self {
+            DidKeyPair::Sr25519(pair) => pair.public().0,
+            DidKeyPair::Ed25519(pair) => pair.public().0,
+        };
+        format!(
+            "did:kilt:{}",
+            AccountId32::from(public).to_ss58check_with_version(Ss58AddressFormat::custom(38))
+        )
+    }
+
+    // the key id of this key in a DID document
+    pub fn key_id(&self) -> [u8; 32] {
+        get_key_id(&self.public_key())
+    }
+
+    // the key uri of this key in its own full DID, i.e. "did:kilt:4abc...#0x1234..."
+    pub fn key_uri(&self) -> String {
+        format!("{}#{}", self.did(), hex_encode(self.key_id()))
+    }
+
+    // sign a message with the key pair
+    pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        match self {
+            DidKeyPair::Sr25519(pair) => pair.sign(msg).0.to_vec(),
+            DidKeyPair::Ed25519(pair) => pair.sign(msg).0.to_vec(),
+        }
+    }
+}
+
+/// An account as KILT address, i.e. "4abc..."
+pub fn kilt_address(account: &AccountId32) -> String {
+    account.to_ss58check_with_version(Ss58AddressFormat::custom(KILT_SS58_PREFIX))
+}
+
+/// The ss58 prefix of KILT addresses
+pub const KILT_SS58_PREFIX: u16 = 38;
+
+/// What an ss58 address turned out to be, see `analyze_address`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressAnalysis {
+    Kilt(AccountId32),
+    /// A valid address of another network, `kilt` is the same public key as KILT address
+    OtherNetwork {
+        prefix: u16,
+        kilt: String,
+    },
+    /// The checksum does not match the rest of the address, i.e. it has a typo
+    BadChecksum,
+    /// Not base58 or not the length of an address of a 32 byte public key
+    Malformed,
+}
+
+/// Decode an ss58 address and tell a KILT address from the same public key encoded for another
+/// network, i.e. the generic substrate prefix 42 of addresses starting with `5`
+pub fn analyze_address(address: &str) -> AddressAnalysis {
+    match AccountId32::from_ss58check_with_version(address) {
+        Ok((account, format)) if u16::from(format) == KILT_SS58_PREFIX => {
+            AddressAnalysis::Kilt(account)
+        }
+        Ok((account, format)) => AddressAnalysis::OtherNetwork {
+            prefix: format.into(),
+            kilt: kilt_address(&account),
+        },
+        Err(PublicError::InvalidChecksum) => AddressAnalysis::BadChecksum,
+        Err(_) => AddressAnalysis::Malformed,
+    }
+}
+
+/// The account of an allowed issuer given as DID, "did:kilt:4abc...", or as KILT address, "4abc...".
+/// Addresses of other networks than KILT are rejected.
+pub fn issuer_account(issuer: &str) -> Result<AccountId32, Error> {
+    let address = issuer.strip_prefix("did:kilt:").unwrap_or(issuer);
+    let invalid = |reason: &str| Error::InvalidIssuerEntry(format!("{}: {}", issuer, reason));
+    match analyze_address(address) {
+        AddressAnalysis::Kilt(account) => Ok(account),
+        AddressAnalysis::OtherNetwork { prefix, kilt } => Err(invalid(&format!(
+            "ss58 prefix {} instead of the KILT prefix {}, {}",
+            prefix,
+            KILT_SS58_PREFIX,
+            other_network_hint(&kilt)
+        ))),
+        AddressAnalysis::BadChecksum => Err(invalid(
+            "the checksum of the address does not match, it may have a typo",
+        )),
+        AddressAnalysis::Malformed => Err(invalid("not a DID or KILT address")),
+    }
+}
+
+/// Explains an address of another network, with the KILT address of the same public key
+pub fn other_network_hint(kilt: &str) -> String {
+    format!(
+        "this is the same public key encoded for another network; the KILT form is did:kilt:{}",
+        kilt
+    )
+}
+
+/// An allowed issuer as its canonical DID, it is validated when the issuers are loaded
+pub fn normalize_issuer(issuer: &str) -> Result<String, Error> {
+    Ok(format!(
+        "did:kilt:{}",
+        kilt_address(&issuer_account(issuer)?)
+    ))
+}
+
+/// The native token of a chain, to format balances for people
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub decimals: u32,
+    pub symbol: String,
+}
+
+/// The KILT token, for chains that do not tell their properties
+impl Default for Token {
+    fn default() -> Self {
+        Token {
+            decimals: 15,
+            symbol: "KILT".to_string(),
+        }
+    }
+}
+
+impl Token {
+    /// Read the token from the `system_properties` of a node.
+    /// Chains can list several tokens, the first one is the native token.
+    pub fn from_properties(properties: &serde_json::Map<String, serde_json::Value>) -> Self {
+        let first = |key: &str| match properties.get(key) {
+            Some(serde_json::Value::Array(values)) => values.first().cloned(),
+            value => value.cloned(),
+        };
+        let default = Token::default();
+        Token {
+            decimals: first("tokenDecimals")
+                .and_then(|decimals| decimals.as_u64())
+                .map_or(default.decimals, |decimals| decimals as u32),
+            symbol: first("tokenSymbol")
+                .and_then(|symbol| symbol.as_str().map(str::to_string))
+                .unwrap_or(default.symbol),
+        }
+    }
+
+    /// Format an amount of the smallest unit, i.e. "0.12095 KILT" for 120950000000000
+    pub fn format(&self, amount: u128) -> String {
+        let unit = 10u128.pow(self.decimals);
+        let fraction = format!("{:0width$}", amount % unit, width = self.decimals as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            format!("{} {}", amount / unit, self.symbol)
+        } else {
+            format!("{}.{} {}", amount / unit, fraction, self.symbol)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use base58::ToBase58;
 
     #[test]
-    fn test_get_did_parts() {
-        let did = "did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH#0x78579576fa15684e5d868c9e123d62d471f1a95d8f9fc8032179d3735069784d";
-        let did_parts = get_did_parts(did).unwrap();
-        assert_eq!(did_parts[0], "did");
-        assert_eq!(did_parts[1], "kilt");
-        assert_eq!(did_parts[2], "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH#0x78579576fa15684e5d868c9e123d62d471f1a95d8f9fc8032179d3735069784d");
+    fn test_issuer_account() {
+        let address = "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH";
+        let account = AccountId32::from_ss58check(address).unwrap();
+        let did = format!("did:kilt:{}", address);
+        assert_eq!(issuer_account(&did).unwrap(), account);
+        assert_eq!(issuer_account(address).unwrap(), account);
+        assert_eq!(normalize_issuer(address).unwrap(), did);
+
+        // the same account with the generic substrate prefix 42
+        let substrate = account.to_ss58check_with_version(Ss58AddressFormat::custom(42));
+        let res = issuer_account(&substrate);
+        let hint = format!("the KILT form is {}", did);
+        assert!(
+            matches!(&res, Err(Error::InvalidIssuerEntry(msg)) if msg.contains("ss58 prefix 42 instead of the KILT prefix 38") && msg.ends_with(&hint)),
+            "{:?}",
+            res
+        );
+        for issuer in [
+            "",
+            "did:kilt:",
+            "did:kilt:4abc",
+            "did:web:example.com",
+            &did[..did.len() - 1],
+        ] {
+            assert!(
+                matches!(issuer_account(issuer), Err(Error::InvalidIssuerEntry(_))),
+                "{}",
+                issuer
+            );
+        }
+    }
+
+    #[test]
+    fn test_token() {
+        let kilt = Token::default();
+        assert_eq!(kilt.format(120_950_000_000_000), "0.12095 KILT");
+        assert_eq!(kilt.format(2_000_000_000_000_000), "2 KILT");
+        assert_eq!(kilt.format(0), "0 KILT");
+        assert_eq!(kilt.format(1), "0.000000000000001 KILT");
+
+        let properties =
+            serde_json::json!({"ss58Format": 38, "tokenDecimals": 12, "tokenSymbol": "PILT"});
+        let token = Token::from_properties(properties.as_object().unwrap());
+        assert_eq!(token.format(1_500_000_000_000), "1.5 PILT");
+        let properties =
+            serde_json::json!({"tokenDecimals": [10, 12], "tokenSymbol": ["DOT", "X"]});
+        let token = Token::from_properties(properties.as_object().unwrap());
+        assert_eq!((token.decimals, token.symbol.as_str()), (10, "DOT"));
+        assert_eq!(Token::from_properties(&Default::default()), kilt);
+    }
+
+    #[test]
+    fn test_did_url() {
+        let did = "did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH";
+        let key = "0x78579576fa15684e5d868c9e123d62d471f1a95d8f9fc8032179d3735069784d";
+        let parsed = |query: Option<&str>, fragment: Option<&str>| DidUrl {
+            address: "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH".to_string(),
+            form: DidForm::Full,
+            query: query.map(str::to_string),
+            fragment: fragment.map(str::to_string),
+        };
+        // the url, how it is parsed and how it is written again
+        let valid = [
+            (did.to_string(), parsed(None, None), did.to_string()),
+            (
+                format!("{}#{}", did, key),
+                parsed(None, Some(key)),
+                format!("{}#{}", did, key),
+            ),
+            (
+                format!("{}?version=1#{}", did, key),
+                parsed(Some("version=1"), Some(key)),
+                format!("{}?version=1#{}", did, key),
+            ),
+            (
+                format!("{}?version=1", did),
+                parsed(Some("version=1"), None),
+                format!("{}?version=1", did),
+            ),
+            (
+                format!("{}%23{}", did, key),
+                parsed(None, Some(key)),
+                format!("{}#{}", did, key),
+            ),
+            (
+                format!("{}?version=1%23{}", did, key),
+                parsed(Some("version=1"), Some(key)),
+                format!("{}?version=1#{}", did, key),
+            ),
+            (
+                format!("{}#%30{}", did, &key[1..]),
+                parsed(None, Some(key)),
+                format!("{}#{}", did, key),
+            ),
+            (
+                format!("{}?#{}", did, key),
+                parsed(None, Some(key)),
+                format!("{}#{}", did, key),
+            ),
+            (
+                format!("{}#a%25b", did),
+                parsed(None, Some("a%b")),
+                format!("{}#a%25b", did),
+            ),
+        ];
+        for (url, expected, written) in valid {
+            let res = DidUrl::parse(&url);
+            assert_eq!(res.as_ref().ok(), Some(&expected), "{}: {:?}", url, res);
+            assert_eq!(expected.to_string(), written);
+            assert_eq!(written.parse::<DidUrl>().unwrap(), expected);
+        }
+        let key_uri = DidUrl::parse(&format!("{}?version=1#{}", did, key)).unwrap();
+        assert_eq!(key_uri.key_id().unwrap().0, hex_decode_h256(key).unwrap().0);
+
+        // a light DID names the same account as the full DID of its address
+        let address = "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH";
+        for (light, details) in [
+            (format!("did:kilt:light:00{}", address), None),
+            (format!("did:kilt:light:01{}:z1Ac9", address), Some("z1Ac9")),
+        ] {
+            let parsed = DidUrl::parse(&format!("{}#authentication", light)).unwrap();
+            assert!(parsed.is_light());
+            assert_eq!(parsed.address, address);
+            assert_eq!(parsed.did(), light);
+            assert_eq!(parsed.full_did(), did);
+            assert_eq!(
+                parsed.account_id().unwrap(),
+                get_did_account_id(did).unwrap()
+            );
+            assert!(
+                matches!(&parsed.form, DidForm::Light { details: d, .. } if d.as_deref() == details)
+            );
+        }
+
+        let invalid = [
+            (format!("{}#{}#0x12", did, key), "has more than one #"),
+            (format!("{}#{}%230x12", did, key), "has more than one #"),
+            (format!("{}%23{}%230x12", did, key), "has more than one #"),
+            (format!("{}#", did), "has an empty fragment"),
+            (format!("{}%23", did), "has an empty fragment"),
+            (format!("{}#0x1%2", did), "is not percent-encoded"),
+            (format!("{}#0x1%zz", did), "is not percent-encoded"),
+            (format!("{}#%ff", did), "is not percent-encoded"),
+        ];
+        for (url, reason) in invalid {
+            let res = DidUrl::parse(&url);
+            assert!(
+                matches!(&res, Err(Error::InvalidDidUrl(msg)) if msg.ends_with(reason)),
+                "{}: {:?}",
+                url,
+                res
+            );
+        }
+        for url in [
+            "",
+            "did:kilt:",
+            "did:web:example.com",
+            "did:kilt:4abc:extra",
+            "did:kilt:light:",
+            "did:kilt:light:00",
+            "did:kilt:light:xx4abc",
+            "did:kilt:light:004abc:z1:extra",
+            "kilt:4abc#0x12",
+        ] {
+            let res = DidUrl::parse(url);
+            assert!(matches!(res, Err(Error::InvalidDid)), "{}: {:?}", url, res);
+        }
+    }
+
+    #[test]
+    fn test_analyze_address() {
+        let address = "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH";
+        let account = AccountId32::from_ss58check(address).unwrap();
+        assert_eq!(
+            analyze_address(address),
+            AddressAnalysis::Kilt(account.clone())
+        );
+
+        // the generic substrate prefix, polkadot and a prefix of two bytes
+        for prefix in [42, 0, 1000] {
+            let other = account.to_ss58check_with_version(Ss58AddressFormat::custom(prefix));
+            assert_eq!(
+                analyze_address(&other),
+                AddressAnalysis::OtherNetwork {
+                    prefix,
+                    kilt: address.to_string()
+                },
+                "{}",
+                other
+            );
+        }
+        let substrate = account.to_ss58check_with_version(Ss58AddressFormat::custom(42));
+        assert!(substrate.starts_with('5'));
+
+        // a changed character breaks the checksum
+        let mut typo = address.to_string();
+        typo.replace_range(10..11, "X");
+        assert_eq!(analyze_address(&typo), AddressAnalysis::BadChecksum);
+        let last = address.len() - 1;
+        let mut typo = address.to_string();
+        typo.replace_range(last.., "J");
+        assert_eq!(analyze_address(&typo), AddressAnalysis::BadChecksum);
+
+        for malformed in [
+            "",
+            "4abc",
+            // not base58
+            "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a30I",
+            &address[..address.len() - 1],
+            &format!("{}1", address),
+            "did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH",
+        ] {
+            assert_eq!(
+                analyze_address(malformed),
+                AddressAnalysis::Malformed,
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn test_foreign_address() {
+        let address = "4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH";
+        let account = AccountId32::from_ss58check(address).unwrap();
+        let substrate = account.to_ss58check_with_version(Ss58AddressFormat::custom(42));
+        let res = get_did_account_id(&format!("did:kilt:{}#0x12", substrate));
+        assert!(
+            matches!(&res, Err(Error::ForeignAddress { prefix: 42, kilt, .. }) if kilt == address),
+            "{:?}",
+            res
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            format!(
+                "Invalid DID: {} has the ss58 prefix 42 instead of 38, this is the same public key encoded for another network; the KILT form is did:kilt:{}",
+                substrate, address
+            )
+        );
+        // a typo is no address at all
+        assert!(matches!(
+            get_did_account_id("did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mJ"),
+            Err(Error::InvalidDid)
+        ));
     }
 
     #[test]
@@ -118,6 +981,222 @@ mod test {
             )
             .0
         );
+
+        // the same key id in multibase
+        let base58 = format!("z{}", key_id.0.to_base58());
+        assert_eq!(decode_key_id(&base58).unwrap().0, key_id.0);
+        let base16 = format!("F{}", hex::encode_upper(key_id.0));
+        assert_eq!(decode_key_id(&base16).unwrap().0, key_id.0);
+        assert_eq!(decode_key_id(&base16.to_lowercase()).unwrap().0, key_id.0);
+
+        // short or non ascii fragments are errors instead of panics
+        for fragment in [
+            "0", "x", "é", "0é", "0x", "0x1234", "1234", "z", "z0OIl", "f",
+        ] {
+            let did = format!(
+                "did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH#{}",
+                fragment
+            );
+            assert!(
+                matches!(get_did_key_uri(&did), Err(Error::InvalidKeyId(_))),
+                "{}",
+                fragment
+            );
+        }
+        let res = decode_key_id("1234");
+        assert!(
+            matches!(&res, Err(Error::InvalidKeyId(msg)) if msg.ends_with(KEY_ID_ENCODINGS)),
+            "{:?}",
+            res
+        );
+        let res = decode_key_id("z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK");
+        assert!(
+            matches!(&res, Err(Error::InvalidKeyId(msg)) if msg.ends_with("is 34 bytes instead of 32")),
+            "{:?}",
+            res
+        );
+        assert!(matches!(
+            get_did_key_uri("did:kilt:4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH"),
+            Err(Error::InvalidDid)
+        ));
+    }
+
+    #[test]
+    fn test_parse_credential() {
+        assert!(parse_credential(b"").is_err());
+        assert!(parse_credential(b"{}").is_err());
+        assert!(parse_credential(&[0xff, 0xfe]).is_err());
+        let credential = parse_credential(include_bytes!("../presentation-1.json"));
+        assert!(credential.is_ok(), "{:?}", credential);
+    }
+
+    #[test]
+    fn test_parse_credential_lenient() {
+        let strict = include_str!("../presentation-1.json");
+        let credential = parse_credential(strict.as_bytes()).unwrap();
+        // a comment and a trailing comma, like in a templated test plan
+        let templated = strict.replacen('{', "{\n  // filled in by the test plan\n", 1);
+        let templated = format!("{},}}", templated.trim_end().trim_end_matches('}'));
+        assert!(parse_credential(templated.as_bytes()).is_err());
+        let lenient = parse_credential_lenient(templated.as_bytes()).unwrap();
+        assert_eq!(lenient.root_hash, credential.root_hash);
+        assert_eq!(lenient.claim.contents, credential.claim.contents);
+
+        let res = parse_credential_lenient(b"{\"claim\": {,}}");
+        assert!(
+            matches!(&res, Err(err @ Error::Json5(_)) if err.to_string() == "JSON5 error: expected identifier or string at line 1 column 12"),
+            "{:?}",
+            res
+        );
+        assert!(matches!(
+            parse_credential_lenient(b"{claim: 1}"),
+            Err(Error::Serde(_))
+        ));
+        assert!(parse_credential_lenient(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_byte_order_mark() {
+        let json = include_bytes!("../presentation-1.json");
+        let with_bom = [b"\xef\xbb\xbf".as_slice(), json].concat();
+        assert!(parse_credential(&with_bom).is_err());
+        assert_eq!(strip_wrapping(&with_bom), json);
+
+        let parse = ParseOptions::default();
+        let expected = parse.parse("a.json", json).unwrap().root_hash;
+        assert_eq!(
+            parse.parse("a.json", &with_bom).unwrap().root_hash,
+            expected
+        );
+        let lenient = ParseOptions {
+            lenient_json: true,
+            ..parse.clone()
+        };
+        assert_eq!(
+            lenient.parse("a.json", &with_bom).unwrap().root_hash,
+            expected
+        );
+        let yaml = [b"\xef\xbb\xbf".as_slice(), b"claim: 1"].concat();
+        assert!(matches!(parse.to_json("a.yaml", &yaml), Ok(json) if json["claim"] == 1));
+        // only at the start
+        let inner = [b" ".as_slice(), b"\xef\xbb\xbf", json].concat();
+        assert_eq!(strip_wrapping(&inner), inner);
+    }
+
+    #[test]
+    fn test_code_fences() {
+        let json = include_str!("../presentation-1.json");
+        let parse = ParseOptions::default();
+        let expected = parse.parse("a.json", json.as_bytes()).unwrap().root_hash;
+        for fenced in [
+            format!("```json\n{}\n```", json),
+            format!("```\n{}```\n", json),
+            format!("\n  ```JSON \r\n{}\n```  \n\n", json),
+            format!("\u{feff}```json\n{}\n```", json),
+        ] {
+            let res = parse
+                .parse("a.json", fenced.as_bytes())
+                .map(|cred| cred.root_hash);
+            assert_eq!(res.as_ref().ok(), Some(&expected), "{}: {:?}", fenced, res);
+        }
+        let yaml = "```yaml\nclaim: 1\n```\n";
+        assert!(matches!(parse.to_json("a.yaml", yaml.as_bytes()), Ok(json) if json["claim"] == 1));
+
+        // not a fence around the whole input
+        for kept in [
+            "```{}```",
+            "```json\n{}",
+            "{}\n```",
+            "````json\n{}\n````",
+            "``json\n{}\n``",
+        ] {
+            assert_eq!(strip_wrapping(kept.as_bytes()), kept.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_fences_and_encodings() {
+        let json = include_str!("../presentation-1.json");
+        let fenced = format!("```json\n{}\n```", json);
+        let parse = ParseOptions::default();
+        let expected = parse.parse("a.json", json.as_bytes()).unwrap().root_hash;
+
+        // base64url has no backticks, so a deep link is decoded as it is and its payload can be
+        // fenced like a pasted file
+        use base64::Engine;
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&fenced);
+        assert_eq!(strip_wrapping(payload.as_bytes()), payload.as_bytes());
+        let link = format!("kilt://verify?credential={}", payload);
+        let decoded = crate::uri::decode(&link).unwrap();
+        assert_eq!(parse.parse("uri", &decoded).unwrap().root_hash, expected);
+        // a fenced deep link is not a deep link
+        assert!(!crate::uri::is_uri(&format!("```\n{}\n```", link)));
+
+        // compressed input is decompressed before the fence is stripped
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        std::io::Write::write_all(&mut gzip, fenced.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(parse.parse("a.json.gz", &gzip).unwrap().root_hash, expected);
+    }
+
+    #[test]
+    fn test_hex_decode_h256() {
+        let hash =
+            hex_decode_h256("0xf69ce26ca50b5d5f38cd32a99d031cd52fff42f17b9afb32895ffba260fb616a")
+                .unwrap();
+        assert_eq!(hash.0[0], 0xf6);
+        assert_eq!(hash.0[31], 0x6a);
+
+        assert!(hex_decode_h256("0x12345678").is_err());
+    }
+
+    #[test]
+    fn test_read_seed() {
+        assert_eq!(*read_seed(Some("//Alice"), None).unwrap(), "//Alice");
+        assert!(read_seed(None, None).is_err());
+    }
+
+    #[test]
+    fn test_sr25519_pair() {
+        let pair = sr25519_pair("//Alice").unwrap();
+        assert_eq!(
+            pair.public().to_ss58check(),
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        assert!(sr25519_pair("not a seed").is_err());
+    }
+
+    #[test]
+    fn test_get_key_id() {
+        // the authentication key of this DID is the sr25519 key of its account
+        let account =
+            AccountId32::from_ss58check("4siDmerNEBREZJsFoLM95x6cxEho73bCWKEDAXrKdou4a3mH")
+                .unwrap();
+        let key = DidPublicKey::PublicVerificationKey(DidVerificationKey::Sr25519(
+            runtime_sp_core::sr25519::Public(account.into()),
+        ));
+        assert_eq!(
+            hex_encode(get_key_id(&key)),
+            "0x78579576fa15684e5d868c9e123d62d471f1a95d8f9fc8032179d3735069784d"
+        );
+    }
+
+    #[test]
+    fn test_did_key_pair() {
+        for key_type in [KeyType::Sr25519, KeyType::Ed25519] {
+            let pair = DidKeyPair::from_seed("//Alice", key_type).unwrap();
+            assert!(pair.did().starts_with("did:kilt:4"));
+            assert_eq!(
+                get_did_account_id(&pair.key_uri()).unwrap(),
+                get_did_account_id(&pair.did()).unwrap()
+            );
+            assert_eq!(
+                get_did_key_uri(&pair.key_uri()).unwrap().0,
+                get_key_id(&pair.public_key())
+            );
+            assert_eq!(pair.sign(b"message").len(), 64);
+        }
+        assert!(DidKeyPair::from_seed("not a seed", KeyType::Ed25519).is_err());
     }
 
     #[test]
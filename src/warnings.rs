@@ -0,0 +1,19 @@
+use crate::errors::Error;
+
+/// How warnings about the setup of a run are reported, printed or with `--deny-warnings` as the
+/// error the run fails with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Warnings {
+    pub deny: bool,
+}
+
+impl Warnings {
+    pub fn warn(&self, message: impl Into<String>) -> Result<(), Error> {
+        let message = message.into();
+        if self.deny {
+            return Err(Error::DeniedWarning(message));
+        }
+        eprintln!("Warning: {}", message);
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+use async_trait::async_trait;
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use subxt::{sp_core::H256, sp_runtime::AccountId32};
+
+use crate::{
+    backend::{AttestationHistory, ChainBackend},
+    errors::Error,
+    kilt::runtime_types::{
+        attestation::attestations::AttestationDetails,
+        did::{did_details::DidDetails, service_endpoints::DidEndpoint},
+    },
+    metrics,
+    utils::{cache_dir, issuer_account, kilt_address, Token},
+};
+
+/// How long a resolution is used by default. Names can be released and claimed by another DID,
+/// so this is minutes and not hours
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    /// The KILT address of the owner of a name or the name of an owner, `None` if there is none
+    value: Option<String>,
+    /// Unix time in seconds the value was read from chain
+    resolved_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Entries {
+    /// Owners by name
+    owners: BTreeMap<String, Entry>,
+    /// Names by owner address
+    names: BTreeMap<String, Entry>,
+}
+
+impl Entries {
+    // a name is owned by the address or by nobody, other owners that had it are stale
+    fn set_owner(&mut self, name: &str, owner: Option<String>, resolved_at: u64) {
+        self.names.retain(|address, entry| {
+            entry.value.as_deref() != Some(name) || Some(address) == owner.as_ref()
+        });
+        if let Some(owner) = &owner {
+            self.names.insert(
+                owner.clone(),
+                Entry {
+                    value: Some(name.to_string()),
+                    resolved_at,
+                },
+            );
+        }
+        self.owners.insert(
+            name.to_string(),
+            Entry {
+                value: owner,
+                resolved_at,
+            },
+        );
+    }
+
+    // the address owns the name or no name, the names it had before are stale
+    fn set_name(&mut self, owner: &str, name: Option<String>, resolved_at: u64) {
+        self.owners.retain(|other, entry| {
+            entry.value.as_deref() != Some(owner) || Some(other) == name.as_ref()
+        });
+        match &name {
+            Some(name) => self.set_owner(name, Some(owner.to_string()), resolved_at),
+            None => {
+                self.names.insert(
+                    owner.to_string(),
+                    Entry {
+                        value: None,
+                        resolved_at,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Web3name resolutions in both directions, name to owner and owner to name, used until they are
+/// older than the TTL. Resolutions at the latest block only, historical lookups are not cached.
+/// The cache can be kept in a file to be shared by the runs of a few minutes.
+pub struct Web3NameCache {
+    file: Option<PathBuf>,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    changed: AtomicBool,
+}
+
+impl Web3NameCache {
+    /// An empty cache that is not written anywhere
+    pub fn new(ttl: Duration) -> Self {
+        Web3NameCache {
+            file: None,
+            ttl,
+            entries: Mutex::default(),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// `w3n.json` in the cache directory, see `utils::cache_dir`
+    pub fn default_file() -> PathBuf {
+        cache_dir().join("w3n.json")
+    }
+
+    /// The cache of earlier runs in the file, it starts empty if the file is missing or unreadable
+    pub fn open(file: &Path, ttl: Duration) -> Self {
+        let entries = std::fs::read(file)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Web3NameCache {
+            file: Some(file.to_path_buf()),
+            entries: Mutex::new(entries),
+            ..Web3NameCache::new(ttl)
+        }
+    }
+
+    /// Write the cache back to its file if anything changed
+    pub fn save(&self) -> Result<(), Error> {
+        let file = match (&self.file, self.changed.load(Ordering::Relaxed)) {
+            (Some(file), true) => file,
+            _ => return Ok(()),
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let partial = file.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec_pretty(&*self.lock())?)?;
+        std::fs::rename(&partial, file)?;
+        self.changed.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Forget all resolutions
+    pub fn clear(&self) {
+        *self.lock() = Entries::default();
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Forget the owner of a name and the name of that owner, i.e. after it was released
+    pub fn invalidate(&self, name: &str) {
+        let mut entries = self.lock();
+        entries.owners.remove(name);
+        entries
+            .names
+            .retain(|_, entry| entry.value.as_deref() != Some(name));
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().expect("web3name cache is not poisoned")
+    }
+
+    // the value of a fresh entry, counted as hit or miss
+    fn cached(&self, entry: Option<&Entry>, now: u64) -> Option<Option<String>> {
+        let value = entry
+            .filter(|entry| now.saturating_sub(entry.resolved_at) < self.ttl.as_secs())
+            .map(|entry| entry.value.clone());
+        metrics::record_w3n_lookup(value.is_some());
+        value
+    }
+
+    /// The owner of a name, from the cache if it was resolved within the TTL before `now`
+    pub async fn owner(
+        &self,
+        backend: &dyn ChainBackend,
+        name: &str,
+        now: SystemTime,
+    ) -> Result<Option<AccountId32>, Error> {
+        let now = unix_time(now);
+        if let Some(owner) = self.cached(self.lock().owners.get(name), now) {
+            return owner.as_deref().map(issuer_account).transpose();
+        }
+        let owner = backend.web3_name_owner(name, None).await?;
+        self.lock()
+            .set_owner(name, owner.as_ref().map(kilt_address), now);
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(owner)
+    }
+
+    /// The name of an owner, from the cache if it was resolved within the TTL before `now`
+    pub async fn name(
+        &self,
+        backend: &dyn ChainBackend,
+        owner: &AccountId32,
+        now: SystemTime,
+    ) -> Result<Option<String>, Error> {
+        let now = unix_time(now);
+        let address = kilt_address(owner);
+        if let Some(name) = self.cached(self.lock().names.get(&address), now) {
+            return Ok(name);
+        }
+        let name = backend.web3_name(owner, None).await?;
+        self.lock().set_name(&address, name.clone(), now);
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(name)
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Resolves web3names at the latest block through the cache, everything else goes to `inner`
+pub struct Web3NameBackend<'a> {
+    inner: &'a dyn ChainBackend,
+    cache: &'a Web3NameCache,
+}
+
+impl<'a> Web3NameBackend<'a> {
+    pub fn new(inner: &'a dyn ChainBackend, cache: &'a Web3NameCache) -> Self {
+        Web3NameBackend { inner, cache }
+    }
+}
+
+#[async_trait]
+impl ChainBackend for Web3NameBackend<'_> {
+    async fn did(&self, did: &AccountId32, at: Option<H256>) -> Result<Option<DidDetails>, Error> {
+        self.inner.did(did, at).await
+    }
+
+    async fn attestation(
+        &self,
+        root_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AttestationDetails>, Error> {
+        self.inner.attestation(root_hash, at).await
+    }
+
+    async fn attestation_history(
+        &self,
+        root_hash: &H256,
+    ) -> Result<Option<AttestationHistory>, Error> {
+        self.inner.attestation_history(root_hash).await
+    }
+
+    async fn web3_name_owner(
+        &self,
+        name: &str,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        match at {
+            Some(_) => self.inner.web3_name_owner(name, at).await,
+            None => self.cache.owner(self.inner, name, SystemTime::now()).await,
+        }
+    }
+
+    async fn web3_name(
+        &self,
+        owner: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Option<String>, Error> {
+        match at {
+            Some(_) => self.inner.web3_name(owner, at).await,
+            None => self.cache.name(self.inner, owner, SystemTime::now()).await,
+        }
+    }
+
+    async fn ctype_creator(
+        &self,
+        ctype_hash: &H256,
+        at: Option<H256>,
+    ) -> Result<Option<AccountId32>, Error> {
+        self.inner.ctype_creator(ctype_hash, at).await
+    }
+
+    async fn service_endpoints(
+        &self,
+        did: &AccountId32,
+        at: Option<H256>,
+    ) -> Result<Vec<DidEndpoint>, Error> {
+        self.inner.service_endpoints(did, at).await
+    }
+
+    async fn token(&self) -> Result<Token, Error> {
+        self.inner.token().await
+    }
+}
+
+/// Work with the caches kept between runs
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[clap(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Forget cached resolutions, i.e. after a web3name was released
+    Clear(ClearArgs),
+}
+
+#[derive(Args, Debug)]
+struct ClearArgs {
+    /// Clear the web3name cache
+    #[clap(long, value_parser, required = true)]
+    w3n: bool,
+
+    /// Only forget the owner of this web3name
+    #[clap(long, value_parser, requires = "w3n")]
+    name: Option<String>,
+}
+
+pub fn run(cache: &Web3NameCache, args: &CacheArgs) -> Result<(), Error> {
+    match &args.command {
+        CacheCommand::Clear(clear_args) => {
+            match &clear_args.name {
+                Some(name) => cache.invalidate(name),
+                None => cache.clear(),
+            }
+            cache.save()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backend::CountingBackend, fixtures, utils::get_did_account_id};
+
+    #[tokio::test]
+    async fn test_owner_changes() {
+        let alice = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let bob = get_did_account_id(&fixtures::attester_did()).unwrap();
+        let mut backend = fixtures::backend();
+        backend
+            .latest_mut()
+            .insert_web3_name("alice", alice.clone());
+        let cache = Web3NameCache::new(DEFAULT_TTL);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let counting = CountingBackend::new(&backend);
+
+        assert_eq!(
+            cache.owner(&counting, "alice", start).await.unwrap(),
+            Some(alice.clone())
+        );
+        // the other direction is known from the first lookup
+        assert_eq!(
+            cache.name(&counting, &alice, start).await.unwrap(),
+            Some("alice".to_string())
+        );
+        assert_eq!(counting.calls(), 1);
+
+        // the name is released and claimed by bob, the cache answers with alice until the ttl is up
+        let mut backend = fixtures::backend();
+        backend.latest_mut().insert_web3_name("alice", bob.clone());
+        let counting = CountingBackend::new(&backend);
+        let later = start + DEFAULT_TTL - Duration::from_secs(1);
+        assert_eq!(
+            cache.owner(&counting, "alice", later).await.unwrap(),
+            Some(alice.clone())
+        );
+        assert_eq!(counting.calls(), 0);
+
+        let expired = start + DEFAULT_TTL;
+        assert_eq!(
+            cache.owner(&counting, "alice", expired).await.unwrap(),
+            Some(bob.clone())
+        );
+        // alice no longer owns the name, that is read again instead of taken from her stale entry
+        assert_eq!(
+            cache.name(&counting, &bob, expired).await.unwrap(),
+            Some("alice".to_string())
+        );
+        assert_eq!(cache.name(&counting, &alice, expired).await.unwrap(), None);
+        assert_eq!(counting.calls(), 2);
+
+        // invalidated names are read again right away
+        cache.invalidate("alice");
+        assert_eq!(
+            cache.owner(&counting, "alice", expired).await.unwrap(),
+            Some(bob)
+        );
+        assert_eq!(counting.calls(), 3);
+        cache.clear();
+        assert_eq!(cache.name(&counting, &alice, expired).await.unwrap(), None);
+        assert_eq!(counting.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_web3name_backend() {
+        let alice = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        let mut backend = fixtures::backend();
+        backend
+            .latest_mut()
+            .insert_web3_name("alice", alice.clone());
+        let counting = CountingBackend::new(&backend);
+        let file =
+            std::env::temp_dir().join(format!("kilt-verify-w3n-{}.json", std::process::id()));
+
+        let cache = Web3NameCache::open(&file, DEFAULT_TTL);
+        let names = Web3NameBackend::new(&counting, &cache);
+        for _ in 0..3 {
+            assert_eq!(
+                names.web3_name(&alice, None).await.unwrap(),
+                Some("alice".to_string())
+            );
+        }
+        // historical lookups go to the node, which does not know the block
+        let res = names.web3_name(&alice, Some(H256([1; 32]))).await;
+        assert!(matches!(res, Err(Error::BlockNotFound)), "{:?}", res);
+        assert_eq!(counting.calls(), 2);
+        cache.save().unwrap();
+
+        // the next run starts with the names of this one
+        let cache = Web3NameCache::open(&file, DEFAULT_TTL);
+        let names = Web3NameBackend::new(&counting, &cache);
+        assert_eq!(
+            names.web3_name_owner("alice", None).await.unwrap(),
+            Some(alice)
+        );
+        assert_eq!(counting.calls(), 2);
+        std::fs::remove_file(&file).unwrap();
+    }
+}
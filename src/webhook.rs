@@ -0,0 +1,166 @@
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::errors::Error;
+
+/// How long a webhook may take to answer
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A webhook URL split into the address to connect to and the request target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    /// Host with the port, i.e. "127.0.0.1:8080"
+    pub address: String,
+    pub host: String,
+    pub path: String,
+}
+
+impl Webhook {
+    /// Parse a plain `http://` URL, TLS is not supported so `https://` hooks need a local relay
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            Error::Webhook(format!(
+                "{} is not a http:// URL, https is not supported",
+                url
+            ))
+        })?;
+        let (host, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(Error::Webhook(format!("{} has no host", url)));
+        }
+        let address = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+        {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Webhook {
+            address,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// POST the json body and fail unless the hook answers with a 2xx status
+    pub async fn post(&self, body: &[u8]) -> Result<(), Error> {
+        tokio::time::timeout(TIMEOUT, self.send(body))
+            .await
+            .map_err(|_| Error::Webhook(format!("{} did not answer in time", self.host)))?
+    }
+
+    async fn send(&self, body: &[u8]) -> Result<(), Error> {
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        let mut request = head.into_bytes();
+        request.extend_from_slice(body);
+        let (status, _) = self.exchange(&request).await?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(Error::Webhook(format!(
+                "{} answered {:?}",
+                self.host, status
+            ))),
+        }
+    }
+
+    /// Send a request to the host and read the answer until the host closes the connection.
+    /// Returns the status line and the body as it was sent, so requests whose answer is read
+    /// should be HTTP/1.0 to not get a chunked body
+    pub(crate) async fn exchange(&self, request: &[u8]) -> std::io::Result<(String, Vec<u8>)> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        stream.write_all(request).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let (head, body) = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => (&response[..end], &response[end + 4..]),
+            None => (&response[..], &[][..]),
+        };
+        let status = String::from_utf8_lossy(head)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Ok((status, body.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse() {
+        let hook = Webhook::parse("http://example.com/hooks/kilt?x=1").unwrap();
+        assert_eq!(hook.address, "example.com:80");
+        assert_eq!(hook.path, "/hooks/kilt?x=1");
+        let hook = Webhook::parse("http://127.0.0.1:8080").unwrap();
+        assert_eq!(
+            (hook.address.as_str(), hook.path.as_str()),
+            ("127.0.0.1:8080", "/")
+        );
+        assert!(matches!(
+            Webhook::parse("https://example.com"),
+            Err(Error::Webhook(_))
+        ));
+        assert!(matches!(
+            Webhook::parse("http:///path"),
+            Err(Error::Webhook(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["204 No Content", "500 Internal Server Error"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 1024];
+                let len = stream.read(&mut request).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request[..len]).into_owned());
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+
+        let hook = Webhook::parse(&url).unwrap();
+        hook.post(b"{\"a\":1}").await.unwrap();
+        let res = hook.post(b"{}").await;
+        assert!(
+            matches!(&res, Err(Error::Webhook(msg)) if msg.contains("500 Internal Server Error")),
+            "{:?}",
+            res
+        );
+        let requests = server.await.unwrap();
+        assert!(
+            requests[0].starts_with("POST /hook HTTP/1.1\r\n"),
+            "{}",
+            requests[0]
+        );
+        assert!(
+            requests[0].contains("Content-Length: 7\r\n"),
+            "{}",
+            requests[0]
+        );
+        assert!(
+            requests[0].ends_with("\r\n\r\n{\"a\":1}"),
+            "{}",
+            requests[0]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use dialoguer::{console::Term, theme::ColorfulTheme, Confirm, Input, MultiSelect, Select};
+use std::io::Read;
+
+use crate::{
+    backend::ChainBackend,
+    credential::{reads_as_other_value, ClaimRequirements, Credential, REDACTED},
+    errors::Error,
+    kilt::NETWORKS,
+    utils::{
+        get_did_account_id, issuer_account, parse_credential, read_credential, strip_wrapping,
+        STDIN_GUIDANCE,
+    },
+};
+
+/// Exit code after Ctrl-C, the usual 128 + SIGINT
+pub const ABORT_EXIT_CODE: i32 = 130;
+
+/// The answers of the wizard. Every answer can also be given with a flag,
+/// so a run of the wizard can be repeated without it by `command_line`.
+#[derive(Debug, Clone)]
+pub struct WizardAnswers {
+    /// Credential file, or "stdin" if the credential was pasted
+    pub file: String,
+    /// The pasted credential
+    pub credential: Option<Credential>,
+    pub endpoint: String,
+    pub allowed_issuers: Vec<String>,
+    pub requirements: ClaimRequirements,
+}
+
+impl WizardAnswers {
+    /// The flags that give the same answers, a pasted credential has to be piped to stdin
+    pub fn command_line(&self) -> String {
+        let mut args = vec!["kilt-verify".to_string()];
+        if self.file != "stdin" {
+            args.push(format!("--file {}", quote(&self.file)));
+        }
+        args.push(format!("--endpoint {}", quote(&self.endpoint)));
+        for issuer in self.allowed_issuers.iter() {
+            args.push(format!("--issuer {}", quote(issuer)));
+        }
+        if let Some(owner) = &self.requirements.owner {
+            args.push(format!("--expected-owner {}", quote(owner)));
+        }
+        for property in self.requirements.properties.iter() {
+            args.push(format!("--require-property {}", quote(property)));
+        }
+        if self.requirements.full_did {
+            args.push("--require-full-did".to_string());
+        }
+        args.join(" ")
+    }
+}
+
+// quote a shell argument if it is not made of safe characters only
+fn quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:#@=+,".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Restore the terminal and exit when the wizard is interrupted with Ctrl-C at any prompt
+pub fn install_abort_handler() -> Result<(), Error> {
+    ctrlc::set_handler(|| {
+        let _ = Term::stderr().show_cursor();
+        eprintln!("\nAborted");
+        std::process::exit(ABORT_EXIT_CODE);
+    })
+    .map_err(|err| Error::Io(std::io::Error::other(err)))
+}
+
+// Esc or q at a selection aborts the wizard as well
+fn answered<T>(answer: Option<T>) -> Result<T, Error> {
+    answer.ok_or(Error::Aborted)
+}
+
+fn ask_credential(theme: &ColorfulTheme, defaults: &WizardAnswers) -> Result<WizardAnswers, Error> {
+    let choice = answered(
+        Select::with_theme(theme)
+            .with_prompt("How do you want to provide the credential?")
+            .items(["Choose a file", "Paste the credential"])
+            .default(0)
+            .interact_opt()?,
+    )?;
+    let mut answers = defaults.clone();
+    if choice == 0 {
+        let mut input = Input::<String>::with_theme(theme)
+            .with_prompt("Credential file")
+            .validate_with(|file: &String| read_credential(file).map(|_| ()));
+        if defaults.file != "stdin" {
+            input = input.default(defaults.file.clone());
+        }
+        answers.file = input.interact_text()?;
+        answers.credential = None;
+    } else {
+        loop {
+            eprintln!("{}", STDIN_GUIDANCE);
+            let mut pasted = String::new();
+            std::io::stdin().read_to_string(&mut pasted)?;
+            match parse_credential(strip_wrapping(pasted.as_bytes())) {
+                Ok(credential) => {
+                    answers.file = "stdin".to_string();
+                    answers.credential = Some(credential);
+                    break;
+                }
+                Err(err) => eprintln!("This is not a credential ({}), please try again", err),
+            }
+        }
+    }
+    Ok(answers)
+}
+
+fn ask_endpoint(theme: &ColorfulTheme, default: &str) -> Result<String, Error> {
+    let mut items: Vec<String> = NETWORKS
+        .iter()
+        .map(|network| format!("{} ({})", network.name, network.endpoint))
+        .collect();
+    items.push("Other endpoint".to_string());
+    let known = NETWORKS
+        .iter()
+        .position(|network| network.endpoint == default);
+    let choice = answered(
+        Select::with_theme(theme)
+            .with_prompt("Which network is the credential attested on?")
+            .items(&items)
+            .default(known.unwrap_or(NETWORKS.len()))
+            .interact_opt()?,
+    )?;
+    match NETWORKS.get(choice) {
+        Some(network) => Ok(network.endpoint.to_string()),
+        None => Ok(Input::<String>::with_theme(theme)
+            .with_prompt("Websocket endpoint")
+            .default(default.to_string())
+            .interact_text()?),
+    }
+}
+
+fn ask_issuers(theme: &ColorfulTheme, defaults: &[String]) -> Result<Vec<String>, Error> {
+    loop {
+        let selected = answered(
+            MultiSelect::with_theme(theme)
+                .with_prompt("Which issuers do you trust? (space to toggle, enter to confirm)")
+                .items(defaults)
+                .defaults(&vec![true; defaults.len()])
+                .interact_opt()?,
+        )?;
+        let mut issuers: Vec<String> = selected.into_iter().map(|i| defaults[i].clone()).collect();
+        while answered(
+            Confirm::with_theme(theme)
+                .with_prompt("Add another issuer DID?")
+                .default(false)
+                .interact_opt()?,
+        )? {
+            issuers.push(
+                Input::<String>::with_theme(theme)
+                    .with_prompt("Issuer DID or KILT address")
+                    .validate_with(|issuer: &String| issuer_account(issuer).map(|_| ()))
+                    .interact_text()?,
+            );
+        }
+        if !issuers.is_empty() {
+            return Ok(issuers);
+        }
+        eprintln!("At least one issuer must be trusted");
+    }
+}
+
+fn ask_requirements(
+    theme: &ColorfulTheme,
+    defaults: &ClaimRequirements,
+) -> Result<ClaimRequirements, Error> {
+    let owner = Input::<String>::with_theme(theme)
+        .with_prompt("Expected owner DID (empty for any owner)")
+        .default(defaults.owner.clone().unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(|did: &String| {
+            if did.is_empty() {
+                Ok(())
+            } else {
+                get_did_account_id(did).map(|_| ())
+            }
+        })
+        .interact_text()?;
+    let properties = Input::<String>::with_theme(theme)
+        .with_prompt("Required properties, comma separated (empty for none)")
+        .default(defaults.properties.join(","))
+        .allow_empty(true)
+        .interact_text()?;
+    Ok(ClaimRequirements {
+        owner: (!owner.is_empty()).then_some(owner),
+        properties: properties
+            .split(',')
+            .map(str::trim)
+            .filter(|property| !property.is_empty())
+            .map(str::to_string)
+            .collect(),
+        full_did: defaults.full_did,
+    })
+}
+
+/// Ask for everything a verification needs step by step, the defaults are taken from the flags
+pub fn run(defaults: &WizardAnswers) -> Result<WizardAnswers, Error> {
+    let theme = ColorfulTheme::default();
+    let mut answers = ask_credential(&theme, defaults)?;
+    answers.endpoint = ask_endpoint(&theme, &defaults.endpoint)?;
+    answers.allowed_issuers = ask_issuers(&theme, &defaults.allowed_issuers)?;
+    answers.requirements = ask_requirements(&theme, &defaults.requirements)?;
+    Ok(answers)
+}
+
+// a DID with its web3name if it has one
+async fn display_name(backend: &dyn ChainBackend, did: &str) -> String {
+    let name = match get_did_account_id(did) {
+        Ok(account) => backend.web3_name(&account, None).await.ok().flatten(),
+        Err(_) => None,
+    };
+    match name {
+        Some(name) => format!("w3n:{} ({})", name, did),
+        None => did.to_string(),
+    }
+}
+
+/// The result of a verification for people, with the attester and the disclosed claims.
+/// With `redact_pii` the claim values and the parts of errors that can quote them are left out
+pub async fn result_screen(
+    backend: &dyn ChainBackend,
+    credential: &Credential,
+    result: &Result<String, Error>,
+    redact_pii: bool,
+) -> String {
+    let attester = match result {
+        Ok(attester) => attester,
+        Err(err) => {
+            let redacted = err.redacted().filter(|_| redact_pii);
+            return format!(
+                "❌ The credential is NOT valid: {}\n",
+                redacted.as_ref().unwrap_or(err)
+            );
+        }
+    };
+    let mut screen = String::from("✅ The credential is valid\n\n");
+    screen += &format!("  Issued by: {}\n", display_name(backend, attester).await);
+    screen += &format!(
+        "  Owner:     {}\n",
+        display_name(backend, &credential.claim.owner).await
+    );
+    screen += "  Claims:\n";
+    if let Some(contents) = credential.claim.contents.as_object() {
+        let mut claims: Vec<_> = contents.iter().collect();
+        claims.sort_by_key(|(property, _)| *property);
+        for (property, value) in claims {
+            let value = match value {
+                _ if redact_pii => REDACTED.to_string(),
+                serde_json::Value::String(text) if !reads_as_other_value(text) => text.clone(),
+                value => value.to_string(),
+            };
+            screen += &format!("    {}: {}\n", property, value);
+        }
+    }
+    screen
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_command_line() {
+        let answers = WizardAnswers {
+            file: "my credential.json".to_string(),
+            credential: None,
+            endpoint: NETWORKS[0].endpoint.to_string(),
+            allowed_issuers: vec!["did:kilt:4abc".to_string()],
+            requirements: ClaimRequirements {
+                owner: Some("did:kilt:4owner".to_string()),
+                properties: vec!["Email".to_string(), "it's".to_string()],
+                full_did: false,
+            },
+        };
+        assert_eq!(
+            answers.command_line(),
+            "kilt-verify --file 'my credential.json' --endpoint wss://spiritnet.kilt.io:443 \
+             --issuer did:kilt:4abc --expected-owner did:kilt:4owner \
+             --require-property Email --require-property 'it'\\''s'"
+        );
+
+        let pasted = WizardAnswers {
+            file: "stdin".to_string(),
+            requirements: Default::default(),
+            ..answers
+        };
+        assert!(pasted.command_line().starts_with("kilt-verify --endpoint "));
+    }
+
+    #[tokio::test]
+    async fn test_result_screen() {
+        let mut backend = fixtures::backend();
+        let owner = get_did_account_id(&fixtures::owner_key().did()).unwrap();
+        backend.latest_mut().insert_web3_name("alice", owner);
+        let credential = fixtures::credential();
+        let attester = fixtures::attester_did();
+
+        let screen = result_screen(&backend, &credential, &Ok(attester.clone()), false).await;
+        assert!(screen.starts_with("✅"), "{}", screen);
+        assert!(
+            screen.contains(&format!("Issued by: {}\n", attester)),
+            "{}",
+            screen
+        );
+        assert!(
+            screen.contains("Owner:     w3n:alice (did:kilt:"),
+            "{}",
+            screen
+        );
+        assert!(
+            screen.ends_with("    Email: alice@example.com\n    Name: Alice\n"),
+            "{}",
+            screen
+        );
+
+        let screen = result_screen(&backend, &credential, &Ok(attester.clone()), true).await;
+        assert!(
+            screen.ends_with("    Email: [redacted]\n    Name: [redacted]\n"),
+            "{}",
+            screen
+        );
+
+        let screen = result_screen(
+            &backend,
+            &credential,
+            &Err(Error::AttestationRevoked),
+            false,
+        )
+        .await;
+        assert_eq!(
+            screen,
+            "❌ The credential is NOT valid: Attestation revoked\n"
+        );
+        let err = Error::InvalidUri("kilt://verify?credential=alice".to_string());
+        let screen = result_screen(&backend, &credential, &Err(err), true).await;
+        assert!(!screen.contains("alice"), "{}", screen);
+    }
+}
@@ -0,0 +1,255 @@
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Value};
+use serde_yaml::{Mapping, Value as Yaml};
+use std::fmt;
+
+use crate::errors::Error;
+
+/// Turn a YAML document into json. YAML types plain scalars by how they look, so a plain scalar is
+/// only a number, bool or null if json reads its text the same way. Scalars like `0x1f`, `1e3`,
+/// `~` or `True` are refused, they have to be quoted to be strings or written as json would.
+pub fn to_json(text: &str) -> Result<Value, Error> {
+    // the typed document tells the shape, reading it again as strings gives the text of scalars
+    let typed: Yaml = serde_yaml::from_str(text)?;
+    let guided = Guided {
+        typed: &typed,
+        path: "$".to_string(),
+    };
+    Ok(guided.deserialize(serde_yaml::Deserializer::from_str(text))?)
+}
+
+// the json of a typed scalar with the text it was written as, `None` if json reads the text
+// differently
+fn scalar(typed: &Yaml, text: &str) -> Option<Value> {
+    let json = match typed {
+        Yaml::String(string) => return Some(Value::String(string.clone())),
+        Yaml::Null => Value::Null,
+        Yaml::Bool(boolean) => Value::Bool(*boolean),
+        Yaml::Number(number) => serde_json::to_value(number).ok()?,
+        Yaml::Sequence(_) | Yaml::Mapping(_) | Yaml::Tagged(_) => return None,
+    };
+    let rendered = json.to_string();
+    (rendered == text).then_some(json)
+}
+
+fn ambiguous<E: de::Error>(text: &str, path: &str) -> E {
+    E::custom(format!(
+        "ambiguous scalar `{}` at {}, quote it if it is a string or write it as in json",
+        text, path
+    ))
+}
+
+struct Guided<'a> {
+    typed: &'a Yaml,
+    path: String,
+}
+
+impl<'de> DeserializeSeed<'de> for Guided<'_> {
+    type Value = Value;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        match self.typed {
+            Yaml::Mapping(mapping) => deserializer.deserialize_map(MapVisitor {
+                mapping,
+                path: self.path,
+            }),
+            Yaml::Sequence(sequence) => deserializer.deserialize_seq(SeqVisitor {
+                sequence,
+                path: self.path,
+            }),
+            Yaml::Tagged(tagged) => Err(de::Error::custom(format!(
+                "unsupported tag {} at {}",
+                tagged.tag, self.path
+            ))),
+            typed => {
+                let text = deserializer.deserialize_str(TextVisitor)?;
+                scalar(typed, &text).ok_or_else(|| ambiguous(&text, &self.path))
+            }
+        }
+    }
+}
+
+// the text of a scalar as it was written, without the quotes
+struct TextVisitor;
+
+impl<'de> Visitor<'de> for TextVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a scalar")
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<String, E> {
+        Ok(text.to_string())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for TextVisitor {
+    type Value = String;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+struct MapVisitor<'a> {
+    mapping: &'a Mapping,
+    path: String,
+}
+
+impl<'de> Visitor<'de> for MapVisitor<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a mapping")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let mut members = Map::new();
+        // the entries come in the same order as in the typed mapping
+        for (key, typed) in self.mapping {
+            let text = access
+                .next_key_seed(TextVisitor)?
+                .ok_or_else(|| de::Error::custom("the mapping changed while reading it"))?;
+            let name = match key {
+                Yaml::String(name) => name.clone(),
+                key => match scalar(key, &text) {
+                    Some(_) => text,
+                    None => return Err(ambiguous(&text, &self.path)),
+                },
+            };
+            let path = format!("{}.{}", self.path, name);
+            let value = access.next_value_seed(Guided { typed, path })?;
+            members.insert(name, value);
+        }
+        if access.next_key::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::custom("the mapping changed while reading it"));
+        }
+        Ok(Value::Object(members))
+    }
+}
+
+struct SeqVisitor<'a> {
+    sequence: &'a [Yaml],
+    path: String,
+}
+
+impl<'de> Visitor<'de> for SeqVisitor<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let mut values = Vec::new();
+        for (index, typed) in self.sequence.iter().enumerate() {
+            let path = format!("{}[{}]", self.path, index);
+            let value = access
+                .next_element_seed(Guided { typed, path })?
+                .ok_or_else(|| de::Error::custom("the sequence changed while reading it"))?;
+            values.push(value);
+        }
+        if access.next_element::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::custom("the sequence changed while reading it"));
+        }
+        Ok(Value::Array(values))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures,
+        utils::{parse_credential, InputFormat, ParseOptions},
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_to_json() {
+        let yaml = "
+name: Alice
+quoted: '010'
+zip: 01234
+answer: yes
+age: 42
+score: -1.5
+verified: true
+nothing: null
+tags: [a, 'b', 3]
+nested: {a: {b: c}}
+";
+        assert_eq!(
+            to_json(yaml).unwrap(),
+            json!({
+                "name": "Alice",
+                "quoted": "010",
+                "zip": "01234",
+                "answer": "yes",
+                "age": 42,
+                "score": -1.5,
+                "verified": true,
+                "nothing": null,
+                "tags": ["a", "b", 3],
+                "nested": {"a": {"b": "c"}},
+            })
+        );
+
+        for (yaml, text, path) in [
+            ("a: {b: 0x1f}", "0x1f", "$.a.b"),
+            ("a: [1, 1e3]", "1e3", "$.a[1]"),
+            ("a: ~", "~", "$.a"),
+            ("a: True", "True", "$.a"),
+            ("a:", "", "$.a"),
+            ("a: 1.50", "1.50", "$.a"),
+            ("0x10: a", "0x10", "$"),
+        ] {
+            let res = to_json(yaml);
+            assert!(
+                matches!(&res, Err(err) if err.to_string().contains(&format!("ambiguous scalar `{}` at {},", text, path))),
+                "{}: {:?}",
+                yaml,
+                res
+            );
+        }
+        assert!(matches!(to_json("a: !custom b"), Err(Error::Yaml(_))));
+        assert!(matches!(to_json("a: [b"), Err(Error::Yaml(_))));
+        assert!(matches!(to_json("a: 1\n---\nb: 2"), Err(Error::Yaml(_))));
+    }
+
+    #[tokio::test]
+    async fn test_yaml_credential() {
+        let backend = fixtures::backend();
+        let attester = fixtures::attester_did();
+        let cred = fixtures::credential();
+        let json = serde_json::to_vec(&cred).unwrap();
+        let yaml = serde_yaml::to_string(&cred).unwrap();
+        let from_json = parse_credential(&json).unwrap();
+        let yaml_options = ParseOptions {
+            format: Some(InputFormat::Yaml),
+            ..Default::default()
+        };
+        let from_yaml = yaml_options.parse("stdin", yaml.as_bytes()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_yaml).unwrap(),
+            serde_json::to_value(&from_json).unwrap()
+        );
+        let res = from_json.verify(&backend, &[&attester], None).await;
+        assert_eq!(
+            format!("{:?}", res),
+            format!("{:?}", from_yaml.verify(&backend, &[&attester], None).await)
+        );
+        assert!(res.is_ok(), "{:?}", res);
+
+        // and back, a credential that fails fails the same way
+        let mut tampered = serde_json::to_value(&cred).unwrap();
+        tampered["claim"]["contents"]["Email"] = json!("mallory@example.com");
+        let yaml = serde_yaml::to_string(&tampered).unwrap();
+        let from_yaml = ParseOptions::default()
+            .parse("cred.yaml", yaml.as_bytes())
+            .unwrap();
+        let res = from_yaml.verify(&backend, &[&attester], None).await;
+        assert!(matches!(res, Err(Error::InvalidClaimContents)), "{:?}", res);
+    }
+}
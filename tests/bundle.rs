@@ -0,0 +1,38 @@
+// Proof bundles are only written after a verification against a node, these runs are refused
+// before connecting or check bundles offline.
+
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CREDENTIAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+
+fn run(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap()
+}
+
+#[test]
+fn test_export_proof_bundle_usage() {
+    let output = run(&["--file", CREDENTIAL, "--export-proof-bundle", "bundle.json"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(".tar.gz or .tgz"), "{}", stderr);
+
+    // the bundle proves the state of one block, not the confirmations or an earlier key
+    for flag in ["--min-confirmations", "--key-grace-blocks"] {
+        let args = ["--export-proof-bundle", "bundle.tar.gz", flag, "3"];
+        let output = run(&[&["--file", CREDENTIAL], &args[..]].concat());
+        assert_eq!(output.status.code(), Some(2), "{}", flag);
+    }
+}
+
+#[test]
+fn test_audit_bundle_offline() {
+    let output = run(&["audit-bundle", "missing.tar.gz"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("InvalidBundle"), "{}", stderr);
+
+    // a credential is no bundle
+    let output = run(&["audit-bundle", CREDENTIAL]);
+    assert!(!output.status.success());
+}
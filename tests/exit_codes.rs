@@ -0,0 +1,148 @@
+// Exit codes of batches: all valid, some invalid and runs the node broke.
+//
+// Nothing here needs a node, an unreachable endpoint stands in for a node that is down.
+
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use kilt_verify::{
+    batch::{BatchOptions, BatchReport, BatchResult, MaxFailures, OutputFormat, REPORT_VERSION},
+    credential::ClaimRequirements,
+    ctypes::CtypeNames,
+    errors::{Error, INFRASTRUCTURE_EXIT_CODE, INVALID_EXIT_CODE},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const DIR: &str = env!("CARGO_MANIFEST_DIR");
+
+// the exit code of a batch with these results, 0 if it succeeds
+fn exit_code(results: Vec<Result<(), Error>>, max_failures: &str) -> u8 {
+    let requirements = ClaimRequirements::default();
+    let ctype_names = CtypeNames::builtin();
+    let options = BatchOptions {
+        allowed_issuers: &[],
+        challenge: None,
+        requirements: &requirements,
+        concurrency: 1,
+        output: OutputFormat::Json,
+        quiet: true,
+        summary_only: false,
+        sort_by: None,
+        include_services: false,
+        redact_claims: false,
+        redact_pii: false,
+        parse: Default::default(),
+        require_same_owner: false,
+        require_same_key: false,
+        cancel: CancellationToken::new(),
+        ctype_names: &ctype_names,
+        report_version: REPORT_VERSION,
+    };
+    let results = results
+        .into_iter()
+        .enumerate()
+        .map(|(i, result)| BatchResult {
+            result,
+            ..BatchResult::failed(&i.to_string(), Error::Timeout)
+        })
+        .collect();
+    let report = BatchReport::new(results, 0, &options);
+    match report.into_result(max_failures.parse::<MaxFailures>().unwrap()) {
+        Ok(()) => 0,
+        Err(err) => err.exit_code(),
+    }
+}
+
+#[test]
+fn test_batch_outcomes() {
+    let valid = || Ok(());
+    let invalid = || Err(Error::InvalidSignature);
+    let broken = || Err(Error::BlockNotFound);
+
+    assert_eq!(exit_code(vec![valid(), valid()], "0"), 0);
+    assert_eq!(exit_code(vec![valid(), invalid()], "0"), INVALID_EXIT_CODE);
+    assert_eq!(
+        exit_code(vec![invalid(), invalid()], "0"),
+        INVALID_EXIT_CODE
+    );
+    assert_eq!(
+        exit_code(vec![valid(), broken()], "0"),
+        INFRASTRUCTURE_EXIT_CODE
+    );
+
+    // failures up to the threshold still succeed, infrastructure errors never do
+    let batch = || vec![valid(), valid(), valid(), invalid()];
+    assert_eq!(exit_code(batch(), "1"), 0);
+    assert_eq!(exit_code(batch(), "25%"), 0);
+    assert_eq!(exit_code(batch(), "24.9%"), INVALID_EXIT_CODE);
+    assert_eq!(
+        exit_code(vec![valid(), broken()], "100%"),
+        INFRASTRUCTURE_EXIT_CODE
+    );
+}
+
+#[test]
+fn test_incomplete_run() {
+    let dir = std::env::temp_dir().join(format!("kilt-verify-exit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["presentation-1.json", "presentation-2.json"] {
+        std::fs::copy(format!("{}/{}", DIR, name), dir.join(name)).unwrap();
+    }
+    let manifest = dir.join("manifest.json");
+    std::fs::write(
+        &manifest,
+        serde_json::json!([
+            {"path": "presentation-1.json", "endpoint": "ws://127.0.0.1:1"},
+            {"path": "presentation-2.json", "endpoint": "ws://127.0.0.1:1"},
+        ])
+        .to_string(),
+    )
+    .unwrap();
+
+    let output = Command::new(BIN)
+        .args([
+            "--manifest",
+            manifest.to_str().unwrap(),
+            "--max-failures",
+            "100%",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(INFRASTRUCTURE_EXIT_CODE as i32));
+    assert!(
+        stdout.contains("2 credentials: 0 valid, 2 invalid"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("2 connection_error"), "{}", stdout);
+    assert!(stdout.contains("the run is incomplete"), "{}", stdout);
+
+    // a run that cannot connect at all says nothing about the credentials either
+    let output = Command::new(BIN)
+        .args([
+            "--file",
+            dir.to_str().unwrap(),
+            "--endpoint",
+            "ws://127.0.0.1:1",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(INFRASTRUCTURE_EXIT_CODE as i32));
+
+    let output = Command::new(BIN)
+        .args(["--file", dir.to_str().unwrap(), "--max-failures", "120%"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    // only the versions of the report that can still be written
+    for version in ["0", &(REPORT_VERSION + 1).to_string()] {
+        let output = Command::new(BIN)
+            .args(["--file", dir.to_str().unwrap(), "--report-version", version])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", version);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,95 @@
+// Several credential files named on the command line, verified one after another.
+//
+// Nothing listens on port 1, the runs that are not `--dry-run` fail to connect.
+
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CREDENTIAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+
+fn run(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_multiple_files() {
+    let output = run(&["--dry-run", CREDENTIAL, CREDENTIAL]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let plans = stdout(&output);
+    assert_eq!(plans.matches(&format!("{}:\n", CREDENTIAL)).count(), 2);
+    assert_eq!(plans.matches("Verification plan").count(), 2);
+
+    // a single positional file is the same as --file
+    let output = run(&["--dry-run", CREDENTIAL]);
+    assert!(output.status.success());
+    assert!(!stdout(&output).contains(&format!("{}:\n", CREDENTIAL)));
+    assert_eq!(
+        stdout(&output),
+        stdout(&run(&["--dry-run", "-f", CREDENTIAL]))
+    );
+
+    // a file that fails does not stop the others
+    let output = run(&["--dry-run", "missing.json", CREDENTIAL]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(
+        stderr(&output).contains("Error: missing.json: Io("),
+        "{}",
+        stderr(&output)
+    );
+    assert!(stdout(&output).contains("Verification plan"));
+
+    // a node that is down fails the run like a batch
+    let output = run(&["--endpoint", "ws://127.0.0.1:1", CREDENTIAL, "missing.json"]);
+    assert_eq!(output.status.code(), Some(5));
+    let errors = stderr(&output);
+    assert!(
+        errors.contains(&format!("Error: {}: ", CREDENTIAL)),
+        "{}",
+        errors
+    );
+    assert!(errors.contains("Error: missing.json: "), "{}", errors);
+}
+
+#[test]
+fn test_files_conflict_with_file() {
+    let output = run(&["--dry-run", "--file", CREDENTIAL, CREDENTIAL]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("cannot be used with"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_session_usage() {
+    // a session is bound to a challenge and is not planned
+    for args in [
+        vec!["--session", CREDENTIAL, CREDENTIAL],
+        vec!["--session", "-c", "0x1234", "--dry-run", CREDENTIAL],
+    ] {
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+    }
+
+    // all presentations are read before connecting
+    let args = [
+        "--endpoint",
+        "ws://127.0.0.1:1",
+        "--session",
+        "-c",
+        "0x1234",
+    ];
+    let output = run(&[&args[..], &[CREDENTIAL, "missing.json"]].concat());
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let output = run(&[&args[..], &[CREDENTIAL, CREDENTIAL]].concat());
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+}
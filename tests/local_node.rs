@@ -0,0 +1,283 @@
+// Smoke test of the real storage encodings against a disposable local KILT node.
+//
+// Start a dev node of the spiritnet runtime the metadata was taken from, i.e.
+// `docker run -p 9944:9944 kiltprotocol/kilt-node:<version> --dev --ws-external`,
+// and run the test with
+// `KILT_NODE_WS=ws://127.0.0.1:9944 cargo test --test local_node -- --ignored`.
+//
+// //Alice pays all fees and deposits, the DIDs are created from fresh keys on every run
+// and the deposits of the attestation and the DIDs are freed again at the end.
+
+use codec::Encode;
+use serde_json::json;
+use subxt::{
+    sp_core::{hashing::blake2_256, sr25519, Pair, H256},
+    sp_runtime::AccountId32,
+};
+
+use kilt_verify::{
+    audit,
+    bundle::{self, BundleReport},
+    credential::{Claim, Credential},
+    errors::Error,
+    history::{self, BlockHistory},
+    kilt::{
+        connect,
+        runtime_types::{
+            attestation::pallet::Call as AttestationCall,
+            ctype::pallet::Call as CtypeCall,
+            did::{
+                did_details::{DidCreationDetails, DidSignature, DidVerificationKey},
+                pallet::Call as DidCall,
+            },
+            frame_support::storage::bounded_btree_set::BoundedBTreeSet,
+            sp_core as runtime_sp_core,
+            spiritnet_runtime::Call,
+        },
+        submit_did_call, KiltRuntimeApi, KiltSigner,
+    },
+    submission::find_submission,
+    utils::{get_did_account_id, hex_decode_h256, hex_encode, sr25519_pair, DidKeyPair},
+};
+
+const ENDPOINT_VAR: &str = "KILT_NODE_WS";
+
+// connect to the node given in the env var, or explain how to run the test
+async fn local_node() -> Option<KiltRuntimeApi> {
+    match std::env::var(ENDPOINT_VAR) {
+        Ok(endpoint) => Some(
+            connect(endpoint.as_str())
+                .await
+                .unwrap_or_else(|err| panic!("Failed to connect to {}: {:?}", endpoint, err)),
+        ),
+        Err(_) => {
+            eprintln!(
+                "skipping: {} is not set, start a local dev node and set it to its websocket endpoint, i.e. ws://127.0.0.1:9944",
+                ENDPOINT_VAR
+            );
+            None
+        }
+    }
+}
+
+// create a full DID for the key, optionally with the same key as attestation key
+async fn create_did(
+    cli: &KiltRuntimeApi,
+    payer: &KiltSigner,
+    key: &sr25519::Pair,
+    attester: bool,
+) -> AccountId32 {
+    let did = AccountId32::from(key.public().0);
+    let details = DidCreationDetails {
+        did: did.clone(),
+        submitter: payer.account_id().clone(),
+        new_key_agreement_keys: BoundedBTreeSet(vec![]),
+        new_attestation_key: attester
+            .then(|| DidVerificationKey::Sr25519(runtime_sp_core::sr25519::Public(key.public().0))),
+        new_delegation_key: None,
+        new_service_details: vec![],
+    };
+    let signature = DidSignature::Sr25519(runtime_sp_core::sr25519::Signature(
+        key.sign(&details.encode()).0,
+    ));
+    cli.tx()
+        .did()
+        .create(details, signature)
+        .unwrap()
+        .sign_and_submit_then_watch_default(payer)
+        .await
+        .unwrap()
+        .wait_for_in_block()
+        .await
+        .unwrap()
+        .wait_for_success()
+        .await
+        .expect("Failed to create DID");
+    did
+}
+
+async fn submit(
+    cli: &KiltRuntimeApi,
+    did: &AccountId32,
+    key: &sr25519::Pair,
+    payer: &KiltSigner,
+    call: Call,
+) {
+    submit_did_call(cli, did, key, payer, call, false)
+        .await
+        .expect("Failed to submit DID call");
+}
+
+#[tokio::test]
+#[ignore = "requires a local dev node in KILT_NODE_WS"]
+async fn test_attest_verify_revoke() {
+    let Some(cli) = local_node().await else {
+        return;
+    };
+    let payer = KiltSigner::new(sr25519_pair("//Alice").unwrap());
+    let (attester_key, _) = sr25519::Pair::generate();
+    let (claimer_key, _) = sr25519::Pair::generate();
+    let attester = DidKeyPair::Sr25519(attester_key.clone());
+    let claimer = DidKeyPair::Sr25519(claimer_key.clone());
+
+    let attester_did = create_did(&cli, &payer, &attester_key, true).await;
+    let claimer_did = create_did(&cli, &payer, &claimer_key, false).await;
+    assert_eq!(attester_did, get_did_account_id(&attester.did()).unwrap());
+
+    // The title contains the attester so every run creates a new ctype
+    let ctype = json!({
+        "$schema": "http://kilt-protocol.org/draft-01/ctype#",
+        "title": format!("Email {}", attester.did()),
+        "properties": {"Email": {"type": "string"}},
+        "type": "object",
+    })
+    .to_string()
+    .into_bytes();
+    let ctype_hash = H256(blake2_256(&ctype));
+    submit(
+        &cli,
+        &attester_did,
+        &attester_key,
+        &payer,
+        Call::Ctype(CtypeCall::add { ctype }),
+    )
+    .await;
+
+    let claim = Claim {
+        ctype_hash: hex_encode(ctype_hash),
+        contents: json!({"Email": "claimer@example.com"}),
+        owner: claimer.did(),
+        ..Default::default()
+    };
+    let credential = Credential::create(claim, &claimer, &mut rand::thread_rng()).unwrap();
+    let root_hash = hex_decode_h256(&credential.root_hash).unwrap();
+    submit(
+        &cli,
+        &attester_did,
+        &attester_key,
+        &payer,
+        Call::Attestation(AttestationCall::add {
+            claim_hash: root_hash,
+            ctype_hash,
+            delegation_id: None,
+        }),
+    )
+    .await;
+
+    let allowed_issuers = [attester.did()];
+    let allowed_issuers: Vec<&str> = allowed_issuers.iter().map(String::as_str).collect();
+    credential
+        .verify(&cli, &allowed_issuers, None)
+        .await
+        .expect("Failed to verify the attested credential");
+
+    let presentation = credential
+        .create_presentation(&[], "0x1234", &claimer)
+        .unwrap();
+    presentation
+        .verify(&cli, &allowed_issuers, Some("0x1234"))
+        .await
+        .expect("Failed to verify the presentation");
+
+    // The attestation was just created, it is below a few blocks at most
+    let best = cli.best_block().await.unwrap();
+    history::check_confirmations(&cli, &cli, &root_hash, Some(best), 0)
+        .await
+        .unwrap();
+    let res = history::check_confirmations(&cli, &cli, &root_hash, Some(best), 1_000).await;
+    assert!(
+        matches!(res, Err(Error::AttestationTooRecent { .. })),
+        "{:?}",
+        res
+    );
+    let submission = find_submission(&cli, &cli, &credential.root_hash)
+        .await
+        .unwrap()
+        .expect("The extrinsic of the attestation is not found");
+    let alice = AccountId32::from(sr25519_pair("//Alice").unwrap().public());
+    assert_eq!(submission.submitter, Some(alice));
+    assert!(submission.fee.is_some_and(|fee| fee > 0));
+
+    // The proofs of a real node check out against its header
+    let block = cli
+        .client
+        .rpc()
+        .block_hash(Some(best.into()))
+        .await
+        .unwrap()
+        .unwrap();
+    let policy_hash = audit::policy_hash(
+        &allowed_issuers,
+        None,
+        &Default::default(),
+        &Default::default(),
+    );
+    let report = BundleReport::new(&credential, "local", &policy_hash, &Ok(()));
+    let path = std::env::temp_dir()
+        .join(format!(
+            "kilt-verify-local-node-{}.tar.gz",
+            std::process::id()
+        ))
+        .display()
+        .to_string();
+    let requirements = Default::default();
+    bundle::export(
+        &cli,
+        &credential,
+        block,
+        &allowed_issuers,
+        None,
+        &requirements,
+        report,
+        &path,
+    )
+    .await
+    .unwrap();
+    let checked = bundle::audit(&path).await.unwrap();
+    assert_eq!((checked.block_number, checked.error), (best, None));
+    std::fs::remove_file(&path).unwrap();
+
+    submit(
+        &cli,
+        &attester_did,
+        &attester_key,
+        &payer,
+        Call::Attestation(AttestationCall::revoke {
+            claim_hash: root_hash,
+            max_parent_checks: 0,
+        }),
+    )
+    .await;
+    let res = credential.verify(&cli, &allowed_issuers, None).await;
+    assert!(matches!(res, Err(Error::AttestationRevoked)), "{:?}", res);
+
+    // Free the deposits again, the ctype fee is not refundable
+    submit(
+        &cli,
+        &attester_did,
+        &attester_key,
+        &payer,
+        Call::Attestation(AttestationCall::remove {
+            claim_hash: root_hash,
+            max_parent_checks: 0,
+        }),
+    )
+    .await;
+    let res = credential.verify(&cli, &allowed_issuers, None).await;
+    assert!(matches!(res, Err(Error::AttestationNotFound)), "{:?}", res);
+
+    for (did, key) in [(&attester_did, &attester_key), (&claimer_did, &claimer_key)] {
+        submit(
+            &cli,
+            did,
+            key,
+            &payer,
+            Call::Did(DidCall::delete {
+                endpoints_to_remove: 0,
+            }),
+        )
+        .await;
+    }
+    let res = credential.verify(&cli, &allowed_issuers, None).await;
+    assert!(matches!(res, Err(Error::DidNotFound)), "{:?}", res);
+}
@@ -0,0 +1,117 @@
+// Choosing the network and the endpoints, checked with `--dry-run` or refused before connecting
+// so nothing is sent to a node.
+
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CREDENTIAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+
+fn dry_run(args: &[&str]) -> Output {
+    Command::new(BIN)
+        .args(["--dry-run", "--file", CREDENTIAL])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_network_defaults() {
+    let output = dry_run(&["--network", "peregrine"]);
+    assert!(output.status.success());
+    let plan = String::from_utf8_lossy(&output.stdout);
+    assert!(plan.contains("endpoint:    wss://peregrine.kilt.io:443/parachain-public-ws"));
+    assert!(plan.contains("did:kilt:4pehddkhEanexVTTzWAtrrfo2R7xPnePpuiJLC7shQU894aY"));
+    // the issuers of spiritnet are not trusted on peregrine
+    assert!(!plan.contains("did:kilt:4pnfkRn5UurBJTW92d9TaVLR2CqJdY4z5HPjrEbpGyBykare"));
+
+    // a local node can be a node of the network, that is checked once connected
+    let output = dry_run(&[
+        "--network",
+        "peregrine",
+        "--endpoint",
+        "ws://127.0.0.1:9944",
+    ]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_network_mismatch() {
+    let output = dry_run(&[
+        "--network",
+        "peregrine",
+        "--endpoint",
+        "wss://spiritnet.kilt.io:443",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is spiritnet, not peregrine"), "{}", stderr);
+
+    let output = dry_run(&["--network", "kusama"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_quorum_endpoints() {
+    let output = dry_run(&["--endpoint", "ws://a", "--endpoint", "ws://b"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = dry_run(&[
+        "--quorum",
+        "3",
+        "--endpoint",
+        "ws://a",
+        "--endpoint",
+        "ws://b",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--quorum 3 needs as many --endpoint values"),
+        "{}",
+        stderr
+    );
+
+    let output = dry_run(&[
+        "--quorum",
+        "2",
+        "--endpoint",
+        "ws://a",
+        "--endpoint",
+        "ws://b",
+    ]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_insecure_endpoints() {
+    let run = |args: &[&str]| {
+        Command::new(BIN)
+            .args(["--deny-warnings", "--file", CREDENTIAL])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = run(&["--endpoint", "ws://192.0.2.1:9944"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is not encrypted"), "{}", stderr);
+
+    // a local node is used without a warning, nothing listens on port 1
+    let output = run(&["--endpoint", "ws://127.0.0.1:1"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("is not encrypted"), "{}", stderr);
+
+    let output = Command::new(BIN)
+        .args([
+            "monitor",
+            "--dir",
+            "credentials",
+            "--endpoint",
+            "ws://192.0.2.1:9944",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("InsecureEndpoint"), "{}", stderr);
+}
@@ -0,0 +1,51 @@
+// `--publish` is checked before anything is verified. Nothing listens on port 1, the runs that
+// get as far as connecting fail there.
+
+#![cfg(feature = "nats")]
+
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CREDENTIAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+
+fn run(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_publish_usage() {
+    for args in [
+        vec!["--publish", "http://127.0.0.1:4222/results", CREDENTIAL],
+        vec!["--publish", "nats://127.0.0.1:4222/kilt.*", CREDENTIAL],
+        vec![
+            "monitor",
+            "--dir",
+            ".",
+            "--publish",
+            "nats://127.0.0.1:4222",
+        ],
+    ] {
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(
+            stderr(&output).contains("Publish error: "),
+            "{}",
+            stderr(&output)
+        );
+    }
+
+    // the node fails before there is a result to publish
+    let output = run(&[
+        "--endpoint",
+        "ws://127.0.0.1:1",
+        "--publish",
+        "nats://127.0.0.1:1/kilt.results",
+        CREDENTIAL,
+    ]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("could not be published"));
+}
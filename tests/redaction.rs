@@ -0,0 +1,92 @@
+// `--redact-pii` keeps the claim values of the input out of everything that is printed, also of
+// the errors of credentials that do not parse. Nothing listens on port 1, a run that gets as far
+// as connecting fails there. The verbose output of a credential that verifies is tested with a
+// mock backend in `main.rs`.
+
+use std::{path::PathBuf, process::Command};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CREDENTIAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+/// The claim value of presentation-1.json
+const EMAIL: &str = "tino@kilt.io";
+
+// the fixture with its claim value where the credential has no string, which serde quotes
+fn write_credentials(test: &str) -> PathBuf {
+    let name = format!("kilt-verify-{}-{}", test, std::process::id());
+    let dir = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(CREDENTIAL).unwrap()).unwrap();
+    json["claimHashes"] = EMAIL.into();
+    std::fs::write(dir.join("wrong-type.json"), json.to_string()).unwrap();
+    let json5 = format!(
+        r#"{{claim: {{contents: {{Email: "{0}"}}}}, rootHash: {0}}}"#,
+        EMAIL
+    );
+    std::fs::write(dir.join("broken.json5"), json5).unwrap();
+    dir
+}
+
+// stdout and stderr of a verbose run that explains missing attestations
+fn run(redact: bool, args: &[&str]) -> String {
+    let mut command = Command::new(BIN);
+    command.args(["--verbose", "--explain", "--endpoint", "ws://127.0.0.1:1"]);
+    if redact {
+        command.arg("--redact-pii");
+    }
+    let output = command.args(args).output().unwrap();
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr)
+}
+
+#[test]
+fn test_redact_pii() {
+    let dir = write_credentials("redact-pii");
+    let wrong_type = dir.join("wrong-type.json");
+    let broken = dir.join("broken.json5");
+    let runs = [
+        vec!["--file", wrong_type.to_str().unwrap()],
+        vec!["--lenient-json", "--file", broken.to_str().unwrap()],
+        vec!["--file", CREDENTIAL],
+    ];
+    for args in runs.iter() {
+        let output = run(true, args);
+        assert!(!output.contains(EMAIL), "{:?}: {}", args, output);
+    }
+
+    // the same runs quote the value without the flag
+    let output = run(false, &runs[0]);
+    assert!(output.contains(EMAIL), "{}", output);
+    let output = run(false, &runs[1]);
+    assert!(output.contains(EMAIL), "{}", output);
+    // where the error is stays
+    let output = run(true, &runs[0]);
+    assert!(output.contains("invalid_json"), "{}", output);
+    assert!(output.contains("line 1 column"), "{}", output);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_redact_pii_subcommands() {
+    let dir = write_credentials("redact-pii-subcommands");
+    let output = Command::new(BIN)
+        .args(["--redact-pii", "explain-structure", "--file", CREDENTIAL])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[redacted]"), "{}", stdout);
+    assert!(!stdout.contains(EMAIL), "{}", stdout);
+
+    let output = Command::new(BIN)
+        .args(["--redact-pii", "diff", CREDENTIAL])
+        .arg(dir.join("wrong-type.json"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains(EMAIL), "{}", stderr);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
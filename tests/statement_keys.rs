@@ -0,0 +1,95 @@
+// Credentials of forks that key their claim statements another way, created, explained,
+// minimized and canonicalized with --statement-prefix without a node.
+
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CTYPE: &str = "0x3291bb126e33b4862d421bfaa1d2f272e6cdfc4f96658988fbcffea8914bd9ac";
+const FORK: &str = "fork:ctype:{ctype}#{property}";
+
+fn run(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap()
+}
+
+fn explain(file: &str, prefix: &[&str]) -> serde_json::Value {
+    let output = run(&[&["explain-structure", "-f", file], prefix].concat());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_statement_prefix() {
+    let file = std::env::temp_dir().join(format!("kilt-verify-fork-{}.json", std::process::id()));
+    let file = file.to_str().unwrap();
+    let output = run(&[
+        "credential",
+        "create",
+        "--ctype",
+        CTYPE,
+        "--claim",
+        r#"{"Email":"alice@example.com"}"#,
+        "--owner-seed",
+        "//Alice",
+        "--statement-prefix",
+        FORK,
+        "-o",
+        file,
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let structure = explain(file, &["--statement-prefix", FORK]);
+    assert_eq!(structure["statementKeys"], FORK);
+    assert_eq!(structure["rootHash"]["matches"], true);
+    assert_eq!(
+        structure["statements"][1]["statement"],
+        format!(r#"{{"fork:ctype:{}#Email":"alice@example.com"}}"#, CTYPE)
+    );
+    assert!(structure["statements"][1]["nonce"].is_string());
+
+    // with the KILT keys the statement has no nonce in the credential
+    let structure = explain(file, &[]);
+    assert!(structure.get("statementKeys").is_none());
+    assert!(structure["statements"][1]["nonce"].is_null());
+    std::fs::remove_file(file).unwrap();
+
+    // a template without the property is refused
+    let output = run(&[
+        "explain-structure",
+        "-f",
+        file,
+        "--statement-prefix",
+        "fork:ctype:{ctype}",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_statement_prefix_offline_commands() {
+    let fork = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/vectors/custom-statement-keys.json"
+    );
+    for command in ["minimize", "canonicalize"] {
+        let output = run(&[command, "-f", fork, "--statement-prefix", FORK]);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert!(json["rootHash"].is_string());
+
+        // with the KILT keys the claim does not match its hashes
+        let output = run(&[command, "-f", fork]);
+        assert!(!output.status.success(), "{}", command);
+    }
+}
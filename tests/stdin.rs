@@ -0,0 +1,132 @@
+// Reading the credential from stdin, piped and from a terminal.
+//
+// All runs use `--dry-run`, the credential is read but nothing is sent to a node.
+
+#![cfg(unix)]
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::fd::{FromRawFd, OwnedFd},
+    process::{Command, Output, Stdio},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_kilt-verify");
+const CREDENTIAL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/presentation-1.json");
+const GUIDANCE: &str = "paste the credential JSON and press Ctrl-D";
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// a pseudo terminal, the child gets the slave side as stdin
+fn pty() -> (File, OwnedFd) {
+    let (mut master, mut slave) = (0, 0);
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(res, 0, "Failed to open a pty");
+    unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) }
+}
+
+#[test]
+fn test_piped_stdin() {
+    let output = Command::new(BIN)
+        .arg("--dry-run")
+        .stdin(File::open(CREDENTIAL).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("stdin (1 credential)"));
+    assert!(!stderr(&output).contains(GUIDANCE));
+
+    // --no-input only refuses terminals
+    let output = Command::new(BIN)
+        .args(["--dry-run", "--no-input"])
+        .stdin(File::open(CREDENTIAL).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn test_empty_stdin() {
+    let output = Command::new(BIN)
+        .arg("--dry-run")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("NoInput"), "{}", stderr(&output));
+}
+
+#[test]
+fn test_terminal_stdin() {
+    let (mut master, slave) = pty();
+    let child = Command::new(BIN)
+        .arg("--dry-run")
+        .stdin(slave)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // paste the credential line by line and end the input with Ctrl-D
+    let mut credential = String::new();
+    File::open(CREDENTIAL)
+        .unwrap()
+        .read_to_string(&mut credential)
+        .unwrap();
+    master.write_all(credential.trim_end().as_bytes()).unwrap();
+    master.write_all(b"\n\x04").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains(GUIDANCE), "{}", stderr(&output));
+}
+
+#[test]
+fn test_terminal_no_input() {
+    let (_master, slave) = pty();
+    let output = Command::new(BIN)
+        .args(["--dry-run", "--no-input"])
+        .stdin(slave)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!stderr(&output).contains(GUIDANCE));
+}
+
+#[test]
+fn test_lenient_stdin() {
+    let credential = std::fs::read_to_string(CREDENTIAL).unwrap();
+    let templated = format!(
+        "// templated by the test plan\n{},}}\n",
+        credential.trim_end().trim_end_matches('}')
+    );
+    let run = |args: &[&str]| {
+        let mut child = Command::new(BIN)
+            .arg("--dry-run")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(templated.as_bytes()).unwrap();
+        drop(stdin);
+        child.wait_with_output().unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+    let output = run(&["--lenient-json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("parsing:     lenient"));
+}